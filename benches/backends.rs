@@ -0,0 +1,48 @@
+// Groth16 proving time of the membership circuit, per hash backend and tree
+// height. The constraint counts of the same circuits are printed first.
+//
+// cargo bench --bench backends
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use merkle_tree_tutorial::common::poseidon::PoseidonParams;
+use merkle_tree_tutorial::common::MerkleParams;
+use merkle_tree_tutorial::report::{constraint_report, pedersen_circuit, poseidon_circuit, report_table, HashBackend};
+use merkle_tree_tutorial::rng::OsRngProvider;
+use merkle_tree_tutorial::snark;
+
+const TREE_HEIGHTS: [usize; 3] = [4, 8, 16];
+const LEAF_LEN: usize = 32;
+
+fn proving(c: &mut Criterion) {
+    let mut rng = OsRngProvider::default();
+    let reports = constraint_report(&TREE_HEIGHTS, LEAF_LEN, &mut rng).unwrap();
+    println!("{}", report_table(&reports));
+
+    let pedersen = MerkleParams::setup(&mut rng).unwrap();
+    let poseidon = PoseidonParams::setup(&mut rng).unwrap();
+    let mut group = c.benchmark_group("prove_membership");
+    group.sample_size(10);
+    for &height in TREE_HEIGHTS.iter() {
+        for &backend in HashBackend::ALL.iter() {
+            let id = BenchmarkId::new(backend.name(), height);
+            match backend {
+                HashBackend::Pedersen => {
+                    let (pk, _) = snark::setup(pedersen_circuit(&pedersen, height, LEAF_LEN).unwrap(), &mut rng).unwrap();
+                    group.bench_function(id, |b| {
+                        b.iter(|| snark::prove(&pk, pedersen_circuit(&pedersen, height, LEAF_LEN).unwrap(), &mut rng).unwrap())
+                    });
+                }
+                HashBackend::Poseidon => {
+                    let (pk, _) = snark::setup(poseidon_circuit(&poseidon, height, LEAF_LEN).unwrap(), &mut rng).unwrap();
+                    group.bench_function(id, |b| {
+                        b.iter(|| snark::prove(&pk, poseidon_circuit(&poseidon, height, LEAF_LEN).unwrap(), &mut rng).unwrap())
+                    });
+                }
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, proving);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+// Building a tree of 2^16 leaves and opening 1024 of its paths, with and
+// without the `parallel` feature. arkworks' `SimpleMerkleTree` always hashes
+// in order, so it is the sequential baseline in both runs; `LevelTree` and
+// `generate_proofs` use the rayon pool only with the feature:
+//
+// cargo bench --bench parallel_build
+// cargo bench --bench parallel_build --features parallel
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use merkle_tree_tutorial::common::{generate_proofs, MerkleParams};
+use merkle_tree_tutorial::level_tree::LevelTree;
+
+const LEAVES: u32 = 1 << 16;
+
+fn mode() -> &'static str {
+    if cfg!(feature = "parallel") {
+        "parallel"
+    } else {
+        "sequential"
+    }
+}
+
+fn build(c: &mut Criterion) {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0..LEAVES).map(|i| i.to_le_bytes().to_vec()).collect();
+
+    let mut group = c.benchmark_group("build_2^16");
+    group.sample_size(10);
+    group.bench_function("simple_merkle_tree", |b| b.iter(|| params.build_tree(&leaves).unwrap()));
+    group.bench_function(format!("level_tree/{}", mode()), |b| {
+        b.iter(|| LevelTree::new(params.clone(), &leaves).unwrap())
+    });
+    group.finish();
+
+    let tree = params.build_tree(&leaves).unwrap();
+    let indices: Vec<usize> = (0..LEAVES as usize).step_by(64).collect();
+    let mut group = c.benchmark_group("prove_1024_of_2^16");
+    group.bench_function("one_by_one", |b| {
+        b.iter(|| indices.iter().map(|i| tree.generate_proof(*i).unwrap()).collect::<Vec<_>>())
+    });
+    group.bench_function(format!("generate_proofs/{}", mode()), |b| {
+        b.iter(|| generate_proofs(&tree, &indices).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, build);
+criterion_main!(benches);
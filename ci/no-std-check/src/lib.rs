@@ -0,0 +1,72 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use merkle_tree_tutorial::ark::{CanonicalDeserialize, RngCore};
+use merkle_tree_tutorial::append_only::{verify_inclusion, AppendOnlyTree};
+use merkle_tree_tutorial::common::MerkleParams;
+use merkle_tree_tutorial::error::MerkleError;
+use merkle_tree_tutorial::multiproof::{CompressedMultiProof, MultiPath};
+use merkle_tree_tutorial::root::MerkleRoot;
+use merkle_tree_tutorial::serialization::{deserialize_params, deserialize_path, serialize_path, Compression};
+use merkle_tree_tutorial::{Root, SimpleMerkleTree};
+
+/// Builds a tree from `leaves`, proves the first one and checks the proof
+/// after a round trip through bytes. The randomness comes from the caller.
+pub fn build_and_prove<R: RngCore>(rng: &mut R, leaves: &[Vec<u8>]) -> Result<bool, MerkleError> {
+    let params = MerkleParams::setup(rng)?;
+    let tree = SimpleMerkleTree::new(&params.leaf_crh_params, &params.two_to_one_crh_params, leaves)?;
+    let path = deserialize_path(&serialize_path(&tree.generate_proof(0)?))?;
+    if !path.verify(&params.leaf_crh_params, &params.two_to_one_crh_params, &tree.root(), &leaves[0])? {
+        return Ok(false);
+    }
+
+    let mut log = AppendOnlyTree::new(params.clone());
+    for leaf in leaves {
+        log.append(leaf)?;
+    }
+    let proof = log.prove_inclusion(0)?;
+    verify_inclusion(&params, &leaves[0], &proof, &log.root()?)
+}
+
+/// Checks a membership proof that arrived as bytes, as a verifier with
+/// neither std nor randomness would: the parameters, the root and the path
+/// are all decoded from what the prover sent.
+pub fn verify_received(params: &[u8], root: &[u8], path: &[u8], leaf: &[u8]) -> Result<bool, MerkleError> {
+    let params = deserialize_params(params, Compression::Compressed)?;
+    let root: Root = MerkleRoot::from_bytes(root)?.into();
+    let path = deserialize_path(path)?;
+    params.verify_path(&path, &root, leaf)
+}
+
+/// The same for several leaves, as one path each and as one multiproof.
+pub fn verify_received_batch(
+    params: &[u8],
+    root: &[u8],
+    paths: &[Vec<u8>],
+    multiproof: &MultiPath,
+    leaves: &[(usize, Vec<u8>)],
+) -> Result<bool, MerkleError> {
+    let params = deserialize_params(params, Compression::Compressed)?;
+    let root: Root = MerkleRoot::from_bytes(root)?.into();
+    let items = paths
+        .iter()
+        .zip(leaves)
+        .map(|(path, (_, leaf))| Ok((leaf.clone(), deserialize_path(path)?)))
+        .collect::<Result<Vec<_>, MerkleError>>()?;
+    Ok(params.verify_batch(&root, &items)? && multiproof.verify(&params, &root, leaves)?)
+}
+
+/// The same for a compressed multiproof, its siblings decoded from bytes too.
+pub fn verify_received_compressed(
+    params: &[u8],
+    root: &[u8],
+    proof: &[u8],
+    leaves: &[(usize, Vec<u8>)],
+) -> Result<bool, MerkleError> {
+    let params = deserialize_params(params, Compression::Compressed)?;
+    let root: Root = MerkleRoot::from_bytes(root)?.into();
+    let proof = CompressedMultiProof::deserialize(proof)?;
+    proof.verify(&params, &root, leaves)
+}
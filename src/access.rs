@@ -0,0 +1,597 @@
+use crate::common::*;
+use crate::constraints::{blank_path, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::PublicInputsBuilder;
+use crate::leaf::Leaf;
+use crate::nullifier::{nullifier, nullifier_var, Nullifier, NullifierSet};
+use crate::range::{enforce_bit_length, enforce_gt};
+use crate::serialization::{path_serialized_size, read_path, write_path};
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::io::{Read, Write};
+use ark_std::rand::{CryptoRng, RngCore};
+
+// Membership-gated access tokens.
+//
+// The issuer commits to a tree of tokens `token_id || audience_hash || expiry`
+// (32 + 32 + 8 bytes). A holder proves in zero knowledge that they hold some
+// unexpired token for a given audience, without revealing which one. The
+// audience hash is part of the statement, so a proof presented to service A
+// does not verify at service B. Gates may additionally run in single-use mode,
+// where the proof exposes a nullifier derived from the token id and audience
+// and each nullifier is accepted only once.
+
+/// Size of a token leaf in bytes.
+pub const TOKEN_LEAF_LEN: usize = 72;
+
+// Timestamps are u64 seconds.
+const TIME_BITS: usize = 64;
+
+/// Hashes a service name into the 32 bytes stored in a token.
+pub fn audience_hash(audience: &str) -> [u8; 32] {
+    keccak256(audience.as_bytes())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub token_id: [u8; 32],
+    pub audience_hash: [u8; 32],
+    pub expiry: u64,
+}
+
+impl Token {
+    pub fn to_leaf_bytes(&self) -> Vec<u8> {
+        let mut leaf = self.token_id.to_vec();
+        leaf.extend_from_slice(&self.audience_hash);
+        leaf.extend_from_slice(&self.expiry.to_le_bytes());
+        leaf
+    }
+
+    /// The nullifier a single-use gate sees for this token.
+    pub fn nullifier(&self, params: &MerkleParams) -> Result<Nullifier, MerkleError> {
+        Ok(nullifier(params, &self.token_id, &self.audience_hash)?)
+    }
+
+    // Padding leaves: all zero, and in particular expired at every `now`.
+    fn padding() -> Self {
+        Token {
+            token_id: [0u8; 32],
+            audience_hash: [0u8; 32],
+            expiry: 0,
+        }
+    }
+}
+
+/// What the issuer hands to a holder: the token and its path in the current tree.
+#[derive(Clone)]
+pub struct Credential {
+    pub token: Token,
+    pub root: Root,
+    pub path: SimplePath,
+}
+
+/// The issuer side: mints tokens and republishes the tree.
+pub struct Issuer {
+    params: MerkleParams,
+    options: TreeOptions,
+    // the tokens in the current tree, in leaf order
+    tokens: Vec<Token>,
+    // minted since the last rotation
+    pending: Vec<Token>,
+    tree: SimpleMerkleTree,
+}
+
+impl Issuer {
+    /// Creates an issuer whose trees hold up to `capacity` tokens. The capacity
+    /// fixes the circuit shape, so it must stay the same for the lifetime of the keys.
+    pub fn new(params: MerkleParams, capacity: usize) -> Result<Self, MerkleError> {
+        let padding = PaddingStrategy::Custom(Leaf::from(Token::padding().to_leaf_bytes()));
+        let options = TreeOptions::for_capacity(capacity).padding(padding);
+        let tree = Self::build_tree(&params, &options, &[])?;
+        Ok(Issuer {
+            params,
+            options,
+            tokens: Vec::new(),
+            pending: Vec::new(),
+            tree,
+        })
+    }
+
+    fn build_tree(
+        params: &MerkleParams,
+        options: &TreeOptions,
+        tokens: &[Token],
+    ) -> Result<SimpleMerkleTree, MerkleError> {
+        Ok(params.build_tree_with_options(tokens.iter().map(Token::to_leaf_bytes), options)?.tree)
+    }
+
+    /// Number of levels of the issuer's trees, leaves and root included.
+    pub fn tree_height(&self) -> usize {
+        self.options.height
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// Mints a fresh token. It becomes provable after the next `rotate`.
+    pub fn mint<R: RngCore + CryptoRng>(&mut self, rng: &mut R, audience: &str, expiry: u64) -> Token {
+        let mut token_id = [0u8; 32];
+        rng.fill_bytes(&mut token_id);
+        let token = Token {
+            token_id,
+            audience_hash: audience_hash(audience),
+            expiry,
+        };
+        self.pending.push(token.clone());
+        token
+    }
+
+    /// Publishes the pending tokens, dropping every token that expired by `now`.
+    /// On error the issuer is left as it was, pending tokens included.
+    pub fn rotate(&mut self, now: u64) -> Result<Root, MerkleError> {
+        let tokens: Vec<Token> = self
+            .tokens
+            .iter()
+            .chain(&self.pending)
+            .filter(|t| t.expiry > now)
+            .cloned()
+            .collect();
+        if tokens.len() > self.options.capacity() {
+            return Err(MerkleError::CapacityExceeded {
+                len: tokens.len(),
+                capacity: self.options.capacity(),
+            });
+        }
+        self.tree = Self::build_tree(&self.params, &self.options, &tokens)?;
+        self.tokens = tokens;
+        self.pending.clear();
+        Ok(self.root())
+    }
+
+    /// The credential for `token` in the current tree, if the token is in it.
+    pub fn credential(&self, token: &Token) -> Result<Credential, MerkleError> {
+        let index = self
+            .tokens
+            .iter()
+            .position(|t| t == token)
+            .ok_or(MerkleError::LeafNotFound)?;
+        Ok(Credential {
+            token: token.clone(),
+            root: self.root(),
+            path: generate_proof(&self.tree, LeafIndex::checked_new(index as u64, leaf_count(&self.tree))?)?,
+        })
+    }
+
+    /// Generates the proving and verifying keys for this issuer's trees.
+    pub fn setup<R: RngCore + CryptoRng>(
+        &self,
+        single_use: bool,
+        rng: &mut R,
+    ) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+        snark::setup(
+            AccessCircuit::blank(self.params.clone(), self.tree_height(), single_use),
+            rng,
+        )
+    }
+}
+
+/// Proves "I hold an unexpired token for `audience_hash` in the tree under `root`".
+pub struct AccessCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub single_use: bool,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub audience_hash: [u8; 32],
+    pub now: u64,
+    pub nullifier: Option<Nullifier>,
+
+    // These are the private witnesses to the circuit.
+    pub token_id: Option<[u8; 32]>,
+    pub expiry: Option<u64>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl AccessCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, tree_height: usize, single_use: bool) -> Self {
+        AccessCircuit {
+            params,
+            single_use,
+            root: Default::default(),
+            audience_hash: [0u8; 32],
+            now: 0,
+            nullifier: None,
+            token_id: Some([0u8; 32]),
+            expiry: Some(0),
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+}
+
+/// The public inputs of `AccessCircuit`, in allocation order.
+pub fn public_inputs(
+    root: &Root,
+    audience_hash: &[u8; 32],
+    now: u64,
+    nullifier: Option<&Nullifier>,
+) -> Vec<ConstraintF> {
+    match nullifier {
+        Some(nullifier) => PublicInputsBuilder::single_use_threshold()
+            .root(root)
+            .leaf_bytes(audience_hash)
+            .scalar(now)
+            .nullifier(nullifier)
+            .finish(),
+        None => PublicInputsBuilder::threshold()
+            .root(root)
+            .leaf_bytes(audience_hash)
+            .scalar(now)
+            .finish(),
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for AccessCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let audience_hash =
+            UInt8::new_input_vec(ark_relations::ns!(cs, "audience_var"), &self.audience_hash)?;
+        let now = FpVar::new_input(ark_relations::ns!(cs, "now_var"), || {
+            Ok(ConstraintF::from(self.now))
+        })?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the private token fields and path:
+        let token_id = UInt8::new_witness_vec(
+            ark_relations::ns!(cs, "token_id_var"),
+            &self.token_id.unwrap_or_default(),
+        )?;
+        let expiry = UInt64::new_witness(ark_relations::ns!(cs, "expiry_var"), || {
+            self.expiry.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let expiry_bits = expiry.to_bits_le();
+        let mut leaf_bytes = token_id.clone();
+        leaf_bytes.extend_from_slice(&audience_hash);
+        leaf_bytes.extend(expiry_bits.chunks(8).map(UInt8::from_bits_le));
+
+        let is_member = path.verify_membership(
+            &leaf_crh_params,
+            &two_to_one_crh_params,
+            &root,
+            &leaf_bytes.as_slice(),
+        )?;
+        is_member.enforce_equal(&Boolean::TRUE)?;
+
+        // expiry > now
+        enforce_bit_length(&now, TIME_BITS)?;
+        enforce_gt(&Boolean::le_bits_to_fp_var(&expiry_bits)?, &now, TIME_BITS)?;
+
+        if self.single_use {
+            let expected = FpVar::new_input(ark_relations::ns!(cs, "nullifier_var"), || {
+                self.nullifier.ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            let computed = nullifier_var(&leaf_crh_params, &token_id, &audience_hash)?;
+            computed.enforce_equal(&expected)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What a holder presents to a gate.
+#[derive(Clone)]
+pub struct Presentation {
+    pub proof: Proof,
+    pub root: Root,
+    pub now: u64,
+    pub nullifier: Option<Nullifier>,
+}
+
+impl Credential {
+    /// Proves possession of this credential at time `now`.
+    pub fn present<R: RngCore + CryptoRng>(
+        &self,
+        params: &MerkleParams,
+        pk: &ProvingKey,
+        now: u64,
+        single_use: bool,
+        rng: &mut R,
+    ) -> Result<Presentation, MerkleError> {
+        let nullifier = if single_use {
+            Some(self.token.nullifier(params)?)
+        } else {
+            None
+        };
+        let circuit = AccessCircuit {
+            params: params.clone(),
+            single_use,
+            root: self.root,
+            audience_hash: self.token.audience_hash,
+            now,
+            nullifier,
+            token_id: Some(self.token.token_id),
+            expiry: Some(self.token.expiry),
+            auth_path: Some(self.path.clone()),
+        };
+        Ok(Presentation {
+            proof: snark::prove(pk, circuit, rng)?,
+            root: self.root,
+            now,
+            nullifier,
+        })
+    }
+}
+
+/// The verifying side, run by each service.
+pub struct Gate {
+    vk: VerifyingKey,
+    audience_hash: [u8; 32],
+    root: Root,
+    // how old a presentation may be, in seconds
+    max_age: u64,
+    // `Some` iff the gate runs in single-use mode
+    spent: Option<NullifierSet>,
+}
+
+impl Gate {
+    pub fn new(vk: VerifyingKey, audience: &str, root: Root, max_age: u64, single_use: bool) -> Self {
+        Gate {
+            vk,
+            audience_hash: audience_hash(audience),
+            root,
+            max_age,
+            spent: if single_use { Some(NullifierSet::new()) } else { None },
+        }
+    }
+
+    /// Switches to the issuer's newly published root.
+    pub fn update_root(&mut self, root: Root) {
+        self.root = root;
+    }
+
+    /// Checks `presentation` at time `now`, consuming its nullifier in single-use mode.
+    pub fn verify(&mut self, presentation: &Presentation, now: u64) -> Result<bool, MerkleError> {
+        if presentation.root != self.root
+            || presentation.now > now
+            || now - presentation.now > self.max_age
+        {
+            return Ok(false);
+        }
+        let nullifier = match (&self.spent, &presentation.nullifier) {
+            (Some(spent), Some(n)) if !spent.contains(n) => Some(*n),
+            (None, None) => None,
+            _ => return Ok(false),
+        };
+
+        let inputs = public_inputs(&self.root, &self.audience_hash, presentation.now, nullifier.as_ref());
+        if !snark::verify(&self.vk, &inputs, &presentation.proof)? {
+            return Ok(false);
+        }
+        if let (Some(spent), Some(n)) = (self.spent.as_mut(), nullifier) {
+            spent.insert(n);
+        }
+        Ok(true)
+    }
+}
+
+impl CanonicalSerialize for Token {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        writer.write_all(&self.token_id)?;
+        writer.write_all(&self.audience_hash)?;
+        self.expiry.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        TOKEN_LEAF_LEN
+    }
+}
+
+impl CanonicalDeserialize for Token {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let mut token_id = [0u8; 32];
+        reader.read_exact(&mut token_id)?;
+        let mut audience_hash = [0u8; 32];
+        reader.read_exact(&mut audience_hash)?;
+        Ok(Token {
+            token_id,
+            audience_hash,
+            expiry: u64::deserialize(&mut reader)?,
+        })
+    }
+}
+
+impl CanonicalSerialize for Credential {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.token.serialize(&mut writer)?;
+        self.root.serialize(&mut writer)?;
+        write_path(&self.path, &mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.token.serialized_size() + self.root.serialized_size() + path_serialized_size(&self.path)
+    }
+}
+
+impl CanonicalDeserialize for Credential {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(Credential {
+            token: Token::deserialize(&mut reader)?,
+            root: Root::deserialize(&mut reader)?,
+            path: read_path(&mut reader)?,
+        })
+    }
+}
+
+impl CanonicalSerialize for Presentation {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.proof.serialize(&mut writer)?;
+        self.root.serialize(&mut writer)?;
+        self.now.serialize(&mut writer)?;
+        self.nullifier.is_some().serialize(&mut writer)?;
+        if let Some(n) = &self.nullifier {
+            n.serialize(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.proof.serialized_size()
+            + self.root.serialized_size()
+            + self.now.serialized_size()
+            + true.serialized_size()
+            + self.nullifier.as_ref().map_or(0, |n| n.serialized_size())
+    }
+}
+
+impl CanonicalDeserialize for Presentation {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let proof = Proof::deserialize(&mut reader)?;
+        let root = Root::deserialize(&mut reader)?;
+        let now = u64::deserialize(&mut reader)?;
+        let nullifier = if bool::deserialize(&mut reader)? {
+            Some(Nullifier::deserialize(&mut reader)?)
+        } else {
+            None
+        };
+        Ok(Presentation {
+            proof,
+            root,
+            now,
+            nullifier,
+        })
+    }
+}
+
+#[cfg(test)]
+fn test_issuer() -> Issuer {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    Issuer::new(params, 4).unwrap()
+}
+
+#[test]
+fn test_access_expiry_boundary() {
+    let mut rng = ark_std::test_rng();
+    let mut issuer = test_issuer();
+    let token = issuer.mint(&mut rng, "service-a", 1_000);
+    issuer.rotate(0).unwrap();
+    let credential = issuer.credential(&token).unwrap();
+
+    let is_satisfied_at = |now: u64| {
+        let circuit = AccessCircuit {
+            params: issuer.params.clone(),
+            single_use: false,
+            root: credential.root,
+            audience_hash: token.audience_hash,
+            now,
+            nullifier: None,
+            token_id: Some(token.token_id),
+            expiry: Some(token.expiry),
+            auth_path: Some(credential.path.clone()),
+        };
+        let cs = ark_relations::r1cs::ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    };
+    assert!(is_satisfied_at(0));
+    assert!(is_satisfied_at(999));
+    // the token is valid strictly before its expiry
+    assert!(!is_satisfied_at(1_000));
+    assert!(!is_satisfied_at(1_001));
+
+    // rotation prunes the token once it has expired
+    issuer.rotate(1_000).unwrap();
+    assert!(issuer.credential(&token).is_err());
+}
+
+#[test]
+fn test_access_wrong_audience() {
+    let mut rng = ark_std::test_rng();
+    let mut issuer = test_issuer();
+    let token = issuer.mint(&mut rng, "service-a", 1_000);
+    let root = issuer.rotate(0).unwrap();
+    let (pk, vk) = issuer.setup(false, &mut rng).unwrap();
+
+    let credential = issuer.credential(&token).unwrap();
+    let presentation = credential.present(&issuer.params, &pk, 10, false, &mut rng).unwrap();
+
+    let mut gate_a = Gate::new(vk.clone(), "service-a", root, 60, false);
+    let mut gate_b = Gate::new(vk, "service-b", root, 60, false);
+    assert!(gate_a.verify(&presentation, 20).unwrap());
+    assert!(!gate_b.verify(&presentation, 20).unwrap());
+    // too old by the time it reaches the gate
+    assert!(!gate_a.verify(&presentation, 100).unwrap());
+}
+
+#[test]
+fn test_access_single_use_reuse() {
+    let mut rng = ark_std::test_rng();
+    let mut issuer = test_issuer();
+    let token = issuer.mint(&mut rng, "service-a", 1_000);
+    let other = issuer.mint(&mut rng, "service-a", 1_000);
+    let root = issuer.rotate(0).unwrap();
+    let (pk, vk) = issuer.setup(true, &mut rng).unwrap();
+    let mut gate = Gate::new(vk, "service-a", root, 60, true);
+
+    let credential = issuer.credential(&token).unwrap();
+    let presentation = credential.present(&issuer.params, &pk, 10, true, &mut rng).unwrap();
+
+    // a presentation survives the trip through bytes
+    let mut bytes = Vec::new();
+    presentation.serialize(&mut bytes).unwrap();
+    let decoded = Presentation::deserialize(&bytes[..]).unwrap();
+
+    assert!(gate.verify(&decoded, 20).unwrap());
+    assert!(!gate.verify(&presentation, 20).unwrap());
+
+    // a fresh proof for the same token carries the same nullifier
+    let again = credential.present(&issuer.params, &pk, 30, true, &mut rng).unwrap();
+    assert!(!gate.verify(&again, 40).unwrap());
+
+    // other tokens are unaffected
+    let other_credential = issuer.credential(&other).unwrap();
+    let mut bytes = Vec::new();
+    other_credential.serialize(&mut bytes).unwrap();
+    let other_credential = Credential::deserialize(&bytes[..]).unwrap();
+    let presentation = other_credential.present(&issuer.params, &pk, 30, true, &mut rng).unwrap();
+    assert!(gate.verify(&presentation, 40).unwrap());
+}
+
+#[test]
+fn test_access_rotate_over_capacity() {
+    let mut rng = ark_std::test_rng();
+    let mut issuer = test_issuer();
+    let kept = issuer.mint(&mut rng, "service-a", 1_000);
+    let root = issuer.rotate(0).unwrap();
+    let later: Vec<Token> = (0..4).map(|_| issuer.mint(&mut rng, "service-a", 2_000)).collect();
+
+    assert!(matches!(issuer.rotate(0), Err(MerkleError::CapacityExceeded { len: 5, capacity: 4 })));
+    assert_eq!(issuer.root(), root);
+    assert_eq!((issuer.tokens.len(), issuer.pending.len()), (1, 4));
+    assert!(issuer.credential(&kept).is_ok());
+    // once the first token has expired, the four pending ones fit
+    issuer.rotate(1_000).unwrap();
+    assert!(issuer.credential(&kept).is_err());
+    assert!(later.iter().all(|token| issuer.credential(token).is_ok()));
+}
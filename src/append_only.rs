@@ -0,0 +1,489 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::RootVar;
+use crate::error::MerkleError;
+use crate::Root;
+#[cfg(feature = "constraints")]
+use ark_crypto_primitives::crh::TwoToOneCRHGadget;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::prelude::*;
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::vec::Vec;
+
+// An append-only Merkle tree in the style of RFC 6962 (Certificate Transparency).
+//
+// Unlike `SimpleMerkleTree` the number of leaves doesn't have to be a power of
+// two: the root of n > 1 leaves is `H(root(first k), root(rest))` where k is
+// the largest power of two smaller than n, and the root of a single leaf is
+// its leaf hash. Leaves and internal nodes use the same two hashes as
+// `SimpleMerkleTree`, so the two Pedersen parameter sets also provide the
+// leaf/node domain separation RFC 6962 gets from its 0x00/0x01 prefixes.
+//
+// Inclusion paths and consistency proofs are ordered bottom-up, as in the RFC.
+//
+// Which node of a consistency proof is hashed into which root, and from which
+// side, depends on the two sizes alone. `ConsistencyCircuit` fixes the sizes
+// and replays the verifier's walk with the nodes as witnesses, proving that
+// one public root extends the other without revealing the nodes.
+
+/// A proof that a leaf is at `leaf_index` in the tree of `tree_size` leaves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InclusionProof {
+    pub leaf_index: u64,
+    pub tree_size: u64,
+    pub path: Vec<Root>,
+}
+
+/// A proof that the tree of `old_size` leaves is a prefix of the tree of `new_size` leaves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsistencyProof {
+    pub old_size: u64,
+    pub new_size: u64,
+    pub path: Vec<Root>,
+}
+
+pub struct AppendOnlyTree {
+    params: MerkleParams,
+    leaf_hashes: Vec<Root>,
+}
+
+// the largest power of two strictly smaller than n, for n > 1
+fn split_point(n: usize) -> usize {
+    n.next_power_of_two() / 2
+}
+
+impl AppendOnlyTree {
+    pub fn new(params: MerkleParams) -> Self {
+        AppendOnlyTree {
+            params,
+            leaf_hashes: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaf_hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaf_hashes.is_empty()
+    }
+
+    /// Appends a leaf, returning its index.
+    pub fn append(&mut self, leaf: &[u8]) -> Result<usize, MerkleError> {
+        self.leaf_hashes.push(self.params.hash_leaf(leaf)?);
+        Ok(self.leaf_hashes.len() - 1)
+    }
+
+    /// The root of the current tree. The empty tree has the all-zero root.
+    pub fn root(&self) -> Result<Root, MerkleError> {
+        self.root_at(self.len())
+    }
+
+    /// The root the tree had when it held its first `size` leaves.
+    pub fn root_at(&self, size: usize) -> Result<Root, MerkleError> {
+        if size > self.len() {
+            return Err(MerkleError::LeafIndexOutOfRange {
+                index: size,
+                len: self.len(),
+            });
+        }
+        if size == 0 {
+            return Ok(Root::default());
+        }
+        self.subtree_root(0, size)
+    }
+
+    fn subtree_root(&self, start: usize, end: usize) -> Result<Root, MerkleError> {
+        if end - start == 1 {
+            return Ok(self.leaf_hashes[start]);
+        }
+        let k = split_point(end - start);
+        let left = self.subtree_root(start, start + k)?;
+        let right = self.subtree_root(start + k, end)?;
+        self.params.hash_children(&left, &right)
+    }
+
+    /// The inclusion proof of leaf `index` in the current tree.
+    pub fn prove_inclusion(&self, index: usize) -> Result<InclusionProof, MerkleError> {
+        if index >= self.len() {
+            return Err(MerkleError::LeafIndexOutOfRange {
+                index,
+                len: self.len(),
+            });
+        }
+        let mut path = Vec::new();
+        self.inclusion_path(index, 0, self.len(), &mut path)?;
+        Ok(InclusionProof {
+            leaf_index: index as u64,
+            tree_size: self.len() as u64,
+            path,
+        })
+    }
+
+    // PATH(m, D[start:end]) from RFC 6962, section 2.1.1
+    fn inclusion_path(
+        &self,
+        m: usize,
+        start: usize,
+        end: usize,
+        path: &mut Vec<Root>,
+    ) -> Result<(), MerkleError> {
+        if end - start == 1 {
+            return Ok(());
+        }
+        let k = split_point(end - start);
+        if m < k {
+            self.inclusion_path(m, start, start + k, path)?;
+            path.push(self.subtree_root(start + k, end)?);
+        } else {
+            self.inclusion_path(m - k, start + k, end, path)?;
+            path.push(self.subtree_root(start, start + k)?);
+        }
+        Ok(())
+    }
+
+    /// The consistency proof between the first `old_size` leaves and the current tree.
+    pub fn prove_consistency(&self, old_size: usize) -> Result<ConsistencyProof, MerkleError> {
+        self.consistency_proof(old_size, self.len())
+    }
+
+    /// The consistency proof between the trees the log had at `old_size` and
+    /// at `new_size` leaves, for a client that has only seen the latter.
+    pub fn consistency_proof(&self, old_size: usize, new_size: usize) -> Result<ConsistencyProof, MerkleError> {
+        if new_size > self.len() || old_size > new_size {
+            return Err(MerkleError::LeafIndexOutOfRange {
+                index: old_size.max(new_size),
+                len: self.len().min(new_size),
+            });
+        }
+        let mut path = Vec::new();
+        if old_size > 0 && old_size < new_size {
+            self.subproof(old_size, 0, new_size, true, &mut path)?;
+        }
+        Ok(ConsistencyProof {
+            old_size: old_size as u64,
+            new_size: new_size as u64,
+            path,
+        })
+    }
+
+    // SUBPROOF(m, D[start:end], b) from RFC 6962, section 2.1.2
+    fn subproof(
+        &self,
+        m: usize,
+        start: usize,
+        end: usize,
+        complete: bool,
+        path: &mut Vec<Root>,
+    ) -> Result<(), MerkleError> {
+        let n = end - start;
+        if m == n {
+            if !complete {
+                path.push(self.subtree_root(start, end)?);
+            }
+            return Ok(());
+        }
+        let k = split_point(n);
+        if m <= k {
+            self.subproof(m, start, start + k, complete, path)?;
+            path.push(self.subtree_root(start + k, end)?);
+        } else {
+            self.subproof(m - k, start + k, end, false, path)?;
+            path.push(self.subtree_root(start, start + k)?);
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `leaf` is included in the tree with `root`, following RFC 9162, section 2.1.3.2.
+pub fn verify_inclusion(
+    params: &MerkleParams,
+    leaf: &[u8],
+    proof: &InclusionProof,
+    root: &Root,
+) -> Result<bool, MerkleError> {
+    if proof.leaf_index >= proof.tree_size {
+        return Ok(false);
+    }
+    let mut f_n = proof.leaf_index;
+    let mut s_n = proof.tree_size - 1;
+    let mut r = params.hash_leaf(leaf)?;
+    for p in &proof.path {
+        if s_n == 0 {
+            return Ok(false);
+        }
+        if f_n & 1 == 1 || f_n == s_n {
+            r = params.hash_children(p, &r)?;
+            while f_n & 1 == 0 && f_n != 0 {
+                f_n >>= 1;
+                s_n >>= 1;
+            }
+        } else {
+            r = params.hash_children(&r, p)?;
+        }
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+    Ok(s_n == 0 && &r == root)
+}
+
+/// Checks that the tree with `old_root` is a prefix of the tree with `new_root`,
+/// following RFC 9162, section 2.1.4.2.
+pub fn verify_consistency(
+    params: &MerkleParams,
+    proof: &ConsistencyProof,
+    old_root: &Root,
+    new_root: &Root,
+) -> Result<bool, MerkleError> {
+    let (old_size, new_size) = (proof.old_size, proof.new_size);
+    if old_size > new_size {
+        return Ok(false);
+    }
+    if old_size == new_size {
+        return Ok(proof.path.is_empty() && old_root == new_root);
+    }
+    // the empty tree is a prefix of everything
+    if old_size == 0 {
+        return Ok(proof.path.is_empty());
+    }
+
+    let mut path = proof.path.clone();
+    if old_size.is_power_of_two() {
+        path.insert(0, *old_root);
+    }
+    if path.is_empty() {
+        return Ok(false);
+    }
+
+    let mut f_n = old_size - 1;
+    let mut s_n = new_size - 1;
+    while f_n & 1 == 1 {
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+    let mut f_r = path[0];
+    let mut s_r = path[0];
+    for c in &path[1..] {
+        if s_n == 0 {
+            return Ok(false);
+        }
+        if f_n & 1 == 1 || f_n == s_n {
+            f_r = params.hash_children(c, &f_r)?;
+            s_r = params.hash_children(c, &s_r)?;
+            while f_n & 1 == 0 && f_n != 0 {
+                f_n >>= 1;
+                s_n >>= 1;
+            }
+        } else {
+            s_r = params.hash_children(&s_r, c)?;
+        }
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+    Ok(s_n == 0 && &f_r == old_root && &s_r == new_root)
+}
+
+// The walk of `verify_consistency` for `0 < old_size < new_size`: for each
+// node after the first, true if it is hashed, from the left, into both roots,
+// false if only into the new root, from the right.
+#[cfg(any(test, feature = "constraints"))]
+fn consistency_steps(old_size: u64, new_size: u64) -> Vec<bool> {
+    let (mut f_n, mut s_n) = (old_size - 1, new_size - 1);
+    while f_n & 1 == 1 {
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+    let mut steps = Vec::new();
+    while s_n != 0 {
+        let both = f_n & 1 == 1 || f_n == s_n;
+        if both {
+            while f_n & 1 == 0 && f_n != 0 {
+                f_n >>= 1;
+                s_n >>= 1;
+            }
+        }
+        steps.push(both);
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+    steps
+}
+
+// The number of nodes in a consistency proof between the two sizes. When the
+// old tree is a perfect one, its root is the first node and isn't in the proof.
+#[cfg(any(test, feature = "constraints"))]
+fn consistency_proof_len(old_size: u64, new_size: u64) -> usize {
+    consistency_steps(old_size, new_size).len() + 1 - old_size.is_power_of_two() as usize
+}
+
+/// Proves that the tree with root `old_root` is a prefix of the tree with
+/// root `new_root`, with the nodes of the consistency proof as witnesses. The
+/// sizes fix the shape of the circuit, and must satisfy `0 < old_size < new_size`.
+#[cfg(feature = "constraints")]
+pub struct ConsistencyCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub old_size: u64,
+    pub new_size: u64,
+
+    // These are the public inputs to the circuit.
+    pub old_root: Root,
+    pub new_root: Root,
+
+    // This is the private witness to the circuit.
+    pub proof: Option<Vec<Root>>,
+}
+
+#[cfg(feature = "constraints")]
+impl ConsistencyCircuit {
+    pub fn new(params: &MerkleParams, proof: &ConsistencyProof, old_root: Root, new_root: Root) -> Self {
+        ConsistencyCircuit {
+            params: params.clone(),
+            old_size: proof.old_size,
+            new_size: proof.new_size,
+            old_root,
+            new_root,
+            proof: Some(proof.path.clone()),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order: the old root, then the new one.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        vec![self.old_root, self.new_root]
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for ConsistencyCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        if self.old_size == 0 || self.old_size >= self.new_size {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        // First, we allocate the public inputs
+        let old_root = RootVar::new_input(ark_relations::ns!(cs, "old_root_var"), || Ok(&self.old_root))?;
+        let new_root = RootVar::new_input(ark_relations::ns!(cs, "new_root_var"), || Ok(&self.new_root))?;
+
+        // Then, we allocate the public parameters as constants:
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the nodes of the proof as private witnesses:
+        let mut nodes = (0..consistency_proof_len(self.old_size, self.new_size))
+            .map(|i| {
+                RootVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+                    self.proof.as_ref().and_then(|path| path.get(i)).ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.old_size.is_power_of_two() {
+            nodes.insert(0, old_root.clone());
+        }
+
+        let hash = |left: &RootVar, right: &RootVar| {
+            <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::evaluate(
+                &two_to_one_crh_params,
+                &left.to_bytes()?,
+                &right.to_bytes()?,
+            )
+        };
+        let _consistency = ark_relations::ns!(cs, "consistency");
+        let (mut f_r, mut s_r) = (nodes[0].clone(), nodes[0].clone());
+        for (node, both) in nodes[1..].iter().zip(consistency_steps(self.old_size, self.new_size)) {
+            if both {
+                f_r = hash(node, &f_r)?;
+                s_r = hash(node, &s_r)?;
+            } else {
+                s_r = hash(&s_r, node)?;
+            }
+        }
+        f_r.enforce_equal(&old_root)?;
+        s_r.enforce_equal(&new_root)
+    }
+}
+
+#[test]
+fn test_append_only_proofs_all_sizes() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut tree = AppendOnlyTree::new(params.clone());
+
+    let mut roots = vec![tree.root().unwrap()];
+    for i in 0..7u8 {
+        tree.append(&[i]).unwrap();
+        roots.push(tree.root().unwrap());
+    }
+
+    // power-of-two sizes agree with the balanced tree
+    let leaves: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i]).collect();
+    let balanced = crate::SimpleMerkleTree::new(
+        &params.leaf_crh_params,
+        &params.two_to_one_crh_params,
+        &leaves,
+    )
+    .unwrap();
+    assert_eq!(roots[4], balanced.root());
+
+    for index in 0..7 {
+        let proof = tree.prove_inclusion(index).unwrap();
+        assert!(verify_inclusion(&params, &[index as u8], &proof, &roots[7]).unwrap());
+        assert!(!verify_inclusion(&params, &[index as u8 + 1], &proof, &roots[7]).unwrap());
+    }
+    for old_size in 0..=7 {
+        let proof = tree.prove_consistency(old_size).unwrap();
+        assert!(verify_consistency(&params, &proof, &roots[old_size], &roots[7]).unwrap());
+        if old_size > 0 && old_size < 7 {
+            assert!(!verify_consistency(&params, &proof, &roots[old_size - 1], &roots[7]).unwrap());
+        }
+    }
+}
+
+#[test]
+fn test_consistency_between_past_sizes() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut tree = AppendOnlyTree::new(params.clone());
+    let mut roots = vec![tree.root().unwrap()];
+    for i in 0..11u8 {
+        tree.append(&[i]).unwrap();
+        roots.push(tree.root().unwrap());
+    }
+
+    for new_size in 1..=11 {
+        for old_size in 1..new_size {
+            let proof = tree.consistency_proof(old_size, new_size).unwrap();
+            assert_eq!(proof.path.len(), consistency_proof_len(old_size as u64, new_size as u64));
+            assert!(verify_consistency(&params, &proof, &roots[old_size], &roots[new_size]).unwrap());
+        }
+    }
+    assert_eq!(tree.consistency_proof(3, 11).unwrap(), tree.prove_consistency(3).unwrap());
+    assert!(tree.consistency_proof(5, 4).is_err());
+    assert!(tree.consistency_proof(5, 12).is_err());
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_consistency_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut tree = AppendOnlyTree::new(params.clone());
+    for i in 0..7u8 {
+        tree.append(&[i]).unwrap();
+    }
+    // a perfect old tree, and one that isn't
+    for old_size in [4, 3].iter().copied() {
+        let proof = tree.consistency_proof(old_size, 7).unwrap();
+        let old_root = tree.root_at(old_size).unwrap();
+        let circuit = ConsistencyCircuit::new(&params, &proof, old_root, tree.root().unwrap());
+        assert_eq!(circuit.public_inputs(), vec![old_root, tree.root().unwrap()]);
+        assert_satisfied_with_report(circuit);
+
+        // a forked history: the old root of another log
+        let forked = tree.root_at(old_size - 1).unwrap();
+        expect_unsatisfied(ConsistencyCircuit::new(&params, &proof, forked, tree.root().unwrap()), "consistency");
+    }
+}
@@ -0,0 +1,34 @@
+//! The arkworks crates and types that appear in this crate's public API,
+//! re-exported so that downstream code can name them without depending on
+//! the exact same arkworks versions itself.
+
+#[cfg(feature = "snark")]
+pub use ark_bls12_381 as bls12_381;
+pub use ark_crypto_primitives as crypto_primitives;
+pub use ark_ec as ec;
+pub use ark_ed_on_bls12_381 as ed_on_bls12_381;
+pub use ark_ff as ff;
+#[cfg(feature = "snark")]
+pub use ark_groth16 as groth16;
+#[cfg(feature = "constraints")]
+pub use ark_r1cs_std as r1cs_std;
+#[cfg(feature = "constraints")]
+pub use ark_relations as relations;
+pub use ark_serialize as serialize;
+#[cfg(feature = "snark")]
+pub use ark_snark as snark;
+pub use ark_std as std;
+
+// The individual types public signatures are written in terms of.
+pub use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+#[cfg(feature = "constraints")]
+pub use ark_crypto_primitives::crh::{CRHGadget, TwoToOneCRHGadget};
+pub use ark_crypto_primitives::merkle_tree::{Config, MerkleTree, Path};
+pub use ark_crypto_primitives::Error;
+pub use ark_ed_on_bls12_381::{EdwardsAffine, EdwardsProjective, Fq, Fr};
+#[cfg(feature = "constraints")]
+pub use ark_r1cs_std::{bits::uint8::UInt8, boolean::Boolean, fields::fp::FpVar};
+#[cfg(feature = "constraints")]
+pub use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, SynthesisError};
+pub use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+pub use ark_std::rand::{CryptoRng, Rng, RngCore};
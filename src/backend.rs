@@ -0,0 +1,217 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::MTreeVerification;
+use crate::error::{ErrorContext, MerkleError, ResultExt};
+use crate::index::LeafIndex;
+use crate::leaf::Leaf;
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_std::vec::Vec;
+
+// The operations application code needs from a tree, so the tree behind an
+// application can be swapped without touching the application.
+//
+// Every backend is a fixed-size tree over `MerkleParams` whose roots and paths
+// are those of `SimpleMerkleTree` over the same leaves: a backend changes how
+// the tree is stored and updated, never what it commits to. Paths from any
+// backend verify with `MerkleParams::verify_path` and feed the same circuits.
+// `conformance::check` tests exactly that, and any new backend should pass it.
+//
+// `EagerTree` is the reference: it keeps every leaf and rebuilds the whole
+// tree on update, like the application modules always did. `level_tree::LevelTree`
+// keeps every level of digests and rehashes only the updated path.
+
+pub trait MerkleBackend {
+    fn root(&self) -> Root;
+
+    /// The path of the leaf at `index`.
+    fn prove(&self, index: usize) -> Result<SimplePath, MerkleError>;
+
+    /// Replaces the leaf at `index`, returning the new root. On error the tree
+    /// is left as it was.
+    fn update(&mut self, index: usize, leaf: Leaf) -> Result<Root, MerkleError>;
+
+    /// The number of leaves, padding included.
+    fn leaf_count(&self) -> u64;
+}
+
+/// A tree that keeps all its leaves and rebuilds itself on every update.
+#[derive(Clone)]
+pub struct EagerTree {
+    params: MerkleParams,
+    leaves: Vec<Leaf>,
+    tree: SimpleMerkleTree,
+}
+
+impl EagerTree {
+    /// The tree over `leaves`; their number must be a power of two.
+    pub fn new<L: Into<Leaf>>(params: MerkleParams, leaves: impl IntoIterator<Item = L>) -> Result<Self, MerkleError> {
+        let leaves: Vec<Leaf> = leaves.into_iter().map(Into::into).collect();
+        Ok(EagerTree {
+            tree: params.build_tree(&leaves)?,
+            params,
+            leaves,
+        })
+    }
+
+    /// A tree of `capacity` leaves, rounded up to a power of two, all set to `padding`.
+    pub fn with_capacity(params: MerkleParams, capacity: usize, padding: impl Into<Leaf>) -> Result<Self, MerkleError> {
+        let padding = padding.into();
+        Self::new(params, vec![padding; capacity.next_power_of_two().max(2)])
+    }
+
+    pub fn params(&self) -> &MerkleParams {
+        &self.params
+    }
+
+    pub fn leaf(&self, index: usize) -> Option<&Leaf> {
+        self.leaves.get(index)
+    }
+
+    /// Checks that `path` opens `leaf` against `root`, with this tree's parameters.
+    pub fn verify(&self, root: &Root, leaf: impl AsRef<[u8]>, path: &SimplePath) -> Result<bool, MerkleError> {
+        self.params.verify_path(path, root, leaf)
+    }
+
+    /// The membership circuit for the leaf at `index`, with its path in this tree.
+    #[cfg(feature = "constraints")]
+    pub fn circuit(&self, index: usize) -> Result<MTreeVerification, MerkleError> {
+        let path = self.prove(index)?;
+        Ok(<MTreeVerification>::new(&self.params, self.root(), &self.leaves[index], path))
+    }
+
+    fn check_index(&self, index: usize) -> Result<LeafIndex, MerkleError> {
+        LeafIndex::checked_new(index as u64, self.leaves.len() as u64)
+    }
+}
+
+impl MerkleBackend for EagerTree {
+    fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    fn prove(&self, index: usize) -> Result<SimplePath, MerkleError> {
+        generate_proof(&self.tree, self.check_index(index)?)
+    }
+
+    fn update(&mut self, index: usize, leaf: Leaf) -> Result<Root, MerkleError> {
+        self.check_index(index)?;
+        let mut leaves = self.leaves.clone();
+        leaves[index] = leaf;
+        self.tree = self
+            .params
+            .build_tree(&leaves)
+            .with_context(|| ErrorContext::new("update").index(index))?;
+        self.leaves = leaves;
+        Ok(self.root())
+    }
+
+    fn leaf_count(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+}
+
+/// The checks every `MerkleBackend` has to pass, over trees of several sizes
+/// and over `gen::adversarial_cases`.
+///
+/// A backend's own tests call `check` with a constructor for it:
+///
+/// ```ignore
+/// conformance::check(&params, |params, leaves| MyTree::new(params.clone(), leaves));
+/// ```
+#[cfg(any(test, feature = "testing"))]
+pub mod conformance {
+    use super::*;
+
+    fn leaves(n: usize, salt: u8) -> Vec<Leaf> {
+        (0..n).map(|i| Leaf::from(vec![salt, i as u8, (i >> 8) as u8])).collect()
+    }
+
+    fn reference_root(params: &MerkleParams, leaves: &[Leaf]) -> Root {
+        SimpleMerkleTree::new(&params.leaf_crh_params, &params.two_to_one_crh_params, leaves)
+            .unwrap()
+            .root()
+    }
+
+    /// Runs the suite against the backends `make` builds over given leaves
+    /// (always a power of two of them), panicking on the first violation.
+    pub fn check<B, F>(params: &MerkleParams, make: F)
+    where
+        B: MerkleBackend,
+        F: Fn(&MerkleParams, Vec<Leaf>) -> Result<B, MerkleError>,
+    {
+        for &n in &[2usize, 4, 16] {
+            let mut expected = leaves(n, 0);
+            let mut backend = make(params, expected.clone()).unwrap();
+            assert_eq!(backend.leaf_count(), n as u64, "leaf count of a {}-leaf tree", n);
+            assert_eq!(backend.root(), reference_root(params, &expected), "root of a {}-leaf tree", n);
+
+            // every leaf opens at its own index, and only there
+            for (index, leaf) in expected.iter().enumerate() {
+                let path = backend.prove(index).unwrap();
+                assert_eq!(path.leaf_index, index);
+                assert!(params.verify_path(&path, &backend.root(), leaf).unwrap());
+                let other = &expected[(index + 1) % n];
+                assert!(!params.verify_path(&path, &backend.root(), other).unwrap());
+            }
+            assert!(matches!(
+                backend.prove(n),
+                Err(MerkleError::LeafIndexOutOfRange { index, len }) if index == n && len == n
+            ));
+
+            // updates move the root to the reference root of the new leaves
+            for (index, leaf) in leaves(n, 1).into_iter().enumerate().step_by(3) {
+                let old_root = backend.root();
+                let old_path = backend.prove(index).unwrap();
+                let old_leaf = ark_std::mem::replace(&mut expected[index], leaf.clone());
+                let new_root = backend.update(index, leaf.clone()).unwrap();
+                assert_eq!(new_root, backend.root());
+                assert_eq!(new_root, reference_root(params, &expected), "root after updating leaf {}", index);
+                assert!(!params.verify_path(&old_path, &new_root, &old_leaf).unwrap());
+                assert!(params.verify_path(&backend.prove(index).unwrap(), &new_root, &leaf).unwrap());
+                assert_ne!(new_root, old_root);
+            }
+            assert_eq!(backend.leaf_count(), n as u64);
+
+            // failed updates change nothing
+            let root = backend.root();
+            assert!(matches!(
+                backend.update(n, Leaf::default()).unwrap_err().root_cause(),
+                MerkleError::LeafIndexOutOfRange { .. }
+            ));
+            assert!(matches!(
+                backend.update(0, Leaf::from(vec![0u8; MAX_LEAF_LEN + 1])).unwrap_err().root_cause(),
+                MerkleError::LeafTooLong { .. }
+            ));
+            assert_eq!(backend.root(), root);
+
+            // setting a leaf to its current value is a no-op
+            assert_eq!(backend.update(1, expected[1].clone()).unwrap(), root);
+        }
+
+        for case in crate::gen::adversarial_cases() {
+            let expected: Vec<Leaf> = case.leaves.into_iter().map(Leaf::from).collect();
+            let backend = make(params, expected.clone()).unwrap();
+            assert_eq!(backend.root(), reference_root(params, &expected), "root of {}", case.name);
+            for (index, leaf) in expected.iter().enumerate() {
+                let path = backend.prove(index).unwrap();
+                assert!(params.verify_path(&path, &backend.root(), leaf).unwrap(), "{}", case.name);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_eager_tree_conformance() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    conformance::check(&params, |params, leaves| EagerTree::new(params.clone(), leaves));
+}
+
+#[test]
+fn test_eager_tree_with_capacity() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let tree = EagerTree::with_capacity(params.clone(), 3, vec![0u8; 4]).unwrap();
+    assert_eq!(tree.leaf_count(), 4);
+    assert_eq!(tree.leaf(3), Some(&Leaf::from(vec![0u8; 4])));
+    assert_eq!(tree.leaf(4), None);
+    assert_eq!(tree.root(), params.build_tree(vec![vec![0u8; 4]; 4]).unwrap().root());
+}
@@ -0,0 +1,110 @@
+use crate::common::*;
+use crate::constraints::MTreeVerification;
+use crate::error::MerkleError;
+use crate::snark::{self, Proof, ProvingKey};
+use crate::{SimpleMerkleTree, SimplePath};
+use ark_std::rand::{rngs::StdRng, CryptoRng, RngCore, SeedableRng};
+use rayon::prelude::*;
+
+// Many paths and Groth16 proofs at once, on a thread pool of its own.
+//
+// `common::generate_proofs` already opens paths on rayon's global pool with
+// the `parallel` feature. A service answering proof requests wants to bound
+// the threads proving takes from everything else it runs, so `BatchProver`
+// owns a pool of a chosen size and does its work there: the paths, and the
+// proofs, which take far longer. All proofs of a batch share the proving key.
+//
+// The caller's generator can't be shared between threads, so it only draws
+// one seed per proof, in order; each proof then runs on a ChaCha20 generator
+// (`StdRng`) of its own seed. A batch is as random as the caller's generator
+// and, for a deterministic one, reproducible whatever the scheduling.
+// arkworks errors aren't `Send`, so a batch that fails somewhere is run again
+// in order to return the first error, like `generate_proofs` does.
+
+/// Opens paths and makes membership proofs on a thread pool of its own.
+pub struct BatchProver {
+    pool: rayon::ThreadPool,
+}
+
+impl BatchProver {
+    /// A prover with `threads` worker threads, or as many as there are CPUs for 0.
+    pub fn new(threads: usize) -> Result<Self, rayon::ThreadPoolBuildError> {
+        Ok(BatchProver {
+            pool: rayon::ThreadPoolBuilder::new().num_threads(threads).build()?,
+        })
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// The paths of the leaves at `indices`, in their order.
+    pub fn generate_proofs_par(&self, tree: &SimpleMerkleTree, indices: &[usize]) -> Result<Vec<SimplePath>, MerkleError> {
+        self.pool.install(|| generate_proofs(tree, indices))
+    }
+
+    /// A Groth16 membership proof for each `(index, leaf)` of `requests`
+    /// against the root of `tree`, in their order, all with `pk`.
+    pub fn prove_membership_batch<R: RngCore + CryptoRng>(
+        &self,
+        pk: &ProvingKey,
+        params: &MerkleParams,
+        tree: &SimpleMerkleTree,
+        requests: &[(usize, &[u8])],
+        rng: &mut R,
+    ) -> Result<Vec<Proof>, MerkleError> {
+        let indices: Vec<usize> = requests.iter().map(|(index, _)| *index).collect();
+        let paths = self.generate_proofs_par(tree, &indices)?;
+        let seeds: Vec<[u8; 32]> = requests
+            .iter()
+            .map(|_| {
+                let mut seed = [0u8; 32];
+                rng.fill_bytes(&mut seed);
+                seed
+            })
+            .collect();
+        let root = tree.root();
+        let prove = |i: usize| {
+            let circuit = <MTreeVerification>::new(params, root, requests[i].1, paths[i].clone());
+            snark::prove(pk, circuit, &mut StdRng::from_seed(seeds[i]))
+        };
+
+        let proofs: Option<Vec<Proof>> = self
+            .pool
+            .install(|| (0..requests.len()).into_par_iter().map(|i| prove(i).ok()).collect());
+        match proofs {
+            Some(proofs) => Ok(proofs),
+            None => (0..requests.len()).map(prove).collect(),
+        }
+    }
+}
+
+#[test]
+fn test_batch_prover() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let prover = BatchProver::new(2).unwrap();
+    assert_eq!(prover.threads(), 2);
+
+    let indices = [6, 0, 3];
+    let paths = prover.generate_proofs_par(&tree, &indices).unwrap();
+    for (path, index) in paths.iter().zip(indices.iter()) {
+        assert_eq!(path.leaf_index, *index);
+    }
+    assert!(matches!(
+        prover.generate_proofs_par(&tree, &[1, 8]),
+        Err(MerkleError::LeafIndexOutOfRange { index: 8, len: 8 })
+    ));
+
+    let (pk, vk) = snark::setup_groth16(&params, tree.height(), 8, &mut rng).unwrap();
+    let requests: Vec<(usize, &[u8])> = indices.iter().map(|i| (*i, &leaves[*i][..])).collect();
+    let proofs = prover.prove_membership_batch(&pk, &params, &tree, &requests, &mut rng).unwrap();
+    assert_eq!(proofs.len(), 3);
+    for ((_, leaf), proof) in requests.iter().zip(&proofs) {
+        assert!(snark::verify_membership_proof(&vk, &tree.root(), leaf, proof).unwrap());
+    }
+    // a proof goes with its own leaf only
+    assert!(!snark::verify_membership_proof(&vk, &tree.root(), requests[1].1, &proofs[0]).unwrap());
+}
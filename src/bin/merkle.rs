@@ -0,0 +1,313 @@
+//! Builds trees from files and opens and checks their paths, for scripts.
+//!
+//! ```text
+//! merkle build --input leaves.txt [--params-seed <hex>] --out tree.bin
+//! merkle root --tree tree.bin
+//! merkle prove --tree tree.bin --index 5 --out proof.bin
+//! merkle verify --root <hex> --leaf <hex> --proof proof.bin [--params-seed <hex>]
+//! merkle snark-setup --tree tree.bin --leaf-len 32 --pk pk.bin --vk vk.bin
+//! merkle snark-prove --tree tree.bin --index 5 --pk pk.bin --out proof.bin
+//! merkle snark-verify --vk vk.bin --root <hex> --leaf <hex> --proof proof.bin
+//! ```
+//!
+//! `leaves.txt` has one hex-encoded leaf per line; blank lines are skipped.
+//! The hash parameters come from the 32-byte `--params-seed`, by default the
+//! seed of `kit::default_params`, and a tree file remembers the seed it was
+//! built with. Trees that aren't a power of two leaves are padded with
+//! `PADDING_LEAF`, which can't be opened.
+//!
+//! `build` and `root` print the hex root, `prove` the hex leaf it opened, and
+//! `verify` prints `valid` and exits with 0, or `invalid` and exits with 1.
+//! Anything else that goes wrong is reported on stderr with exit code 2.
+//!
+//! With the `snark` feature, the `snark-` commands do the same with Groth16
+//! proofs that reveal the root and the leaf but not the path. The keys are
+//! for one tree height and one leaf length, which their files start with;
+//! `snark-setup` makes them with the operating system's randomness, so
+//! whoever runs it must be trusted to forget it.
+
+use merkle_tree_tutorial::backend::MerkleBackend;
+use merkle_tree_tutorial::common::{from_hex, setup_params_from_seed, MerkleParams, PADDING_LEAF};
+use merkle_tree_tutorial::kit::DEFAULT_PARAMS_SEED;
+use merkle_tree_tutorial::leaf::Leaf;
+use merkle_tree_tutorial::level_tree::LevelTree;
+use merkle_tree_tutorial::root::MerkleRoot;
+use merkle_tree_tutorial::serialization::{deserialize_path, serialize_path};
+#[cfg(feature = "snark")]
+use merkle_tree_tutorial::{
+    ark::serialize::{CanonicalDeserialize, CanonicalSerialize},
+    rng::OsRngProvider,
+    snark::{self, Proof, ProvingKey, VerifyingKey},
+};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::process::exit;
+
+const USAGE: &str = "usage:
+  merkle build --input <leaves.txt> [--params-seed <hex>] --out <tree.bin>
+  merkle root --tree <tree.bin>
+  merkle prove --tree <tree.bin> --index <n> --out <proof.bin>
+  merkle verify --root <hex> --leaf <hex> --proof <proof.bin> [--params-seed <hex>]
+  merkle snark-setup --tree <tree.bin> --leaf-len <n> --pk <pk.bin> --vk <vk.bin>
+  merkle snark-prove --tree <tree.bin> --index <n> --pk <pk.bin> --out <proof.bin>
+  merkle snark-verify --vk <vk.bin> --root <hex> --leaf <hex> --proof <proof.bin>";
+
+// A tree file: the params seed, the number of leaves given without the
+// padding as a u64, then the tree as `LevelTree::save_to` writes it.
+struct TreeFile {
+    seed: [u8; 32],
+    len: usize,
+    tree: LevelTree,
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(code) => exit(code),
+        Err(message) => {
+            eprintln!("merkle: {}", message);
+            exit(2);
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<i32, String> {
+    let (command, rest) = args.split_first().ok_or_else(|| USAGE.to_string())?;
+    let flags = parse_flags(rest)?;
+    match command.as_str() {
+        "build" => {
+            check_flags(&flags, &["input", "params-seed", "out"])?;
+            let seed = params_seed(&flags)?;
+            let leaves = read_leaves(required(&flags, "input")?)?;
+            let tree = build(seed, leaves)?;
+            write_tree(required(&flags, "out")?, &tree)?;
+            println!("{}", MerkleRoot::from(tree.tree.root()).to_hex());
+        }
+        "root" => {
+            check_flags(&flags, &["tree"])?;
+            let tree = read_tree(required(&flags, "tree")?)?;
+            println!("{}", MerkleRoot::from(tree.tree.root()).to_hex());
+        }
+        "prove" => {
+            check_flags(&flags, &["tree", "index", "out"])?;
+            let tree = read_tree(required(&flags, "tree")?)?;
+            let index = leaf_index(&flags, &tree)?;
+            let path = tree.tree.prove(index).map_err(|e| e.to_string())?;
+            let out = required(&flags, "out")?;
+            fs::write(out, serialize_path(&path)).map_err(|e| format!("cannot write {}: {}", out, e))?;
+            println!("{}", merkle_tree_tutorial::common::to_hex(tree.tree.leaf(index).unwrap().as_ref()));
+        }
+        "verify" => {
+            check_flags(&flags, &["root", "leaf", "proof", "params-seed"])?;
+            let params = params_from_seed(params_seed(&flags)?)?;
+            let root = MerkleRoot::from_hex(required(&flags, "root")?).map_err(|e| format!("--root: {}", e))?;
+            let leaf = Leaf::from_hex(required(&flags, "leaf")?).map_err(|e| format!("--leaf: {}", e))?;
+            let file = required(&flags, "proof")?;
+            let bytes = fs::read(file).map_err(|e| format!("cannot read {}: {}", file, e))?;
+            let path = deserialize_path(&bytes).map_err(|e| format!("{} is not a proof: {}", file, e))?;
+            let valid = params.verify_path(&path, &root.into_inner(), leaf).unwrap_or(false);
+            println!("{}", if valid { "valid" } else { "invalid" });
+            return Ok(if valid { 0 } else { 1 });
+        }
+        #[cfg(feature = "snark")]
+        "snark-setup" => {
+            check_flags(&flags, &["tree", "leaf-len", "pk", "vk"])?;
+            let tree = read_tree(required(&flags, "tree")?)?;
+            let leaf_len = required(&flags, "leaf-len")?;
+            let leaf_len: usize = leaf_len.parse().map_err(|_| format!("--leaf-len {} is not a length", leaf_len))?;
+            let shape = KeyShape {
+                height: tree.tree.depth() + 1,
+                leaf_len,
+            };
+            let params = params_from_seed(tree.seed)?;
+            let (pk, vk) = snark::setup_groth16(&params, shape.height, shape.leaf_len, &mut OsRngProvider::default())
+                .map_err(|e| format!("setup failed: {}", e))?;
+            write_key(required(&flags, "pk")?, shape, &pk)?;
+            write_key(required(&flags, "vk")?, shape, &vk)?;
+        }
+        #[cfg(feature = "snark")]
+        "snark-prove" => {
+            check_flags(&flags, &["tree", "index", "pk", "out"])?;
+            let tree = read_tree(required(&flags, "tree")?)?;
+            let index = leaf_index(&flags, &tree)?;
+            let (shape, pk): (_, ProvingKey) = read_key(required(&flags, "pk")?)?;
+            let leaf = tree.tree.leaf(index).unwrap().clone();
+            if shape.height != tree.tree.depth() + 1 || shape.leaf_len != leaf.len() {
+                return Err(format!(
+                    "the key is for trees of height {} and {}-byte leaves, not height {} and {} bytes",
+                    shape.height,
+                    shape.leaf_len,
+                    tree.tree.depth() + 1,
+                    leaf.len()
+                ));
+            }
+            let path = tree.tree.prove(index).map_err(|e| e.to_string())?;
+            let params = params_from_seed(tree.seed)?;
+            let root = tree.tree.root();
+            let proof = snark::prove_membership(&pk, &params, root, leaf.as_ref(), path, &mut OsRngProvider::default())
+                .map_err(|e| format!("proving failed: {}", e))?;
+            write_canonical(required(&flags, "out")?, &proof)?;
+            println!("{}", merkle_tree_tutorial::common::to_hex(leaf.as_ref()));
+        }
+        #[cfg(feature = "snark")]
+        "snark-verify" => {
+            check_flags(&flags, &["vk", "root", "leaf", "proof"])?;
+            let (shape, vk): (_, VerifyingKey) = read_key(required(&flags, "vk")?)?;
+            let root = MerkleRoot::from_hex(required(&flags, "root")?).map_err(|e| format!("--root: {}", e))?;
+            let leaf = Leaf::from_hex(required(&flags, "leaf")?).map_err(|e| format!("--leaf: {}", e))?;
+            let file = required(&flags, "proof")?;
+            let bytes = fs::read(file).map_err(|e| format!("cannot read {}: {}", file, e))?;
+            let proof = Proof::deserialize(&bytes[..]).map_err(|e| format!("{} is not a proof: {}", file, e))?;
+            // a leaf of another length has other public inputs than the key
+            let valid = leaf.len() == shape.leaf_len
+                && snark::verify_membership_proof(&vk, &root.into_inner(), leaf.as_ref(), &proof).unwrap_or(false);
+            println!("{}", if valid { "valid" } else { "invalid" });
+            return Ok(if valid { 0 } else { 1 });
+        }
+        _ => return Err(format!("unknown command {}\n{}", command, USAGE)),
+    }
+    Ok(0)
+}
+
+// `--name value` pairs, each name at most once.
+fn parse_flags(args: &[String]) -> Result<BTreeMap<&str, &str>, String> {
+    let mut flags = BTreeMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .ok_or_else(|| format!("unexpected argument {}\n{}", arg, USAGE))?;
+        let value = args.next().ok_or_else(|| format!("--{} needs a value", name))?;
+        if flags.insert(name, value.as_str()).is_some() {
+            return Err(format!("--{} was given more than once", name));
+        }
+    }
+    Ok(flags)
+}
+
+fn check_flags(flags: &BTreeMap<&str, &str>, known: &[&str]) -> Result<(), String> {
+    match flags.keys().find(|name| !known.contains(name)) {
+        Some(name) => Err(format!("unknown option --{}\n{}", name, USAGE)),
+        None => Ok(()),
+    }
+}
+
+fn required<'a>(flags: &BTreeMap<&str, &'a str>, name: &str) -> Result<&'a str, String> {
+    flags.get(name).copied().ok_or_else(|| format!("--{} is missing\n{}", name, USAGE))
+}
+
+// `--index`, checked against the leaves the tree was built from.
+fn leaf_index(flags: &BTreeMap<&str, &str>, tree: &TreeFile) -> Result<usize, String> {
+    let index = required(flags, "index")?;
+    let index: usize = index.parse().map_err(|_| format!("--index {} is not a leaf index", index))?;
+    if index >= tree.len {
+        return Err(format!("leaf index {} is out of range for a tree of {} leaves", index, tree.len));
+    }
+    Ok(index)
+}
+
+fn params_seed(flags: &BTreeMap<&str, &str>) -> Result<[u8; 32], String> {
+    let hex = match flags.get("params-seed") {
+        Some(hex) => hex,
+        None => return Ok(DEFAULT_PARAMS_SEED),
+    };
+    let bytes = from_hex(hex).filter(|bytes| bytes.len() == 32);
+    let bytes = bytes.ok_or_else(|| "--params-seed must be 32 bytes of hex".to_string())?;
+    let mut seed = [0u8; 32];
+    seed.copy_from_slice(&bytes);
+    Ok(seed)
+}
+
+fn params_from_seed(seed: [u8; 32]) -> Result<MerkleParams, String> {
+    setup_params_from_seed(seed).map_err(|e| format!("parameter setup failed: {}", e))
+}
+
+fn read_leaves(file: &str) -> Result<Vec<Leaf>, String> {
+    let text = fs::read_to_string(file).map_err(|e| format!("cannot read {}: {}", file, e))?;
+    let leaves = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| Leaf::from_hex(line.trim()).map_err(|e| format!("{} line {}: {}", file, number + 1, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if leaves.is_empty() {
+        return Err(format!("{} has no leaves", file));
+    }
+    Ok(leaves)
+}
+
+fn build(seed: [u8; 32], mut leaves: Vec<Leaf>) -> Result<TreeFile, String> {
+    let len = leaves.len();
+    leaves.resize(len.next_power_of_two().max(2), Leaf::from(PADDING_LEAF));
+    let tree = LevelTree::new(params_from_seed(seed)?, leaves).map_err(|e| e.to_string())?;
+    Ok(TreeFile { seed, len, tree })
+}
+
+fn write_tree(file: &str, tree: &TreeFile) -> Result<(), String> {
+    let error = |e: &dyn std::fmt::Display| format!("cannot write {}: {}", file, e);
+    let mut writer = BufWriter::new(File::create(file).map_err(|e| error(&e))?);
+    writer.write_all(&tree.seed).map_err(|e| error(&e))?;
+    writer.write_all(&(tree.len as u64).to_le_bytes()).map_err(|e| error(&e))?;
+    tree.tree.save_to(&mut writer).map_err(|e| error(&e))?;
+    writer.flush().map_err(|e| error(&e))
+}
+
+fn read_tree(file: &str) -> Result<TreeFile, String> {
+    let error = |e: &dyn std::fmt::Display| format!("{} is not a tree file: {}", file, e);
+    let mut reader = BufReader::new(File::open(file).map_err(|e| format!("cannot read {}: {}", file, e))?);
+    let mut seed = [0u8; 32];
+    reader.read_exact(&mut seed).map_err(|e| error(&e))?;
+    let mut len = [0u8; 8];
+    reader.read_exact(&mut len).map_err(|e| error(&e))?;
+    let len = u64::from_le_bytes(len) as usize;
+    let tree = LevelTree::load_from(&mut reader, params_from_seed(seed)?, false).map_err(|e| error(&e))?;
+    if len == 0 || len > tree.leaf_count() as usize {
+        return Err(error(&"the leaf count doesn't fit the tree"));
+    }
+    Ok(TreeFile { seed, len, tree })
+}
+
+// What a Groth16 key is for. Key files start with it, the height and the leaf
+// length as u64s, followed by the key as `CanonicalSerialize` writes it.
+#[cfg(feature = "snark")]
+#[derive(Clone, Copy)]
+struct KeyShape {
+    height: usize,
+    leaf_len: usize,
+}
+
+#[cfg(feature = "snark")]
+fn write_key<K: CanonicalSerialize>(file: &str, shape: KeyShape, key: &K) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(shape.height as u64).to_le_bytes());
+    bytes.extend_from_slice(&(shape.leaf_len as u64).to_le_bytes());
+    key.serialize(&mut bytes).map_err(|e| format!("cannot write {}: {}", file, e))?;
+    fs::write(file, bytes).map_err(|e| format!("cannot write {}: {}", file, e))
+}
+
+#[cfg(feature = "snark")]
+fn read_key<K: CanonicalDeserialize>(file: &str) -> Result<(KeyShape, K), String> {
+    let bytes = fs::read(file).map_err(|e| format!("cannot read {}: {}", file, e))?;
+    if bytes.len() < 16 {
+        return Err(format!("{} is not a key file", file));
+    }
+    let number = |at: usize| {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&bytes[at..at + 8]);
+        u64::from_le_bytes(word) as usize
+    };
+    let shape = KeyShape {
+        height: number(0),
+        leaf_len: number(8),
+    };
+    let key = K::deserialize(&bytes[16..]).map_err(|e| format!("{} is not a key file: {}", file, e))?;
+    Ok((shape, key))
+}
+
+#[cfg(feature = "snark")]
+fn write_canonical<T: CanonicalSerialize>(file: &str, value: &T) -> Result<(), String> {
+    let mut bytes = Vec::new();
+    value.serialize(&mut bytes).map_err(|e| format!("cannot write {}: {}", file, e))?;
+    fs::write(file, bytes).map_err(|e| format!("cannot write {}: {}", file, e))
+}
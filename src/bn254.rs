@@ -0,0 +1,223 @@
+use crate::common::{LeafWindow, TreeParams, TwoToOneWindow};
+use crate::constraints::{MerkleCircuitConfig, MerkleTreeVerification};
+use crate::error::MerkleError;
+use ark_bn254::{Bn254, Fq, Fq2, G1Affine, G2Affine};
+use ark_crypto_primitives::crh::injective_map::constraints::{PedersenCRHCompressorGadget, TECompressorGadget};
+use ark_crypto_primitives::crh::injective_map::{PedersenCRHCompressor, TECompressor};
+use ark_crypto_primitives::merkle_tree::{Config, Path};
+use ark_ed_on_bn254::constraints::EdwardsVar;
+use ark_ed_on_bn254::EdwardsProjective;
+use ark_ff::{BigInteger, PrimeField, ToConstraintField};
+use ark_groth16::Groth16;
+use ark_snark::SNARK;
+use ark_std::rand::{CryptoRng, RngCore};
+use ark_std::vec::Vec;
+
+// Membership proofs the EVM can check.
+//
+// The trees of `common` hash on Jubjub, whose base field is the scalar field
+// of BLS12-381, so their Groth16 proofs are over BLS12-381, which only the
+// EIP-2537 precompiles can check. The precompiles every EVM chain has are
+// EIP-196/197's, for BN254. Here are the same Pedersen trees, with the same
+// windows, over Baby Jubjub (ark-ed-on-bn254), whose base field is BN254's
+// scalar field: `Bn254MerkleCircuit` is the generic membership circuit over
+// it, and its Groth16 proofs are over BN254.
+//
+// The roots are different from those of `common`, being points on another
+// curve, and so are the parameters; a tree is either, not both. Everything
+// else is the generic tree and circuit: `Bn254Params::build_tree` builds the
+// tree, `verify_path` checks a path natively, and `prove_membership` and
+// `verify_membership_proof` mirror `snark`'s.
+//
+// `proof_calldata` and `input_words` are in the EIP-197 encoding: a base
+// field element is 32 big-endian bytes, a G1 point `x || y`, a G2 point
+// `x.c1 || x.c0 || y.c1 || y.c0` (the imaginary part first), and the point
+// at infinity all zeros.
+
+/// The EIP-196 address of G1 addition.
+pub const G1_ADD_PRECOMPILE: u8 = 0x06;
+/// The EIP-196 address of G1 scalar multiplication.
+pub const G1_MUL_PRECOMPILE: u8 = 0x07;
+/// The EIP-197 address of the pairing check.
+pub const PAIRING_PRECOMPILE: u8 = 0x08;
+
+/// The scalar field of BN254, and the base field of Baby Jubjub, which the
+/// membership circuit lives in.
+pub type Bn254F = ark_bn254::Fr;
+
+pub type LeafHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, LeafWindow>;
+pub type TwoToOneHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, TwoToOneWindow>;
+pub type LeafHashGadget =
+    PedersenCRHCompressorGadget<EdwardsProjective, TECompressor, LeafWindow, EdwardsVar, TECompressorGadget>;
+pub type TwoToOneHashGadget =
+    PedersenCRHCompressorGadget<EdwardsProjective, TECompressor, TwoToOneWindow, EdwardsVar, TECompressorGadget>;
+
+/// The trees of this crate, over Baby Jubjub.
+#[derive(Clone)]
+pub struct Bn254MerkleConfig;
+
+impl Config for Bn254MerkleConfig {
+    type LeafHash = LeafHash;
+    type TwoToOneHash = TwoToOneHash;
+}
+
+/// The membership circuit of `Bn254MerkleConfig` trees, over the scalar
+/// field of BN254.
+pub struct Bn254MerkleCircuit;
+
+impl MerkleCircuitConfig for Bn254MerkleCircuit {
+    type Config = Bn254MerkleConfig;
+    type ConstraintF = Bn254F;
+    type LeafHashGadget = LeafHashGadget;
+    type TwoToOneHashGadget = TwoToOneHashGadget;
+}
+
+pub type Bn254Params = TreeParams<Bn254MerkleConfig>;
+pub type Bn254Path = Path<Bn254MerkleConfig>;
+pub type Bn254MerkleTree = ark_crypto_primitives::merkle_tree::MerkleTree<Bn254MerkleConfig>;
+pub type Bn254Circuit = MerkleTreeVerification<Bn254MerkleCircuit>;
+
+pub type ProvingKey = ark_groth16::ProvingKey<Bn254>;
+pub type VerifyingKey = ark_groth16::VerifyingKey<Bn254>;
+pub type Proof = ark_groth16::Proof<Bn254>;
+
+/// The circuit proving that `leaf` opens `path` against `root`.
+pub fn membership_circuit(params: &Bn254Params, root: Bn254F, leaf: &[u8], path: Bn254Path) -> Bn254Circuit {
+    MerkleTreeVerification {
+        leaf_crh_params: params.leaf_crh_params.clone(),
+        two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+        root,
+        leaf: leaf.to_vec(),
+        auth_path: Some(path),
+    }
+}
+
+/// The public inputs of a membership proof: the root, then the leaf bytes
+/// packed like `UInt8::new_input_vec` packs them.
+pub fn public_inputs(root: &Bn254F, leaf: &[u8]) -> Vec<Bn254F> {
+    let mut inputs = vec![*root];
+    inputs.extend(<[u8] as ToConstraintField<Bn254F>>::to_field_elements(leaf).unwrap());
+    inputs
+}
+
+/// The keys for membership proofs in trees of `tree_height` levels (leaves
+/// and root included) over leaves of `leaf_len` bytes.
+pub fn setup_groth16<R: RngCore + CryptoRng>(
+    params: &Bn254Params,
+    tree_height: usize,
+    leaf_len: usize,
+    rng: &mut R,
+) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+    let path = Bn254Path {
+        leaf_sibling_hash: Default::default(),
+        auth_path: vec![Default::default(); tree_height - 2],
+        leaf_index: 0,
+    };
+    let circuit = membership_circuit(params, Default::default(), &vec![0u8; leaf_len], path);
+    Ok(Groth16::<Bn254>::circuit_specific_setup(circuit, rng)?)
+}
+
+/// Proves that `leaf` opens `path` against `root`, revealing the root and
+/// the leaf but not the path.
+pub fn prove_membership<R: RngCore + CryptoRng>(
+    pk: &ProvingKey,
+    params: &Bn254Params,
+    root: Bn254F,
+    leaf: &[u8],
+    path: Bn254Path,
+    rng: &mut R,
+) -> Result<Proof, MerkleError> {
+    Ok(Groth16::<Bn254>::prove(pk, membership_circuit(params, root, leaf, path), rng)?)
+}
+
+/// Checks a proof that `leaf` is in the tree of `root`.
+pub fn verify_membership_proof(
+    vk: &VerifyingKey,
+    root: &Bn254F,
+    leaf: &[u8],
+    proof: &Proof,
+) -> Result<bool, MerkleError> {
+    Ok(Groth16::<Bn254>::verify(vk, &public_inputs(root, leaf), proof)?)
+}
+
+/// `a || b || c`, 256 bytes, as the pairing precompile takes the points.
+pub fn proof_calldata(proof: &Proof) -> Vec<u8> {
+    let mut calldata = g1_bytes(&proof.a);
+    calldata.extend(g2_bytes(&proof.b));
+    calldata.extend(g1_bytes(&proof.c));
+    calldata
+}
+
+/// The public inputs of a proof that `leaf` is in the tree of `root`, as
+/// big-endian `uint256`s.
+pub fn input_words(root: &Bn254F, leaf: &[u8]) -> Vec<[u8; 32]> {
+    public_inputs(root, leaf)
+        .iter()
+        .map(|input| {
+            let mut word = [0u8; 32];
+            word.copy_from_slice(&input.into_repr().to_bytes_be());
+            word
+        })
+        .collect()
+}
+
+fn fq_bytes(x: &Fq) -> Vec<u8> {
+    x.into_repr().to_bytes_be()
+}
+
+fn fq2_bytes(x: &Fq2) -> Vec<u8> {
+    let mut bytes = fq_bytes(&x.c1);
+    bytes.extend(fq_bytes(&x.c0));
+    bytes
+}
+
+fn g1_bytes(point: &G1Affine) -> Vec<u8> {
+    if point.infinity {
+        return vec![0u8; 64];
+    }
+    let mut bytes = fq_bytes(&point.x);
+    bytes.extend(fq_bytes(&point.y));
+    bytes
+}
+
+fn g2_bytes(point: &G2Affine) -> Vec<u8> {
+    if point.infinity {
+        return vec![0u8; 128];
+    }
+    let mut bytes = fq2_bytes(&point.x);
+    bytes.extend(fq2_bytes(&point.y));
+    bytes
+}
+
+#[test]
+fn test_bn254_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = Bn254Params::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let path = tree.generate_proof(2).unwrap();
+    assert!(params.verify_path(&path, &tree.root(), &leaves[2]).unwrap());
+
+    assert_satisfied_with_report(membership_circuit(&params, tree.root(), &leaves[2], path.clone()));
+    expect_unsatisfied(membership_circuit(&params, tree.root(), &leaves[3], path), "membership");
+}
+
+#[test]
+fn test_bn254_groth16_round_trip() {
+    let mut rng = ark_std::test_rng();
+    let params = Bn254Params::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = tree.root();
+    let (pk, vk) = setup_groth16(&params, tree.height(), 8, &mut rng).unwrap();
+    let proof = prove_membership(&pk, &params, root, &leaves[1], tree.generate_proof(1).unwrap(), &mut rng).unwrap();
+
+    assert!(verify_membership_proof(&vk, &root, &leaves[1], &proof).unwrap());
+    assert!(!verify_membership_proof(&vk, &root, &leaves[2], &proof).unwrap());
+
+    assert_eq!(proof_calldata(&proof).len(), 256);
+    let words = input_words(&root, &leaves[1]);
+    assert_eq!(words.len(), public_inputs(&root, &leaves[1]).len());
+    assert_eq!(Bn254F::from_be_bytes_mod_order(&words[0]), root);
+}
@@ -0,0 +1,271 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::leaf::Leaf;
+use crate::root::MerkleRoot;
+use crate::serialization::{read_path, write_path};
+use crate::{SimpleMerkleTree, SimplePath};
+#[cfg(feature = "constraints")]
+use crate::constraints::MTreeVerification;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::marker::PhantomData;
+use ark_std::rand::{CryptoRng, RngCore};
+use ark_std::vec::Vec;
+
+// Hash parameters that know which trees and proofs they belong to.
+//
+// Building a tree with one `MerkleParams` and verifying with another fails
+// silently: the proof just doesn't verify. A `ParamsBundle<Tag>` ties every
+// artifact made with it to `Tag`, so with distinct tags the mix-up is a type
+// error; and it stamps them with a fingerprint of the parameters, so when the
+// tag is lost (e.g. a proof that went through `to_bytes`) the mix-up is a
+// `ParamsMismatch` error instead of a `false`.
+
+/// Identifies a parameter set: the leaf and node hash, under those parameters,
+/// of a fixed domain string.
+pub type Fingerprint = [u8; 32];
+
+pub(crate) fn fingerprint(params: &MerkleParams) -> Result<Fingerprint, MerkleError> {
+    let leaf = params.hash_leaf(b"merkle-tutorial/params-fingerprint")?;
+    let node = MerkleRoot::from(params.hash_children(&leaf, &leaf)?).to_bytes();
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&node);
+    Ok(out)
+}
+
+/// Both hash parameter sets, tagged with `Tag`.
+///
+/// Artifacts from bundles with different tags don't mix:
+///
+/// ```compile_fail
+/// use merkle_tree_tutorial::bundle::ParamsBundle;
+///
+/// struct Staging;
+/// struct Production;
+///
+/// let mut rng = merkle_tree_tutorial::rng::OsRngProvider::default();
+/// let staging = ParamsBundle::<Staging>::setup(&mut rng).unwrap();
+/// let production = ParamsBundle::<Production>::setup(&mut rng).unwrap();
+/// let tree = staging.build_tree(vec![vec![0u8], vec![1u8]]).unwrap();
+/// production.verify(&tree.prove(0).unwrap()).unwrap();
+/// ```
+pub struct ParamsBundle<Tag> {
+    params: MerkleParams,
+    fingerprint: Fingerprint,
+    _tag: PhantomData<fn() -> Tag>,
+}
+
+/// A tree built with a `ParamsBundle<Tag>`.
+pub struct Tree<Tag> {
+    tree: SimpleMerkleTree,
+    leaves: Vec<Leaf>,
+    fingerprint: Fingerprint,
+    _tag: PhantomData<fn() -> Tag>,
+}
+
+/// A proof that `leaf` is at `index` in the tree with root `root`, made from
+/// a `Tree<Tag>`.
+pub struct MembershipProof<Tag> {
+    pub index: LeafIndex,
+    pub root: MerkleRoot,
+    pub leaf: Vec<u8>,
+    pub path: SimplePath,
+    pub fingerprint: Fingerprint,
+    _tag: PhantomData<fn() -> Tag>,
+}
+
+impl<Tag> ParamsBundle<Tag> {
+    pub fn setup<R: RngCore + CryptoRng>(rng: &mut R) -> Result<Self, MerkleError> {
+        Self::from_params(MerkleParams::setup(rng)?)
+    }
+
+    pub fn from_params(params: MerkleParams) -> Result<Self, MerkleError> {
+        Ok(ParamsBundle {
+            fingerprint: fingerprint(&params)?,
+            params,
+            _tag: PhantomData,
+        })
+    }
+
+    pub fn params(&self) -> &MerkleParams {
+        &self.params
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.fingerprint
+    }
+
+    fn check(&self, fingerprint: &Fingerprint) -> Result<(), MerkleError> {
+        if *fingerprint != self.fingerprint {
+            return Err(MerkleError::ParamsMismatch);
+        }
+        Ok(())
+    }
+
+    /// Builds the tree over `leaves`; their number must be a power of two.
+    pub fn build_tree<L: Into<Leaf>>(&self, leaves: impl IntoIterator<Item = L>) -> Result<Tree<Tag>, MerkleError> {
+        let leaves: Vec<Leaf> = leaves.into_iter().map(Into::into).collect();
+        Ok(Tree {
+            tree: self.params.build_tree(&leaves)?,
+            leaves,
+            fingerprint: self.fingerprint,
+            _tag: PhantomData,
+        })
+    }
+
+    /// Checks `proof`, failing with `ParamsMismatch` if it was made with other parameters.
+    pub fn verify(&self, proof: &MembershipProof<Tag>) -> Result<bool, MerkleError> {
+        self.check(&proof.fingerprint)?;
+        Ok(proof.path.leaf_index as u64 == proof.index.get()
+            && self.params.verify_path(&proof.path, &proof.root.into(), &proof.leaf)?)
+    }
+
+    /// The membership circuit for `proof`, failing with `ParamsMismatch` if it
+    /// was made with other parameters.
+    #[cfg(feature = "constraints")]
+    pub fn membership_circuit(&self, proof: &MembershipProof<Tag>) -> Result<MTreeVerification, MerkleError> {
+        self.check(&proof.fingerprint)?;
+        Ok(<MTreeVerification>::new(
+            &self.params,
+            proof.root.into(),
+            &proof.leaf,
+            proof.path.clone(),
+        ))
+    }
+}
+
+impl<Tag> Clone for ParamsBundle<Tag> {
+    fn clone(&self) -> Self {
+        ParamsBundle {
+            params: self.params.clone(),
+            fingerprint: self.fingerprint,
+            _tag: PhantomData,
+        }
+    }
+}
+
+impl<Tag> Tree<Tag> {
+    pub fn root(&self) -> MerkleRoot {
+        self.tree.root().into()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn prove(&self, index: usize) -> Result<MembershipProof<Tag>, MerkleError> {
+        let index = LeafIndex::checked_new(index as u64, self.leaves.len() as u64)?;
+        Ok(MembershipProof {
+            index,
+            root: self.root(),
+            leaf: self.leaves[index.as_usize()].as_ref().to_vec(),
+            path: generate_proof(&self.tree, index)?,
+            fingerprint: self.fingerprint,
+            _tag: PhantomData,
+        })
+    }
+}
+
+impl<Tag> MembershipProof<Tag> {
+    /// Encodes the proof: fingerprint, index, root, leaf, then the path.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.fingerprint.to_vec();
+        // writing into a Vec cannot fail
+        self.index.serialize(&mut bytes).unwrap();
+        self.root.into_inner().serialize(&mut bytes).unwrap();
+        self.leaf.serialize(&mut bytes).unwrap();
+        write_path(&self.path, &mut bytes).unwrap();
+        bytes
+    }
+
+    /// Decodes a proof written by `to_bytes`. The tag is whatever the caller
+    /// asks for; the fingerprint still says which parameters made the proof.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, MerkleError> {
+        if bytes.len() < 32 {
+            return Err(SerializationError::InvalidData.into());
+        }
+        let mut fingerprint = [0u8; 32];
+        fingerprint.copy_from_slice(&bytes[..32]);
+        bytes = &bytes[32..];
+        let index = LeafIndex::deserialize(&mut bytes)?;
+        let root = MerkleRoot::from(crate::Root::deserialize(&mut bytes)?);
+        let leaf = Vec::<u8>::deserialize(&mut bytes)?;
+        let path = read_path(&mut bytes)?;
+        if !bytes.is_empty() {
+            return Err(SerializationError::InvalidData.into());
+        }
+        Ok(MembershipProof {
+            index,
+            root,
+            leaf,
+            path,
+            fingerprint,
+            _tag: PhantomData,
+        })
+    }
+}
+
+impl<Tag> Clone for MembershipProof<Tag> {
+    fn clone(&self) -> Self {
+        MembershipProof {
+            index: self.index,
+            root: self.root,
+            leaf: self.leaf.clone(),
+            path: self.path.clone(),
+            fingerprint: self.fingerprint,
+            _tag: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+struct TestTag;
+
+#[test]
+fn test_bundle_proofs_round_trip() {
+    let mut rng = ark_std::test_rng();
+    let bundle = ParamsBundle::<TestTag>::setup(&mut rng).unwrap();
+    let tree = bundle.build_tree((0u8..4).map(|i| vec![i; 8])).unwrap();
+    let proof = tree.prove(1).unwrap();
+    assert!(bundle.verify(&proof).unwrap());
+
+    let decoded = MembershipProof::<TestTag>::from_bytes(&proof.to_bytes()).unwrap();
+    assert_eq!(decoded.fingerprint, bundle.fingerprint());
+    assert!(bundle.verify(&decoded).unwrap());
+
+    // the same parameters give the same fingerprint
+    let again = ParamsBundle::<TestTag>::from_params(bundle.params().clone()).unwrap();
+    assert_eq!(again.fingerprint(), bundle.fingerprint());
+}
+
+#[test]
+fn test_bundle_mismatch_after_deserialization() {
+    let mut rng = ark_std::test_rng();
+    let first = ParamsBundle::<TestTag>::setup(&mut rng).unwrap();
+    let second = ParamsBundle::<TestTag>::setup(&mut rng).unwrap();
+    assert_ne!(first.fingerprint(), second.fingerprint());
+
+    let tree = first.build_tree((0u8..4).map(|i| vec![i; 8])).unwrap();
+    let bytes = tree.prove(2).unwrap().to_bytes();
+
+    // serialization erased which bundle the proof came from
+    let proof = MembershipProof::<TestTag>::from_bytes(&bytes).unwrap();
+    assert!(matches!(second.verify(&proof), Err(MerkleError::ParamsMismatch)));
+    assert!(first.verify(&proof).unwrap());
+
+    #[cfg(feature = "constraints")]
+    {
+        assert!(matches!(second.membership_circuit(&proof), Err(MerkleError::ParamsMismatch)));
+        assert!(first.membership_circuit(&proof).is_ok());
+    }
+
+    // a truncated or padded encoding doesn't decode
+    assert!(MembershipProof::<TestTag>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    let mut long = bytes.clone();
+    long.push(0);
+    assert!(MembershipProof::<TestTag>::from_bytes(&long).is_err());
+}
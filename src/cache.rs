@@ -0,0 +1,241 @@
+use crate::common::{keccak256, MerkleParams};
+use crate::error::MerkleError;
+use crate::root::MerkleRoot;
+use crate::serialization::serialize_path;
+#[cfg(feature = "snark")]
+use crate::snark::{self, Proof, VerifyingKey};
+use crate::{Root, SimplePath};
+#[cfg(feature = "snark")]
+use ark_serialize::CanonicalSerialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+// Verification results, remembered.
+//
+// A verifier service sees the same proofs again and again: a client retrying,
+// many clients checking the same member. `CachedVerifier` remembers the last
+// `capacity` results, of native paths and, with `snark` and a verifying key,
+// of Groth16 proofs, and answers a repeated query without hashing anything.
+// It evicts the least recently used result when full.
+//
+// A query is keyed by the keccak256 of what it is about: a tag for its kind,
+// the root, the leaf with its length, and the serialized path or proof, so a
+// cached answer is only given for exactly the same query. Both answers are
+// cached, a proof that doesn't verify being as expensive to check as one that
+// does; errors aren't. The parameters and the key are fixed per verifier, so
+// they are not part of the key.
+
+type Key = [u8; 32];
+
+fn key(kind: u8, root: &Root, leaf: &[u8], evidence: &[u8]) -> Key {
+    let mut data = vec![kind];
+    data.extend_from_slice(&MerkleRoot::from(*root).to_bytes());
+    data.extend_from_slice(&(leaf.len() as u64).to_le_bytes());
+    data.extend_from_slice(leaf);
+    data.extend_from_slice(evidence);
+    keccak256(&data)
+}
+
+const PATH: u8 = 0;
+#[cfg(feature = "snark")]
+const SNARK: u8 = 1;
+
+/// The hits and misses of a `CachedVerifier` so far, and its size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+// Results by key, and keys by when they were last used.
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<Key, (bool, u64)>,
+    recency: BTreeMap<u64, Key>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &Key) -> Option<bool> {
+        self.tick += 1;
+        match self.entries.get_mut(key) {
+            Some((result, used)) => {
+                self.recency.remove(used);
+                *used = self.tick;
+                self.recency.insert(self.tick, *key);
+                self.hits += 1;
+                Some(*result)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, key: Key, result: bool, capacity: usize) {
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.insert(key, (result, self.tick)) {
+            self.recency.remove(&used);
+        }
+        self.recency.insert(self.tick, key);
+        while self.entries.len() > capacity {
+            let oldest = *self.recency.keys().next().expect("a full cache has entries");
+            let key = self.recency.remove(&oldest).unwrap();
+            self.entries.remove(&key);
+        }
+    }
+}
+
+/// A verifier that remembers its last `capacity` results.
+pub struct CachedVerifier {
+    params: MerkleParams,
+    #[cfg(feature = "snark")]
+    vk: Option<VerifyingKey>,
+    capacity: usize,
+    lru: Mutex<Lru>,
+}
+
+impl CachedVerifier {
+    /// A verifier of paths under `params`, remembering `capacity` results;
+    /// with a capacity of 0 it remembers none.
+    pub fn new(params: MerkleParams, capacity: usize) -> Self {
+        CachedVerifier {
+            params,
+            #[cfg(feature = "snark")]
+            vk: None,
+            capacity,
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+
+    /// Also verifies membership proofs made for `vk`.
+    #[cfg(feature = "snark")]
+    pub fn with_verifying_key(mut self, vk: VerifyingKey) -> Self {
+        self.vk = Some(vk);
+        self
+    }
+
+    pub fn params(&self) -> &MerkleParams {
+        &self.params
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let lru = self.lru.lock().unwrap();
+        CacheStats {
+            hits: lru.hits,
+            misses: lru.misses,
+            len: lru.entries.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    /// Forgets every result, keeping the hit and miss counts.
+    pub fn clear(&self) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.entries.clear();
+        lru.recency.clear();
+    }
+
+    // The cached result for `key`, or `verify`'s, cached. The lock isn't held
+    // while verifying, so two threads may both verify the same query.
+    fn cached(&self, key: Key, verify: impl FnOnce() -> Result<bool, MerkleError>) -> Result<bool, MerkleError> {
+        if let Some(result) = self.lru.lock().unwrap().get(&key) {
+            return Ok(result);
+        }
+        let result = verify()?;
+        if self.capacity > 0 {
+            self.lru.lock().unwrap().insert(key, result, self.capacity);
+        }
+        Ok(result)
+    }
+
+    /// `MerkleParams::verify_path`, answered from the cache if it was asked before.
+    pub fn verify_path(&self, path: &SimplePath, root: &Root, leaf: &[u8]) -> Result<bool, MerkleError> {
+        self.cached(key(PATH, root, leaf, &serialize_path(path)), || {
+            self.params.verify_path(path, root, leaf)
+        })
+    }
+
+    /// `snark::verify_membership_proof` with the verifying key, answered from
+    /// the cache if it was asked before. Fails with `ParamsMismatch` if the
+    /// verifier has no key.
+    #[cfg(feature = "snark")]
+    pub fn verify_membership_proof(&self, root: &Root, leaf: &[u8], proof: &Proof) -> Result<bool, MerkleError> {
+        let vk = self.vk.as_ref().ok_or(MerkleError::ParamsMismatch)?;
+        let mut bytes = Vec::new();
+        proof.serialize(&mut bytes)?;
+        self.cached(key(SNARK, root, leaf, &bytes), || {
+            snark::verify_membership_proof(vk, root, leaf, proof)
+        })
+    }
+}
+
+#[test]
+fn test_cached_path_verification() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = tree.root();
+    let paths: Vec<SimplePath> = (0..8).map(|index| tree.generate_proof(index).unwrap()).collect();
+    let verifier = CachedVerifier::new(params, 2);
+
+    assert!(verifier.verify_path(&paths[0], &root, &leaves[0]).unwrap());
+    assert!(verifier.verify_path(&paths[0], &root, &leaves[0]).unwrap());
+    // a rejection is remembered too, apart from the acceptance
+    assert!(!verifier.verify_path(&paths[0], &root, &leaves[1]).unwrap());
+    assert!(!verifier.verify_path(&paths[0], &root, &leaves[1]).unwrap());
+    assert_eq!(
+        verifier.stats(),
+        CacheStats {
+            hits: 2,
+            misses: 2,
+            len: 2,
+            capacity: 2
+        }
+    );
+
+    // the first query, used again, is now the more recent: a third evicts the second
+    assert!(verifier.verify_path(&paths[0], &root, &leaves[0]).unwrap());
+    assert!(verifier.verify_path(&paths[2], &root, &leaves[2]).unwrap());
+    assert!(verifier.verify_path(&paths[0], &root, &leaves[0]).unwrap());
+    assert!(!verifier.verify_path(&paths[0], &root, &leaves[1]).unwrap());
+    let stats = verifier.stats();
+    assert_eq!((stats.hits, stats.misses, stats.len), (4, 4, 2));
+
+    verifier.clear();
+    assert_eq!(verifier.stats().len, 0);
+    let uncached = CachedVerifier::new(verifier.params().clone(), 0);
+    uncached.verify_path(&paths[1], &root, &leaves[1]).unwrap();
+    uncached.verify_path(&paths[1], &root, &leaves[1]).unwrap();
+    assert_eq!((uncached.stats().hits, uncached.stats().len), (0, 0));
+}
+
+#[cfg(feature = "snark")]
+#[test]
+fn test_cached_snark_verification() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let (pk, vk) = snark::setup_groth16(&params, tree.height(), 8, &mut rng).unwrap();
+    let path = tree.generate_proof(1).unwrap();
+    let proof = snark::prove_membership(&pk, &params, tree.root(), &leaves[1], path, &mut rng).unwrap();
+
+    let verifier = CachedVerifier::new(params.clone(), 16);
+    assert!(matches!(
+        verifier.verify_membership_proof(&tree.root(), &leaves[1], &proof),
+        Err(MerkleError::ParamsMismatch)
+    ));
+    let verifier = verifier.with_verifying_key(vk);
+    for _ in 0..3 {
+        assert!(verifier.verify_membership_proof(&tree.root(), &leaves[1], &proof).unwrap());
+    }
+    assert!(!verifier.verify_membership_proof(&tree.root(), &leaves[2], &proof).unwrap());
+    let stats = verifier.stats();
+    assert_eq!((stats.hits, stats.misses), (2, 2));
+}
@@ -0,0 +1,215 @@
+use ark_ff::{Field, Zero};
+use ark_relations::r1cs::{
+    ConstraintLayer, ConstraintSynthesizer, ConstraintSystem, SynthesisError, TracingMode,
+};
+use ark_std::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+// Synthesizing a circuit and saying what is wrong with it when it isn't
+// satisfied, for tests.
+//
+// `synthesize_with_report` installs a `ConstraintLayer` for the duration of
+// the synthesis, so each constraint remembers the namespaces (`ns!` and
+// instrumented gadget functions) it was created in, and then finds the first
+// unsatisfied constraint in the finalized matrices. The report has that
+// constraint's namespace path, every variable it reads with its assigned
+// value, and the size of the circuit. `assert_satisfied_with_report` and
+// `expect_unsatisfied` panic with the report.
+
+/// The size of a synthesized circuit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitMetrics {
+    pub constraints: usize,
+    /// Including the constant one.
+    pub instance_variables: usize,
+    pub witness_variables: usize,
+}
+
+/// A variable of the constraint system, numbered like `ConstraintSystem` does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variable {
+    One,
+    Instance(usize),
+    Witness(usize),
+}
+
+/// One term `coefficient * variable` of a linear combination, with the value
+/// assigned to the variable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Term<F: Field> {
+    pub coefficient: F,
+    pub variable: Variable,
+    pub value: F,
+}
+
+/// The first constraint `a * b = c` that doesn't hold.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unsatisfied<F: Field> {
+    pub index: usize,
+    /// The trace `ConstraintLayer` recorded for the constraint, outermost
+    /// namespace first, or just the index if there is none.
+    pub namespace: String,
+    pub a: Vec<Term<F>>,
+    pub b: Vec<Term<F>>,
+    pub c: Vec<Term<F>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitReport<F: Field> {
+    pub metrics: CircuitMetrics,
+    /// `None` if the circuit is satisfied.
+    pub unsatisfied: Option<Unsatisfied<F>>,
+}
+
+impl<F: Field> CircuitReport<F> {
+    pub fn is_satisfied(&self) -> bool {
+        self.unsatisfied.is_none()
+    }
+}
+
+/// Synthesizes `circuit` and reports on it.
+pub fn synthesize_with_report<F: Field, C: ConstraintSynthesizer<F>>(
+    circuit: C,
+) -> Result<CircuitReport<F>, SynthesisError> {
+    let mut layer = ConstraintLayer::default();
+    layer.mode = TracingMode::OnlyConstraints;
+    let _guard = tracing_subscriber::Registry::default().with(layer).set_default();
+
+    let cs = ConstraintSystem::<F>::new_ref();
+    circuit.generate_constraints(cs.clone())?;
+    let metrics = CircuitMetrics {
+        constraints: cs.num_constraints(),
+        instance_variables: cs.num_instance_variables(),
+        witness_variables: cs.num_witness_variables(),
+    };
+    if cs.is_satisfied()? {
+        return Ok(CircuitReport {
+            metrics,
+            unsatisfied: None,
+        });
+    }
+    // read the trace before finalizing, which rewrites the linear combinations
+    let namespace = cs.which_is_unsatisfied()?.unwrap_or_default();
+
+    cs.finalize();
+    let matrices = cs.to_matrices().ok_or(SynthesisError::AssignmentMissing)?;
+    let system = cs.borrow().ok_or(SynthesisError::MissingCS)?;
+    let num_instance = matrices.num_instance_variables;
+    let assignment = |index: usize| {
+        if index < num_instance {
+            system.instance_assignment[index]
+        } else {
+            system.witness_assignment[index - num_instance]
+        }
+    };
+    let terms = |row: &[(F, usize)]| -> Vec<Term<F>> {
+        row.iter()
+            .map(|(coefficient, index)| Term {
+                coefficient: *coefficient,
+                variable: match *index {
+                    0 => Variable::One,
+                    i if i < num_instance => Variable::Instance(i),
+                    i => Variable::Witness(i - num_instance),
+                },
+                value: assignment(*index),
+            })
+            .collect()
+    };
+    let eval = |terms: &[Term<F>]| terms.iter().fold(F::zero(), |acc, t| acc + t.coefficient * t.value);
+
+    let unsatisfied = (0..matrices.num_constraints)
+        .map(|index| Unsatisfied {
+            index,
+            namespace: namespace.clone(),
+            a: terms(&matrices.a[index]),
+            b: terms(&matrices.b[index]),
+            c: terms(&matrices.c[index]),
+        })
+        .find(|u| eval(&u.a) * eval(&u.b) != eval(&u.c));
+    Ok(CircuitReport {
+        metrics,
+        unsatisfied,
+    })
+}
+
+/// Panics with the report unless `circuit` synthesizes and is satisfied.
+pub fn assert_satisfied_with_report<F: Field, C: ConstraintSynthesizer<F>>(circuit: C) -> CircuitMetrics {
+    let report = synthesize_with_report(circuit).expect("circuit synthesizes");
+    assert!(report.is_satisfied(), "circuit is not satisfied\n{}", report);
+    report.metrics
+}
+
+/// Panics unless `circuit` synthesizes, isn't satisfied, and the first
+/// unsatisfied constraint was created in a namespace whose path contains
+/// `expected_ns`.
+pub fn expect_unsatisfied<F: Field, C: ConstraintSynthesizer<F>>(circuit: C, expected_ns: &str) -> Unsatisfied<F> {
+    let report = synthesize_with_report(circuit).expect("circuit synthesizes");
+    match report.unsatisfied {
+        Some(unsatisfied) if unsatisfied.namespace.contains(expected_ns) => unsatisfied,
+        Some(_) => panic!("circuit fails outside `{}`\n{}", expected_ns, report),
+        None => panic!("circuit is satisfied, expected it to fail in `{}`\n{}", expected_ns, report),
+    }
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Variable::One => write!(f, "one"),
+            Variable::Instance(i) => write!(f, "instance {}", i),
+            Variable::Witness(i) => write!(f, "witness {}", i),
+        }
+    }
+}
+
+impl<F: Field> fmt::Display for CircuitReport<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} constraints, {} instance and {} witness variables",
+            self.metrics.constraints, self.metrics.instance_variables, self.metrics.witness_variables
+        )?;
+        let unsatisfied = match &self.unsatisfied {
+            Some(unsatisfied) => unsatisfied,
+            None => return writeln!(f, "all constraints are satisfied"),
+        };
+        writeln!(f, "constraint {} is the first unsatisfied one, created in:", unsatisfied.index)?;
+        writeln!(f, "{}", unsatisfied.namespace)?;
+        for (name, terms) in [("a", &unsatisfied.a), ("b", &unsatisfied.b), ("c", &unsatisfied.c)].iter() {
+            writeln!(f, "{}:", name)?;
+            for term in terms.iter() {
+                writeln!(f, "    {} * {} (= {})", term.coefficient, term.variable, term.value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_reports_the_failing_membership_constraint() {
+    use crate::constraints::MerkleTreeVerification;
+    use crate::mock::{MockMerkleCircuit, MockParams};
+
+    let params = MockParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let circuit = |leaf: &[u8]| MerkleTreeVerification::<MockMerkleCircuit> {
+        leaf_crh_params: params.leaf_crh_params.clone(),
+        two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+        root: tree.root(),
+        leaf: leaf.to_vec(),
+        auth_path: Some(tree.generate_proof(1).unwrap()),
+    };
+
+    let metrics = assert_satisfied_with_report(circuit(&leaves[1]));
+    assert!(metrics.constraints > 0);
+    // the constant one, the root and the leaf bytes
+    assert!(metrics.instance_variables > 2);
+
+    let unsatisfied = expect_unsatisfied(circuit(&leaves[2]), "membership");
+    assert!(unsatisfied.index < metrics.constraints);
+    assert!(!unsatisfied.a.is_empty());
+
+    let report = synthesize_with_report(circuit(&leaves[2])).unwrap();
+    assert!(format!("{}", report).contains("membership"));
+}
@@ -1,35 +1,96 @@
 use ark_crypto_primitives::crh::{injective_map::{PedersenCRHCompressor, TECompressor}, pedersen};
+#[cfg(feature = "constraints")]
 use ark_crypto_primitives::crh::constraints::{CRHGadget, TwoToOneCRHGadget};
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+#[cfg(feature = "constraints")]
 use ark_crypto_primitives::crh::injective_map::constraints::{
     PedersenCRHCompressorGadget, TECompressorGadget,
 };
-use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
+#[cfg(feature = "constraints")]
+use ark_ed_on_bls12_381::constraints::EdwardsVar;
+use ark_ed_on_bls12_381::EdwardsProjective;
+use ark_crypto_primitives::crh::pedersen::Window;
+use ark_ff::to_bytes;
+use crate::ark::{CryptoRng, RngCore};
+use ark_std::rand::{rngs::StdRng, SeedableRng};
+use crate::error::{ErrorContext, MerkleError, ResultExt};
+use crate::index::LeafIndex;
+use crate::leaf::{Leaf, LeafRef};
+use crate::{MerkleConfig, Root, SimpleMerkleTree, SimplePath};
+use ark_crypto_primitives::merkle_tree::{Config, MerkleTree, Path};
+use ark_std::collections::BTreeMap;
+use ark_std::vec::Vec;
+#[cfg(feature = "std")]
+use sha3::{Digest, Keccak256};
 
-// Pedersen hash with two input
-pub type TwoToOneHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, TwoToOneWindow>;
+/// Trees, and their membership circuit, over Blake2s instead of Pedersen.
+pub mod blake2s;
+/// Trees of field-element leaves, and their membership circuit, over
+/// Poseidon without any bytes.
+pub mod field;
+/// Trees, and their membership circuit, over Poseidon instead of Pedersen.
+pub mod poseidon;
 
-#[derive(Clone, PartialEq, Eq, Hash)]
-pub struct TwoToOneWindow;
+// The Poseidon configuration and its gadgets, beside the Pedersen ones.
+pub use poseidon::{PoseidonMerkleConfig, PoseidonParams};
+#[cfg(feature = "constraints")]
+pub use poseidon::{PoseidonLeafHashGadget, PoseidonTwoToOneHashGadget};
 
-// `WINDOW_SIZE * NUM_WINDOWS` = 2 * 256 bits = enough for hashing two outputs.
-impl pedersen::Window for TwoToOneWindow {
-    const WINDOW_SIZE: usize = 2;
-    const NUM_WINDOWS: usize = 256;
-}
+// Pedersen hash with two input
+pub type TwoToOneHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, TwoToOneWindow>;
 
 // pedersen Hash with single input
 pub type LeafHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, LeafWindow>;
 
+/// Pedersen windows: `NUM_WINDOWS` windows of `WINDOW_SIZE` bits each, which
+/// hash inputs of up to `WINDOW_SIZE * NUM_WINDOWS` bits. Pick other sizes to
+/// build a tree configuration for longer or shorter leaves.
 #[derive(Clone, PartialEq, Eq, Hash)]
-pub struct LeafWindow;
+pub struct Windows<const WINDOW_SIZE: usize, const NUM_WINDOWS: usize>;
+
+impl<const W: usize, const N: usize> pedersen::Window for Windows<W, N> {
+    const WINDOW_SIZE: usize = W;
+    const NUM_WINDOWS: usize = N;
+}
 
-// `WINDOW_SIZE * NUM_WINDOWS` = 2 * 256 bits = enough for hashing single input.
-impl pedersen::Window for LeafWindow {
-    const WINDOW_SIZE: usize = 4;
-    const NUM_WINDOWS: usize = 256;
+impl<const W: usize, const N: usize> Windows<W, N> {
+    /// The longest input, in bytes, these windows absorb.
+    pub const INPUT_LEN: usize = W * N / 8;
 }
 
+/// Fails with `WindowsTooSmall` unless windows `W` absorb inputs of `len` bytes.
+pub fn check_windows<W: pedersen::Window>(len: usize) -> Result<(), MerkleError> {
+    let capacity = W::WINDOW_SIZE * W::NUM_WINDOWS;
+    if len * 8 > capacity {
+        return Err(MerkleError::WindowsTooSmall { bits: len * 8, capacity });
+    }
+    Ok(())
+}
+
+/// The bytes of one digest as the two-to-one hash takes it: the x coordinate
+/// `TECompressor` keeps of a Jubjub point.
+pub const DIGEST_LEN: usize = 32;
+
+// `WINDOW_SIZE * NUM_WINDOWS` = 2 * 256 = 512 bits = two digests.
+pub type TwoToOneWindow = Windows<2, 256>;
+
+// `WINDOW_SIZE * NUM_WINDOWS` = 4 * 256 = 1024 bits = leaves of up to 128 bytes.
+pub type LeafWindow = Windows<4, 256>;
+
+/// The longest leaf, in bytes, the leaf hash can absorb.
+pub const MAX_LEAF_LEN: usize = LeafWindow::INPUT_LEN;
+
+// Changing the windows above can't silently truncate what they hash: a node
+// is two digests, and a leaf hash digest must itself fit as a leaf, for the
+// trees of leaf hashes like `build_preimage_tree`.
+const _: () = assert!(
+    TwoToOneWindow::INPUT_LEN >= 2 * DIGEST_LEN,
+    "the two-to-one windows can't absorb two digests"
+);
+const _: () = assert!(MAX_LEAF_LEN >= DIGEST_LEN, "the leaf windows can't absorb a digest");
+
 //Zksnark gadget for pedersen hash with two input
+#[cfg(feature = "constraints")]
 pub type TwoToOneHashGadget = PedersenCRHCompressorGadget<
     EdwardsProjective,
     TECompressor,
@@ -39,6 +100,7 @@ pub type TwoToOneHashGadget = PedersenCRHCompressorGadget<
 >;
 
 //Zksnark gadget for pedersen hash with single input
+#[cfg(feature = "constraints")]
 pub type LeafHashGadget = PedersenCRHCompressorGadget<
     EdwardsProjective,
     TECompressor,
@@ -51,10 +113,872 @@ pub type LeafHashGadget = PedersenCRHCompressorGadget<
 pub type ConstraintF = ark_ed_on_bls12_381::Fq;
 
 //hash generator setup for leaf hash
+#[cfg(feature = "constraints")]
 pub type LeafHashParamsVar = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::ParametersVar;
 
 //hash generator setup for internal node hash
+#[cfg(feature = "constraints")]
 pub type TwoToOneHashParamsVar =
 <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::ParametersVar;
 
+// Both sets of hash parameters a tree is built and verified with. They always
+// travel together, so they are bundled here instead of being threaded around
+// as two separate arguments. Generic over the tree configuration so the same
+// code runs over other hashes, e.g. the fast `mock` config in tests.
+#[derive(Clone)]
+pub struct TreeParams<P: Config> {
+    pub leaf_crh_params: <P::LeafHash as CRH>::Parameters,
+    pub two_to_one_crh_params: <P::TwoToOneHash as TwoToOneCRH>::Parameters,
+}
+
+/// The parameters of the Pedersen trees of this crate.
+pub type MerkleParams = TreeParams<MerkleConfig>;
+
+// The longest leaf, in bytes, the leaf hash of `P` can absorb.
+fn max_leaf_len<P: Config>() -> usize {
+    <P::LeafHash as CRH>::INPUT_SIZE_BITS / 8
+}
+
+fn check_leaf_len<P: Config>(leaf: &[u8]) -> Result<(), MerkleError> {
+    if leaf.len() > max_leaf_len::<P>() {
+        return Err(MerkleError::LeafTooLong {
+            len: leaf.len(),
+            max: max_leaf_len::<P>(),
+        });
+    }
+    Ok(())
+}
+
+impl<P: Config> TreeParams<P> {
+    /// Samples fresh parameters for both hashes.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "params_setup", skip_all))]
+    pub fn setup<R: RngCore + CryptoRng>(rng: &mut R) -> Result<Self, MerkleError> {
+        Ok(TreeParams {
+            leaf_crh_params: <P::LeafHash as CRH>::setup(rng)?,
+            two_to_one_crh_params: <P::TwoToOneHash as TwoToOneCRH>::setup(rng)?,
+        })
+    }
+
+    /// Hashes a leaf exactly like `MerkleTree::new` does, but errors on leaves
+    /// that don't fit the leaf window instead of panicking inside Pedersen.
+    pub fn hash_leaf(&self, leaf: &[u8]) -> Result<<P::LeafHash as CRH>::Output, MerkleError> {
+        check_leaf_len::<P>(leaf)?;
+        Ok(<P::LeafHash as CRH>::evaluate(&self.leaf_crh_params, leaf)?)
+    }
+
+    /// Hashes two child digests into their parent, like the internal nodes of `MerkleTree`.
+    pub fn hash_children(
+        &self,
+        left: &<P::TwoToOneHash as TwoToOneCRH>::Output,
+        right: &<P::TwoToOneHash as TwoToOneCRH>::Output,
+    ) -> Result<<P::TwoToOneHash as TwoToOneCRH>::Output, MerkleError> {
+        Ok(<P::TwoToOneHash as TwoToOneCRH>::evaluate(
+            &self.two_to_one_crh_params,
+            &to_bytes![left]?,
+            &to_bytes![right]?,
+        )?)
+    }
+}
+
+/// Parameters derived from `seed` alone, so that a prover and a verifier can
+/// each set them up from a published seed and agree on every root. The seed
+/// drives a ChaCha20 generator (`StdRng` of rand 0.7); the parameters are as
+/// good as the seed is unpredictable to whoever builds trees with them.
+pub fn setup_params_from_seed(seed: [u8; 32]) -> Result<MerkleParams, MerkleError> {
+    MerkleParams::setup(&mut StdRng::from_seed(seed))
+}
+
+// The domain tag `MerkleParams::from_seed` hashes in front of its seed.
+#[cfg(feature = "std")]
+const SEED_DOMAIN: &[u8] = b"merkle-tree-tutorial/params-from-seed/v1";
+
+impl MerkleParams {
+    /// Parameters derived from a seed of any length, for parties that agree
+    /// on a name or a transcript rather than on 32 bytes: the seed is hashed
+    /// with keccak256 under a domain tag and passed to `setup_params_from_seed`.
+    ///
+    /// The Pedersen setup draws every generator by sampling x-coordinates
+    /// until one is on the curve, so nobody, including whoever picked the
+    /// seed, knows a discrete log relation between them.
+    #[cfg(feature = "std")]
+    pub fn from_seed(seed: &[u8]) -> Result<Self, MerkleError> {
+        let mut tagged = SEED_DOMAIN.to_vec();
+        tagged.extend_from_slice(seed);
+        setup_params_from_seed(keccak256(&tagged))
+    }
+
+    /// The parameters of `kit::default_params`, derived from the fixed,
+    /// domain-separated `kit::DEFAULT_PARAMS_SEED`.
+    pub fn deterministic() -> Result<Self, MerkleError> {
+        setup_params_from_seed(crate::kit::DEFAULT_PARAMS_SEED)
+    }
+}
+
+// Tree construction, opening and verification. These are thin wrappers around
+// `MerkleTree` and `Path` that give every module the same error type and, with
+// the `tracing` feature, the same spans.
+impl<P: Config> TreeParams<P> {
+    /// Builds the tree over `leaves`; their number must be a power of two.
+    /// The leaves are only borrowed for hashing, never copied, so passing
+    /// `&leaves` costs a slice per leaf rather than a second copy of them.
+    pub fn build_tree<L: AsRef<[u8]>>(
+        &self,
+        leaves: impl IntoIterator<Item = L>,
+    ) -> Result<MerkleTree<P>, MerkleError> {
+        let leaves: Vec<L> = leaves.into_iter().collect();
+        self.build_borrowed_tree(leaves.iter().map(AsRef::as_ref))
+    }
+
+    fn build_borrowed_tree<'a>(&self, leaves: impl Iterator<Item = &'a [u8]>) -> Result<MerkleTree<P>, MerkleError> {
+        let leaves = leaves
+            .enumerate()
+            .map(|(index, leaf)| {
+                check_leaf_len::<P>(leaf).with_context(|| ErrorContext::new("build_tree").index(index))?;
+                Ok(LeafRef(leaf))
+            })
+            .collect::<Result<Vec<LeafRef>, MerkleError>>()?;
+        self.build_checked_tree(&leaves)
+            .with_context(|| ErrorContext::new("build_tree"))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "tree_build",
+            skip_all,
+            fields(leaf_count = leaves.len(), height = tracing::field::Empty)
+        )
+    )]
+    fn build_checked_tree(&self, leaves: &[LeafRef]) -> Result<MerkleTree<P>, MerkleError> {
+        let tree = MerkleTree::<P>::new(&self.leaf_crh_params, &self.two_to_one_crh_params, leaves)?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("height", &tree.height());
+        Ok(tree)
+    }
 
+    /// Checks that `path` opens `leaf` against `root`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "path_verify",
+            skip_all,
+            fields(index = path.leaf_index, result = tracing::field::Empty)
+        )
+    )]
+    pub fn verify_path(
+        &self,
+        path: &Path<P>,
+        root: &<P::TwoToOneHash as TwoToOneCRH>::Output,
+        leaf: impl AsRef<[u8]>,
+    ) -> Result<bool, MerkleError> {
+        let leaf = LeafRef(leaf.as_ref());
+        check_leaf_len::<P>(leaf.0)?;
+        let result = path.verify(&self.leaf_crh_params, &self.two_to_one_crh_params, root, &leaf)?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("result", &result);
+        Ok(result)
+    }
+
+    /// `verify_path` for callers that treat a path that doesn't verify as an
+    /// error: `InvalidProof` rather than `Ok(false)`.
+    pub fn check_path(
+        &self,
+        path: &Path<P>,
+        root: &<P::TwoToOneHash as TwoToOneCRH>::Output,
+        leaf: impl AsRef<[u8]>,
+    ) -> Result<(), MerkleError> {
+        if !self.verify_path(path, root, leaf)? {
+            return Err(MerkleError::InvalidProof);
+        }
+        Ok(())
+    }
+}
+
+/// The number of leaves of `tree`.
+pub fn leaf_count<P: Config>(tree: &MerkleTree<P>) -> u64 {
+    1 << (tree.height() - 1)
+}
+
+/// Opens the leaf at `index` of `tree`. `index` was checked against some leaf
+/// count, not necessarily this tree's, so it is checked once more here.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "proof_generate", skip(tree, index), fields(index = %index))
+)]
+pub fn generate_proof<P: Config>(tree: &MerkleTree<P>, index: LeafIndex) -> Result<Path<P>, MerkleError> {
+    let index = LeafIndex::checked_new(index.get(), leaf_count(tree))?;
+    Ok(tree.generate_proof(index.as_usize())?)
+}
+
+/// Replaces the leaf at `index` of `tree`, rehashing only its path, and
+/// returns the new root. A leaf too long for the leaf hash leaves the tree
+/// as it was.
+pub fn update_leaf<P: Config>(
+    tree: &mut MerkleTree<P>,
+    index: LeafIndex,
+    new_leaf: impl Into<Leaf>,
+) -> Result<<P::TwoToOneHash as TwoToOneCRH>::Output, MerkleError> {
+    let index = LeafIndex::checked_new(index.get(), leaf_count(tree))?;
+    let new_leaf = new_leaf.into();
+    new_leaf.check()?;
+    tree.update(index.as_usize(), &new_leaf)?;
+    Ok(tree.root())
+}
+
+/// Opens the leaves at `indices` of `tree`, in parallel with the `parallel`
+/// feature; the paths come back in the order of `indices` either way.
+pub fn generate_proofs(tree: &SimpleMerkleTree, indices: &[usize]) -> Result<Vec<SimplePath>, MerkleError> {
+    let leaf_count = leaf_count(tree);
+    try_map_indices(indices.len(), |i| {
+        let index = LeafIndex::checked_new(indices[i] as u64, leaf_count)?;
+        Ok(tree.generate_proof(index.as_usize())?)
+    })
+}
+
+/// One proof for the leaves at `indices` of `tree`, which holds each sibling
+/// they share once; `constraints::MTreeBatchVerification` checks it in a
+/// circuit. The proof of `multiproof::generate_multiproof`.
+pub fn generate_multi_proof(
+    tree: &SimpleMerkleTree,
+    indices: &[usize],
+) -> Result<crate::multiproof::MultiPath, MerkleError> {
+    crate::multiproof::generate_multiproof(tree, indices)
+}
+
+impl MerkleParams {
+    /// Whether every `(leaf, path)` of `items` opens against `root`, as
+    /// `verify_path` on each would say, but hashing each node of the tree at
+    /// most once. Once a path reaches a node an earlier path led to the root
+    /// from, the rest of it only has to match the nodes already known, so
+    /// overlapping paths cost little more than one. Stops at the first path
+    /// that doesn't open; `verify_each` checks all of them and says which.
+    pub fn verify_batch<L: AsRef<[u8]>>(&self, root: &Root, items: &[(L, SimplePath)]) -> Result<bool, MerkleError> {
+        let height = match items.first() {
+            Some((_, path)) => path.auth_path.len(),
+            None => return Ok(true),
+        };
+        // the nodes known to lead to `root`, by level and index, the leaf hashes at level 0
+        let mut known: BTreeMap<(usize, usize), Root> = BTreeMap::new();
+        for (i, (leaf, path)) in items.iter().enumerate() {
+            if path.auth_path.len() != height {
+                return Ok(false);
+            }
+            let context = || ErrorContext::new("verify_batch").index(i);
+            // `None` once the path has joined the known nodes
+            let mut current = Some(self.hash_leaf(leaf.as_ref()).with_context(context)?);
+            let mut claimed = Vec::new();
+            let siblings = ark_std::iter::once(&path.leaf_sibling_hash).chain(path.auth_path.iter().rev());
+            for (level, sibling) in siblings.enumerate() {
+                let position = path.leaf_index >> level;
+                match known.get(&(level, position ^ 1)) {
+                    Some(known_sibling) if known_sibling != sibling => return Ok(false),
+                    // the siblings above a known node are all known
+                    None if current.is_none() => return Ok(false),
+                    _ => {}
+                }
+                let digest = match current {
+                    Some(digest) => digest,
+                    None => continue,
+                };
+                match known.get(&(level, position)) {
+                    Some(known_digest) if *known_digest != digest => return Ok(false),
+                    Some(_) => current = None,
+                    None => {
+                        claimed.push(((level, position), digest));
+                        claimed.push(((level, position ^ 1), *sibling));
+                        let (left, right) = if position & 1 == 1 { (sibling, &digest) } else { (&digest, sibling) };
+                        current = Some(self.hash_children(left, right).with_context(context)?);
+                    }
+                }
+            }
+            if matches!(current, Some(top) if top != *root) {
+                return Ok(false);
+            }
+            known.extend(claimed);
+        }
+        Ok(true)
+    }
+
+    /// Whether each `(leaf, path)` of `items` opens against `root`, in order,
+    /// to find the ones that don't.
+    pub fn verify_each<L: AsRef<[u8]> + Sync>(
+        &self,
+        root: &Root,
+        items: &[(L, SimplePath)],
+    ) -> Result<Vec<bool>, MerkleError> {
+        try_map_indices(items.len(), |i| {
+            let (leaf, path) = &items[i];
+            self.verify_path(path, root, leaf)
+                .with_context(|| ErrorContext::new("verify_batch").index(i))
+        })
+    }
+}
+
+// `f` of each of `0..len`, in order. With the `parallel` feature the calls run
+// on the rayon pool; arkworks errors aren't `Send`, so a parallel pass that
+// fails somewhere is run again in order to return the first error.
+pub(crate) fn try_map_indices<T: Send>(
+    len: usize,
+    f: impl Fn(usize) -> Result<T, MerkleError> + Sync,
+) -> Result<Vec<T>, MerkleError> {
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        if let Some(items) = (0..len).into_par_iter().map(|i| f(i).ok()).collect() {
+            return Ok(items);
+        }
+    }
+    (0..len).map(f).collect()
+}
+
+/// The leaf committing to `secret`: the bytes of its leaf hash, which is what
+/// `constraints::PreimageMembershipCircuit` recomputes from the secret.
+pub fn preimage_leaf(params: &MerkleParams, secret: &[u8]) -> Result<Vec<u8>, MerkleError> {
+    Ok(to_bytes![params.hash_leaf(secret)?]?)
+}
+
+impl MerkleParams {
+    /// The tree over the `preimage_leaf`s of `secrets`, whose number must be a
+    /// power of two.
+    pub fn build_preimage_tree(&self, secrets: &[Vec<u8>]) -> Result<SimpleMerkleTree, MerkleError> {
+        let leaves = secrets
+            .iter()
+            .map(|secret| preimage_leaf(self, secret))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.build_tree(leaves)
+    }
+}
+
+/// `leaf` followed by zeros up to `leaf_len` bytes: the one padding rule of
+/// fixed-length trees and `MTreeVerification::with_leaf_len`, whose public
+/// inputs then don't depend on the length of the leaf. Leaves that differ only
+/// in trailing zeros pad to the same bytes, so give them a fixed width or a
+/// length prefix (`leaf_encoding::ToLeafBytes`) first. Fails if the leaf is
+/// longer than `leaf_len`, or `leaf_len` longer than the leaf hash takes.
+pub fn pad_leaf(leaf: &[u8], leaf_len: usize) -> Result<Leaf, MerkleError> {
+    if leaf_len > MAX_LEAF_LEN {
+        return Err(MerkleError::LeafTooLong {
+            len: leaf_len,
+            max: MAX_LEAF_LEN,
+        });
+    }
+    if leaf.len() > leaf_len {
+        return Err(MerkleError::LeafTooLong {
+            len: leaf.len(),
+            max: leaf_len,
+        });
+    }
+    let mut padded = leaf.to_vec();
+    padded.resize(leaf_len, 0);
+    Ok(Leaf::from(padded))
+}
+
+impl MerkleParams {
+    /// The tree over `leaves`, each padded with `pad_leaf` to `leaf_len`
+    /// bytes; their number must be a power of two.
+    pub fn build_fixed_length_tree<L: AsRef<[u8]>>(
+        &self,
+        leaves: &[L],
+        leaf_len: usize,
+    ) -> Result<SimpleMerkleTree, MerkleError> {
+        let leaves = leaves
+            .iter()
+            .enumerate()
+            .map(|(index, leaf)| {
+                pad_leaf(leaf.as_ref(), leaf_len)
+                    .with_context(|| ErrorContext::new("build_fixed_length_tree").index(index))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.build_tree(leaves)
+    }
+}
+
+/// Leaves as field elements, for hashes that absorb field elements rather
+/// than bytes. Each leaf is packed like `UInt8::new_input_vec` packs public
+/// bytes: little-endian, as many whole bytes per element as fit below the
+/// modulus.
+pub fn bytes_to_field_leaves(leaves: &[Vec<u8>]) -> Vec<Vec<ConstraintF>> {
+    leaves
+        .iter()
+        // packing bytes into field elements cannot fail
+        .map(|leaf| ark_ff::ToConstraintField::<ConstraintF>::to_field_elements(leaf.as_slice()).unwrap())
+        .collect()
+}
+
+/// The leaf `build_padded_tree` fills trees up with. Verifiers must not
+/// accept it as a member: it is in every padded tree that isn't full.
+pub const PADDING_LEAF: &[u8] = b"merkle-tree-tutorial/padding-leaf";
+
+/// A tree over any number of leaves, padded up to a power of two, with
+/// `PADDING_LEAF` unless `TreeOptions` say otherwise. Only the leaves it was
+/// built from can be opened.
+pub struct PaddedTree<P: Config> {
+    pub tree: MerkleTree<P>,
+    len: usize,
+}
+
+impl<P: Config> TreeParams<P> {
+    /// Builds the tree over `leaves`, padded to the next power of two, at least two.
+    pub fn build_padded_tree<L: AsRef<[u8]>>(
+        &self,
+        leaves: impl IntoIterator<Item = L>,
+    ) -> Result<PaddedTree<P>, MerkleError> {
+        let leaves: Vec<L> = leaves.into_iter().collect();
+        let len = leaves.len();
+        let padding = ark_std::iter::repeat(PADDING_LEAF).take(len.next_power_of_two().max(2) - len);
+        Ok(PaddedTree {
+            tree: self.build_borrowed_tree(leaves.iter().map(AsRef::as_ref).chain(padding))?,
+            len,
+        })
+    }
+}
+
+/// What `TreeOptions` fill the slots past the given leaves with. A padding
+/// leaf that could also be a real leaf, like all zeros, must be rejected by
+/// the application, as `PADDING_LEAF` must.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PaddingStrategy {
+    /// `PADDING_LEAF`, like `build_padded_tree`.
+    PaddingLeaf,
+    /// Leaves of this many zero bytes.
+    Zeros(usize),
+    /// The empty leaf, so every padding digest is the leaf hash of nothing.
+    Empty,
+    /// A leaf of the application's choosing.
+    Custom(Leaf),
+}
+
+impl PaddingStrategy {
+    pub(crate) fn leaf(&self) -> Leaf {
+        match self {
+            PaddingStrategy::PaddingLeaf => Leaf::from(PADDING_LEAF),
+            PaddingStrategy::Zeros(len) => Leaf::from(vec![0u8; *len]),
+            PaddingStrategy::Empty => Leaf::from(Vec::new()),
+            PaddingStrategy::Custom(leaf) => leaf.clone(),
+        }
+    }
+}
+
+/// The shape of a tree fixed up front: `height` levels, leaves and root
+/// included like `MerkleTree::height`, whatever the number of leaves. All
+/// trees built with the same options open paths of the same length, so one
+/// membership circuit, `MTreeVerification::blank(params, height, leaf_len)`,
+/// covers them all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeOptions {
+    pub height: usize,
+    pub padding: PaddingStrategy,
+}
+
+impl TreeOptions {
+    /// Trees of `height` levels, at least two, padded with `PADDING_LEAF`.
+    pub fn new(height: usize) -> Self {
+        assert!(height >= 2, "a tree has at least two levels");
+        TreeOptions {
+            height,
+            padding: PaddingStrategy::PaddingLeaf,
+        }
+    }
+
+    /// Trees of room for at least `capacity` leaves: the next power of two,
+    /// at least two. The shape of a tree an application sizes by its number
+    /// of leaves rather than by height.
+    pub fn for_capacity(capacity: usize) -> Self {
+        TreeOptions::new(capacity.next_power_of_two().max(2).trailing_zeros() as usize + 1)
+    }
+
+    pub fn padding(mut self, padding: PaddingStrategy) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// The number of leaves a tree of this height holds.
+    pub fn capacity(&self) -> usize {
+        1 << (self.height - 1)
+    }
+}
+
+impl<P: Config> TreeParams<P> {
+    /// Builds the tree over `leaves` with the height and padding of `options`.
+    /// Fails if there are more leaves than the height has room for.
+    pub fn build_tree_with_options<L: AsRef<[u8]>>(
+        &self,
+        leaves: impl IntoIterator<Item = L>,
+        options: &TreeOptions,
+    ) -> Result<PaddedTree<P>, MerkleError> {
+        let leaves: Vec<L> = leaves.into_iter().collect();
+        let len = leaves.len();
+        if len > options.capacity() {
+            return Err(MerkleError::CapacityExceeded {
+                len,
+                capacity: options.capacity(),
+            });
+        }
+        let padding = options.padding.leaf();
+        let padding = ark_std::iter::repeat(padding.as_ref()).take(options.capacity() - len);
+        Ok(PaddedTree {
+            tree: self.build_borrowed_tree(leaves.iter().map(AsRef::as_ref).chain(padding))?,
+            len,
+        })
+    }
+}
+
+impl<P: Config> PaddedTree<P> {
+    /// The number of leaves the tree was built from, without the padding.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The height of the padded tree, leaves and root included, like `MerkleTree::height`.
+    pub fn height(&self) -> usize {
+        self.tree.height()
+    }
+
+    pub fn root(&self) -> <P::TwoToOneHash as TwoToOneCRH>::Output {
+        self.tree.root()
+    }
+
+    /// Opens the leaf at `index`; the padding leaves are out of range.
+    pub fn prove(&self, index: usize) -> Result<Path<P>, MerkleError> {
+        generate_proof(&self.tree, LeafIndex::checked_new(index as u64, self.len as u64)?)
+    }
+}
+
+// keccak256 of `data`, as raw digest bytes. Used to turn names and identifiers
+// into the fixed-size fields that application leaves are made of.
+#[cfg(feature = "std")]
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&Keccak256::digest(data));
+    out
+}
+
+// Lowercase hex encoding of `bytes`, for printing digests and leaves.
+pub fn to_hex(bytes: &[u8]) -> ark_std::string::String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = ark_std::string::String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        out.push(DIGITS[(b >> 4) as usize] as char);
+        out.push(DIGITS[(b & 0xf) as usize] as char);
+    }
+    out
+}
+
+// Decodes hex produced by `to_hex` (either case); `None` if `s` isn't hex.
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    fn digit(c: u8) -> Option<u8> {
+        match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            b'A'..=b'F' => Some(c - b'A' + 10),
+            _ => None,
+        }
+    }
+    let s = s.as_bytes();
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    s.chunks(2).map(|pair| Some((digit(pair[0])? << 4) | digit(pair[1])?)).collect()
+}
+
+#[test]
+fn test_update_leaf_matches_a_rebuild() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let mut tree = params.build_tree(&leaves).unwrap();
+
+    for index in [5usize, 0, 7].iter().copied() {
+        leaves[index] = vec![0xee, index as u8];
+        let root = update_leaf(&mut tree, LeafIndex::checked_new(index as u64, 8).unwrap(), &leaves[index]).unwrap();
+        assert_eq!(root, params.build_tree(&leaves).unwrap().root());
+        assert!(params.verify_path(&tree.generate_proof(index).unwrap(), &root, &leaves[index]).unwrap());
+    }
+
+    // the LeafUpdateCircuit statement: the same path opens old and new leaf
+    let (old_root, path) = (tree.root(), tree.generate_proof(2).unwrap());
+    let root = update_leaf(&mut tree, LeafIndex::checked_new(2, 8).unwrap(), vec![9u8; 4]).unwrap();
+    assert!(params.verify_path(&path, &old_root, &leaves[2]).unwrap());
+    assert!(params.verify_path(&path, &root, vec![9u8; 4]).unwrap());
+
+    let index = LeafIndex::checked_new(1, 8).unwrap();
+    assert!(matches!(
+        update_leaf(&mut tree, index, vec![0u8; MAX_LEAF_LEN + 1]),
+        Err(MerkleError::LeafTooLong { .. })
+    ));
+    assert_eq!(tree.root(), root);
+    let beyond = LeafIndex::checked_new(8, 16).unwrap();
+    assert!(matches!(update_leaf(&mut tree, beyond, vec![1u8]), Err(MerkleError::LeafIndexOutOfRange { .. })));
+}
+
+#[test]
+fn test_windows_absorb_what_they_hash() {
+    assert_eq!(LeafWindow::INPUT_LEN, 128);
+    assert_eq!(TwoToOneWindow::INPUT_LEN, 2 * DIGEST_LEN);
+    assert!(check_windows::<LeafWindow>(MAX_LEAF_LEN).is_ok());
+    assert!(matches!(
+        check_windows::<Windows<4, 64>>(40),
+        Err(MerkleError::WindowsTooSmall { bits: 320, capacity: 256 })
+    ));
+
+    // a digest is as long as the two-to-one hash expects half its input to be
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    assert_eq!(to_bytes![params.hash_leaf(b"leaf").unwrap()].unwrap().len(), DIGEST_LEN);
+}
+
+#[test]
+fn test_errors_name_what_went_wrong() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+
+    let past_the_end = LeafIndex::checked_new(99, 128).unwrap();
+    assert!(matches!(
+        generate_proof(&tree, past_the_end),
+        Err(MerkleError::LeafIndexOutOfRange { index: 99, len: 8 })
+    ));
+    let path = tree.generate_proof(3).unwrap();
+    assert!(params.check_path(&path, &tree.root(), &leaves[3]).is_ok());
+    assert!(matches!(
+        params.check_path(&path, &tree.root(), &leaves[4]),
+        Err(MerkleError::InvalidProof)
+    ));
+    assert!(matches!(
+        params.check_path(&path, &tree.root(), vec![0u8; MAX_LEAF_LEN + 1]),
+        Err(MerkleError::LeafTooLong { .. })
+    ));
+}
+
+#[test]
+fn test_verify_batch_flags_the_corrupted_proof() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..128).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let indices: Vec<usize> = (0..100).collect();
+    let mut items: Vec<(Vec<u8>, SimplePath)> = indices
+        .iter()
+        .zip(generate_proofs(&tree, &indices).unwrap())
+        .map(|(index, path)| (leaves[*index].clone(), path))
+        .collect();
+    assert!(params.verify_batch(&tree.root(), &items).unwrap());
+
+    items[42].1.auth_path[2] = items[41].1.leaf_sibling_hash;
+    assert!(!params.verify_batch(&tree.root(), &items).unwrap());
+    let each = params.verify_each(&tree.root(), &items).unwrap();
+    assert_eq!(each.iter().filter(|ok| !**ok).count(), 1);
+    assert!(!each[42]);
+}
+
+#[test]
+fn test_verify_batch_agrees_with_verify_path() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..16).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = tree.root();
+    let item = |index: usize| (leaves[index].clone(), tree.generate_proof(index).unwrap());
+    let valid: Vec<_> = [5usize, 4, 7, 5, 12, 0].iter().map(|i| item(*i)).collect();
+    assert!(params.verify_batch(&root, &valid).unwrap());
+    assert!(params.verify_batch::<Vec<u8>>(&root, &[]).unwrap());
+
+    // each corruption after paths that share nodes with it, which the batch
+    // has stopped hashing at by then
+    let mut high_sibling = item(6);
+    high_sibling.1.auth_path[0] = high_sibling.1.leaf_sibling_hash;
+    let mut leaf_sibling = item(6);
+    leaf_sibling.1.leaf_sibling_hash = root;
+    let mut moved = item(6);
+    moved.1.leaf_index = 7;
+    let mut short = item(6);
+    short.1.auth_path.pop();
+    let corrupted = [high_sibling, leaf_sibling, moved, short, (leaves[3].clone(), tree.generate_proof(6).unwrap())];
+    for bad in corrupted.iter() {
+        assert!(!params.verify_path(&bad.1, &root, &bad.0).unwrap());
+        let mut items = valid.clone();
+        items.push(bad.clone());
+        assert!(!params.verify_batch(&root, &items).unwrap());
+        assert_eq!(params.verify_each(&root, &items).unwrap(), [true, true, true, true, true, true, false]);
+    }
+    assert!(!params.verify_batch(&params.hash_leaf(&[1]).unwrap(), &valid).unwrap());
+}
+
+// `cargo test --release -- --ignored --nocapture time_verify_batch`; with
+// `--features parallel`, `verify_each` checks the paths on the rayon pool.
+#[cfg(feature = "std")]
+#[test]
+#[ignore]
+fn time_verify_batch() {
+    use std::time::Instant;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u16..1024).map(|i| i.to_le_bytes().to_vec()).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let indices: Vec<usize> = (0..1000).collect();
+    let items: Vec<(Vec<u8>, SimplePath)> = indices
+        .iter()
+        .zip(generate_proofs(&tree, &indices).unwrap())
+        .map(|(index, path)| (leaves[*index].clone(), path))
+        .collect();
+
+    let start = Instant::now();
+    for (leaf, path) in &items {
+        assert!(params.verify_path(path, &tree.root(), leaf).unwrap());
+    }
+    let naive = start.elapsed();
+    let start = Instant::now();
+    assert!(params.verify_batch(&tree.root(), &items).unwrap());
+    let batch = start.elapsed();
+    let start = Instant::now();
+    assert!(!params.verify_each(&tree.root(), &items).unwrap().contains(&false));
+    let each = start.elapsed();
+    println!("1000 proofs: {:?} one by one, {:?} batched, {:?} each", naive, batch, each);
+}
+
+#[test]
+fn test_params_from_seed() {
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 4]).collect();
+    let root = |seed: [u8; 32]| setup_params_from_seed(seed).unwrap().build_tree(&leaves).unwrap().root();
+    assert_eq!(root([7; 32]), root([7; 32]));
+    assert_ne!(root([7; 32]), root([8; 32]));
+}
+
+#[test]
+fn test_padded_trees() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    for (n, height) in [(1, 2), (5, 4), (8, 4)].iter().copied() {
+        let leaves: Vec<Vec<u8>> = (0..n as u8).map(|i| vec![i; 4]).collect();
+        let tree = params.build_padded_tree(&leaves).unwrap();
+        assert_eq!((tree.len(), tree.height()), (n, height));
+        for (index, leaf) in leaves.iter().enumerate() {
+            assert!(params.verify_path(&tree.prove(index).unwrap(), &tree.root(), leaf).unwrap());
+        }
+        assert!(matches!(
+            tree.prove(n),
+            Err(MerkleError::LeafIndexOutOfRange { index, len }) if index == n && len == n
+        ));
+
+        // the padding is canonical: the same leaves always give the same root
+        let mut padded = leaves.clone();
+        padded.resize(1 << (height - 1), PADDING_LEAF.to_vec());
+        assert_eq!(tree.root(), params.build_tree(&padded).unwrap().root());
+        assert_eq!(tree.root(), params.build_padded_tree(&leaves).unwrap().root());
+    }
+}
+
+#[test]
+fn test_bytes_to_field_leaves() {
+    use ark_ff::PrimeField;
+
+    // whole bytes below the capacity, one bit short of the modulus
+    let per_element = (ConstraintF::size_in_bits() - 1) / 8;
+    let leaves = vec![vec![], vec![1u8; 4], vec![2u8; per_element], vec![2u8; per_element + 1]];
+    let packed = bytes_to_field_leaves(&leaves);
+    let lens: Vec<usize> = packed.iter().map(Vec::len).collect();
+    assert_eq!(lens, vec![0, 1, 1, 2]);
+    assert_eq!(packed[1], vec![ConstraintF::from(0x01010101u64)]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_params_from_seed_agree_between_parties() {
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let root = |params: MerkleParams| params.build_tree(&leaves).unwrap().root();
+
+    let prover = root(MerkleParams::from_seed(b"round 7").unwrap());
+    assert_eq!(root(MerkleParams::from_seed(b"round 7").unwrap()), prover);
+    assert_ne!(root(MerkleParams::from_seed(b"round 8").unwrap()), prover);
+    assert_ne!(root(MerkleParams::from_seed(b"").unwrap()), prover);
+
+    assert_eq!(
+        root(MerkleParams::deterministic().unwrap()),
+        root(crate::kit::default_params().unwrap())
+    );
+}
+
+#[test]
+fn test_tree_options_fix_the_height() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..5).map(|i| vec![i; 4]).collect();
+    let options = TreeOptions::new(5);
+
+    for count in [1, 3, 5].iter().copied() {
+        let tree = params.build_tree_with_options(&leaves[..count], &options).unwrap();
+        assert_eq!((tree.height(), tree.len()), (5, count));
+        let path = tree.prove(count - 1).unwrap();
+        assert_eq!(path.auth_path.len(), 3);
+        assert!(params.verify_path(&path, &tree.root(), &leaves[count - 1]).unwrap());
+        assert!(tree.prove(count).is_err());
+    }
+
+    // the padded slots hold the strategy's leaf
+    let zeros = options.clone().padding(PaddingStrategy::Zeros(4));
+    let padded: Vec<Vec<u8>> = leaves.iter().cloned().chain(vec![vec![0u8; 4]; 11]).collect();
+    assert_eq!(
+        params.build_tree_with_options(&leaves, &zeros).unwrap().root(),
+        params.build_tree(&padded).unwrap().root()
+    );
+    let strategies = [
+        PaddingStrategy::PaddingLeaf,
+        PaddingStrategy::Empty,
+        PaddingStrategy::Custom(Leaf::from(&b"-"[..])),
+    ];
+    let roots: Vec<Root> = strategies
+        .iter()
+        .map(|padding| {
+            let options = options.clone().padding(padding.clone());
+            params.build_tree_with_options(&leaves, &options).unwrap().root()
+        })
+        .collect();
+    assert!(roots[0] != roots[1] && roots[1] != roots[2] && roots[0] != roots[2]);
+    // the default padding at the minimal height is `build_padded_tree`
+    assert_eq!(
+        params.build_tree_with_options(&leaves, &TreeOptions::new(4)).unwrap().root(),
+        params.build_padded_tree(&leaves).unwrap().root()
+    );
+
+    assert!(matches!(
+        params.build_tree_with_options(&leaves, &TreeOptions::new(3)),
+        Err(MerkleError::CapacityExceeded { len: 5, capacity: 4 })
+    ));
+
+    // capacities round up like `build_padded_tree` rounds the leaves
+    let heights: Vec<usize> = [0, 1, 2, 3, 5, 8].iter().map(|c| TreeOptions::for_capacity(*c).height).collect();
+    assert_eq!(heights, vec![2, 2, 2, 3, 4, 4]);
+    assert_eq!(TreeOptions::for_capacity(5).capacity(), 8);
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_pedersen_and_poseidon_circuits() {
+    use crate::circuit_report::synthesize_with_report;
+    use crate::constraints::MTreeVerification;
+    use poseidon::PoseidonMTreeVerification;
+
+    let mut rng = ark_std::test_rng();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 16]).collect();
+    let pedersen = MerkleParams::setup(&mut rng).unwrap();
+    let poseidon = PoseidonParams::setup(&mut rng).unwrap();
+    let (pedersen_tree, poseidon_tree) = (pedersen.build_tree(&leaves).unwrap(), poseidon.build_tree(&leaves).unwrap());
+
+    // each circuit accepts exactly the leaves its native verification does
+    let mut counts = (0, 0);
+    for claimed in [3usize, 4].iter().copied() {
+        let path = pedersen_tree.generate_proof(3).unwrap();
+        let native = pedersen.verify_path(&path, &pedersen_tree.root(), &leaves[claimed]).unwrap();
+        let circuit = <MTreeVerification>::new(&pedersen, pedersen_tree.root(), &leaves[claimed][..], path);
+        let report = synthesize_with_report(circuit).unwrap();
+        assert_eq!(report.is_satisfied(), native);
+        counts.0 = report.metrics.constraints;
+
+        let path = poseidon_tree.generate_proof(3).unwrap();
+        let native = poseidon.verify_path(&path, &poseidon_tree.root(), &leaves[claimed]).unwrap();
+        let circuit = PoseidonMTreeVerification::new(&poseidon, poseidon_tree.root(), &leaves[claimed][..], path);
+        let report = synthesize_with_report(circuit).unwrap();
+        assert_eq!(report.is_satisfied(), native);
+        counts.1 = report.metrics.constraints;
+    }
+    println!("membership of a 16-byte leaf in a tree of 8: pedersen {} constraints, poseidon {}", counts.0, counts.1);
+    assert!(counts.1 < counts.0);
+}
@@ -0,0 +1,419 @@
+use super::{TreeParams, MAX_LEAF_LEN};
+#[cfg(feature = "constraints")]
+use super::ConstraintF;
+#[cfg(feature = "constraints")]
+use crate::constraints::{MerkleCircuitConfig, MerkleTreeVerification};
+#[cfg(feature = "constraints")]
+use crate::inputs::byte_inputs;
+#[cfg(feature = "constraints")]
+use crate::leaf::Leaf;
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::merkle_tree::{Config, MerkleTree, Path};
+use ark_ff::{PrimeField, ToBytes, ToConstraintField};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::io::{Read, Result as IoResult, Write};
+use ark_std::rand::Rng;
+use ark_std::vec::Vec;
+use blake2::{Blake2s, Digest};
+#[cfg(feature = "constraints")]
+use ark_crypto_primitives::crh::{CRHGadget, TwoToOneCRHGadget};
+#[cfg(feature = "constraints")]
+use ark_crypto_primitives::prf::blake2s::constraints::evaluate_blake2s;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::prelude::*;
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSystemRef, Namespace, SynthesisError};
+#[cfg(feature = "constraints")]
+use ark_std::borrow::Borrow;
+
+// Trees over Blake2s instead of Pedersen.
+//
+// Pedersen digests are curve points, which only an arkworks verifier with
+// the same parameters can recompute. Blake2s-256 is a standard byte hash with
+// implementations everywhere, so a Blake2s root or path checks out with any
+// of them: a leaf hashes to `blake2s(0x00 || leaf)` and two nodes to
+// `blake2s(0x01 || left || right)`, the prefixes keeping a leaf from ever
+// hashing like a node. There are no parameters to agree on; `setup` returns
+// the unit `Blake2sParameters` whatever the generator.
+//
+// In the circuit the hash is `evaluate_blake2s` of ark-crypto-primitives,
+// over the little-endian bits of each byte, and a digest is its 32 bytes,
+// the words of the Blake2s state in little-endian order like the native
+// digest. It costs about 21k constraints per compression, several times
+// Pedersen, so Blake2s trees are for interoperability, not for small
+// circuits. A root is a public input of its bytes, packed like a leaf's.
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+fn hash_with_prefix(prefix: u8, input: &[u8]) -> Blake2sDigest {
+    let mut hasher = Blake2s::new();
+    hasher.update(&[prefix]);
+    hasher.update(input);
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&hasher.finalize());
+    Blake2sDigest(digest)
+}
+
+/// A Blake2s-256 digest, the nodes of a Blake2s tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Blake2sDigest(pub [u8; 32]);
+
+impl AsRef<[u8]> for Blake2sDigest {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl ToBytes for Blake2sDigest {
+    fn write<W: Write>(&self, mut writer: W) -> IoResult<()> {
+        writer.write_all(&self.0)
+    }
+}
+
+impl CanonicalSerialize for Blake2sDigest {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        Ok(writer.write_all(&self.0)?)
+    }
+
+    fn serialized_size(&self) -> usize {
+        32
+    }
+}
+
+impl CanonicalDeserialize for Blake2sDigest {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let mut digest = [0u8; 32];
+        reader.read_exact(&mut digest)?;
+        Ok(Blake2sDigest(digest))
+    }
+}
+
+// The public inputs of a digest, packed like `UInt8::new_input_vec` packs them.
+impl<F: PrimeField> ToConstraintField<F> for Blake2sDigest {
+    fn to_field_elements(&self) -> Option<Vec<F>> {
+        <[u8] as ToConstraintField<F>>::to_field_elements(&self.0)
+    }
+}
+
+/// Blake2s has no parameters; this stands in for them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Blake2sParameters;
+
+/// The Blake2s leaf hash.
+pub struct Blake2sLeafHash;
+
+impl CRH for Blake2sLeafHash {
+    // the same limit as the Pedersen leaf hash, so leaf length checks agree
+    const INPUT_SIZE_BITS: usize = MAX_LEAF_LEN * 8;
+    type Output = Blake2sDigest;
+    type Parameters = Blake2sParameters;
+
+    fn setup<R: Rng>(_rng: &mut R) -> Result<Self::Parameters, ark_crypto_primitives::Error> {
+        Ok(Blake2sParameters)
+    }
+
+    fn evaluate(_params: &Self::Parameters, input: &[u8]) -> Result<Self::Output, ark_crypto_primitives::Error> {
+        Ok(hash_with_prefix(LEAF_PREFIX, input))
+    }
+}
+
+/// The Blake2s node hash, over `left || right`.
+pub struct Blake2sTwoToOneHash;
+
+impl TwoToOneCRH for Blake2sTwoToOneHash {
+    const LEFT_INPUT_SIZE_BITS: usize = 256;
+    const RIGHT_INPUT_SIZE_BITS: usize = 256;
+    type Output = Blake2sDigest;
+    type Parameters = Blake2sParameters;
+
+    fn setup<R: Rng>(_rng: &mut R) -> Result<Self::Parameters, ark_crypto_primitives::Error> {
+        Ok(Blake2sParameters)
+    }
+
+    fn evaluate(
+        _params: &Self::Parameters,
+        left_input: &[u8],
+        right_input: &[u8],
+    ) -> Result<Self::Output, ark_crypto_primitives::Error> {
+        let mut input = left_input.to_vec();
+        input.extend_from_slice(right_input);
+        Ok(hash_with_prefix(NODE_PREFIX, &input))
+    }
+}
+
+#[derive(Clone)]
+pub struct Blake2sMerkleConfig;
+
+impl Config for Blake2sMerkleConfig {
+    type LeafHash = Blake2sLeafHash;
+    type TwoToOneHash = Blake2sTwoToOneHash;
+}
+
+pub type Blake2sParams = TreeParams<Blake2sMerkleConfig>;
+pub type Blake2sMerkleTree = MerkleTree<Blake2sMerkleConfig>;
+pub type Blake2sPath = Path<Blake2sMerkleConfig>;
+
+/// The parameters in a circuit: nothing, whatever the allocation mode.
+#[cfg(feature = "constraints")]
+#[derive(Clone)]
+pub struct Blake2sParametersVar;
+
+#[cfg(feature = "constraints")]
+impl AllocVar<Blake2sParameters, ConstraintF> for Blake2sParametersVar {
+    fn new_variable<T: Borrow<Blake2sParameters>>(
+        _cs: impl Into<Namespace<ConstraintF>>,
+        _f: impl FnOnce() -> Result<T, SynthesisError>,
+        _mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        Ok(Blake2sParametersVar)
+    }
+}
+
+/// A digest in a circuit, as its 32 bytes.
+#[cfg(feature = "constraints")]
+#[derive(Clone, Debug)]
+pub struct Blake2sDigestVar(pub Vec<UInt8<ConstraintF>>);
+
+#[cfg(feature = "constraints")]
+impl AllocVar<Blake2sDigest, ConstraintF> for Blake2sDigestVar {
+    fn new_variable<T: Borrow<Blake2sDigest>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        // the shape doesn't depend on the value, so a missing one is zeros
+        let digest = f().map(|digest| digest.borrow().0).unwrap_or([0u8; 32]);
+        let bytes = match mode {
+            AllocationMode::Constant => UInt8::constant_vec(&digest),
+            AllocationMode::Input => UInt8::new_input_vec(cs, &digest)?,
+            AllocationMode::Witness => UInt8::new_witness_vec(cs, &digest)?,
+        };
+        Ok(Blake2sDigestVar(bytes))
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl R1CSVar<ConstraintF> for Blake2sDigestVar {
+    type Value = Blake2sDigest;
+
+    fn cs(&self) -> ConstraintSystemRef<ConstraintF> {
+        self.0.cs()
+    }
+
+    fn value(&self) -> Result<Blake2sDigest, SynthesisError> {
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&self.0.value()?);
+        Ok(Blake2sDigest(digest))
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl EqGadget<ConstraintF> for Blake2sDigestVar {
+    fn is_eq(&self, other: &Self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.0.as_slice().is_eq(other.0.as_slice())
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ToBytesGadget<ConstraintF> for Blake2sDigestVar {
+    fn to_bytes(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        Ok(self.0.clone())
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl CondSelectGadget<ConstraintF> for Blake2sDigestVar {
+    fn conditionally_select(
+        cond: &Boolean<ConstraintF>,
+        true_value: &Self,
+        false_value: &Self,
+    ) -> Result<Self, SynthesisError> {
+        let bits = true_value
+            .0
+            .to_bits_le()?
+            .iter()
+            .zip(false_value.0.to_bits_le()?)
+            .map(|(t, f)| cond.select(t, &f))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Blake2sDigestVar(bits.chunks(8).map(UInt8::from_bits_le).collect()))
+    }
+}
+
+// Blake2s-256 of `input` in the circuit, as the bytes the native digest has.
+#[cfg(feature = "constraints")]
+fn blake2s_var(input: &[UInt8<ConstraintF>]) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+    let words = evaluate_blake2s(&input.to_bits_le()?)?;
+    let mut bytes = Vec::with_capacity(32);
+    for word in &words {
+        bytes.extend(word.to_bytes()?);
+    }
+    Ok(bytes)
+}
+
+#[cfg(feature = "constraints")]
+fn hash_with_prefix_var(prefix: u8, input: &[UInt8<ConstraintF>]) -> Result<Blake2sDigestVar, SynthesisError> {
+    let mut prefixed = vec![UInt8::constant(prefix)];
+    prefixed.extend_from_slice(input);
+    Ok(Blake2sDigestVar(blake2s_var(&prefixed)?))
+}
+
+#[cfg(feature = "constraints")]
+pub struct Blake2sLeafHashGadget;
+
+#[cfg(feature = "constraints")]
+impl CRHGadget<Blake2sLeafHash, ConstraintF> for Blake2sLeafHashGadget {
+    type OutputVar = Blake2sDigestVar;
+    type ParametersVar = Blake2sParametersVar;
+
+    fn evaluate(
+        _params: &Self::ParametersVar,
+        input: &[UInt8<ConstraintF>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        hash_with_prefix_var(LEAF_PREFIX, input)
+    }
+}
+
+#[cfg(feature = "constraints")]
+pub struct Blake2sTwoToOneHashGadget;
+
+#[cfg(feature = "constraints")]
+impl TwoToOneCRHGadget<Blake2sTwoToOneHash, ConstraintF> for Blake2sTwoToOneHashGadget {
+    type OutputVar = Blake2sDigestVar;
+    type ParametersVar = Blake2sParametersVar;
+
+    fn evaluate(
+        _params: &Self::ParametersVar,
+        left_input: &[UInt8<ConstraintF>],
+        right_input: &[UInt8<ConstraintF>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        let mut input = left_input.to_vec();
+        input.extend_from_slice(right_input);
+        hash_with_prefix_var(NODE_PREFIX, &input)
+    }
+}
+
+/// The membership circuit over the Blake2s hashes.
+#[cfg(feature = "constraints")]
+pub struct Blake2sMerkleCircuit;
+
+#[cfg(feature = "constraints")]
+impl MerkleCircuitConfig for Blake2sMerkleCircuit {
+    type Config = Blake2sMerkleConfig;
+    type ConstraintF = ConstraintF;
+    type LeafHashGadget = Blake2sLeafHashGadget;
+    type TwoToOneHashGadget = Blake2sTwoToOneHashGadget;
+}
+
+/// The membership circuit for Blake2s trees.
+#[cfg(feature = "constraints")]
+pub type Blake2sMTreeVerification = MerkleTreeVerification<Blake2sMerkleCircuit>;
+
+#[cfg(feature = "constraints")]
+impl Blake2sMTreeVerification {
+    /// The circuit proving that `leaf` opens `auth_path` against `root`.
+    pub fn new(params: &Blake2sParams, root: Blake2sDigest, leaf: impl Into<Leaf>, auth_path: Blake2sPath) -> Self {
+        Blake2sMTreeVerification {
+            leaf_crh_params: params.leaf_crh_params,
+            two_to_one_crh_params: params.two_to_one_crh_params,
+            root,
+            leaf: leaf.into().into_vec(),
+            auth_path: Some(auth_path),
+        }
+    }
+
+    /// Like `MTreeVerification::blank`: a tree of `tree_height` levels and a
+    /// leaf of `leaf_len` bytes, with dummy inputs and path.
+    pub fn blank(params: &Blake2sParams, tree_height: usize, leaf_len: usize) -> Self {
+        let path = Blake2sPath {
+            leaf_sibling_hash: Default::default(),
+            auth_path: vec![Default::default(); tree_height - 2],
+            leaf_index: 0,
+        };
+        Self::new(params, Default::default(), vec![0u8; leaf_len], path)
+    }
+
+    /// The public inputs: the root bytes, then the leaf bytes, each packed
+    /// like `UInt8::new_input_vec` packs them.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        let mut inputs = <Blake2sDigest as ToConstraintField<ConstraintF>>::to_field_elements(&self.root).unwrap();
+        inputs.extend(byte_inputs(&self.leaf));
+        inputs
+    }
+}
+
+#[test]
+fn test_blake2s_tree() {
+    let params = Blake2sParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 40]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = tree.root();
+
+    let path = tree.generate_proof(5).unwrap();
+    assert!(params.verify_path(&path, &root, &leaves[5]).unwrap());
+    assert!(!params.verify_path(&path, &root, &leaves[4]).unwrap());
+
+    // any Blake2s implementation recomputes the tree from the prefixed inputs
+    let blake2s = |input: &[u8]| {
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&Blake2s::digest(input));
+        digest
+    };
+    let leaf_hash = |leaf: &[u8]| blake2s(&[&[LEAF_PREFIX][..], leaf].concat());
+    let node_hash = |left: [u8; 32], right: [u8; 32]| blake2s(&[&[NODE_PREFIX][..], &left, &right].concat());
+    let mut level: Vec<[u8; 32]> = leaves.iter().map(|leaf| leaf_hash(leaf)).collect();
+    while level.len() > 1 {
+        level = level.chunks(2).map(|pair| node_hash(pair[0], pair[1])).collect();
+    }
+    assert_eq!(root, Blake2sDigest(level[0]));
+    assert_eq!(params.hash_leaf(&leaves[0]).unwrap().0, leaf_hash(&leaves[0]));
+
+    let mut bytes = Vec::new();
+    root.serialize(&mut bytes).unwrap();
+    assert_eq!(Blake2sDigest::deserialize(&bytes[..]).unwrap(), root);
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_blake2s_gadget_matches_native() {
+    use ark_relations::r1cs::ConstraintSystem;
+
+    // the test vector of RFC 7693, without the prefixes
+    let cs = ConstraintSystem::<ConstraintF>::new_ref();
+    let abc = UInt8::new_witness_vec(cs.clone(), b"abc").unwrap();
+    let digest = blake2s_var(&abc).unwrap().value().unwrap();
+    assert_eq!(
+        crate::common::to_hex(&digest),
+        "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982"
+    );
+
+    let params = Blake2sParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaf = UInt8::new_witness_vec(cs.clone(), &[7u8; 40]).unwrap();
+    let leaf_hash = Blake2sLeafHashGadget::evaluate(&Blake2sParametersVar, &leaf).unwrap();
+    assert_eq!(leaf_hash.value().unwrap(), params.hash_leaf(&[7u8; 40]).unwrap());
+    let node = Blake2sTwoToOneHashGadget::evaluate(
+        &Blake2sParametersVar,
+        &leaf_hash.to_bytes().unwrap(),
+        &leaf_hash.to_bytes().unwrap(),
+    )
+    .unwrap();
+    let native = params.hash_leaf(&[7u8; 40]).unwrap();
+    assert_eq!(node.value().unwrap(), params.hash_children(&native, &native).unwrap());
+    assert!(cs.is_satisfied().unwrap());
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_blake2s_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = Blake2sParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 16]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let circuit = |claimed: &[u8]| {
+        Blake2sMTreeVerification::new(&params, tree.root(), claimed, tree.generate_proof(2).unwrap())
+    };
+    let report = assert_satisfied_with_report(circuit(&leaves[2]));
+    expect_unsatisfied(circuit(&leaves[1]), "membership");
+    assert_eq!(circuit(&leaves[2]).public_inputs().len(), report.instance_variables - 1);
+}
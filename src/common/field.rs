@@ -0,0 +1,333 @@
+use super::poseidon::{sponge_elements, Domain, PoseidonParams};
+#[cfg(feature = "constraints")]
+use super::poseidon::sponge_elements_var;
+use super::ConstraintF;
+use crate::error::MerkleError;
+use ark_std::vec::Vec;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::{fields::fp::FpVar, prelude::*};
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, Namespace, SynthesisError};
+#[cfg(feature = "constraints")]
+use ark_std::borrow::Borrow;
+
+// Trees whose leaves are field elements.
+//
+// Every other tree of the crate hashes bytes, so a circuit whose leaf is an
+// `FpVar` already has to split it into 255 bits first (`FieldLeaf`), and
+// every node digest is split again on the way up, because the arkworks
+// `PathVar` passes digests to the node hash as bytes. Here neither happens:
+// a leaf is a list of field elements, absorbed by the Poseidon sponge as
+// they are, and a node is the sponge over its two children, so the
+// membership circuit is the permutations and a selection per level, with no
+// bit decomposition anywhere.
+//
+// The hashes are the sponge of `common::poseidon` with its `PoseidonParams`,
+// in domains of their own, so a field tree never hashes like a byte tree
+// over the same parameters. Paths are the siblings from the leaf up
+// with the leaf index, whose bits say which side each sibling is on.
+
+/// The digest of `leaf`.
+pub fn hash_field_leaf(params: &PoseidonParams, leaf: &[ConstraintF]) -> ConstraintF {
+    sponge_elements(&params.leaf_crh_params, Domain::FieldLeaf, leaf)
+}
+
+/// The parent of `left` and `right`.
+pub fn hash_field_children(params: &PoseidonParams, left: &ConstraintF, right: &ConstraintF) -> ConstraintF {
+    sponge_elements(&params.two_to_one_crh_params, Domain::FieldNode, &[*left, *right])
+}
+
+/// A tree over leaves of field elements.
+#[derive(Clone)]
+pub struct FieldMerkleTree {
+    params: PoseidonParams,
+    leaves: Vec<Vec<ConstraintF>>,
+    // levels[0] are the leaf hashes, the last level is [root]
+    levels: Vec<Vec<ConstraintF>>,
+}
+
+impl FieldMerkleTree {
+    /// The tree over `leaves`; their number must be a power of two, at least two.
+    pub fn new(params: PoseidonParams, leaves: Vec<Vec<ConstraintF>>) -> Self {
+        assert!(
+            leaves.len().is_power_of_two() && leaves.len() > 1,
+            "a tree needs a power of two leaves, at least two"
+        );
+        let mut levels = vec![leaves.iter().map(|leaf| hash_field_leaf(&params, leaf)).collect::<Vec<_>>()];
+        while levels[levels.len() - 1].len() > 1 {
+            let below = &levels[levels.len() - 1];
+            let parents = below
+                .chunks(2)
+                .map(|pair| hash_field_children(&params, &pair[0], &pair[1]))
+                .collect();
+            levels.push(parents);
+        }
+        FieldMerkleTree { params, leaves, levels }
+    }
+
+    pub fn params(&self) -> &PoseidonParams {
+        &self.params
+    }
+
+    pub fn root(&self) -> ConstraintF {
+        self.levels[self.levels.len() - 1][0]
+    }
+
+    /// Levels, leaves and root included, like `MerkleTree::height`.
+    pub fn height(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn leaf(&self, index: usize) -> Option<&[ConstraintF]> {
+        self.leaves.get(index).map(Vec::as_slice)
+    }
+
+    /// The path of the leaf at `index`.
+    pub fn prove(&self, index: usize) -> Result<FieldPath, MerkleError> {
+        if index >= self.leaves.len() {
+            return Err(MerkleError::LeafIndexOutOfRange {
+                index,
+                len: self.leaves.len(),
+            });
+        }
+        let siblings = self.levels[..self.levels.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(level, nodes)| nodes[(index >> level) ^ 1])
+            .collect();
+        Ok(FieldPath {
+            leaf_index: index,
+            siblings,
+        })
+    }
+}
+
+/// The path of a leaf of a `FieldMerkleTree`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FieldPath {
+    pub leaf_index: usize,
+    /// From the leaf's own sibling up to the root's child.
+    pub siblings: Vec<ConstraintF>,
+}
+
+impl FieldPath {
+    /// The root the path leads to from `leaf`.
+    pub fn compute_root(&self, params: &PoseidonParams, leaf: &[ConstraintF]) -> ConstraintF {
+        let mut current = hash_field_leaf(params, leaf);
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            current = if (self.leaf_index >> level) & 1 == 1 {
+                hash_field_children(params, sibling, &current)
+            } else {
+                hash_field_children(params, &current, sibling)
+            };
+        }
+        current
+    }
+
+    /// Whether the path opens `leaf` against `root`. An index past the
+    /// leaves of the tree the siblings make up opens nothing.
+    pub fn verify(&self, params: &PoseidonParams, root: &ConstraintF, leaf: &[ConstraintF]) -> bool {
+        self.siblings.len() < usize::BITS as usize
+            && self.leaf_index >> self.siblings.len() == 0
+            && self.compute_root(params, leaf) == *root
+    }
+}
+
+/// The R1CS equivalent of a `FieldPath`: a position bit and a sibling per level.
+#[cfg(feature = "constraints")]
+pub struct FieldPathVar {
+    // `position[i]` is true iff the node at height i on the path is a right child
+    pub position: Vec<Boolean<ConstraintF>>,
+    pub siblings: Vec<FpVar<ConstraintF>>,
+}
+
+#[cfg(feature = "constraints")]
+impl FieldPathVar {
+    /// Recomputes the root from `leaf` along this path.
+    pub fn calculate_root(
+        &self,
+        params: &PoseidonParams,
+        leaf: &[FpVar<ConstraintF>],
+    ) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        let mut current = sponge_elements_var(&params.leaf_crh_params, Domain::FieldLeaf, leaf)?;
+        for (is_right, sibling) in self.position.iter().zip(&self.siblings) {
+            let left = is_right.select(sibling, &current)?;
+            let right = is_right.select(&current, sibling)?;
+            current = sponge_elements_var(&params.two_to_one_crh_params, Domain::FieldNode, &[left, right])?;
+        }
+        Ok(current)
+    }
+
+    /// Returns a Boolean that is true iff `leaf` is at this position under `root`.
+    pub fn verify_membership(
+        &self,
+        params: &PoseidonParams,
+        root: &FpVar<ConstraintF>,
+        leaf: &[FpVar<ConstraintF>],
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.calculate_root(params, leaf)?.is_eq(root)
+    }
+
+    /// The leaf index as a field element, recomposed from the position bits.
+    pub fn index(&self) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        Boolean::le_bits_to_fp_var(&self.position)
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl AllocVar<FieldPath, ConstraintF> for FieldPathVar {
+    fn new_variable<T: Borrow<FieldPath>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let ns = cs.into();
+        let cs = ns.cs();
+        f().and_then(|val| {
+            let path = val.borrow();
+            let position = (0..path.siblings.len())
+                .map(|i| Boolean::new_variable(cs.clone(), || Ok((path.leaf_index >> i) & 1 == 1), mode))
+                .collect::<Result<Vec<_>, _>>()?;
+            let siblings = Vec::<FpVar<ConstraintF>>::new_variable(cs.clone(), || Ok(&path.siblings[..]), mode)?;
+            Ok(FieldPathVar { position, siblings })
+        })
+    }
+}
+
+/// Proves that `leaf`, a list of field elements, is in the `FieldMerkleTree`
+/// with root `root`. The number of leaf elements is part of the shape of
+/// the circuit.
+#[cfg(feature = "constraints")]
+pub struct FieldMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: PoseidonParams,
+
+    // These are the public inputs to the circuit.
+    pub root: ConstraintF,
+    pub leaf: Vec<ConstraintF>,
+
+    // This is the private witness to the circuit.
+    pub auth_path: Option<FieldPath>,
+}
+
+#[cfg(feature = "constraints")]
+impl FieldMembershipCircuit {
+    /// The circuit proving that `leaf` opens `auth_path` against `root`.
+    pub fn new(params: &PoseidonParams, root: ConstraintF, leaf: &[ConstraintF], auth_path: FieldPath) -> Self {
+        FieldMembershipCircuit {
+            params: params.clone(),
+            root,
+            leaf: leaf.to_vec(),
+            auth_path: Some(auth_path),
+        }
+    }
+
+    /// A circuit of the right shape for key generation: a tree of
+    /// `tree_height` levels and a leaf of `leaf_len` elements.
+    pub fn blank(params: &PoseidonParams, tree_height: usize, leaf_len: usize) -> Self {
+        let path = FieldPath {
+            leaf_index: 0,
+            siblings: vec![Default::default(); tree_height - 1],
+        };
+        Self::new(params, Default::default(), &vec![Default::default(); leaf_len], path)
+    }
+
+    /// The public inputs: the root, then the leaf elements.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        let mut inputs = vec![self.root];
+        inputs.extend_from_slice(&self.leaf);
+        inputs
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for FieldMembershipCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<ConstraintF>) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = FpVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(self.root))?;
+        let leaf = Vec::<FpVar<ConstraintF>>::new_input(ark_relations::ns!(cs, "leaf_var"), || Ok(&self.leaf[..]))?;
+
+        // The hash constants go straight into the permutations, so there is
+        // nothing to allocate for them. Then the path as a private witness:
+        let path = FieldPathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        path.verify_membership(&self.params, &root, &leaf)?
+            .enforce_equal(&Boolean::TRUE)
+    }
+}
+
+#[test]
+fn test_field_tree() {
+    let params = PoseidonParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<ConstraintF>> =
+        (0u64..8).map(|i| vec![ConstraintF::from(i), ConstraintF::from(i * i)]).collect();
+    let tree = FieldMerkleTree::new(params.clone(), leaves.clone());
+    assert_eq!(tree.height(), 4);
+
+    for (index, leaf) in leaves.iter().enumerate() {
+        let path = tree.prove(index).unwrap();
+        assert!(path.verify(&params, &tree.root(), leaf));
+        assert!(!path.verify(&params, &tree.root(), &leaves[index ^ 1]));
+        let mut moved = path.clone();
+        moved.leaf_index ^= 1;
+        assert!(!moved.verify(&params, &tree.root(), leaf));
+        // an index past the tree isn't a way to open the same leaf twice
+        moved.leaf_index = index + 8;
+        assert!(!moved.verify(&params, &tree.root(), leaf));
+    }
+    assert!(matches!(tree.prove(8), Err(MerkleError::LeafIndexOutOfRange { index: 8, len: 8 })));
+
+    // the leaf elements are told apart by position and number
+    let hash = |leaf: &[u64]| {
+        let leaf: Vec<ConstraintF> = leaf.iter().map(|i| ConstraintF::from(*i)).collect();
+        hash_field_leaf(&params, &leaf)
+    };
+    assert_ne!(hash(&[1, 2]), hash(&[2, 1]));
+    assert_ne!(hash(&[1]), hash(&[1, 0]));
+    // and no field leaf hashes like the byte leaf with the same packing
+    assert_ne!(hash(&[7]), params.hash_leaf(&[7u8]).unwrap());
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_field_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+    use crate::common::poseidon::PoseidonMerkleCircuit;
+    use crate::constraints::{FieldLeaf, LeafInput, MerkleTreeVerification};
+
+    let params = PoseidonParams::setup(&mut ark_std::test_rng()).unwrap();
+    let values: Vec<ConstraintF> = (10u64..18).map(ConstraintF::from).collect();
+    let tree = FieldMerkleTree::new(params.clone(), values.iter().map(|v| vec![*v]).collect());
+    let circuit =
+        |leaf: ConstraintF| FieldMembershipCircuit::new(&params, tree.root(), &[leaf], tree.prove(5).unwrap());
+
+    let inputs = circuit(values[5]).public_inputs();
+    let field = assert_satisfied_with_report(circuit(values[5]));
+    assert_eq!(inputs.len(), field.instance_variables - 1);
+    expect_unsatisfied(circuit(values[4]), "membership");
+    assert_eq!(FieldMembershipCircuit::blank(&params, 4, 1).public_inputs().len(), inputs.len());
+
+    // the same values as `FieldLeaf`s of a byte tree, split into bits at the
+    // leaf and at every node
+    let leaves: Vec<Vec<u8>> = values.iter().map(<FieldLeaf as LeafInput<ConstraintF>>::to_bytes).collect();
+    let byte_tree = params.build_tree(&leaves).unwrap();
+    let bytes = assert_satisfied_with_report(MerkleTreeVerification::<PoseidonMerkleCircuit, FieldLeaf> {
+        leaf_crh_params: params.leaf_crh_params.clone(),
+        two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+        root: byte_tree.root(),
+        leaf: values[5],
+        auth_path: Some(byte_tree.generate_proof(5).unwrap()),
+    });
+    assert!(field.constraints * 2 < bytes.constraints);
+}
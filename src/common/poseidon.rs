@@ -0,0 +1,437 @@
+use super::{from_hex, ConstraintF, TreeParams, MAX_LEAF_LEN};
+#[cfg(feature = "constraints")]
+use crate::constraints::{MerkleCircuitConfig, MerkleTreeVerification};
+#[cfg(feature = "constraints")]
+use crate::inputs::PublicInputs;
+#[cfg(feature = "constraints")]
+use crate::leaf::Leaf;
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::merkle_tree::{Config, MerkleTree, Path};
+use ark_ff::{Field, PrimeField, Zero};
+use ark_std::rand::Rng;
+use ark_std::vec::Vec;
+#[cfg(feature = "constraints")]
+use ark_crypto_primitives::crh::{CRHGadget, TwoToOneCRHGadget};
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::{fields::fp::FpVar, prelude::*};
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{Namespace, SynthesisError};
+#[cfg(feature = "constraints")]
+use ark_std::borrow::Borrow;
+
+// Trees over the Poseidon permutation instead of Pedersen.
+//
+// Pedersen costs a curve addition per input window, thousands of constraints
+// per node. A Poseidon permutation is a few hundred field multiplications, and
+// its circuit is the same arithmetic over `FpVar`s, so the membership circuit
+// of a Poseidon tree is several times smaller.
+//
+// The permutation is the reference one of the Poseidon authors for this
+// field (Jubjub's base field is the BLS12-381 scalar field), width 3 with x^5
+// S-boxes: 8 full rounds, half before and half after 57 partial ones. Its
+// round constants and MDS matrix are the published ones, in `constants`, and
+// a test checks the permutation against the reference test vector, so it is
+// the permutation of every other implementation of `poseidonperm_x5_255_3`.
+// The pinned crypto-primitives has no Poseidon sponge to take it from.
+//
+// The sponge over it has one capacity element and a rate of 2, the layout of
+// the arkworks sponge. Input bytes are packed 31 to an element, little-endian,
+// which keeps every element below the modulus, so the packing is a linear
+// combination of the input bits in the circuit and costs no constraints. The
+// capacity element starts at the input length, so inputs that only differ by
+// trailing zero bytes don't collide, plus 2^64 times a domain tag: the
+// constants are fixed, so `setup` gives the leaf and the node hash the same
+// ones, and the tag is what keeps a leaf from hashing like a node, or a byte
+// tree like a `common::field` tree.
+
+const WIDTH: usize = 3;
+const FULL_ROUNDS: usize = 8;
+const PARTIAL_ROUNDS: usize = 57;
+const BYTES_PER_ELEMENT: usize = 31;
+
+mod constants;
+
+/// What a sponge is hashing, which starts it from its own capacity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Domain {
+    Leaf = 0,
+    Node = 1,
+    FieldLeaf = 2,
+    FieldNode = 3,
+}
+
+fn capacity(domain: Domain, len: usize) -> ConstraintF {
+    let two_to_64 = ConstraintF::from(u64::MAX) + ConstraintF::from(1u64);
+    ConstraintF::from(domain as u64) * two_to_64 + ConstraintF::from(len as u64)
+}
+
+fn constant(hex: &str) -> ConstraintF {
+    // the tables are fixed, and checked by the known-answer test
+    ConstraintF::from_be_bytes_mod_order(&from_hex(hex).unwrap())
+}
+
+/// The constants of a Poseidon permutation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PoseidonParameters {
+    /// `WIDTH` round constants per round, full and partial.
+    pub ark: Vec<Vec<ConstraintF>>,
+    pub mds: Vec<Vec<ConstraintF>>,
+}
+
+impl PoseidonParameters {
+    /// The published constants of the reference permutation.
+    pub fn reference() -> Self {
+        let ark = constants::ROUND_CONSTANTS
+            .chunks(WIDTH)
+            .map(|round| round.iter().copied().map(constant).collect())
+            .collect();
+        let mds = constants::MDS.iter().map(|row| row.iter().copied().map(constant).collect()).collect();
+        PoseidonParameters { ark, mds }
+    }
+}
+
+fn is_full_round(round: usize) -> bool {
+    round < FULL_ROUNDS / 2 || round >= FULL_ROUNDS / 2 + PARTIAL_ROUNDS
+}
+
+fn permute(params: &PoseidonParameters, state: &mut [ConstraintF; WIDTH]) {
+    for (round, constants) in params.ark.iter().enumerate() {
+        for (s, c) in state.iter_mut().zip(constants) {
+            *s += c;
+        }
+        let sboxes = if is_full_round(round) { WIDTH } else { 1 };
+        for s in state.iter_mut().take(sboxes) {
+            *s = s.pow([5u64]);
+        }
+        let mut mixed = [ConstraintF::zero(); WIDTH];
+        for (m, row) in mixed.iter_mut().zip(&params.mds) {
+            *m = row.iter().zip(state.iter()).fold(ConstraintF::zero(), |acc, (a, s)| acc + *a * s);
+        }
+        *state = mixed;
+    }
+}
+
+// The elements absorbed per permutation. An empty input is still permuted once.
+fn blocks<T>(elements: &[T]) -> Vec<&[T]> {
+    if elements.is_empty() {
+        vec![&[]]
+    } else {
+        elements.chunks(WIDTH - 1).collect()
+    }
+}
+
+// Absorbs `elements` into a state whose capacity starts at `capacity`.
+fn absorb(params: &PoseidonParameters, capacity: ConstraintF, elements: &[ConstraintF]) -> ConstraintF {
+    let mut state = [capacity, ConstraintF::zero(), ConstraintF::zero()];
+    for block in blocks(elements) {
+        for (s, e) in state[1..].iter_mut().zip(block) {
+            *s += e;
+        }
+        permute(params, &mut state);
+    }
+    state[1]
+}
+
+fn sponge(params: &PoseidonParameters, domain: Domain, input: &[u8]) -> ConstraintF {
+    let elements: Vec<ConstraintF> = input
+        .chunks(BYTES_PER_ELEMENT)
+        .map(ConstraintF::from_le_bytes_mod_order)
+        .collect();
+    absorb(params, capacity(domain, input.len()), &elements)
+}
+
+/// The sponge over field elements themselves, for `common::field`.
+pub(crate) fn sponge_elements(params: &PoseidonParameters, domain: Domain, elements: &[ConstraintF]) -> ConstraintF {
+    absorb(params, capacity(domain, elements.len()), elements)
+}
+
+/// The Poseidon leaf hash.
+pub struct PoseidonLeafHash;
+
+impl CRH for PoseidonLeafHash {
+    // the same limit as the Pedersen leaf hash, so leaf length checks agree
+    const INPUT_SIZE_BITS: usize = MAX_LEAF_LEN * 8;
+    type Output = ConstraintF;
+    type Parameters = PoseidonParameters;
+
+    fn setup<R: Rng>(_rng: &mut R) -> Result<Self::Parameters, ark_crypto_primitives::Error> {
+        Ok(PoseidonParameters::reference())
+    }
+
+    fn evaluate(params: &Self::Parameters, input: &[u8]) -> Result<Self::Output, ark_crypto_primitives::Error> {
+        Ok(sponge(params, Domain::Leaf, input))
+    }
+}
+
+/// The Poseidon node hash: the sponge over `left || right`, in its own domain.
+pub struct PoseidonTwoToOneHash;
+
+impl TwoToOneCRH for PoseidonTwoToOneHash {
+    const LEFT_INPUT_SIZE_BITS: usize = 256;
+    const RIGHT_INPUT_SIZE_BITS: usize = 256;
+    type Output = ConstraintF;
+    type Parameters = PoseidonParameters;
+
+    fn setup<R: Rng>(_rng: &mut R) -> Result<Self::Parameters, ark_crypto_primitives::Error> {
+        Ok(PoseidonParameters::reference())
+    }
+
+    fn evaluate(
+        params: &Self::Parameters,
+        left_input: &[u8],
+        right_input: &[u8],
+    ) -> Result<Self::Output, ark_crypto_primitives::Error> {
+        let mut input = left_input.to_vec();
+        input.extend_from_slice(right_input);
+        Ok(sponge(params, Domain::Node, &input))
+    }
+}
+
+#[derive(Clone)]
+pub struct PoseidonMerkleConfig;
+
+impl Config for PoseidonMerkleConfig {
+    type LeafHash = PoseidonLeafHash;
+    type TwoToOneHash = PoseidonTwoToOneHash;
+}
+
+pub type PoseidonParams = TreeParams<PoseidonMerkleConfig>;
+pub type PoseidonMerkleTree = MerkleTree<PoseidonMerkleConfig>;
+pub type PoseidonPath = Path<PoseidonMerkleConfig>;
+
+/// The constants of a permutation in a circuit. They are always constants,
+/// whatever the allocation mode.
+#[cfg(feature = "constraints")]
+#[derive(Clone)]
+pub struct PoseidonParametersVar(PoseidonParameters);
+
+#[cfg(feature = "constraints")]
+impl AllocVar<PoseidonParameters, ConstraintF> for PoseidonParametersVar {
+    fn new_variable<T: Borrow<PoseidonParameters>>(
+        _cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        _mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        Ok(PoseidonParametersVar(f()?.borrow().clone()))
+    }
+}
+
+#[cfg(feature = "constraints")]
+fn permute_var(params: &PoseidonParameters, state: &mut [FpVar<ConstraintF>; WIDTH]) -> Result<(), SynthesisError> {
+    for (round, constants) in params.ark.iter().enumerate() {
+        for (s, c) in state.iter_mut().zip(constants) {
+            *s += *c;
+        }
+        let sboxes = if is_full_round(round) { WIDTH } else { 1 };
+        for s in state.iter_mut().take(sboxes) {
+            let s4 = s.square()?.square()?;
+            *s = s4 * &*s;
+        }
+        let mut mixed = [FpVar::zero(), FpVar::zero(), FpVar::zero()];
+        for (m, row) in mixed.iter_mut().zip(&params.mds) {
+            for (a, s) in row.iter().zip(state.iter()) {
+                *m += s.clone() * *a;
+            }
+        }
+        *state = mixed;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "constraints")]
+fn absorb_var(
+    params: &PoseidonParameters,
+    capacity: ConstraintF,
+    elements: &[FpVar<ConstraintF>],
+) -> Result<FpVar<ConstraintF>, SynthesisError> {
+    let mut state = [FpVar::constant(capacity), FpVar::zero(), FpVar::zero()];
+    for block in blocks(elements) {
+        for (s, e) in state[1..].iter_mut().zip(block) {
+            *s += e;
+        }
+        permute_var(params, &mut state)?;
+    }
+    Ok(state[1].clone())
+}
+
+#[cfg(feature = "constraints")]
+fn sponge_var(
+    params: &PoseidonParameters,
+    domain: Domain,
+    input: &[UInt8<ConstraintF>],
+) -> Result<FpVar<ConstraintF>, SynthesisError> {
+    let elements = input
+        .chunks(BYTES_PER_ELEMENT)
+        .map(|chunk| Boolean::le_bits_to_fp_var(&chunk.to_bits_le()?))
+        .collect::<Result<Vec<_>, _>>()?;
+    absorb_var(params, capacity(domain, input.len()), &elements)
+}
+
+/// `sponge_elements` in the circuit.
+#[cfg(feature = "constraints")]
+pub(crate) fn sponge_elements_var(
+    params: &PoseidonParameters,
+    domain: Domain,
+    elements: &[FpVar<ConstraintF>],
+) -> Result<FpVar<ConstraintF>, SynthesisError> {
+    absorb_var(params, capacity(domain, elements.len()), elements)
+}
+
+#[cfg(feature = "constraints")]
+pub struct PoseidonLeafHashGadget;
+
+#[cfg(feature = "constraints")]
+impl CRHGadget<PoseidonLeafHash, ConstraintF> for PoseidonLeafHashGadget {
+    type OutputVar = FpVar<ConstraintF>;
+    type ParametersVar = PoseidonParametersVar;
+
+    fn evaluate(params: &Self::ParametersVar, input: &[UInt8<ConstraintF>]) -> Result<Self::OutputVar, SynthesisError> {
+        sponge_var(&params.0, Domain::Leaf, input)
+    }
+}
+
+#[cfg(feature = "constraints")]
+pub struct PoseidonTwoToOneHashGadget;
+
+#[cfg(feature = "constraints")]
+impl TwoToOneCRHGadget<PoseidonTwoToOneHash, ConstraintF> for PoseidonTwoToOneHashGadget {
+    type OutputVar = FpVar<ConstraintF>;
+    type ParametersVar = PoseidonParametersVar;
+
+    fn evaluate(
+        params: &Self::ParametersVar,
+        left_input: &[UInt8<ConstraintF>],
+        right_input: &[UInt8<ConstraintF>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        let mut input = left_input.to_vec();
+        input.extend_from_slice(right_input);
+        sponge_var(&params.0, Domain::Node, &input)
+    }
+}
+
+/// The membership circuit over the Poseidon hashes.
+#[cfg(feature = "constraints")]
+pub struct PoseidonMerkleCircuit;
+
+#[cfg(feature = "constraints")]
+impl MerkleCircuitConfig for PoseidonMerkleCircuit {
+    type Config = PoseidonMerkleConfig;
+    type ConstraintF = ConstraintF;
+    type LeafHashGadget = PoseidonLeafHashGadget;
+    type TwoToOneHashGadget = PoseidonTwoToOneHashGadget;
+}
+
+/// The membership circuit for Poseidon trees.
+#[cfg(feature = "constraints")]
+pub type PoseidonMTreeVerification = MerkleTreeVerification<PoseidonMerkleCircuit>;
+
+#[cfg(feature = "constraints")]
+impl PoseidonMTreeVerification {
+    /// The circuit proving that `leaf` opens `auth_path` against `root`.
+    pub fn new(params: &PoseidonParams, root: ConstraintF, leaf: impl Into<Leaf>, auth_path: PoseidonPath) -> Self {
+        PoseidonMTreeVerification {
+            leaf_crh_params: params.leaf_crh_params.clone(),
+            two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+            root,
+            leaf: leaf.into().into_vec(),
+            auth_path: Some(auth_path),
+        }
+    }
+
+    /// Like `MTreeVerification::blank`: a tree of `tree_height` levels and a
+    /// leaf of `leaf_len` bytes, with dummy inputs and path.
+    pub fn blank(params: &PoseidonParams, tree_height: usize, leaf_len: usize) -> Self {
+        let path = PoseidonPath {
+            leaf_sibling_hash: Default::default(),
+            auth_path: vec![Default::default(); tree_height - 2],
+            leaf_index: 0,
+        };
+        Self::new(params, Default::default(), vec![0u8; leaf_len], path)
+    }
+
+    /// The public inputs, laid out like those of `MTreeVerification`: the
+    /// root, then the leaf bytes.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        PublicInputs::from_parts(&self.root, &self.leaf).to_field_elements()
+    }
+}
+
+#[test]
+fn test_poseidon_reference_vector() {
+    // `poseidonperm_x5_255_3` of the reference test vectors, on 0, 1, 2
+    let params = PoseidonParameters::reference();
+    let mut state = [ConstraintF::from(0u64), ConstraintF::from(1u64), ConstraintF::from(2u64)];
+    permute(&params, &mut state);
+    let expected = [
+        "28ce19420fc246a05553ad1e8c98f5c9d67166be2c18e9e4cb4b4e317dd2a78a",
+        "51f3e312c95343a896cfd8945ea82ba956c1118ce9b9859b6ea56637b4b1ddc4",
+        "3b2b69139b235626a0bfb56c9527ae66a7bf486ad8c11c14d1da0c69bbe0f79a",
+    ];
+    assert_eq!(state.to_vec(), expected.iter().copied().map(constant).collect::<Vec<_>>());
+
+    // and the gadget is the same permutation
+    #[cfg(feature = "constraints")]
+    {
+        let cs = ark_relations::r1cs::ConstraintSystem::<ConstraintF>::new_ref();
+        let mut vars = [FpVar::zero(), FpVar::zero(), FpVar::zero()];
+        for (var, i) in vars.iter_mut().zip(0u64..) {
+            *var = FpVar::new_witness(cs.clone(), || Ok(ConstraintF::from(i))).unwrap();
+        }
+        permute_var(&params, &mut vars).unwrap();
+        let values: Vec<ConstraintF> = vars.iter().map(|var| var.value().unwrap()).collect();
+        assert_eq!(values, state.to_vec());
+        assert!(cs.is_satisfied().unwrap());
+    }
+}
+
+#[test]
+fn test_poseidon_tree() {
+    let params = PoseidonParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 40]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = tree.root();
+
+    let path = tree.generate_proof(5).unwrap();
+    assert!(params.verify_path(&path, &root, &leaves[5]).unwrap());
+    assert!(!params.verify_path(&path, &root, &leaves[4]).unwrap());
+
+    // the constants are fixed, but a leaf doesn't hash like a node
+    assert_eq!(params.leaf_crh_params, params.two_to_one_crh_params);
+    let (left, right) = ([1u8; 32], [2u8; 32]);
+    let as_leaf = PoseidonLeafHash::evaluate(&params.leaf_crh_params, &[left, right].concat()).unwrap();
+    let as_node = PoseidonTwoToOneHash::evaluate(&params.two_to_one_crh_params, &left, &right).unwrap();
+    assert_ne!(as_leaf, as_node);
+
+    // trailing zeros and the empty leaf still hash apart
+    let hashes: Vec<_> = [&[][..], &[0u8][..], &[0u8; 31][..], &[0u8; 32][..]]
+        .iter()
+        .map(|leaf| params.hash_leaf(leaf).unwrap())
+        .collect();
+    for (i, a) in hashes.iter().enumerate() {
+        assert!(hashes[i + 1..].iter().all(|b| a != b));
+    }
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_poseidon_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied, synthesize_with_report};
+    use crate::constraints::MTreeVerification;
+
+    let params = PoseidonParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 40]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let circuit = |claimed: &[u8]| {
+        PoseidonMTreeVerification::new(&params, tree.root(), claimed, tree.generate_proof(3).unwrap())
+    };
+    let poseidon = assert_satisfied_with_report(circuit(&leaves[3]));
+    expect_unsatisfied(circuit(&leaves[2]), "membership");
+    assert_eq!(circuit(&leaves[3]).public_inputs().len(), poseidon.instance_variables - 1);
+
+    // the same tree shape over Pedersen
+    let pedersen_params = crate::common::MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let pedersen = synthesize_with_report(<MTreeVerification>::blank(&pedersen_params, 4, 40)).unwrap().metrics;
+    let blank = synthesize_with_report(PoseidonMTreeVerification::blank(&params, 4, 40)).unwrap().metrics;
+    assert_eq!(blank.constraints, poseidon.constraints);
+    assert!(poseidon.constraints * 4 < pedersen.constraints);
+}
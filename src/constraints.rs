@@ -1,62 +1,1635 @@
 
 use crate::common::*;
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::{byte_inputs, PublicInputs, PublicInputsBuilder};
+use crate::leaf::Leaf;
 use crate::{Root, SimplePath};
-use ark_crypto_primitives::crh::{TwoToOneCRH, TwoToOneCRHGadget, CRH};
+use ark_crypto_primitives::crh::{CRHGadget, TwoToOneCRH, TwoToOneCRHGadget, CRH};
 use ark_crypto_primitives::merkle_tree::constraints::PathVar;
+use ark_crypto_primitives::merkle_tree::{Config, Path};
+use ark_ff::{to_bytes, PrimeField, ToConstraintField};
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
-use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, Namespace, SynthesisError};
+use ark_std::borrow::Borrow;
 
 
 
+/// Everything the membership circuit is generic over: the native tree
+/// configuration, the field the constraints live in, and the gadgets that
+/// compute the tree's two hashes in that field.
+pub trait MerkleCircuitConfig {
+    type Config: Config;
+    type ConstraintF: PrimeField;
+    type LeafHashGadget: CRHGadget<<Self::Config as Config>::LeafHash, Self::ConstraintF>;
+    type TwoToOneHashGadget: TwoToOneCRHGadget<<Self::Config as Config>::TwoToOneHash, Self::ConstraintF>;
+}
+
+/// The hash backend `MTreeVerification<C>` is generic over, under the name
+/// the backend swap goes by; `JubjubMerkleCircuit` is the default one.
+pub use self::MerkleCircuitConfig as TreeBackend;
+
+/// How the membership circuit takes its leaf as a public input: the native
+/// value, the bytes the tree hashes for it, the field elements a verifier
+/// passes for it, and the variable handed to the leaf hash gadget.
+pub trait LeafInput<F: PrimeField> {
+    type Value;
+    type Var: ToBytesGadget<F>;
+
+    /// The leaf as the native tree hashes it.
+    fn to_bytes(value: &Self::Value) -> Vec<u8>;
+
+    /// The public inputs `new_input` allocates, in order.
+    fn to_field_elements(value: &Self::Value) -> Vec<F>;
+
+    fn new_input(cs: impl Into<Namespace<F>>, value: &Self::Value) -> Result<Self::Var, SynthesisError>;
+}
+
+/// A leaf of raw bytes, allocated with `UInt8::new_input_vec`. The leaves of
+/// this crate.
+pub struct ByteLeaf;
+
+impl<F: PrimeField> LeafInput<F> for ByteLeaf {
+    type Value = Vec<u8>;
+    type Var = Vec<UInt8<F>>;
+
+    fn to_bytes(value: &Vec<u8>) -> Vec<u8> {
+        value.clone()
+    }
+
+    fn to_field_elements(value: &Vec<u8>) -> Vec<F> {
+        <[u8] as ToConstraintField<F>>::to_field_elements(value).unwrap()
+    }
+
+    fn new_input(cs: impl Into<Namespace<F>>, value: &Vec<u8>) -> Result<Vec<UInt8<F>>, SynthesisError> {
+        UInt8::new_input_vec(cs, value)
+    }
+}
+
+/// A leaf that is one field element, hashed as its canonical bytes and
+/// allocated as a single public input rather than byte by byte.
+pub struct FieldLeaf;
+
+impl<F: PrimeField> LeafInput<F> for FieldLeaf {
+    type Value = F;
+    type Var = FpVar<F>;
+
+    fn to_bytes(value: &F) -> Vec<u8> {
+        to_bytes![value].unwrap()
+    }
+
+    fn to_field_elements(value: &F) -> Vec<F> {
+        vec![*value]
+    }
+
+    fn new_input(cs: impl Into<Namespace<F>>, value: &F) -> Result<FpVar<F>, SynthesisError> {
+        FpVar::new_input(cs, || Ok(*value))
+    }
+}
+
+/// A leaf of at most `LEN` bytes, padded with `pad_leaf` to exactly `LEN`
+/// both where the native tree hashes it and where the circuit allocates it,
+/// so that every leaf of a fixed-length tree gives a circuit of the same
+/// shape. A longer leaf can't be padded; `FixedLenMTreeVerification::new`
+/// rejects it with `LeafTooLong`, and a circuit built around that check
+/// fails to synthesize rather than be unsatisfiable.
+pub struct PaddedLeaf<const LEN: usize>;
+
+impl<F: PrimeField, const LEN: usize> LeafInput<F> for PaddedLeaf<LEN> {
+    type Value = Vec<u8>;
+    type Var = Vec<UInt8<F>>;
+
+    fn to_bytes(value: &Vec<u8>) -> Vec<u8> {
+        // a leaf too long to pad is left as it is, for `new_input` to refuse
+        pad_leaf(value, LEN).map(Leaf::into_vec).unwrap_or_else(|_| value.clone())
+    }
+
+    fn to_field_elements(value: &Vec<u8>) -> Vec<F> {
+        <[u8] as ToConstraintField<F>>::to_field_elements(&<Self as LeafInput<F>>::to_bytes(value)).unwrap()
+    }
+
+    fn new_input(cs: impl Into<Namespace<F>>, value: &Vec<u8>) -> Result<Vec<UInt8<F>>, SynthesisError> {
+        if value.len() > LEN {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+        UInt8::new_input_vec(cs, &<Self as LeafInput<F>>::to_bytes(value))
+    }
+}
+
+/// The tree of this crate: Pedersen over Jubjub, constraints over its base field.
+pub struct JubjubMerkleCircuit;
+
+impl MerkleCircuitConfig for JubjubMerkleCircuit {
+    type Config = crate::MerkleConfig;
+    type ConstraintF = ConstraintF;
+    type LeafHashGadget = LeafHashGadget;
+    type TwoToOneHashGadget = TwoToOneHashGadget;
+}
+
+/// The R1CS equivalent of the root of a `C` tree.
+pub type MerkleRootVar<C> = <<C as MerkleCircuitConfig>::TwoToOneHashGadget as TwoToOneCRHGadget<
+    <<C as MerkleCircuitConfig>::Config as Config>::TwoToOneHash,
+    <C as MerkleCircuitConfig>::ConstraintF,
+>>::OutputVar;
+
+// The hash parameters of a `C` tree, as circuit constants.
+type MerkleLeafParamsVar<C> = <<C as MerkleCircuitConfig>::LeafHashGadget as CRHGadget<
+    <<C as MerkleCircuitConfig>::Config as Config>::LeafHash,
+    <C as MerkleCircuitConfig>::ConstraintF,
+>>::ParametersVar;
+type MerkleTwoToOneParamsVar<C> = <<C as MerkleCircuitConfig>::TwoToOneHashGadget as TwoToOneCRHGadget<
+    <<C as MerkleCircuitConfig>::Config as Config>::TwoToOneHash,
+    <C as MerkleCircuitConfig>::ConstraintF,
+>>::ParametersVar;
+
+/// The R1CS equivalent of the path of a `C` tree.
+pub type MerklePathVar<C> = PathVar<
+    <C as MerkleCircuitConfig>::Config,
+    <C as MerkleCircuitConfig>::LeafHashGadget,
+    <C as MerkleCircuitConfig>::TwoToOneHashGadget,
+    <C as MerkleCircuitConfig>::ConstraintF,
+>;
+
 /// The R1CS equivalent of the the Merkle tree root.
-pub type RootVar = <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::OutputVar;
+pub type RootVar = MerkleRootVar<JubjubMerkleCircuit>;
 
 /// The R1CS equivalent of the the Merkle tree path.
-pub type SimplePathVar =
-PathVar<crate::MerkleConfig, LeafHashGadget, TwoToOneHashGadget, ConstraintF>;
+pub type SimplePathVar = MerklePathVar<JubjubMerkleCircuit>;
+
+/// The root `path` leads to from `leaf`. `SimplePathVar::verify_membership`
+/// only says whether that is a given root; this hands back the root itself,
+/// for circuits that feed it into further constraints, like a state
+/// transition whose new root is the input of the next step.
+/// `IndexedPathVar::calculate_root` is the same for indexed paths.
+pub fn compute_root_var(
+    leaf_crh_params: &LeafHashParamsVar,
+    two_to_one_crh_params: &TwoToOneHashParamsVar,
+    leaf: &[UInt8<ConstraintF>],
+    path: &SimplePathVar,
+) -> Result<RootVar, SynthesisError> {
+    path.calculate_root(leaf_crh_params, two_to_one_crh_params, &leaf)
+}
+
+/// A tree built inside the circuit, from leaf variables up to its root, for
+/// circuits that commit to a fixed set of witnesses. The digests are those
+/// of `MerkleParams::build_tree` over the same leaves, so the root can be
+/// compared with a native one or opened by a native path.
+pub struct MerkleTreeVar {
+    leaf_hashes: Vec<LeafDigestVar>,
+    // levels[0] are the parents of the leaves, the last level is [root]
+    levels: Vec<Vec<RootVar>>,
+}
+
+impl MerkleTreeVar {
+    /// Hashes `leaves` into a tree. Fails with `Unsatisfiable` unless their
+    /// number is a power of two, at least two.
+    pub fn new(
+        leaf_crh_params: &LeafHashParamsVar,
+        two_to_one_crh_params: &TwoToOneHashParamsVar,
+        leaves: &[Vec<UInt8<ConstraintF>>],
+    ) -> Result<Self, SynthesisError> {
+        if !leaves.len().is_power_of_two() || leaves.len() < 2 {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+        let hash = |left: &[UInt8<ConstraintF>], right: &[UInt8<ConstraintF>]| {
+            <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::evaluate(
+                two_to_one_crh_params,
+                left,
+                right,
+            )
+        };
+        let leaf_hashes = leaves
+            .iter()
+            .map(|leaf| <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(leaf_crh_params, leaf))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut levels = vec![leaf_hashes
+            .chunks(2)
+            .map(|pair| hash(&pair[0].to_bytes()?, &pair[1].to_bytes()?))
+            .collect::<Result<Vec<_>, _>>()?];
+        while levels[levels.len() - 1].len() > 1 {
+            let parents = levels[levels.len() - 1]
+                .chunks(2)
+                .map(|pair| hash(&pair[0].to_bytes()?, &pair[1].to_bytes()?))
+                .collect::<Result<Vec<_>, _>>()?;
+            levels.push(parents);
+        }
+        Ok(MerkleTreeVar { leaf_hashes, levels })
+    }
+
+    pub fn root(&self) -> &RootVar {
+        &self.levels[self.levels.len() - 1][0]
+    }
 
-pub struct MTreeVerification {
+    pub fn leaf_hash(&self, index: usize) -> Option<&LeafDigestVar> {
+        self.leaf_hashes.get(index)
+    }
+
+    /// The inner node at `index` of `level`, counting levels from the leaves
+    /// at 0 like `LevelTree::node`; `None` on the leaf level, see `leaf_hash`.
+    pub fn node(&self, level: usize, index: usize) -> Option<&RootVar> {
+        self.levels.get(level.checked_sub(1)?)?.get(index)
+    }
+}
+
+/// A path of the right shape for a tree with `tree_height` levels (leaves and
+/// root included, like `MerkleTree::height`), filled with dummy values. Used to
+/// synthesize circuits for key generation, where only the shape matters.
+pub fn blank_path(tree_height: usize) -> SimplePath {
+    SimplePath {
+        leaf_sibling_hash: Default::default(),
+        auth_path: vec![Default::default(); tree_height - 2],
+        leaf_index: 0,
+    }
+}
+
+/// The membership circuit of a `C` tree, with a public leaf taken as `L`.
+pub struct MerkleTreeVerification<C: MerkleCircuitConfig, L: LeafInput<C::ConstraintF> = ByteLeaf> {
+    // These are constants that will be embedded into the circuit
+    pub leaf_crh_params: <<C::Config as Config>::LeafHash as CRH>::Parameters,
+    pub two_to_one_crh_params: <<C::Config as Config>::TwoToOneHash as TwoToOneCRH>::Parameters,
+
+    // These are the public inputs to the circuit.
+    pub root: <<C::Config as Config>::TwoToOneHash as TwoToOneCRH>::Output,
+    pub leaf: L::Value,
+
+    // This is the private witness to the circuit.
+    pub auth_path: Option<Path<C::Config>>,
+}
+
+/// The membership circuit of a `C` tree, by default one of this crate's
+/// Pedersen trees. An expression leaves `C` to inference, and so finds the
+/// `new` of `Blake2sMTreeVerification` and `PoseidonMTreeVerification` as
+/// well: the constructors below are called as `<MTreeVerification>::new`,
+/// which takes the default.
+pub type MTreeVerification<C = JubjubMerkleCircuit> = MerkleTreeVerification<C>;
+
+impl MTreeVerification {
+    /// The circuit proving that `leaf` opens `auth_path` against `root`.
+    pub fn new(params: &MerkleParams, root: Root, leaf: impl Into<Leaf>, auth_path: SimplePath) -> Self {
+        Self {
+            leaf_crh_params: params.leaf_crh_params.clone(),
+            two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+            root,
+            leaf: leaf.into().into_vec(),
+            auth_path: Some(auth_path),
+        }
+    }
+
+    /// The circuit for trees built with `MerkleParams::build_fixed_length_tree`:
+    /// `leaf` is padded with `pad_leaf` to `leaf_len` bytes, so every leaf
+    /// gives the same public inputs and one verifying key covers them all.
+    /// Fails, before any constraint is generated, if `leaf` doesn't fit.
+    pub fn with_leaf_len(
+        params: &MerkleParams,
+        root: Root,
+        leaf: &[u8],
+        leaf_len: usize,
+        auth_path: SimplePath,
+    ) -> Result<Self, MerkleError> {
+        Ok(Self::new(params, root, pad_leaf(leaf, leaf_len)?, auth_path))
+    }
+
+    /// A circuit of the right shape for key generation and constraint
+    /// counting: a tree of `tree_height` levels and a leaf of `leaf_len`
+    /// bytes, with dummy inputs and path.
+    pub fn blank(params: &MerkleParams, tree_height: usize, leaf_len: usize) -> Self {
+        Self::new(params, Default::default(), vec![0u8; leaf_len], blank_path(tree_height))
+    }
+
+    /// The public inputs to verify a proof of this circuit with, in the order
+    /// `generate_constraints` allocates them: the root, then the leaf bytes.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        PublicInputs::from_parts(&self.root, &self.leaf).to_field_elements()
+    }
+}
+
+/// The membership circuit for leaves of a fixed `LEN` bytes, as the trees of
+/// `MerkleParams::build_fixed_length_tree(leaves, LEN)` hash them.
+pub type FixedLenMTreeVerification<const LEN: usize> = MerkleTreeVerification<JubjubMerkleCircuit, PaddedLeaf<LEN>>;
+
+impl<const LEN: usize> FixedLenMTreeVerification<LEN> {
+    /// The circuit proving that `leaf`, padded to `LEN` bytes, opens
+    /// `auth_path` against `root`. Fails with `LeafTooLong` if `leaf` is
+    /// longer than `LEN`, or `LEN` longer than the leaf hash takes.
+    pub fn new(params: &MerkleParams, root: Root, leaf: &[u8], auth_path: SimplePath) -> Result<Self, MerkleError> {
+        pad_leaf(leaf, LEN)?;
+        Ok(MerkleTreeVerification {
+            leaf_crh_params: params.leaf_crh_params.clone(),
+            two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+            root,
+            leaf: leaf.to_vec(),
+            auth_path: Some(auth_path),
+        })
+    }
+
+    /// A circuit of the right shape for key generation: a tree of
+    /// `tree_height` levels, and leaves of `LEN` bytes.
+    pub fn blank(params: &MerkleParams, tree_height: usize) -> Self {
+        MerkleTreeVerification {
+            leaf_crh_params: params.leaf_crh_params.clone(),
+            two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+            root: Default::default(),
+            leaf: Vec::new(),
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit: the root, then the padded leaf bytes.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        let mut inputs = vec![self.root];
+        inputs.extend(<PaddedLeaf<LEN> as LeafInput<ConstraintF>>::to_field_elements(&self.leaf));
+        inputs
+    }
+}
+
+impl<C: MerkleCircuitConfig, L: LeafInput<C::ConstraintF>> ConstraintSynthesizer<C::ConstraintF>
+    for MerkleTreeVerification<C, L>
+{
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<C::ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // A longer leaf doesn't fit the leaf hash gadget, which would panic on
+        // it. Natively such a leaf never verifies, so the circuit can't be satisfied.
+        if L::to_bytes(&self.leaf).len() * 8 > <<C::Config as Config>::LeafHash as CRH>::INPUT_SIZE_BITS {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        // First, we allocate the public inputs
+        let root = MerkleRootVar::<C>::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+
+        let leaf = L::new_input(ark_relations::ns!(cs, "leaf_var"), &self.leaf)?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params = MerkleLeafParamsVar::<C>::new_constant(cs.clone(), &self.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            MerkleTwoToOneParamsVar::<C>::new_constant(cs.clone(), &self.two_to_one_crh_params)?;
+
+        // Finally, we allocate our path as a private witness variable:
+        // The shape of the path comes from its value, so even in setup mode a
+        // circuit needs one; `MTreeVerification::blank` has a dummy one.
+        let path = MerklePathVar::<C>::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        //let leaf_bytes = vec![leaf;1];
+
+        // Everything from here on is traced under "membership", so a wrong
+        // leaf or path is reported there rather than in some gadget.
+        let _membership = ark_relations::ns!(cs, "membership");
+        let is_member = path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaf)?;
+
+        is_member.enforce_equal(&Boolean::TRUE)?;
+
+        Ok(())
+    }
+}
+
+/// The values one membership proof is about: the root and leaf it reveals,
+/// and the path it keeps private. An `MTreeCircuit` of the matching shape
+/// proves it.
+#[derive(Clone)]
+pub struct WitnessAssignment {
+    pub root: Root,
+    pub leaf: Vec<u8>,
+    pub auth_path: SimplePath,
+}
+
+impl WitnessAssignment {
+    pub fn new(root: Root, leaf: impl Into<Leaf>, auth_path: SimplePath) -> Self {
+        WitnessAssignment {
+            root,
+            leaf: leaf.into().into_vec(),
+            auth_path,
+        }
+    }
+
+    /// The levels of the path above the leaf: one less than the height of its tree.
+    pub fn depth(&self) -> usize {
+        self.auth_path.auth_path.len() + 1
+    }
+
+    /// The public inputs of a proof of this assignment, as for `MTreeVerification`.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        PublicInputs::from_parts(&self.root, &self.leaf).to_field_elements()
+    }
+}
+
+/// The membership circuit for trees of `DEPTH` levels above their leaves
+/// and leaves of `LEAF_LEN` bytes, whatever is proven with it. The shape
+/// is in the type and the witness is kept apart from it: `MTreeCircuit::setup`
+/// is the circuit with dummy values, for generating the keys once, and
+/// `assign` the same circuit with a `WitnessAssignment`, for proving with
+/// those keys any leaf and path of that shape.
+pub struct MTreeCircuit<const DEPTH: usize, const LEAF_LEN: usize = 32> {
+    // These are constants that will be embedded into the circuit
+    pub leaf_crh_params: <LeafHash as CRH>::Parameters,
+    pub two_to_one_crh_params: <TwoToOneHash as TwoToOneCRH>::Parameters,
+
+    // These are the public inputs and private witness to the circuit; none
+    // for setup, where dummy ones of the right shape stand in
+    pub assignment: Option<WitnessAssignment>,
+}
+
+impl<const DEPTH: usize, const LEAF_LEN: usize> MTreeCircuit<DEPTH, LEAF_LEN> {
+    /// The circuit for key generation.
+    pub fn setup(params: &MerkleParams) -> Self {
+        MTreeCircuit {
+            leaf_crh_params: params.leaf_crh_params.clone(),
+            two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+            assignment: None,
+        }
+    }
+
+    /// The circuit proving `assignment`. Fails with `ShapeMismatch` unless
+    /// its path has `DEPTH` levels and its leaf `LEAF_LEN` bytes.
+    pub fn assign(params: &MerkleParams, assignment: WitnessAssignment) -> Result<Self, MerkleError> {
+        Self::setup(params).with_assignment(assignment)
+    }
+
+    /// This circuit, proving `assignment` instead.
+    pub fn with_assignment(mut self, assignment: WitnessAssignment) -> Result<Self, MerkleError> {
+        if assignment.depth() != DEPTH || assignment.leaf.len() != LEAF_LEN {
+            return Err(MerkleError::ShapeMismatch {
+                depth: assignment.depth(),
+                leaf_len: assignment.leaf.len(),
+                expected_depth: DEPTH,
+                expected_leaf_len: LEAF_LEN,
+            });
+        }
+        self.assignment = Some(assignment);
+        Ok(self)
+    }
+
+    /// The public inputs to verify a proof of this circuit with, if it has
+    /// an assignment.
+    pub fn public_inputs(&self) -> Option<Vec<ConstraintF>> {
+        self.assignment.as_ref().map(WitnessAssignment::public_inputs)
+    }
+}
+
+impl<const DEPTH: usize, const LEAF_LEN: usize> ConstraintSynthesizer<ConstraintF> for MTreeCircuit<DEPTH, LEAF_LEN> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<ConstraintF>) -> Result<(), SynthesisError> {
+        let assignment = self.assignment.unwrap_or_else(|| {
+            WitnessAssignment::new(Default::default(), vec![0u8; LEAF_LEN], blank_path(DEPTH + 1))
+        });
+        let circuit: MTreeVerification = MTreeVerification {
+            leaf_crh_params: self.leaf_crh_params,
+            two_to_one_crh_params: self.two_to_one_crh_params,
+            root: assignment.root,
+            leaf: assignment.leaf,
+            auth_path: Some(assignment.auth_path),
+        };
+        circuit.generate_constraints(cs)
+    }
+}
+
+/// The membership circuit for trees of any depth up to `MAX_DEPTH` levels
+/// above their leaves, and leaves of `LEAF_LEN` bytes. It always has
+/// `MAX_DEPTH` levels, each with a Boolean selector: the selectors of the
+/// lowest `depth` levels are on and hash the path up, those of the levels
+/// above are off, pass the node through unchanged and must have the zero
+/// sibling and position bit. The depth is a public input, so one key proves
+/// membership in every tree up to the bound, and a verifier still checks the
+/// proof against the depth of the tree it means.
+pub struct MaxDepthCircuit<const MAX_DEPTH: usize, const LEAF_LEN: usize = 32> {
     // These are constants that will be embedded into the circuit
     pub leaf_crh_params: <LeafHash as CRH>::Parameters,
     pub two_to_one_crh_params: <TwoToOneHash as TwoToOneCRH>::Parameters,
 
+    // These are the public inputs and private witness to the circuit; none
+    // for setup, where dummy ones of the full depth stand in
+    pub assignment: Option<WitnessAssignment>,
+}
+
+impl<const MAX_DEPTH: usize, const LEAF_LEN: usize> MaxDepthCircuit<MAX_DEPTH, LEAF_LEN> {
+    /// The circuit for key generation.
+    pub fn setup(params: &MerkleParams) -> Self {
+        MaxDepthCircuit {
+            leaf_crh_params: params.leaf_crh_params.clone(),
+            two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+            assignment: None,
+        }
+    }
+
+    /// The circuit proving `assignment`. Fails with `ShapeMismatch` unless
+    /// its path has at most `MAX_DEPTH` levels and its leaf `LEAF_LEN` bytes.
+    pub fn assign(params: &MerkleParams, assignment: WitnessAssignment) -> Result<Self, MerkleError> {
+        if assignment.depth() > MAX_DEPTH || assignment.leaf.len() != LEAF_LEN {
+            return Err(MerkleError::ShapeMismatch {
+                depth: assignment.depth(),
+                leaf_len: assignment.leaf.len(),
+                expected_depth: MAX_DEPTH,
+                expected_leaf_len: LEAF_LEN,
+            });
+        }
+        Ok(MaxDepthCircuit {
+            assignment: Some(assignment),
+            ..Self::setup(params)
+        })
+    }
+
+    /// The public inputs to verify a proof of this circuit with, if it has an
+    /// assignment: the root, the depth, then the leaf bytes.
+    pub fn public_inputs(&self) -> Option<Vec<ConstraintF>> {
+        let assignment = self.assignment.as_ref()?;
+        Some(max_depth_public_inputs(&assignment.root, assignment.depth(), &assignment.leaf))
+    }
+}
+
+/// The public inputs of a `MaxDepthCircuit` proof that `leaf` is in the tree
+/// of `root`, `depth` levels deep.
+pub fn max_depth_public_inputs(root: &Root, depth: usize, leaf: &[u8]) -> Vec<ConstraintF> {
+    let mut inputs = vec![*root, ConstraintF::from(depth as u64)];
+    inputs.extend(byte_inputs(leaf));
+    inputs
+}
+
+impl<const MAX_DEPTH: usize, const LEAF_LEN: usize> ConstraintSynthesizer<ConstraintF>
+    for MaxDepthCircuit<MAX_DEPTH, LEAF_LEN>
+{
+    fn generate_constraints(self, cs: ConstraintSystemRef<ConstraintF>) -> Result<(), SynthesisError> {
+        let assignment = self.assignment.unwrap_or_else(|| {
+            WitnessAssignment::new(Default::default(), vec![0u8; LEAF_LEN], blank_path(MAX_DEPTH + 1))
+        });
+        let depth = assignment.depth();
+        if depth > MAX_DEPTH || assignment.leaf.len() != LEAF_LEN {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+        let siblings = crate::walkthrough::path_siblings(&assignment.auth_path);
+
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(assignment.root))?;
+        let depth_var = FpVar::new_input(ark_relations::ns!(cs, "depth_var"), || Ok(ConstraintF::from(depth as u64)))?;
+        let leaf = UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_var"), &assignment.leaf)?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params = LeafHashParamsVar::new_constant(cs.clone(), &self.leaf_crh_params)?;
+        let two_to_one_crh_params = TwoToOneHashParamsVar::new_constant(cs.clone(), &self.two_to_one_crh_params)?;
+        let padding = RootVar::new_constant(cs.clone(), Root::default())?;
+
+        // Finally, per level, the selector, the position bit and the sibling as
+        // private witnesses, the levels past the depth padded:
+        let mut enabled = Vec::with_capacity(MAX_DEPTH);
+        let mut position = Vec::with_capacity(MAX_DEPTH);
+        let mut sibling_vars = Vec::with_capacity(MAX_DEPTH);
+        for level in 0..MAX_DEPTH {
+            enabled.push(Boolean::new_witness(ark_relations::ns!(cs, "enabled_var"), || Ok(level < depth))?);
+            position.push(Boolean::new_witness(ark_relations::ns!(cs, "position_var"), || {
+                Ok(level < depth && (assignment.auth_path.leaf_index >> level) & 1 == 1)
+            })?);
+            sibling_vars.push(RootVar::new_witness(ark_relations::ns!(cs, "sibling_var"), || {
+                Ok(siblings.get(level).copied().unwrap_or_default())
+            })?);
+        }
+
+        // The selectors are on for the lowest levels, and then off: at least
+        // one is on, none is after one that is off, and there are `depth` of
+        // them. The levels that are off are all padding.
+        {
+            let _depth = ark_relations::ns!(cs, "depth");
+            enabled[0].enforce_equal(&Boolean::TRUE)?;
+            for pair in enabled.windows(2) {
+                pair[1].and(&pair[0])?.enforce_equal(&pair[1])?;
+            }
+            let count = enabled.iter().fold(FpVar::zero(), |count, on| count + FpVar::from(on.clone()));
+            count.enforce_equal(&depth_var)?;
+            for ((on, is_right), sibling) in enabled.iter().zip(&position).zip(&sibling_vars) {
+                is_right.conditional_enforce_equal(&Boolean::FALSE, &on.not())?;
+                sibling.conditional_enforce_equal(&padding, &on.not())?;
+            }
+        }
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        let mut current = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(&leaf_crh_params, &leaf)?;
+        for ((on, is_right), sibling) in enabled.iter().zip(&position).zip(&sibling_vars) {
+            let left = is_right.select(sibling, &current)?;
+            let right = is_right.select(&current, sibling)?;
+            let parent = <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::evaluate(
+                &two_to_one_crh_params,
+                &left.to_bytes()?,
+                &right.to_bytes()?,
+            )?;
+            current = on.select(&parent, &current)?;
+        }
+        current.enforce_equal(&root)
+    }
+}
+
+/// The R1CS equivalent of a leaf digest.
+pub type LeafDigestVar = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::OutputVar;
+
+/// A Merkle path witness that, unlike `SimplePathVar`, keeps the position bits
+/// of the leaf accessible, so circuits can reason about *where* a leaf sits
+/// (ordering, distinctness, index binding) and not only whether it is present.
+pub struct IndexedPathVar {
+    // `position[i]` is true iff the node at height i on the path is a right child.
+    // `position[0]` therefore is the lowest bit of the leaf index.
+    pub position: Vec<Boolean<ConstraintF>>,
+    pub leaf_sibling: LeafDigestVar,
+    // Ordered like `SimplePath::auth_path`, i.e. from the root down.
+    pub auth_path: Vec<RootVar>,
+}
+
+impl IndexedPathVar {
+    /// Recomputes the root from `leaf` along this path.
+    pub fn calculate_root(
+        &self,
+        leaf_crh_params: &LeafHashParamsVar,
+        two_to_one_crh_params: &TwoToOneHashParamsVar,
+        leaf: &[UInt8<ConstraintF>],
+    ) -> Result<RootVar, SynthesisError> {
+        let leaf_hash =
+            <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(leaf_crh_params, leaf)?;
+
+        let is_right = &self.position[0];
+        let left = is_right.select(&self.leaf_sibling, &leaf_hash)?;
+        let right = is_right.select(&leaf_hash, &self.leaf_sibling)?;
+        let mut current = <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::evaluate(
+            two_to_one_crh_params,
+            &left.to_bytes()?,
+            &right.to_bytes()?,
+        )?;
+
+        // walk up the tree, the auth path is stored top-down so we consume it in reverse
+        for (is_right, sibling) in self.position[1..].iter().zip(self.auth_path.iter().rev()) {
+            let left = is_right.select(sibling, &current)?;
+            let right = is_right.select(&current, sibling)?;
+            current = <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::evaluate(
+                two_to_one_crh_params,
+                &left.to_bytes()?,
+                &right.to_bytes()?,
+            )?;
+        }
+        Ok(current)
+    }
+
+    /// Returns a Boolean that is true iff `leaf` is at this position under `root`.
+    pub fn verify_membership(
+        &self,
+        leaf_crh_params: &LeafHashParamsVar,
+        two_to_one_crh_params: &TwoToOneHashParamsVar,
+        root: &RootVar,
+        leaf: &[UInt8<ConstraintF>],
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let computed_root = self.calculate_root(leaf_crh_params, two_to_one_crh_params, leaf)?;
+        computed_root.is_eq(root)
+    }
+
+    /// Enforces that replacing `old_leaf` by `new_leaf` at this position turns
+    /// `old_root` into `new_root`. Both roots are recomputed from the same
+    /// siblings and position bits, so the prover can't switch paths in between.
+    pub fn enforce_update(
+        &self,
+        leaf_crh_params: &LeafHashParamsVar,
+        two_to_one_crh_params: &TwoToOneHashParamsVar,
+        old_root: &RootVar,
+        new_root: &RootVar,
+        old_leaf: &[UInt8<ConstraintF>],
+        new_leaf: &[UInt8<ConstraintF>],
+    ) -> Result<(), SynthesisError> {
+        self.calculate_root(leaf_crh_params, two_to_one_crh_params, old_leaf)?
+            .enforce_equal(old_root)?;
+        self.calculate_root(leaf_crh_params, two_to_one_crh_params, new_leaf)?
+            .enforce_equal(new_root)
+    }
+
+    /// The leaf index as a field element, recomposed from the position bits.
+    pub fn index(&self) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        Boolean::le_bits_to_fp_var(&self.position)
+    }
+}
+
+impl AllocVar<SimplePath, ConstraintF> for IndexedPathVar {
+    fn new_variable<T: Borrow<SimplePath>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let ns = cs.into();
+        let cs = ns.cs();
+        f().and_then(|val| {
+            let path = val.borrow();
+            // one position bit for the leaf level and one per auth path entry
+            let position = (0..path.auth_path.len() + 1)
+                .map(|i| {
+                    Boolean::new_variable(cs.clone(), || Ok((path.leaf_index >> i) & 1 == 1), mode)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let leaf_sibling =
+                LeafDigestVar::new_variable(cs.clone(), || Ok(path.leaf_sibling_hash), mode)?;
+            let auth_path =
+                Vec::<RootVar>::new_variable(cs.clone(), || Ok(&path.auth_path[..]), mode)?;
+            Ok(IndexedPathVar {
+                position,
+                leaf_sibling,
+                auth_path,
+            })
+        })
+    }
+}
+
+/// Proves that `new_root` is `old_root` with exactly one leaf replaced: the old
+/// leaf opens the path against `old_root`, and the new leaf opens the same
+/// path, siblings and position, against `new_root`. The two leaves are
+/// witnesses, and their lengths are part of the shape of the circuit.
+pub struct LeafUpdateCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub old_root: Root,
+    pub new_root: Root,
+
+    // These are the private witnesses to the circuit.
+    pub old_leaf: Vec<u8>,
+    pub new_leaf: Vec<u8>,
+    pub auth_path: Option<SimplePath>,
+}
+
+/// The update circuit under the name of the check it makes of `update_leaf`:
+/// that the root `update_leaf` returns is the old root with one leaf changed.
+pub type MTreeUpdateVerification = LeafUpdateCircuit;
+
+impl LeafUpdateCircuit {
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        vec![self.old_root, self.new_root]
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for LeafUpdateCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        let max_bits = <LeafHash as CRH>::INPUT_SIZE_BITS;
+        if self.old_leaf.len() * 8 > max_bits || self.new_leaf.len() * 8 > max_bits {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        // First, we allocate the public inputs
+        let old_root =
+            RootVar::new_input(ark_relations::ns!(cs, "old_root_var"), || Ok(&self.old_root))?;
+        let new_root =
+            RootVar::new_input(ark_relations::ns!(cs, "new_root_var"), || Ok(&self.new_root))?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, both leaves and the one path they share as private witnesses:
+        let old_leaf = UInt8::new_witness_vec(ark_relations::ns!(cs, "old_leaf_var"), &self.old_leaf)?;
+        let new_leaf = UInt8::new_witness_vec(ark_relations::ns!(cs, "new_leaf_var"), &self.new_leaf)?;
+        let path = IndexedPathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let _update = ark_relations::ns!(cs, "update");
+        path.enforce_update(
+            &leaf_crh_params,
+            &two_to_one_crh_params,
+            &old_root,
+            &new_root,
+            &old_leaf,
+            &new_leaf,
+        )
+    }
+}
+
+/// Proves that every one of N leaves is in the tree with root `root`, with the
+/// one proof `generate_multi_proof` makes for them. The nodes the leaves share
+/// are hashed once, so it takes fewer constraints than N `MTreeVerification`s.
+pub type MTreeBatchVerification = crate::multiproof::MultiMembershipProofCircuit;
+
+/// Proves that every one of `leaves` is in the tree with root `root`. The root
+/// is allocated once and the hash parameters are constants, so each leaf only
+/// adds its own bytes, path and path verification to the circuit.
+pub struct MultiMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
     // These are the public inputs to the circuit.
     pub root: Root,
+    pub leaves: Vec<Vec<u8>>,
+
+    // These are the private witnesses to the circuit, one path per leaf.
+    pub auth_paths: Option<Vec<SimplePath>>,
+}
+
+impl MultiMembershipCircuit {
+    /// The public inputs of the circuit, in allocation order: the root, then
+    /// the bytes of every leaf.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        let mut inputs = vec![self.root];
+        for leaf in &self.leaves {
+            inputs.extend(byte_inputs(leaf));
+        }
+        inputs
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for MultiMembershipCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        if self.leaves.iter().any(|leaf| leaf.len() * 8 > <LeafHash as CRH>::INPUT_SIZE_BITS) {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let leaves = self
+            .leaves
+            .iter()
+            .map(|leaf| UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_var"), leaf))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Then, we allocate the public parameters as constants, once for all leaves:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, we allocate one path per leaf as private witnesses:
+        let auth_paths = self.auth_paths.as_ref();
+        let paths = (0..leaves.len())
+            .map(|i| {
+                SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+                    auth_paths.and_then(|paths| paths.get(i)).ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        for (leaf, path) in leaves.iter().zip(paths.iter()) {
+            let is_member =
+                path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaf.as_slice())?;
+            is_member.enforce_equal(&Boolean::TRUE)?;
+        }
+        Ok(())
+    }
+}
+
+/// Proves that `leaf` is in the tree with root `root` at position `index`.
+/// `MTreeVerification` only shows that the leaf is somewhere in the tree;
+/// here the index is a public input too, and the position bits the path is
+/// walked with must recompose to it.
+pub struct PositionalMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub index: LeafIndex,
     pub leaf: Vec<u8>,
 
     // This is the private witness to the circuit.
     pub auth_path: Option<SimplePath>,
 }
 
+impl PositionalMembershipCircuit {
+    pub fn new(
+        params: &MerkleParams,
+        root: Root,
+        index: LeafIndex,
+        leaf: impl Into<Leaf>,
+        auth_path: SimplePath,
+    ) -> Self {
+        PositionalMembershipCircuit {
+            params: params.clone(),
+            root,
+            index,
+            leaf: leaf.into().into_vec(),
+            auth_path: Some(auth_path),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order: the root, the
+    /// index, then the leaf bytes.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        PublicInputsBuilder::index_bound()
+            .root(&self.root)
+            .index(self.index)
+            .leaf_bytes(&self.leaf)
+            .finish()
+    }
+}
 
-impl ConstraintSynthesizer<ConstraintF> for MTreeVerification {
+impl ConstraintSynthesizer<ConstraintF> for PositionalMembershipCircuit {
     fn generate_constraints(
         self,
         cs: ConstraintSystemRef<ConstraintF>,
     ) -> Result<(), SynthesisError> {
+        if self.leaf.len() * 8 > <LeafHash as CRH>::INPUT_SIZE_BITS {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
         // First, we allocate the public inputs
         let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let index = FpVar::new_input(ark_relations::ns!(cs, "index_var"), || {
+            Ok(ConstraintF::from(self.index.get()))
+        })?;
+        let leaf = UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_var"), &self.leaf)?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
 
-        let leaf = self.leaf.as_slice();
-        let leaf_bytes = UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_var"),  leaf)?;
+        // Finally, we allocate our path, with its position bits, as a private witness:
+        let path = IndexedPathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaf)?
+            .enforce_equal(&Boolean::TRUE)?;
+        // the bits the root was recomputed with are those of the claimed index
+        let _position = ark_relations::ns!(cs, "position");
+        path.index()?.enforce_equal(&index)
+    }
+}
+
+/// Proves knowledge of a secret whose `preimage_leaf` is in the tree with root
+/// `root`. The root is the only public input: the leaf is hashed from the
+/// secret inside the circuit, so neither appears in the instance.
+pub struct PreimageMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // This is the public input to the circuit.
+    pub root: Root,
+
+    // These are the private witnesses to the circuit. The length of the
+    // secret is part of the shape of the circuit.
+    pub secret_preimage: Vec<u8>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl PreimageMembershipCircuit {
+    /// The public inputs of the circuit: the root alone.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        vec![self.root]
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for PreimageMembershipCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        if self.secret_preimage.len() * 8 > <LeafHash as CRH>::INPUT_SIZE_BITS {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        // First, we allocate the public input
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
 
         // Then, we allocate the public parameters as constants:
-        let leaf_crh_params = LeafHashParamsVar::new_constant(cs.clone(), &self.leaf_crh_params)?;
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
         let two_to_one_crh_params =
-            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.two_to_one_crh_params)?;
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
 
-        // Finally, we allocate our path as a private witness variable:
+        // Finally, the secret and its path as private witnesses:
+        let secret =
+            UInt8::new_witness_vec(ark_relations::ns!(cs, "secret_var"), &self.secret_preimage)?;
         let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
-            Ok(self.auth_path.as_ref().unwrap())
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
         })?;
 
-        //let leaf_bytes = vec![leaf;1];
+        // the leaf is the hash of the secret, as `preimage_leaf` computes it
+        let leaf = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(&leaf_crh_params, &secret)?
+            .to_bytes()?;
 
-        let is_member = path.verify_membership(&leaf_crh_params,
-                                               &two_to_one_crh_params, &root, &leaf_bytes.as_slice())?;
+        let _membership = ark_relations::ns!(cs, "membership");
+        path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaf.as_slice())?
+            .enforce_equal(&Boolean::TRUE)
+    }
+}
 
-        is_member.enforce_equal(&Boolean::TRUE)?;
+/// Proves that the keccak256 digest of a secret preimage is in the tree with
+/// root `root`, for trees whose leaves are `Leaf::keccak_of` their data. The
+/// digest is computed from the preimage inside the circuit, so the proof
+/// covers the data itself, not just a leaf someone claims to know the data
+/// of. The root is the only public input.
+pub struct MTreePreimageVerification {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
 
-        Ok(())
+    // This is the public input to the circuit.
+    pub root: Root,
+
+    // These are the private witnesses to the circuit. The length of the
+    // preimage is part of the shape of the circuit.
+    pub preimage: Vec<u8>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl MTreePreimageVerification {
+    /// The circuit proving that `keccak256(preimage)` opens `auth_path` against `root`.
+    pub fn new(params: &MerkleParams, root: Root, preimage: &[u8], auth_path: SimplePath) -> Self {
+        MTreePreimageVerification {
+            params: params.clone(),
+            root,
+            preimage: preimage.to_vec(),
+            auth_path: Some(auth_path),
+        }
+    }
+
+    /// The public inputs of the circuit: the root alone.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        vec![self.root]
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for MTreePreimageVerification {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public input
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the preimage and its path as private witnesses:
+        let preimage = UInt8::new_witness_vec(ark_relations::ns!(cs, "preimage_var"), &self.preimage)?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // the leaf is the digest of the preimage, as `Leaf::keccak_of` computes it
+        let leaf = {
+            let _keccak = ark_relations::ns!(cs, "keccak");
+            crate::keccak::keccak256(&preimage)?
+        };
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaf.as_slice())?
+            .enforce_equal(&Boolean::TRUE)
     }
 }
+
+// A second instantiation of the membership circuit, with the same Pedersen
+// windows over the Edwards curve on BLS12-377, whose constraints live in a
+// different field than Jubjub's. Groth16 proofs over BLS12-377 can be
+// verified inside BW6-761 circuits, which is what `recursion` does with it.
+#[cfg(any(test, feature = "recursion"))]
+type Bls12_377LeafHash = ark_crypto_primitives::crh::injective_map::PedersenCRHCompressor<
+    ark_ed_on_bls12_377::EdwardsProjective,
+    ark_crypto_primitives::crh::injective_map::TECompressor,
+    LeafWindow,
+>;
+
+#[cfg(any(test, feature = "recursion"))]
+type Bls12_377TwoToOneHash = ark_crypto_primitives::crh::injective_map::PedersenCRHCompressor<
+    ark_ed_on_bls12_377::EdwardsProjective,
+    ark_crypto_primitives::crh::injective_map::TECompressor,
+    TwoToOneWindow,
+>;
+
+/// The trees of this crate, over the Edwards curve on BLS12-377.
+#[cfg(any(test, feature = "recursion"))]
+#[derive(Clone)]
+pub struct Bls12_377MerkleConfig;
+
+#[cfg(any(test, feature = "recursion"))]
+impl Config for Bls12_377MerkleConfig {
+    type LeafHash = Bls12_377LeafHash;
+    type TwoToOneHash = Bls12_377TwoToOneHash;
+}
+
+/// The membership circuit of `Bls12_377MerkleConfig` trees, over the
+/// scalar field of BLS12-377.
+#[cfg(any(test, feature = "recursion"))]
+pub struct Bls12_377MerkleCircuit;
+
+#[cfg(any(test, feature = "recursion"))]
+impl MerkleCircuitConfig for Bls12_377MerkleCircuit {
+    type Config = Bls12_377MerkleConfig;
+    type ConstraintF = ark_ed_on_bls12_377::Fq;
+    type LeafHashGadget = ark_crypto_primitives::crh::injective_map::constraints::PedersenCRHCompressorGadget<
+        ark_ed_on_bls12_377::EdwardsProjective,
+        ark_crypto_primitives::crh::injective_map::TECompressor,
+        LeafWindow,
+        ark_ed_on_bls12_377::constraints::EdwardsVar,
+        ark_crypto_primitives::crh::injective_map::constraints::TECompressorGadget,
+    >;
+    type TwoToOneHashGadget = ark_crypto_primitives::crh::injective_map::constraints::PedersenCRHCompressorGadget<
+        ark_ed_on_bls12_377::EdwardsProjective,
+        ark_crypto_primitives::crh::injective_map::TECompressor,
+        TwoToOneWindow,
+        ark_ed_on_bls12_377::constraints::EdwardsVar,
+        ark_crypto_primitives::crh::injective_map::constraints::TECompressorGadget,
+    >;
+}
+
+// Builds a four-leaf `C` tree, opens leaf 2 and claims `claimed_leaf` is there.
+#[cfg(test)]
+fn membership_circuit<C: MerkleCircuitConfig>(claimed_leaf: &[u8]) -> MerkleTreeVerification<C> {
+    let mut rng = ark_std::test_rng();
+    let leaf_crh_params = <<C::Config as Config>::LeafHash as CRH>::setup(&mut rng).unwrap();
+    let two_to_one_crh_params = <<C::Config as Config>::TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = ark_crypto_primitives::merkle_tree::MerkleTree::<C::Config>::new(
+        &leaf_crh_params,
+        &two_to_one_crh_params,
+        &leaves,
+    )
+    .unwrap();
+
+    MerkleTreeVerification::<C> {
+        root: tree.root(),
+        auth_path: Some(tree.generate_proof(2).unwrap()),
+        leaf_crh_params,
+        two_to_one_crh_params,
+        leaf: claimed_leaf.to_vec(),
+    }
+}
+
+#[test]
+fn test_membership_circuit_over_two_fields() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    assert_satisfied_with_report(membership_circuit::<JubjubMerkleCircuit>(&[2u8; 8]));
+    expect_unsatisfied(membership_circuit::<JubjubMerkleCircuit>(&[3u8; 8]), "membership");
+
+    assert_satisfied_with_report(membership_circuit::<Bls12_377MerkleCircuit>(&[2u8; 8]));
+    expect_unsatisfied(membership_circuit::<Bls12_377MerkleCircuit>(&[3u8; 8]), "membership");
+}
+
+#[test]
+fn test_membership_circuit_with_a_field_element_leaf() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+    use ark_relations::r1cs::ConstraintSystem;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let values: Vec<ConstraintF> = (0u64..4).map(|i| ConstraintF::from(i * 1_000)).collect();
+    let leaves: Vec<Vec<u8>> = values.iter().map(<FieldLeaf as LeafInput<ConstraintF>>::to_bytes).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let circuit = |leaf: ConstraintF| MerkleTreeVerification::<JubjubMerkleCircuit, FieldLeaf> {
+        leaf_crh_params: params.leaf_crh_params.clone(),
+        two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+        root: tree.root(),
+        leaf,
+        auth_path: Some(tree.generate_proof(2).unwrap()),
+    };
+
+    let metrics = assert_satisfied_with_report(circuit(values[2]));
+    expect_unsatisfied(circuit(values[3]), "membership");
+
+    // the root and the leaf, one element each
+    assert_eq!(metrics.instance_variables, 3);
+    let cs = ConstraintSystem::new_ref();
+    circuit(values[2]).generate_constraints(cs.clone()).unwrap();
+    assert_eq!(cs.borrow().unwrap().instance_assignment[1..], [tree.root(), values[2]]);
+}
+
+#[test]
+fn test_fixed_length_leaves_share_the_public_inputs() {
+    use crate::circuit_report::assert_satisfied_with_report;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves = vec![vec![1u8], vec![2u8; 32], vec![3u8; MAX_LEAF_LEN], vec![]];
+    let tree = params.build_fixed_length_tree(&leaves, MAX_LEAF_LEN).unwrap();
+
+    let mut instance_sizes = Vec::new();
+    for (index, leaf) in leaves.iter().enumerate() {
+        let path = tree.generate_proof(index).unwrap();
+        let circuit = <MTreeVerification>::with_leaf_len(&params, tree.root(), leaf, MAX_LEAF_LEN, path).unwrap();
+        let expected = PublicInputs::padded(&tree.root(), leaf, MAX_LEAF_LEN).unwrap();
+        assert_eq!(circuit.public_inputs(), expected.to_field_elements());
+        instance_sizes.push(assert_satisfied_with_report(circuit).instance_variables);
+    }
+    assert!(instance_sizes.iter().all(|size| *size == instance_sizes[0]));
+
+    // too long for the tree, or for the leaf hash, fails up front
+    let long = vec![4u8; MAX_LEAF_LEN + 1];
+    let path = tree.generate_proof(0).unwrap();
+    assert!(matches!(
+        <MTreeVerification>::with_leaf_len(&params, tree.root(), &long, MAX_LEAF_LEN, path.clone()),
+        Err(MerkleError::LeafTooLong { len, max: MAX_LEAF_LEN }) if len == MAX_LEAF_LEN + 1
+    ));
+    assert!(matches!(
+        <MTreeVerification>::with_leaf_len(&params, tree.root(), &[1u8; 33], 32, path.clone()),
+        Err(MerkleError::LeafTooLong { len: 33, max: 32 })
+    ));
+    assert!(matches!(
+        <MTreeVerification>::with_leaf_len(&params, tree.root(), &[1u8], MAX_LEAF_LEN + 1, path),
+        Err(MerkleError::LeafTooLong { .. })
+    ));
+    let e = params.build_fixed_length_tree(&[vec![0u8; 8], long], MAX_LEAF_LEN).unwrap_err();
+    assert_eq!(e.context().unwrap().index, Some(1));
+    assert!(matches!(e.root_cause(), MerkleError::LeafTooLong { .. }));
+}
+
+#[test]
+fn test_fixed_len_circuit_pads_in_and_out_of_the_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied, synthesize_with_report};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves = vec![vec![1u8], vec![2u8; 16], vec![3u8; 32], vec![]];
+    let tree = params.build_fixed_length_tree(&leaves, 32).unwrap();
+    let blank = synthesize_with_report(FixedLenMTreeVerification::<32>::blank(&params, tree.height())).unwrap().metrics;
+
+    for (index, leaf) in leaves.iter().enumerate() {
+        let circuit = FixedLenMTreeVerification::<32>::new(&params, tree.root(), leaf, tree.generate_proof(index).unwrap()).unwrap();
+        let expected = PublicInputs::padded(&tree.root(), leaf, 32).unwrap();
+        assert_eq!(circuit.public_inputs(), expected.to_field_elements());
+        assert_eq!(assert_satisfied_with_report(circuit), blank);
+    }
+    let wrong =
+        FixedLenMTreeVerification::<32>::new(&params, tree.root(), &[2u8; 15], tree.generate_proof(1).unwrap()).unwrap();
+    expect_unsatisfied(wrong, "membership");
+
+    // a leaf longer than the circuit takes is an error up front, not an unsatisfiable system
+    let path = tree.generate_proof(2).unwrap();
+    assert!(matches!(
+        FixedLenMTreeVerification::<16>::new(&params, tree.root(), &leaves[2], path.clone()),
+        Err(MerkleError::LeafTooLong { len: 32, max: 16 })
+    ));
+    assert!(matches!(
+        FixedLenMTreeVerification::<{ MAX_LEAF_LEN + 1 }>::new(&params, tree.root(), &leaves[0], path),
+        Err(MerkleError::LeafTooLong { .. })
+    ));
+}
+
+#[test]
+fn test_compute_root_var_composes_with_other_constraints() {
+    use ark_relations::r1cs::ConstraintSystem;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    // a second tree with the first one's root as a leaf
+    let outer_leaves: Vec<Vec<u8>> = vec![to_bytes![tree.root()].unwrap(), vec![9u8; 32]];
+    let outer = params.build_tree(&outer_leaves).unwrap();
+
+    let cs = ConstraintSystem::<ConstraintF>::new_ref();
+    let leaf_crh_params = LeafHashParamsVar::new_constant(cs.clone(), &params.leaf_crh_params).unwrap();
+    let two_to_one_crh_params = TwoToOneHashParamsVar::new_constant(cs.clone(), &params.two_to_one_crh_params).unwrap();
+    let leaf = UInt8::new_witness_vec(cs.clone(), &leaves[2]).unwrap();
+    let path = SimplePathVar::new_witness(cs.clone(), || Ok(tree.generate_proof(2).unwrap())).unwrap();
+    let root = compute_root_var(&leaf_crh_params, &two_to_one_crh_params, &leaf, &path).unwrap();
+    assert_eq!(root.value().unwrap(), tree.root());
+
+    // the recomputed root opens the outer tree, without ever being an input
+    let outer_root = RootVar::new_input(cs.clone(), || Ok(outer.root())).unwrap();
+    let outer_path = SimplePathVar::new_witness(cs.clone(), || Ok(outer.generate_proof(0).unwrap())).unwrap();
+    let computed = compute_root_var(&leaf_crh_params, &two_to_one_crh_params, &root.to_bytes().unwrap(), &outer_path).unwrap();
+    computed.enforce_equal(&outer_root).unwrap();
+    assert!(cs.is_satisfied().unwrap());
+}
+
+#[test]
+fn test_merkle_tree_var_matches_the_native_tree() {
+    use ark_relations::r1cs::ConstraintSystem;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let build = |leaves: &[Vec<u8>]| {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let leaf_crh_params = LeafHashParamsVar::new_constant(cs.clone(), &params.leaf_crh_params).unwrap();
+        let two_to_one_crh_params = TwoToOneHashParamsVar::new_constant(cs.clone(), &params.two_to_one_crh_params).unwrap();
+        let leaf_vars: Vec<_> = leaves.iter().map(|leaf| UInt8::new_witness_vec(cs.clone(), leaf).unwrap()).collect();
+        let tree_var = MerkleTreeVar::new(&leaf_crh_params, &two_to_one_crh_params, &leaf_vars).unwrap();
+        let root = RootVar::new_input(cs.clone(), || Ok(tree.root())).unwrap();
+        tree_var.root().enforce_equal(&root).unwrap();
+        (cs, tree_var)
+    };
+
+    let (cs, tree_var) = build(&leaves);
+    assert!(cs.is_satisfied().unwrap());
+    assert_eq!(tree_var.leaf_hash(3).unwrap().value().unwrap(), params.hash_leaf(&leaves[3]).unwrap());
+    assert_eq!(tree_var.node(3, 0).unwrap().value().unwrap(), tree.root());
+    assert!(tree_var.node(0, 0).is_none() && tree_var.node(4, 0).is_none());
+
+    let mut other = leaves.clone();
+    other[5] = vec![0xee; 8];
+    assert!(!build(&other).0.is_satisfied().unwrap());
+
+    // a leaf count that isn't a power of two is an error, not a panic
+    let cs = ConstraintSystem::<ConstraintF>::new_ref();
+    let leaf_crh_params = LeafHashParamsVar::new_constant(cs.clone(), &params.leaf_crh_params).unwrap();
+    let two_to_one_crh_params = TwoToOneHashParamsVar::new_constant(cs.clone(), &params.two_to_one_crh_params).unwrap();
+    for len in [0, 1, 3, 6].iter().copied() {
+        let leaf_vars: Vec<_> = leaves[..len]
+            .iter()
+            .map(|leaf| UInt8::new_witness_vec(cs.clone(), leaf).unwrap())
+            .collect();
+        assert!(matches!(
+            MerkleTreeVar::new(&leaf_crh_params, &two_to_one_crh_params, &leaf_vars),
+            Err(SynthesisError::Unsatisfiable)
+        ));
+    }
+}
+
+#[test]
+fn test_leaf_update_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 8]).collect();
+    let old_tree = params.build_tree(&leaves).unwrap();
+    let old_leaf = leaves[2].clone();
+    leaves[2] = vec![0xaa; 8];
+    let new_tree = params.build_tree(&leaves).unwrap();
+    let circuit = || LeafUpdateCircuit {
+        params: params.clone(),
+        old_root: old_tree.root(),
+        new_root: new_tree.root(),
+        old_leaf: old_leaf.clone(),
+        new_leaf: leaves[2].clone(),
+        auth_path: Some(old_tree.generate_proof(2).unwrap()),
+    };
+    assert_eq!(circuit().public_inputs(), vec![old_tree.root(), new_tree.root()]);
+    assert_satisfied_with_report(circuit());
+
+    // a new root the update doesn't lead to
+    let mut wrong_root = circuit();
+    wrong_root.new_root = old_tree.root();
+    expect_unsatisfied(wrong_root, "update");
+
+    // a new root from a change at another position: the one path can't open
+    // leaf 2 in the old tree and leaf 5 in the new one
+    let mut moved = leaves.clone();
+    moved[2] = old_leaf.clone();
+    moved[5] = vec![0xaa; 8];
+    let mut swapped = circuit();
+    swapped.new_root = params.build_tree(&moved).unwrap().root();
+    expect_unsatisfied(swapped, "update");
+}
+
+#[test]
+fn test_update_leaf_agrees_with_the_update_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 8]).collect();
+    let mut tree = params.build_tree(&leaves).unwrap();
+    let old_root = tree.root();
+    // the siblings don't change, so the path opened before the update is the witness
+    let path = tree.generate_proof(6).unwrap();
+    let new_root = update_leaf(&mut tree, LeafIndex::checked_new(6, 8).unwrap(), vec![0xbb; 8]).unwrap();
+    let circuit = |old_root: Root, new_root: Root| MTreeUpdateVerification {
+        params: params.clone(),
+        old_root,
+        new_root,
+        old_leaf: leaves[6].clone(),
+        new_leaf: vec![0xbb; 8],
+        auth_path: Some(path.clone()),
+    };
+    assert_satisfied_with_report(circuit(old_root, new_root));
+
+    // a second update starts from the root the first one returned
+    let newer_root = update_leaf(&mut tree, LeafIndex::checked_new(1, 8).unwrap(), vec![0xcc; 8]).unwrap();
+    expect_unsatisfied(circuit(old_root, newer_root), "update");
+}
+
+#[test]
+fn test_multi_membership_circuit_shares_the_root_and_params() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let circuit = |indices: &[usize]| MultiMembershipCircuit {
+        params: params.clone(),
+        root: tree.root(),
+        leaves: indices.iter().map(|i| leaves[*i].clone()).collect(),
+        auth_paths: Some(indices.iter().map(|i| tree.generate_proof(*i).unwrap()).collect()),
+    };
+
+    // one leaf is the plain membership circuit
+    let one = assert_satisfied_with_report(circuit(&[3]));
+    let single = <MTreeVerification>::new(&params, tree.root(), &leaves[3], tree.generate_proof(3).unwrap());
+    assert_eq!(circuit(&[3]).public_inputs(), single.public_inputs());
+    assert_eq!(one, assert_satisfied_with_report(single));
+
+    // four leaves cost no more than four single-leaf circuits
+    let four = assert_satisfied_with_report(circuit(&[0, 3, 5, 7]));
+    assert!(four.constraints <= 4 * one.constraints, "{:?} vs 4 x {:?}", four, one);
+    assert_eq!(four.instance_variables - 2, 4 * (one.instance_variables - 2));
+
+    let mut wrong = circuit(&[0, 3, 5, 7]);
+    wrong.leaves[2] = vec![0xee; 8];
+    expect_unsatisfied(wrong, "membership");
+}
+
+#[test]
+fn test_blank_membership_circuit_has_the_shape_of_a_real_one() {
+    use ark_relations::r1cs::{ConstraintSystem, SynthesisMode};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+
+    let setup = ConstraintSystem::<ConstraintF>::new_ref();
+    setup.set_mode(SynthesisMode::Setup);
+    <MTreeVerification>::blank(&params, tree.height(), 8).generate_constraints(setup.clone()).unwrap();
+
+    let proving = ConstraintSystem::<ConstraintF>::new_ref();
+    let circuit = <MTreeVerification>::new(&params, tree.root(), &leaves[5], tree.generate_proof(5).unwrap());
+    circuit.generate_constraints(proving.clone()).unwrap();
+    assert!(proving.is_satisfied().unwrap());
+    assert_eq!(setup.num_constraints(), proving.num_constraints());
+    assert_eq!(setup.num_instance_variables(), proving.num_instance_variables());
+
+    // without a path there is no shape to synthesize
+    let mut circuit = <MTreeVerification>::blank(&params, tree.height(), 8);
+    circuit.auth_path = None;
+    assert!(matches!(
+        circuit.generate_constraints(ConstraintSystem::new_ref()),
+        Err(SynthesisError::AssignmentMissing)
+    ));
+}
+
+#[test]
+fn test_positional_membership_binds_the_index() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let circuit = |index: u64| {
+        PositionalMembershipCircuit::new(
+            &params,
+            tree.root(),
+            LeafIndex::checked_new(index, 8).unwrap(),
+            &leaves[6],
+            tree.generate_proof(6).unwrap(),
+        )
+    };
+    assert_satisfied_with_report(circuit(6));
+
+    // the leaf and root are right and the path opens them, but not at index 2
+    expect_unsatisfied(circuit(2), "position");
+}
+
+#[test]
+fn test_preimage_membership_keeps_the_leaf_private() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+    use ark_relations::r1cs::ConstraintSystem;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let secrets: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 16]).collect();
+    let tree = params.build_preimage_tree(&secrets).unwrap();
+    let circuit = |secret: &[u8]| PreimageMembershipCircuit {
+        params: params.clone(),
+        root: tree.root(),
+        secret_preimage: secret.to_vec(),
+        auth_path: Some(tree.generate_proof(4).unwrap()),
+    };
+    assert_satisfied_with_report(circuit(&secrets[4]));
+    // the secret of another leaf doesn't open this path
+    expect_unsatisfied(circuit(&secrets[5]), "membership");
+
+    // the instance is the root alone: neither the secret nor the leaf digest
+    let cs = ConstraintSystem::new_ref();
+    let honest = circuit(&secrets[4]);
+    let inputs = honest.public_inputs();
+    honest.generate_constraints(cs.clone()).unwrap();
+    let instance = cs.borrow().unwrap().instance_assignment.clone();
+    assert_eq!(instance[1..], inputs[..]);
+    assert!(!instance.contains(&params.hash_leaf(&secrets[4]).unwrap()));
+}
+
+#[test]
+fn test_preimage_circuit_hashes_the_data_with_keccak() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let records: [&[u8]; 4] = [b"alice:100", b"bob:250", b"carol:75", b"dave:0"];
+    let tree = params.build_tree(records.iter().map(|r| Leaf::keccak_of(r))).unwrap();
+    let path = tree.generate_proof(1).unwrap();
+
+    let circuit = MTreePreimageVerification::new(&params, tree.root(), records[1], path.clone());
+    assert_eq!(circuit.public_inputs(), vec![tree.root()]);
+    assert_satisfied_with_report(circuit);
+    expect_unsatisfied(
+        MTreePreimageVerification::new(&params, tree.root(), b"bob:251", path),
+        "membership",
+    );
+}
+
+#[test]
+fn test_one_circuit_shape_for_every_tree_of_a_height() {
+    use crate::circuit_report::assert_satisfied_with_report;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let options = TreeOptions::new(5);
+    let leaves: Vec<Vec<u8>> = (0u8..9).map(|i| vec![i; 8]).collect();
+    let blank = crate::circuit_report::synthesize_with_report(<MTreeVerification>::blank(&params, options.height, 8))
+        .unwrap()
+        .metrics;
+    for count in [2, 9].iter().copied() {
+        let tree = params.build_tree_with_options(&leaves[..count], &options).unwrap();
+        let circuit = <MTreeVerification>::new(&params, tree.root(), &leaves[1], tree.prove(1).unwrap());
+        assert_eq!(assert_satisfied_with_report(circuit), blank);
+    }
+}
+
+#[test]
+fn test_fixed_shape_circuit_takes_any_assignment_of_its_shape() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied, synthesize_with_report};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 32]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let blank = synthesize_with_report(MTreeCircuit::<3>::setup(&params)).unwrap().metrics;
+    for index in [0, 5].iter().copied() {
+        let path = tree.generate_proof(index).unwrap();
+        let circuit = MTreeCircuit::<3>::assign(
+            &params,
+            WitnessAssignment::new(tree.root(), leaves[index].clone(), path.clone()),
+        )
+        .unwrap();
+        let inputs = <MTreeVerification>::new(&params, tree.root(), &leaves[index], path).public_inputs();
+        assert_eq!(circuit.public_inputs(), Some(inputs));
+        assert_eq!(assert_satisfied_with_report(circuit), blank);
+    }
+    let wrong = WitnessAssignment::new(tree.root(), leaves[1].clone(), tree.generate_proof(2).unwrap());
+    expect_unsatisfied(MTreeCircuit::<3>::assign(&params, wrong).unwrap(), "membership");
+
+    // a path or leaf of another shape is refused before any constraint
+    let assignment = WitnessAssignment::new(tree.root(), leaves[1].clone(), tree.generate_proof(1).unwrap());
+    assert!(matches!(
+        MTreeCircuit::<4>::assign(&params, assignment.clone()),
+        Err(MerkleError::ShapeMismatch { depth: 3, expected_depth: 4, .. })
+    ));
+    assert!(matches!(
+        MTreeCircuit::<3, 16>::assign(&params, assignment),
+        Err(MerkleError::ShapeMismatch { leaf_len: 32, expected_leaf_len: 16, .. })
+    ));
+}
+
+#[test]
+fn test_max_depth_circuit_takes_trees_of_every_depth_up_to_its_bound() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied, synthesize_with_report};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let blank = synthesize_with_report(MaxDepthCircuit::<5>::setup(&params)).unwrap().metrics;
+    for depth in [1, 3, 5].iter().copied() {
+        let leaves: Vec<Vec<u8>> = (0..1u8 << depth).map(|i| vec![i; 32]).collect();
+        let tree = params.build_tree(&leaves).unwrap();
+        let index = leaves.len() - 1;
+        let path = tree.generate_proof(index).unwrap();
+        let assignment = WitnessAssignment::new(tree.root(), leaves[index].clone(), path);
+        let circuit = MaxDepthCircuit::<5>::assign(&params, assignment).unwrap();
+        assert_eq!(
+            circuit.public_inputs(),
+            Some(max_depth_public_inputs(&tree.root(), depth, &leaves[index]))
+        );
+        assert_eq!(assert_satisfied_with_report(circuit), blank);
+
+        let wrong = WitnessAssignment::new(tree.root(), leaves[0].clone(), tree.generate_proof(index).unwrap());
+        expect_unsatisfied(MaxDepthCircuit::<5>::assign(&params, wrong).unwrap(), "membership");
+    }
+
+    let leaves: Vec<Vec<u8>> = (0u8..64).map(|i| vec![i; 32]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let too_deep = WitnessAssignment::new(tree.root(), leaves[0].clone(), tree.generate_proof(0).unwrap());
+    assert!(matches!(
+        MaxDepthCircuit::<5>::assign(&params, too_deep),
+        Err(MerkleError::ShapeMismatch { depth: 6, expected_depth: 5, .. })
+    ));
+}
+
+#[test]
+fn test_batch_verification_beats_separate_circuits() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..16).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let indices = [2, 3, 5, 12];
+
+    let separate: usize = indices
+        .iter()
+        .map(|i| {
+            let circuit = <MTreeVerification>::new(&params, tree.root(), &leaves[*i], tree.generate_proof(*i).unwrap());
+            assert_satisfied_with_report(circuit).constraints
+        })
+        .sum();
+    let opened: Vec<(usize, Vec<u8>)> = indices.iter().map(|i| (*i, leaves[*i].clone())).collect();
+    let proof = generate_multi_proof(&tree, &indices).unwrap();
+    let circuit = || MTreeBatchVerification::new(&params, tree.root(), opened.clone(), proof.clone());
+    let batch = assert_satisfied_with_report(circuit());
+    assert!(batch.constraints < separate);
+
+    let mut wrong = circuit();
+    wrong.leaves[1].1 = leaves[4].clone();
+    expect_unsatisfied(wrong, "membership");
+}
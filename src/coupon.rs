@@ -0,0 +1,566 @@
+use crate::common::*;
+use crate::constraints::{blank_path, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::byte_inputs;
+use crate::nullifier::{nullifier, nullifier_var, Nullifier, NullifierSet};
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_crypto_primitives::crh::{CRHGadget, CRH};
+use ark_ff::to_bytes;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::rand::{CryptoRng, RngCore};
+
+// Coupons with double-spend prevention and batch settlement.
+//
+// A coupon is a random secret; the issuer's tree holds the commitments
+// `LeafHash(domain || secret)`. At the till the customer proves that their
+// coupon is in the tree and reveals its nullifier, which the point of sale
+// accepts only once, and then surrenders the coupon. At the end of the day
+// the merchant settles with the issuer by proving, in a single circuit, that
+// each of the k nullifiers it claims comes from a distinct coupon in the
+// tree. Distinctness is enforced in-circuit pairwise, so a batch can't count
+// the same coupon twice.
+//
+// A coupon has one nullifier, in the one scope of coupons, whichever
+// merchant takes it: the issuer keeps every nullifier it has settled, so a
+// coupon redeemed at two merchants is paid out to the first to settle only.
+// The merchant is a public input of its own, so a redemption or settlement
+// made for one merchant doesn't verify for another.
+//
+// The issuer republishes its tree with every `issue`, and a point of sale
+// proves against whichever root it was given. A settlement names that root,
+// and the issuer accepts any root it has published, so coupons issued in the
+// meantime don't turn away the settlements of earlier ones.
+
+const COUPON_DOMAIN: &[u8] = b"merkle-tutorial/coupon";
+// The scope of every coupon's nullifier.
+const COUPON_SCOPE: &[u8] = b"merkle-tutorial/coupon-spent";
+
+/// Hashes a merchant name into the 32 bytes coupons are scoped to.
+pub fn merchant_id(merchant: &str) -> [u8; 32] {
+    keccak256(merchant.as_bytes())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Coupon {
+    secret: [u8; 32],
+}
+
+impl Coupon {
+    pub fn commitment(&self, params: &MerkleParams) -> Result<Vec<u8>, MerkleError> {
+        let mut input = COUPON_DOMAIN.to_vec();
+        input.extend_from_slice(&self.secret);
+        Ok(to_bytes![<LeafHash as CRH>::evaluate(&params.leaf_crh_params, &input)?]?)
+    }
+
+    /// The nullifier of the coupon, the same at every merchant.
+    pub fn nullifier(&self, params: &MerkleParams) -> Result<Nullifier, MerkleError> {
+        Ok(nullifier(params, &self.secret, COUPON_SCOPE)?)
+    }
+}
+
+// Recomputes the leaf of `secret` in-circuit.
+fn commitment_var(
+    leaf_crh_params: &LeafHashParamsVar,
+    secret: &[UInt8<ConstraintF>],
+) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+    let mut input = UInt8::constant_vec(COUPON_DOMAIN);
+    input.extend_from_slice(secret);
+    <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(leaf_crh_params, &input)?.to_bytes()
+}
+
+/// The issuer side: mints coupons and settles with merchants.
+pub struct CouponIssuer {
+    params: MerkleParams,
+    options: TreeOptions,
+    commitments: Vec<Vec<u8>>,
+    tree: SimpleMerkleTree,
+    // every root `issue` published, which settlements may refer to
+    published: Vec<Root>,
+    settled: NullifierSet,
+}
+
+impl CouponIssuer {
+    /// Creates an issuer for up to `capacity` coupons. The capacity fixes the circuit shape.
+    pub fn new(params: MerkleParams, capacity: usize) -> Result<Self, MerkleError> {
+        // padding leaves are all zero, which is no coupon's commitment
+        let options = TreeOptions::for_capacity(capacity).padding(PaddingStrategy::Zeros(32));
+        let tree = Self::build_tree(&params, &options, &[])?;
+        Ok(CouponIssuer {
+            params,
+            options,
+            commitments: Vec::new(),
+            tree,
+            published: Vec::new(),
+            settled: NullifierSet::new(),
+        })
+    }
+
+    fn build_tree(
+        params: &MerkleParams,
+        options: &TreeOptions,
+        commitments: &[Vec<u8>],
+    ) -> Result<SimpleMerkleTree, MerkleError> {
+        Ok(params.build_tree_with_options(commitments, options)?.tree)
+    }
+
+    /// Number of levels of the coupon tree, leaves and root included.
+    pub fn tree_height(&self) -> usize {
+        self.options.height
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// Mints `count` fresh coupons and republishes the tree.
+    pub fn issue<R: RngCore + CryptoRng>(&mut self, count: usize, rng: &mut R) -> Result<Vec<Coupon>, MerkleError> {
+        if self.commitments.len() + count > self.options.capacity() {
+            return Err(MerkleError::CapacityExceeded {
+                len: self.commitments.len() + count,
+                capacity: self.options.capacity(),
+            });
+        }
+        let mut coupons = Vec::with_capacity(count);
+        let mut commitments = self.commitments.clone();
+        for _ in 0..count {
+            let mut secret = [0u8; 32];
+            rng.fill_bytes(&mut secret);
+            let coupon = Coupon { secret };
+            commitments.push(coupon.commitment(&self.params)?);
+            coupons.push(coupon);
+        }
+        self.tree = Self::build_tree(&self.params, &self.options, &commitments)?;
+        self.commitments = commitments;
+        self.published.push(self.tree.root());
+        Ok(coupons)
+    }
+
+    pub fn path(&self, coupon: &Coupon) -> Result<SimplePath, MerkleError> {
+        let commitment = coupon.commitment(&self.params)?;
+        let index = self
+            .commitments
+            .iter()
+            .position(|c| *c == commitment)
+            .ok_or(MerkleError::LeafNotFound)?;
+        generate_proof(&self.tree, LeafIndex::checked_new(index as u64, leaf_count(&self.tree))?)
+    }
+
+    /// Keys for single redemptions, checked at the till.
+    pub fn setup_redemption<R: RngCore + CryptoRng>(
+        &self,
+        rng: &mut R,
+    ) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+        snark::setup(RedemptionCircuit::blank(self.params.clone(), self.tree_height()), rng)
+    }
+
+    /// Keys for settling batches of exactly `batch_size` redemptions.
+    pub fn setup_settlement<R: RngCore + CryptoRng>(
+        &self,
+        batch_size: usize,
+        rng: &mut R,
+    ) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+        snark::setup(
+            SettlementCircuit::blank(self.params.clone(), self.tree_height(), batch_size),
+            rng,
+        )
+    }
+
+    /// Accepts `settlement` from `merchant` unless its proof fails, its root
+    /// isn't one this issuer published, or one of its coupons has already
+    /// been settled, by this merchant or another.
+    pub fn settle(
+        &mut self,
+        vk: &VerifyingKey,
+        merchant: &str,
+        settlement: &Settlement,
+    ) -> Result<bool, MerkleError> {
+        if !self.published.contains(&settlement.root)
+            || settlement.nullifiers.iter().any(|n| self.settled.contains(n))
+        {
+            return Ok(false);
+        }
+        let inputs =
+            SettlementCircuit::public_inputs(&settlement.root, &merchant_id(merchant), &settlement.nullifiers);
+        if !snark::verify(vk, &inputs, &settlement.proof)? {
+            return Ok(false);
+        }
+        for n in &settlement.nullifiers {
+            self.settled.insert(*n);
+        }
+        Ok(true)
+    }
+}
+
+/// Proves "I hold a coupon in the tree under `root` whose nullifier is
+/// `nullifier`, and redeem it at the merchant of `merchant_id`".
+pub struct RedemptionCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub merchant_id: [u8; 32],
+    pub nullifier: Nullifier,
+
+    // These are the private witnesses to the circuit.
+    pub coupon: Option<Coupon>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl RedemptionCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, tree_height: usize) -> Self {
+        RedemptionCircuit {
+            params,
+            root: Default::default(),
+            merchant_id: [0u8; 32],
+            nullifier: Default::default(),
+            coupon: None,
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(root: &Root, merchant_id: &[u8; 32], nullifier: &Nullifier) -> Vec<ConstraintF> {
+        let mut inputs = vec![*root];
+        inputs.extend(byte_inputs(&merchant_id[..]));
+        inputs.push(*nullifier);
+        inputs
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for RedemptionCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        // only carried along, so that the proof is for this merchant alone
+        let _merchant_id = UInt8::new_input_vec(ark_relations::ns!(cs, "merchant_id_var"), &self.merchant_id)?;
+        let expected = FpVar::new_input(ark_relations::ns!(cs, "nullifier_var"), || Ok(self.nullifier))?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the coupon and its path:
+        let secret = UInt8::new_witness_vec(
+            ark_relations::ns!(cs, "secret_var"),
+            &self.coupon.map_or([0u8; 32], |c| c.secret),
+        )?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let leaf = commitment_var(&leaf_crh_params, &secret)?;
+        let is_member = path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaf.as_slice())?;
+        is_member.enforce_equal(&Boolean::TRUE)?;
+        nullifier_var(&leaf_crh_params, &secret, &UInt8::constant_vec(COUPON_SCOPE))?.enforce_equal(&expected)?;
+
+        Ok(())
+    }
+}
+
+/// Proves that `nullifiers` come from as many distinct coupons in the tree under `root`.
+pub struct SettlementCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub batch_size: usize,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub merchant_id: [u8; 32],
+    pub nullifiers: Vec<Nullifier>,
+
+    // These are the private witnesses to the circuit.
+    pub coupons: Option<Vec<(Coupon, SimplePath)>>,
+    // only used for the shape of blank circuits
+    pub tree_height: usize,
+}
+
+impl SettlementCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, tree_height: usize, batch_size: usize) -> Self {
+        SettlementCircuit {
+            params,
+            batch_size,
+            root: Default::default(),
+            merchant_id: [0u8; 32],
+            nullifiers: vec![Default::default(); batch_size],
+            coupons: None,
+            tree_height,
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(root: &Root, merchant_id: &[u8; 32], nullifiers: &[Nullifier]) -> Vec<ConstraintF> {
+        let mut inputs = vec![*root];
+        inputs.extend(byte_inputs(&merchant_id[..]));
+        inputs.extend_from_slice(nullifiers);
+        inputs
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for SettlementCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        // only carried along, so that the proof is for this merchant alone
+        let _merchant_id = UInt8::new_input_vec(ark_relations::ns!(cs, "merchant_id_var"), &self.merchant_id)?;
+        let nullifiers =
+            Vec::<FpVar<_>>::new_input(ark_relations::ns!(cs, "nullifiers_var"), || Ok(&self.nullifiers[..]))?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, each coupon is in the tree and produces its nullifier
+        let scope = UInt8::constant_vec(COUPON_SCOPE);
+        let blank = (Coupon { secret: [0u8; 32] }, blank_path(self.tree_height));
+        for i in 0..self.batch_size {
+            let (coupon, path) = self.coupons.as_ref().map_or(&blank, |c| &c[i]);
+            let secret = UInt8::new_witness_vec(ark_relations::ns!(cs, "secret_var"), &coupon.secret)?;
+            let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || Ok(path))?;
+
+            let leaf = commitment_var(&leaf_crh_params, &secret)?;
+            let is_member =
+                path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaf.as_slice())?;
+            is_member.enforce_equal(&Boolean::TRUE)?;
+            nullifier_var(&leaf_crh_params, &secret, &scope)?.enforce_equal(&nullifiers[i])?;
+        }
+
+        // and no coupon is counted twice
+        let _distinct = ark_relations::ns!(cs, "distinct");
+        for i in 0..nullifiers.len() {
+            for j in i + 1..nullifiers.len() {
+                nullifiers[i].enforce_not_equal(&nullifiers[j])?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What a customer shows at the till.
+#[derive(Clone)]
+pub struct Redemption {
+    pub nullifier: Nullifier,
+    pub proof: Proof,
+}
+
+impl Coupon {
+    /// Proves that this coupon can be redeemed at `merchant`.
+    pub fn redeem<R: RngCore + CryptoRng>(
+        &self,
+        params: &MerkleParams,
+        pk: &ProvingKey,
+        root: Root,
+        path: SimplePath,
+        merchant: &str,
+        rng: &mut R,
+    ) -> Result<Redemption, MerkleError> {
+        let nullifier = self.nullifier(params)?;
+        let circuit = RedemptionCircuit {
+            params: params.clone(),
+            root,
+            merchant_id: merchant_id(merchant),
+            nullifier,
+            coupon: Some(self.clone()),
+            auth_path: Some(path),
+        };
+        Ok(Redemption {
+            nullifier,
+            proof: snark::prove(pk, circuit, rng)?,
+        })
+    }
+}
+
+/// What a merchant sends to the issuer at the end of the day.
+#[derive(Clone)]
+pub struct Settlement {
+    /// The root the redemptions were proven against.
+    pub root: Root,
+    pub nullifiers: Vec<Nullifier>,
+    pub proof: Proof,
+}
+
+/// The merchant side: checks redemptions and collects the surrendered coupons.
+pub struct PointOfSale {
+    params: MerkleParams,
+    vk: VerifyingKey,
+    root: Root,
+    merchant_id: [u8; 32],
+    spent: NullifierSet,
+    // redeemed since the last settlement
+    pending: Vec<(Coupon, SimplePath)>,
+}
+
+impl PointOfSale {
+    pub fn new(params: MerkleParams, vk: VerifyingKey, root: Root, merchant: &str) -> Self {
+        PointOfSale {
+            params,
+            vk,
+            root,
+            merchant_id: merchant_id(merchant),
+            spent: NullifierSet::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Checks `redemption` and, if it is valid and fresh, takes in the surrendered coupon.
+    pub fn redeem(
+        &mut self,
+        redemption: &Redemption,
+        coupon: Coupon,
+        path: SimplePath,
+    ) -> Result<bool, MerkleError> {
+        if self.spent.contains(&redemption.nullifier)
+            || coupon.nullifier(&self.params)? != redemption.nullifier
+        {
+            return Ok(false);
+        }
+        let inputs = RedemptionCircuit::public_inputs(&self.root, &self.merchant_id, &redemption.nullifier);
+        if !snark::verify(&self.vk, &inputs, &redemption.proof)? {
+            return Ok(false);
+        }
+        self.spent.insert(redemption.nullifier);
+        self.pending.push((coupon, path));
+        Ok(true)
+    }
+
+    pub fn settlement_circuit(&self, coupons: Vec<(Coupon, SimplePath)>) -> Result<SettlementCircuit, MerkleError> {
+        let nullifiers = coupons
+            .iter()
+            .map(|(c, _)| c.nullifier(&self.params))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tree_height = coupons.first().map_or(2, |(_, p)| p.auth_path.len() + 2);
+        Ok(SettlementCircuit {
+            params: self.params.clone(),
+            batch_size: coupons.len(),
+            root: self.root,
+            merchant_id: self.merchant_id,
+            nullifiers,
+            coupons: Some(coupons),
+            tree_height,
+        })
+    }
+
+    /// Proves the next `batch_size` pending redemptions in one settlement.
+    pub fn settle<R: RngCore + CryptoRng>(
+        &mut self,
+        pk: &ProvingKey,
+        batch_size: usize,
+        rng: &mut R,
+    ) -> Result<Settlement, MerkleError> {
+        if self.pending.len() < batch_size {
+            return Err(MerkleError::LeafIndexOutOfRange {
+                index: batch_size,
+                len: self.pending.len(),
+            });
+        }
+        let batch: Vec<_> = self.pending.drain(..batch_size).collect();
+        let circuit = self.settlement_circuit(batch)?;
+        let nullifiers = circuit.nullifiers.clone();
+        Ok(Settlement {
+            root: self.root,
+            nullifiers,
+            proof: snark::prove(pk, circuit, rng)?,
+        })
+    }
+}
+
+#[test]
+fn test_coupon_redeem_and_settle() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut issuer = CouponIssuer::new(params.clone(), 4).unwrap();
+    let coupons = issuer.issue(3, &mut rng).unwrap();
+    let (redeem_pk, redeem_vk) = issuer.setup_redemption(&mut rng).unwrap();
+    let (settle_pk, settle_vk) = issuer.setup_settlement(2, &mut rng).unwrap();
+    let mut pos = PointOfSale::new(params.clone(), redeem_vk, issuer.root(), "corner-shop");
+
+    for coupon in &coupons[..2] {
+        let path = issuer.path(coupon).unwrap();
+        let redemption = coupon
+            .redeem(&params, &redeem_pk, issuer.root(), path.clone(), "corner-shop", &mut rng)
+            .unwrap();
+        assert!(pos.redeem(&redemption, coupon.clone(), path.clone()).unwrap());
+        // the same coupon can't be spent twice
+        assert!(!pos.redeem(&redemption, coupon.clone(), path).unwrap());
+    }
+
+    let settlement = pos.settle(&settle_pk, 2, &mut rng).unwrap();
+    assert!(!issuer.settle(&settle_vk, "other-shop", &settlement).unwrap());
+    assert!(issuer.settle(&settle_vk, "corner-shop", &settlement).unwrap());
+    // nor settled twice
+    assert!(!issuer.settle(&settle_vk, "corner-shop", &settlement).unwrap());
+}
+
+#[test]
+fn test_coupon_duplicate_in_batch_unsatisfiable() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut issuer = CouponIssuer::new(params.clone(), 4).unwrap();
+    let coupons = issuer.issue(2, &mut rng).unwrap();
+    let (_, redeem_vk) = issuer.setup_redemption(&mut rng).unwrap();
+    let pos = PointOfSale::new(params, redeem_vk, issuer.root(), "corner-shop");
+
+    let circuit = |batch: Vec<&Coupon>| {
+        let batch = batch.into_iter().map(|c| (c.clone(), issuer.path(c).unwrap())).collect();
+        pos.settlement_circuit(batch).unwrap()
+    };
+    assert_satisfied_with_report(circuit(vec![&coupons[0], &coupons[1]]));
+    expect_unsatisfied(circuit(vec![&coupons[0], &coupons[0]]), "distinct");
+}
+
+#[test]
+fn test_coupon_spent_at_two_merchants_settles_once() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut issuer = CouponIssuer::new(params.clone(), 8).unwrap();
+    let coupons = issuer.issue(3, &mut rng).unwrap();
+    let root = issuer.root();
+    let (redeem_pk, redeem_vk) = issuer.setup_redemption(&mut rng).unwrap();
+    let (settle_pk, settle_vk) = issuer.setup_settlement(2, &mut rng).unwrap();
+    let mut shop = PointOfSale::new(params.clone(), redeem_vk.clone(), root, "corner-shop");
+    let mut kiosk = PointOfSale::new(params.clone(), redeem_vk, root, "kiosk");
+
+    // coupon 0 goes to both tills, which can't know of each other
+    let mut redeem = |pos: &mut PointOfSale, merchant: &str, coupon: &Coupon| {
+        let path = issuer.path(coupon).unwrap();
+        let redemption = coupon.redeem(&params, &redeem_pk, root, path.clone(), merchant, &mut rng).unwrap();
+        pos.redeem(&redemption, coupon.clone(), path).unwrap()
+    };
+    assert!(redeem(&mut shop, "corner-shop", &coupons[0]));
+    assert!(redeem(&mut shop, "corner-shop", &coupons[1]));
+    assert!(redeem(&mut kiosk, "kiosk", &coupons[0]));
+    assert!(redeem(&mut kiosk, "kiosk", &coupons[2]));
+    let from_shop = shop.settle(&settle_pk, 2, &mut rng).unwrap();
+    let from_kiosk = kiosk.settle(&settle_pk, 2, &mut rng).unwrap();
+
+    // coupons issued in the meantime don't turn the settlements away
+    issuer.issue(2, &mut rng).unwrap();
+    assert_ne!(issuer.root(), root);
+    assert!(issuer.settle(&settle_vk, "corner-shop", &from_shop).unwrap());
+    // but coupon 0 is paid out once
+    assert!(!issuer.settle(&settle_vk, "kiosk", &from_kiosk).unwrap());
+
+    // nor is a root the issuer never published accepted
+    let mut forged = from_kiosk;
+    forged.root = Root::from(7u64);
+    assert!(!issuer.settle(&settle_vk, "kiosk", &forged).unwrap());
+}
@@ -0,0 +1,127 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::walkthrough::path_siblings;
+use crate::{Root, SimplePath};
+use ark_crypto_primitives::crh::TwoToOneCRH;
+use ark_ff::to_bytes;
+use ark_std::vec::Vec;
+
+// Path verification that doesn't branch on the path, the leaf or the root.
+//
+// `verify_path` goes through arkworks' `Path::verify`, which picks the order
+// of each pair of children with an `if` on the leaf index and compares the
+// recomputed root with `==`. For a verifier whose leaves are secret, both can
+// tell a timing observer something: where the leaf sits, and for a bad proof,
+// how far the comparison got. `verify_ct` replays the path on the canonical
+// bytes of the digests instead. Each pair of children is ordered by a masked
+// byte-wise swap, every level is hashed whatever happened below it, and the
+// roots are compared by OR-ing together the XOR of every byte, so a bad proof
+// takes the same steps as a good one, wherever it is wrong.
+//
+// What is left is the hashing itself: Pedersen hashes with arkworks' curve
+// and field arithmetic, which make no constant-time promises, and whose time
+// can depend on the bits hashed. The length of the leaf and of the path are
+// public, and a leaf too long for the leaf hash is an error, as it is for
+// `verify_path`.
+
+// `a` if `choice` is 0, `b` if it is 1, without branching on `choice`.
+fn select(choice: u8, a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mask = 0u8.wrapping_sub(choice);
+    a.iter().zip(b).map(|(x, y)| x ^ (mask & (x ^ y))).collect()
+}
+
+// Whether `a` and `b` are equal, looking at every byte of both.
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    let difference = a.iter().zip(b).fold(0u8, |difference, (x, y)| difference | (x ^ y));
+    (difference == 0) & (a.len() == b.len())
+}
+
+impl MerkleParams {
+    /// `verify_path`, without branching on the path, the leaf or the root.
+    /// Same answer, at the same cost whatever makes a path fail.
+    pub fn verify_ct(&self, path: &SimplePath, root: &Root, leaf: impl AsRef<[u8]>) -> Result<bool, MerkleError> {
+        let mut current = to_bytes![self.hash_leaf(leaf.as_ref())?]?;
+        for (level, sibling) in path_siblings(path).iter().enumerate() {
+            let sibling = to_bytes![sibling]?;
+            let is_right = ((path.leaf_index >> level) & 1) as u8;
+            let left = select(is_right, &current, &sibling);
+            let right = select(is_right, &sibling, &current);
+            let parent = <TwoToOneHash as TwoToOneCRH>::evaluate(&self.two_to_one_crh_params, &left, &right)?;
+            current = to_bytes![parent]?;
+        }
+        Ok(equal(&current, &to_bytes![root]?))
+    }
+}
+
+#[test]
+fn test_verify_ct_agrees_with_verify_path() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..16).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = tree.root();
+
+    for (index, leaf) in leaves.iter().enumerate() {
+        let path = tree.generate_proof(index).unwrap();
+        assert!(params.verify_ct(&path, &root, leaf).unwrap());
+        assert!(!params.verify_ct(&path, &root, &leaves[index ^ 1]).unwrap());
+        assert!(!params.verify_ct(&path, &Root::from(7u64), leaf).unwrap());
+
+        let mut moved = path.clone();
+        moved.leaf_index ^= 2;
+        assert_eq!(
+            params.verify_ct(&moved, &root, leaf).unwrap(),
+            params.verify_path(&moved, &root, leaf).unwrap()
+        );
+        for level in 0..path.auth_path.len() {
+            let mut tampered = path.clone();
+            tampered.auth_path[level] = Root::from(level as u64);
+            assert!(!params.verify_ct(&tampered, &root, leaf).unwrap());
+        }
+    }
+    assert!(matches!(
+        params.verify_ct(&tree.generate_proof(0).unwrap(), &root, vec![0u8; MAX_LEAF_LEN + 1]),
+        Err(MerkleError::LeafTooLong { .. })
+    ));
+}
+
+// A timing-variance harness: the fastest of many runs of each case, so that
+// scheduling noise drops out, and the spread between the cases as the ratio
+// of the slowest to the fastest. Wall-clock times in a debug build on a
+// loaded machine say little, so it only runs when asked for:
+// `cargo test --release -- --ignored --nocapture test_verify_ct_timing`
+#[cfg(feature = "std")]
+#[test]
+#[ignore]
+fn test_verify_ct_timing_does_not_tell_where_a_proof_fails() {
+    use std::time::{Duration, Instant};
+
+    const RUNS: usize = 30;
+    fn fastest(mut run: impl FnMut() -> bool) -> Duration {
+        (0..RUNS)
+            .map(|_| {
+                let start = Instant::now();
+                assert!(!run());
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..32).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = tree.root();
+    let path = tree.generate_proof(13).unwrap();
+
+    // a bad proof failing at the leaf, at every level of the path, and at the root
+    let mut cases = vec![fastest(|| params.verify_ct(&path, &root, &leaves[12]).unwrap())];
+    for level in 0..path.auth_path.len() {
+        let mut tampered = path.clone();
+        tampered.auth_path[level] = Root::from(level as u64);
+        cases.push(fastest(|| params.verify_ct(&tampered, &root, &leaves[13]).unwrap()));
+    }
+    cases.push(fastest(|| params.verify_ct(&path, &Root::from(1u64), &leaves[13]).unwrap()));
+
+    let spread = cases.iter().max().unwrap().as_secs_f64() / cases.iter().min().unwrap().as_secs_f64();
+    assert!(spread < 1.5, "verify_ct times spread by a factor of {:.2}: {:?}", spread, cases);
+}
@@ -0,0 +1,360 @@
+use crate::common::*;
+use crate::constraints::{blank_path, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::nullifier::{nullifier, nullifier_var, Nullifier, NullifierSet};
+use crate::schnorr::{self, PublicKey, SecretKey, Signature, SignatureVar};
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_ed_on_bls12_381::{constraints::EdwardsVar, Fr};
+use ark_ff::{to_bytes, BigInteger, One, PrimeField};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::rand::{CryptoRng, RngCore};
+
+// Anonymous endorsements by DAO members.
+//
+// The DAO publishes a tree whose leaves are its members' Schnorr public keys
+// (the 64-byte affine encoding). An endorsement of a message M is a proof that
+// "someone in this tree signed M": the circuit witnesses the secret key,
+// derives the public key from it, checks that the key is a leaf and that the
+// signature over M verifies under it. Only the root and the leaf hash of M are
+// public. In linkable mode the proof additionally exposes the nullifier of the
+// secret key scoped to M, so each member can endorse a given message once.
+
+/// Hashes a message the way endorsements refer to it.
+pub fn message_hash(params: &MerkleParams, message: &[u8]) -> Result<ConstraintF, MerkleError> {
+    params.hash_leaf(message)
+}
+
+fn member_leaf(pk: &PublicKey) -> Result<Vec<u8>, MerkleError> {
+    Ok(to_bytes![pk]?)
+}
+
+/// The DAO side: the member tree.
+pub struct Dao {
+    params: MerkleParams,
+    members: Vec<PublicKey>,
+    tree: SimpleMerkleTree,
+}
+
+impl Dao {
+    pub fn new(params: MerkleParams, members: Vec<PublicKey>) -> Result<Self, MerkleError> {
+        let leaves = members.iter().map(member_leaf).collect::<Result<Vec<_>, _>>()?;
+        // an all-zero key is not a curve point, so nobody can sign for padding leaves
+        let options = TreeOptions::for_capacity(leaves.len()).padding(PaddingStrategy::Zeros(64));
+        let tree = params.build_tree_with_options(&leaves, &options)?.tree;
+        Ok(Dao { params, members, tree })
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// Number of levels of the member tree, leaves and root included.
+    pub fn tree_height(&self) -> usize {
+        self.tree.height()
+    }
+
+    pub fn path(&self, member: &PublicKey) -> Result<SimplePath, MerkleError> {
+        let index = self
+            .members
+            .iter()
+            .position(|m| m == member)
+            .ok_or(MerkleError::LeafNotFound)?;
+        generate_proof(&self.tree, LeafIndex::checked_new(index as u64, leaf_count(&self.tree))?)
+    }
+
+    pub fn setup<R: RngCore + CryptoRng>(
+        &self,
+        linkable: bool,
+        rng: &mut R,
+    ) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+        snark::setup(EndorsementCircuit::blank(self.params.clone(), self.tree_height(), linkable), rng)
+    }
+}
+
+/// Proves "a member of the tree under `root` signed the message hashing to `message_hash`".
+pub struct EndorsementCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub linkable: bool,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub message_hash: ConstraintF,
+    pub nullifier: Option<Nullifier>,
+
+    // These are the private witnesses to the circuit.
+    pub sk: Option<SecretKey>,
+    pub signature: Option<Signature>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl EndorsementCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, tree_height: usize, linkable: bool) -> Self {
+        EndorsementCircuit {
+            params,
+            linkable,
+            root: Default::default(),
+            message_hash: Default::default(),
+            nullifier: None,
+            sk: None,
+            signature: None,
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(root: &Root, message_hash: &ConstraintF, nullifier: Option<&Nullifier>) -> Vec<ConstraintF> {
+        let mut inputs = vec![*root, *message_hash];
+        inputs.extend(nullifier.copied());
+        inputs
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for EndorsementCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let message_hash =
+            FpVar::new_input(ark_relations::ns!(cs, "message_hash_var"), || Ok(self.message_hash))?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the private key, signature and path:
+        let sk = self.sk;
+        let sk_bits = (0..Fr::size_in_bits())
+            .map(|i| {
+                Boolean::new_witness(ark_relations::ns!(cs, "sk_var"), || {
+                    sk.map(|sk| sk.into_repr().get_bit(i))
+                        .ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let signature = SignatureVar::new_witness(ark_relations::ns!(cs, "signature_var"), || {
+            self.signature.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // the key must be canonical, otherwise sk + r would give the same public
+        // key but a different nullifier
+        Boolean::enforce_smaller_or_equal_than_le(&sk_bits, (-Fr::one()).into_repr())?;
+
+        // the public key derived from the secret key is a member
+        let pk = EdwardsVar::constant(schnorr::generator()).scalar_mul_le(sk_bits.iter())?;
+        {
+            let _membership = ark_relations::ns!(cs, "membership");
+            let is_member = path.verify_membership(
+                &leaf_crh_params,
+                &two_to_one_crh_params,
+                &root,
+                &pk.to_bytes()?.as_slice(),
+            )?;
+            is_member.enforce_equal(&Boolean::TRUE)?;
+        }
+
+        // and signed the message
+        {
+            let _signature = ark_relations::ns!(cs, "signature");
+            let is_signed = schnorr::verify_hashed_var(&leaf_crh_params, &pk, &message_hash, &signature)?;
+            is_signed.enforce_equal(&Boolean::TRUE)?;
+        }
+
+        if self.linkable {
+            let expected = FpVar::new_input(ark_relations::ns!(cs, "nullifier_var"), || {
+                self.nullifier.ok_or(SynthesisError::AssignmentMissing)
+            })?;
+            // the same bytes as `to_bytes![sk]`
+            let mut padded = sk_bits;
+            padded.resize(256, Boolean::FALSE);
+            let sk_bytes: Vec<_> = padded.chunks(8).map(UInt8::from_bits_le).collect();
+            let computed = nullifier_var(&leaf_crh_params, &sk_bytes, &message_hash.to_bytes()?)?;
+            computed.enforce_equal(&expected)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An anonymous endorsement of `message`.
+#[derive(Clone)]
+pub struct Endorsement {
+    pub message: Vec<u8>,
+    pub nullifier: Option<Nullifier>,
+    pub proof: Proof,
+}
+
+/// The nullifier of `sk` endorsing the message with `message_hash`.
+pub fn endorsement_nullifier(
+    params: &MerkleParams,
+    sk: &SecretKey,
+    message_hash: &ConstraintF,
+) -> Result<Nullifier, MerkleError> {
+    Ok(nullifier(params, &to_bytes![sk]?, &to_bytes![message_hash]?)?)
+}
+
+/// Signs `message` with `sk` and builds the endorsement circuit for it.
+pub fn endorsement_circuit(
+    params: &MerkleParams,
+    dao: &Dao,
+    sk: &SecretKey,
+    message: &[u8],
+    linkable: bool,
+) -> Result<EndorsementCircuit, MerkleError> {
+    let message_hash = message_hash(params, message)?;
+    let nullifier = if linkable {
+        Some(endorsement_nullifier(params, sk, &message_hash)?)
+    } else {
+        None
+    };
+    Ok(EndorsementCircuit {
+        params: params.clone(),
+        linkable,
+        root: dao.root(),
+        message_hash,
+        nullifier,
+        sk: Some(*sk),
+        signature: Some(schnorr::sign(params, sk, message)?),
+        auth_path: Some(dao.path(&schnorr::public_key(sk))?),
+    })
+}
+
+/// Endorses `message` as the member holding `sk`.
+pub fn endorse<R: RngCore + CryptoRng>(
+    params: &MerkleParams,
+    pk: &ProvingKey,
+    dao: &Dao,
+    sk: &SecretKey,
+    message: &[u8],
+    linkable: bool,
+    rng: &mut R,
+) -> Result<Endorsement, MerkleError> {
+    let circuit = endorsement_circuit(params, dao, sk, message, linkable)?;
+    let nullifier = circuit.nullifier;
+    Ok(Endorsement {
+        message: message.to_vec(),
+        nullifier,
+        proof: snark::prove(pk, circuit, rng)?,
+    })
+}
+
+/// Checks endorsements against the DAO root, hashing the message bytes itself.
+pub struct EndorsementVerifier {
+    params: MerkleParams,
+    vk: VerifyingKey,
+    root: Root,
+    // `Some` iff endorsements are linkable
+    seen: Option<NullifierSet>,
+}
+
+impl EndorsementVerifier {
+    pub fn new(params: MerkleParams, vk: VerifyingKey, root: Root, linkable: bool) -> Self {
+        EndorsementVerifier {
+            params,
+            vk,
+            root,
+            seen: if linkable { Some(NullifierSet::new()) } else { None },
+        }
+    }
+
+    /// Checks that `endorsement` endorses `message`, recording its nullifier in linkable mode.
+    pub fn verify(&mut self, endorsement: &Endorsement, message: &[u8]) -> Result<bool, MerkleError> {
+        if endorsement.message != message {
+            return Ok(false);
+        }
+        let nullifier = match (&self.seen, &endorsement.nullifier) {
+            (Some(seen), Some(n)) if !seen.contains(n) => Some(*n),
+            (None, None) => None,
+            _ => return Ok(false),
+        };
+        let message_hash = message_hash(&self.params, message)?;
+        let inputs = EndorsementCircuit::public_inputs(&self.root, &message_hash, nullifier.as_ref());
+        if !snark::verify(&self.vk, &inputs, &endorsement.proof)? {
+            return Ok(false);
+        }
+        if let (Some(seen), Some(n)) = (self.seen.as_mut(), nullifier) {
+            seen.insert(n);
+        }
+        Ok(true)
+    }
+}
+
+#[test]
+fn test_dao_endorsement() {
+    use crate::circuit_report::assert_satisfied_with_report;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let keys: Vec<_> = (0..3).map(|_| schnorr::keygen(&mut rng)).collect();
+    let dao = Dao::new(params.clone(), keys.iter().map(|(_, pk)| *pk).collect()).unwrap();
+    assert_eq!(dao.tree_height(), 3);
+    assert_satisfied_with_report(endorsement_circuit(&params, &dao, &keys[0].0, b"yes", true).unwrap());
+    let (pk, vk) = dao.setup(true, &mut rng).unwrap();
+    let mut verifier = EndorsementVerifier::new(params.clone(), vk, dao.root(), true);
+
+    let message = b"proposal #7: fund the audit";
+    let endorsement = endorse(&params, &pk, &dao, &keys[1].0, message, true, &mut rng).unwrap();
+    assert!(verifier.verify(&endorsement, message).unwrap());
+    // once per member and message
+    let again = endorse(&params, &pk, &dao, &keys[1].0, message, true, &mut rng).unwrap();
+    assert!(!verifier.verify(&again, message).unwrap());
+    let other = endorse(&params, &pk, &dao, &keys[2].0, message, true, &mut rng).unwrap();
+    assert!(verifier.verify(&other, message).unwrap());
+}
+
+#[test]
+fn test_dao_mismatched_message() {
+    use crate::circuit_report::expect_unsatisfied;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (sk, member) = schnorr::keygen(&mut rng);
+    let dao = Dao::new(params.clone(), vec![member]).unwrap();
+    let (pk, vk) = dao.setup(false, &mut rng).unwrap();
+    let mut verifier = EndorsementVerifier::new(params.clone(), vk, dao.root(), false);
+
+    let endorsement = endorse(&params, &pk, &dao, &sk, b"yes", false, &mut rng).unwrap();
+    assert!(!verifier.verify(&endorsement, b"no").unwrap());
+    let mut relabelled = endorsement.clone();
+    relabelled.message = b"no".to_vec();
+    assert!(!verifier.verify(&relabelled, b"no").unwrap());
+    assert!(verifier.verify(&endorsement, b"yes").unwrap());
+
+    // a signature over another message doesn't satisfy the circuit either
+    let mut circuit = endorsement_circuit(&params, &dao, &sk, b"yes", false).unwrap();
+    circuit.signature = Some(schnorr::sign(&params, &sk, b"no").unwrap());
+    expect_unsatisfied(circuit, "signature");
+}
+
+#[test]
+fn test_dao_non_member_key() {
+    use crate::circuit_report::expect_unsatisfied;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (sk, member) = schnorr::keygen(&mut rng);
+    let (outsider_sk, _) = schnorr::keygen(&mut rng);
+    let dao = Dao::new(params.clone(), vec![member]).unwrap();
+
+    assert!(matches!(
+        endorsement_circuit(&params, &dao, &outsider_sk, b"yes", false),
+        Err(MerkleError::LeafNotFound)
+    ));
+    // an outsider with a valid signature of their own, borrowing the member's path
+    let mut circuit = endorsement_circuit(&params, &dao, &sk, b"yes", false).unwrap();
+    circuit.sk = Some(outsider_sk);
+    circuit.signature = Some(schnorr::sign(&params, &outsider_sk, b"yes").unwrap());
+    expect_unsatisfied(circuit, "membership");
+}
@@ -0,0 +1,193 @@
+use crate::common::*;
+use crate::constraints::{MerkleCircuitConfig, MerkleTreeVerification};
+use crate::gen::random_leaf;
+use crate::index::LeafIndex;
+use ark_crypto_primitives::crh::CRH;
+use ark_crypto_primitives::merkle_tree::Config;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+use ark_std::rand::Rng;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+// Native verification and the membership circuit have to agree: a path
+// verifies against a root for a leaf exactly when `MerkleTreeVerification`
+// over the same values is satisfiable. Every zk application in this crate
+// leans on that, so this module checks it case by case.
+//
+// A `Case` is a tree, an opened index, the leaf claimed to be there and
+// optionally one tampered value. `check_case` verifies it natively, where an
+// error counts as "doesn't verify", synthesizes the circuit, where a synthesis
+// error counts as "unsatisfied" and a panic as neither, and panics with the
+// whole case if the two verdicts differ. `random_case` draws cases for
+// randomized runs; property-test strategies can build `Case`s directly.
+
+/// One value of the opening to replace before checking.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Tamper {
+    /// The sibling of the leaf.
+    LeafSibling,
+    /// The auth path entry at this position, top-down.
+    AuthPath(usize),
+    /// Flip this bit of the leaf index; only bits inside the tree are drawn.
+    IndexBit(u32),
+    /// The root the path is checked against.
+    Root,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Case {
+    /// The leaves of the tree, a power of two of them.
+    pub leaves: Vec<Vec<u8>>,
+    pub index: usize,
+    /// The leaf claimed to be at `index`, not necessarily the one that is.
+    pub claimed_leaf: Vec<u8>,
+    pub tamper: Option<Tamper>,
+}
+
+/// What both sides said about a case. `circuit` is `None` if synthesis panicked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Verdicts {
+    pub native: bool,
+    pub circuit: Option<bool>,
+}
+
+impl Verdicts {
+    pub fn agree(&self) -> bool {
+        self.circuit == Some(self.native)
+    }
+}
+
+/// Runs `case` natively and through `MerkleTreeVerification<C>`.
+pub fn run_case<C: MerkleCircuitConfig>(params: &TreeParams<C::Config>, case: &Case) -> Verdicts {
+    let tree = params.build_tree(&case.leaves).expect("case leaves build a tree");
+    let index = LeafIndex::checked_new(case.index as u64, leaf_count(&tree)).expect("case index is in the tree");
+    let mut path = generate_proof(&tree, index).unwrap();
+    let mut root = tree.root();
+    match case.tamper {
+        None => {}
+        Some(Tamper::LeafSibling) => path.leaf_sibling_hash = Default::default(),
+        Some(Tamper::AuthPath(i)) => path.auth_path[i] = Default::default(),
+        Some(Tamper::IndexBit(bit)) => path.leaf_index ^= 1 << bit,
+        Some(Tamper::Root) => root = Default::default(),
+    }
+
+    let native = params.verify_path(&path, &root, &case.claimed_leaf).unwrap_or(false);
+
+    let circuit = MerkleTreeVerification::<C> {
+        leaf_crh_params: params.leaf_crh_params.clone(),
+        two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+        root,
+        leaf: case.claimed_leaf.clone(),
+        auth_path: Some(path),
+    };
+    let circuit = catch_unwind(AssertUnwindSafe(|| {
+        let cs = ConstraintSystem::<C::ConstraintF>::new_ref();
+        match circuit.generate_constraints(cs.clone()) {
+            Ok(()) => cs.is_satisfied().unwrap(),
+            Err(_) => false,
+        }
+    }))
+    .ok();
+
+    Verdicts { native, circuit }
+}
+
+/// Runs `case` and panics with it if native verification and the circuit disagree.
+pub fn check_case<C: MerkleCircuitConfig>(params: &TreeParams<C::Config>, case: &Case) -> Verdicts {
+    let verdicts = run_case::<C>(params, case);
+    assert!(
+        verdicts.agree(),
+        "native verification says {} but the circuit says {:?} for {:#?}",
+        verdicts.native,
+        verdicts.circuit,
+        case
+    );
+    verdicts
+}
+
+/// A random case over a tree of 2 to `max_leaves` leaves of config `P`. About
+/// half the cases should verify; the rest claim another leaf, random bytes or
+/// a leaf too long for the leaf hash, or tamper with the opening.
+pub fn random_case<P: Config, R: Rng>(rng: &mut R, max_leaves: usize) -> Case {
+    let max_len = <P::LeafHash as CRH>::INPUT_SIZE_BITS / 8;
+    let height = rng.gen_range(1, max_leaves.trailing_zeros().max(1) + 1);
+    let n = 1usize << height;
+    let leaves: Vec<Vec<u8>> = (0..n)
+        .map(|_| {
+            let len = if rng.gen_ratio(1, 8) { max_len } else { rng.gen_range(0, 17) };
+            random_leaf(rng, len)
+        })
+        .collect();
+    let index = rng.gen_range(0, n);
+
+    let claimed_leaf = match rng.gen_range(0, 8) {
+        0 => leaves[(index + 1) % n].clone(),
+        1 => random_leaf(rng, 8),
+        2 => {
+            let len = max_len + rng.gen_range(1, 9);
+            random_leaf(rng, len)
+        }
+        _ => leaves[index].clone(),
+    };
+    let tamper = match rng.gen_range(0, 8) {
+        0 => Some(Tamper::LeafSibling),
+        1 if height > 1 => Some(Tamper::AuthPath(rng.gen_range(0, height as usize - 1))),
+        2 => Some(Tamper::IndexBit(rng.gen_range(0, height))),
+        3 => Some(Tamper::Root),
+        _ => None,
+    };
+    Case {
+        leaves,
+        index,
+        claimed_leaf,
+        tamper,
+    }
+}
+
+#[cfg(test)]
+const RANDOM_CASES: usize = 200;
+
+#[cfg(test)]
+fn check_random_cases<C: MerkleCircuitConfig>() {
+    let mut rng = ark_std::test_rng();
+    let params = TreeParams::<C::Config>::setup(&mut rng).unwrap();
+    let mut verified = 0;
+    for _ in 0..RANDOM_CASES {
+        let case = random_case::<C::Config, _>(&mut rng, 8);
+        if check_case::<C>(&params, &case).native {
+            verified += 1;
+        }
+    }
+    // both verdicts must actually come up
+    assert!(verified > RANDOM_CASES / 5 && verified < RANDOM_CASES * 4 / 5, "{} verified", verified);
+}
+
+#[test]
+fn test_native_and_circuit_agree_on_jubjub() {
+    check_random_cases::<crate::constraints::JubjubMerkleCircuit>();
+}
+
+#[test]
+fn test_native_and_circuit_agree_on_bls12_377() {
+    check_random_cases::<crate::constraints::Bls12_377MerkleCircuit>();
+}
+
+#[test]
+fn test_native_and_circuit_agree_on_mock() {
+    check_random_cases::<crate::mock::MockMerkleCircuit>();
+}
+
+#[test]
+fn test_too_long_claimed_leaf_is_rejected_by_both() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    for len in [MAX_LEAF_LEN, MAX_LEAF_LEN + 1, MAX_LEAF_LEN + 32].iter() {
+        let case = Case {
+            leaves: leaves.clone(),
+            index: 1,
+            claimed_leaf: vec![1u8; *len],
+            tamper: None,
+        };
+        let verdicts = check_case::<crate::constraints::JubjubMerkleCircuit>(&params, &case);
+        assert!(!verdicts.native);
+    }
+}
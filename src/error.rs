@@ -0,0 +1,263 @@
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::SynthesisError;
+use ark_serialize::SerializationError;
+use ark_std::boxed::Box;
+use ark_std::fmt;
+use ark_std::string::String;
+
+/// Errors returned by the tree wrappers and the application modules built on top of them.
+#[derive(Debug)]
+pub enum MerkleError {
+    /// A leaf index pointed past the end of the tree.
+    LeafIndexOutOfRange { index: usize, len: usize },
+    /// A leaf was longer than the leaf hash can absorb.
+    LeafTooLong { len: usize, max: usize },
+    /// A leaf hashes like the padding of a tree, so nobody can be told
+    /// apart from an empty slot by it.
+    PaddingLeaf,
+    /// The same leaf was referenced twice where distinct leaves are required.
+    DuplicateLeafIndex(usize),
+    /// The requested leaf is not in the tree.
+    LeafNotFound,
+    /// A path did not open the claimed leaf against the expected root.
+    InvalidProof,
+    /// A signature did not verify under the expected key.
+    InvalidSignature,
+    /// An artifact was made with other hash parameters than the ones checking it.
+    ParamsMismatch,
+    /// A string that should have been hex wasn't.
+    InvalidHex,
+    /// A tree transcript entry is not a valid operation, or doesn't lead to the root it records.
+    InvalidTranscript { entry: usize },
+    /// A sorted tree was given a value not greater than the one before it,
+    /// or one of its reserved bounds.
+    UnsortedValue { index: usize },
+    /// The value whose absence should be proven is in the tree.
+    ValueInTree,
+    /// A stored tree node is not the digest of what is below it.
+    CorruptTree { level: usize, index: usize },
+    /// More leaves were given than the tree was sized for.
+    CapacityExceeded { len: usize, capacity: usize },
+    /// A tree was asked for over no leaves at all.
+    EmptyTree,
+    /// Two keys of a `MerkleMap` hash to the same leaf index.
+    KeyCollision { index: u64 },
+    /// A proof was made against another root than the current one.
+    StaleProof,
+    /// A `VersionedTree` was asked about a version it hasn't reached.
+    UnknownVersion { version: usize, latest: usize },
+    /// Pedersen windows too small for the inputs they were chosen to hash.
+    WindowsTooSmall { bits: usize, capacity: usize },
+    /// A path of `depth` levels and a leaf of `leaf_len` bytes were given to
+    /// a fixed-shape circuit set up for others.
+    ShapeMismatch {
+        depth: usize,
+        leaf_len: usize,
+        expected_depth: usize,
+        expected_leaf_len: usize,
+    },
+    /// Test vector `vector` doesn't match what this crate computes for
+    /// `field`.
+    VectorMismatch { vector: usize, field: &'static str },
+    /// Hashing or tree construction failed inside arkworks.
+    Hash(ark_crypto_primitives::Error),
+    /// Constraint synthesis failed.
+    #[cfg(feature = "constraints")]
+    Synthesis(SynthesisError),
+    /// Bytes could not be decoded into the expected artifact.
+    Serialization(SerializationError),
+    /// Marlin's setup, indexing, proving or verifying failed other than in
+    /// constraint synthesis.
+    #[cfg(feature = "marlin")]
+    Marlin(String),
+    /// `source`, raised while the tree code was doing what `context` says.
+    WithContext {
+        context: ErrorContext,
+        source: Box<MerkleError>,
+    },
+}
+
+/// What a tree operation was working on when it failed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The operation, like `"build_tree"`.
+    pub operation: &'static str,
+    /// The label of the tree, for code that keeps several.
+    pub tree: Option<String>,
+    pub index: Option<usize>,
+    /// The tree level, counted from the leaves at 0.
+    pub level: Option<usize>,
+}
+
+impl ErrorContext {
+    pub fn new(operation: &'static str) -> Self {
+        ErrorContext {
+            operation,
+            ..Default::default()
+        }
+    }
+
+    pub fn tree(mut self, label: impl Into<String>) -> Self {
+        self.tree = Some(label.into());
+        self
+    }
+
+    pub fn index(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    pub fn level(mut self, level: usize) -> Self {
+        self.level = Some(level);
+        self
+    }
+}
+
+impl MerkleError {
+    /// The error underneath any context.
+    pub fn root_cause(&self) -> &MerkleError {
+        match self {
+            MerkleError::WithContext { source, .. } => source.root_cause(),
+            e => e,
+        }
+    }
+
+    /// The outermost context, if there is one.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            MerkleError::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+}
+
+/// `with_context` for the tree code's results.
+pub(crate) trait ResultExt<T> {
+    fn with_context(self, context: impl FnOnce() -> ErrorContext) -> Result<T, MerkleError>;
+}
+
+impl<T, E: Into<MerkleError>> ResultExt<T> for Result<T, E> {
+    fn with_context(self, context: impl FnOnce() -> ErrorContext) -> Result<T, MerkleError> {
+        self.map_err(|e| MerkleError::WithContext {
+            context: context(),
+            source: Box::new(e.into()),
+        })
+    }
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MerkleError::LeafIndexOutOfRange { index, len } => {
+                write!(f, "leaf index {} is out of range for a tree of {} leaves", index, len)
+            }
+            MerkleError::LeafTooLong { len, max } => {
+                write!(f, "leaf of {} bytes exceeds the maximum of {} bytes", len, max)
+            }
+            MerkleError::PaddingLeaf => write!(f, "the leaf hashes like the padding of the tree"),
+            MerkleError::DuplicateLeafIndex(index) => {
+                write!(f, "leaf index {} was given more than once", index)
+            }
+            MerkleError::LeafNotFound => write!(f, "leaf not found in the tree"),
+            MerkleError::InvalidProof => write!(f, "the path does not open the leaf against the root"),
+            MerkleError::InvalidSignature => write!(f, "invalid signature"),
+            MerkleError::ParamsMismatch => write!(f, "made with different hash parameters"),
+            MerkleError::InvalidHex => write!(f, "invalid hex string"),
+            MerkleError::InvalidTranscript { entry } => {
+                write!(f, "entry {} of the tree transcript does not replay", entry)
+            }
+            MerkleError::UnsortedValue { index } => {
+                write!(f, "value {} is out of order or a reserved bound", index)
+            }
+            MerkleError::ValueInTree => write!(f, "the value is in the tree"),
+            MerkleError::CorruptTree { level, index } => {
+                write!(f, "stored node {} of level {} does not match the tree", index, level)
+            }
+            MerkleError::CapacityExceeded { len, capacity } => {
+                write!(f, "{} leaves do not fit into a tree of capacity {}", len, capacity)
+            }
+            MerkleError::EmptyTree => write!(f, "a tree needs at least one leaf"),
+            MerkleError::KeyCollision { index } => {
+                write!(f, "another key is already stored at leaf index {}", index)
+            }
+            MerkleError::StaleProof => write!(f, "the proof is against an earlier root"),
+            MerkleError::UnknownVersion { version, latest } => {
+                write!(f, "version {} is past the latest version, {}", version, latest)
+            }
+            MerkleError::WindowsTooSmall { bits, capacity } => {
+                write!(f, "an input of {} bits doesn't fit Pedersen windows of {} bits", bits, capacity)
+            }
+            MerkleError::ShapeMismatch {
+                depth,
+                leaf_len,
+                expected_depth,
+                expected_leaf_len,
+            } => write!(
+                f,
+                "a path of depth {} and a leaf of {} bytes don't fit a circuit for depth {} and leaves of {} bytes",
+                depth, leaf_len, expected_depth, expected_leaf_len
+            ),
+            MerkleError::VectorMismatch { vector, field } => {
+                write!(f, "test vector {} has another {} than this crate computes", vector, field)
+            }
+            MerkleError::Hash(e) => write!(f, "hash error: {}", e),
+            #[cfg(feature = "constraints")]
+            MerkleError::Synthesis(e) => write!(f, "synthesis error: {}", e),
+            MerkleError::Serialization(e) => write!(f, "serialization error: {}", e),
+            #[cfg(feature = "marlin")]
+            MerkleError::Marlin(e) => write!(f, "marlin error: {}", e),
+            // one line: the outermost context, then what went wrong
+            MerkleError::WithContext { context, source } => write!(f, "{}: {}", context, source.root_cause()),
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.operation)?;
+        if let Some(tree) = &self.tree {
+            write!(f, " of tree {}", tree)?;
+        }
+        match (self.index, self.level) {
+            (Some(index), None) | (Some(index), Some(0)) => write!(f, " at leaf {}", index)?,
+            (Some(index), Some(level)) => write!(f, " at node {} of level {}", index, level)?,
+            (None, Some(level)) => write!(f, " on level {}", level)?,
+            (None, None) => {}
+        }
+        Ok(())
+    }
+}
+
+impl ark_std::error::Error for MerkleError {
+    fn source(&self) -> Option<&(dyn ark_std::error::Error + 'static)> {
+        match self {
+            MerkleError::WithContext { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ark_crypto_primitives::Error> for MerkleError {
+    fn from(e: ark_crypto_primitives::Error) -> Self {
+        MerkleError::Hash(e)
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl From<SynthesisError> for MerkleError {
+    fn from(e: SynthesisError) -> Self {
+        MerkleError::Synthesis(e)
+    }
+}
+
+impl From<SerializationError> for MerkleError {
+    fn from(e: SerializationError) -> Self {
+        MerkleError::Serialization(e)
+    }
+}
+
+impl From<ark_std::io::Error> for MerkleError {
+    fn from(e: ark_std::io::Error) -> Self {
+        MerkleError::Serialization(e.into())
+    }
+}
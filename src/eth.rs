@@ -0,0 +1,252 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::serialization::{PathSiblings, PathSiblingsExt};
+use crate::SimplePath;
+use ark_std::string::String;
+use ark_std::vec::Vec;
+
+// Merkle trees and proofs in the format of OpenZeppelin's tooling.
+//
+// `@openzeppelin/merkle-tree`'s `StandardMerkleTree` and the Solidity
+// `MerkleProof` library it pairs with hash with keccak256 throughout. A leaf
+// is `keccak256(keccak256(abi.encode(values)))`, hashed twice so it can't be
+// confused with an inner node; two nodes are combined by hashing the smaller
+// one first (`hash_pair`), so a proof is only the list of siblings, bottom-up,
+// without positions. The tree is the JS library's: leaves sorted by hash,
+// laid out as one array with the root at 0, the children of node `i` at
+// `2i + 1` and `2i + 2`, and the leaves at its end in reverse order, so any
+// number of leaves works without padding. Given the same values, `EthTree`
+// has the root the JS library computes, and its proofs verify on-chain with
+// `MerkleProof.verify`. Proofs are hex strings with `0x`, as both print them.
+//
+// Pedersen paths can be carried in the same hex arrays with `path_to_hex`,
+// for tooling that only passes proofs around. They are positional, so
+// `path_from_hex` needs the leaf index back, and they verify only with
+// `MerkleParams::verify_path`.
+
+/// A keccak256 digest.
+pub type Hash = [u8; 32];
+
+/// Combines two nodes the way `MerkleProof` does: the smaller one first.
+pub fn hash_pair(a: &Hash, b: &Hash) -> Hash {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    let mut input = [0u8; 64];
+    input[..32].copy_from_slice(first);
+    input[32..].copy_from_slice(second);
+    keccak256(&input)
+}
+
+/// The leaf of a `StandardMerkleTree` whose values ABI-encode to `abi_encoded`.
+pub fn standard_leaf_hash(abi_encoded: &[u8]) -> Hash {
+    keccak256(&keccak256(abi_encoded))
+}
+
+/// `abi.encode(address, uint256)`, the values of an airdrop entry: two
+/// 32-byte words, both big-endian and left-padded.
+pub fn abi_encode_address_uint256(address: &[u8; 20], amount: u128) -> Vec<u8> {
+    let mut encoded = vec![0u8; 64];
+    encoded[12..32].copy_from_slice(address);
+    encoded[48..].copy_from_slice(&amount.to_be_bytes());
+    encoded
+}
+
+/// The leaf of an airdrop entry, as `StandardMerkleTree.of(entries, ["address", "uint256"])` has it.
+pub fn airdrop_leaf(address: &[u8; 20], amount: u128) -> Hash {
+    standard_leaf_hash(&abi_encode_address_uint256(address, amount))
+}
+
+/// A tree laid out like `StandardMerkleTree`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EthTree {
+    nodes: Vec<Hash>,
+    // the node of each leaf, in the order the leaves were given
+    positions: Vec<usize>,
+}
+
+impl EthTree {
+    /// The tree over the leaf hashes, which it sorts like the JS library.
+    /// `index` arguments below count in the order given here. Fails with
+    /// `EmptyTree` on no leaves, which the JS library rejects too.
+    pub fn new(leaves: &[Hash]) -> Result<Self, MerkleError> {
+        if leaves.is_empty() {
+            return Err(MerkleError::EmptyTree);
+        }
+        let mut order: Vec<usize> = (0..leaves.len()).collect();
+        order.sort_by(|a, b| leaves[*a].cmp(&leaves[*b]));
+
+        let len = 2 * leaves.len() - 1;
+        let mut nodes = vec![[0u8; 32]; len];
+        let mut positions = vec![0; leaves.len()];
+        for (rank, &index) in order.iter().enumerate() {
+            positions[index] = len - 1 - rank;
+            nodes[len - 1 - rank] = leaves[index];
+        }
+        for node in (0..len - leaves.len()).rev() {
+            nodes[node] = hash_pair(&nodes[2 * node + 1], &nodes[2 * node + 2]);
+        }
+        Ok(EthTree { nodes, positions })
+    }
+
+    pub fn root(&self) -> Hash {
+        self.nodes[0]
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// The hash of leaf `index`.
+    pub fn leaf(&self, index: usize) -> Option<Hash> {
+        self.positions.get(index).map(|position| self.nodes[*position])
+    }
+
+    /// The siblings of leaf `index`, bottom-up, as `MerkleProof.verify` takes them.
+    pub fn proof(&self, index: usize) -> Result<Vec<Hash>, MerkleError> {
+        let mut node = *self.positions.get(index).ok_or(MerkleError::LeafIndexOutOfRange {
+            index,
+            len: self.len(),
+        })?;
+        let mut proof = Vec::new();
+        while node > 0 {
+            let sibling = if node % 2 == 1 { node + 1 } else { node - 1 };
+            proof.push(self.nodes[sibling]);
+            node = (node - 1) / 2;
+        }
+        Ok(proof)
+    }
+}
+
+/// The root `proof` leads to from `leaf`, like `MerkleProof.processProof`.
+pub fn process_proof(leaf: &Hash, proof: &[Hash]) -> Hash {
+    proof.iter().fold(*leaf, |node, sibling| hash_pair(&node, sibling))
+}
+
+/// Whether `proof` opens `leaf` against `root`, like `MerkleProof.verify`.
+pub fn verify_proof(root: &Hash, leaf: &Hash, proof: &[Hash]) -> bool {
+    process_proof(leaf, proof) == *root
+}
+
+/// A digest as `0x`-prefixed hex.
+pub fn hash_to_hex(hash: &Hash) -> String {
+    format!("0x{}", to_hex(hash))
+}
+
+/// Reads a digest written by `hash_to_hex`, or without the `0x`.
+pub fn hash_from_hex(hex: &str) -> Result<Hash, MerkleError> {
+    let bytes = from_hex(hex.strip_prefix("0x").unwrap_or(hex)).ok_or(MerkleError::InvalidHex)?;
+    let mut hash = [0u8; 32];
+    if bytes.len() != hash.len() {
+        return Err(MerkleError::InvalidHex);
+    }
+    hash.copy_from_slice(&bytes);
+    Ok(hash)
+}
+
+/// A proof as the array of hex strings the JS library prints.
+pub fn proof_to_hex(proof: &[Hash]) -> Vec<String> {
+    proof.iter().map(hash_to_hex).collect()
+}
+
+pub fn proof_from_hex<S: AsRef<str>>(proof: &[S]) -> Result<Vec<Hash>, MerkleError> {
+    proof.iter().map(|hex| hash_from_hex(hex.as_ref())).collect()
+}
+
+/// The siblings of a Pedersen path, bottom-up like a `MerkleProof` proof, as
+/// `0x`-prefixed hex of their canonical encoding.
+pub fn path_to_hex(path: &SimplePath) -> Vec<String> {
+    let siblings = path.into_siblings();
+    ark_std::iter::once(&siblings.leaf_sibling)
+        .chain(&siblings.nodes)
+        .map(|digest| format!("0x{}", to_hex(digest)))
+        .collect()
+}
+
+/// The path of leaf `leaf_index` from the siblings `path_to_hex` wrote.
+pub fn path_from_hex<S: AsRef<str>>(leaf_index: u64, proof: &[S]) -> Result<SimplePath, MerkleError> {
+    let mut digests = proof
+        .iter()
+        .map(|hex| {
+            let hex = hex.as_ref();
+            from_hex(hex.strip_prefix("0x").unwrap_or(hex)).ok_or(MerkleError::InvalidHex)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if digests.is_empty() {
+        return Err(MerkleError::InvalidProof);
+    }
+    let leaf_sibling = digests.remove(0);
+    SimplePath::from_siblings(PathSiblings {
+        leaf_index,
+        leaf_sibling,
+        nodes: digests,
+    })
+}
+
+#[test]
+fn test_eth_tree_layout_and_proofs() {
+    let leaves: Vec<Hash> = (1u8..=5).map(|i| airdrop_leaf(&[i; 20], i as u128 * 1_000)).collect();
+    let tree = EthTree::new(&leaves).unwrap();
+    assert_eq!(tree.len(), 5);
+
+    // the JS library's layout: nine nodes, the sorted leaves at the end, reversed
+    let mut sorted = leaves.clone();
+    sorted.sort();
+    assert_eq!(tree.nodes.len(), 9);
+    assert_eq!(tree.nodes[8], sorted[0]);
+    assert_eq!(tree.nodes[4], sorted[4]);
+    assert_eq!(tree.nodes[3], hash_pair(&tree.nodes[7], &tree.nodes[8]));
+    assert_eq!(tree.root(), hash_pair(&tree.nodes[1], &tree.nodes[2]));
+
+    for (index, leaf) in leaves.iter().enumerate() {
+        assert_eq!(tree.leaf(index), Some(*leaf));
+        let proof = tree.proof(index).unwrap();
+        assert!(verify_proof(&tree.root(), leaf, &proof));
+        assert!(!verify_proof(&tree.root(), &leaves[(index + 1) % 5], &proof));
+        assert_eq!(proof_from_hex(&proof_to_hex(&proof)).unwrap(), proof);
+    }
+    assert!(matches!(tree.proof(5), Err(MerkleError::LeafIndexOutOfRange { index: 5, len: 5 })));
+
+    // the order the leaves come in doesn't change the tree
+    let mut reversed = leaves.clone();
+    reversed.reverse();
+    assert_eq!(EthTree::new(&reversed).unwrap().root(), tree.root());
+    assert_eq!(EthTree::new(&leaves[..1]).unwrap().root(), leaves[0]);
+    assert!(matches!(EthTree::new(&[]), Err(MerkleError::EmptyTree)));
+}
+
+#[test]
+fn test_eth_encoding() {
+    let encoded = abi_encode_address_uint256(&[0xab; 20], 0x0102);
+    assert_eq!(encoded.len(), 64);
+    assert!(encoded[..12].iter().all(|b| *b == 0));
+    assert_eq!(&encoded[12..32], &[0xab; 20]);
+    assert_eq!(&encoded[62..], &[0x01, 0x02]);
+
+    let hash = airdrop_leaf(&[0xab; 20], 0x0102);
+    let hex = hash_to_hex(&hash);
+    assert!(hex.starts_with("0x") && hex.len() == 66);
+    assert_eq!(hash_from_hex(&hex).unwrap(), hash);
+    assert_eq!(hash_from_hex(&hex[2..]).unwrap(), hash);
+    assert!(matches!(hash_from_hex("0x1234"), Err(MerkleError::InvalidHex)));
+    assert!(matches!(hash_from_hex(&format!("0x{}", "zz".repeat(32))), Err(MerkleError::InvalidHex)));
+}
+
+#[test]
+fn test_pedersen_path_as_hex_array() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let path = tree.generate_proof(5).unwrap();
+
+    let hex = path_to_hex(&path);
+    assert_eq!(hex.len(), 3);
+    let decoded = path_from_hex(5, &hex).unwrap();
+    assert!(params.verify_path(&decoded, &tree.root(), &leaves[5]).unwrap());
+    // the index isn't in the array
+    assert!(!params.verify_path(&path_from_hex(4, &hex).unwrap(), &tree.root(), &leaves[5]).unwrap());
+    assert!(path_from_hex(8, &hex).is_err());
+    assert!(matches!(path_from_hex::<&str>(0, &[]), Err(MerkleError::InvalidProof)));
+}
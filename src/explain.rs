@@ -0,0 +1,170 @@
+use crate::common::*;
+use crate::walkthrough::{digest_hex, path_siblings, replay_path};
+use crate::{Root, SimplePath};
+use ark_std::string::String;
+use ark_std::vec::Vec;
+
+// Why a proof did or didn't verify, step by step, for people who don't read
+// Rust: the leaf hash, every parent recomputed on the way up, and the final
+// comparison with the expected root.
+//
+// The digests are the ones `walkthrough::replay_path` computes, which is the
+// same replay the `Walkthrough` records, and the verdict is the one
+// `MerkleParams::verify_path` gives. Digests are shown as the first 8 hex
+// digits of their canonical encoding followed by an ellipsis. Digest i is the
+// node on the path at level i, so d0 is the leaf hash; sibling i is its sibling.
+
+const SHORT_HEX_LEN: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExplanationStep {
+    pub description: String,
+    pub passed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Explanation {
+    pub steps: Vec<ExplanationStep>,
+    /// What `MerkleParams::verify_path` said.
+    pub verified: bool,
+}
+
+/// Replays `path` for `leaf` and compares the result with `root`.
+pub fn explain_verification(params: &MerkleParams, root: &Root, leaf: &[u8], path: &SimplePath) -> Explanation {
+    let mut steps = Vec::new();
+    let mut step = |description: String, passed: bool| steps.push(ExplanationStep { description, passed });
+
+    let (leaf_hash, replayed) = match replay_path(params, leaf, path) {
+        Ok(replayed) => replayed,
+        Err(e) => {
+            step(format!("hashed leaf of {} bytes: {}", leaf.len(), e), false);
+            return Explanation { steps, verified: false };
+        }
+    };
+    step(format!("hashed leaf → d0 = {}", short_hex(&leaf_hash)), true);
+
+    let siblings = path_siblings(path);
+    for (level, (replayed, sibling)) in replayed.iter().zip(siblings.iter()).enumerate() {
+        step(
+            format!(
+                "level {}: combined d{} ({}) with sibling s{} = {} → d{} = {}",
+                level,
+                level,
+                if replayed.is_right { "right" } else { "left" },
+                level,
+                short_hex(sibling),
+                level + 1,
+                short_hex(&replayed.parent)
+            ),
+            true,
+        );
+    }
+
+    let last = replayed.len();
+    let computed = replayed.last().map_or(leaf_hash, |step| step.parent);
+    let matches = computed == *root;
+    step(
+        format!(
+            "compared d{} = {} with expected root {} → {}",
+            last,
+            short_hex(&computed),
+            short_hex(root),
+            if matches { "MATCH" } else { "MISMATCH" }
+        ),
+        matches,
+    );
+
+    Explanation {
+        steps,
+        verified: params.verify_path(path, root, leaf).unwrap_or(false),
+    }
+}
+
+impl Explanation {
+    /// One numbered line per step.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let verdict = if step.passed { "ok" } else { "FAIL" };
+            text += &format!("{}. [{}] {}\n", i + 1, verdict, step.description);
+        }
+        text += if self.verified { "proof verifies\n" } else { "proof does not verify\n" };
+        text
+    }
+
+    /// `{"verified": bool, "steps": [{"description": string, "passed": bool}, ...]}`.
+    pub fn to_json(&self) -> String {
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|step| format!("{{\"description\":{},\"passed\":{}}}", json_string(&step.description), step.passed))
+            .collect();
+        format!("{{\"verified\":{},\"steps\":[{}]}}", self.verified, steps.join(","))
+    }
+}
+
+fn short_hex(digest: &Root) -> String {
+    // a field element always serializes
+    let hex = digest_hex(digest).unwrap();
+    format!("{}…", &hex[..SHORT_HEX_LEN])
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Replaces every shortened digest with `<digest>`, so the text doesn't depend
+// on the hash parameters.
+#[cfg(test)]
+fn redact(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            line.split(' ')
+                .map(|word| if word.ends_with('…') { "<digest>" } else { word })
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn test_explanation_of_a_wrong_leaf() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (1u8..5).map(|i| vec![i]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let path = tree.generate_proof(2).unwrap();
+
+    let explanation = explain_verification(&params, &tree.root(), &[9u8], &path);
+    assert!(!explanation.verified);
+    assert_eq!(
+        redact(&explanation.to_text()),
+        "1. [ok] hashed leaf → d0 = <digest>\n\
+         2. [ok] level 0: combined d0 (left) with sibling s0 = <digest> → d1 = <digest>\n\
+         3. [ok] level 1: combined d1 (right) with sibling s1 = <digest> → d2 = <digest>\n\
+         4. [FAIL] compared d2 = <digest> with expected root <digest> → MISMATCH\n\
+         proof does not verify"
+    );
+    assert!(explanation.to_json().starts_with("{\"verified\":false,\"steps\":[{\"description\":\"hashed leaf → d0 = "));
+
+    // the right leaf matches, and so does verify_path
+    let explanation = explain_verification(&params, &tree.root(), &leaves[2], &path);
+    assert!(explanation.verified);
+    assert!(explanation.steps.iter().all(|step| step.passed));
+    assert!(explanation.to_text().ends_with("→ MATCH\nproof verifies\n"));
+
+    // a leaf too long for the leaf hash fails at the first step
+    let explanation = explain_verification(&params, &tree.root(), &[0u8; MAX_LEAF_LEN + 1], &path);
+    assert_eq!(explanation.steps.len(), 1);
+    assert!(!explanation.steps[0].passed);
+}
@@ -0,0 +1,92 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::MTreeVerification;
+use crate::index::LeafIndex;
+use crate::kit::default_params;
+use crate::leaf::Leaf;
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_std::rand::{rngs::StdRng, SeedableRng};
+
+// Deterministic trees for tests, shared by the unit tests, the integration
+// tests and benches. They need nothing but `std`, so unlike the rest of the
+// test helpers they are there without the `testing` feature, for the
+// default `cargo test`.
+//
+// Leaf i is the keccak256 digest of the one-byte value `raw_values[i]`. The
+// first eight values are the ones the original tutorial tests used. The hash
+// parameters are `kit::default_params`, computed once per thread.
+
+const TUTORIAL_VALUES: [u8; 8] = [1, 2, 3, 10, 9, 17, 70, 45];
+
+/// The largest fixture tree.
+pub const MAX_FIXTURE_LEAVES: usize = 128;
+
+pub struct Fixture {
+    pub tree: SimpleMerkleTree,
+    pub params: MerkleParams,
+    pub leaves: Vec<Vec<u8>>,
+    pub raw_values: Vec<u8>,
+}
+
+thread_local! {
+    static PARAMS: MerkleParams = default_params().unwrap();
+}
+
+/// The hash parameters of every fixture.
+pub fn fixture_params() -> MerkleParams {
+    PARAMS.with(|params| params.clone())
+}
+
+/// An RNG with a fixed seed, for whatever else a test needs to sample.
+pub fn fixture_rng() -> StdRng {
+    StdRng::from_seed(*b"merkle-tree-tutorial/fixture-rng")
+}
+
+/// The leaf for the one-byte value `value`.
+pub fn fixture_leaf(value: u8) -> Vec<u8> {
+    Leaf::keccak_of(&[value]).into_vec()
+}
+
+/// A tree of `n` leaves; `n` must be a power of two of at most `MAX_FIXTURE_LEAVES`.
+pub fn fixture_tree(n: usize) -> Fixture {
+    assert!(
+        n.is_power_of_two() && n > 1 && n <= MAX_FIXTURE_LEAVES,
+        "fixture trees have 2 to {} leaves, a power of two",
+        MAX_FIXTURE_LEAVES
+    );
+    // past the tutorial values, continue with values none of them collide with
+    let raw_values: Vec<u8> = (0..n)
+        .map(|i| TUTORIAL_VALUES.get(i).copied().unwrap_or(100 + i as u8))
+        .collect();
+    let leaves: Vec<Vec<u8>> = raw_values.iter().map(|v| fixture_leaf(*v)).collect();
+    let params = fixture_params();
+    let tree = params.build_tree(&leaves).unwrap();
+    Fixture {
+        tree,
+        params,
+        leaves,
+        raw_values,
+    }
+}
+
+impl Fixture {
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// The index of the leaf for `value`.
+    pub fn index_of(&self, value: u8) -> Option<usize> {
+        self.raw_values.iter().position(|v| *v == value)
+    }
+
+    pub fn proof(&self, index: usize) -> SimplePath {
+        let index = LeafIndex::checked_new(index as u64, self.leaves.len() as u64).unwrap();
+        generate_proof(&self.tree, index).unwrap()
+    }
+
+    /// The membership circuit for the leaf at `index`, with its real path.
+    #[cfg(feature = "constraints")]
+    pub fn circuit(&self, index: usize) -> MTreeVerification {
+        <MTreeVerification>::new(&self.params, self.root(), &self.leaves[index], self.proof(index))
+    }
+}
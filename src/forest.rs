@@ -0,0 +1,422 @@
+use crate::common::*;
+use crate::constraints::{blank_path, IndexedPathVar, RootVar};
+use crate::error::{ErrorContext, MerkleError, ResultExt};
+use crate::index::LeafIndex;
+use crate::inputs::byte_inputs;
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::collections::BTreeMap;
+use ark_std::rand::{CryptoRng, RngCore};
+
+// A forest of independent regional trees.
+//
+// Users are registered in exactly one named tree; relying parties only get
+// the current `RootSet`, i.e. the roots of all trees stamped with an epoch
+// that is bumped on every change. A `ForestProof` names the tree a leaf lives
+// in and is checked against that tree's root. In private mode the region is
+// hidden: `OneOfRootsCircuit` proves that the leaf is under *one of* the roots
+// without saying which.
+//
+// All trees share the same capacity, so that a single circuit covers every
+// region.
+//
+// The slots past a tree's leaves hold the empty leaf. The leaf hash pads its
+// input with zeros, so every leaf of nothing but zero bytes hashes like it,
+// and would open any empty slot with a path anyone can put together from a
+// published proof. Such leaves are refused: `insert` won't register them and
+// neither `verify` nor `verify_private` accepts them, the leaf being public
+// in both.
+
+// Whether `leaf` hashes like the padding of a region tree.
+fn is_padding(leaf: &[u8]) -> bool {
+    leaf.iter().all(|byte| *byte == 0)
+}
+
+struct RegionTree {
+    leaves: Vec<Vec<u8>>,
+    tree: SimpleMerkleTree,
+}
+
+pub struct Forest {
+    params: MerkleParams,
+    options: TreeOptions,
+    trees: BTreeMap<String, RegionTree>,
+    epoch: u64,
+}
+
+/// The roots of every tree in the forest at one epoch, in name order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RootSet {
+    pub epoch: u64,
+    pub roots: Vec<(String, Root)>,
+}
+
+/// A membership proof that reveals the tree the leaf lives in.
+#[derive(Clone)]
+pub struct ForestProof {
+    pub tree: String,
+    pub epoch: u64,
+    pub leaf: Vec<u8>,
+    pub path: SimplePath,
+}
+
+/// A membership proof that hides the tree the leaf lives in.
+#[derive(Clone)]
+pub struct PrivateForestProof {
+    pub epoch: u64,
+    pub leaf: Vec<u8>,
+    pub proof: Proof,
+}
+
+impl Forest {
+    /// Creates an empty forest whose trees hold up to `capacity` leaves each.
+    pub fn new(params: MerkleParams, capacity: usize) -> Self {
+        Forest {
+            params,
+            options: TreeOptions::for_capacity(capacity).padding(PaddingStrategy::Empty),
+            trees: BTreeMap::new(),
+            epoch: 0,
+        }
+    }
+
+    fn build_tree(&self, leaves: &[Vec<u8>]) -> Result<SimpleMerkleTree, MerkleError> {
+        Ok(self.params.build_tree_with_options(leaves, &self.options)?.tree)
+    }
+
+    /// Number of levels of every tree, leaves and root included.
+    pub fn tree_height(&self) -> usize {
+        self.options.height
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Adds an empty tree called `name`, or does nothing if it exists already.
+    pub fn add_tree(&mut self, name: &str) -> Result<(), MerkleError> {
+        if !self.trees.contains_key(name) {
+            let tree = self.build_tree(&[])?;
+            self.trees.insert(name.to_string(), RegionTree { leaves: Vec::new(), tree });
+            self.epoch += 1;
+        }
+        Ok(())
+    }
+
+    /// Registers `leaf` in the tree called `name`. Fails with `PaddingLeaf`
+    /// for a leaf of zero bytes only, which would be the padding's.
+    pub fn insert(&mut self, name: &str, leaf: &[u8]) -> Result<(), MerkleError> {
+        if is_padding(leaf) {
+            return Err(MerkleError::PaddingLeaf);
+        }
+        if leaf.len() > MAX_LEAF_LEN {
+            return Err(MerkleError::LeafTooLong {
+                len: leaf.len(),
+                max: MAX_LEAF_LEN,
+            });
+        }
+        let mut leaves = self.trees.get(name).ok_or(MerkleError::LeafNotFound)?.leaves.clone();
+        if leaves.len() == self.options.capacity() {
+            return Err(MerkleError::CapacityExceeded {
+                len: leaves.len() + 1,
+                capacity: self.options.capacity(),
+            });
+        }
+        leaves.push(leaf.to_vec());
+        let tree = self
+            .build_tree(&leaves)
+            .with_context(|| ErrorContext::new("insert").tree(name).index(leaves.len() - 1))?;
+        self.trees.insert(name.to_string(), RegionTree { leaves, tree });
+        self.epoch += 1;
+        Ok(())
+    }
+
+    pub fn root_set(&self) -> RootSet {
+        RootSet {
+            epoch: self.epoch,
+            roots: self
+                .trees
+                .iter()
+                .map(|(name, region)| (name.clone(), region.tree.root()))
+                .collect(),
+        }
+    }
+
+    /// Finds `leaf` and proves its membership, naming the tree it is in.
+    pub fn prove(&self, leaf: &[u8]) -> Result<ForestProof, MerkleError> {
+        for (name, region) in &self.trees {
+            if let Some(index) = region.leaves.iter().position(|l| l == leaf) {
+                return Ok(ForestProof {
+                    tree: name.clone(),
+                    epoch: self.epoch,
+                    leaf: leaf.to_vec(),
+                    path: generate_proof(&region.tree, LeafIndex::checked_new(index as u64, leaf_count(&region.tree))?)?,
+                });
+            }
+        }
+        Err(MerkleError::LeafNotFound)
+    }
+
+    /// Generates the keys for private proofs over a forest of the current size.
+    pub fn setup<R: RngCore + CryptoRng>(
+        &self,
+        leaf_len: usize,
+        rng: &mut R,
+    ) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+        snark::setup(
+            OneOfRootsCircuit::blank(self.params.clone(), self.trees.len(), self.tree_height(), leaf_len),
+            rng,
+        )
+    }
+
+    /// Proves membership of `leaf` without revealing its tree.
+    pub fn prove_private<R: RngCore + CryptoRng>(
+        &self,
+        pk: &ProvingKey,
+        leaf: &[u8],
+        rng: &mut R,
+    ) -> Result<PrivateForestProof, MerkleError> {
+        let proof = self.prove(leaf)?;
+        let roots = self.root_set();
+        let circuit = OneOfRootsCircuit {
+            params: self.params.clone(),
+            epoch: roots.epoch,
+            roots: roots.roots.into_iter().map(|(_, root)| root).collect(),
+            leaf: leaf.to_vec(),
+            auth_path: Some(proof.path),
+        };
+        Ok(PrivateForestProof {
+            epoch: self.epoch,
+            leaf: leaf.to_vec(),
+            proof: snark::prove(pk, circuit, rng)?,
+        })
+    }
+}
+
+impl RootSet {
+    /// Checks `proof` against the root of the tree it names, at this epoch.
+    pub fn verify(&self, params: &MerkleParams, proof: &ForestProof) -> Result<bool, MerkleError> {
+        if proof.epoch != self.epoch || is_padding(&proof.leaf) {
+            return Ok(false);
+        }
+        let root = match self.roots.iter().find(|(name, _)| *name == proof.tree) {
+            Some((_, root)) => root,
+            None => return Ok(false),
+        };
+        params.verify_path(&proof.path, root, &proof.leaf)
+    }
+
+    /// Checks a private proof against the whole root set, at this epoch.
+    pub fn verify_private(&self, vk: &VerifyingKey, proof: &PrivateForestProof) -> Result<bool, MerkleError> {
+        if proof.epoch != self.epoch || is_padding(&proof.leaf) {
+            return Ok(false);
+        }
+        let roots: Vec<Root> = self.roots.iter().map(|(_, root)| *root).collect();
+        let inputs = OneOfRootsCircuit::public_inputs(self.epoch, &roots, &proof.leaf);
+        snark::verify(vk, &inputs, &proof.proof)
+    }
+}
+
+/// Proves "`leaf` is in the tree under one of `roots`".
+pub struct OneOfRootsCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub epoch: u64,
+    pub roots: Vec<Root>,
+    pub leaf: Vec<u8>,
+
+    // This is the private witness to the circuit.
+    pub auth_path: Option<SimplePath>,
+}
+
+impl OneOfRootsCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, num_roots: usize, tree_height: usize, leaf_len: usize) -> Self {
+        OneOfRootsCircuit {
+            params,
+            epoch: 0,
+            roots: vec![Default::default(); num_roots],
+            leaf: vec![0u8; leaf_len],
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(epoch: u64, roots: &[Root], leaf: &[u8]) -> Vec<ConstraintF> {
+        let mut inputs = vec![ConstraintF::from(epoch)];
+        inputs.extend_from_slice(roots);
+        inputs.extend(byte_inputs(leaf));
+        inputs
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for OneOfRootsCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs. The epoch isn't used in any
+        // constraint, it only ties the proof to one root set.
+        let _epoch = FpVar::new_input(ark_relations::ns!(cs, "epoch_var"), || {
+            Ok(ConstraintF::from(self.epoch))
+        })?;
+        let roots = Vec::<RootVar>::new_input(ark_relations::ns!(cs, "roots_var"), || Ok(&self.roots[..]))?;
+        let leaf = UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_var"), &self.leaf)?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the path, which also hides which tree it belongs to:
+        let path = IndexedPathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let computed_root = path.calculate_root(&leaf_crh_params, &two_to_one_crh_params, &leaf)?;
+        let mut is_member = Boolean::FALSE;
+        for root in &roots {
+            is_member = is_member.or(&computed_root.is_eq(root)?)?;
+        }
+        is_member.enforce_equal(&Boolean::TRUE)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn test_forest() -> Forest {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut forest = Forest::new(params, 4);
+    for region in 1..=5u8 {
+        let name = format!("region-{}", region);
+        forest.add_tree(&name).unwrap();
+        forest.insert(&name, &[region; 32]).unwrap();
+        forest.insert(&name, &[region + 100; 32]).unwrap();
+    }
+    forest
+}
+
+#[test]
+fn test_forest_user_in_third_of_five_trees() {
+    let mut rng = ark_std::test_rng();
+    let forest = test_forest();
+    let roots = forest.root_set();
+    assert_eq!(roots.roots.len(), 5);
+
+    let user = [3u8; 32];
+    let proof = forest.prove(&user).unwrap();
+    assert_eq!(proof.tree, "region-3");
+    assert!(roots.verify(&forest.params, &proof).unwrap());
+
+    // naming another region fails
+    let mut wrong_tree = proof.clone();
+    wrong_tree.tree = "region-4".to_string();
+    assert!(!roots.verify(&forest.params, &wrong_tree).unwrap());
+
+    let (pk, vk) = forest.setup(user.len(), &mut rng).unwrap();
+    let private = forest.prove_private(&pk, &user, &mut rng).unwrap();
+    assert!(roots.verify_private(&vk, &private).unwrap());
+}
+
+#[test]
+fn test_forest_user_in_no_tree() {
+    let forest = test_forest();
+    let stranger = [42u8; 32];
+    assert!(matches!(forest.prove(&stranger), Err(MerkleError::LeafNotFound)));
+
+    // borrowing a member's path doesn't help either
+    let mut proof = forest.prove(&[3u8; 32]).unwrap();
+    proof.leaf = stranger.to_vec();
+    assert!(!forest.root_set().verify(&forest.params, &proof).unwrap());
+
+    let roots: Vec<Root> = forest.root_set().roots.into_iter().map(|(_, root)| root).collect();
+    let circuit = OneOfRootsCircuit {
+        params: forest.params.clone(),
+        epoch: forest.epoch(),
+        roots,
+        leaf: stranger.to_vec(),
+        auth_path: Some(proof.path),
+    };
+    let cs = ark_relations::r1cs::ConstraintSystem::new_ref();
+    circuit.generate_constraints(cs.clone()).unwrap();
+    assert!(!cs.is_satisfied().unwrap());
+}
+
+#[test]
+fn test_forest_rotation_invalidates_old_proofs() {
+    let mut rng = ark_std::test_rng();
+    let mut forest = test_forest();
+    let user = [3u8; 32];
+    let (pk, vk) = forest.setup(user.len(), &mut rng).unwrap();
+    let proof = forest.prove(&user).unwrap();
+    let private = forest.prove_private(&pk, &user, &mut rng).unwrap();
+
+    forest.insert("region-1", &[7u8; 32]).unwrap();
+    let roots = forest.root_set();
+    assert_eq!(roots.epoch, proof.epoch + 1);
+    assert!(!roots.verify(&forest.params, &proof).unwrap());
+    assert!(!roots.verify_private(&vk, &private).unwrap());
+
+    // even with the epoch patched up, the private proof was made for the old roots
+    let mut patched = private.clone();
+    patched.epoch = roots.epoch;
+    assert!(!roots.verify_private(&vk, &patched).unwrap());
+
+    // fresh proofs verify again
+    assert!(roots.verify(&forest.params, &forest.prove(&user).unwrap()).unwrap());
+    let private = forest.prove_private(&pk, &user, &mut rng).unwrap();
+    assert!(roots.verify_private(&vk, &private).unwrap());
+}
+
+#[test]
+fn test_forest_padding_is_not_a_member() {
+    let mut rng = ark_std::test_rng();
+    let mut forest = test_forest();
+    let roots = forest.root_set();
+    // region-3 holds two leaves, so its slots 2 and 3 are padding, and the
+    // path of slot 2 is the sibling padding and the hash of the two members
+    let member = forest.prove(&[3u8; 32]).unwrap();
+    let params = forest.params.clone();
+    let padding_path = SimplePath {
+        leaf_sibling_hash: params.hash_leaf(&[]).unwrap(),
+        auth_path: vec![params
+            .hash_children(&params.hash_leaf(&[3u8; 32]).unwrap(), &member.path.leaf_sibling_hash)
+            .unwrap()],
+        leaf_index: 2,
+    };
+    let root = roots.roots.iter().find(|(name, _)| name == "region-3").unwrap().1;
+    for leaf in [vec![], vec![0u8; 32]].iter() {
+        assert!(params.verify_path(&padding_path, &root, leaf).unwrap());
+        let forged = ForestProof {
+            tree: "region-3".to_string(),
+            epoch: roots.epoch,
+            leaf: leaf.clone(),
+            path: padding_path.clone(),
+        };
+        assert!(!roots.verify(&params, &forged).unwrap());
+    }
+
+    // nor can an all-zero leaf be registered, or pass privately
+    assert!(matches!(forest.insert("region-3", &[0u8; 32]), Err(MerkleError::PaddingLeaf)));
+    let (pk, vk) = forest.setup(32, &mut rng).unwrap();
+    let circuit = OneOfRootsCircuit {
+        params: params.clone(),
+        epoch: roots.epoch,
+        roots: roots.roots.iter().map(|(_, root)| *root).collect(),
+        leaf: vec![0u8; 32],
+        auth_path: Some(padding_path),
+    };
+    let forged = PrivateForestProof {
+        epoch: roots.epoch,
+        leaf: vec![0u8; 32],
+        proof: snark::prove(&pk, circuit, &mut rng).unwrap(),
+    };
+    assert!(!roots.verify_private(&vk, &forged).unwrap());
+}
@@ -0,0 +1,181 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use ark_crypto_primitives::merkle_tree::{Config, MerkleTree};
+use ark_std::rand::Rng;
+use ark_std::vec::Vec;
+
+// Random and hand-picked leaf sets for tests and benches, so they all draw
+// from the same inputs instead of each making up its own.
+//
+// Everything returns raw leaves, a power of two of them, ready for
+// `TreeParams::build_tree`; nothing here pads or hashes. Where a case or a
+// scenario needs padding, it is the empty leaf, which is distinct from every
+// leaf these generators draw except where a case says otherwise.
+
+/// `len` random bytes.
+pub fn random_leaf<R: Rng>(rng: &mut R, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+/// `n` random leaves of `len` bytes each.
+pub fn random_leaves<R: Rng>(rng: &mut R, n: usize, len: usize) -> Vec<Vec<u8>> {
+    (0..n).map(|_| random_leaf(rng, len)).collect()
+}
+
+/// A tree over `n` random 32-byte leaves, with the leaves; `n` must be a
+/// power of two.
+pub fn random_tree<P: Config, R: Rng>(
+    rng: &mut R,
+    n: usize,
+    params: &TreeParams<P>,
+) -> Result<(Vec<Vec<u8>>, MerkleTree<P>), MerkleError> {
+    let leaves = random_leaves(rng, n, 32);
+    let tree = params.build_tree(&leaves)?;
+    Ok((leaves, tree))
+}
+
+/// A leaf set that has broken, or could break, some tree code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdversarialCase {
+    pub name: &'static str,
+    pub leaves: Vec<Vec<u8>>,
+}
+
+/// The leaf sets every tree implementation should handle: leaves of the
+/// longest length the leaf hash takes, repeated leaves, leaves of zeros that
+/// differ only in length, the empty leaf, and a single leaf padded to the
+/// smallest tree.
+pub fn adversarial_cases() -> Vec<AdversarialCase> {
+    let case = |name, leaves| AdversarialCase { name, leaves };
+    vec![
+        case("max-length leaves", (0..4u8).map(|i| vec![i; MAX_LEAF_LEN]).collect()),
+        case("duplicate leaves", vec![vec![7u8; 32]; 4]),
+        case("all-zero leaves", vec![vec![0u8; 32]; 8]),
+        case("zero leaves of different lengths", (0..4).map(|len| vec![0u8; len]).collect()),
+        case("empty leaves", vec![Vec::new(); 2]),
+        case("single leaf", vec![vec![1u8; 32], Vec::new()]),
+        case("duplicate halves", {
+            let half = (0..4u8).map(|i| vec![i; 32]).collect::<Vec<_>>();
+            half.iter().chain(half.iter()).cloned().collect()
+        }),
+    ]
+}
+
+/// Which leaves of a scenario are drawn; the rest are the empty leaf.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    /// Every leaf.
+    Full,
+    /// The first this many, like a tree that has been appended to.
+    Prefix(usize),
+    /// This many, at random positions, like a sparse tree.
+    Scattered(usize),
+}
+
+/// The length of every drawn leaf.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeafLen {
+    Fixed(usize),
+    /// Uniform from 0 up to and including this.
+    UpTo(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Spec {
+    /// The tree has `2^height` leaves.
+    pub height: u32,
+    pub shape: Shape,
+    pub leaf_len: LeafLen,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scenario {
+    pub leaves: Vec<Vec<u8>>,
+    /// The positions of the drawn leaves, in increasing order.
+    pub filled: Vec<usize>,
+}
+
+impl Scenario {
+    pub fn tree<P: Config>(&self, params: &TreeParams<P>) -> Result<MerkleTree<P>, MerkleError> {
+        params.build_tree(&self.leaves)
+    }
+}
+
+/// Leaves shaped as `spec` says. Drawn leaves are never empty, so they can't
+/// be mistaken for padding.
+pub fn scenario<R: Rng>(rng: &mut R, spec: Spec) -> Scenario {
+    let n = 1usize << spec.height;
+    let mut filled: Vec<usize> = match spec.shape {
+        Shape::Full => (0..n).collect(),
+        Shape::Prefix(k) => (0..k.min(n)).collect(),
+        Shape::Scattered(k) => {
+            let mut positions: Vec<usize> = (0..n).collect();
+            // a partial Fisher-Yates shuffle picks the first k
+            let k = k.min(n);
+            for i in 0..k {
+                let j = rng.gen_range(i, n);
+                positions.swap(i, j);
+            }
+            positions.truncate(k);
+            positions
+        }
+    };
+    filled.sort_unstable();
+
+    let mut leaves = vec![Vec::new(); n];
+    for &i in &filled {
+        let len = match spec.leaf_len {
+            LeafLen::Fixed(len) => len,
+            LeafLen::UpTo(max) => rng.gen_range(0, max + 1),
+        };
+        let mut leaf = random_leaf(rng, len);
+        if leaf.is_empty() {
+            leaf.push(rng.gen());
+        }
+        leaves[i] = leaf;
+    }
+    Scenario { leaves, filled }
+}
+
+#[test]
+fn test_adversarial_cases_build() {
+    let params = crate::mock::MockParams::setup(&mut ark_std::test_rng()).unwrap();
+    for case in adversarial_cases() {
+        assert!(case.leaves.len().is_power_of_two() && case.leaves.len() > 1, "{}", case.name);
+        let tree = params.build_tree(&case.leaves).unwrap();
+        for (index, leaf) in case.leaves.iter().enumerate() {
+            let path = tree.generate_proof(index).unwrap();
+            assert!(params.verify_path(&path, &tree.root(), leaf).unwrap(), "{}", case.name);
+        }
+    }
+}
+
+#[test]
+fn test_scenario_shapes() {
+    let mut rng = ark_std::test_rng();
+    let spec = |shape| Spec {
+        height: 4,
+        shape,
+        leaf_len: LeafLen::UpTo(8),
+    };
+
+    let full = scenario(&mut rng, spec(Shape::Full));
+    assert_eq!(full.filled, (0..16).collect::<Vec<_>>());
+    assert!(full.leaves.iter().all(|leaf| !leaf.is_empty() && leaf.len() <= 8));
+
+    let prefix = scenario(&mut rng, spec(Shape::Prefix(5)));
+    assert_eq!(prefix.filled, vec![0, 1, 2, 3, 4]);
+    assert!(prefix.leaves[5..].iter().all(Vec::is_empty));
+
+    let scattered = scenario(&mut rng, spec(Shape::Scattered(3)));
+    assert_eq!(scattered.filled.len(), 3);
+    assert_eq!(scattered.leaves.iter().filter(|leaf| !leaf.is_empty()).count(), 3);
+    for &i in &scattered.filled {
+        assert!(!scattered.leaves[i].is_empty());
+    }
+
+    let params = crate::mock::MockParams::setup(&mut rng).unwrap();
+    let (leaves, tree) = random_tree(&mut rng, 8, &params).unwrap();
+    assert_eq!(leaves.len(), 8);
+    assert_eq!(leaf_count(&tree), 8);
+}
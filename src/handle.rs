@@ -0,0 +1,217 @@
+use crate::backend::MerkleBackend;
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::leaf::Leaf;
+use crate::level_tree::LevelTree;
+use crate::{Root, SimplePath};
+use std::sync::RwLock;
+
+// A growable tree that always hands out its root together with the epoch it
+// belongs to.
+//
+// Callers that cache a root themselves tend to keep using it after the tree
+// has moved on, and then wonder why fresh proofs don't verify against it.
+// `TreeHandle` keeps the current `(Root, Epoch)` pair next to the tree, behind
+// one lock: every mutation replaces the pair in the same critical section
+// that changes the tree and bumps the epoch, and every read copies the pair
+// whole. A root read with epoch e is the root after exactly e mutations, and a
+// proof comes with the root and epoch it was taken at.
+//
+// Leaves past `len()` are empty padding up to the next power of two. Appends
+// fill the padding in place; an append past it rebuilds the tree at twice
+// the size.
+
+/// The number of mutations a `TreeHandle` has seen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Epoch(u64);
+
+impl Epoch {
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+struct State {
+    len: usize,
+    tree: LevelTree,
+    current: (Root, Epoch),
+}
+
+impl State {
+    // rebuilds the tree around the first `len` leaves, padded to fit `len + extra`
+    fn grow(&self, extra: usize) -> Result<LevelTree, MerkleError> {
+        let capacity = (self.len + extra).next_power_of_two().max(2);
+        let mut leaves: Vec<Leaf> = (0..self.len).map(|i| self.tree.leaf(i).unwrap().clone()).collect();
+        leaves.resize(capacity, Leaf::default());
+        LevelTree::new(self.tree.params().clone(), leaves)
+    }
+
+    fn commit(&mut self, tree: LevelTree, len: usize) -> (Root, Epoch) {
+        self.tree = tree;
+        self.len = len;
+        let Epoch(epoch) = self.current.1;
+        self.current = (self.tree.root(), Epoch(epoch + 1));
+        self.current
+    }
+}
+
+pub struct TreeHandle {
+    state: RwLock<State>,
+}
+
+impl TreeHandle {
+    /// An empty tree, at epoch 0.
+    pub fn new(params: MerkleParams) -> Result<Self, MerkleError> {
+        let tree = LevelTree::with_capacity(params, 0, Leaf::default())?;
+        let current = (tree.root(), Epoch::default());
+        Ok(TreeHandle {
+            state: RwLock::new(State { len: 0, tree, current }),
+        })
+    }
+
+    /// The current root and the epoch it belongs to.
+    pub fn root_with_epoch(&self) -> (Root, Epoch) {
+        self.state.read().unwrap().current
+    }
+
+    pub fn epoch(&self) -> Epoch {
+        self.root_with_epoch().1
+    }
+
+    /// The number of leaves appended, without the padding.
+    pub fn len(&self) -> usize {
+        self.state.read().unwrap().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The path of the leaf at `index`, with the root and epoch it is for.
+    pub fn prove(&self, index: usize) -> Result<(SimplePath, Root, Epoch), MerkleError> {
+        let state = self.state.read().unwrap();
+        let index = LeafIndex::checked_new(index as u64, state.len as u64)?;
+        let (root, epoch) = state.current;
+        Ok((state.tree.prove(index.as_usize())?, root, epoch))
+    }
+
+    /// Replaces the leaf at `index`.
+    pub fn update_leaf(&self, index: usize, leaf: impl Into<Leaf>) -> Result<(Root, Epoch), MerkleError> {
+        let mut state = self.state.write().unwrap();
+        LeafIndex::checked_new(index as u64, state.len as u64)?;
+        let mut tree = state.tree.clone();
+        tree.update(index, leaf.into())?;
+        let len = state.len;
+        Ok(state.commit(tree, len))
+    }
+
+    /// Appends `leaf`, returning its index with the new root and epoch.
+    pub fn append(&self, leaf: impl Into<Leaf>) -> Result<(usize, Root, Epoch), MerkleError> {
+        let mut state = self.state.write().unwrap();
+        let index = state.len;
+        let (root, epoch) = Self::push(&mut state, vec![leaf.into()])?;
+        Ok((index, root, epoch))
+    }
+
+    /// Appends all of `leaves` as one mutation: either all of them are
+    /// appended, under a single new epoch, or none is.
+    pub fn extend<L: Into<Leaf>>(&self, leaves: impl IntoIterator<Item = L>) -> Result<(Root, Epoch), MerkleError> {
+        let mut state = self.state.write().unwrap();
+        Self::push(&mut state, leaves.into_iter().map(Into::into).collect())
+    }
+
+    fn push(state: &mut State, leaves: Vec<Leaf>) -> Result<(Root, Epoch), MerkleError> {
+        for leaf in &leaves {
+            leaf.check()?;
+        }
+        let mut tree = if state.len + leaves.len() > state.tree.leaf_count() as usize {
+            state.grow(leaves.len())?
+        } else {
+            state.tree.clone()
+        };
+        for (i, leaf) in leaves.iter().enumerate() {
+            tree.update(state.len + i, leaf.clone())?;
+        }
+        let len = state.len + leaves.len();
+        Ok(state.commit(tree, len))
+    }
+}
+
+#[test]
+fn test_tree_handle_grows_and_proves() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let handle = TreeHandle::new(params.clone()).unwrap();
+    assert_eq!(handle.epoch(), Epoch(0));
+
+    assert_eq!(handle.append(vec![0u8]).unwrap().0, 0);
+    handle.extend((1u8..5).map(|i| vec![i])).unwrap();
+    assert_eq!(handle.len(), 5);
+    assert_eq!(handle.epoch(), Epoch(2));
+    handle.update_leaf(2, vec![9u8]).unwrap();
+
+    let mut leaves: Vec<Vec<u8>> = vec![vec![0], vec![1], vec![9], vec![3], vec![4]];
+    leaves.resize(8, Vec::new());
+    let (root, epoch) = handle.root_with_epoch();
+    assert_eq!(root, params.build_tree(&leaves).unwrap().root());
+    assert_eq!(epoch, Epoch(3));
+
+    let (path, proof_root, proof_epoch) = handle.prove(2).unwrap();
+    assert_eq!((proof_root, proof_epoch), (root, epoch));
+    assert!(params.verify_path(&path, &root, vec![9u8]).unwrap());
+    assert!(handle.prove(5).is_err());
+
+    // failed mutations don't move the epoch
+    assert!(handle.update_leaf(5, vec![0u8]).is_err());
+    assert!(handle.extend(vec![vec![1u8], vec![0u8; MAX_LEAF_LEN + 1]]).is_err());
+    assert_eq!(handle.root_with_epoch(), (root, epoch));
+    assert_eq!(handle.len(), 5);
+}
+
+#[test]
+fn test_tree_handle_never_pairs_a_stale_root_with_a_newer_epoch() {
+    use std::sync::Arc;
+    use std::thread;
+
+    const UPDATES: usize = 16;
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let update = |i: usize| (i % 4, vec![i as u8 + 1]);
+
+    // the root after every epoch, worked out on a separate tree
+    let mut reference = LevelTree::with_capacity(params.clone(), 4, Leaf::default()).unwrap();
+    for i in 0..4 {
+        reference.update(i, Leaf::from(vec![0u8])).unwrap();
+    }
+    let mut roots = vec![reference.root()];
+    for i in 0..UPDATES {
+        let (index, leaf) = update(i);
+        roots.push(reference.update(index, Leaf::from(leaf)).unwrap());
+    }
+
+    let handle = Arc::new(TreeHandle::new(params).unwrap());
+    handle.extend(vec![vec![0u8]; 4]).unwrap();
+    let first = handle.epoch().get();
+
+    let readers: Vec<_> = (0..3)
+        .map(|_| {
+            let handle = Arc::clone(&handle);
+            let roots = roots.clone();
+            thread::spawn(move || {
+                let mut last = 0;
+                while last < first + UPDATES as u64 {
+                    let (root, epoch) = handle.root_with_epoch();
+                    assert!(epoch.get() >= last, "epochs went backwards");
+                    assert_eq!(root, roots[(epoch.get() - first) as usize], "stale root at epoch {}", epoch.get());
+                    last = epoch.get();
+                }
+            })
+        })
+        .collect();
+    for i in 0..UPDATES {
+        let (index, leaf) = update(i);
+        handle.update_leaf(index, leaf).unwrap();
+    }
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::{blank_path, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+#[cfg(feature = "constraints")]
+use crate::{Root, SimplePath};
+use ark_crypto_primitives::commitment::pedersen::{Commitment, Randomness};
+use ark_crypto_primitives::commitment::CommitmentScheme;
+use ark_ed_on_bls12_381::{EdwardsProjective, Fr};
+use ark_ff::UniformRand;
+use ark_std::rand::{CryptoRng, RngCore};
+#[cfg(feature = "constraints")]
+use ark_std::vec::Vec;
+#[cfg(feature = "constraints")]
+use ark_crypto_primitives::commitment::{pedersen::constraints::CommGadget, CommitmentGadget};
+#[cfg(feature = "constraints")]
+use ark_ed_on_bls12_381::constraints::EdwardsVar;
+#[cfg(feature = "constraints")]
+use ark_ff::ToConstraintField;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::prelude::*;
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+// Membership of a hidden leaf.
+//
+// The membership circuit takes its leaf as a public input, so a proof tells
+// the verifier which member made it. `HiddenLeafCircuit` takes a Pedersen
+// commitment to the leaf instead, with the leaf and the commitment randomness
+// as witnesses: it proves that whatever the commitment opens to is in the
+// tree, and nothing else about it. The commitment is hiding, so it reveals
+// nothing of the leaf without the randomness, and binding, so it can't be
+// opened to another leaf later; a prover who wants the verifier to learn the
+// leaf after all hands over the randomness, and `open_leaf` checks it.
+//
+// `commit_leaf` makes the commitment natively, with fresh randomness each
+// time, so two commitments to the same leaf don't look alike. The length of
+// the leaf is part of the shape of the circuit, as for `MTreeVerification`.
+
+/// Pedersen commitments to leaves of up to `MAX_LEAF_LEN` bytes.
+pub type LeafCommitment = Commitment<EdwardsProjective, LeafCommitWindow>;
+pub type LeafCommitmentParams = <LeafCommitment as CommitmentScheme>::Parameters;
+/// A commitment to a leaf, a Jubjub point.
+pub type LeafCommitmentOutput = <LeafCommitment as CommitmentScheme>::Output;
+/// The blinding a commitment is opened with.
+pub type LeafRandomness = Randomness<EdwardsProjective>;
+#[cfg(feature = "constraints")]
+pub type LeafCommitmentGadget = CommGadget<EdwardsProjective, EdwardsVar, LeafCommitWindow>;
+#[cfg(feature = "constraints")]
+type CommitmentVar = <LeafCommitmentGadget as CommitmentGadget<LeafCommitment, ConstraintF>>::OutputVar;
+#[cfg(feature = "constraints")]
+type CommitmentParamsVar = <LeafCommitmentGadget as CommitmentGadget<LeafCommitment, ConstraintF>>::ParametersVar;
+#[cfg(feature = "constraints")]
+type RandomnessVar = <LeafCommitmentGadget as CommitmentGadget<LeafCommitment, ConstraintF>>::RandomnessVar;
+
+// `WINDOW_SIZE * NUM_WINDOWS` = 1024 bits = `MAX_LEAF_LEN` bytes, like the leaf hash.
+pub type LeafCommitWindow = Windows<4, 256>;
+
+/// Samples fresh commitment parameters. Like the hash parameters, they must
+/// be shared by whoever commits and whoever verifies.
+pub fn commitment_setup<R: RngCore + CryptoRng>(rng: &mut R) -> Result<LeafCommitmentParams, MerkleError> {
+    Ok(LeafCommitment::setup(rng)?)
+}
+
+/// Commits to `leaf` with fresh randomness, which opens the commitment.
+pub fn commit_leaf<R: RngCore + CryptoRng>(
+    comm_params: &LeafCommitmentParams,
+    leaf: &[u8],
+    rng: &mut R,
+) -> Result<(LeafCommitmentOutput, LeafRandomness), MerkleError> {
+    if leaf.len() > MAX_LEAF_LEN {
+        return Err(MerkleError::LeafTooLong {
+            len: leaf.len(),
+            max: MAX_LEAF_LEN,
+        });
+    }
+    let randomness = Randomness(Fr::rand(rng));
+    Ok((LeafCommitment::commit(comm_params, leaf, &randomness)?, randomness))
+}
+
+/// Whether `commitment` opens to `leaf` with `randomness`.
+pub fn open_leaf(
+    comm_params: &LeafCommitmentParams,
+    commitment: &LeafCommitmentOutput,
+    leaf: &[u8],
+    randomness: &LeafRandomness,
+) -> Result<bool, MerkleError> {
+    if leaf.len() > MAX_LEAF_LEN {
+        return Ok(false);
+    }
+    Ok(LeafCommitment::commit(comm_params, leaf, randomness)? == *commitment)
+}
+
+/// Proves that the leaf `commitment` opens to is in the tree with root
+/// `root`, without revealing the leaf.
+#[cfg(feature = "constraints")]
+pub struct HiddenLeafCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub comm_params: LeafCommitmentParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub commitment: LeafCommitmentOutput,
+
+    // These are the private witnesses to the circuit. The length of the leaf
+    // is part of the shape of the circuit.
+    pub leaf: Vec<u8>,
+    pub randomness: Option<LeafRandomness>,
+    pub auth_path: Option<SimplePath>,
+}
+
+#[cfg(feature = "constraints")]
+impl HiddenLeafCircuit {
+    /// The circuit proving that `leaf`, which `commitment` opens to with
+    /// `randomness`, opens `auth_path` against `root`.
+    pub fn new(
+        params: &MerkleParams,
+        comm_params: &LeafCommitmentParams,
+        root: Root,
+        commitment: LeafCommitmentOutput,
+        leaf: &[u8],
+        randomness: LeafRandomness,
+        auth_path: SimplePath,
+    ) -> Self {
+        HiddenLeafCircuit {
+            params: params.clone(),
+            comm_params: comm_params.clone(),
+            root,
+            commitment,
+            leaf: leaf.to_vec(),
+            randomness: Some(randomness),
+            auth_path: Some(auth_path),
+        }
+    }
+
+    /// A circuit of the right shape for key generation: a tree of
+    /// `tree_height` levels and a leaf of `leaf_len` bytes.
+    pub fn blank(
+        params: &MerkleParams,
+        comm_params: &LeafCommitmentParams,
+        tree_height: usize,
+        leaf_len: usize,
+    ) -> Self {
+        HiddenLeafCircuit {
+            params: params.clone(),
+            comm_params: comm_params.clone(),
+            root: Default::default(),
+            commitment: Default::default(),
+            leaf: vec![0u8; leaf_len],
+            randomness: None,
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit: the root, then the coordinates of
+    /// the commitment.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        let mut inputs = vec![self.root];
+        inputs.extend(self.commitment.to_field_elements().unwrap());
+        inputs
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for HiddenLeafCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        if self.leaf.len() > MAX_LEAF_LEN {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let commitment = CommitmentVar::new_input(ark_relations::ns!(cs, "commitment_var"), || Ok(&self.commitment))?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+        let comm_params = CommitmentParamsVar::new_constant(cs.clone(), &self.comm_params)?;
+
+        // Finally, the opening of the commitment and the path as private witnesses:
+        let leaf = UInt8::new_witness_vec(ark_relations::ns!(cs, "leaf_var"), &self.leaf)?;
+        let randomness = RandomnessVar::new_witness(ark_relations::ns!(cs, "randomness_var"), || {
+            self.randomness.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        {
+            let _commitment = ark_relations::ns!(cs, "commitment");
+            LeafCommitmentGadget::commit(&comm_params, &leaf, &randomness)?.enforce_equal(&commitment)?;
+        }
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaf.as_slice())?
+            .enforce_equal(&Boolean::TRUE)
+    }
+}
+
+#[test]
+fn test_commit_leaf_hides_and_binds() {
+    let mut rng = ark_std::test_rng();
+    let comm_params = commitment_setup(&mut rng).unwrap();
+    let (commitment, randomness) = commit_leaf(&comm_params, b"alice", &mut rng).unwrap();
+    assert!(open_leaf(&comm_params, &commitment, b"alice", &randomness).unwrap());
+    assert!(!open_leaf(&comm_params, &commitment, b"alicf", &randomness).unwrap());
+
+    // a second commitment to the same leaf is another point
+    let (again, _) = commit_leaf(&comm_params, b"alice", &mut rng).unwrap();
+    assert_ne!(again, commitment);
+    assert!(matches!(
+        commit_leaf(&comm_params, &[0u8; MAX_LEAF_LEN + 1], &mut rng),
+        Err(MerkleError::LeafTooLong { .. })
+    ));
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_hidden_leaf_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let comm_params = commitment_setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 16]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let (commitment, randomness) = commit_leaf(&comm_params, &leaves[3], &mut rng).unwrap();
+    let circuit = |leaf: &[u8], randomness: LeafRandomness, index: usize| {
+        let path = tree.generate_proof(index).unwrap();
+        HiddenLeafCircuit::new(&params, &comm_params, tree.root(), commitment, leaf, randomness, path)
+    };
+
+    let honest = circuit(&leaves[3], randomness.clone(), 3);
+    let inputs = honest.public_inputs();
+    let report = assert_satisfied_with_report(honest);
+    assert_eq!(inputs.len(), report.instance_variables - 1);
+    // the instance is the root and the commitment, not the leaf bytes
+    assert_eq!(inputs.len(), 3);
+
+    // another member's leaf and path don't open this commitment
+    expect_unsatisfied(circuit(&leaves[4], randomness.clone(), 4), "commitment");
+    // nor does the right leaf with other randomness
+    expect_unsatisfied(circuit(&leaves[3], Randomness(Fr::rand(&mut rng)), 3), "commitment");
+    // and a leaf committed to but not in the tree isn't a member
+    let outsider = vec![9u8; 16];
+    let (commitment, randomness) = commit_leaf(&comm_params, &outsider, &mut rng).unwrap();
+    let path = tree.generate_proof(3).unwrap();
+    expect_unsatisfied(
+        HiddenLeafCircuit::new(&params, &comm_params, tree.root(), commitment, &outsider, randomness, path),
+        "membership",
+    );
+}
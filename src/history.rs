@@ -0,0 +1,365 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::{blank_path, IndexedPathVar, RootVar};
+use crate::inputs::byte_inputs;
+use crate::Root;
+#[cfg(feature = "constraints")]
+use crate::SimplePath;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::fields::fp::FpVar;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::prelude::*;
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::collections::VecDeque;
+use ark_std::vec::Vec;
+
+// Proofs against any of the last few roots.
+//
+// A tree that changes often moves its root on while clients are still
+// proving against the one they fetched. `RootHistory` keeps the last N roots,
+// newest first, and forgets the oldest on every push past N.
+// `MembershipWithRootSetCircuit` takes all N as public inputs and a private
+// choice of one of them, so a verifier holding the history accepts a proof
+// against any root in it without learning which.
+//
+// The choice is N selector bits, exactly one of them set, and the root the
+// path climbs to must equal the sum of selector times root. A history that
+// isn't full yet pads the inputs with `ROOT_SENTINEL`, zero. A path only
+// reaches zero if its Pedersen hash is the identity, which nobody can find an
+// input for, so the padding can be selected but never satisfied.
+//
+// With N = 1 the public inputs are those of `MTreeVerification`, root then
+// leaf, and the circuit accepts exactly what it does.
+
+/// The root the public inputs of a history are padded with.
+pub const ROOT_SENTINEL: Root = ark_ff::Fp384::new(ark_ff::BigInteger384([0; 6]));
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RootHistory {
+    capacity: usize,
+    // newest first
+    roots: VecDeque<Root>,
+}
+
+impl RootHistory {
+    /// An empty history of the last `capacity` roots; `capacity` is at least one.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a root history holds at least one root");
+        RootHistory {
+            capacity,
+            roots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Makes `root` the newest root, and returns the oldest one if it no
+    /// longer fits.
+    pub fn push(&mut self, root: Root) -> Option<Root> {
+        self.roots.push_front(root);
+        if self.roots.len() > self.capacity {
+            self.roots.pop_back()
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, root: &Root) -> bool {
+        self.roots.contains(root)
+    }
+
+    pub fn latest(&self) -> Option<&Root> {
+        self.roots.front()
+    }
+
+    /// The roots, newest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Root> {
+        self.roots.iter()
+    }
+
+    /// Where `root` sits in `padded_roots`, if it is in the history.
+    pub fn position(&self, root: &Root) -> Option<usize> {
+        self.roots.iter().position(|r| r == root)
+    }
+
+    /// The roots, newest first, padded with `ROOT_SENTINEL` to `capacity`:
+    /// the root set `MembershipWithRootSetCircuit` takes.
+    pub fn padded_roots(&self) -> Vec<Root> {
+        let mut roots: Vec<Root> = self.roots.iter().copied().collect();
+        roots.resize(self.capacity, ROOT_SENTINEL);
+        roots
+    }
+
+    /// The public inputs to verify a proof for `leaf` against this history with.
+    pub fn public_inputs(&self, leaf: &[u8]) -> Vec<ConstraintF> {
+        let mut inputs = self.padded_roots();
+        inputs.extend(byte_inputs(leaf));
+        inputs
+    }
+}
+
+/// Proves "`leaf` is in the tree under one of `roots`", for a fixed number of
+/// roots, choosing the root by `root_index`.
+#[cfg(feature = "constraints")]
+pub struct MembershipWithRootSetCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub roots: Vec<Root>,
+    pub leaf: Vec<u8>,
+
+    // This is the private witness to the circuit.
+    pub root_index: Option<usize>,
+    pub auth_path: Option<SimplePath>,
+}
+
+#[cfg(feature = "constraints")]
+impl MembershipWithRootSetCircuit {
+    /// The circuit proving that `leaf` opens `auth_path` against the root of
+    /// `history` that `position` names.
+    pub fn new(
+        params: &MerkleParams,
+        history: &RootHistory,
+        leaf: &[u8],
+        position: usize,
+        auth_path: SimplePath,
+    ) -> Self {
+        MembershipWithRootSetCircuit {
+            params: params.clone(),
+            roots: history.padded_roots(),
+            leaf: leaf.to_vec(),
+            root_index: Some(position),
+            auth_path: Some(auth_path),
+        }
+    }
+
+    /// A circuit of the right shape for key generation.
+    pub fn blank(
+        params: MerkleParams,
+        capacity: usize,
+        tree_height: usize,
+        leaf_len: usize,
+    ) -> Self {
+        MembershipWithRootSetCircuit {
+            params,
+            roots: vec![ROOT_SENTINEL; capacity],
+            leaf: vec![0u8; leaf_len],
+            root_index: Some(0),
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for MembershipWithRootSetCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let roots =
+            Vec::<RootVar>::new_input(ark_relations::ns!(cs, "roots_var"), || Ok(&self.roots[..]))?;
+        let leaf = UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_var"), &self.leaf)?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the path and the choice of root, as private witnesses:
+        let path = IndexedPathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path
+                .as_ref()
+                .ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let root_index = self.root_index;
+        let selectors = (0..roots.len())
+            .map(|i| {
+                Boolean::new_witness(ark_relations::ns!(cs, "selector_var"), || {
+                    root_index
+                        .map(|index| index == i)
+                        .ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        let computed_root = path.calculate_root(&leaf_crh_params, &two_to_one_crh_params, &leaf)?;
+
+        // exactly one root is selected, and it is the one the path climbs to
+        let _selection = ark_relations::ns!(cs, "root_selection");
+        let mut selected = FpVar::zero();
+        let mut chosen_root = FpVar::zero();
+        for (selector, root) in selectors.iter().zip(&roots) {
+            let selector = FpVar::from(selector.clone());
+            chosen_root += &selector * root;
+            selected += selector;
+        }
+        selected.enforce_equal(&FpVar::one())?;
+        computed_root.enforce_equal(&chosen_root)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn rotating_tree(
+    history: &mut RootHistory,
+    rounds: usize,
+) -> (MerkleParams, Vec<crate::SimpleMerkleTree>) {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let mut trees = Vec::new();
+    for round in 0..rounds {
+        // a new leaf at index 3 every round; leaf 1 stays put
+        leaves[3] = vec![100 + round as u8; 8];
+        let tree = params.build_tree(&leaves).unwrap();
+        history.push(tree.root());
+        trees.push(tree);
+    }
+    (params, trees)
+}
+
+#[test]
+fn test_root_history_forgets_the_oldest_root() {
+    let mut history = RootHistory::new(3);
+    let (_, trees) = rotating_tree(&mut history, 3);
+    assert_eq!(history.len(), 3);
+    assert_eq!(history.latest(), Some(&trees[2].root()));
+    assert_eq!(history.position(&trees[0].root()), Some(2));
+
+    assert_eq!(history.push(Root::from(7u64)), Some(trees[0].root()));
+    assert!(!history.contains(&trees[0].root()));
+    assert!(history.contains(&trees[1].root()));
+    assert_eq!(history.iter().count(), 3);
+
+    let partial = RootHistory::new(4);
+    assert!(partial.is_empty());
+    assert_eq!(partial.padded_roots(), vec![ROOT_SENTINEL; 4]);
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_root_set_circuit_accepts_any_recent_root() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let mut history = RootHistory::new(4);
+    let (params, trees) = rotating_tree(&mut history, 3);
+    let leaf = vec![1u8; 8];
+    // one slot is still padding
+    assert_eq!(history.padded_roots()[3], ROOT_SENTINEL);
+
+    for tree in &trees {
+        let position = history.position(&tree.root()).unwrap();
+        let path = tree.generate_proof(1).unwrap();
+        assert_satisfied_with_report(MembershipWithRootSetCircuit::new(
+            &params,
+            &history,
+            &leaf,
+            position,
+            path.clone(),
+        ));
+        // selecting another root, the padding included, doesn't work
+        for other in (0..4).filter(|other| *other != position) {
+            expect_unsatisfied(
+                MembershipWithRootSetCircuit::new(&params, &history, &leaf, other, path.clone()),
+                "root_selection",
+            );
+        }
+    }
+
+    // selecting no root at all doesn't either
+    let mut circuit = MembershipWithRootSetCircuit::new(
+        &params,
+        &history,
+        &leaf,
+        0,
+        trees[2].generate_proof(1).unwrap(),
+    );
+    circuit.root_index = Some(4);
+    expect_unsatisfied(circuit, "root_selection");
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_root_set_circuit_rejects_a_rotated_out_root() {
+    use crate::circuit_report::expect_unsatisfied;
+
+    let mut history = RootHistory::new(2);
+    let (params, trees) = rotating_tree(&mut history, 3);
+    assert!(!history.contains(&trees[0].root()));
+
+    let stale = trees[0].generate_proof(1).unwrap();
+    assert!(params
+        .verify_path(&stale, &trees[0].root(), &[1u8; 8])
+        .unwrap());
+    for position in 0..2 {
+        expect_unsatisfied(
+            MembershipWithRootSetCircuit::new(
+                &params,
+                &history,
+                &[1u8; 8],
+                position,
+                stale.clone(),
+            ),
+            "root_selection",
+        );
+    }
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_root_set_of_one_is_the_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+    use crate::constraints::MTreeVerification;
+    use crate::inputs::PublicInputs;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    let mut history = RootHistory::new(1);
+    let (params, trees) = rotating_tree(&mut history, 2);
+    let (tree, path) = (&trees[1], trees[1].generate_proof(1).unwrap());
+    let leaf = vec![1u8; 8];
+    assert_eq!(
+        history.public_inputs(&leaf),
+        PublicInputs::from_parts(&tree.root(), &leaf).to_field_elements()
+    );
+
+    let circuit = MembershipWithRootSetCircuit::new(&params, &history, &leaf, 0, path.clone());
+    let cs = ConstraintSystem::new_ref();
+    circuit.generate_constraints(cs.clone()).unwrap();
+    assert!(cs.is_satisfied().unwrap());
+    assert_eq!(
+        cs.borrow().unwrap().instance_assignment[1..],
+        history.public_inputs(&leaf)[..]
+    );
+    assert_satisfied_with_report(<MTreeVerification>::new(
+        &params,
+        tree.root(),
+        leaf.clone(),
+        path.clone(),
+    ));
+
+    // and they reject the same leaf
+    let other = vec![2u8; 8];
+    expect_unsatisfied(
+        MembershipWithRootSetCircuit::new(&params, &history, &other, 0, path.clone()),
+        "membership",
+    );
+    expect_unsatisfied(
+        <MTreeVerification>::new(&params, tree.root(), other, path),
+        "membership",
+    );
+}
@@ -0,0 +1,147 @@
+use crate::common::{MerkleParams, TreeOptions};
+use crate::error::MerkleError;
+use crate::Root;
+use ark_std::vec::Vec;
+
+// A fixed-height tree appended to one leaf at a time, keeping only its
+// frontier, in the style of the deposit trees of Tornado Cash and Semaphore.
+//
+// Every slot past the last leaf holds the padding leaf of the tree's
+// `TreeOptions`, so a subtree of nothing but padding has a digest fixed by
+// its height: `zeros[0]` is the padding leaf's hash and `zeros[i + 1]` is
+// `H(zeros[i], zeros[i])`. Appending walks from the new leaf to the root.
+// Where the new node is a left child its right sibling is still padding,
+// `zeros` at that level; where it is a right child its left sibling is the
+// last left child completed at that level, which `frontier` remembers. The
+// state is two digests per level and the number of leaves, whatever the
+// number of leaves, and an append hashes once per level.
+//
+// The root is the root of `build_tree_with_options` over the same leaves and
+// options, so the paths opened in that tree verify against it: the full
+// tree can live off-chain with whoever needs paths, and the contract-like
+// side only ever appends.
+
+pub struct IncrementalMerkleTree {
+    params: MerkleParams,
+    options: TreeOptions,
+    // zeros[i]: the digest of a subtree of 2^i padding leaves, up to the
+    // empty tree's root
+    zeros: Vec<Root>,
+    // frontier[i]: the last left child completed at level i
+    frontier: Vec<Root>,
+    len: usize,
+    root: Root,
+}
+
+impl IncrementalMerkleTree {
+    /// The empty tree of the height and padding of `options`.
+    pub fn new(params: MerkleParams, options: TreeOptions) -> Result<Self, MerkleError> {
+        let levels = options.height - 1;
+        let mut zeros = Vec::with_capacity(levels + 1);
+        zeros.push(params.hash_leaf(options.padding.leaf().as_ref())?);
+        for level in 0..levels {
+            zeros.push(params.hash_children(&zeros[level], &zeros[level])?);
+        }
+        Ok(IncrementalMerkleTree {
+            frontier: zeros[..levels].to_vec(),
+            root: zeros[levels],
+            zeros,
+            params,
+            options,
+            len: 0,
+        })
+    }
+
+    pub fn params(&self) -> &MerkleParams {
+        &self.params
+    }
+
+    pub fn options(&self) -> &TreeOptions {
+        &self.options
+    }
+
+    /// The number of leaves appended so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The height of the tree, leaves and root included, like `MerkleTree::height`.
+    pub fn height(&self) -> usize {
+        self.options.height
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.options.capacity()
+    }
+
+    pub fn root(&self) -> Root {
+        self.root
+    }
+
+    /// The root of the tree with no leaves at all.
+    pub fn empty_root(&self) -> Root {
+        self.zeros[self.zeros.len() - 1]
+    }
+
+    /// Appends `leaf`, returning its index and the new root. Fails with
+    /// `CapacityExceeded` once the tree is full, leaving it as it was.
+    pub fn insert(&mut self, leaf: &[u8]) -> Result<(usize, Root), MerkleError> {
+        if self.len == self.capacity() {
+            return Err(MerkleError::CapacityExceeded {
+                len: self.len + 1,
+                capacity: self.capacity(),
+            });
+        }
+        let mut current = self.params.hash_leaf(leaf)?;
+        let mut frontier = self.frontier.clone();
+        let mut index = self.len;
+        for (level, node) in frontier.iter_mut().enumerate() {
+            current = if index % 2 == 0 {
+                *node = current;
+                self.params.hash_children(&current, &self.zeros[level])?
+            } else {
+                self.params.hash_children(node, &current)?
+            };
+            index /= 2;
+        }
+        self.frontier = frontier;
+        self.root = current;
+        self.len += 1;
+        Ok((self.len - 1, current))
+    }
+}
+
+#[test]
+fn test_incremental_tree_matches_the_full_tree() {
+    use crate::common::PaddingStrategy;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let options = TreeOptions::new(4).padding(PaddingStrategy::Zeros(8));
+    let leaves: Vec<Vec<u8>> = (1u8..=8).map(|i| vec![i; 8]).collect();
+    let mut tree = IncrementalMerkleTree::new(params.clone(), options.clone()).unwrap();
+    let none: [&[u8]; 0] = [];
+    assert_eq!(tree.root(), params.build_tree_with_options(none, &options).unwrap().root());
+    assert_eq!(tree.root(), tree.empty_root());
+
+    for (i, leaf) in leaves.iter().enumerate() {
+        let (index, root) = tree.insert(leaf).unwrap();
+        assert_eq!((index, root, tree.len()), (i, tree.root(), i + 1));
+
+        let full = params.build_tree_with_options(&leaves[..=i], &options).unwrap();
+        assert_eq!(root, full.root());
+        for (j, earlier) in leaves[..=i].iter().enumerate() {
+            assert!(params.verify_path(&full.prove(j).unwrap(), &root, earlier).unwrap());
+        }
+    }
+
+    let root = tree.root();
+    assert!(matches!(
+        tree.insert(&[9u8; 8]),
+        Err(MerkleError::CapacityExceeded { len: 9, capacity: 8 })
+    ));
+    assert_eq!((tree.len(), tree.root()), (8, root));
+}
@@ -0,0 +1,118 @@
+use crate::error::MerkleError;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::fmt;
+use ark_std::io::{Read, Write};
+#[cfg(test)]
+use ark_std::vec::Vec;
+
+// The position of a leaf in a tree.
+//
+// Leaf indices, leaf counts, tree heights and byte offsets are all integers,
+// and bare `usize`s get swapped silently. A `LeafIndex` can only be made by
+// `checked_new`, against the number of leaves it indexes into, which is the
+// one place the bounds check lives: code that needs to know the index also
+// fits another tree goes through `checked_new` again. Decoding an index
+// (`CanonicalDeserialize`) doesn't know the tree, so a decoded index is only
+// as good as the proof it came with.
+//
+// Indices count from 0 at the leftmost leaf. Up the tree, the same numbering
+// holds level by level: the node above leaf i is node i / 2 of the next level.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LeafIndex(u64);
+
+impl LeafIndex {
+    /// The index `index` into `leaf_count` leaves, failing if it is past the end.
+    pub fn checked_new(index: u64, leaf_count: u64) -> Result<Self, MerkleError> {
+        if index >= leaf_count {
+            return Err(MerkleError::LeafIndexOutOfRange {
+                index: index as usize,
+                len: leaf_count as usize,
+            });
+        }
+        Ok(LeafIndex(index))
+    }
+
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    pub fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+
+    /// Whether the leaf is the right child of its parent.
+    pub fn is_right(self) -> bool {
+        self.0 & 1 == 1
+    }
+
+    /// The other child of the same parent. In a tree of a power of two leaves,
+    /// which is every tree of this crate, it is always in range.
+    pub fn sibling(self) -> Self {
+        LeafIndex(self.0 ^ 1)
+    }
+
+    /// The position of the parent in the level above.
+    pub fn parent(self) -> Self {
+        LeafIndex(self.0 >> 1)
+    }
+
+    /// The position of the ancestor `levels` levels up; `ancestor(1)` is the parent.
+    pub fn ancestor(self, levels: u32) -> Self {
+        LeafIndex(self.0.checked_shr(levels).unwrap_or(0))
+    }
+}
+
+impl fmt::Display for LeafIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<LeafIndex> for u64 {
+    fn from(index: LeafIndex) -> Self {
+        index.0
+    }
+}
+
+// Encoded exactly like the `u64` indices proofs carried before.
+impl CanonicalSerialize for LeafIndex {
+    fn serialize<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        self.0.serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.0.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for LeafIndex {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        Ok(LeafIndex(u64::deserialize(reader)?))
+    }
+}
+
+#[test]
+fn test_leaf_index_bounds_and_navigation() {
+    assert_eq!(LeafIndex::checked_new(3, 4).unwrap().get(), 3);
+    assert!(matches!(
+        LeafIndex::checked_new(4, 4),
+        Err(MerkleError::LeafIndexOutOfRange { index: 4, len: 4 })
+    ));
+    assert!(LeafIndex::checked_new(0, 0).is_err());
+
+    let index = LeafIndex::checked_new(5, 8).unwrap();
+    assert!(index.is_right());
+    assert_eq!(index.sibling().get(), 4);
+    assert_eq!(index.sibling().sibling(), index);
+    assert_eq!(index.parent().get(), 2);
+    assert_eq!(index.ancestor(2).get(), 1);
+    assert_eq!(index.ancestor(3).get(), 0);
+    assert_eq!(index.ancestor(64).get(), 0);
+    assert_eq!(format!("{}", index), "5");
+
+    let mut bytes = Vec::new();
+    index.serialize(&mut bytes).unwrap();
+    assert_eq!(bytes, 5u64.to_le_bytes());
+    assert_eq!(LeafIndex::deserialize(&bytes[..]).unwrap(), index);
+}
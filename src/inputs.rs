@@ -0,0 +1,306 @@
+use crate::common::{pad_leaf, ConstraintF};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::leaf_encoding::ToLeafBytes;
+use crate::nullifier::Nullifier;
+use crate::Root;
+use ark_ff::ToConstraintField;
+use ark_std::marker::PhantomData;
+use ark_std::vec::Vec;
+
+// Public inputs, assembled the way circuits allocate them.
+//
+// Groth16 verification takes the public inputs as one flat list of field
+// elements, in allocation order. `PublicInputsBuilder` has one typed method per
+// kind of input and tracks, in its type parameter, which input the circuit
+// variant it was created for allocates next: `.root()` only exists while a
+// root is expected, `.finish()` only once all inputs are in. Calling them out
+// of order is a type error rather than a failed verification.
+
+/// The public inputs `UInt8::new_input_vec` allocates for `bytes`: the bytes
+/// packed little-endian into field elements, as many whole bytes to an
+/// element as fit below the modulus. For circuits whose inputs don't follow
+/// one of the `PublicInputsBuilder` layouts.
+pub fn byte_inputs(bytes: &[u8]) -> Vec<ConstraintF> {
+    // packing bytes into field elements cannot fail
+    <[u8] as ToConstraintField<ConstraintF>>::to_field_elements(bytes).unwrap()
+}
+
+/// The next input is a tree root.
+pub struct RootStep<Next>(PhantomData<Next>);
+/// The next input is a byte string allocated with `UInt8::new_input_vec`.
+pub struct LeafBytesStep<Next>(PhantomData<Next>);
+/// The next input is a leaf index.
+pub struct IndexStep<Next>(PhantomData<Next>);
+/// The next input is a nullifier.
+pub struct NullifierStep<Next>(PhantomData<Next>);
+/// The next input is a u64 allocated as a single field element (thresholds, timestamps).
+pub struct ScalarStep<Next>(PhantomData<Next>);
+/// All inputs are in.
+pub struct Done;
+
+pub struct PublicInputsBuilder<Step> {
+    inputs: Vec<ConstraintF>,
+    _step: PhantomData<Step>,
+}
+
+impl<Step> PublicInputsBuilder<Step> {
+    fn start() -> Self {
+        PublicInputsBuilder {
+            inputs: Vec::new(),
+            _step: PhantomData,
+        }
+    }
+
+    fn push<Next>(mut self, inputs: impl IntoIterator<Item = ConstraintF>) -> PublicInputsBuilder<Next> {
+        self.inputs.extend(inputs);
+        PublicInputsBuilder {
+            inputs: self.inputs,
+            _step: PhantomData,
+        }
+    }
+}
+
+impl<Next> PublicInputsBuilder<RootStep<Next>> {
+    pub fn root(self, root: &Root) -> PublicInputsBuilder<Next> {
+        self.push(Some(*root))
+    }
+}
+
+impl<Next> PublicInputsBuilder<LeafBytesStep<Next>> {
+    pub fn leaf_bytes(self, bytes: &[u8]) -> PublicInputsBuilder<Next> {
+        self.push(byte_inputs(bytes))
+    }
+
+    /// The `ToLeafBytes` encoding of `value`, as the tree hashed it.
+    pub fn leaf_value(self, value: &impl ToLeafBytes) -> PublicInputsBuilder<Next> {
+        self.leaf_bytes(&value.to_leaf_bytes())
+    }
+}
+
+impl<Next> PublicInputsBuilder<IndexStep<Next>> {
+    pub fn index(self, index: LeafIndex) -> PublicInputsBuilder<Next> {
+        self.push(Some(ConstraintF::from(index.get())))
+    }
+}
+
+impl<Next> PublicInputsBuilder<NullifierStep<Next>> {
+    pub fn nullifier(self, nullifier: &Nullifier) -> PublicInputsBuilder<Next> {
+        self.push(Some(*nullifier))
+    }
+}
+
+impl<Next> PublicInputsBuilder<ScalarStep<Next>> {
+    pub fn scalar(self, value: u64) -> PublicInputsBuilder<Next> {
+        self.push(Some(ConstraintF::from(value)))
+    }
+}
+
+impl PublicInputsBuilder<Done> {
+    pub fn finish(self) -> Vec<ConstraintF> {
+        self.inputs
+    }
+}
+
+// One constructor per circuit variant, each fixing the order its circuit allocates in.
+
+impl PublicInputsBuilder<RootStep<LeafBytesStep<Done>>> {
+    /// `MTreeVerification`: root, then the public leaf.
+    pub fn membership() -> Self {
+        Self::start()
+    }
+}
+
+impl PublicInputsBuilder<RootStep<LeafBytesStep<ScalarStep<Done>>>> {
+    /// Membership plus one public bound over a private value: root, the public
+    /// part of the leaf, then the bound (e.g. `access::AccessCircuit` without
+    /// nullifier, `solvency::BalanceThresholdCircuit`).
+    pub fn threshold() -> Self {
+        Self::start()
+    }
+}
+
+impl PublicInputsBuilder<RootStep<LeafBytesStep<ScalarStep<NullifierStep<Done>>>>> {
+    /// `access::AccessCircuit` in single-use mode: root, audience, time, nullifier.
+    pub fn single_use_threshold() -> Self {
+        Self::start()
+    }
+}
+
+impl PublicInputsBuilder<RootStep<LeafBytesStep<ScalarStep<ScalarStep<Done>>>>> {
+    /// Membership with both bounds public: root, the public part of the leaf,
+    /// lower bound, upper bound (`payroll::SalaryBracketCircuit`).
+    pub fn bracket() -> Self {
+        Self::start()
+    }
+}
+
+impl PublicInputsBuilder<RootStep<IndexStep<LeafBytesStep<Done>>>> {
+    /// Index-bound membership: root, index, then the public leaf
+    /// (`constraints::PositionalMembershipCircuit`) or, with a private leaf,
+    /// the bytes the proof is bound to (`lottery::ClaimCircuit`).
+    pub fn index_bound() -> Self {
+        Self::start()
+    }
+}
+
+impl PublicInputsBuilder<RootStep<LeafBytesStep<LeafBytesStep<NullifierStep<Done>>>>> {
+    /// Private-leaf membership with a scoped nullifier: root, scope, the bytes
+    /// the proof is bound to, nullifier (`survey::ResponseCircuit`).
+    pub fn scoped_nullifier() -> Self {
+        Self::start()
+    }
+}
+
+/// The public inputs of `MTreeVerification` as the values they stand for,
+/// for verifiers that have a root and a leaf rather than a circuit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublicInputs {
+    pub root: Root,
+    pub leaf: Vec<u8>,
+}
+
+impl PublicInputs {
+    pub fn from_parts(root: &Root, leaf: &[u8]) -> Self {
+        PublicInputs {
+            root: *root,
+            leaf: leaf.to_vec(),
+        }
+    }
+
+    /// The inputs of `MTreeVerification::with_leaf_len`: `leaf` as `pad_leaf`
+    /// pads it to `leaf_len` bytes.
+    pub fn padded(root: &Root, leaf: &[u8], leaf_len: usize) -> Result<Self, MerkleError> {
+        Ok(Self::from_parts(root, pad_leaf(leaf, leaf_len)?.as_ref()))
+    }
+
+    /// The field elements to verify a membership proof with, in the order
+    /// `MTreeVerification::generate_constraints` allocates them.
+    pub fn to_field_elements(&self) -> Vec<ConstraintF> {
+        PublicInputsBuilder::membership().root(&self.root).leaf_bytes(&self.leaf).finish()
+    }
+}
+
+#[cfg(all(test, feature = "snark"))]
+fn instance(circuit: impl ark_relations::r1cs::ConstraintSynthesizer<ConstraintF>) -> Vec<ConstraintF> {
+    let cs = ark_relations::r1cs::ConstraintSystem::new_ref();
+    circuit.generate_constraints(cs.clone()).unwrap();
+    let cs = cs.borrow().unwrap();
+    // the first instance variable is the constant one
+    cs.instance_assignment[1..].to_vec()
+}
+
+#[cfg(all(test, feature = "snark"))]
+#[test]
+fn test_builder_matches_membership_and_threshold_circuits() {
+    use crate::access::{AccessCircuit, Issuer};
+    use crate::common::MerkleParams;
+    use crate::constraints::MTreeVerification;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut issuer = Issuer::new(params.clone(), 4).unwrap();
+    let token = issuer.mint(&mut rng, "service-a", 1_000);
+    let root = issuer.rotate(0).unwrap();
+    let credential = issuer.credential(&token).unwrap();
+
+    let leaf = token.to_leaf_bytes();
+    let circuit: MTreeVerification = MTreeVerification {
+        leaf_crh_params: params.leaf_crh_params.clone(),
+        two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+        root,
+        leaf: leaf.clone(),
+        auth_path: Some(credential.path.clone()),
+    };
+    let expected = PublicInputsBuilder::membership().root(&root).leaf_bytes(&leaf).finish();
+    assert_eq!(circuit.public_inputs(), expected);
+    assert_eq!(PublicInputs::from_parts(&root, &leaf).to_field_elements(), expected);
+    assert_eq!(instance(circuit), expected);
+
+    for single_use in [false, true].iter().copied() {
+        let nullifier = token.nullifier(&params).unwrap();
+        let circuit = AccessCircuit {
+            params: params.clone(),
+            single_use,
+            root,
+            audience_hash: token.audience_hash,
+            now: 10,
+            nullifier: Some(nullifier),
+            token_id: Some(token.token_id),
+            expiry: Some(token.expiry),
+            auth_path: Some(credential.path.clone()),
+        };
+        let expected = if single_use {
+            PublicInputsBuilder::single_use_threshold()
+                .root(&root)
+                .leaf_bytes(&token.audience_hash)
+                .scalar(10)
+                .nullifier(&nullifier)
+                .finish()
+        } else {
+            PublicInputsBuilder::threshold()
+                .root(&root)
+                .leaf_bytes(&token.audience_hash)
+                .scalar(10)
+                .finish()
+        };
+        assert_eq!(instance(circuit), expected);
+    }
+}
+
+#[cfg(all(test, feature = "snark"))]
+#[test]
+fn test_builder_matches_index_bound_and_nullifier_circuits() {
+    use crate::common::{keccak256, MerkleParams};
+    use crate::lottery::{Organizer, Ticket};
+    use crate::survey::{Participant, ResponseCircuit, Roster};
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+
+    let ticket = Ticket::new(&mut rng);
+    let mut organizer = Organizer::new(params.clone());
+    organizer.add_ticket(ticket.commitment(&params).unwrap()).unwrap();
+    let root = organizer.seal().unwrap();
+    let payout = keccak256(b"payout");
+    let first = LeafIndex::checked_new(0, 1).unwrap();
+    let circuit = ticket.claim_circuit(&params, root, first, payout, organizer.path(0).unwrap());
+    let expected = PublicInputsBuilder::index_bound().root(&root).index(first).leaf_bytes(&payout).finish();
+    assert_eq!(instance(circuit), expected);
+
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let third = LeafIndex::checked_new(2, 4).unwrap();
+    let circuit = crate::constraints::PositionalMembershipCircuit::new(
+        &params,
+        tree.root(),
+        third,
+        &leaves[2],
+        tree.generate_proof(2).unwrap(),
+    );
+    let expected = PublicInputsBuilder::index_bound().root(&tree.root()).index(third).leaf_bytes(&leaves[2]).finish();
+    assert_eq!(circuit.public_inputs(), expected);
+    assert_eq!(instance(circuit), expected);
+
+    let participant = Participant::new(&mut rng);
+    let roster = Roster::new(&params, vec![participant.commitment(&params).unwrap()]).unwrap();
+    let nullifier = participant.nullifier(&params, 7).unwrap();
+    let response_hash = keccak256(b"yes");
+    let circuit = ResponseCircuit {
+        params: params.clone(),
+        root: roster.root(),
+        survey_id: 7,
+        response_hash,
+        nullifier,
+        secret: None,
+        auth_path: Some(roster.path(&participant.commitment(&params).unwrap()).unwrap()),
+    };
+    let expected = PublicInputsBuilder::scoped_nullifier()
+        .root(&roster.root())
+        .leaf_bytes(&7u64.to_le_bytes())
+        .leaf_bytes(&response_hash)
+        .nullifier(&nullifier)
+        .finish();
+    assert_eq!(instance(circuit), expected);
+    assert_eq!(expected, ResponseCircuit::public_inputs(&roster.root(), 7, &response_hash, &nullifier));
+}
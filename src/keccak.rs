@@ -0,0 +1,142 @@
+use ark_ff::PrimeField;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_std::vec::Vec;
+
+// keccak256 in R1CS, the hash of `common::keccak256` and `Leaf::keccak_of`.
+//
+// The 1600-bit state is 25 lanes of 64 `Boolean`s, least significant bit
+// first, lane `x + 5 * y` at column x and row y. Rotations and the lane
+// permutation only rename bits; the cost is in theta's XORs and chi's
+// ANDs, about 150k constraints for each 136-byte block absorbed. Padding is
+// the original Keccak one, `0x01 .. 0x80`, not SHA-3's.
+
+const RATE_BYTES: usize = 136;
+
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000_0000_0000_0001,
+    0x0000_0000_0000_8082,
+    0x8000_0000_0000_808A,
+    0x8000_0000_8000_8000,
+    0x0000_0000_0000_808B,
+    0x0000_0000_8000_0001,
+    0x8000_0000_8000_8081,
+    0x8000_0000_0000_8009,
+    0x0000_0000_0000_008A,
+    0x0000_0000_0000_0088,
+    0x0000_0000_8000_8009,
+    0x0000_0000_8000_000A,
+    0x0000_0000_8000_808B,
+    0x8000_0000_0000_008B,
+    0x8000_0000_0000_8089,
+    0x8000_0000_0000_8003,
+    0x8000_0000_0000_8002,
+    0x8000_0000_0000_0080,
+    0x0000_0000_0000_800A,
+    0x8000_0000_8000_000A,
+    0x8000_0000_8000_8081,
+    0x8000_0000_0000_8080,
+    0x0000_0000_8000_0001,
+    0x8000_0000_8000_8008,
+];
+
+// the rho rotation of the lane at column x, row y, as ROTATIONS[x][y]
+const ROTATIONS: [[usize; 5]; 5] = [
+    [0, 36, 3, 41, 18],
+    [1, 44, 10, 45, 2],
+    [62, 6, 43, 15, 61],
+    [28, 55, 25, 21, 56],
+    [27, 20, 39, 8, 14],
+];
+
+type Lane<F> = Vec<Boolean<F>>;
+
+fn xor<F: PrimeField>(a: &[Boolean<F>], b: &[Boolean<F>]) -> Result<Lane<F>, SynthesisError> {
+    a.iter().zip(b).map(|(a, b)| a.xor(b)).collect()
+}
+
+// rotates towards the more significant bits, like `u64::rotate_left`
+fn rotate_left<F: PrimeField>(lane: &[Boolean<F>], by: usize) -> Lane<F> {
+    (0..64).map(|i| lane[(i + 64 - by) % 64].clone()).collect()
+}
+
+fn keccak_f<F: PrimeField>(state: &mut [Lane<F>]) -> Result<(), SynthesisError> {
+    for round_constant in ROUND_CONSTANTS.iter() {
+        // theta
+        let mut parity = Vec::with_capacity(5);
+        for x in 0..5 {
+            let mut column = state[x].clone();
+            for y in 1..5 {
+                column = xor(&column, &state[x + 5 * y])?;
+            }
+            parity.push(column);
+        }
+        for x in 0..5 {
+            let d = xor(&parity[(x + 4) % 5], &rotate_left(&parity[(x + 1) % 5], 1))?;
+            for y in 0..5 {
+                state[x + 5 * y] = xor(&state[x + 5 * y], &d)?;
+            }
+        }
+
+        // rho and pi
+        let mut b = vec![Vec::new(); 25];
+        for x in 0..5 {
+            for y in 0..5 {
+                b[y + 5 * ((2 * x + 3 * y) % 5)] = rotate_left(&state[x + 5 * y], ROTATIONS[x][y]);
+            }
+        }
+
+        // chi
+        for x in 0..5 {
+            for y in 0..5 {
+                let (next, after) = (&b[(x + 1) % 5 + 5 * y], &b[(x + 2) % 5 + 5 * y]);
+                state[x + 5 * y] = b[x + 5 * y]
+                    .iter()
+                    .zip(next.iter().zip(after))
+                    .map(|(a, (n, c))| a.xor(&n.not().and(c)?))
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+
+        // iota
+        let constant: Lane<F> = (0..64).map(|i| Boolean::constant((round_constant >> i) & 1 == 1)).collect();
+        state[0] = xor(&state[0], &constant)?;
+    }
+    Ok(())
+}
+
+/// The 32-byte keccak256 digest of `input`.
+pub fn keccak256<F: PrimeField>(input: &[UInt8<F>]) -> Result<Vec<UInt8<F>>, SynthesisError> {
+    let mut padding = vec![0u8; RATE_BYTES - input.len() % RATE_BYTES];
+    padding[0] |= 0x01;
+    *padding.last_mut().unwrap() |= 0x80;
+    let mut padded = input.to_vec();
+    padded.extend(padding.into_iter().map(UInt8::constant));
+
+    let mut state: Vec<Lane<F>> = vec![vec![Boolean::FALSE; 64]; 25];
+    for block in padded.chunks(RATE_BYTES) {
+        for (lane, bytes) in state.iter_mut().zip(block.chunks(8)) {
+            let bits = bytes.to_bits_le()?;
+            *lane = xor(lane, &bits)?;
+        }
+        keccak_f(&mut state)?;
+    }
+
+    Ok(state[..4].iter().flat_map(|lane| lane.chunks(8)).map(UInt8::from_bits_le).collect())
+}
+
+#[test]
+fn test_keccak256_gadget_matches_native() {
+    use crate::common::{keccak256 as native, ConstraintF};
+    use ark_relations::r1cs::ConstraintSystem;
+
+    // empty, one word, and across the block boundary
+    for len in [0usize, 32, 135, 136].iter().copied() {
+        let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let input = UInt8::new_witness_vec(cs.clone(), &data).unwrap();
+        let digest = keccak256(&input).unwrap();
+        assert_eq!(digest.value().unwrap(), native(&data).to_vec(), "{} bytes", len);
+        assert!(cs.is_satisfied().unwrap());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::bundle::{self, ParamsBundle, Tree};
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::leaf::Leaf;
+use crate::leaf_encoding::ToLeafBytes;
+use crate::root::MerkleRoot;
+use ark_std::vec::Vec;
+#[cfg(feature = "snark")]
+use crate::constraints::MTreeVerification;
+#[cfg(feature = "snark")]
+use crate::inputs::PublicInputsBuilder;
+#[cfg(feature = "snark")]
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+#[cfg(feature = "snark")]
+use ark_std::cell::RefCell;
+#[cfg(feature = "snark")]
+use ark_std::collections::BTreeMap;
+#[cfg(feature = "snark")]
+use crate::rng::OsRngProvider;
+
+// A one-stop facade over the tree, its proofs and the membership circuit:
+// leaves in, root and proofs out, no parameters, configs or circuits in sight.
+//
+// Everything is delegated to the lower-level APIs: the tree and its proofs
+// come from a `ParamsBundle`, and zk proofs are Groth16 proofs of its
+// membership circuit. The hash parameters are the fixed `default_params`, so
+// two kits over the same leaves agree on the root.
+
+/// The seed of `default_params`: `merkle-tree-tutorial/params/v1` padded with
+/// two zero bytes. Changing it changes every default root.
+pub const DEFAULT_PARAMS_SEED: [u8; 32] = *b"merkle-tree-tutorial/params/v1\0\0";
+
+/// The hash parameters every `MerkleKit` uses, `setup_params_from_seed(DEFAULT_PARAMS_SEED)`.
+pub fn default_params() -> Result<MerkleParams, MerkleError> {
+    Ok(setup_params_from_seed(DEFAULT_PARAMS_SEED)?)
+}
+
+/// The tag of the `default_params` bundle.
+pub struct DefaultParams;
+
+/// A proof that `leaf` is at `index` in the tree with root `root`.
+pub type MembershipProof = bundle::MembershipProof<DefaultParams>;
+
+/// A zero-knowledge proof that `leaf` is somewhere in the tree with root `root`.
+#[cfg(feature = "snark")]
+#[derive(Clone)]
+pub struct ZkMembershipProof {
+    pub root: MerkleRoot,
+    pub leaf: Vec<u8>,
+    pub proof: Proof,
+}
+
+pub struct MerkleKit {
+    bundle: ParamsBundle<DefaultParams>,
+    // the number of leaves given, without the padding
+    len: usize,
+    tree: Tree<DefaultParams>,
+    // Groth16 keys by leaf length, since the leaf is a public input and its
+    // length fixes the shape of the circuit. Generated on first use.
+    #[cfg(feature = "snark")]
+    keys: RefCell<BTreeMap<usize, (ProvingKey, VerifyingKey)>>,
+}
+
+impl MerkleKit {
+    /// Builds a tree over `leaves`, padded with empty leaves up to a power of two.
+    pub fn new<L: Into<Leaf>>(leaves: impl IntoIterator<Item = L>) -> Result<Self, MerkleError> {
+        let mut leaves: Vec<Leaf> = leaves.into_iter().map(Into::into).collect();
+        let len = leaves.len();
+        leaves.resize(len.next_power_of_two().max(2), Leaf::default());
+        let bundle = ParamsBundle::from_params(default_params()?)?;
+        let tree = bundle.build_tree(leaves)?;
+        Ok(MerkleKit {
+            bundle,
+            len,
+            tree,
+            #[cfg(feature = "snark")]
+            keys: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    /// Builds a tree over the `ToLeafBytes` encodings of `items`, padded like `new`.
+    pub fn from_items<T: ToLeafBytes>(items: &[T]) -> Result<Self, MerkleError> {
+        Self::new(items.iter().map(ToLeafBytes::to_leaf_bytes))
+    }
+
+    pub fn root(&self) -> MerkleRoot {
+        self.tree.root()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Proves that the leaf at `index` is in the tree.
+    pub fn prove(&self, index: usize) -> Result<MembershipProof, MerkleError> {
+        // the padding leaves are in the tree, but not in the kit
+        let index = LeafIndex::checked_new(index as u64, self.len as u64)?;
+        self.tree.prove(index.as_usize())
+    }
+
+    /// Checks `proof` against the current root.
+    pub fn verify(&self, proof: &MembershipProof) -> bool {
+        proof.root == self.root() && self.bundle.verify(proof).unwrap_or(false)
+    }
+
+    /// Checks `proof` against the current root, and that it opens `item`.
+    pub fn verify_item<T: ToLeafBytes>(&self, proof: &MembershipProof, item: &T) -> bool {
+        proof.leaf == item.to_leaf_bytes() && self.verify(proof)
+    }
+}
+
+#[cfg(feature = "snark")]
+impl MerkleKit {
+    // Runs `f` with the keys for leaves of `leaf_len` bytes, generating them first if needed.
+    fn with_keys<T>(
+        &self,
+        leaf_len: usize,
+        f: impl FnOnce(&ProvingKey, &VerifyingKey) -> Result<T, MerkleError>,
+    ) -> Result<T, MerkleError> {
+        let mut keys = self.keys.borrow_mut();
+        if !keys.contains_key(&leaf_len) {
+            let path = self.tree.prove(0)?.path;
+            let circuit =
+                <MTreeVerification>::new(self.bundle.params(), self.root().into(), vec![0u8; leaf_len], path);
+            keys.insert(leaf_len, snark::setup(circuit, &mut OsRngProvider::default())?);
+        }
+        let (pk, vk) = &keys[&leaf_len];
+        f(pk, vk)
+    }
+
+    /// Proves in zero knowledge that the leaf at `index` is in the tree,
+    /// revealing the leaf but not its position.
+    pub fn zk_prove(&self, index: usize) -> Result<ZkMembershipProof, MerkleError> {
+        let proof = self.prove(index)?;
+        let circuit = self.bundle.membership_circuit(&proof)?;
+        let groth16 = self.with_keys(proof.leaf.len(), |pk, _| {
+            snark::prove(pk, circuit, &mut OsRngProvider::default())
+        })?;
+        Ok(ZkMembershipProof {
+            root: proof.root,
+            leaf: proof.leaf,
+            proof: groth16,
+        })
+    }
+
+    /// Checks a proof made by `zk_prove` on this kit against the current root.
+    pub fn zk_verify(&self, package: &ZkMembershipProof) -> Result<bool, MerkleError> {
+        if package.root != self.root() {
+            return Ok(false);
+        }
+        let inputs = PublicInputsBuilder::membership()
+            .root(&package.root.into())
+            .leaf_bytes(&package.leaf)
+            .finish();
+        self.with_keys(package.leaf.len(), |_, vk| snark::verify(vk, &inputs, &package.proof))
+    }
+}
+
+#[test]
+fn test_kit_pads_and_rejects() {
+    let kit = MerkleKit::new((0u8..3).map(|i| vec![i; 8])).unwrap();
+    assert_eq!(kit.len(), 3);
+    assert!(matches!(
+        kit.prove(3),
+        Err(MerkleError::LeafIndexOutOfRange { index: 3, len: 3 })
+    ));
+
+    // the default params are fixed, so a second kit agrees on the root
+    let again = MerkleKit::new((0u8..3).map(|i| vec![i; 8])).unwrap();
+    assert_eq!(kit.root(), again.root());
+
+    // a proof for one leaf doesn't pass for another
+    let mut proof = kit.prove(1).unwrap();
+    assert!(kit.verify(&proof));
+    proof.leaf = vec![2u8; 8];
+    assert!(!kit.verify(&proof));
+
+    assert!(matches!(
+        MerkleKit::new(vec![vec![0u8; MAX_LEAF_LEN + 1]]).unwrap_err().root_cause(),
+        MerkleError::LeafTooLong { .. }
+    ));
+}
+
+#[test]
+fn test_kit_over_accounts() {
+    use crate::leaf_encoding::Account;
+
+    let accounts: Vec<Account> = (0u64..5)
+        .map(|id| Account {
+            id,
+            balance: 100 * id,
+            pubkey: [id as u8; 32],
+        })
+        .collect();
+    let kit = MerkleKit::from_items(&accounts).unwrap();
+    let proof = kit.prove(3).unwrap();
+    assert!(kit.verify_item(&proof, &accounts[3]));
+    assert!(!kit.verify_item(&proof, &accounts[2]));
+    assert!(!kit.verify_item(&proof, &Account { balance: 301, ..accounts[3] }));
+
+    // the circuit's public leaf is the same encoding
+    #[cfg(feature = "snark")]
+    {
+        let inputs = PublicInputsBuilder::membership()
+            .root(&kit.root().into())
+            .leaf_value(&accounts[3])
+            .finish();
+        assert_eq!(kit.bundle.membership_circuit(&proof).unwrap().public_inputs(), inputs);
+    }
+}
+
+#[test]
+fn test_kit_reports_the_offending_leaf_of_a_batch() {
+    use ark_std::error::Error;
+
+    let mut leaves: Vec<Vec<u8>> = (0u8..100).map(|i| vec![i; 8]).collect();
+    leaves[57] = vec![0u8; MAX_LEAF_LEN + 1];
+    let e = MerkleKit::new(leaves).err().unwrap();
+
+    let context = e.context().unwrap();
+    assert_eq!(context.operation, "build_tree");
+    assert_eq!(context.index, Some(57));
+    assert!(matches!(
+        e.root_cause(),
+        MerkleError::LeafTooLong { len, max } if *len == MAX_LEAF_LEN + 1 && *max == MAX_LEAF_LEN
+    ));
+    assert!(matches!(e.source().unwrap().downcast_ref(), Some(MerkleError::LeafTooLong { .. })));
+
+    let message = format!("{}", e);
+    assert!(!message.contains('\n'));
+    assert!(message.starts_with("build_tree at leaf 57: leaf of 129 bytes"), "{}", message);
+}
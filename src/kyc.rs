@@ -0,0 +1,445 @@
+use crate::common::*;
+use crate::constraints::{blank_path, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::byte_inputs;
+use crate::range::{enforce_bit_length, enforce_geq};
+use crate::schnorr::{self, PublicKey, SecretKey, Signature};
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_ff::to_bytes;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::rand::{CryptoRng, RngCore};
+
+// KYC attribute proofs.
+//
+// An issuer packs each user's attributes into a fixed 32-byte bundle, signs
+// the bundle's hash and publishes a tree whose leaves are
+// `H(attributes) || x(R) || s`, i.e. the bundle hash followed by the part of
+// the issuer's signature that fits the leaf (the y-coordinate of R is left
+// out; the user keeps the full signature to check it offline). A user then
+// proves membership of their leaf together with a predicate over a single
+// attribute, e.g. "age >= 18" or "country != XX". The attributes are witnessed
+// and hashed inside the circuit, so nothing but the predicate is disclosed.
+//
+// Bundle layout: age (1 byte) || country (2 bytes, ISO 3166 alpha-2) ||
+// salt (29 bytes). The salt keeps `H(attributes)` from being brute-forced
+// over the small attribute space.
+
+/// Size of a packed attribute bundle in bytes.
+pub const ATTRIBUTES_LEN: usize = 32;
+const SALT_LEN: usize = 29;
+
+/// Size of a KYC leaf in bytes: bundle hash, x(R) and s.
+pub const KYC_LEAF_LEN: usize = 96;
+
+// Byte offsets into the packed bundle.
+const AGE_OFFSET: usize = 0;
+const COUNTRY_OFFSET: usize = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attributes {
+    pub age: u8,
+    pub country: [u8; 2],
+    pub salt: [u8; SALT_LEN],
+}
+
+impl Attributes {
+    pub fn new<R: RngCore + CryptoRng>(age: u8, country: &str, rng: &mut R) -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        rng.fill_bytes(&mut salt);
+        Attributes {
+            age,
+            country: pack_country(country),
+            salt,
+        }
+    }
+}
+
+/// Packs a two-letter country code. Longer codes are truncated, shorter ones zero-padded.
+pub fn pack_country(country: &str) -> [u8; 2] {
+    let mut packed = [0u8; 2];
+    for (dst, src) in packed.iter_mut().zip(country.bytes()) {
+        *dst = src.to_ascii_uppercase();
+    }
+    packed
+}
+
+pub fn pack_attributes(attributes: &Attributes) -> [u8; ATTRIBUTES_LEN] {
+    let mut packed = [0u8; ATTRIBUTES_LEN];
+    packed[AGE_OFFSET] = attributes.age;
+    packed[COUNTRY_OFFSET..COUNTRY_OFFSET + 2].copy_from_slice(&attributes.country);
+    packed[COUNTRY_OFFSET + 2..].copy_from_slice(&attributes.salt);
+    packed
+}
+
+pub fn unpack_attributes(packed: &[u8; ATTRIBUTES_LEN]) -> Attributes {
+    let mut country = [0u8; 2];
+    country.copy_from_slice(&packed[COUNTRY_OFFSET..COUNTRY_OFFSET + 2]);
+    let mut salt = [0u8; SALT_LEN];
+    salt.copy_from_slice(&packed[COUNTRY_OFFSET + 2..]);
+    Attributes {
+        age: packed[AGE_OFFSET],
+        country,
+        salt,
+    }
+}
+
+fn leaf_bytes(bundle_hash: &[u8], signature: &Signature) -> Result<Vec<u8>, MerkleError> {
+    let mut leaf = bundle_hash.to_vec();
+    leaf.extend(to_bytes![signature.r.x, signature.s]?);
+    Ok(leaf)
+}
+
+/// What the issuer hands to a user.
+#[derive(Clone)]
+pub struct SignedBundle {
+    pub attributes: Attributes,
+    pub signature: Signature,
+}
+
+impl SignedBundle {
+    /// The hash the issuer signs, as the 32 bytes that start the leaf.
+    pub fn bundle_hash(&self, params: &MerkleParams) -> Result<Vec<u8>, MerkleError> {
+        Ok(to_bytes![params.hash_leaf(&pack_attributes(&self.attributes))?]?)
+    }
+
+    /// Checks the issuer's signature over the bundle.
+    pub fn verify(&self, params: &MerkleParams, issuer: &PublicKey) -> Result<bool, MerkleError> {
+        schnorr::verify(params, issuer, &self.bundle_hash(params)?, &self.signature)
+    }
+
+    pub fn to_leaf_bytes(&self, params: &MerkleParams) -> Result<Vec<u8>, MerkleError> {
+        leaf_bytes(&self.bundle_hash(params)?, &self.signature)
+    }
+}
+
+pub struct KycIssuer {
+    params: MerkleParams,
+    sk: SecretKey,
+    options: TreeOptions,
+    bundles: Vec<SignedBundle>,
+    // issued since the last publication
+    pending: Vec<SignedBundle>,
+    tree: SimpleMerkleTree,
+}
+
+impl KycIssuer {
+    /// Creates an issuer whose trees hold up to `capacity` bundles. The
+    /// capacity fixes the circuit shape, so it must stay the same for the
+    /// lifetime of the keys.
+    pub fn new<R: RngCore + CryptoRng>(params: MerkleParams, capacity: usize, rng: &mut R) -> Result<Self, MerkleError> {
+        let (sk, _) = schnorr::keygen(rng);
+        let options = TreeOptions::for_capacity(capacity).padding(PaddingStrategy::Zeros(KYC_LEAF_LEN));
+        let tree = Self::build_tree(&params, &options, &[])?;
+        Ok(KycIssuer {
+            params,
+            sk,
+            options,
+            bundles: Vec::new(),
+            pending: Vec::new(),
+            tree,
+        })
+    }
+
+    fn build_tree(
+        params: &MerkleParams,
+        options: &TreeOptions,
+        bundles: &[SignedBundle],
+    ) -> Result<SimpleMerkleTree, MerkleError> {
+        let leaves = bundles
+            .iter()
+            .map(|b| b.to_leaf_bytes(params))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(params.build_tree_with_options(&leaves, options)?.tree)
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        schnorr::public_key(&self.sk)
+    }
+
+    /// Number of levels of the issuer's trees, leaves and root included.
+    pub fn tree_height(&self) -> usize {
+        self.options.height
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// Signs `attributes`. The bundle becomes provable after the next `publish`.
+    pub fn issue(&mut self, attributes: Attributes) -> Result<SignedBundle, MerkleError> {
+        let bundle_hash = to_bytes![self.params.hash_leaf(&pack_attributes(&attributes))?]?;
+        let bundle = SignedBundle {
+            attributes,
+            signature: schnorr::sign(&self.params, &self.sk, &bundle_hash)?,
+        };
+        self.pending.push(bundle.clone());
+        Ok(bundle)
+    }
+
+    /// Rebuilds the tree with every bundle issued so far and returns its root.
+    pub fn publish(&mut self) -> Result<Root, MerkleError> {
+        let len = self.bundles.len() + self.pending.len();
+        if len > self.options.capacity() {
+            return Err(MerkleError::CapacityExceeded {
+                len,
+                capacity: self.options.capacity(),
+            });
+        }
+        self.bundles.append(&mut self.pending);
+        self.tree = Self::build_tree(&self.params, &self.options, &self.bundles)?;
+        Ok(self.root())
+    }
+
+    /// The path of `bundle` in the published tree.
+    pub fn path(&self, bundle: &SignedBundle) -> Result<SimplePath, MerkleError> {
+        let index = self
+            .bundles
+            .iter()
+            .position(|b| b.attributes == bundle.attributes && b.signature == bundle.signature)
+            .ok_or(MerkleError::LeafNotFound)?;
+        generate_proof(&self.tree, LeafIndex::checked_new(index as u64, leaf_count(&self.tree))?)
+    }
+}
+
+/// A statement about one attribute of a bundle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Predicate {
+    AgeAtLeast(u8),
+    CountryNot([u8; 2]),
+}
+
+impl Predicate {
+    pub fn holds(&self, attributes: &Attributes) -> bool {
+        match self {
+            Predicate::AgeAtLeast(min) => attributes.age >= *min,
+            Predicate::CountryNot(country) => attributes.country != *country,
+        }
+    }
+
+    // A predicate of the same kind, with a dummy parameter.
+    fn blank(&self) -> Self {
+        match self {
+            Predicate::AgeAtLeast(_) => Predicate::AgeAtLeast(0),
+            Predicate::CountryNot(_) => Predicate::CountryNot([0u8; 2]),
+        }
+    }
+}
+
+/// Proves "I hold a bundle in the tree under `root` whose attributes satisfy `predicate`".
+///
+/// The kind of predicate is fixed by the circuit, its parameter is a public input.
+pub struct PredicateCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub predicate: Predicate,
+
+    // These are the private witnesses to the circuit.
+    pub attributes: Option<[u8; ATTRIBUTES_LEN]>,
+    pub signature_fragment: Option<Vec<u8>>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl PredicateCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, tree_height: usize, predicate: Predicate) -> Self {
+        PredicateCircuit {
+            params,
+            root: Default::default(),
+            predicate: predicate.blank(),
+            attributes: Some([0u8; ATTRIBUTES_LEN]),
+            signature_fragment: Some(vec![0u8; KYC_LEAF_LEN - 32]),
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(root: &Root, predicate: &Predicate) -> Vec<ConstraintF> {
+        let mut inputs = vec![*root];
+        match predicate {
+            Predicate::AgeAtLeast(min) => inputs.push(ConstraintF::from(*min)),
+            Predicate::CountryNot(country) => inputs.extend(
+                byte_inputs(&country[..]),
+            ),
+        }
+        inputs
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for PredicateCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Then the private bundle, signature fragment and path:
+        let attributes = UInt8::new_witness_vec(
+            ark_relations::ns!(cs, "attributes_var"),
+            &self.attributes.unwrap_or([0u8; ATTRIBUTES_LEN]),
+        )?;
+        let signature_fragment = UInt8::new_witness_vec(
+            ark_relations::ns!(cs, "signature_fragment_var"),
+            &self
+                .signature_fragment
+                .unwrap_or_else(|| vec![0u8; KYC_LEAF_LEN - 32]),
+        )?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // the leaf is H(attributes) || signature fragment
+        {
+            let _membership = ark_relations::ns!(cs, "membership");
+            let bundle_hash =
+                <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(&leaf_crh_params, &attributes)?;
+            let mut leaf_bytes = bundle_hash.to_bytes()?;
+            leaf_bytes.extend(signature_fragment);
+
+            let is_member = path.verify_membership(
+                &leaf_crh_params,
+                &two_to_one_crh_params,
+                &root,
+                &leaf_bytes.as_slice(),
+            )?;
+            is_member.enforce_equal(&Boolean::TRUE)?;
+        }
+
+        // and the disclosed predicate holds for the witnessed attribute
+        let _predicate = ark_relations::ns!(cs, "predicate");
+        match self.predicate {
+            Predicate::AgeAtLeast(min) => {
+                let min = FpVar::new_input(ark_relations::ns!(cs, "min_age_var"), || {
+                    Ok(ConstraintF::from(min))
+                })?;
+                enforce_bit_length(&min, 8)?;
+                let age = Boolean::le_bits_to_fp_var(&attributes[AGE_OFFSET].to_bits_le()?)?;
+                enforce_geq(&age, &min, 8)?;
+            }
+            Predicate::CountryNot(country) => {
+                let excluded = UInt8::new_input_vec(ark_relations::ns!(cs, "excluded_country_var"), &country)?;
+                let is_excluded =
+                    attributes[COUNTRY_OFFSET..COUNTRY_OFFSET + 2].is_eq(&excluded[..])?;
+                is_excluded.enforce_equal(&Boolean::FALSE)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl SignedBundle {
+    /// The circuit proving `predicate` for this bundle against `root`.
+    pub fn predicate_circuit(
+        &self,
+        params: &MerkleParams,
+        root: Root,
+        path: SimplePath,
+        predicate: Predicate,
+    ) -> Result<PredicateCircuit, MerkleError> {
+        let leaf = self.to_leaf_bytes(params)?;
+        Ok(PredicateCircuit {
+            params: params.clone(),
+            root,
+            predicate,
+            attributes: Some(pack_attributes(&self.attributes)),
+            signature_fragment: Some(leaf[32..].to_vec()),
+            auth_path: Some(path),
+        })
+    }
+}
+
+pub fn setup_predicate<R: RngCore + CryptoRng>(
+    params: &MerkleParams,
+    tree_height: usize,
+    predicate: Predicate,
+    rng: &mut R,
+) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+    snark::setup(PredicateCircuit::blank(params.clone(), tree_height, predicate), rng)
+}
+
+pub fn verify_predicate(
+    vk: &VerifyingKey,
+    root: &Root,
+    predicate: &Predicate,
+    proof: &Proof,
+) -> Result<bool, MerkleError> {
+    snark::verify(vk, &PredicateCircuit::public_inputs(root, predicate), proof)
+}
+
+#[test]
+fn test_kyc_attribute_packing() {
+    let mut rng = ark_std::test_rng();
+    let attributes = Attributes::new(42, "de", &mut rng);
+    assert_eq!(attributes.country, *b"DE");
+    assert_eq!(unpack_attributes(&pack_attributes(&attributes)), attributes);
+}
+
+#[test]
+fn test_kyc_predicates() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut issuer = KycIssuer::new(params.clone(), 4, &mut rng).unwrap();
+    let adult = issuer.issue(Attributes::new(30, "DE", &mut rng)).unwrap();
+    let minor = issuer.issue(Attributes::new(17, "FR", &mut rng)).unwrap();
+    let root = issuer.publish().unwrap();
+    assert!(adult.verify(&params, &issuer.public_key()).unwrap());
+
+    let over_18 = Predicate::AgeAtLeast(18);
+    let (pk, vk) = setup_predicate(&params, issuer.tree_height(), over_18, &mut rng).unwrap();
+    let circuit = adult
+        .predicate_circuit(&params, root, issuer.path(&adult).unwrap(), over_18)
+        .unwrap();
+    let proof = snark::prove(&pk, circuit, &mut rng).unwrap();
+    assert!(verify_predicate(&vk, &root, &over_18, &proof).unwrap());
+    assert!(!verify_predicate(&vk, &root, &Predicate::AgeAtLeast(31), &proof).unwrap());
+
+    // the predicate fails for the minor, on the boundary too
+    let path = issuer.path(&minor).unwrap();
+    expect_unsatisfied(minor.predicate_circuit(&params, root, path.clone(), over_18).unwrap(), "predicate");
+    assert_satisfied_with_report(
+        minor.predicate_circuit(&params, root, path.clone(), Predicate::AgeAtLeast(17)).unwrap(),
+    );
+
+    let not_fr = Predicate::CountryNot(pack_country("FR"));
+    expect_unsatisfied(minor.predicate_circuit(&params, root, path, not_fr).unwrap(), "predicate");
+    let path = issuer.path(&adult).unwrap();
+    assert_satisfied_with_report(adult.predicate_circuit(&params, root, path, not_fr).unwrap());
+}
+
+#[test]
+fn test_kyc_leaf_from_other_issuer_rejected() {
+    use crate::circuit_report::expect_unsatisfied;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut issuer = KycIssuer::new(params.clone(), 4, &mut rng).unwrap();
+    let mut rogue = KycIssuer::new(params.clone(), 4, &mut rng).unwrap();
+    issuer.issue(Attributes::new(30, "DE", &mut rng)).unwrap();
+    let root = issuer.publish().unwrap();
+
+    let forged = rogue.issue(Attributes::new(30, "DE", &mut rng)).unwrap();
+    rogue.publish().unwrap();
+    assert!(!forged.verify(&params, &issuer.public_key()).unwrap());
+
+    // a valid path in the rogue tree doesn't lead to the real issuer's root
+    let path = rogue.path(&forged).unwrap();
+    let over_18 = Predicate::AgeAtLeast(18);
+    expect_unsatisfied(forged.predicate_circuit(&params, root, path, over_18).unwrap(), "membership");
+}
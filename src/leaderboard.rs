@@ -0,0 +1,376 @@
+use crate::common::*;
+use crate::constraints::{blank_path, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::byte_inputs;
+use crate::range::{enforce_bit_length, enforce_geq};
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::collections::BTreeMap;
+use ark_std::rand::{CryptoRng, RngCore};
+
+// Seasonal leaderboards with score-threshold proofs.
+//
+// At the end of each season the game server publishes a tree whose leaves are
+// `player_id_hash || season || score` (32 bytes of keccak256, then the season
+// and the score as 8 little-endian bytes each). A player can show a third
+// party that they scored at least S in a given season. The season is hashed
+// into the leaf and is a public input, so a proof made for one season does not
+// pass as a proof for another, even if the two trees happen to share a root.
+
+/// Size of a leaderboard leaf in bytes.
+pub const SCORE_LEAF_LEN: usize = 48;
+
+// Scores are u64, so every comparison is 64 bits wide.
+const SCORE_BITS: usize = 64;
+
+/// Hashes a player identifier into the 32 bytes stored in the leaf.
+pub fn player_id_hash(player_id: &[u8]) -> [u8; 32] {
+    keccak256(player_id)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScoreEntry {
+    pub player_id_hash: [u8; 32],
+    pub season: u64,
+    pub score: u64,
+}
+
+impl ScoreEntry {
+    /// The leaf bytes this entry is committed as.
+    pub fn to_leaf_bytes(&self) -> Vec<u8> {
+        let mut leaf = self.player_id_hash.to_vec();
+        leaf.extend_from_slice(&self.season.to_le_bytes());
+        leaf.extend_from_slice(&self.score.to_le_bytes());
+        leaf
+    }
+}
+
+struct Season {
+    entries: Vec<ScoreEntry>,
+    tree: SimpleMerkleTree,
+}
+
+/// The published root of every closed season.
+pub type SeasonRoots = BTreeMap<u64, Root>;
+
+/// The game server side: one tree per season.
+pub struct Leaderboard {
+    params: MerkleParams,
+    options: TreeOptions,
+    seasons: BTreeMap<u64, Season>,
+    // the season scores are currently recorded for, and its scores so far
+    current: u64,
+    scores: Vec<ScoreEntry>,
+}
+
+impl Leaderboard {
+    /// Creates a leaderboard starting at `season`, with up to `capacity`
+    /// players per season. The capacity fixes the circuit shape.
+    pub fn new(params: MerkleParams, capacity: usize, season: u64) -> Self {
+        Leaderboard {
+            params,
+            options: TreeOptions::for_capacity(capacity).padding(PaddingStrategy::Zeros(SCORE_LEAF_LEN)),
+            seasons: BTreeMap::new(),
+            current: season,
+            scores: Vec::new(),
+        }
+    }
+
+    /// Number of levels of the season trees, leaves and root included.
+    pub fn tree_height(&self) -> usize {
+        self.options.height
+    }
+
+    pub fn current_season(&self) -> u64 {
+        self.current
+    }
+
+    /// Records `score` for `player_id` in the current season, keeping the best one.
+    pub fn record_score(&mut self, player_id: &[u8], score: u64) -> Result<(), MerkleError> {
+        let id_hash = player_id_hash(player_id);
+        match self.scores.iter_mut().find(|e| e.player_id_hash == id_hash) {
+            Some(entry) => entry.score = entry.score.max(score),
+            None if self.scores.len() == self.options.capacity() => {
+                return Err(MerkleError::CapacityExceeded {
+                    len: self.scores.len() + 1,
+                    capacity: self.options.capacity(),
+                })
+            }
+            None => self.scores.push(ScoreEntry {
+                player_id_hash: id_hash,
+                season: self.current,
+                score,
+            }),
+        }
+        Ok(())
+    }
+
+    /// Publishes the current season's tree and starts the next season.
+    pub fn close_season(&mut self) -> Result<Root, MerkleError> {
+        let entries = ark_std::mem::take(&mut self.scores);
+        let tree = self
+            .params
+            .build_tree_with_options(entries.iter().map(ScoreEntry::to_leaf_bytes), &self.options)?
+            .tree;
+        let root = tree.root();
+        self.seasons.insert(self.current, Season { entries, tree });
+        self.current += 1;
+        Ok(root)
+    }
+
+    /// The roots of all closed seasons.
+    pub fn season_roots(&self) -> SeasonRoots {
+        self.seasons
+            .iter()
+            .map(|(season, s)| (*season, s.tree.root()))
+            .collect()
+    }
+
+    /// The witness `player_id` needs to prove their score in a closed `season`.
+    pub fn witness(&self, player_id: &[u8], season: u64) -> Result<ScoreWitness, MerkleError> {
+        let s = self.seasons.get(&season).ok_or(MerkleError::LeafNotFound)?;
+        let id_hash = player_id_hash(player_id);
+        let index = s
+            .entries
+            .iter()
+            .position(|e| e.player_id_hash == id_hash)
+            .ok_or(MerkleError::LeafNotFound)?;
+        Ok(ScoreWitness {
+            entry: s.entries[index].clone(),
+            root: s.tree.root(),
+            path: generate_proof(&s.tree, LeafIndex::checked_new(index as u64, leaf_count(&s.tree))?)?,
+        })
+    }
+
+    pub fn setup<R: RngCore + CryptoRng>(
+        &self,
+        rng: &mut R,
+    ) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+        snark::setup(ScoreThresholdCircuit::blank(self.params.clone(), self.tree_height()), rng)
+    }
+}
+
+/// Proves that the player with a public `player_id_hash` scored at least
+/// `threshold` in `season`, according to the tree under `root`.
+pub struct ScoreThresholdCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub player_id_hash: [u8; 32],
+    pub season: u64,
+    pub threshold: u64,
+
+    // These are the private witnesses to the circuit.
+    pub score: Option<u64>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl ScoreThresholdCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, tree_height: usize) -> Self {
+        ScoreThresholdCircuit {
+            params,
+            root: Default::default(),
+            player_id_hash: [0u8; 32],
+            season: 0,
+            threshold: 0,
+            score: Some(0),
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(root: &Root, player_id_hash: &[u8; 32], season: u64, threshold: u64) -> Vec<ConstraintF> {
+        let mut inputs = vec![*root];
+        inputs.extend(byte_inputs(&player_id_hash[..]));
+        inputs.extend(byte_inputs(&season.to_le_bytes()[..]));
+        inputs.push(ConstraintF::from(threshold));
+        inputs
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for ScoreThresholdCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let player_id_hash =
+            UInt8::new_input_vec(ark_relations::ns!(cs, "player_id_var"), &self.player_id_hash)?;
+        let season = UInt8::new_input_vec(ark_relations::ns!(cs, "season_var"), &self.season.to_le_bytes())?;
+        let threshold = FpVar::new_input(ark_relations::ns!(cs, "threshold_var"), || {
+            Ok(ConstraintF::from(self.threshold))
+        })?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the private score and path:
+        let score = UInt64::new_witness(ark_relations::ns!(cs, "score_var"), || {
+            self.score.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let score_bits = score.to_bits_le();
+        {
+            let _membership = ark_relations::ns!(cs, "membership");
+            let mut leaf_bytes = player_id_hash;
+            leaf_bytes.extend(season);
+            leaf_bytes.extend(score_bits.chunks(8).map(UInt8::from_bits_le));
+            let is_member = path.verify_membership(
+                &leaf_crh_params,
+                &two_to_one_crh_params,
+                &root,
+                &leaf_bytes.as_slice(),
+            )?;
+            is_member.enforce_equal(&Boolean::TRUE)?;
+        }
+
+        // the score is range checked by its UInt64 decomposition, the threshold here
+        let _threshold = ark_relations::ns!(cs, "threshold");
+        enforce_bit_length(&threshold, SCORE_BITS)?;
+        enforce_geq(&Boolean::le_bits_to_fp_var(&score_bits)?, &threshold, SCORE_BITS)?;
+
+        Ok(())
+    }
+}
+
+/// A player's entry in a closed season, with its path.
+#[derive(Clone)]
+pub struct ScoreWitness {
+    pub entry: ScoreEntry,
+    pub root: Root,
+    pub path: SimplePath,
+}
+
+/// What a player hands to a third party.
+#[derive(Clone)]
+pub struct ScoreProof {
+    pub season: u64,
+    pub threshold: u64,
+    pub proof: Proof,
+}
+
+impl ScoreWitness {
+    pub fn threshold_circuit(&self, params: &MerkleParams, threshold: u64) -> ScoreThresholdCircuit {
+        ScoreThresholdCircuit {
+            params: params.clone(),
+            root: self.root,
+            player_id_hash: self.entry.player_id_hash,
+            season: self.entry.season,
+            threshold,
+            score: Some(self.entry.score),
+            auth_path: Some(self.path.clone()),
+        }
+    }
+
+    /// Proves a score of at least `threshold`.
+    pub fn prove<R: RngCore + CryptoRng>(
+        &self,
+        params: &MerkleParams,
+        pk: &ProvingKey,
+        threshold: u64,
+        rng: &mut R,
+    ) -> Result<ScoreProof, MerkleError> {
+        if self.entry.score < threshold {
+            return Err(MerkleError::Synthesis(SynthesisError::Unsatisfiable));
+        }
+        Ok(ScoreProof {
+            season: self.entry.season,
+            threshold,
+            proof: snark::prove(pk, self.threshold_circuit(params, threshold), rng)?,
+        })
+    }
+}
+
+/// Checks that `proof` shows `player_id` scoring at least `threshold` in `season`.
+pub fn verify_score(
+    vk: &VerifyingKey,
+    roots: &SeasonRoots,
+    player_id: &[u8],
+    season: u64,
+    threshold: u64,
+    proof: &ScoreProof,
+) -> Result<bool, MerkleError> {
+    let root = match roots.get(&season) {
+        Some(root) => root,
+        None => return Ok(false),
+    };
+    if proof.season != season || proof.threshold != threshold {
+        return Ok(false);
+    }
+    let inputs = ScoreThresholdCircuit::public_inputs(root, &player_id_hash(player_id), season, threshold);
+    snark::verify(vk, &inputs, &proof.proof)
+}
+
+#[cfg(test)]
+fn test_leaderboard() -> Leaderboard {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut leaderboard = Leaderboard::new(params, 4, 1);
+    leaderboard.record_score(b"alice", 1_200).unwrap();
+    leaderboard.record_score(b"bob", 800).unwrap();
+    leaderboard.close_season().unwrap();
+    leaderboard.record_score(b"alice", 300).unwrap();
+    leaderboard.record_score(b"bob", 1_500).unwrap();
+    leaderboard.close_season().unwrap();
+    leaderboard
+}
+
+#[test]
+fn test_leaderboard_wrong_season_replay() {
+    use crate::circuit_report::expect_unsatisfied;
+
+    let mut rng = ark_std::test_rng();
+    let leaderboard = test_leaderboard();
+    let params = &leaderboard.params;
+    let roots = leaderboard.season_roots();
+    let (pk, vk) = leaderboard.setup(&mut rng).unwrap();
+
+    let witness = leaderboard.witness(b"alice", 1).unwrap();
+    let proof = witness.prove(params, &pk, 1_000, &mut rng).unwrap();
+    assert!(verify_score(&vk, &roots, b"alice", 1, 1_000, &proof).unwrap());
+
+    // alice didn't reach 1000 in season 2; neither the proof nor a relabelled copy says she did
+    assert!(!verify_score(&vk, &roots, b"alice", 2, 1_000, &proof).unwrap());
+    let mut relabelled = proof.clone();
+    relabelled.season = 2;
+    assert!(!verify_score(&vk, &roots, b"alice", 2, 1_000, &relabelled).unwrap());
+    // no such season yet
+    assert!(!verify_score(&vk, &roots, b"alice", 3, 1_000, &proof).unwrap());
+
+    // the season-1 witness can't be paired with the season-2 root either
+    let mut circuit = witness.threshold_circuit(params, 1_000);
+    circuit.root = roots[&2];
+    circuit.season = 2;
+    expect_unsatisfied(circuit, "membership");
+}
+
+#[test]
+fn test_leaderboard_threshold_above_score() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let mut rng = ark_std::test_rng();
+    let leaderboard = test_leaderboard();
+    let params = &leaderboard.params;
+    let witness = leaderboard.witness(b"bob", 1).unwrap();
+
+    assert_satisfied_with_report(witness.threshold_circuit(params, 800));
+    expect_unsatisfied(witness.threshold_circuit(params, 801), "threshold");
+
+    let (pk, _) = leaderboard.setup(&mut rng).unwrap();
+    assert!(witness.prove(params, &pk, 801, &mut rng).is_err());
+}
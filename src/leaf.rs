@@ -0,0 +1,165 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use ark_ff::ToBytes;
+use ark_std::io::{Result as IoResult, Write};
+use ark_std::vec::Vec;
+
+// The bytes of a tree leaf.
+//
+// Anything that keeps leaves accepts `impl Into<Leaf>`, so plain `Vec<u8>`s
+// and byte slices keep working, and an owned `Vec<u8>` moves in without a
+// copy. What only hashes leaves, like `MerkleParams::build_tree` and
+// `verify_path`, takes `impl AsRef<[u8]>` and borrows them as `LeafRef`s
+// instead: building a tree over `&leaves` copies none of their bytes. Neither
+// kind of conversion checks anything; the length is checked when the leaf
+// is hashed, or up front by `from_raw_checked`.
+// Prefer `keccak_of` over hex-encoding a digest into a leaf: the hex string is
+// twice as long and no more unique.
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Leaf(Vec<u8>);
+
+impl Leaf {
+    /// Wraps `bytes`, failing if they don't fit the leaf hash.
+    pub fn from_raw_checked(bytes: impl Into<Vec<u8>>) -> Result<Self, MerkleError> {
+        let leaf = Leaf(bytes.into());
+        leaf.check()?;
+        Ok(leaf)
+    }
+
+    /// The 32-byte keccak256 digest of `data`, as a leaf.
+    #[cfg(feature = "std")]
+    pub fn keccak_of(data: &[u8]) -> Self {
+        Leaf(keccak256(data).to_vec())
+    }
+
+    /// Decodes a hex-encoded leaf, failing on bad hex or if it doesn't fit the leaf hash.
+    pub fn from_hex(hex: &str) -> Result<Self, MerkleError> {
+        Self::from_raw_checked(from_hex(hex).ok_or(MerkleError::InvalidHex)?)
+    }
+
+    /// Fails with the offending and the maximum length if the leaf doesn't fit the leaf hash.
+    pub fn check(&self) -> Result<(), MerkleError> {
+        if self.0.len() > MAX_LEAF_LEN {
+            return Err(MerkleError::LeafTooLong {
+                len: self.0.len(),
+                max: MAX_LEAF_LEN,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl AsRef<[u8]> for Leaf {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for Leaf {
+    fn from(bytes: Vec<u8>) -> Self {
+        Leaf(bytes)
+    }
+}
+
+impl From<&Vec<u8>> for Leaf {
+    fn from(bytes: &Vec<u8>) -> Self {
+        Leaf(bytes.clone())
+    }
+}
+
+impl From<&[u8]> for Leaf {
+    fn from(bytes: &[u8]) -> Self {
+        Leaf(bytes.to_vec())
+    }
+}
+
+impl From<&Leaf> for Leaf {
+    fn from(leaf: &Leaf) -> Self {
+        leaf.clone()
+    }
+}
+
+impl From<Leaf> for Vec<u8> {
+    fn from(leaf: Leaf) -> Self {
+        leaf.0
+    }
+}
+
+// Leaf hashing goes through `to_bytes!`; this writes the raw bytes, exactly
+// like the `Vec<u8>` leaves the tree used to be built from.
+impl ToBytes for Leaf {
+    fn write<W: Write>(&self, mut writer: W) -> IoResult<()> {
+        writer.write_all(&self.0)
+    }
+}
+
+// A borrowed leaf, for hashing without a copy. Writes the same bytes as the
+// `Leaf` of the same slice.
+pub(crate) struct LeafRef<'a>(pub(crate) &'a [u8]);
+
+impl ToBytes for LeafRef<'_> {
+    fn write<W: Write>(&self, mut writer: W) -> IoResult<()> {
+        writer.write_all(self.0)
+    }
+}
+
+#[test]
+fn test_leaf_length_boundary() {
+    assert_eq!(Leaf::from_raw_checked(vec![7u8; MAX_LEAF_LEN]).unwrap().len(), MAX_LEAF_LEN);
+    assert!(Leaf::from_raw_checked(Vec::new()).unwrap().is_empty());
+    match Leaf::from_raw_checked(vec![7u8; MAX_LEAF_LEN + 1]) {
+        Err(MerkleError::LeafTooLong { len, max }) => {
+            assert_eq!(len, MAX_LEAF_LEN + 1);
+            assert_eq!(max, MAX_LEAF_LEN);
+        }
+        _ => panic!("a leaf one byte too long was accepted"),
+    }
+
+    // unchecked conversions defer the check to hashing
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves = vec![vec![1u8; MAX_LEAF_LEN + 1], vec![2u8]];
+    assert!(matches!(
+        params.build_tree(&leaves).unwrap_err().root_cause(),
+        MerkleError::LeafTooLong { len, max } if *len == MAX_LEAF_LEN + 1 && *max == MAX_LEAF_LEN
+    ));
+}
+
+#[test]
+fn test_leaf_hex_and_keccak() {
+    let leaf = Leaf::from_hex("00ff10Ab").unwrap();
+    assert_eq!(leaf.as_ref(), &[0x00, 0xff, 0x10, 0xab][..]);
+    assert_eq!(Leaf::from_hex(&to_hex(leaf.as_ref())).unwrap(), leaf);
+    assert!(matches!(Leaf::from_hex("abc"), Err(MerkleError::InvalidHex)));
+    assert!(matches!(Leaf::from_hex("zz"), Err(MerkleError::InvalidHex)));
+    assert!(matches!(
+        Leaf::from_hex(&to_hex(&[0u8; MAX_LEAF_LEN + 1])),
+        Err(MerkleError::LeafTooLong { .. })
+    ));
+
+    #[cfg(feature = "std")]
+    {
+        let digest = Leaf::keccak_of(&[10u8]);
+        assert_eq!(digest.len(), 32);
+        assert_eq!(digest.as_ref(), &keccak256(&[10u8])[..]);
+    }
+
+    // a `Leaf` hashes like the `Vec<u8>` it wraps
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let raw: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let wrapped: Vec<Leaf> = raw.iter().map(Leaf::from).collect();
+    let tree = crate::SimpleMerkleTree::new(&params.leaf_crh_params, &params.two_to_one_crh_params, &raw).unwrap();
+    assert_eq!(params.build_tree(wrapped).unwrap().root(), tree.root());
+}
@@ -0,0 +1,355 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::leaf::Leaf;
+use ark_crypto_primitives::merkle_tree::{Config, MerkleTree, Path};
+use ark_crypto_primitives::crh::TwoToOneCRH;
+use ark_serialize::CanonicalSerialize;
+use ark_std::vec::Vec;
+
+// How application data becomes leaf bytes.
+//
+// A tree and the circuits proving membership in it have to encode their data
+// the same way, or the circuit's public leaf won't be the leaf that was
+// hashed. A `LeafEncoder` is that choice, made once: trees built with
+// `build_tree_with` and circuits built from `encode_leaf` of the same encoder
+// agree by construction. Every encoder checks the result against the leaf
+// hash's capacity.
+//
+// Structured data goes through `ToLeafBytes` first, which fixes one encoding
+// per type: integers little-endian at their full width, byte strings after
+// their length as a u32, and structs as their fields in declaration order
+// (`impl_to_leaf_bytes!` writes that impl). Every field either has a fixed
+// width or says how long it is, so two values of a type encode alike only if
+// they are equal. Types that already have a canonical byte form, field
+// elements, curve points and the structs arkworks derives it for, wrap it in
+// `Canonical` to encode as their `CanonicalSerialize` bytes.
+
+pub trait LeafEncoder {
+    fn encode_leaf(&self, data: &[u8]) -> Result<Leaf, MerkleError>;
+}
+
+/// The data itself, if it fits the leaf hash.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RawLeaf;
+
+impl LeafEncoder for RawLeaf {
+    fn encode_leaf(&self, data: &[u8]) -> Result<Leaf, MerkleError> {
+        Leaf::from_raw_checked(data)
+    }
+}
+
+/// The 32 bytes of the keccak256 digest of the data, like `Leaf::keccak_of`.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Keccak256Leaf;
+
+#[cfg(feature = "std")]
+impl LeafEncoder for Keccak256Leaf {
+    fn encode_leaf(&self, data: &[u8]) -> Result<Leaf, MerkleError> {
+        Ok(Leaf::keccak_of(data))
+    }
+}
+
+/// The lowercase hex string of the keccak256 digest, as ASCII bytes: the 64-byte
+/// leaves of the original tutorial. Only for reproducing roots built with them;
+/// new trees should use `Keccak256Leaf`.
+#[cfg(feature = "legacy")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LegacyHexLeaf;
+
+#[cfg(feature = "legacy")]
+impl LeafEncoder for LegacyHexLeaf {
+    fn encode_leaf(&self, data: &[u8]) -> Result<Leaf, MerkleError> {
+        Leaf::from_raw_checked(to_hex(&keccak256(data)).into_bytes())
+    }
+}
+
+/// The canonical leaf encoding of a value.
+pub trait ToLeafBytes {
+    /// Appends the encoding of `self` to `out`.
+    fn write_leaf_bytes(&self, out: &mut Vec<u8>);
+
+    fn to_leaf_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_leaf_bytes(&mut out);
+        out
+    }
+
+    /// The encoding as a leaf, failing if it doesn't fit the leaf hash.
+    fn to_leaf(&self) -> Result<Leaf, MerkleError> {
+        Leaf::from_raw_checked(self.to_leaf_bytes())
+    }
+}
+
+macro_rules! impl_to_leaf_bytes_for_int {
+    ($($int:ty),*) => {
+        $(impl ToLeafBytes for $int {
+            fn write_leaf_bytes(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+        })*
+    };
+}
+
+impl_to_leaf_bytes_for_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl ToLeafBytes for bool {
+    fn write_leaf_bytes(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+/// Fixed-width, so without a length.
+impl<const N: usize> ToLeafBytes for [u8; N] {
+    fn write_leaf_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl ToLeafBytes for [u8] {
+    fn write_leaf_bytes(&self, out: &mut Vec<u8>) {
+        // a leaf is far shorter than 4 GiB
+        (self.len() as u32).write_leaf_bytes(out);
+        out.extend_from_slice(self);
+    }
+}
+
+impl ToLeafBytes for Vec<u8> {
+    fn write_leaf_bytes(&self, out: &mut Vec<u8>) {
+        self.as_slice().write_leaf_bytes(out)
+    }
+}
+
+impl ToLeafBytes for str {
+    fn write_leaf_bytes(&self, out: &mut Vec<u8>) {
+        self.as_bytes().write_leaf_bytes(out)
+    }
+}
+
+impl<T: ToLeafBytes + ?Sized> ToLeafBytes for &T {
+    fn write_leaf_bytes(&self, out: &mut Vec<u8>) {
+        (**self).write_leaf_bytes(out)
+    }
+}
+
+/// A value encoded as its compressed `CanonicalSerialize` bytes, which have a
+/// fixed width for field elements and points and carry the lengths of what
+/// doesn't.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Canonical<T>(pub T);
+
+impl<T: CanonicalSerialize> ToLeafBytes for Canonical<T> {
+    fn write_leaf_bytes(&self, out: &mut Vec<u8>) {
+        // writing to a Vec can't fail
+        self.0.serialize(out).expect("serializing into a Vec");
+    }
+}
+
+/// Implements `ToLeafBytes` for a struct as the encodings of the listed fields,
+/// in order; list them in declaration order.
+///
+/// ```ignore
+/// impl_to_leaf_bytes!(Account { id, balance, pubkey });
+/// ```
+#[macro_export]
+macro_rules! impl_to_leaf_bytes {
+    ($name:ident { $($field:ident),+ $(,)? }) => {
+        impl $crate::leaf_encoding::ToLeafBytes for $name {
+            fn write_leaf_bytes(&self, out: &mut $crate::leaf_encoding::__Vec<u8>) {
+                $($crate::leaf_encoding::ToLeafBytes::write_leaf_bytes(&self.$field, out);)+
+            }
+        }
+    };
+}
+
+// for `impl_to_leaf_bytes!` in `no_std` crates, which have no `Vec` in scope
+#[doc(hidden)]
+pub use ark_std::vec::Vec as __Vec;
+
+/// The account a leaf of the tutorial's account tree stands for: 48 bytes,
+/// the id, the balance and the public key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Account {
+    pub id: u64,
+    pub balance: u64,
+    pub pubkey: [u8; 32],
+}
+
+impl_to_leaf_bytes!(Account { id, balance, pubkey });
+
+impl<P: Config> TreeParams<P> {
+    /// Builds the tree over `data`, each item encoded with `encoder`; their
+    /// number must be a power of two.
+    pub fn build_tree_with<'a>(
+        &self,
+        encoder: &impl LeafEncoder,
+        data: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<MerkleTree<P>, MerkleError> {
+        let leaves = data
+            .into_iter()
+            .map(|item| encoder.encode_leaf(item))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.build_tree(leaves)
+    }
+
+    /// Builds the tree over the `ToLeafBytes` encodings of `items`; their
+    /// number must be a power of two.
+    pub fn build_tree_of<T: ToLeafBytes>(&self, items: &[T]) -> Result<MerkleTree<P>, MerkleError> {
+        self.build_tree(items.iter().map(ToLeafBytes::to_leaf_bytes))
+    }
+
+    /// `verify_path` for the `ToLeafBytes` encoding of `item`.
+    pub fn verify_item_path<T: ToLeafBytes + ?Sized>(
+        &self,
+        path: &Path<P>,
+        root: &<P::TwoToOneHash as TwoToOneCRH>::Output,
+        item: &T,
+    ) -> Result<bool, MerkleError> {
+        self.verify_path(path, root, item.to_leaf_bytes())
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl crate::constraints::MTreeVerification {
+    /// The circuit proving that the `ToLeafBytes` encoding of `item`, the leaf
+    /// `MerkleParams::build_tree_of` hashed for it, opens `auth_path`. Fails
+    /// if the encoding doesn't fit the leaf hash.
+    pub fn for_item<T: ToLeafBytes + ?Sized>(
+        params: &MerkleParams,
+        root: crate::Root,
+        item: &T,
+        auth_path: crate::SimplePath,
+    ) -> Result<Self, MerkleError> {
+        Ok(Self::new(params, root, item.to_leaf()?, auth_path))
+    }
+}
+
+#[test]
+fn test_leaf_encoders() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let data: Vec<&[u8]> = vec![&b"alice"[..], &b"bob"[..], &b"carol"[..], &b"dave"[..]];
+
+    let tree = params.build_tree_with(&RawLeaf, data.iter().copied()).unwrap();
+    let leaf = RawLeaf.encode_leaf(data[2]).unwrap();
+    assert!(params.verify_path(&tree.generate_proof(2).unwrap(), &tree.root(), leaf).unwrap());
+    assert!(matches!(
+        RawLeaf.encode_leaf(&[0u8; MAX_LEAF_LEN + 1]),
+        Err(MerkleError::LeafTooLong { .. })
+    ));
+
+    #[cfg(feature = "std")]
+    {
+        let tree = params.build_tree_with(&Keccak256Leaf, data.iter().copied()).unwrap();
+        let leaf = Keccak256Leaf.encode_leaf(data[1]).unwrap();
+        assert_eq!(leaf.as_ref(), &keccak256(b"bob")[..]);
+        assert!(params.verify_path(&tree.generate_proof(1).unwrap(), &tree.root(), leaf).unwrap());
+        // the circuit takes the same encoded leaf
+        #[cfg(feature = "constraints")]
+        {
+            use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+            let circuit = <crate::constraints::MTreeVerification>::new(
+                &params,
+                tree.root(),
+                Keccak256Leaf.encode_leaf(data[1]).unwrap(),
+                tree.generate_proof(1).unwrap(),
+            );
+            let cs = ConstraintSystem::new_ref();
+            circuit.generate_constraints(cs.clone()).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+        }
+    }
+}
+
+#[test]
+fn test_account_encoding_is_canonical() {
+    let account = Account {
+        id: 7,
+        balance: 1_000,
+        pubkey: [9u8; 32],
+    };
+    let bytes = account.to_leaf_bytes();
+    assert_eq!(bytes.len(), 48);
+    assert_eq!(&bytes[..8], &7u64.to_le_bytes());
+    assert_eq!(account.to_leaf().unwrap().as_ref(), &bytes[..]);
+
+    // the same account with its fields declared in another order is another leaf
+    struct ReorderedAccount {
+        balance: u64,
+        id: u64,
+        pubkey: [u8; 32],
+    }
+    impl_to_leaf_bytes!(ReorderedAccount { balance, id, pubkey });
+    let reordered = ReorderedAccount {
+        balance: account.balance,
+        id: account.id,
+        pubkey: account.pubkey,
+    };
+    assert_ne!(reordered.to_leaf_bytes(), bytes);
+    assert_ne!(Account { id: 1_000, balance: 7, ..account }.to_leaf_bytes(), bytes);
+
+    // byte strings carry their length, so where one ends is never ambiguous
+    struct Pair {
+        first: Vec<u8>,
+        second: Vec<u8>,
+    }
+    impl_to_leaf_bytes!(Pair { first, second });
+    let split = |at: usize| Pair {
+        first: b"abcd"[..at].to_vec(),
+        second: b"abcd"[at..].to_vec(),
+    };
+    assert_ne!(split(1).to_leaf_bytes(), split(2).to_leaf_bytes());
+    assert_eq!("ab".to_leaf_bytes(), vec![2, 0, 0, 0, b'a', b'b']);
+}
+
+#[test]
+fn test_trees_of_items() {
+    use ark_ed_on_bls12_381::Fq;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let accounts: Vec<Account> = (0..4)
+        .map(|id| Account {
+            id,
+            balance: 100 * id,
+            pubkey: [id as u8; 32],
+        })
+        .collect();
+    let tree = params.build_tree_of(&accounts).unwrap();
+    let path = tree.generate_proof(2).unwrap();
+    assert!(params.verify_item_path(&path, &tree.root(), &accounts[2]).unwrap());
+    assert!(!params.verify_item_path(&path, &tree.root(), &accounts[3]).unwrap());
+
+    // field elements encode as their 32 canonical bytes, not a hex string of them
+    let fields: Vec<Canonical<Fq>> = (0u64..4).map(|i| Canonical(Fq::from(i))).collect();
+    assert_eq!(fields[1].to_leaf_bytes().len(), 32);
+    let tree = params.build_tree_of(&fields).unwrap();
+    assert!(params.verify_item_path(&tree.generate_proof(1).unwrap(), &tree.root(), &fields[1]).unwrap());
+
+    #[cfg(feature = "constraints")]
+    {
+        use crate::circuit_report::assert_satisfied_with_report;
+        use crate::constraints::MTreeVerification;
+
+        let circuit = <MTreeVerification>::for_item(&params, tree.root(), &fields[1], tree.generate_proof(1).unwrap());
+        assert_satisfied_with_report(circuit.unwrap());
+        let long = vec![0u8; MAX_LEAF_LEN];
+        let path = tree.generate_proof(0).unwrap();
+        assert!(<MTreeVerification>::for_item(&params, tree.root(), &long, path).is_err());
+    }
+}
+
+// The original tutorial built its trees over `Sha3::keccak256` digests as hex
+// strings (`result_str`); this pins that encoding.
+#[cfg(feature = "legacy")]
+#[test]
+fn test_legacy_hex_leaves() {
+    let leaf = LegacyHexLeaf.encode_leaf(&[10u8]).unwrap();
+    assert_eq!(leaf.len(), 64);
+    assert_eq!(leaf.as_ref(), to_hex(&keccak256(&[10u8])).as_bytes());
+    assert!(leaf.as_ref().iter().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()));
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let values: Vec<[u8; 1]> = [1u8, 2, 3, 10, 9, 17, 70, 45].iter().map(|v| [*v]).collect();
+    let hex_leaves: Vec<Vec<u8>> = values.iter().map(|v| to_hex(&keccak256(v)).into_bytes()).collect();
+    let tree = params.build_tree_with(&LegacyHexLeaf, values.iter().map(|v| &v[..])).unwrap();
+    assert_eq!(tree.root(), params.build_tree(&hex_leaves).unwrap().root());
+}
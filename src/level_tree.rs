@@ -0,0 +1,535 @@
+use crate::backend::MerkleBackend;
+use crate::bundle::{fingerprint, Fingerprint};
+use crate::common::*;
+use crate::error::{ErrorContext, MerkleError, ResultExt};
+use crate::index::LeafIndex;
+use crate::leaf::Leaf;
+use crate::walkthrough::digest_hex;
+use crate::{Root, SimplePath};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::io::{Read, Write};
+use ark_std::string::String;
+use ark_std::vec::Vec;
+
+// A tree that keeps every level of digests, for looking inside it.
+//
+// `SimpleMerkleTree` keeps its nodes to itself; `LevelTree` stores them level
+// by level, the leaf hashes at level 0 and the root alone at level `depth()`,
+// and answers `levels`, `node` and `print_levels` straight from that storage.
+// Its paths are read off the stored levels and its updates rehash only the
+// path of the changed leaf. The digests are computed with the same
+// `MerkleParams::hash_leaf`/`hash_children` the rest of the crate uses, and
+// `backend::conformance` checks they are those of `SimpleMerkleTree`. With
+// the `parallel` feature each level is hashed on the rayon pool, the nodes of
+// a level being independent of each other.
+//
+// `save_to` writes the whole tree, leaves and every level of digests, so
+// `load_from` hands it back without hashing anything: the magic `MTLT` and
+// the format version, then the number of leaves as a u64, each leaf as its
+// length as a u64 and its bytes, and the digests level by level from the leaf
+// hashes up, each in its canonical encoding. The number of leaves fixes the
+// size of every level, so none is written. With `verify_integrity`, the
+// loaded leaves are hashed again and every stored digest is compared against
+// the rebuilt one, which costs as much as building the tree.
+//
+// `export_snapshot` wraps the same body in a header for exchanging trees
+// between services, which may not share a configuration:
+//
+//     magic        4 bytes  `MTSN`
+//     version      1 byte   `SNAPSHOT_FORMAT_VERSION`
+//     hash config  1 byte of length, then that many bytes of ASCII,
+//                           `SNAPSHOT_HASH_CONFIG` for the Pedersen trees
+//     params       32 bytes `bundle::Fingerprint` of the hash parameters
+//     body         as `save_to` writes it after its version: the leaf count
+//                  as a u64, the leaves, the digests level by level
+//
+// `import_snapshot` reads the header first, with `read_snapshot_header`, and
+// refuses a snapshot of another version, another hash configuration or other
+// parameters before reading any leaf, since every digest of such a tree would
+// be wrong here.
+
+/// The first bytes of a saved `LevelTree`.
+pub const LEVEL_TREE_MAGIC: [u8; 4] = *b"MTLT";
+
+/// The version of the format `LevelTree::save_to` writes.
+pub const LEVEL_TREE_FORMAT_VERSION: u8 = 1;
+
+/// The first bytes of a snapshot, as `LevelTree::export_snapshot` writes it.
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"MTSN";
+
+/// The version of the snapshot format.
+pub const SNAPSHOT_FORMAT_VERSION: u8 = 1;
+
+/// The hash configuration of the trees of this crate, as snapshots name it.
+pub const SNAPSHOT_HASH_CONFIG: &str = "pedersen-jubjub/v1";
+
+/// What a snapshot says about the tree in it, before the tree itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub version: u8,
+    pub hash_config: String,
+    pub params_fingerprint: Fingerprint,
+}
+
+/// Reads the header of a snapshot, leaving `reader` at its body. Fails on
+/// anything but a snapshot.
+pub fn read_snapshot_header<R: Read>(mut reader: R) -> Result<SnapshotHeader, MerkleError> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != SNAPSHOT_MAGIC {
+        return Err(SerializationError::InvalidData.into());
+    }
+    let mut version_and_len = [0u8; 2];
+    reader.read_exact(&mut version_and_len)?;
+    let mut hash_config = vec![0u8; version_and_len[1] as usize];
+    reader.read_exact(&mut hash_config)?;
+    let hash_config = String::from_utf8(hash_config).map_err(|_| SerializationError::InvalidData)?;
+    let mut params_fingerprint = [0u8; 32];
+    reader.read_exact(&mut params_fingerprint)?;
+    Ok(SnapshotHeader {
+        version: version_and_len[0],
+        hash_config,
+        params_fingerprint,
+    })
+}
+
+#[derive(Clone)]
+pub struct LevelTree {
+    params: MerkleParams,
+    leaves: Vec<Leaf>,
+    // levels[0] are the leaf hashes, levels[depth] is [root]
+    levels: Vec<Vec<Root>>,
+}
+
+impl LevelTree {
+    /// The tree over `leaves`; their number must be a power of two, at least two.
+    pub fn new<L: Into<Leaf>>(params: MerkleParams, leaves: impl IntoIterator<Item = L>) -> Result<Self, MerkleError> {
+        let leaves: Vec<Leaf> = leaves.into_iter().map(Into::into).collect();
+        assert!(
+            leaves.len().is_power_of_two() && leaves.len() > 1,
+            "a tree needs a power of two leaves, at least two"
+        );
+        let mut levels = vec![try_map_indices(leaves.len(), |index| {
+            params
+                .hash_leaf(leaves[index].as_ref())
+                .with_context(|| ErrorContext::new("build_levels").index(index))
+        })?];
+        while levels[levels.len() - 1].len() > 1 {
+            let level = levels.len();
+            let below = &levels[level - 1];
+            let parents = try_map_indices(below.len() / 2, |index| {
+                params
+                    .hash_children(&below[2 * index], &below[2 * index + 1])
+                    .with_context(|| ErrorContext::new("build_levels").index(index).level(level))
+            })?;
+            levels.push(parents);
+        }
+        Ok(LevelTree { params, leaves, levels })
+    }
+
+    /// A tree of `capacity` leaves, rounded up to a power of two, all set to `padding`.
+    pub fn with_capacity(params: MerkleParams, capacity: usize, padding: impl Into<Leaf>) -> Result<Self, MerkleError> {
+        let padding = padding.into();
+        Self::new(params, vec![padding; capacity.next_power_of_two().max(2)])
+    }
+
+    /// The number of levels above the leaves.
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    /// Every level, from the leaf hashes up to the root.
+    pub fn levels(&self) -> impl Iterator<Item = &[Root]> {
+        self.levels.iter().map(Vec::as_slice)
+    }
+
+    /// The node at `index` of `level`, counting levels from the leaves at 0
+    /// and nodes from the left at 0.
+    pub fn node(&self, level: usize, index: usize) -> Option<Root> {
+        self.levels.get(level)?.get(index).copied()
+    }
+
+    /// The tree as lines of hex, root first, each at most `max_width`
+    /// characters wide. Digests are shortened to fit; on levels too wide even
+    /// for one hex digit per node, only the nodes that fit are shown.
+    pub fn print_levels(&self, max_width: usize) -> String {
+        let width = self.leaves.len();
+        // digits per leaf-level node, with a space between nodes
+        let digits = (max_width / width).saturating_sub(1).clamp(1, 16);
+        let cell = digits + 1;
+        let mut out = String::new();
+        for level in self.levels.iter().rev() {
+            let span = width / level.len() * cell;
+            let mut line = String::new();
+            for node in level {
+                let hex = digest_hex(node).unwrap_or_default();
+                let text = &hex[..digits.min(hex.len())];
+                let pad = span.saturating_sub(text.len());
+                let piece = format!("{}{}{}", " ".repeat(pad / 2), text, " ".repeat(pad - pad / 2));
+                if line.len() + piece.len() > max_width {
+                    line.push('…');
+                    break;
+                }
+                line += &piece;
+            }
+            out += line.trim_end();
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Replaces the leaf at `index`, rehashing only its path, and returns the
+    /// roots before and after the update, for state-transition statements.
+    pub fn update_leaf(&mut self, index: usize, new_leaf: &[u8]) -> Result<(Root, Root), MerkleError> {
+        let old_root = self.root();
+        let new_root = self.update(index, Leaf::from(new_leaf))?;
+        Ok((old_root, new_root))
+    }
+
+    pub fn params(&self) -> &MerkleParams {
+        &self.params
+    }
+
+    pub fn leaf(&self, index: usize) -> Option<&Leaf> {
+        self.leaves.get(index)
+    }
+
+    fn check_index(&self, index: usize) -> Result<LeafIndex, MerkleError> {
+        LeafIndex::checked_new(index as u64, self.leaves.len() as u64)
+    }
+
+    /// Writes the tree in the format `load_from` reads.
+    pub fn save_to<W: Write>(&self, mut writer: W) -> Result<(), MerkleError> {
+        writer.write_all(&LEVEL_TREE_MAGIC)?;
+        writer.write_all(&[LEVEL_TREE_FORMAT_VERSION])?;
+        self.write_body(writer)
+    }
+
+    /// Writes the tree as a snapshot, for `import_snapshot` to read with the
+    /// same parameters, here or in another service.
+    pub fn export_snapshot<W: Write>(&self, mut writer: W) -> Result<(), MerkleError> {
+        writer.write_all(&SNAPSHOT_MAGIC)?;
+        writer.write_all(&[SNAPSHOT_FORMAT_VERSION, SNAPSHOT_HASH_CONFIG.len() as u8])?;
+        writer.write_all(SNAPSHOT_HASH_CONFIG.as_bytes())?;
+        writer.write_all(&fingerprint(&self.params)?)?;
+        self.write_body(writer)
+    }
+
+    /// Reads a snapshot written by `export_snapshot`. Fails with
+    /// `UnknownVersion` on a snapshot of a later format and `ParamsMismatch`
+    /// on one of another hash configuration or other parameters than
+    /// `params`; `verify_integrity` is that of `load_from`.
+    pub fn import_snapshot<R: Read>(
+        mut reader: R,
+        params: MerkleParams,
+        verify_integrity: bool,
+    ) -> Result<Self, MerkleError> {
+        let header = read_snapshot_header(&mut reader)?;
+        if header.version != SNAPSHOT_FORMAT_VERSION {
+            return Err(MerkleError::UnknownVersion {
+                version: header.version as usize,
+                latest: SNAPSHOT_FORMAT_VERSION as usize,
+            });
+        }
+        if header.hash_config != SNAPSHOT_HASH_CONFIG || header.params_fingerprint != fingerprint(&params)? {
+            return Err(MerkleError::ParamsMismatch);
+        }
+        Self::read_body(reader, params, verify_integrity)
+    }
+
+    // The leaf count, the leaves and every level of digests.
+    fn write_body<W: Write>(&self, mut writer: W) -> Result<(), MerkleError> {
+        (self.leaves.len() as u64).serialize(&mut writer)?;
+        for leaf in &self.leaves {
+            (leaf.len() as u64).serialize(&mut writer)?;
+            writer.write_all(leaf.as_ref())?;
+        }
+        for digest in self.levels.iter().flatten() {
+            digest.serialize(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a tree written by `save_to` with the same `params`. With
+    /// `verify_integrity`, rebuilds it from the leaves and fails with
+    /// `CorruptTree` on the first stored digest that differs.
+    pub fn load_from<R: Read>(mut reader: R, params: MerkleParams, verify_integrity: bool) -> Result<Self, MerkleError> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        if header[..4] != LEVEL_TREE_MAGIC || header[4] != LEVEL_TREE_FORMAT_VERSION {
+            return Err(SerializationError::InvalidData.into());
+        }
+        Self::read_body(reader, params, verify_integrity)
+    }
+
+    fn read_body<R: Read>(mut reader: R, params: MerkleParams, verify_integrity: bool) -> Result<Self, MerkleError> {
+        let len = u64::deserialize(&mut reader)? as usize;
+        if !len.is_power_of_two() || len < 2 {
+            return Err(SerializationError::InvalidData.into());
+        }
+        // the counts come from the file, so nothing is allocated up front
+        let mut leaves = Vec::new();
+        for _ in 0..len {
+            let leaf_len = u64::deserialize(&mut reader)? as usize;
+            if leaf_len > MAX_LEAF_LEN {
+                return Err(SerializationError::InvalidData.into());
+            }
+            let mut leaf = vec![0u8; leaf_len];
+            reader.read_exact(&mut leaf)?;
+            leaves.push(Leaf::from(leaf));
+        }
+        let mut levels = Vec::new();
+        let mut width = len;
+        while width > 0 {
+            let level = (0..width)
+                .map(|_| Root::deserialize(&mut reader))
+                .collect::<Result<Vec<_>, _>>()?;
+            levels.push(level);
+            width /= 2;
+        }
+
+        if verify_integrity {
+            let rebuilt = LevelTree::new(params.clone(), leaves.iter())?;
+            for (level, (stored, expected)) in levels.iter().zip(&rebuilt.levels).enumerate() {
+                if let Some(index) = (0..stored.len()).find(|index| stored[*index] != expected[*index]) {
+                    return Err(MerkleError::CorruptTree { level, index });
+                }
+            }
+        }
+        Ok(LevelTree { params, leaves, levels })
+    }
+}
+
+impl MerkleBackend for LevelTree {
+    fn root(&self) -> Root {
+        self.levels[self.depth()][0]
+    }
+
+    fn prove(&self, index: usize) -> Result<SimplePath, MerkleError> {
+        let index = self.check_index(index)?.as_usize();
+        Ok(SimplePath {
+            leaf_sibling_hash: self.levels[0][index ^ 1],
+            // top-down, and neither the leaf level nor the root
+            auth_path: (1..self.depth())
+                .rev()
+                .map(|level| self.levels[level][(index >> level) ^ 1])
+                .collect(),
+            leaf_index: index,
+        })
+    }
+
+    fn update(&mut self, index: usize, leaf: Leaf) -> Result<Root, MerkleError> {
+        self.check_index(index)?;
+        // rehash the path into a copy first, so a failure leaves the tree alone
+        let mut path = vec![self
+            .params
+            .hash_leaf(leaf.as_ref())
+            .with_context(|| ErrorContext::new("update").index(index))?];
+        for level in 1..self.levels.len() {
+            let child = index >> (level - 1);
+            let sibling = self.levels[level - 1][child ^ 1];
+            let current = path[level - 1];
+            let (left, right) = if child & 1 == 1 { (sibling, current) } else { (current, sibling) };
+            path.push(
+                self.params
+                    .hash_children(&left, &right)
+                    .with_context(|| ErrorContext::new("update").index(index).level(level))?,
+            );
+        }
+        for (level, digest) in path.into_iter().enumerate() {
+            self.levels[level][index >> level] = digest;
+        }
+        self.leaves[index] = leaf;
+        Ok(self.root())
+    }
+
+    fn leaf_count(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+}
+
+#[test]
+fn test_level_tree_conformance() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    crate::backend::conformance::check(&params, |params, leaves| LevelTree::new(params.clone(), leaves));
+}
+
+#[test]
+fn test_level_tree_inspection() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    // five leaves padded to eight
+    let mut tree = LevelTree::with_capacity(params.clone(), 5, Leaf::default()).unwrap();
+    tree.update(1, Leaf::from(vec![1u8; 4])).unwrap();
+    assert_eq!(tree.depth(), 3);
+
+    let widths: Vec<usize> = tree.levels().map(|level| level.len()).collect();
+    assert_eq!(widths, vec![8, 4, 2, 1]);
+    assert_eq!(tree.node(tree.depth(), 0), Some(tree.root()));
+    assert_eq!(tree.node(tree.depth(), 1), None);
+    assert_eq!(tree.node(tree.depth() + 1, 0), None);
+    assert_eq!(tree.node(0, 1), Some(params.hash_leaf(&[1u8; 4]).unwrap()));
+    for (level, nodes) in tree.levels().enumerate().skip(1) {
+        for (index, node) in nodes.iter().enumerate() {
+            let left = tree.node(level - 1, 2 * index).unwrap();
+            let right = tree.node(level - 1, 2 * index + 1).unwrap();
+            assert_eq!(*node, params.hash_children(&left, &right).unwrap());
+        }
+    }
+
+    let text = tree.print_levels(80);
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines.iter().all(|line| line.chars().count() <= 80));
+    let root_hex = digest_hex(&tree.root()).unwrap();
+    assert!(lines[0].trim().len() > 1 && root_hex.starts_with(lines[0].trim()));
+    assert_eq!(lines[3].split_whitespace().count(), 8);
+    // too narrow for every leaf
+    assert!(tree.print_levels(8).lines().last().unwrap().ends_with('…'));
+}
+
+#[test]
+fn test_level_tree_update_leaf() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let mut tree = LevelTree::new(params.clone(), &leaves).unwrap();
+
+    // the first, the last and a middle leaf
+    for index in [0, 7, 3].iter().copied() {
+        let old_path = tree.prove(index).unwrap();
+        let before = tree.root();
+        leaves[index] = vec![0xff, index as u8];
+
+        let (old_root, new_root) = tree.update_leaf(index, &leaves[index]).unwrap();
+        assert_eq!(old_root, before);
+        assert_eq!(new_root, tree.root());
+        assert_eq!(new_root, params.build_tree(&leaves).unwrap().root());
+        // a proof from before the update doesn't open the new root
+        assert!(!params.verify_path(&old_path, &new_root, &leaves[index]).unwrap());
+        assert!(params.verify_path(&tree.prove(index).unwrap(), &new_root, &leaves[index]).unwrap());
+    }
+}
+
+#[test]
+fn test_level_tree_save_and_load() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u32..1 << 12).map(|i| i.to_le_bytes().to_vec()).collect();
+    let tree = LevelTree::new(params.clone(), &leaves).unwrap();
+    let mut saved = Vec::new();
+    tree.save_to(&mut saved).unwrap();
+
+    let loaded = LevelTree::load_from(&saved[..], params.clone(), true).unwrap();
+    assert_eq!(loaded.root(), tree.root());
+    assert_eq!(loaded.levels, tree.levels);
+    assert_eq!(loaded.leaves, tree.leaves);
+    for index in [0, 1, 2047, 4095].iter().copied() {
+        let (before, after) = (tree.prove(index).unwrap(), loaded.prove(index).unwrap());
+        assert_eq!(after.leaf_index, before.leaf_index);
+        assert_eq!(after.leaf_sibling_hash, before.leaf_sibling_hash);
+        assert_eq!(after.auth_path, before.auth_path);
+    }
+    assert!(params.verify_path(&loaded.prove(7).unwrap(), &tree.root(), &leaves[7]).unwrap());
+}
+
+#[test]
+fn test_level_tree_load_rejects_a_corrupt_file() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let mut saved = Vec::new();
+    LevelTree::new(params.clone(), &leaves).unwrap().save_to(&mut saved).unwrap();
+    // magic, version, leaf count, then eight leaves of 8 + 4 bytes
+    let leaves_end = 4 + 1 + 8 + 8 * 12;
+    let digest_len = Root::default().serialized_size();
+    assert_eq!(saved.len(), leaves_end + 15 * digest_len);
+
+    // a flipped bit in the third leaf
+    let mut flipped = saved.clone();
+    flipped[4 + 1 + 8 + 2 * 12 + 8] ^= 1;
+    assert!(LevelTree::load_from(&flipped[..], params.clone(), false).is_ok());
+    assert!(matches!(
+        LevelTree::load_from(&flipped[..], params.clone(), true),
+        Err(MerkleError::CorruptTree { level: 0, index: 2 })
+    ));
+
+    // and in the first node above the leaves, in its lowest bit
+    let mut flipped = saved.clone();
+    flipped[leaves_end + 8 * digest_len] ^= 1;
+    assert!(matches!(
+        LevelTree::load_from(&flipped[..], params.clone(), true),
+        Err(MerkleError::CorruptTree { level: 1, index: 0 })
+    ));
+
+    let mut other_version = saved.clone();
+    other_version[4] = LEVEL_TREE_FORMAT_VERSION + 1;
+    assert!(LevelTree::load_from(&other_version[..], params.clone(), false).is_err());
+    assert!(LevelTree::load_from(&saved[..saved.len() - 1], params, false).is_err());
+}
+
+#[test]
+fn test_level_tree_snapshot() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = LevelTree::new(params.clone(), &leaves).unwrap();
+    let mut snapshot = Vec::new();
+    tree.export_snapshot(&mut snapshot).unwrap();
+
+    let header = read_snapshot_header(&snapshot[..]).unwrap();
+    assert_eq!(header.version, SNAPSHOT_FORMAT_VERSION);
+    assert_eq!(header.hash_config, SNAPSHOT_HASH_CONFIG);
+    assert_eq!(header.params_fingerprint, fingerprint(&params).unwrap());
+    // the header, then the body `save_to` writes after its own header
+    let mut saved = Vec::new();
+    tree.save_to(&mut saved).unwrap();
+    let header_len = 4 + 2 + SNAPSHOT_HASH_CONFIG.len() + 32;
+    assert_eq!(snapshot[header_len..], saved[5..]);
+
+    let imported = LevelTree::import_snapshot(&snapshot[..], params.clone(), true).unwrap();
+    assert_eq!(imported.levels, tree.levels);
+    assert_eq!(imported.leaves, tree.leaves);
+
+    // other parameters are refused before the tree is read
+    let other = MerkleParams::setup(&mut rng).unwrap();
+    assert!(matches!(
+        LevelTree::import_snapshot(&snapshot[..], other, false),
+        Err(MerkleError::ParamsMismatch)
+    ));
+    let mut later = snapshot.clone();
+    later[4] = SNAPSHOT_FORMAT_VERSION + 1;
+    assert!(matches!(
+        LevelTree::import_snapshot(&later[..], params.clone(), false),
+        Err(MerkleError::UnknownVersion { version: 2, latest: 1 })
+    ));
+    let mut renamed = snapshot.clone();
+    renamed[6] ^= 1;
+    assert!(matches!(
+        LevelTree::import_snapshot(&renamed[..], params.clone(), false),
+        Err(MerkleError::ParamsMismatch)
+    ));
+    // and a saved tree is no snapshot
+    assert!(LevelTree::import_snapshot(&saved[..], params, false).is_err());
+}
+
+// With `parallel`, `LevelTree` hashes on the rayon pool while arkworks builds
+// `SimpleMerkleTree` in order; the digests must not depend on which.
+#[test]
+fn test_parallel_build_is_deterministic() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u16..64).map(|i| i.to_le_bytes().to_vec()).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let levels = LevelTree::new(params.clone(), &leaves).unwrap();
+    assert_eq!(levels.root(), tree.root());
+    assert_eq!(LevelTree::new(params, &leaves).unwrap().levels, levels.levels);
+
+    let indices = [63, 0, 17, 17];
+    let paths = generate_proofs(&tree, &indices).unwrap();
+    for (index, path) in indices.iter().zip(paths.iter()) {
+        let expected = levels.prove(*index).unwrap();
+        assert_eq!(path.leaf_index, expected.leaf_index);
+        assert_eq!(path.leaf_sibling_hash, expected.leaf_sibling_hash);
+        assert_eq!(path.auth_path, expected.auth_path);
+    }
+    assert!(matches!(
+        generate_proofs(&tree, &[3, 64]),
+        Err(MerkleError::LeafIndexOutOfRange { index: 64, len: 64 })
+    ));
+}
@@ -1,20 +1,205 @@
 
+//! Merkle trees over Pedersen hashes, with R1CS membership circuits and the
+//! applications built on them. `common::poseidon` has the same trees and
+//! circuit over Poseidon, for much smaller circuits, `common::blake2s` over
+//! Blake2s, for roots and paths any Blake2s implementation can check, and
+//! `common::field` over leaves of field elements, hashed without any bytes.
+//!
+//! Features:
+//! - `std` (default): keccak256 and everything built on it (`schnorr`,
+//!   `signed_root`, `map`, `log`, `provenance`, the OpenZeppelin-compatible
+//!   trees of `eth`, the verification cache of `cache`) and the file-backed
+//!   trees of `storage`. Without it the crate is `no_std` + `alloc`, leaving
+//!   the native tree, paths and multiproofs (`multiproof`), the Blake2s
+//!   trees of `common::blake2s`, `append_only`, `incremental`, `mmr`,
+//!   `versioned`, `nullifier`, `serialization` and errors: enough to verify
+//!   proofs from bytes on an HSM or light client. Randomness always comes
+//!   from a caller-provided `RngCore + CryptoRng`; with `std`,
+//!   `rng::OsRngProvider` is one. The deterministic trees of `fixtures`
+//!   need only `std`, so integration tests have them without `testing`.
+//! - `constraints` (default, needs `std`): the R1CS gadgets and circuits.
+//! - `snark` (default): Groth16, the EVM verifier export in `solidity`,
+//!   membership in two trees at once in `paired`, and the application modules.
+//! - `marlin`: membership proofs over Marlin in `marlin`, one universal
+//!   setup for every tree height up to a bound instead of one per circuit.
+//! - `recursion`: `recursion::AggregationCircuit` verifies membership proofs
+//!   over BLS12-377 inside a BW6-761 circuit, many of them in one proof.
+//! - `bn254`: the trees and membership circuit of `bn254` over Baby Jubjub,
+//!   with Groth16 proofs over BN254 that the EIP-197 precompiles can check.
+//! - `tracing`: spans around parameter setup, tree construction, proof
+//!   generation and verification (`MerkleParams::build_tree`,
+//!   `common::generate_proof`, `MerkleParams::verify_path`) and Groth16
+//!   proving and verifying.
+//! - `parallel`: `level_tree::LevelTree` hashes each level, and
+//!   `common::generate_proofs` opens its paths, on the rayon thread pool.
+//!   With `snark`, `batch::BatchProver` also makes Groth16 proofs in bulk on
+//!   a pool of a given size.
+//! - `server`: an axum HTTP service in `server` with `/root`,
+//!   `/proof/{index}`, `/verify` and `/snark/prove` over a shared in-memory
+//!   or file-backed tree.
+//! - `serde` (with `server`): `Serialize` and `Deserialize` for the
+//!   inclusion receipts of `receipt`, as objects of hex strings.
+//! - `mmap`: `mmap::MmapTree`, a read-only tree served from a memory-mapped
+//!   snapshot, for servers that should start without reading their tree.
+//! - `vectors`: JSON test vectors in `vectors` (parameter seeds, leaves,
+//!   roots, paths and public inputs) and `vectors::verify_vector_file`, for
+//!   checking other implementations against this one, and this one against
+//!   a golden file.
+//! - `legacy`: `leaf_encoding::LegacyHexLeaf`, the hex-string leaves of the
+//!   original tutorial.
+//! - `wasm`: wasm-bindgen bindings in `wasm` (`build_tree`, `prove`,
+//!   `verify`) over byte arrays, for proving membership in the browser;
+//!   `wasm-pack build --target web -- --features wasm`.
+//! - `testing`: the `gen` leaf and tree generators, the fast, insecure
+//!   `mock` hash config, the `backend::conformance` suite, the
+//!   native-vs-circuit `differential` harness, the `circuit_report`
+//!   assertions, the `metrics` constraint profiles and the path mutations
+//!   and soundness checks of `test_utils`, for integration tests, benches
+//!   and other backends (always there for the crate's own tests). Turns on
+//!   `tracing`.
+//!
+//! `cargo check --no-default-features --target thumbv7em-none-eabihf` checks the
+//! `no_std` build; `ci/no-std-check` does the same from a `#![no_std]` consumer,
+//! verifying proofs received as bytes, also for `wasm32-unknown-unknown`.
+//!
+//! The `merkle` binary (`src/bin/merkle.rs`) builds trees from files of hex
+//! leaves and opens and verifies their paths, for scripted pipelines.
+//!
+//! `report::constraint_report` compares the size of the membership circuit
+//! over Pedersen and Poseidon across tree heights; `benches/backends.rs`
+//! times proving them.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+
 use ark_crypto_primitives::crh::TwoToOneCRH;
 use ark_crypto_primitives::merkle_tree::{Config, MerkleTree, Path};
-use ark_crypto_primitives::crh::CRH;
-use ark_relations::r1cs::{ConstraintLayer, ConstraintSystem, TracingMode, ConstraintSynthesizer};
-use tracing_subscriber::layer::SubscriberExt;
 
 pub mod common;
 use common::*;
-use crate::constraints::MTreeVerification;
 
+#[cfg(feature = "constraints")]
 mod constraints;
-
-extern crate crypto;
-
-use self::crypto::digest::Digest;
-use self::crypto::sha3::Sha3;
+#[cfg(feature = "snark")]
+pub mod access;
+pub mod ark;
+pub mod append_only;
+pub mod backend;
+#[cfg(all(feature = "snark", feature = "parallel"))]
+pub mod batch;
+#[cfg(feature = "bn254")]
+pub mod bn254;
+pub mod bundle;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(all(feature = "constraints", any(test, feature = "testing")))]
+pub mod circuit_report;
+#[cfg(feature = "snark")]
+pub mod coupon;
+pub mod ct;
+#[cfg(feature = "snark")]
+pub mod dao;
+#[cfg(all(feature = "constraints", any(test, feature = "testing")))]
+pub mod differential;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod eth;
+pub mod explain;
+#[cfg(feature = "std")]
+pub mod fixtures;
+#[cfg(feature = "snark")]
+pub mod forest;
+#[cfg(any(test, feature = "testing"))]
+pub mod gen;
+#[cfg(feature = "std")]
+pub mod handle;
+pub mod hiding;
+pub mod history;
+pub mod incremental;
+pub mod index;
+pub mod inputs;
+#[cfg(feature = "constraints")]
+pub mod keccak;
+pub mod kit;
+pub mod leaf;
+pub mod leaf_encoding;
+pub mod level_tree;
+#[cfg(feature = "std")]
+pub mod map;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
+#[cfg(feature = "snark")]
+pub mod kyc;
+#[cfg(feature = "snark")]
+pub mod leaderboard;
+#[cfg(feature = "login-demo")]
+pub mod login;
+#[cfg(feature = "snark")]
+pub mod lottery;
+#[cfg(all(feature = "constraints", any(test, feature = "testing")))]
+pub mod metrics;
+pub mod migrate;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod mmr;
+pub mod multiproof;
+#[cfg(feature = "std")]
+pub mod log;
+#[cfg(feature = "marlin")]
+pub mod marlin;
+pub mod nullifier;
+pub mod ordering;
+#[cfg(feature = "snark")]
+pub mod paired;
+#[cfg(feature = "snark")]
+pub mod payroll;
+pub mod prelude;
+#[cfg(feature = "std")]
+pub mod provenance;
+#[cfg(feature = "constraints")]
+pub mod range;
+pub mod receipt;
+#[cfg(feature = "recursion")]
+pub mod recursion;
+#[cfg(feature = "snark")]
+pub mod registry;
+#[cfg(feature = "constraints")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod rng;
+pub mod root;
+#[cfg(feature = "std")]
+pub mod schnorr;
+pub mod serialization;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod signed_root;
+#[cfg(feature = "snark")]
+pub mod snark;
+#[cfg(feature = "snark")]
+pub mod solidity;
+#[cfg(feature = "snark")]
+pub mod solvency;
+pub mod sorted;
+pub mod sparse;
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod storage;
+pub mod stream;
+#[cfg(feature = "snark")]
+pub mod survey;
+#[cfg(any(test, feature = "testing"))]
+pub mod test_utils;
+pub mod transcript;
+#[cfg(feature = "vectors")]
+pub mod vectors;
+pub mod versioned;
+pub mod walkthrough;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 
 
@@ -31,133 +216,9 @@ impl Config for MerkleConfig {
 
 /// A Merkle tree containing account information.
 pub type SimpleMerkleTree = MerkleTree<MerkleConfig>;
-/// The root of the account Merkle tree.
+/// The root of the account Merkle tree. Prefer `root::MerkleRoot` in APIs
+/// that shouldn't expose the arkworks digest type.
 pub type Root = <TwoToOneHash as TwoToOneCRH>::Output;
 /// A membership proof for a given account.
 pub type SimplePath = Path<MerkleConfig>;
 
-
-
-// Run this test via `cargo test --release test_merkle_tree`.
-#[test]
-fn test_merkle_tree() {
-
-    let mut leafdata = vec![];
-    for i in &[1u8, 2u8, 3u8, 10u8, 9u8, 17u8, 70u8, 45u8] {
-        let mut hasher = Sha3::keccak256();
-        hasher.input(&[*i]);
-        leafdata.push(hasher.result_str().as_bytes().to_vec());
-    }
-
-    // Let's set up an RNG for use within tests. Note that this is *not* safe
-    // for any production use.
-    let mut rng = ark_std::test_rng();
-
-    // First, let's sample the public parameters for the hash functions:
-    let leaf_crh_params = <LeafHash as CRH>::setup(&mut rng).unwrap();
-    let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
-
-    // Next, let's construct our tree.
-    // This follows the API in https://github.com/arkworks-rs/crypto-primitives/blob/6be606259eab0aec010015e2cfd45e4f134cd9bf/src/merkle_tree/mod.rs#L156
-    let tree = SimpleMerkleTree::new(
-        &leaf_crh_params,
-        &two_to_one_crh_params,
-        &leafdata, // the i-th entry is the i-th leaf.
-    )
-        .unwrap();
-
-
-    // Now, let's try to generate a membership proof for the 4th item.
-    let proof = tree.generate_proof(3).unwrap(); // we're 0-indexing!
-
-    let mut hasher = Sha3::keccak256();
-    hasher.input(&[10u8]);
-
-    let leaf = hasher.result_str().as_bytes().to_vec();
-    // This should be a proof for the membership of a leaf with value 9. Let's check that!
-
-    // First, let's get the root we want to verify against:
-    let root = tree.root();
-    // Next, let's verify the proof!
-    let result = proof
-        .verify(
-            &leaf_crh_params,
-            &two_to_one_crh_params,
-            &root,
-            &leaf, // The claimed leaf
-        )
-        .unwrap();
-    assert!(result);
-}
-
-
-// Run this test via `cargo test --release test_merkle_tree`.
-#[test]
-fn test_merkle_tree_constraints() {
-
-    // Let's set up an RNG for use within tests. Note that this is *not* safe
-    // for any production use.
-    let mut rng = ark_std::test_rng();
-
-    // First, let's sample the public parameters for the hash functions:
-    let leaf_crh_params = <LeafHash as CRH>::setup(&mut rng).unwrap();
-    let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
-
-    let mut leafdata = vec![];
-    for i in &[1u8, 2u8, 3u8, 10u8, 9u8, 17u8, 70u8, 45u8] {
-        let mut hasher = Sha3::keccak256();
-        hasher.input(&[*i]);
-        leafdata.push(hasher.result_str().as_bytes().to_vec());
-    }
-
-    // Next, let's construct our tree.
-    // This follows the API in https://github.com/arkworks-rs/crypto-primitives/blob/6be606259eab0aec010015e2cfd45e4f134cd9bf/src/merkle_tree/mod.rs#L156
-    let tree = crate::SimpleMerkleTree::new(
-        &leaf_crh_params,
-        &two_to_one_crh_params,
-        &leafdata, // the i-th entry is the i-th leaf.
-    )
-        .unwrap();
-
-    let mut hasher = Sha3::keccak256();
-    hasher.input(&[9u8]);
-
-    let leaf = hasher.result_str().as_bytes().to_vec();
-
-    // Now, let's try to generate a membership proof for the 5th item, i.e. 9.
-    let proof = tree.generate_proof(4).unwrap(); // we're 0-indexing!
-    // This should be a proof for the membership of a leaf with value 9. Let's check that!
-
-    // First, let's get the root we want to verify against:
-    let root = tree.root();
-
-    let circuit = MTreeVerification {
-        // constants
-        leaf_crh_params,
-        two_to_one_crh_params,
-
-        // public inputs
-        root,
-        leaf,
-
-        // witness
-        auth_path: Some(proof),
-    };
-    // First, some boilerplat that helps with debugging
-    let mut layer = ConstraintLayer::default();
-    layer.mode = TracingMode::OnlyConstraints;
-    let subscriber = tracing_subscriber::Registry::default().with(layer);
-    let _guard = tracing::subscriber::set_default(subscriber);
-
-    // Next, let's make the circuit!
-    let cs = ConstraintSystem::new_ref();
-    circuit.generate_constraints(cs.clone()).unwrap();
-    // Let's check whether the constraint system is satisfied
-    let is_satisfied = cs.is_satisfied().unwrap();
-    if !is_satisfied {
-        // If it isn't, find out the offending constraint.
-        println!("{:?}", cs.which_is_unsatisfied());
-    }
-    assert!(is_satisfied);
-}
-
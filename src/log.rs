@@ -0,0 +1,276 @@
+use crate::append_only::{
+    verify_consistency, verify_inclusion, AppendOnlyTree, ConsistencyProof, InclusionProof,
+};
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::schnorr::{self, PublicKey, SecretKey, Signature};
+use crate::Root;
+use ark_ff::to_bytes;
+use ark_std::collections::BTreeMap;
+use ark_std::rand::{CryptoRng, RngCore};
+
+// A Certificate-Transparency-style verifiable log.
+//
+// The log appends entries to an append-only tree and periodically signs a
+// tree head (size and root). Clients check that their entry is included under
+// a signed head and that successive heads are consistent with each other;
+// monitors collect heads and flag equivocation, i.e. two validly signed heads
+// for the same size with different roots, which an honest log never produces.
+//
+// The log sits on an `AppendOnlyTree` rather than a `MerkleBackend`: it grows
+// one entry at a time and its clients need RFC 6962 inclusion and consistency
+// proofs between sizes, which fixed-size backends don't provide.
+
+/// A tree head signed by the log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedTreeHead {
+    pub size: u64,
+    pub root: Root,
+    pub signature: Signature,
+}
+
+impl SignedTreeHead {
+    // root || size, the message the log signs
+    fn message(size: u64, root: &Root) -> Result<Vec<u8>, MerkleError> {
+        Ok(to_bytes![root, size]?)
+    }
+
+    pub fn verify(&self, params: &MerkleParams, log_key: &PublicKey) -> Result<bool, MerkleError> {
+        let message = Self::message(self.size, &self.root)?;
+        schnorr::verify(params, log_key, &message, &self.signature)
+    }
+}
+
+pub struct Log {
+    params: MerkleParams,
+    signing_key: SecretKey,
+    entries: Vec<Vec<u8>>,
+    tree: AppendOnlyTree,
+}
+
+impl Log {
+    /// Creates an empty log with a fresh signing key.
+    pub fn new<R: RngCore + CryptoRng>(params: MerkleParams, rng: &mut R) -> Self {
+        let (signing_key, _) = schnorr::keygen(rng);
+        Self::with_key(params, signing_key)
+    }
+
+    pub fn with_key(params: MerkleParams, signing_key: SecretKey) -> Self {
+        Log {
+            tree: AppendOnlyTree::new(params.clone()),
+            params,
+            signing_key,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        schnorr::public_key(&self.signing_key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entry(&self, index: usize) -> Option<&[u8]> {
+        self.entries.get(index).map(Vec::as_slice)
+    }
+
+    /// Appends `entry`, returning its index.
+    pub fn append(&mut self, entry: Vec<u8>) -> Result<usize, MerkleError> {
+        let index = self.tree.append(&entry)?;
+        self.entries.push(entry);
+        Ok(index)
+    }
+
+    /// Signs the current size and root.
+    pub fn signed_tree_head(&self) -> Result<SignedTreeHead, MerkleError> {
+        let size = self.len() as u64;
+        let root = self.tree.root()?;
+        let message = SignedTreeHead::message(size, &root)?;
+        Ok(SignedTreeHead {
+            size,
+            root,
+            signature: schnorr::sign(&self.params, &self.signing_key, &message)?,
+        })
+    }
+
+    pub fn prove_inclusion(&self, index: usize) -> Result<InclusionProof, MerkleError> {
+        self.tree.prove_inclusion(index)
+    }
+
+    pub fn prove_consistency(&self, old_size: usize) -> Result<ConsistencyProof, MerkleError> {
+        self.tree.prove_consistency(old_size)
+    }
+}
+
+/// A client's view of the log: checks heads, inclusion and consistency.
+pub struct LogClient {
+    params: MerkleParams,
+    log_key: PublicKey,
+}
+
+impl LogClient {
+    pub fn new(params: MerkleParams, log_key: PublicKey) -> Self {
+        LogClient { params, log_key }
+    }
+
+    /// Checks that `entry` is included under the signed head `sth`.
+    pub fn verify_inclusion(
+        &self,
+        sth: &SignedTreeHead,
+        entry: &[u8],
+        proof: &InclusionProof,
+    ) -> Result<bool, MerkleError> {
+        Ok(sth.verify(&self.params, &self.log_key)?
+            && proof.tree_size == sth.size
+            && verify_inclusion(&self.params, entry, proof, &sth.root)?)
+    }
+
+    /// Checks that the signed head `newer` extends `older`.
+    pub fn verify_consistency(
+        &self,
+        older: &SignedTreeHead,
+        newer: &SignedTreeHead,
+        proof: &ConsistencyProof,
+    ) -> Result<bool, MerkleError> {
+        Ok(older.verify(&self.params, &self.log_key)?
+            && newer.verify(&self.params, &self.log_key)?
+            && proof.old_size == older.size
+            && proof.new_size == newer.size
+            && verify_consistency(&self.params, proof, &older.root, &newer.root)?)
+    }
+}
+
+/// What a monitor concluded about a tree head it was shown.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Observation {
+    /// A new, validly signed head.
+    Accepted,
+    /// The head was already known.
+    Duplicate,
+    /// The signature does not verify under the log's key.
+    InvalidSignature,
+    /// The log signed two different roots for the same size. Both heads are
+    /// kept as evidence.
+    Equivocation {
+        first: SignedTreeHead,
+        second: SignedTreeHead,
+    },
+}
+
+/// Collects signed tree heads from any number of sources and flags equivocation.
+pub struct Monitor {
+    params: MerkleParams,
+    log_key: PublicKey,
+    heads: BTreeMap<u64, SignedTreeHead>,
+    evidence: Vec<(SignedTreeHead, SignedTreeHead)>,
+}
+
+impl Monitor {
+    pub fn new(params: MerkleParams, log_key: PublicKey) -> Self {
+        Monitor {
+            params,
+            log_key,
+            heads: BTreeMap::new(),
+            evidence: Vec::new(),
+        }
+    }
+
+    pub fn observe(&mut self, sth: SignedTreeHead) -> Result<Observation, MerkleError> {
+        if !sth.verify(&self.params, &self.log_key)? {
+            return Ok(Observation::InvalidSignature);
+        }
+        match self.heads.get(&sth.size) {
+            Some(known) if known.root == sth.root => Ok(Observation::Duplicate),
+            Some(known) => {
+                let (first, second) = (known.clone(), sth);
+                self.evidence.push((first.clone(), second.clone()));
+                Ok(Observation::Equivocation { first, second })
+            }
+            None => {
+                self.heads.insert(sth.size, sth);
+                Ok(Observation::Accepted)
+            }
+        }
+    }
+
+    /// The largest head seen so far.
+    pub fn latest(&self) -> Option<&SignedTreeHead> {
+        self.heads.values().next_back()
+    }
+
+    /// Every pair of conflicting heads observed.
+    pub fn equivocations(&self) -> &[(SignedTreeHead, SignedTreeHead)] {
+        &self.evidence
+    }
+}
+
+#[test]
+fn test_log_inclusion_and_consistency_across_appends() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut log = Log::new(params.clone(), &mut rng);
+    let client = LogClient::new(params.clone(), log.public_key());
+    let mut monitor = Monitor::new(params, log.public_key());
+
+    let entries: Vec<Vec<u8>> = vec![b"cert-a".to_vec(), b"cert-b".to_vec(), b"cert-c".to_vec()];
+    let mut heads = Vec::new();
+    for entry in &entries {
+        log.append(entry.clone()).unwrap();
+        let sth = log.signed_tree_head().unwrap();
+        assert_eq!(monitor.observe(sth.clone()).unwrap(), Observation::Accepted);
+        heads.push(sth);
+    }
+    assert_eq!(monitor.observe(heads[2].clone()).unwrap(), Observation::Duplicate);
+
+    let latest = &heads[2];
+    for (index, entry) in entries.iter().enumerate() {
+        let proof = log.prove_inclusion(index).unwrap();
+        assert!(client.verify_inclusion(latest, entry, &proof).unwrap());
+        assert!(!client.verify_inclusion(latest, b"cert-x", &proof).unwrap());
+    }
+    for older in &heads[..2] {
+        let proof = log.prove_consistency(older.size as usize).unwrap();
+        assert!(client.verify_consistency(older, latest, &proof).unwrap());
+    }
+    // a proof against the wrong old head fails
+    let proof = log.prove_consistency(1).unwrap();
+    assert!(!client.verify_consistency(&heads[1], latest, &proof).unwrap());
+}
+
+#[test]
+fn test_log_monitor_flags_equivocation() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (signing_key, log_key) = schnorr::keygen(&mut rng);
+
+    // an equivocating log: one key, two diverging histories
+    let mut honest_view = Log::with_key(params.clone(), signing_key);
+    let mut forked_view = Log::with_key(params.clone(), signing_key);
+    honest_view.append(b"cert-a".to_vec()).unwrap();
+    forked_view.append(b"cert-a".to_vec()).unwrap();
+    honest_view.append(b"cert-b".to_vec()).unwrap();
+    forked_view.append(b"cert-evil".to_vec()).unwrap();
+
+    let mut monitor = Monitor::new(params.clone(), log_key);
+    let first = honest_view.signed_tree_head().unwrap();
+    let second = forked_view.signed_tree_head().unwrap();
+    assert_eq!(monitor.observe(first.clone()).unwrap(), Observation::Accepted);
+    assert_eq!(
+        monitor.observe(second.clone()).unwrap(),
+        Observation::Equivocation { first, second }
+    );
+    assert_eq!(monitor.equivocations().len(), 1);
+
+    // a head signed by someone else is not evidence of anything
+    let impostor = Log::new(params, &mut rng);
+    assert_eq!(
+        monitor.observe(impostor.signed_tree_head().unwrap()).unwrap(),
+        Observation::InvalidSignature
+    );
+}
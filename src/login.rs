@@ -0,0 +1,431 @@
+use crate::common::*;
+use crate::constraints::{blank_path, LeafDigestVar, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::byte_inputs;
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_crypto_primitives::commitment::pedersen::constraints::CommGadget;
+use ark_crypto_primitives::commitment::pedersen::{self, Commitment, Randomness};
+use ark_crypto_primitives::commitment::{CommitmentGadget, CommitmentScheme};
+use ark_crypto_primitives::crh::{CRHGadget, CRH};
+use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective, Fr};
+use ark_ff::{ToBytes, UniformRand};
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::collections::BTreeMap;
+use ark_std::rand::{CryptoRng, RngCore};
+
+// Whitelist login demo.
+//
+// Registration: the client commits to its identifier with a Pedersen
+// commitment and the server adds the commitment to its whitelist tree.
+// Login: the server hands out a fresh challenge for the current root; the
+// client proves that it can open *some* commitment in the tree and binds the
+// challenge into the statement by exposing `LeafHash(commitment || challenge)`.
+// A proof therefore only ever answers the one session it was made for, and the
+// server refuses it again once the session has been consumed or the tree has
+// moved on to a new epoch.
+
+/// Pedersen commitment to a 32-byte identifier.
+pub type IdCommitment = Commitment<EdwardsProjective, IdWindow>;
+pub type IdCommitmentGadget = CommGadget<EdwardsProjective, EdwardsVar, IdWindow>;
+pub type IdCommitmentParams = <IdCommitment as CommitmentScheme>::Parameters;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct IdWindow;
+
+// `WINDOW_SIZE * NUM_WINDOWS` = 256 bits = one 32-byte identifier.
+impl pedersen::Window for IdWindow {
+    const WINDOW_SIZE: usize = 4;
+    const NUM_WINDOWS: usize = 64;
+}
+
+/// The per-session tag a login proof exposes.
+pub type Binding = <LeafHash as CRH>::Output;
+
+fn commitment_leaf(
+    comm_params: &IdCommitmentParams,
+    identifier: &[u8; 32],
+    randomness: &Randomness<EdwardsProjective>,
+) -> Result<Vec<u8>, MerkleError> {
+    let commitment = IdCommitment::commit(comm_params, identifier, randomness)?;
+    let mut leaf = Vec::new();
+    // writing into a Vec cannot fail
+    commitment.write(&mut leaf).unwrap();
+    Ok(leaf)
+}
+
+fn binding(params: &MerkleParams, leaf: &[u8], challenge: &[u8; 32]) -> Result<Binding, MerkleError> {
+    let mut input = leaf.to_vec();
+    input.extend_from_slice(challenge);
+    Ok(<LeafHash as CRH>::evaluate(&params.leaf_crh_params, &input)?)
+}
+
+/// Proves knowledge of the opening of a whitelisted commitment, bound to `challenge`.
+pub struct LoginCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub comm_params: IdCommitmentParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub challenge: [u8; 32],
+    pub binding: Option<Binding>,
+
+    // These are the private witnesses to the circuit.
+    pub identifier: Option<[u8; 32]>,
+    pub randomness: Option<Randomness<EdwardsProjective>>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl LoginCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, comm_params: IdCommitmentParams, tree_height: usize) -> Self {
+        LoginCircuit {
+            params,
+            comm_params,
+            root: Default::default(),
+            challenge: [0u8; 32],
+            binding: None,
+            identifier: None,
+            randomness: None,
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(root: &Root, challenge: &[u8; 32], binding: &Binding) -> Vec<ConstraintF> {
+        let mut inputs = vec![*root];
+        inputs.extend(byte_inputs(&challenge[..]));
+        inputs.push(*binding);
+        inputs
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for LoginCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let challenge =
+            UInt8::new_input_vec(ark_relations::ns!(cs, "challenge_var"), &self.challenge)?;
+        let binding = LeafDigestVar::new_input(ark_relations::ns!(cs, "binding_var"), || {
+            self.binding.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+        let comm_params = <IdCommitmentGadget as CommitmentGadget<IdCommitment, ConstraintF>>::ParametersVar::new_constant(
+            cs.clone(),
+            &self.comm_params,
+        )?;
+
+        // Finally, the opening of the commitment and the path as private witnesses:
+        let identifier = UInt8::new_witness_vec(
+            ark_relations::ns!(cs, "identifier_var"),
+            &self.identifier.unwrap_or_default(),
+        )?;
+        let randomness = <IdCommitmentGadget as CommitmentGadget<IdCommitment, ConstraintF>>::RandomnessVar::new_witness(
+            ark_relations::ns!(cs, "randomness_var"),
+            || self.randomness.as_ref().ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let commitment = IdCommitmentGadget::commit(&comm_params, &identifier, &randomness)?;
+        let leaf = commitment.to_bytes()?;
+
+        let is_member = path.verify_membership(
+            &leaf_crh_params,
+            &two_to_one_crh_params,
+            &root,
+            &leaf.as_slice(),
+        )?;
+        is_member.enforce_equal(&Boolean::TRUE)?;
+
+        // bind the session: the exposed tag must be the hash of this leaf and this challenge
+        let mut binding_input = leaf;
+        binding_input.extend(challenge);
+        let computed = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(
+            &leaf_crh_params,
+            &binding_input,
+        )?;
+        computed.enforce_equal(&binding)?;
+
+        Ok(())
+    }
+}
+
+/// A challenge handed out by the server for one login attempt.
+#[derive(Clone, Debug)]
+pub struct Challenge {
+    pub session_id: u64,
+    pub nonce: [u8; 32],
+    pub root: Root,
+    pub epoch: u64,
+}
+
+/// A client's answer to a `Challenge`.
+#[derive(Clone)]
+pub struct LoginProof {
+    pub session_id: u64,
+    pub binding: Binding,
+    pub proof: Proof,
+}
+
+/// The user side: knows its identifier and the commitment randomness.
+pub struct Client {
+    identifier: [u8; 32],
+    randomness: Randomness<EdwardsProjective>,
+    leaf_index: Option<usize>,
+}
+
+impl Client {
+    pub fn new<R: RngCore + CryptoRng>(username: &str, rng: &mut R) -> Self {
+        Client {
+            identifier: keccak256(username.as_bytes()),
+            randomness: Randomness(Fr::rand(rng)),
+            leaf_index: None,
+        }
+    }
+
+    /// The commitment the client registers with.
+    pub fn commitment(&self, comm_params: &IdCommitmentParams) -> Result<Vec<u8>, MerkleError> {
+        commitment_leaf(comm_params, &self.identifier, &self.randomness)
+    }
+
+    pub fn register(&mut self, server: &mut LoginServer) -> Result<(), MerkleError> {
+        let commitment = self.commitment(&server.comm_params)?;
+        self.leaf_index = Some(server.register(commitment)?);
+        Ok(())
+    }
+
+    /// The circuit answering `challenge`, with the current path fetched from the server.
+    pub fn login_circuit(
+        &self,
+        server: &LoginServer,
+        challenge: &Challenge,
+    ) -> Result<LoginCircuit, MerkleError> {
+        let index = self.leaf_index.ok_or(MerkleError::LeafNotFound)?;
+        let leaf = self.commitment(&server.comm_params)?;
+        Ok(LoginCircuit {
+            params: server.params.clone(),
+            comm_params: server.comm_params.clone(),
+            root: challenge.root,
+            challenge: challenge.nonce,
+            binding: Some(binding(&server.params, &leaf, &challenge.nonce)?),
+            identifier: Some(self.identifier),
+            randomness: Some(self.randomness.clone()),
+            auth_path: Some(server.path(index)?),
+        })
+    }
+
+    /// Answers `challenge`.
+    pub fn login<R: RngCore + CryptoRng>(
+        &self,
+        server: &LoginServer,
+        pk: &ProvingKey,
+        challenge: &Challenge,
+        rng: &mut R,
+    ) -> Result<LoginProof, MerkleError> {
+        let circuit = self.login_circuit(server, challenge)?;
+        // `login_circuit` always fills in the binding
+        let binding = circuit.binding.unwrap();
+        Ok(LoginProof {
+            session_id: challenge.session_id,
+            binding,
+            proof: snark::prove(pk, circuit, rng)?,
+        })
+    }
+}
+
+struct Session {
+    nonce: [u8; 32],
+    epoch: u64,
+    issued_at: u64,
+}
+
+/// The server side: keeps the whitelist tree, its epoch and the open sessions.
+pub struct LoginServer {
+    params: MerkleParams,
+    comm_params: IdCommitmentParams,
+    options: TreeOptions,
+    members: Vec<Vec<u8>>,
+    tree: SimpleMerkleTree,
+    // bumped on every change of the tree
+    epoch: u64,
+    vk: VerifyingKey,
+    sessions: BTreeMap<u64, Session>,
+    next_session_id: u64,
+    // how long a challenge stays answerable, in seconds
+    challenge_ttl: u64,
+}
+
+impl LoginServer {
+    /// Sets up a server for up to `capacity` users, returning it together with
+    /// the proving key clients need.
+    pub fn new<R: RngCore + CryptoRng>(
+        params: MerkleParams,
+        comm_params: IdCommitmentParams,
+        capacity: usize,
+        challenge_ttl: u64,
+        rng: &mut R,
+    ) -> Result<(Self, ProvingKey), MerkleError> {
+        // all-zero bytes are not the encoding of any curve point, so padding
+        // leaves cannot be opened by anyone
+        let options = TreeOptions::for_capacity(capacity).padding(PaddingStrategy::Zeros(64));
+        let (pk, vk) = snark::setup(
+            LoginCircuit::blank(params.clone(), comm_params.clone(), options.height),
+            rng,
+        )?;
+        let tree = Self::build_tree(&params, &options, &[])?;
+        let server = LoginServer {
+            params,
+            comm_params,
+            options,
+            members: Vec::new(),
+            tree,
+            epoch: 0,
+            vk,
+            sessions: BTreeMap::new(),
+            next_session_id: 0,
+            challenge_ttl,
+        };
+        Ok((server, pk))
+    }
+
+    fn build_tree(
+        params: &MerkleParams,
+        options: &TreeOptions,
+        members: &[Vec<u8>],
+    ) -> Result<SimpleMerkleTree, MerkleError> {
+        Ok(params.build_tree_with_options(members, options)?.tree)
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Adds a commitment to the whitelist, starting a new epoch. Returns its leaf index.
+    pub fn register(&mut self, commitment: Vec<u8>) -> Result<usize, MerkleError> {
+        if self.members.len() == self.options.capacity() {
+            return Err(MerkleError::CapacityExceeded {
+                len: self.members.len() + 1,
+                capacity: self.options.capacity(),
+            });
+        }
+        let mut members = self.members.clone();
+        members.push(commitment);
+        self.tree = Self::build_tree(&self.params, &self.options, &members)?;
+        self.members = members;
+        self.epoch += 1;
+        Ok(self.members.len() - 1)
+    }
+
+    pub fn path(&self, index: usize) -> Result<SimplePath, MerkleError> {
+        let index = LeafIndex::checked_new(index as u64, self.members.len() as u64)?;
+        generate_proof(&self.tree, index)
+    }
+
+    /// Opens a login session at time `now`.
+    pub fn challenge<R: RngCore + CryptoRng>(&mut self, now: u64, rng: &mut R) -> Challenge {
+        let mut nonce = [0u8; 32];
+        rng.fill_bytes(&mut nonce);
+        let session_id = self.next_session_id;
+        self.next_session_id += 1;
+        self.sessions.insert(
+            session_id,
+            Session {
+                nonce,
+                epoch: self.epoch,
+                issued_at: now,
+            },
+        );
+        Challenge {
+            session_id,
+            nonce,
+            root: self.root(),
+            epoch: self.epoch,
+        }
+    }
+
+    /// Checks a login attempt at time `now`. The session is consumed whatever the outcome.
+    pub fn verify_login(&mut self, login: &LoginProof, now: u64) -> Result<bool, MerkleError> {
+        let session = match self.sessions.remove(&login.session_id) {
+            Some(session) => session,
+            None => return Ok(false),
+        };
+        if now < session.issued_at || now - session.issued_at > self.challenge_ttl {
+            return Ok(false);
+        }
+        // the tree changed since the challenge was issued
+        if session.epoch != self.epoch {
+            return Ok(false);
+        }
+        let inputs = LoginCircuit::public_inputs(&self.root(), &session.nonce, &login.binding);
+        snark::verify(&self.vk, &inputs, &login.proof)
+    }
+}
+
+#[test]
+fn test_login_client_server() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let comm_params = IdCommitment::setup(&mut rng).unwrap();
+    let (mut server, pk) = LoginServer::new(params, comm_params, 4, 30, &mut rng).unwrap();
+
+    let mut alice = Client::new("alice", &mut rng);
+    let mut bob = Client::new("bob", &mut rng);
+    alice.register(&mut server).unwrap();
+    bob.register(&mut server).unwrap();
+
+    // a normal login
+    let challenge = server.challenge(100, &mut rng);
+    let login = alice.login(&server, &pk, &challenge, &mut rng).unwrap();
+    assert!(server.verify_login(&login, 105).unwrap());
+
+    // replaying the same proof fails: the session is gone
+    assert!(!server.verify_login(&login, 106).unwrap());
+
+    // so does moving a proof over to another session
+    let other = server.challenge(110, &mut rng);
+    let mut moved = login.clone();
+    moved.session_id = other.session_id;
+    assert!(!server.verify_login(&moved, 111).unwrap());
+
+    // a challenge answered too late is rejected
+    let challenge = server.challenge(200, &mut rng);
+    let login = bob.login(&server, &pk, &challenge, &mut rng).unwrap();
+    assert!(!server.verify_login(&login, 300).unwrap());
+
+    // a registration in between starts a new epoch and invalidates open challenges
+    let challenge = server.challenge(400, &mut rng);
+    let login = bob.login(&server, &pk, &challenge, &mut rng).unwrap();
+    let mut carol = Client::new("carol", &mut rng);
+    carol.register(&mut server).unwrap();
+    assert!(!server.verify_login(&login, 401).unwrap());
+
+    // someone who never registered has no satisfying witness, even borrowing a whitelisted path
+    let mut mallory = Client::new("mallory", &mut rng);
+    mallory.leaf_index = Some(0);
+    let challenge = server.challenge(500, &mut rng);
+    let circuit = mallory.login_circuit(&server, &challenge).unwrap();
+    let cs = ark_relations::r1cs::ConstraintSystem::new_ref();
+    circuit.generate_constraints(cs.clone()).unwrap();
+    assert!(!cs.is_satisfied().unwrap());
+
+    let challenge = server.challenge(600, &mut rng);
+    let login = carol.login(&server, &pk, &challenge, &mut rng).unwrap();
+    assert!(server.verify_login(&login, 601).unwrap());
+}
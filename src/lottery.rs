@@ -0,0 +1,328 @@
+use crate::common::*;
+use crate::constraints::{blank_path, IndexedPathVar, RootVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::PublicInputsBuilder;
+use crate::leaf::Leaf;
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_crypto_primitives::crh::{CRHGadget, CRH};
+use ark_ff::to_bytes;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::rand::{CryptoRng, RngCore};
+
+// A lottery drawn from a public randomness beacon.
+//
+// Participants buy tickets by handing the organizer the commitment
+// `LeafHash(domain || secret)` of a random secret. The organizer seals the
+// tree of commitments before the beacon value is revealed; afterwards the
+// winning leaf is `beacon mod ticket_count`, reading the beacon as a
+// big-endian integer. The winner claims the prize with a proof that they can
+// open the leaf at exactly that index, which says nothing about who they are.
+// The claim also commits to a payout address so that it can't be redirected.
+
+const TICKET_DOMAIN: &[u8] = b"merkle-tutorial/lottery-ticket";
+
+/// A ticket commitment, as stored in the tree.
+pub type TicketCommitment = <LeafHash as CRH>::Output;
+
+/// The index of the winning ticket for `beacon` among `ticket_count` tickets.
+pub fn winning_index(beacon: &[u8; 32], ticket_count: usize) -> LeafIndex {
+    assert!(ticket_count > 0, "no tickets to draw from");
+    let n = ticket_count as u128;
+    let index = beacon.iter().fold(0u128, |acc, b| (acc * 256 + *b as u128) % n);
+    LeafIndex::checked_new(index as u64, ticket_count as u64).expect("reduced modulo the ticket count")
+}
+
+pub struct Ticket {
+    secret: [u8; 32],
+}
+
+impl Ticket {
+    pub fn new<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut secret = [0u8; 32];
+        rng.fill_bytes(&mut secret);
+        Ticket { secret }
+    }
+
+    pub fn commitment(&self, params: &MerkleParams) -> Result<TicketCommitment, MerkleError> {
+        let mut input = TICKET_DOMAIN.to_vec();
+        input.extend_from_slice(&self.secret);
+        Ok(<LeafHash as CRH>::evaluate(&params.leaf_crh_params, &input)?)
+    }
+
+    pub fn claim_circuit(
+        &self,
+        params: &MerkleParams,
+        root: Root,
+        winning_index: LeafIndex,
+        payout: [u8; 32],
+        path: SimplePath,
+    ) -> ClaimCircuit {
+        ClaimCircuit {
+            params: params.clone(),
+            root,
+            winning_index,
+            payout,
+            secret: Some(self.secret),
+            auth_path: Some(path),
+        }
+    }
+
+    /// Claims the prize for the ticket at `winning_index`, which must be this one.
+    #[allow(clippy::too_many_arguments)]
+    pub fn claim<R: RngCore + CryptoRng>(
+        &self,
+        params: &MerkleParams,
+        pk: &ProvingKey,
+        root: Root,
+        winning_index: LeafIndex,
+        payout: [u8; 32],
+        path: SimplePath,
+        rng: &mut R,
+    ) -> Result<Claim, MerkleError> {
+        if path.leaf_index as u64 != winning_index.get() {
+            return Err(MerkleError::LeafIndexOutOfRange {
+                index: path.leaf_index,
+                len: winning_index.as_usize() + 1,
+            });
+        }
+        let circuit = self.claim_circuit(params, root, winning_index, payout, path);
+        Ok(Claim {
+            payout,
+            proof: snark::prove(pk, circuit, rng)?,
+        })
+    }
+}
+
+/// The organizer side: collects ticket commitments and seals the tree.
+pub struct Organizer {
+    params: MerkleParams,
+    commitments: Vec<TicketCommitment>,
+    // `Some` once sales are closed
+    tree: Option<SimpleMerkleTree>,
+}
+
+impl Organizer {
+    pub fn new(params: MerkleParams) -> Self {
+        Organizer {
+            params,
+            commitments: Vec::new(),
+            tree: None,
+        }
+    }
+
+    /// Sells a ticket, returning its index. Fails once the tree is sealed.
+    pub fn add_ticket(&mut self, commitment: TicketCommitment) -> Result<usize, MerkleError> {
+        if self.tree.is_some() {
+            return Err(MerkleError::CapacityExceeded {
+                len: self.commitments.len() + 1,
+                capacity: self.commitments.len(),
+            });
+        }
+        self.commitments.push(commitment);
+        Ok(self.commitments.len() - 1)
+    }
+
+    /// Closes sales and publishes the root. This must happen before the beacon is revealed.
+    pub fn seal(&mut self) -> Result<Root, MerkleError> {
+        let leaves = self
+            .commitments
+            .iter()
+            .map(|c| to_bytes![c])
+            .collect::<Result<Vec<_>, _>>()?;
+        // padding leaves are the zero digest, which no ticket opens to
+        let padding = PaddingStrategy::Custom(Leaf::from(to_bytes![TicketCommitment::default()]?));
+        let options = TreeOptions::for_capacity(leaves.len()).padding(padding);
+        let tree = self.params.build_tree_with_options(&leaves, &options)?.tree;
+        let root = tree.root();
+        self.tree = Some(tree);
+        Ok(root)
+    }
+
+    pub fn ticket_count(&self) -> usize {
+        self.commitments.len()
+    }
+
+    /// Number of levels of the sealed tree, leaves and root included.
+    pub fn tree_height(&self) -> usize {
+        TreeOptions::for_capacity(self.commitments.len()).height
+    }
+
+    /// The path of the ticket at `index` in the sealed tree.
+    pub fn path(&self, index: usize) -> Result<SimplePath, MerkleError> {
+        let tree = self.tree.as_ref().ok_or(MerkleError::LeafNotFound)?;
+        let index = LeafIndex::checked_new(index as u64, self.commitments.len() as u64)?;
+        generate_proof(tree, index)
+    }
+
+    pub fn setup<R: RngCore + CryptoRng>(
+        &self,
+        rng: &mut R,
+    ) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+        snark::setup(ClaimCircuit::blank(self.params.clone(), self.tree_height()), rng)
+    }
+}
+
+/// Proves "I can open the leaf at `winning_index` of the tree under `root`".
+pub struct ClaimCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub winning_index: LeafIndex,
+    pub payout: [u8; 32],
+
+    // These are the private witnesses to the circuit.
+    pub secret: Option<[u8; 32]>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl ClaimCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, tree_height: usize) -> Self {
+        ClaimCircuit {
+            params,
+            root: Default::default(),
+            winning_index: LeafIndex::default(),
+            payout: [0u8; 32],
+            secret: Some([0u8; 32]),
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(root: &Root, winning_index: LeafIndex, payout: &[u8; 32]) -> Vec<ConstraintF> {
+        PublicInputsBuilder::index_bound()
+            .root(root)
+            .index(winning_index)
+            .leaf_bytes(payout)
+            .finish()
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for ClaimCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let winning_index = FpVar::new_input(ark_relations::ns!(cs, "winning_index_var"), || {
+            Ok(ConstraintF::from(self.winning_index.get()))
+        })?;
+        // only carried along, so that the proof commits to the payout address
+        let _payout = UInt8::new_input_vec(ark_relations::ns!(cs, "payout_var"), &self.payout)?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the ticket secret and its path:
+        let secret = UInt8::new_witness_vec(
+            ark_relations::ns!(cs, "secret_var"),
+            &self.secret.unwrap_or_default(),
+        )?;
+        let path = IndexedPathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // open the commitment; it's in the tree, at the winning position
+        {
+            let _membership = ark_relations::ns!(cs, "membership");
+            let mut commitment_input = UInt8::constant_vec(TICKET_DOMAIN);
+            commitment_input.extend_from_slice(&secret);
+            let commitment =
+                <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(&leaf_crh_params, &commitment_input)?;
+            let is_member = path.verify_membership(
+                &leaf_crh_params,
+                &two_to_one_crh_params,
+                &root,
+                &commitment.to_bytes()?,
+            )?;
+            is_member.enforce_equal(&Boolean::TRUE)?;
+        }
+        let _position = ark_relations::ns!(cs, "position");
+        path.index()?.enforce_equal(&winning_index)?;
+
+        Ok(())
+    }
+}
+
+/// What the winner hands in.
+#[derive(Clone)]
+pub struct Claim {
+    pub payout: [u8; 32],
+    pub proof: Proof,
+}
+
+/// Checks `claim` for the draw of `beacon` over the sealed tree under `root`.
+pub fn verify_claim(
+    vk: &VerifyingKey,
+    root: &Root,
+    ticket_count: usize,
+    beacon: &[u8; 32],
+    claim: &Claim,
+) -> Result<bool, MerkleError> {
+    let index = winning_index(beacon, ticket_count);
+    snark::verify(vk, &ClaimCircuit::public_inputs(root, index, &claim.payout), &claim.proof)
+}
+
+#[test]
+fn test_lottery_winning_index() {
+    let mut beacon = [0u8; 32];
+    beacon[31] = 7;
+    assert_eq!(winning_index(&beacon, 5).get(), 2);
+    beacon[30] = 1;
+    // 0x0107 = 263
+    assert_eq!(winning_index(&beacon, 5).get(), 263 % 5);
+    assert_eq!(winning_index(&[0xff; 32], 1).get(), 0);
+}
+
+#[test]
+fn test_lottery_draw() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let tickets: Vec<Ticket> = (0..3).map(|_| Ticket::new(&mut rng)).collect();
+
+    let mut organizer = Organizer::new(params.clone());
+    for ticket in &tickets {
+        organizer.add_ticket(ticket.commitment(&params).unwrap()).unwrap();
+    }
+    let root = organizer.seal().unwrap();
+    assert!(organizer.add_ticket(Default::default()).is_err());
+    let (pk, vk) = organizer.setup(&mut rng).unwrap();
+
+    // revealed only now
+    let mut beacon = [0u8; 32];
+    rng.fill_bytes(&mut beacon);
+    let index = winning_index(&beacon, organizer.ticket_count());
+    let winner = &tickets[index.as_usize()];
+    let loser = &tickets[(index.as_usize() + 1) % tickets.len()];
+    let payout = keccak256(b"winner's address");
+
+    let claim = winner
+        .claim(&params, &pk, root, index, payout, organizer.path(index.as_usize()).unwrap(), &mut rng)
+        .unwrap();
+    assert!(verify_claim(&vk, &root, organizer.ticket_count(), &beacon, &claim).unwrap());
+
+    // the payout can't be redirected
+    let mut redirected = claim.clone();
+    redirected.payout = keccak256(b"thief's address");
+    assert!(!verify_claim(&vk, &root, organizer.ticket_count(), &beacon, &redirected).unwrap());
+
+    // a losing ticket is in the tree, but not at the winning index
+    let loser_path = organizer.path((index.as_usize() + 1) % tickets.len()).unwrap();
+    expect_unsatisfied(loser.claim_circuit(&params, root, index, payout, loser_path), "position");
+    // nor can it borrow the winner's path
+    let winner_path = organizer.path(index.as_usize()).unwrap();
+    expect_unsatisfied(loser.claim_circuit(&params, root, index, payout, winner_path.clone()), "membership");
+    assert_satisfied_with_report(winner.claim_circuit(&params, root, index, payout, winner_path));
+}
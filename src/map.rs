@@ -0,0 +1,214 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::leaf_encoding::ToLeafBytes;
+use crate::sparse::{SparseMerkleTree, EMPTY_LEAF};
+use crate::{Root, SimplePath};
+use ark_std::collections::BTreeMap;
+use ark_std::vec::Vec;
+
+// A key-value map committed to by a Merkle root.
+//
+// `MerkleMap` sits on a `SparseMerkleTree`. A key goes to the leaf index
+// given by the low `depth` bits of its keccak256 hash, and the leaf stores
+// that hash followed by the value, both in their `ToLeafBytes` encoding, so
+// a path opens the key as well as the value and one key's proof can't be
+// passed off as another's. A missing key opens `EMPTY_LEAF`. Two keys with
+// the same index can't both be stored; the second insert fails with
+// `KeyCollision`, which for the default 32 levels becomes likely only
+// around 2^16 keys.
+//
+// A `MapProof` records the root it was made against. Every insert changes
+// the root, so `is_stale` tells whether a proof still describes the map,
+// and `check_proof` refuses stale ones instead of reporting them invalid.
+
+/// The depth of the tree under `MerkleMap::new`.
+pub const MAP_DEPTH: usize = 32;
+
+const KEY_DOMAIN: &[u8] = b"merkle-tree-tutorial/map-key/v1";
+
+/// The value of a key, or its absence, opened against `root`.
+#[derive(Clone)]
+pub struct MapProof {
+    pub root: Root,
+    pub path: SimplePath,
+}
+
+/// The hash a key is stored under.
+pub fn key_hash<K: ToLeafBytes + ?Sized>(key: &K) -> [u8; 32] {
+    let mut input = KEY_DOMAIN.to_vec();
+    key.write_leaf_bytes(&mut input);
+    keccak256(&input)
+}
+
+// The leaf index of a key hash in a tree of `depth` levels.
+fn key_index(key_hash: &[u8; 32], depth: usize) -> u64 {
+    let mut low = [0u8; 8];
+    low.copy_from_slice(&key_hash[..8]);
+    u64::from_le_bytes(low) & ((1 << depth) - 1)
+}
+
+// The leaf storing `value` under `key_hash`.
+fn entry_leaf<V: ToLeafBytes + ?Sized>(key_hash: &[u8; 32], value: &V) -> Vec<u8> {
+    let mut leaf = key_hash.to_vec();
+    value.write_leaf_bytes(&mut leaf);
+    leaf
+}
+
+pub struct MerkleMap<K, V> {
+    tree: SparseMerkleTree,
+    // by leaf index
+    entries: BTreeMap<u64, (K, V)>,
+}
+
+impl<K: ToLeafBytes + Eq, V: ToLeafBytes> MerkleMap<K, V> {
+    /// An empty map of `MAP_DEPTH` levels.
+    pub fn new(params: MerkleParams) -> Result<Self, MerkleError> {
+        Self::with_depth(params, MAP_DEPTH)
+    }
+
+    /// An empty map over a tree of `depth` levels, at most `MAX_SPARSE_DEPTH`.
+    pub fn with_depth(params: MerkleParams, depth: usize) -> Result<Self, MerkleError> {
+        Ok(MerkleMap {
+            tree: SparseMerkleTree::new(params, depth)?,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// The leaf index of `key`.
+    pub fn index_of(&self, key: &K) -> u64 {
+        key_index(&key_hash(key), self.tree.depth())
+    }
+
+    /// Sets the value of `key`, returning the one it replaces. Fails with
+    /// `KeyCollision` if another key is stored at the same index, and with
+    /// `LeafTooLong` if the key hash and the value don't fit a leaf.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, MerkleError> {
+        let hash = key_hash(&key);
+        let index = key_index(&hash, self.tree.depth());
+        if self.entries.get(&index).map_or(false, |(stored, _)| *stored != key) {
+            return Err(MerkleError::KeyCollision { index });
+        }
+        self.tree.insert(index, &entry_leaf(&hash, &value))?;
+        Ok(self.entries.insert(index, (key, value)).map(|(_, old)| old))
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries
+            .get(&self.index_of(key))
+            .filter(|(stored, _)| stored == key)
+            .map(|(_, value)| value)
+    }
+
+    /// The value of `key`, if any, with a proof of it against the current
+    /// root: of the value if there is one, of the empty leaf if there isn't.
+    pub fn get_with_proof(&self, key: &K) -> Result<(Option<&V>, MapProof), MerkleError> {
+        let index = self.index_of(key);
+        let value = self.get(key);
+        if value.is_none() && self.entries.contains_key(&index) {
+            // another key is at its index, so its absence has no empty leaf to open
+            return Err(MerkleError::KeyCollision { index });
+        }
+        let proof = MapProof {
+            root: self.root(),
+            path: self.tree.generate_proof(index)?,
+        };
+        Ok((value, proof))
+    }
+
+    /// Whether `proof` was made against an earlier root than the current one.
+    pub fn is_stale(&self, proof: &MapProof) -> bool {
+        proof.root != self.root()
+    }
+
+    /// `verify_map_proof` against the current root, failing with
+    /// `StaleProof` for a proof of an earlier one.
+    pub fn check_proof(&self, key: &K, value: Option<&V>, proof: &MapProof) -> Result<bool, MerkleError> {
+        if self.is_stale(proof) {
+            return Err(MerkleError::StaleProof);
+        }
+        verify_map_proof(self.tree.params(), &self.root(), self.tree.depth(), key, value, proof)
+    }
+}
+
+/// Checks that `proof` opens `value` at `key`, or its absence for `None`, in
+/// the map of `depth` levels with `root`.
+pub fn verify_map_proof<K: ToLeafBytes + ?Sized, V: ToLeafBytes + ?Sized>(
+    params: &MerkleParams,
+    root: &Root,
+    depth: usize,
+    key: &K,
+    value: Option<&V>,
+    proof: &MapProof,
+) -> Result<bool, MerkleError> {
+    let hash = key_hash(key);
+    if proof.root != *root || proof.path.leaf_index as u64 != key_index(&hash, depth) {
+        return Ok(false);
+    }
+    let leaf = match value {
+        Some(value) => entry_leaf(&hash, value),
+        None => EMPTY_LEAF.to_vec(),
+    };
+    params.verify_path(&proof.path, root, leaf)
+}
+
+#[test]
+fn test_merkle_map() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut map: MerkleMap<&str, u64> = MerkleMap::with_depth(params.clone(), 16).unwrap();
+    let empty_root = map.root();
+    assert_eq!(map.insert("alice", 10).unwrap(), None);
+    assert_eq!(map.insert("bob", 20).unwrap(), None);
+    assert_eq!(map.get(&"alice"), Some(&10));
+    assert_eq!(map.get(&"carol"), None);
+    assert_ne!(map.root(), empty_root);
+
+    let (value, proof) = map.get_with_proof(&"alice").unwrap();
+    assert_eq!(value, Some(&10));
+    assert!(verify_map_proof(&params, &map.root(), 16, &"alice", Some(&10u64), &proof).unwrap());
+    assert!(!verify_map_proof(&params, &map.root(), 16, &"alice", Some(&11u64), &proof).unwrap());
+    assert!(!verify_map_proof(&params, &map.root(), 16, &"bob", Some(&10u64), &proof).unwrap());
+    assert!(map.check_proof(&"alice", Some(&10), &proof).unwrap());
+
+    let (value, absent) = map.get_with_proof(&"carol").unwrap();
+    assert_eq!(value, None);
+    assert!(verify_map_proof::<_, u64>(&params, &map.root(), 16, &"carol", None, &absent).unwrap());
+
+    // updating a value makes the old proofs stale
+    assert_eq!(map.insert("alice", 11).unwrap(), Some(10));
+    assert_eq!(map.len(), 2);
+    assert!(map.is_stale(&proof));
+    assert!(matches!(map.check_proof(&"alice", Some(&10), &proof), Err(MerkleError::StaleProof)));
+    let (_, fresh) = map.get_with_proof(&"alice").unwrap();
+    assert!(!map.is_stale(&fresh));
+    assert!(map.check_proof(&"alice", Some(&11), &fresh).unwrap());
+}
+
+#[test]
+fn test_merkle_map_key_collision() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    // two levels: four indices, so among five keys two share one
+    let mut map: MerkleMap<u8, u8> = MerkleMap::with_depth(params, 2).unwrap();
+    let keys: Vec<u8> = (0..5).collect();
+    let (first, second) = (0..5)
+        .flat_map(|i| (i + 1..5).map(move |j| (i, j)))
+        .find(|&(i, j)| map.index_of(&keys[i]) == map.index_of(&keys[j]))
+        .unwrap();
+    map.insert(keys[first], 1).unwrap();
+    assert!(matches!(map.insert(keys[second], 2), Err(MerkleError::KeyCollision { .. })));
+    assert!(matches!(map.get_with_proof(&keys[second]), Err(MerkleError::KeyCollision { .. })));
+    assert_eq!(map.get(&keys[second]), None);
+    // the stored key can still be updated
+    assert_eq!(map.insert(keys[first], 3).unwrap(), Some(1));
+}
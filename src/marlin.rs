@@ -0,0 +1,127 @@
+use crate::common::*;
+use crate::constraints::MTreeVerification;
+use crate::error::MerkleError;
+use crate::inputs::PublicInputs;
+use crate::{Root, SimplePath};
+use ark_bls12_381::Bls12_381;
+use ark_poly::univariate::DensePolynomial;
+use ark_poly_commit::sonic_pc::SonicKZG10;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+use ark_std::rand::{CryptoRng, RngCore};
+use blake2::Blake2s;
+
+// Membership proofs over Marlin, a proof system with a universal setup.
+//
+// Groth16 needs a trusted setup per circuit, so a new tree height means new
+// keys from a new ceremony. Marlin's structured reference string is made
+// once, for circuits up to some size, and the keys of any circuit that fits
+// are derived from it by anyone without secrets: `universal_setup` sizes the
+// string for the largest tree and leaf the caller will need, and
+// `MarlinProver::index` derives the prover's and verifier's keys of one tree
+// height from it. The circuit is `MTreeVerification`, with the same public
+// inputs as the Groth16 proofs of `snark`. Marlin proofs are larger and
+// slower to make and check than Groth16's.
+
+type MarlinInst = ark_marlin::Marlin<ConstraintF, SonicKZG10<Bls12_381, DensePolynomial<ConstraintF>>, Blake2s>;
+
+pub type UniversalSrs = ark_marlin::UniversalSRS<ConstraintF, SonicKZG10<Bls12_381, DensePolynomial<ConstraintF>>>;
+pub type MarlinProof = ark_marlin::Proof<ConstraintF, SonicKZG10<Bls12_381, DensePolynomial<ConstraintF>>>;
+
+fn marlin_error<E: core::fmt::Debug>(e: ark_marlin::Error<E>) -> MerkleError {
+    match e {
+        ark_marlin::Error::R1CSError(e) => MerkleError::Synthesis(e),
+        e => MerkleError::Marlin(format!("{:?}", e)),
+    }
+}
+
+// The constraints, variables and largest number of non-zero matrix entries
+// of the membership circuit, each rounded up to a power of two for slack.
+fn circuit_size(params: &MerkleParams, tree_height: usize, leaf_len: usize) -> Result<(usize, usize, usize), MerkleError> {
+    let cs = ConstraintSystem::<ConstraintF>::new_ref();
+    <MTreeVerification>::blank(params, tree_height, leaf_len).generate_constraints(cs.clone())?;
+    cs.finalize();
+    let matrices = cs.to_matrices().expect("the constraint system was just built");
+    let non_zero = matrices.a_num_non_zero.max(matrices.b_num_non_zero).max(matrices.c_num_non_zero);
+    let variables = matrices.num_instance_variables + matrices.num_witness_variables;
+    // Marlin squares the matrices, so both dimensions take the larger one
+    let side = matrices.num_constraints.max(variables).next_power_of_two();
+    Ok((side, side, non_zero.next_power_of_two()))
+}
+
+/// A reference string for membership circuits of trees of up to
+/// `max_tree_height` levels over leaves of up to `max_leaf_len` bytes.
+pub fn universal_setup<R: RngCore + CryptoRng>(
+    params: &MerkleParams,
+    max_tree_height: usize,
+    max_leaf_len: usize,
+    rng: &mut R,
+) -> Result<UniversalSrs, MerkleError> {
+    let (constraints, variables, non_zero) = circuit_size(params, max_tree_height, max_leaf_len)?;
+    MarlinInst::universal_setup(constraints, variables, non_zero, rng).map_err(marlin_error)
+}
+
+/// Makes Marlin membership proofs for trees of one height.
+pub struct MarlinProver {
+    params: MerkleParams,
+    pk: ark_marlin::IndexProverKey<ConstraintF, SonicKZG10<Bls12_381, DensePolynomial<ConstraintF>>>,
+}
+
+/// Checks the proofs of the `MarlinProver` indexed with it.
+pub struct MarlinVerifier {
+    vk: ark_marlin::IndexVerifierKey<ConstraintF, SonicKZG10<Bls12_381, DensePolynomial<ConstraintF>>>,
+}
+
+impl MarlinProver {
+    /// The keys for trees of `tree_height` levels over leaves of `leaf_len`
+    /// bytes, derived from `srs` without a setup of their own. Fails if the
+    /// circuit is larger than `srs` was made for.
+    pub fn index(
+        srs: &UniversalSrs,
+        params: &MerkleParams,
+        tree_height: usize,
+        leaf_len: usize,
+    ) -> Result<(MarlinProver, MarlinVerifier), MerkleError> {
+        let (pk, vk) =
+            MarlinInst::index(srs, <MTreeVerification>::blank(params, tree_height, leaf_len)).map_err(marlin_error)?;
+        Ok((MarlinProver { params: params.clone(), pk }, MarlinVerifier { vk }))
+    }
+
+    /// Proves that `leaf` opens `path` against `root`, revealing the root and
+    /// the leaf but not the path.
+    pub fn prove<R: RngCore + CryptoRng>(
+        &self,
+        root: Root,
+        leaf: &[u8],
+        path: SimplePath,
+        rng: &mut R,
+    ) -> Result<MarlinProof, MerkleError> {
+        MarlinInst::prove(&self.pk, <MTreeVerification>::new(&self.params, root, leaf, path), rng).map_err(marlin_error)
+    }
+}
+
+impl MarlinVerifier {
+    /// Checks a proof for `leaf` against `root`.
+    pub fn verify<R: RngCore>(&self, root: &Root, leaf: &[u8], proof: &MarlinProof, rng: &mut R) -> Result<bool, MerkleError> {
+        let inputs = PublicInputs::from_parts(root, leaf).to_field_elements();
+        MarlinInst::verify(&self.vk, &inputs, proof, rng).map_err(marlin_error)
+    }
+}
+
+#[test]
+fn test_marlin_one_srs_for_two_heights() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let srs = universal_setup(&params, 4, 8, &mut rng).unwrap();
+
+    for &count in [4usize, 8].iter() {
+        let leaves: Vec<Vec<u8>> = (0..count as u8).map(|i| vec![i; 8]).collect();
+        let tree = params.build_tree(&leaves).unwrap();
+        let (prover, verifier) = MarlinProver::index(&srs, &params, tree.height(), 8).unwrap();
+        let proof = prover.prove(tree.root(), &leaves[1], tree.generate_proof(1).unwrap(), &mut rng).unwrap();
+        assert!(verifier.verify(&tree.root(), &leaves[1], &proof, &mut rng).unwrap());
+        assert!(!verifier.verify(&tree.root(), &leaves[2], &proof, &mut rng).unwrap());
+    }
+
+    // a tree too tall for the reference string
+    assert!(MarlinProver::index(&srs, &params, 8, 8).is_err());
+}
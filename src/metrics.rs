@@ -0,0 +1,237 @@
+use crate::circuit_report::CircuitMetrics;
+use ark_ff::Field;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem, SynthesisError};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use tracing::span::Id;
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+// Where the constraints of a circuit come from.
+//
+// Every `ns!` of a circuit, and every instrumented gadget function, opens a
+// tracing span. `constraint_profile` synthesizes the circuit with a layer
+// that reads the size of the constraint system when a span is entered and
+// again when it is exited, and adds the difference to the span's path, like
+// `membership/evaluate`. A path's count includes everything under it, so the
+// counts of the top-level paths, plus `outside` for what was created in no
+// namespace at all, add up to the total.
+
+/// The constraints created under one namespace path, nested ones included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamespaceCount {
+    /// The span names from the outermost in, separated by `/`.
+    pub path: String,
+    pub constraints: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitProfile {
+    pub metrics: CircuitMetrics,
+    /// By path, in lexicographic order.
+    pub namespaces: Vec<NamespaceCount>,
+    /// The constraints created outside every namespace.
+    pub outside: usize,
+}
+
+impl CircuitProfile {
+    /// The counts of the outermost namespaces only.
+    pub fn top_level(&self) -> impl Iterator<Item = &NamespaceCount> {
+        self.namespaces
+            .iter()
+            .filter(|count| !count.path.contains('/'))
+    }
+
+    /// The constraints created under `path`, if any span had that path.
+    pub fn constraints_in(&self, path: &str) -> Option<usize> {
+        self.namespaces
+            .iter()
+            .find(|count| count.path == path)
+            .map(|count| count.constraints)
+    }
+
+    /// The profile as one JSON object, with the namespaces in path order, so
+    /// that profiles of two commits diff line by line.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n");
+        let _ = writeln!(json, "  \"constraints\": {},", self.metrics.constraints);
+        let _ = writeln!(
+            json,
+            "  \"instance_variables\": {},",
+            self.metrics.instance_variables
+        );
+        let _ = writeln!(
+            json,
+            "  \"witness_variables\": {},",
+            self.metrics.witness_variables
+        );
+        let _ = writeln!(json, "  \"outside\": {},", self.outside);
+        json.push_str("  \"namespaces\": {");
+        for (i, count) in self.namespaces.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let _ = write!(
+                json,
+                "{}\n    {:?}: {}",
+                separator, count.path, count.constraints
+            );
+        }
+        json.push_str(if self.namespaces.is_empty() {
+            "}\n}"
+        } else {
+            "\n  }\n}"
+        });
+        json
+    }
+}
+
+thread_local! {
+    // the size of the constraint system being profiled on this thread
+    static CONSTRAINTS: RefCell<Option<Box<dyn Fn() -> usize>>> = RefCell::new(None);
+}
+
+fn current_constraints() -> usize {
+    CONSTRAINTS.with(|count| count.borrow().as_ref().map_or(0, |count| count()))
+}
+
+#[derive(Default)]
+struct Counts {
+    // the entered spans, innermost last, with the size when each was entered
+    stack: Vec<(&'static str, usize)>,
+    by_path: BTreeMap<String, usize>,
+}
+
+#[derive(Clone, Default)]
+struct ProfileLayer(Arc<Mutex<Counts>>);
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for ProfileLayer {
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            self.0
+                .lock()
+                .unwrap()
+                .stack
+                .push((span.name(), current_constraints()));
+        }
+    }
+
+    fn on_exit(&self, _id: &Id, _ctx: Context<'_, S>) {
+        let mut counts = self.0.lock().unwrap();
+        let path = counts
+            .stack
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join("/");
+        if let Some((_, entered)) = counts.stack.pop() {
+            *counts.by_path.entry(path).or_default() += current_constraints() - entered;
+        }
+    }
+}
+
+/// Synthesizes `circuit` and counts its constraints by namespace.
+pub fn constraint_profile<F: Field, C: ConstraintSynthesizer<F>>(
+    circuit: C,
+) -> Result<CircuitProfile, SynthesisError> {
+    let layer = ProfileLayer::default();
+    let _guard = tracing_subscriber::Registry::default()
+        .with(layer.clone())
+        .set_default();
+
+    let cs = ConstraintSystem::<F>::new_ref();
+    let counted = cs.clone();
+    CONSTRAINTS
+        .with(|count| *count.borrow_mut() = Some(Box::new(move || counted.num_constraints())));
+    let synthesized = circuit.generate_constraints(cs.clone());
+    CONSTRAINTS.with(|count| *count.borrow_mut() = None);
+    synthesized?;
+
+    let metrics = CircuitMetrics {
+        constraints: cs.num_constraints(),
+        instance_variables: cs.num_instance_variables(),
+        witness_variables: cs.num_witness_variables(),
+    };
+    let counts = layer.0.lock().unwrap();
+    let namespaces: Vec<NamespaceCount> = counts
+        .by_path
+        .iter()
+        .map(|(path, constraints)| NamespaceCount {
+            path: path.clone(),
+            constraints: *constraints,
+        })
+        .collect();
+    let top_level: usize = namespaces
+        .iter()
+        .filter(|count| !count.path.contains('/'))
+        .map(|count| count.constraints)
+        .sum();
+    Ok(CircuitProfile {
+        metrics,
+        namespaces,
+        outside: metrics.constraints - top_level,
+    })
+}
+
+#[test]
+fn test_constraint_profile_of_the_membership_circuit() {
+    use crate::common::MerkleParams;
+    use crate::constraints::MTreeVerification;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let profile = constraint_profile(<MTreeVerification>::blank(&params, 3, 8)).unwrap();
+    assert_eq!(
+        profile
+            .top_level()
+            .map(|count| count.constraints)
+            .sum::<usize>()
+            + profile.outside,
+        profile.metrics.constraints
+    );
+
+    // hashing the path dominates, and doesn't happen while allocating
+    let membership = profile.constraints_in("membership").unwrap();
+    assert!(membership > profile.metrics.constraints / 2);
+    assert!(profile.constraints_in("leaf_var").unwrap() > 0);
+    assert!(profile.constraints_in("root_var").unwrap_or(0) < membership);
+
+    let json = profile.to_json();
+    assert!(json.contains(&format!(
+        "\"constraints\": {},",
+        profile.metrics.constraints
+    )));
+    assert!(json.contains(&format!("\"membership\": {}", membership)));
+
+    // a deeper tree only adds path hashing
+    let deeper = constraint_profile(<MTreeVerification>::blank(&params, 4, 8)).unwrap();
+    assert_eq!(
+        deeper.constraints_in("leaf_var"),
+        profile.constraints_in("leaf_var")
+    );
+    assert!(deeper.constraints_in("membership").unwrap() > membership);
+}
+
+// How the membership circuit grows with the tree and the leaf:
+// `cargo test --release --features testing -- --ignored --nocapture print_membership_profiles`
+#[test]
+#[ignore]
+fn print_membership_profiles() {
+    use crate::common::MerkleParams;
+    use crate::constraints::MTreeVerification;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    for height in [3, 8, 16].iter().copied() {
+        for leaf_len in [8, 64].iter().copied() {
+            let profile =
+                constraint_profile(<MTreeVerification>::blank(&params, height, leaf_len)).unwrap();
+            println!(
+                "height {}, {}-byte leaf:\n{}",
+                height,
+                leaf_len,
+                profile.to_json()
+            );
+        }
+    }
+}
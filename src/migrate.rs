@@ -0,0 +1,102 @@
+use crate::error::MerkleError;
+use crate::root::MerkleRoot;
+use crate::serialization::deserialize_path;
+use ark_serialize::CanonicalSerialize;
+use ark_std::vec::Vec;
+
+// Carrying stored roots and proofs over to projects on crypto-primitives 0.4.
+//
+// Only the byte layouts are mapped here, which needs nothing of 0.4:
+// ark-serialize 0.4 encodes field elements, vectors and `usize` exactly like
+// 0.2 does (compressed little-endian elements, `u64` lengths and indices):
+//
+// - a root is one field element and carries over unchanged;
+// - a path written by `serialization::write_path` (index, leaf sibling, auth
+//   path) is rewritten in the field order 0.4 derives for its `Path` (leaf
+//   sibling, auth path, index).
+//
+// Hash parameters are always listed in the report as unmapped: 0.4 has no
+// serialized form of Pedersen parameters. With the `migrate-v04` feature,
+// `v04` builds them from a `MerkleParams` under 0.4, and its tests check that
+// the migrated roots and paths decode and verify under the 0.4 verifier.
+
+/// The Pedersen trees of this crate under crypto-primitives 0.4.
+#[cfg(feature = "migrate-v04")]
+pub mod v04;
+
+/// Something that could not be carried over, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unmapped {
+    pub artifact: &'static str,
+    pub reason: &'static str,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub unmapped: Vec<Unmapped>,
+}
+
+/// A root and path in their 0.4 encodings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Migrated {
+    pub root: Vec<u8>,
+    pub path: Vec<u8>,
+    pub report: MigrationReport,
+}
+
+/// The 0.4 encoding of a root written by `MerkleRoot::to_bytes`.
+pub fn migrate_root(bytes: &[u8]) -> Result<Vec<u8>, MerkleError> {
+    Ok(MerkleRoot::from_bytes(bytes)?.to_bytes())
+}
+
+/// The 0.4 encoding of a path written by `serialization::serialize_path`.
+pub fn migrate_path(bytes: &[u8]) -> Result<Vec<u8>, MerkleError> {
+    let path = deserialize_path(bytes)?;
+    let mut out = Vec::with_capacity(bytes.len());
+    // writing into a Vec cannot fail
+    path.leaf_sibling_hash.serialize(&mut out).unwrap();
+    path.auth_path.serialize(&mut out).unwrap();
+    (path.leaf_index as u64).serialize(&mut out).unwrap();
+    Ok(out)
+}
+
+/// Migrates a stored root and path, reporting what stays behind.
+pub fn migrate_artifacts(root: &[u8], path: &[u8]) -> Result<Migrated, MerkleError> {
+    Ok(Migrated {
+        root: migrate_root(root)?,
+        path: migrate_path(path)?,
+        report: MigrationReport {
+            unmapped: vec![Unmapped {
+                artifact: "params",
+                reason: "0.4 has no serialized form of Pedersen parameters; build them with `v04::params`",
+            }],
+        },
+    })
+}
+
+#[test]
+fn test_migrate_artifacts() {
+    use crate::common::MerkleParams;
+    use crate::serialization::serialize_path;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = MerkleRoot::from(tree.root()).to_bytes();
+    let path = tree.generate_proof(5).unwrap();
+
+    let migrated = migrate_artifacts(&root, &serialize_path(&path)).unwrap();
+    assert_eq!(migrated.root, root);
+    assert_eq!(migrated.report.unmapped.len(), 1);
+    assert_eq!(migrated.report.unmapped[0].artifact, "params");
+
+    // the same fields, with the index moved to the end
+    let mut expected = Vec::new();
+    path.leaf_sibling_hash.serialize(&mut expected).unwrap();
+    path.auth_path.serialize(&mut expected).unwrap();
+    expected.extend_from_slice(&5u64.to_le_bytes());
+    assert_eq!(migrated.path, expected);
+
+    assert!(migrate_root(&root[1..]).is_err());
+    assert!(migrate_path(&migrated.path[..4]).is_err());
+}
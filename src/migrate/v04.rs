@@ -0,0 +1,107 @@
+use crate::common::{LeafWindow, MerkleParams, TwoToOneWindow, Windows};
+use crate::error::MerkleError;
+use ark_crypto_primitives_v04::crh::injective_map::{PedersenCRHCompressor, PedersenTwoToOneCRHCompressor, TECompressor};
+use ark_crypto_primitives_v04::crh::pedersen;
+use ark_crypto_primitives_v04::merkle_tree::{ByteDigestConverter, Config, Path};
+use ark_ec::ProjectiveCurve;
+use ark_ed_on_bls12_381_v04::{EdwardsAffine, EdwardsProjective, Fq};
+use ark_serialize::{CanonicalSerialize, SerializationError};
+use ark_serialize_v04::CanonicalDeserialize;
+
+// The Pedersen trees of this crate under crypto-primitives 0.4.
+//
+// 0.4 hashes like 0.2: the same windows over the little-endian bits of the
+// input, the same x-coordinate compressor, and a node hashes the
+// uncompressed bytes of its children, which for a field element is its one
+// encoding. So the generators of a `MerkleParams` are all that has to carry
+// over, point by point through their coordinates, and the 0.4 tree over
+// them has the roots and paths of the 0.2 one. 0.4 has no serialized form of
+// Pedersen parameters, which is why `migrate_artifacts` reports them
+// unmapped: a 0.4 project builds them with `params`.
+
+impl<const W: usize, const N: usize> pedersen::Window for Windows<W, N> {
+    const WINDOW_SIZE: usize = W;
+    const NUM_WINDOWS: usize = N;
+}
+
+/// `MerkleConfig` under 0.4.
+#[derive(Clone)]
+pub struct V04MerkleConfig;
+
+impl Config for V04MerkleConfig {
+    type Leaf = [u8];
+    type LeafDigest = Fq;
+    type LeafInnerDigestConverter = ByteDigestConverter<Fq>;
+    type InnerDigest = Fq;
+    type LeafHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, LeafWindow>;
+    type TwoToOneHash = PedersenTwoToOneCRHCompressor<EdwardsProjective, TECompressor, TwoToOneWindow>;
+}
+
+pub type V04Parameters = pedersen::Parameters<EdwardsProjective>;
+pub type V04Path = Path<V04MerkleConfig>;
+
+fn coordinate(x: &ark_ed_on_bls12_381::Fq) -> Result<Fq, MerkleError> {
+    let mut bytes = Vec::new();
+    x.serialize(&mut bytes)?;
+    Ok(Fq::deserialize_compressed(&bytes[..]).map_err(|_| SerializationError::InvalidData)?)
+}
+
+fn generators(generators: &[Vec<ark_ed_on_bls12_381::EdwardsProjective>]) -> Result<V04Parameters, MerkleError> {
+    let generators = generators
+        .iter()
+        .map(|window| {
+            window
+                .iter()
+                .map(|point| {
+                    let point = point.into_affine();
+                    // a point of the same curve, so no check is needed
+                    Ok(EdwardsAffine::new_unchecked(coordinate(&point.x)?, coordinate(&point.y)?).into())
+                })
+                .collect::<Result<Vec<_>, MerkleError>>()
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(pedersen::Parameters { generators })
+}
+
+/// The 0.4 parameters of the leaf hash and of the node hash of `params`.
+pub fn params(params: &MerkleParams) -> Result<(V04Parameters, V04Parameters), MerkleError> {
+    Ok((
+        generators(&params.leaf_crh_params.generators)?,
+        generators(&params.two_to_one_crh_params.generators)?,
+    ))
+}
+
+/// A root `migrate_root` wrote, decoded by 0.4.
+pub fn decode_root(bytes: &[u8]) -> Result<Fq, MerkleError> {
+    Ok(Fq::deserialize_compressed(bytes).map_err(|_| SerializationError::InvalidData)?)
+}
+
+/// A path `migrate_path` wrote, decoded by 0.4.
+pub fn decode_path(bytes: &[u8]) -> Result<V04Path, MerkleError> {
+    Ok(V04Path::deserialize_compressed(bytes).map_err(|_| SerializationError::InvalidData)?)
+}
+
+#[test]
+fn test_migrated_proofs_verify_under_v04() {
+    use crate::fixtures::fixture_tree;
+    use crate::migrate::migrate_artifacts;
+    use crate::root::MerkleRoot;
+    use crate::serialization::serialize_path;
+    use ark_crypto_primitives_v04::merkle_tree::MerkleTree;
+
+    let fixture = fixture_tree(8);
+    let root = MerkleRoot::from(fixture.root()).to_bytes();
+    let (leaf_params, node_params) = params(&fixture.params).unwrap();
+
+    // 0.4 builds the same tree over the same leaves
+    let tree = MerkleTree::<V04MerkleConfig>::new(&leaf_params, &node_params, &fixture.leaves).unwrap();
+    assert_eq!(decode_root(&root).unwrap(), tree.root());
+
+    for index in 0..fixture.leaves.len() {
+        let migrated = migrate_artifacts(&root, &serialize_path(&fixture.proof(index))).unwrap();
+        let (root, path) = (decode_root(&migrated.root).unwrap(), decode_path(&migrated.path).unwrap());
+        assert_eq!(path.leaf_index, index);
+        assert!(path.verify(&leaf_params, &node_params, &root, fixture.leaves[index].as_slice()).unwrap());
+        assert!(!path.verify(&leaf_params, &node_params, &root, fixture.leaves[index ^ 1].as_slice()).unwrap());
+    }
+}
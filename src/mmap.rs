@@ -0,0 +1,217 @@
+use crate::bundle::{fingerprint, Fingerprint};
+use crate::common::MerkleParams;
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::level_tree::{read_snapshot_header, SNAPSHOT_FORMAT_VERSION, SNAPSHOT_HASH_CONFIG};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use memmap2::Mmap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::path::Path;
+
+// A read-only tree served straight from a snapshot file.
+//
+// `LevelTree::import_snapshot` reads every leaf and every digest into memory
+// before it opens a single path, which for a tree of millions of leaves is
+// most of a server's startup time and of its memory. `MmapTree` maps the
+// snapshot instead and reads the digests of a path off the mapping when it is
+// asked for it, so opening the tree touches the header alone and the pages of
+// a path are the only ones the OS has to bring in; the rest stay on disk, or
+// in the page cache shared with every other process serving the same file.
+//
+// The digests are the end of the snapshot, `2n - 1` of them for `n` leaves,
+// each in the canonical encoding of a `Root`, which has one size. So for all
+// that the leaves before them have their own lengths, the digests start
+// `(2n - 1)` digests before the end of the file, and the leaves are never
+// read: the tree opens paths, not leaves. The header is checked as
+// `import_snapshot` checks it, with `params` for the fingerprint, but the
+// digests are not, each being decoded only when a path needs it.
+//
+// `SimpleMerkleTree` is arkworks' `MerkleTree`, which can't have inherent
+// methods here, so `SimpleMerkleTree::open_mmap` comes from the `OpenMmap`
+// extension trait, like `TreeStatsExt::stats`.
+//
+// The file must not change while it is mapped. Snapshots are written once
+// and replaced, never edited in place, so a server should swap in a new file
+// and open it, not rewrite the one it serves.
+
+pub struct MmapTree {
+    map: Mmap,
+    params_fingerprint: Fingerprint,
+    leaf_count: usize,
+    // where the leaf hashes start, and the size of each digest
+    digests: usize,
+    digest_len: usize,
+}
+
+impl MmapTree {
+    /// Maps the snapshot at `path`, as `LevelTree::export_snapshot` writes
+    /// it. Fails like `import_snapshot` on a snapshot of a later format or of
+    /// other parameters than `params`, and on a file too short for its header
+    /// and digests.
+    pub fn open<P: AsRef<Path>>(path: P, params: &MerkleParams) -> Result<Self, MerkleError> {
+        let file = File::open(path)?;
+        // Safety: the mapping is read-only, and snapshots aren't modified in
+        // place, so the bytes under it don't change while it lives.
+        let map = unsafe { Mmap::map(&file)? };
+
+        let mut reader = &map[..];
+        let header = read_snapshot_header(&mut reader)?;
+        if header.version != SNAPSHOT_FORMAT_VERSION {
+            return Err(MerkleError::UnknownVersion {
+                version: header.version as usize,
+                latest: SNAPSHOT_FORMAT_VERSION as usize,
+            });
+        }
+        if header.hash_config != SNAPSHOT_HASH_CONFIG || header.params_fingerprint != fingerprint(params)? {
+            return Err(MerkleError::ParamsMismatch);
+        }
+        // the header is untrusted: a count that doesn't fit, or whose
+        // digests don't, is a corrupt file rather than an overflow
+        let leaf_count = u64::deserialize(&mut reader)?;
+        let leaf_count = usize::try_from(leaf_count).map_err(|_| SerializationError::InvalidData)?;
+        if !leaf_count.is_power_of_two() || leaf_count < 2 {
+            return Err(SerializationError::InvalidData.into());
+        }
+
+        // every leaf takes at least its length, and the digests follow
+        let body = map.len() - reader.len();
+        let digest_len = Root::default().serialized_size();
+        let digests_len = leaf_count
+            .checked_mul(2)
+            .and_then(|nodes| nodes.checked_sub(1))
+            .and_then(|nodes| nodes.checked_mul(digest_len))
+            .ok_or(SerializationError::InvalidData)?;
+        let min_len = leaf_count
+            .checked_mul(8)
+            .and_then(|min_leaves_len| body.checked_add(min_leaves_len))
+            .and_then(|len| len.checked_add(digests_len))
+            .ok_or(SerializationError::InvalidData)?;
+        if min_len > map.len() {
+            return Err(SerializationError::InvalidData.into());
+        }
+        let digests = map.len() - digests_len;
+        Ok(MmapTree {
+            map,
+            params_fingerprint: header.params_fingerprint,
+            leaf_count,
+            digests,
+            digest_len,
+        })
+    }
+
+    /// The number of levels above the leaves.
+    pub fn depth(&self) -> usize {
+        self.leaf_count.trailing_zeros() as usize
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// The fingerprint of the parameters the tree was hashed with.
+    pub fn params_fingerprint(&self) -> &Fingerprint {
+        &self.params_fingerprint
+    }
+
+    /// The node at `index` of `level`, like `LevelTree::node`, decoded from
+    /// the mapping.
+    pub fn node(&self, level: usize, index: usize) -> Result<Root, MerkleError> {
+        if level > self.depth() || index >= self.leaf_count >> level {
+            return Err(MerkleError::LeafIndexOutOfRange {
+                index,
+                len: self.leaf_count >> level.min(self.depth()),
+            });
+        }
+        // the levels below take 2n - 2n / 2^level digests
+        let position = 2 * (self.leaf_count - (self.leaf_count >> level)) + index;
+        let start = self.digests + position * self.digest_len;
+        Ok(Root::deserialize(&self.map[start..start + self.digest_len])?)
+    }
+
+    pub fn root(&self) -> Result<Root, MerkleError> {
+        self.node(self.depth(), 0)
+    }
+
+    /// The path of the leaf at `index`, the one `LevelTree::prove` opens on
+    /// the tree of the snapshot: `depth()` digests read off the mapping.
+    pub fn generate_proof(&self, index: usize) -> Result<SimplePath, MerkleError> {
+        let index = LeafIndex::checked_new(index as u64, self.leaf_count as u64)?.as_usize();
+        Ok(SimplePath {
+            leaf_sibling_hash: self.node(0, index ^ 1)?,
+            // top-down, and neither the leaf level nor the root
+            auth_path: (1..self.depth())
+                .rev()
+                .map(|level| self.node(level, (index >> level) ^ 1))
+                .collect::<Result<_, _>>()?,
+            leaf_index: index,
+        })
+    }
+}
+
+/// `MmapTree::open` as an associated function of `SimpleMerkleTree`.
+pub trait OpenMmap {
+    /// Maps the snapshot at `path`, which `params` must have hashed.
+    fn open_mmap<P: AsRef<Path>>(path: P, params: &MerkleParams) -> Result<MmapTree, MerkleError>;
+}
+
+impl OpenMmap for SimpleMerkleTree {
+    fn open_mmap<P: AsRef<Path>>(path: P, params: &MerkleParams) -> Result<MmapTree, MerkleError> {
+        MmapTree::open(path, params)
+    }
+}
+
+#[test]
+fn test_mmap_tree_serves_the_snapshot() {
+    use crate::backend::MerkleBackend;
+    use crate::level_tree::LevelTree;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    // leaves of different lengths, so the digests don't start at a fixed offset
+    let leaves: Vec<Vec<u8>> = (0u32..1 << 10).map(|i| vec![i as u8; (i % 7) as usize]).collect();
+    let tree = LevelTree::new(params.clone(), &leaves).unwrap();
+    let path = std::env::temp_dir().join(format!("merkle-mmap-{}.snapshot", std::process::id()));
+    let mut snapshot = Vec::new();
+    tree.export_snapshot(&mut snapshot).unwrap();
+    std::fs::write(&path, &snapshot).unwrap();
+
+    let mapped = SimpleMerkleTree::open_mmap(&path, &params).unwrap();
+    assert_eq!((mapped.leaf_count(), mapped.depth()), (1 << 10, 10));
+    assert_eq!(mapped.root().unwrap(), tree.root());
+    assert_eq!(mapped.params_fingerprint(), &fingerprint(&params).unwrap());
+    for index in [0, 1, 511, 1023].iter().copied() {
+        let (expected, opened) = (tree.prove(index).unwrap(), mapped.generate_proof(index).unwrap());
+        assert_eq!(opened.leaf_index, expected.leaf_index);
+        assert_eq!(opened.leaf_sibling_hash, expected.leaf_sibling_hash);
+        assert_eq!(opened.auth_path, expected.auth_path);
+        assert!(params.verify_path(&opened, &tree.root(), &leaves[index]).unwrap());
+    }
+    assert!(matches!(
+        mapped.generate_proof(1 << 10),
+        Err(MerkleError::LeafIndexOutOfRange { index: 1024, len: 1024 })
+    ));
+    assert!(mapped.node(11, 0).is_err());
+
+    // other parameters, and a snapshot cut short of its digests
+    let other = MerkleParams::setup(&mut rng).unwrap();
+    assert!(matches!(MmapTree::open(&path, &other), Err(MerkleError::ParamsMismatch)));
+    std::fs::write(&path, &snapshot[..snapshot.len() / 2]).unwrap();
+    assert!(MmapTree::open(&path, &params).is_err());
+
+    // leaf counts whose digests would overflow the size computations
+    let mut reader = &snapshot[..];
+    read_snapshot_header(&mut reader).unwrap();
+    let count_at = snapshot.len() - reader.len();
+    for count in [1u64 << 63, 1 << 62, 1 << 59].iter() {
+        let mut huge = snapshot.clone();
+        huge[count_at..count_at + 8].copy_from_slice(&count.to_le_bytes());
+        std::fs::write(&path, &huge).unwrap();
+        assert!(matches!(
+            MmapTree::open(&path, &params),
+            Err(MerkleError::Serialization(SerializationError::InvalidData))
+        ));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,354 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::RootVar;
+use crate::error::MerkleError;
+#[cfg(feature = "constraints")]
+use crate::inputs::PublicInputs;
+use crate::Root;
+#[cfg(feature = "constraints")]
+use ark_crypto_primitives::crh::{CRHGadget, TwoToOneCRHGadget};
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::prelude::*;
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::vec::Vec;
+
+// A Merkle Mountain Range: an append-only accumulator that, unlike
+// `AppendOnlyTree`, never rehashes old nodes.
+//
+// The leaves form a row of perfect trees, the mountains, one for each set bit
+// of the leaf count, the largest on the left. Appending a leaf adds a
+// mountain of one leaf and merges the two rightmost mountains for as long as
+// they have the same height, so an append costs at most log n hashes and
+// every node, once hashed, stays. The root bags the peaks from the right:
+// `H(p0, H(p1, .. H(pk-1, pk)))`, a single peak being its own root, and the
+// empty range has the all-zero root. Leaves and nodes use the two hashes of
+// `SimpleMerkleTree`.
+//
+// A membership proof is the path from the leaf up to its peak, bottom-up,
+// and the other peaks, from the left. Which mountain a leaf is in, and so the
+// shape of the proof, depends on its index and the leaf count alone.
+// `MmrMembershipCircuit` fixes the leaf count and the mountain, and keeps the
+// leaf's position inside its mountain and every node private.
+
+/// A proof that a leaf is at `leaf_index` in the range of `mmr_size` leaves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MmrProof {
+    pub leaf_index: u64,
+    pub mmr_size: u64,
+    /// The siblings from the leaf up to its peak.
+    pub path: Vec<Root>,
+    /// The peaks of the other mountains, from the left.
+    pub peaks: Vec<Root>,
+}
+
+pub struct MerkleMountainRange {
+    params: MerkleParams,
+    // `levels[h]` holds the roots of the perfect subtrees of height h, from
+    // the left: the leaf hashes, then their complete pairs, and so on.
+    levels: Vec<Vec<Root>>,
+}
+
+// The heights of the mountains of a range of `size` leaves, from the left.
+fn mountain_heights(size: u64) -> Vec<usize> {
+    (0..64).rev().filter(|h| size >> h & 1 == 1).collect()
+}
+
+// The mountain leaf `index` is in, counted from the left, and its height.
+fn locate(index: u64, size: u64) -> (usize, usize) {
+    let mut start = 0;
+    for (mountain, height) in mountain_heights(size).into_iter().enumerate() {
+        start += 1 << height;
+        if index < start {
+            return (mountain, height);
+        }
+    }
+    unreachable!("index is checked against the size")
+}
+
+impl MerkleMountainRange {
+    pub fn new(params: MerkleParams) -> Self {
+        MerkleMountainRange {
+            params,
+            levels: vec![Vec::new()],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    /// Appends a leaf, returning its index.
+    pub fn append(&mut self, leaf: &[u8]) -> Result<usize, MerkleError> {
+        self.levels[0].push(self.params.hash_leaf(leaf)?);
+        let mut height = 0;
+        // a level with an even number of nodes has just completed a pair
+        while self.levels[height].len() % 2 == 0 {
+            let level = &self.levels[height];
+            let parent = self.params.hash_children(&level[level.len() - 2], &level[level.len() - 1])?;
+            if self.levels.len() == height + 1 {
+                self.levels.push(Vec::new());
+            }
+            self.levels[height + 1].push(parent);
+            height += 1;
+        }
+        Ok(self.len() - 1)
+    }
+
+    /// The peaks, from the left.
+    pub fn peaks(&self) -> Vec<Root> {
+        // the last node of a level with an odd number of them is a peak
+        mountain_heights(self.len() as u64)
+            .into_iter()
+            .map(|height| *self.levels[height].last().unwrap())
+            .collect()
+    }
+
+    /// The root of the range, all of its peaks bagged into one.
+    pub fn bag_peaks(&self) -> Result<Root, MerkleError> {
+        bag_peaks(&self.params, &self.peaks())
+    }
+
+    /// The membership proof of leaf `index`.
+    pub fn prove(&self, index: usize) -> Result<MmrProof, MerkleError> {
+        if index >= self.len() {
+            return Err(MerkleError::LeafIndexOutOfRange {
+                index,
+                len: self.len(),
+            });
+        }
+        let (mountain, height) = locate(index as u64, self.len() as u64);
+        let path = (0..height).map(|level| self.levels[level][(index >> level) ^ 1]).collect();
+        let mut peaks = self.peaks();
+        peaks.remove(mountain);
+        Ok(MmrProof {
+            leaf_index: index as u64,
+            mmr_size: self.len() as u64,
+            path,
+            peaks,
+        })
+    }
+}
+
+/// Bags `peaks`, from the left, into the root of their range.
+pub fn bag_peaks(params: &MerkleParams, peaks: &[Root]) -> Result<Root, MerkleError> {
+    let (last, rest) = match peaks.split_last() {
+        Some(split) => split,
+        None => return Ok(Root::default()),
+    };
+    rest.iter()
+        .rev()
+        .try_fold(*last, |bag, peak| params.hash_children(peak, &bag))
+}
+
+/// Checks that `leaf` is where `proof` says in the range with `root`.
+pub fn verify_mmr_proof(
+    params: &MerkleParams,
+    leaf: &[u8],
+    proof: &MmrProof,
+    root: &Root,
+) -> Result<bool, MerkleError> {
+    if proof.leaf_index >= proof.mmr_size {
+        return Ok(false);
+    }
+    let (mountain, height) = locate(proof.leaf_index, proof.mmr_size);
+    if proof.path.len() != height || proof.peaks.len() + 1 != mountain_heights(proof.mmr_size).len() {
+        return Ok(false);
+    }
+    let mut node = params.hash_leaf(leaf)?;
+    for (level, sibling) in proof.path.iter().enumerate() {
+        node = if proof.leaf_index >> level & 1 == 1 {
+            params.hash_children(sibling, &node)?
+        } else {
+            params.hash_children(&node, sibling)?
+        };
+    }
+    let mut peaks = proof.peaks.clone();
+    peaks.insert(mountain, node);
+    Ok(bag_peaks(params, &peaks)? == *root)
+}
+
+/// `bag_peaks` in the circuit.
+#[cfg(feature = "constraints")]
+pub fn bag_peaks_var(two_to_one_crh_params: &TwoToOneHashParamsVar, peaks: &[RootVar]) -> Result<RootVar, SynthesisError> {
+    let (last, rest) = peaks.split_last().ok_or(SynthesisError::Unsatisfiable)?;
+    let mut bag = last.clone();
+    for peak in rest.iter().rev() {
+        bag = <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::evaluate(
+            two_to_one_crh_params,
+            &peak.to_bytes()?,
+            &bag.to_bytes()?,
+        )?;
+    }
+    Ok(bag)
+}
+
+/// Proves that a public leaf is in the range with a public root. The leaf
+/// count and the leaf's mountain fix the shape of the circuit and are
+/// revealed by it; the position inside the mountain, the path and the other
+/// peaks stay private.
+#[cfg(feature = "constraints")]
+pub struct MmrMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub mmr_size: u64,
+    pub mountain: usize,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub leaf: Vec<u8>,
+
+    // This is the private witness to the circuit.
+    pub proof: Option<MmrProof>,
+}
+
+#[cfg(feature = "constraints")]
+impl MmrMembershipCircuit {
+    pub fn new(params: &MerkleParams, root: Root, leaf: &[u8], proof: &MmrProof) -> Self {
+        MmrMembershipCircuit {
+            params: params.clone(),
+            mmr_size: proof.mmr_size,
+            mountain: locate(proof.leaf_index, proof.mmr_size).0,
+            root,
+            leaf: leaf.to_vec(),
+            proof: Some(proof.clone()),
+        }
+    }
+
+    /// The circuit for leaves of `leaf_len` bytes in mountain `mountain` of a
+    /// range of `mmr_size` leaves, without a witness, for key generation.
+    pub fn blank(params: &MerkleParams, mmr_size: u64, mountain: usize, leaf_len: usize) -> Self {
+        MmrMembershipCircuit {
+            params: params.clone(),
+            mmr_size,
+            mountain,
+            root: Root::default(),
+            leaf: vec![0u8; leaf_len],
+            proof: None,
+        }
+    }
+
+    /// The public inputs, laid out like those of `MTreeVerification`: the
+    /// root, then the leaf bytes.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        PublicInputs::from_parts(&self.root, &self.leaf).to_field_elements()
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for MmrMembershipCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        let heights = mountain_heights(self.mmr_size);
+        let height = *heights.get(self.mountain).ok_or(SynthesisError::Unsatisfiable)?;
+        let proof = self.proof.as_ref();
+
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let leaf = UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_var"), &self.leaf)?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params = LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the position in the mountain, the path and the other peaks as private witnesses:
+        let position = (0..height)
+            .map(|level| {
+                Boolean::new_witness(ark_relations::ns!(cs, "position_var"), || {
+                    proof.map(|p| p.leaf_index >> level & 1 == 1).ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let path = (0..height)
+            .map(|i| {
+                RootVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+                    proof.and_then(|p| p.path.get(i)).ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut peaks = (0..heights.len() - 1)
+            .map(|i| {
+                RootVar::new_witness(ark_relations::ns!(cs, "peak_var"), || {
+                    proof.and_then(|p| p.peaks.get(i)).ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        let mut node = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(&leaf_crh_params, &leaf)?;
+        for (is_right, sibling) in position.iter().zip(&path) {
+            let left = is_right.select(sibling, &node)?;
+            let right = is_right.select(&node, sibling)?;
+            node = <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::evaluate(
+                &two_to_one_crh_params,
+                &left.to_bytes()?,
+                &right.to_bytes()?,
+            )?;
+        }
+        peaks.insert(self.mountain, node);
+        bag_peaks_var(&two_to_one_crh_params, &peaks)?.enforce_equal(&root)
+    }
+}
+
+#[test]
+fn test_mmr_append_and_prove() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut mmr = MerkleMountainRange::new(params.clone());
+    assert_eq!(mmr.bag_peaks().unwrap(), Root::default());
+
+    let mut roots = Vec::new();
+    for i in 0..11u8 {
+        assert_eq!(mmr.append(&[i]).unwrap(), i as usize);
+        roots.push(mmr.bag_peaks().unwrap());
+    }
+    // 11 = 8 + 2 + 1
+    assert_eq!(mmr.peaks().len(), 3);
+    assert_eq!(mountain_heights(11), vec![3, 1, 0]);
+    assert_eq!(locate(7, 11), (0, 3));
+    assert_eq!(locate(9, 11), (1, 1));
+    assert_eq!(locate(10, 11), (2, 0));
+
+    // a power of two is one mountain, the balanced tree of `SimpleMerkleTree`
+    let leaves: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i]).collect();
+    let balanced = params.build_tree(&leaves).unwrap();
+    assert_eq!(roots[7], balanced.root());
+
+    for index in 0..11 {
+        let proof = mmr.prove(index).unwrap();
+        assert!(verify_mmr_proof(&params, &[index as u8], &proof, &roots[10]).unwrap());
+        assert!(!verify_mmr_proof(&params, &[index as u8 + 1], &proof, &roots[10]).unwrap());
+        // the proof is for one size only
+        assert!(!verify_mmr_proof(&params, &[index as u8], &proof, &roots[9]).unwrap());
+    }
+    assert!(mmr.prove(11).is_err());
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_mmr_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied, synthesize_with_report};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut mmr = MerkleMountainRange::new(params.clone());
+    for i in 0..7u8 {
+        mmr.append(&[i; 4]).unwrap();
+    }
+    let root = mmr.bag_peaks().unwrap();
+    // in the mountains of 4, 2 and 1 leaves
+    for index in [2usize, 5, 6].iter().copied() {
+        let proof = mmr.prove(index).unwrap();
+        let circuit = MmrMembershipCircuit::new(&params, root, &[index as u8; 4], &proof);
+        let mountain = circuit.mountain;
+        let metrics = assert_satisfied_with_report(circuit);
+        let blank = synthesize_with_report(MmrMembershipCircuit::blank(&params, 7, mountain, 4)).unwrap().metrics;
+        assert_eq!(metrics, blank);
+        expect_unsatisfied(MmrMembershipCircuit::new(&params, root, &[9; 4], &proof), "membership");
+    }
+}
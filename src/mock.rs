@@ -0,0 +1,242 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::MerkleCircuitConfig;
+#[cfg(test)]
+use crate::error::MerkleError;
+#[cfg(test)]
+use crate::index::LeafIndex;
+#[cfg(test)]
+use crate::serialization::{read_path, write_path};
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::merkle_tree::{Config, MerkleTree, Path};
+use ark_ff::{UniformRand, Zero};
+use ark_std::rand::Rng;
+#[cfg(test)]
+use ark_std::vec::Vec;
+#[cfg(feature = "constraints")]
+use ark_crypto_primitives::crh::{CRHGadget, TwoToOneCRHGadget};
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::{fields::fp::FpVar, prelude::*};
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::SynthesisError;
+
+// A fast stand-in for the Pedersen hashes, for tests of everything that isn't
+// the cryptography: padding, indexing, path serialization, proof assembly.
+//
+// NOT COLLISION RESISTANT. The "hash" of a byte string is the polynomial with
+// the bytes as coefficients, evaluated at a secret random point, plus the
+// length: h(b) = len(b) + sum_i b_i * k^(i+1). It is linear in the input, so
+// anyone who knows k (it is in the public parameters) can produce collisions
+// at will. Never build a production tree with it.
+//
+// It is about a single field multiplication per input byte, natively and in
+// R1CS with the key as a constant, so mock trees and circuits build in
+// milliseconds. Digests are `ConstraintF` elements like the Pedersen ones.
+
+fn fold(key: &ConstraintF, input: &[u8]) -> ConstraintF {
+    let mut acc = ConstraintF::zero();
+    for byte in input.iter().rev() {
+        acc = (acc + ConstraintF::from(*byte)) * key;
+    }
+    acc + ConstraintF::from(input.len() as u64)
+}
+
+/// The mock leaf hash.
+pub struct MockLeafHash;
+
+impl CRH for MockLeafHash {
+    // the same limit as the Pedersen leaf hash, so leaf length checks agree
+    const INPUT_SIZE_BITS: usize = MAX_LEAF_LEN * 8;
+    type Output = ConstraintF;
+    type Parameters = ConstraintF;
+
+    fn setup<R: Rng>(rng: &mut R) -> Result<Self::Parameters, ark_crypto_primitives::Error> {
+        Ok(ConstraintF::rand(rng))
+    }
+
+    fn evaluate(key: &Self::Parameters, input: &[u8]) -> Result<Self::Output, ark_crypto_primitives::Error> {
+        Ok(fold(key, input))
+    }
+}
+
+/// The mock node hash: the mock leaf hash of `left || right`, under its own key.
+pub struct MockTwoToOneHash;
+
+impl TwoToOneCRH for MockTwoToOneHash {
+    const LEFT_INPUT_SIZE_BITS: usize = 256;
+    const RIGHT_INPUT_SIZE_BITS: usize = 256;
+    type Output = ConstraintF;
+    type Parameters = ConstraintF;
+
+    fn setup<R: Rng>(rng: &mut R) -> Result<Self::Parameters, ark_crypto_primitives::Error> {
+        Ok(ConstraintF::rand(rng))
+    }
+
+    fn evaluate(
+        key: &Self::Parameters,
+        left_input: &[u8],
+        right_input: &[u8],
+    ) -> Result<Self::Output, ark_crypto_primitives::Error> {
+        let mut input = left_input.to_vec();
+        input.extend_from_slice(right_input);
+        Ok(fold(key, &input))
+    }
+}
+
+#[derive(Clone)]
+pub struct MockMerkleConfig;
+
+impl Config for MockMerkleConfig {
+    type LeafHash = MockLeafHash;
+    type TwoToOneHash = MockTwoToOneHash;
+}
+
+pub type MockParams = TreeParams<MockMerkleConfig>;
+pub type MockTree = MerkleTree<MockMerkleConfig>;
+pub type MockPath = Path<MockMerkleConfig>;
+
+#[cfg(feature = "constraints")]
+fn fold_var(key: &FpVar<ConstraintF>, input: &[UInt8<ConstraintF>]) -> Result<FpVar<ConstraintF>, SynthesisError> {
+    let mut acc = FpVar::zero();
+    for byte in input.iter().rev() {
+        acc = (acc + Boolean::le_bits_to_fp_var(&byte.to_bits_le()?)?) * key;
+    }
+    Ok(acc + FpVar::constant(ConstraintF::from(input.len() as u64)))
+}
+
+#[cfg(feature = "constraints")]
+pub struct MockLeafHashGadget;
+
+#[cfg(feature = "constraints")]
+impl CRHGadget<MockLeafHash, ConstraintF> for MockLeafHashGadget {
+    type OutputVar = FpVar<ConstraintF>;
+    type ParametersVar = FpVar<ConstraintF>;
+
+    fn evaluate(key: &Self::ParametersVar, input: &[UInt8<ConstraintF>]) -> Result<Self::OutputVar, SynthesisError> {
+        fold_var(key, input)
+    }
+}
+
+#[cfg(feature = "constraints")]
+pub struct MockTwoToOneHashGadget;
+
+#[cfg(feature = "constraints")]
+impl TwoToOneCRHGadget<MockTwoToOneHash, ConstraintF> for MockTwoToOneHashGadget {
+    type OutputVar = FpVar<ConstraintF>;
+    type ParametersVar = FpVar<ConstraintF>;
+
+    fn evaluate(
+        key: &Self::ParametersVar,
+        left_input: &[UInt8<ConstraintF>],
+        right_input: &[UInt8<ConstraintF>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        let mut input = left_input.to_vec();
+        input.extend_from_slice(right_input);
+        fold_var(key, &input)
+    }
+}
+
+/// The membership circuit over the mock hashes.
+#[cfg(feature = "constraints")]
+pub struct MockMerkleCircuit;
+
+#[cfg(feature = "constraints")]
+impl MerkleCircuitConfig for MockMerkleCircuit {
+    type Config = MockMerkleConfig;
+    type ConstraintF = ConstraintF;
+    type LeafHashGadget = MockLeafHashGadget;
+    type TwoToOneHashGadget = MockTwoToOneHashGadget;
+}
+
+#[cfg(test)]
+fn open(tree: &MockTree, index: usize) -> MockPath {
+    generate_proof(tree, LeafIndex::checked_new(index as u64, leaf_count(tree)).unwrap()).unwrap()
+}
+
+#[cfg(test)]
+fn mock_tree(leaves: &[Vec<u8>]) -> (MockParams, MockTree) {
+    let params = MockParams::setup(&mut ark_std::test_rng()).unwrap();
+    let tree = params.build_tree(leaves).unwrap();
+    (params, tree)
+}
+
+#[test]
+fn test_mock_padding_and_indexing() {
+    // three leaves padded to the next power of two, like the tree wrappers do
+    let mut leaves: Vec<Vec<u8>> = (1u8..=3).map(|i| vec![i; 16]).collect();
+    leaves.resize(leaves.len().next_power_of_two().max(2), Vec::new());
+    let (params, tree) = mock_tree(&leaves);
+    assert_eq!(tree.height(), 3);
+    let root = tree.root();
+
+    for (index, leaf) in leaves.iter().enumerate() {
+        let path = open(&tree, index);
+        assert_eq!(path.leaf_index, index);
+        assert!(params.verify_path(&path, &root, leaf).unwrap());
+        // the path only opens its own leaf
+        for other in leaves.iter().filter(|other| *other != leaf) {
+            assert!(!params.verify_path(&path, &root, other).unwrap());
+        }
+        // and not at a neighbouring position
+        let mut moved = path.clone();
+        moved.leaf_index ^= 1;
+        assert!(!params.verify_path(&moved, &root, leaf).unwrap());
+    }
+
+    // the padding leaf is distinguishable from a shorter all-zero leaf
+    let path = open(&tree, 3);
+    assert!(!params.verify_path(&path, &root, vec![0u8]).unwrap());
+
+    assert!(matches!(
+        params.build_tree(vec![vec![0u8; MAX_LEAF_LEN + 1], vec![]]).unwrap_err().root_cause(),
+        MerkleError::LeafTooLong { .. }
+    ));
+}
+
+#[test]
+fn test_mock_path_serialization() {
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let (params, tree) = mock_tree(&leaves);
+    for index in 0..leaves.len() {
+        let path = open(&tree, index);
+        let mut bytes = Vec::new();
+        write_path(&path, &mut bytes).unwrap();
+        let decoded: MockPath = read_path(&bytes[..]).unwrap();
+        assert_eq!(decoded.leaf_index, index);
+        assert_eq!(decoded.auth_path, path.auth_path);
+        assert!(params.verify_path(&decoded, &tree.root(), &leaves[index]).unwrap());
+        assert!(read_path::<MockMerkleConfig, _>(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_mock_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+    use crate::constraints::MerkleTreeVerification;
+
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let (params, tree) = mock_tree(&leaves);
+    let circuit = |claimed: &[u8]| MerkleTreeVerification::<MockMerkleCircuit> {
+        leaf_crh_params: params.leaf_crh_params,
+        two_to_one_crh_params: params.two_to_one_crh_params,
+        root: tree.root(),
+        leaf: claimed.to_vec(),
+        auth_path: Some(open(&tree, 2)),
+    };
+    assert_satisfied_with_report(circuit(&leaves[2]));
+    expect_unsatisfied(circuit(&leaves[1]), "membership");
+}
+
+#[test]
+fn test_mock_padded_tree_of_1000_leaves() {
+    let params = MockParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0..1000u32).map(|i| i.to_le_bytes().to_vec()).collect();
+    let tree = params.build_padded_tree(&leaves).unwrap();
+    assert_eq!((tree.len(), tree.height()), (1000, 11));
+    for index in [0, 511, 999].iter().copied() {
+        assert!(params.verify_path(&tree.prove(index).unwrap(), &tree.root(), &leaves[index]).unwrap());
+    }
+    assert!(tree.prove(1000).is_err());
+    assert_eq!(tree.root(), params.build_padded_tree(&leaves).unwrap().root());
+}
@@ -0,0 +1,1008 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::RootVar;
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::{Root, SimpleMerkleTree, SimplePath};
+#[cfg(feature = "constraints")]
+use ark_crypto_primitives::crh::{CRHGadget, TwoToOneCRHGadget, CRH};
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::prelude::*;
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::io::{Read, Write};
+use ark_std::vec::Vec;
+
+// One proof for several leaves of a tree.
+//
+// The paths of k leaves share every node above the point where they meet, and
+// where two opened leaves are siblings, neither needs the other's digest. A
+// `MultiPath` holds each sibling once and only if the opened leaves can't
+// recompute it: the verifier hashes the leaves, then climbs level by level,
+// pairing known nodes with each other where it can and taking the next
+// sibling from the proof where it can't, until one node, the root, is left.
+//
+// The siblings are ordered by level, from the leaves up, and left to right
+// within a level, which is the order the verifier consumes them in. That
+// order depends only on the set of indices, so the indices may be given in
+// any order, but each at most once.
+//
+// A run of adjacent leaves `start..end` is the case multiproofs are best at:
+// inside the run every node pairs up with its neighbour, so only the siblings
+// along its left and right edges are in the proof, at most two per level.
+// `generate_range_proof` and `RangeMembershipCircuit` are that case.
+//
+// A `CompressedMultiProof` is for clients that want the paths themselves,
+// to store or to hand on, rather than one check of all the leaves. It holds
+// every distinct sibling of the paths once, siblings the opened leaves could
+// recompute included, so it expands back into the paths without the leaves
+// and without hashing. It is larger than a `MultiPath` of the same leaves,
+// but still far smaller than the paths for leaves close together, which
+// share all their siblings above the point where they meet.
+//
+// `MultiPathVar` climbs the same way in a circuit. The indices fix which
+// nodes pair up, so they are part of the shape of the circuit rather than
+// witnesses, and every node shared by the opened leaves is hashed once.
+//
+// `SharedPrefixPathVar` is for leaves whose positions stay private, all in
+// one subtree of a known height: a run of deposits, the accounts of one
+// user. Each path is split where the subtree's root is. The segments below
+// it are witnessed per leaf and each hashes its leaf up to the subtree root,
+// which must be the same for all; the one segment above it is witnessed and
+// hashed once. Only the number of leaves, the depth and the height of the
+// subtree fix the shape of `SubtreeMembershipCircuit`.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultiPath {
+    /// The number of levels above the leaves.
+    pub depth: usize,
+    pub siblings: Vec<Root>,
+}
+
+/// The multiproof for the leaves at `indices` of `tree`.
+pub fn generate_multiproof(tree: &SimpleMerkleTree, indices: &[usize]) -> Result<MultiPath, MerkleError> {
+    let indices = sorted_indices(indices.iter().copied(), leaf_count(tree))?;
+    let paths = indices
+        .iter()
+        .map(|index| Ok(tree.generate_proof(*index)?))
+        .collect::<Result<Vec<SimplePath>, MerkleError>>()?;
+    MultiPath::from_paths(&paths)
+}
+
+/// The multiproof for the leaves `start..end` of `tree`. Panics if the range is empty.
+pub fn generate_range_proof(tree: &SimpleMerkleTree, start: usize, end: usize) -> Result<MultiPath, MerkleError> {
+    assert!(start < end, "a range of at least one leaf");
+    generate_multiproof(tree, &(start..end).collect::<Vec<_>>())
+}
+
+impl MultiPath {
+    /// The multiproof combining `paths`, all of the same tree, in any order.
+    pub fn from_paths(paths: &[SimplePath]) -> Result<Self, MerkleError> {
+        let depth = paths.first().map_or(0, |path| path.auth_path.len() + 1);
+        assert!(
+            paths.iter().all(|path| path.auth_path.len() + 1 == depth),
+            "the paths of one tree"
+        );
+        let mut paths: Vec<&SimplePath> = paths.iter().collect();
+        paths.sort_unstable_by_key(|path| path.leaf_index);
+        let indices = sorted_indices(paths.iter().map(|path| path.leaf_index), 1 << depth)?;
+        let siblings = sibling_slots(&indices, depth)
+            .into_iter()
+            .map(|(height, leaf)| sibling_at(paths[leaf], height, depth))
+            .collect();
+        Ok(MultiPath { depth, siblings })
+    }
+
+    /// Checks that each `(index, leaf)` of `leaves` is in the tree with root
+    /// `root`, at its index. The indices may come in any order, but must be
+    /// the ones the proof was made for.
+    pub fn verify(&self, params: &MerkleParams, root: &Root, leaves: &[(usize, Vec<u8>)]) -> Result<bool, MerkleError> {
+        let leaf_count = 1u64.checked_shl(self.depth as u32).unwrap_or(u64::MAX);
+        sorted_indices(leaves.iter().map(|(index, _)| *index), leaf_count)?;
+        let mut level = leaves
+            .iter()
+            .map(|(index, leaf)| Ok((*index, params.hash_leaf(leaf)?)))
+            .collect::<Result<Vec<(usize, Root)>, MerkleError>>()?;
+        level.sort_unstable_by_key(|node| node.0);
+
+        let mut siblings = self.siblings.iter();
+        for _ in 0..self.depth {
+            let mut parents = Vec::with_capacity(level.len());
+            let mut i = 0;
+            while i < level.len() {
+                let (position, node) = level[i];
+                let (left, right) = match level.get(i + 1) {
+                    Some(&(next, right)) if position % 2 == 0 && next == position + 1 => {
+                        i += 2;
+                        (node, right)
+                    }
+                    _ => {
+                        i += 1;
+                        let sibling = match siblings.next() {
+                            Some(sibling) => *sibling,
+                            None => return Ok(false),
+                        };
+                        if position % 2 == 0 {
+                            (node, sibling)
+                        } else {
+                            (sibling, node)
+                        }
+                    }
+                };
+                parents.push((position / 2, params.hash_children(&left, &right)?));
+            }
+            level = parents;
+        }
+        Ok(siblings.next().is_none() && level.len() == 1 && level[0].1 == *root)
+    }
+
+    /// Checks that `leaves` are the leaves `start..start + leaves.len()` of
+    /// the tree with root `root`, with a proof from `generate_range_proof`.
+    pub fn verify_range(
+        &self,
+        params: &MerkleParams,
+        root: &Root,
+        start: usize,
+        leaves: &[Vec<u8>],
+    ) -> Result<bool, MerkleError> {
+        self.verify(params, root, &indexed_range(start, leaves))
+    }
+}
+
+// `leaves` paired with their indices from `start` on.
+fn indexed_range(start: usize, leaves: &[Vec<u8>]) -> Vec<(usize, Vec<u8>)> {
+    (start..).zip(leaves.iter().cloned()).collect()
+}
+
+// `indices`, sorted, each checked against `leaf_count` and for repeats.
+fn sorted_indices(indices: impl Iterator<Item = usize>, leaf_count: u64) -> Result<Vec<usize>, MerkleError> {
+    let mut sorted = indices
+        .map(|index| Ok(LeafIndex::checked_new(index as u64, leaf_count)?.as_usize()))
+        .collect::<Result<Vec<usize>, MerkleError>>()?;
+    sorted.sort_unstable();
+    if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(MerkleError::DuplicateLeafIndex(pair[0]));
+    }
+    Ok(sorted)
+}
+
+// The siblings a multiproof over the sorted `indices` holds, in order: for
+// each, its height above the leaves and the opened leaf whose path it is on.
+pub(crate) fn sibling_slots(indices: &[usize], depth: usize) -> Vec<(usize, usize)> {
+    let mut slots = Vec::new();
+    // each known node of the level, with the opened leaf it is above
+    let mut level: Vec<(usize, usize)> = indices.iter().copied().zip(0..).collect();
+    for height in 0..depth {
+        let mut i = 0;
+        while i < level.len() {
+            let (position, leaf) = level[i];
+            if position % 2 == 0 && level.get(i + 1).map(|next| next.0) == Some(position + 1) {
+                i += 2;
+            } else {
+                slots.push((height, leaf));
+                i += 1;
+            }
+        }
+        level = level.into_iter().map(|(position, leaf)| (position / 2, leaf)).collect();
+        level.dedup_by_key(|node| node.0);
+    }
+    slots
+}
+
+// The sibling at `height` above the leaves on `path`, in a tree `depth` levels deep.
+fn sibling_at(path: &SimplePath, height: usize, depth: usize) -> Root {
+    if height == 0 {
+        path.leaf_sibling_hash
+    } else {
+        // the auth path runs from the root down, without the leaf level
+        path.auth_path[depth - 1 - height]
+    }
+}
+
+impl CanonicalSerialize for MultiPath {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        (self.depth as u64).serialize(&mut writer)?;
+        self.siblings.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        0u64.serialized_size() + self.siblings.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for MultiPath {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(MultiPath {
+            depth: u64::deserialize(&mut reader)? as usize,
+            siblings: Vec::<Root>::deserialize(&mut reader)?,
+        })
+    }
+}
+
+/// The paths of several leaves of a tree, each distinct sibling stored once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressedMultiProof {
+    /// The number of levels above the leaves.
+    pub depth: usize,
+    /// The opened leaves, sorted.
+    pub indices: Vec<usize>,
+    /// The siblings of their paths, by level from the leaves up and left to
+    /// right within a level, as `compressed_slots` lists them.
+    pub nodes: Vec<Root>,
+}
+
+/// The compressed proof of the leaves at `indices` of `tree`.
+pub fn generate_compressed_multiproof(
+    tree: &SimpleMerkleTree,
+    indices: &[usize],
+) -> Result<CompressedMultiProof, MerkleError> {
+    let indices = sorted_indices(indices.iter().copied(), leaf_count(tree))?;
+    CompressedMultiProof::from_paths(&generate_proofs(tree, &indices)?)
+}
+
+// The nodes a compressed proof over the sorted `indices` holds, in order: the
+// height above the leaves and the position in that level of each.
+fn compressed_slots(indices: &[usize], depth: usize) -> Vec<(usize, usize)> {
+    let mut slots = Vec::new();
+    for height in 0..depth {
+        let mut level: Vec<usize> = indices.iter().map(|index| (index >> height) ^ 1).collect();
+        level.sort_unstable();
+        level.dedup();
+        slots.extend(level.into_iter().map(|position| (height, position)));
+    }
+    slots
+}
+
+impl CompressedMultiProof {
+    /// The proof combining `paths`, all of the same tree, in any order. Fails
+    /// with `InvalidProof` if two of them disagree on a sibling they share.
+    pub fn from_paths(paths: &[SimplePath]) -> Result<Self, MerkleError> {
+        let depth = paths.first().map_or(0, |path| path.auth_path.len() + 1);
+        assert!(
+            paths.iter().all(|path| path.auth_path.len() + 1 == depth),
+            "the paths of one tree"
+        );
+        let indices = sorted_indices(paths.iter().map(|path| path.leaf_index), 1 << depth)?;
+        let slots = compressed_slots(&indices, depth);
+        let mut nodes: Vec<Option<Root>> = vec![None; slots.len()];
+        for path in paths {
+            for height in 0..depth {
+                let slot = slots
+                    .binary_search(&(height, (path.leaf_index >> height) ^ 1))
+                    .expect("every sibling has a slot");
+                let sibling = sibling_at(path, height, depth);
+                match nodes[slot] {
+                    Some(node) if node != sibling => return Err(MerkleError::InvalidProof),
+                    _ => nodes[slot] = Some(sibling),
+                }
+            }
+        }
+        Ok(CompressedMultiProof {
+            depth,
+            indices,
+            nodes: nodes.into_iter().map(|node| node.expect("every slot is on a path")).collect(),
+        })
+    }
+
+    /// The path of each opened leaf, in the order of `indices`.
+    pub fn expand(&self) -> Vec<SimplePath> {
+        let slots = compressed_slots(&self.indices, self.depth);
+        let node = |height: usize, index: usize| {
+            let slot = slots.binary_search(&(height, (index >> height) ^ 1)).expect("every sibling has a slot");
+            self.nodes[slot]
+        };
+        self.indices
+            .iter()
+            .map(|&index| SimplePath {
+                leaf_sibling_hash: node(0, index),
+                // top-down, and neither the leaf level nor the root
+                auth_path: (1..self.depth).rev().map(|height| node(height, index)).collect(),
+                leaf_index: index,
+            })
+            .collect()
+    }
+
+    /// Checks that each `(index, leaf)` of `leaves` is in the tree with root
+    /// `root`, at its index, for exactly the indices the proof was made for.
+    pub fn verify(&self, params: &MerkleParams, root: &Root, leaves: &[(usize, Vec<u8>)]) -> Result<bool, MerkleError> {
+        let mut leaves: Vec<&(usize, Vec<u8>)> = leaves.iter().collect();
+        leaves.sort_unstable_by_key(|(index, _)| *index);
+        if !leaves.iter().map(|(index, _)| *index).eq(self.indices.iter().copied()) {
+            return Ok(false);
+        }
+        let items: Vec<(&[u8], SimplePath)> = leaves
+            .iter()
+            .map(|(_, leaf)| &leaf[..])
+            .zip(self.expand())
+            .collect();
+        params.verify_batch(root, &items)
+    }
+}
+
+impl CanonicalSerialize for CompressedMultiProof {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        (self.depth as u64).serialize(&mut writer)?;
+        self.indices
+            .iter()
+            .map(|index| *index as u64)
+            .collect::<Vec<u64>>()
+            .serialize(&mut writer)?;
+        self.nodes.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        // the depth, the number of indices and the indices
+        0u64.serialized_size() * (2 + self.indices.len()) + self.nodes.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for CompressedMultiProof {
+    // Checks that the indices are sorted, distinct and in range, and that
+    // there is a node for each of their siblings.
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let depth = u64::deserialize(&mut reader)?;
+        let indices = Vec::<u64>::deserialize(&mut reader)?;
+        let nodes = Vec::<Root>::deserialize(&mut reader)?;
+        let sorted = indices.windows(2).all(|pair| pair[0] < pair[1]);
+        if depth >= 64 || !sorted || indices.iter().any(|index| index >> depth != 0) {
+            return Err(SerializationError::InvalidData);
+        }
+        let depth = depth as usize;
+        let indices: Vec<usize> = indices.into_iter().map(|index| index as usize).collect();
+        if compressed_slots(&indices, depth).len() != nodes.len() {
+            return Err(SerializationError::InvalidData);
+        }
+        Ok(CompressedMultiProof { depth, indices, nodes })
+    }
+}
+
+/// The R1CS counterpart of `MultiPath`, for a fixed set of opened indices.
+#[cfg(feature = "constraints")]
+pub struct MultiPathVar {
+    pub depth: usize,
+    /// The opened indices, sorted.
+    pub indices: Vec<usize>,
+    pub siblings: Vec<RootVar>,
+}
+
+#[cfg(feature = "constraints")]
+impl MultiPathVar {
+    /// Allocates the siblings of `proof` as witnesses, as many as a multiproof
+    /// over the sorted `indices` of a tree `depth` levels deep holds. Without a
+    /// proof, for setup, the witnesses are missing but the shape is the same.
+    pub fn new_witness(
+        cs: ConstraintSystemRef<ConstraintF>,
+        indices: &[usize],
+        depth: usize,
+        proof: Option<&MultiPath>,
+    ) -> Result<Self, SynthesisError> {
+        let siblings = (0..sibling_slots(indices, depth).len())
+            .map(|i| {
+                RootVar::new_witness(ark_relations::ns!(cs, "sibling_var"), || {
+                    proof.and_then(|proof| proof.siblings.get(i)).ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(MultiPathVar {
+            depth,
+            indices: indices.to_vec(),
+            siblings,
+        })
+    }
+
+    /// Recomputes the root from `leaves`, one per index, in the order of `indices`.
+    pub fn calculate_root(
+        &self,
+        leaf_crh_params: &LeafHashParamsVar,
+        two_to_one_crh_params: &TwoToOneHashParamsVar,
+        leaves: &[Vec<UInt8<ConstraintF>>],
+    ) -> Result<RootVar, SynthesisError> {
+        let mut level = self
+            .indices
+            .iter()
+            .zip(leaves)
+            .map(|(index, leaf)| {
+                let digest = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(leaf_crh_params, leaf)?;
+                Ok((*index, digest))
+            })
+            .collect::<Result<Vec<(usize, RootVar)>, SynthesisError>>()?;
+
+        let mut siblings = self.siblings.iter();
+        for _ in 0..self.depth {
+            let mut parents = Vec::with_capacity(level.len());
+            let mut i = 0;
+            while i < level.len() {
+                let (position, node) = (level[i].0, &level[i].1);
+                let (left, right) = match level.get(i + 1) {
+                    Some((next, right)) if position % 2 == 0 && *next == position + 1 => {
+                        i += 2;
+                        (node, right)
+                    }
+                    _ => {
+                        i += 1;
+                        let sibling = siblings.next().ok_or(SynthesisError::AssignmentMissing)?;
+                        if position % 2 == 0 {
+                            (node, sibling)
+                        } else {
+                            (sibling, node)
+                        }
+                    }
+                };
+                let parent = <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::evaluate(
+                    two_to_one_crh_params,
+                    &left.to_bytes()?,
+                    &right.to_bytes()?,
+                )?;
+                parents.push((position / 2, parent));
+            }
+            level = parents;
+        }
+        level.pop().map(|(_, root)| root).ok_or(SynthesisError::AssignmentMissing)
+    }
+}
+
+/// Proves that every one of `leaves` is in the tree with root `root`, at its
+/// index, with one `MultiPath` for all of them. Unlike `MultiMembershipCircuit`,
+/// which checks one path per leaf, the nodes the leaves share are hashed once.
+#[cfg(feature = "constraints")]
+pub struct MultiMembershipProofCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub depth: usize,
+
+    // These are the public inputs to the circuit; the indices fix its shape.
+    pub root: Root,
+    pub leaves: Vec<(usize, Vec<u8>)>,
+
+    // This is the private witness to the circuit.
+    pub proof: Option<MultiPath>,
+}
+
+#[cfg(feature = "constraints")]
+impl MultiMembershipProofCircuit {
+    pub fn new(params: &MerkleParams, root: Root, leaves: Vec<(usize, Vec<u8>)>, proof: MultiPath) -> Self {
+        MultiMembershipProofCircuit {
+            params: params.clone(),
+            depth: proof.depth,
+            root,
+            leaves,
+            proof: Some(proof),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order: the root, then
+    /// the bytes of every leaf, by index.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        let mut inputs = vec![self.root];
+        for (_, leaf) in self.sorted_leaves() {
+            inputs.extend(<[u8] as ark_ff::ToConstraintField<ConstraintF>>::to_field_elements(&leaf[..]).unwrap());
+        }
+        inputs
+    }
+
+    fn sorted_leaves(&self) -> Vec<&(usize, Vec<u8>)> {
+        let mut leaves: Vec<_> = self.leaves.iter().collect();
+        leaves.sort_unstable_by_key(|(index, _)| *index);
+        leaves
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for MultiMembershipProofCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        let leaves = self.sorted_leaves();
+        let indices: Vec<usize> = leaves.iter().map(|(index, _)| *index).collect();
+        let leaf_count = 1u64.checked_shl(self.depth as u32).unwrap_or(u64::MAX);
+        if leaves.is_empty()
+            || sorted_indices(indices.iter().copied(), leaf_count).is_err()
+            || leaves.iter().any(|(_, leaf)| leaf.len() * 8 > <LeafHash as CRH>::INPUT_SIZE_BITS)
+        {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let leaf_vars = leaves
+            .iter()
+            .map(|(_, leaf)| UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_var"), leaf))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the one multiproof for all leaves as private witnesses:
+        let proof = MultiPathVar::new_witness(cs.clone(), &indices, self.depth, self.proof.as_ref())?;
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        proof
+            .calculate_root(&leaf_crh_params, &two_to_one_crh_params, &leaf_vars)?
+            .enforce_equal(&root)
+    }
+}
+
+/// Proves that `leaves` are the leaves `start..start + leaves.len()` of the
+/// tree with root `root`, with one range proof. `start` and the number of
+/// leaves fix the shape of the circuit; its public inputs are those of
+/// `MultiMembershipProofCircuit` over the same leaves.
+#[cfg(feature = "constraints")]
+pub struct RangeMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub depth: usize,
+    pub start: usize,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub leaves: Vec<Vec<u8>>,
+
+    // This is the private witness to the circuit.
+    pub proof: Option<MultiPath>,
+}
+
+#[cfg(feature = "constraints")]
+impl RangeMembershipCircuit {
+    pub fn new(params: &MerkleParams, root: Root, start: usize, leaves: Vec<Vec<u8>>, proof: MultiPath) -> Self {
+        RangeMembershipCircuit {
+            params: params.clone(),
+            depth: proof.depth,
+            start,
+            root,
+            leaves,
+            proof: Some(proof),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order: the root, then
+    /// the bytes of every leaf of the range.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        self.to_multiproof_circuit().public_inputs()
+    }
+
+    fn to_multiproof_circuit(&self) -> MultiMembershipProofCircuit {
+        MultiMembershipProofCircuit {
+            params: self.params.clone(),
+            depth: self.depth,
+            root: self.root,
+            leaves: indexed_range(self.start, &self.leaves),
+            proof: self.proof.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for RangeMembershipCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        self.to_multiproof_circuit().generate_constraints(cs)
+    }
+}
+
+/// A stretch of a path, `from` levels above the leaves up to `to`, as
+/// witnesses: the position bit and the sibling at every level of it,
+/// bottom-up, the position bits being those of the leaf index.
+#[cfg(feature = "constraints")]
+pub struct PathSegmentVar {
+    pub position: Vec<Boolean<ConstraintF>>,
+    pub siblings: Vec<RootVar>,
+}
+
+#[cfg(feature = "constraints")]
+impl PathSegmentVar {
+    /// Allocates the levels `from..to` of `path`, in a tree `depth` levels
+    /// deep. Without a path, for setup, the shape is the same.
+    pub fn new_witness(
+        cs: ConstraintSystemRef<ConstraintF>,
+        depth: usize,
+        from: usize,
+        to: usize,
+        path: Option<&SimplePath>,
+    ) -> Result<Self, SynthesisError> {
+        let position = (from..to)
+            .map(|height| {
+                Boolean::new_witness(ark_relations::ns!(cs, "position_var"), || {
+                    path.map(|path| (path.leaf_index >> height) & 1 == 1).ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let siblings = (from..to)
+            .map(|height| {
+                RootVar::new_witness(ark_relations::ns!(cs, "sibling_var"), || {
+                    path.map(|path| sibling_at(path, height, depth)).ok_or(SynthesisError::AssignmentMissing)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(PathSegmentVar { position, siblings })
+    }
+
+    /// Hashes `node`, at the bottom of the segment, up to its top.
+    pub fn climb(
+        &self,
+        two_to_one_crh_params: &TwoToOneHashParamsVar,
+        node: RootVar,
+    ) -> Result<RootVar, SynthesisError> {
+        let mut current = node;
+        for (is_right, sibling) in self.position.iter().zip(&self.siblings) {
+            let left = is_right.select(sibling, &current)?;
+            let right = is_right.select(&current, sibling)?;
+            current = <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::evaluate(
+                two_to_one_crh_params,
+                &left.to_bytes()?,
+                &right.to_bytes()?,
+            )?;
+        }
+        Ok(current)
+    }
+}
+
+/// The paths of leaves that all lie in one subtree `height` levels high:
+/// every leaf's segment inside the subtree, and the segment from the
+/// subtree's root up, which their paths share, once.
+#[cfg(feature = "constraints")]
+pub struct SharedPrefixPathVar {
+    pub lower: Vec<PathSegmentVar>,
+    pub upper: PathSegmentVar,
+}
+
+#[cfg(feature = "constraints")]
+impl SharedPrefixPathVar {
+    /// Allocates `count` paths of a tree `depth` levels deep, split `height`
+    /// levels above the leaves; the shared segment is taken from the first.
+    pub fn new_witness(
+        cs: ConstraintSystemRef<ConstraintF>,
+        count: usize,
+        depth: usize,
+        height: usize,
+        paths: Option<&[SimplePath]>,
+    ) -> Result<Self, SynthesisError> {
+        let lower = (0..count)
+            .map(|i| PathSegmentVar::new_witness(cs.clone(), depth, 0, height, paths.and_then(|paths| paths.get(i))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let upper = PathSegmentVar::new_witness(cs, depth, height, depth, paths.and_then(|paths| paths.first()))?;
+        Ok(SharedPrefixPathVar { lower, upper })
+    }
+
+    /// Returns a Boolean that is true iff every one of `leaves`, one per
+    /// lower segment, climbs to the same subtree root, and that root along
+    /// the shared segment to `root`.
+    pub fn verify_membership(
+        &self,
+        leaf_crh_params: &LeafHashParamsVar,
+        two_to_one_crh_params: &TwoToOneHashParamsVar,
+        root: &RootVar,
+        leaves: &[Vec<UInt8<ConstraintF>>],
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let subtree_roots = self
+            .lower
+            .iter()
+            .zip(leaves)
+            .map(|(segment, leaf)| {
+                let digest = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(leaf_crh_params, leaf)?;
+                segment.climb(two_to_one_crh_params, digest)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (first, rest) = subtree_roots.split_first().ok_or(SynthesisError::AssignmentMissing)?;
+        let mut checks = rest.iter().map(|node| node.is_eq(first)).collect::<Result<Vec<_>, _>>()?;
+        checks.push(self.upper.climb(two_to_one_crh_params, first.clone())?.is_eq(root)?);
+        Boolean::kary_and(&checks)
+    }
+}
+
+/// Proves that every one of `leaves` is in the tree with root `root`, all in
+/// one subtree `height` levels high, at positions kept private. The paths
+/// above the subtree are the same for all of them, and are hashed once:
+/// `leaves.len() * height + depth - height` hashes where
+/// `MultiMembershipCircuit` takes `leaves.len() * depth`.
+#[cfg(feature = "constraints")]
+pub struct SubtreeMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub depth: usize,
+    pub height: usize,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub leaves: Vec<Vec<u8>>,
+
+    // These are the private witnesses to the circuit, one path per leaf.
+    pub auth_paths: Option<Vec<SimplePath>>,
+}
+
+#[cfg(feature = "constraints")]
+impl SubtreeMembershipCircuit {
+    /// The circuit proving that `leaves` open `auth_paths`, in the same
+    /// order, against `root`, all in one subtree `height` levels high.
+    /// Panics without any path.
+    pub fn new(
+        params: &MerkleParams,
+        root: Root,
+        height: usize,
+        leaves: Vec<Vec<u8>>,
+        auth_paths: Vec<SimplePath>,
+    ) -> Self {
+        SubtreeMembershipCircuit {
+            params: params.clone(),
+            depth: auth_paths[0].auth_path.len() + 1,
+            height,
+            root,
+            leaves,
+            auth_paths: Some(auth_paths),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order: the root, then
+    /// the bytes of every leaf.
+    pub fn public_inputs(&self) -> Vec<ConstraintF> {
+        let mut inputs = vec![self.root];
+        for leaf in &self.leaves {
+            inputs.extend(<[u8] as ark_ff::ToConstraintField<ConstraintF>>::to_field_elements(&leaf[..]).unwrap());
+        }
+        inputs
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for SubtreeMembershipCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        if self.leaves.is_empty()
+            || self.height > self.depth
+            || self.leaves.iter().any(|leaf| leaf.len() * 8 > <LeafHash as CRH>::INPUT_SIZE_BITS)
+        {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let leaves = self
+            .leaves
+            .iter()
+            .map(|leaf| UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_var"), leaf))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the lower segment of every path and their shared upper one
+        // as private witnesses:
+        let paths = SharedPrefixPathVar::new_witness(
+            cs.clone(),
+            leaves.len(),
+            self.depth,
+            self.height,
+            self.auth_paths.as_deref(),
+        )?;
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        paths
+            .verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaves)?
+            .enforce_equal(&Boolean::TRUE)
+    }
+}
+
+#[test]
+fn test_multiproofs() {
+    use crate::serialization::path_serialized_size;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let opened = |indices: &[usize]| -> Vec<(usize, Vec<u8>)> {
+        indices.iter().map(|index| (*index, leaves[*index].clone())).collect()
+    };
+
+    // adjacent leaves need only the sibling of their common subtree
+    let proof = generate_multiproof(&tree, &[2, 0, 3, 1]).unwrap();
+    assert_eq!(proof.siblings.len(), 1);
+    assert!(proof.verify(&params, &tree.root(), &opened(&[3, 1, 0, 2])).unwrap());
+    let singles: usize = (0..4).map(|i| path_serialized_size(&tree.generate_proof(i).unwrap())).sum();
+    assert!(proof.serialized_size() < singles);
+    let mut bytes = Vec::new();
+    proof.serialize(&mut bytes).unwrap();
+    assert_eq!(MultiPath::deserialize(&bytes[..]).unwrap(), proof);
+
+    // leaves at both ends share only the root
+    let proof = generate_multiproof(&tree, &[7, 0]).unwrap();
+    assert_eq!(proof.siblings.len(), 4);
+    assert!(proof.verify(&params, &tree.root(), &opened(&[0, 7])).unwrap());
+    // but not other leaves at those positions, nor a subset of them
+    assert!(!proof.verify(&params, &tree.root(), &[(0, leaves[1].clone()), (7, leaves[7].clone())]).unwrap());
+    assert!(!proof.verify(&params, &tree.root(), &opened(&[0])).unwrap());
+
+    // one leaf is its plain path, bottom-up
+    let proof = generate_multiproof(&tree, &[5]).unwrap();
+    let path = tree.generate_proof(5).unwrap();
+    let mut siblings = vec![path.leaf_sibling_hash];
+    siblings.extend(path.auth_path.iter().rev());
+    assert_eq!(proof.siblings, siblings);
+    assert!(proof.verify(&params, &tree.root(), &opened(&[5])).unwrap());
+
+    // a tampered sibling
+    let mut proof = generate_multiproof(&tree, &[1, 2, 6]).unwrap();
+    assert!(proof.verify(&params, &tree.root(), &opened(&[1, 2, 6])).unwrap());
+    proof.siblings[1] = proof.siblings[0];
+    assert!(!proof.verify(&params, &tree.root(), &opened(&[1, 2, 6])).unwrap());
+
+    assert!(matches!(generate_multiproof(&tree, &[1, 4, 1]), Err(MerkleError::DuplicateLeafIndex(1))));
+    assert!(matches!(
+        generate_multiproof(&tree, &[8]),
+        Err(MerkleError::LeafIndexOutOfRange { index: 8, len: 8 })
+    ));
+}
+
+#[test]
+fn test_compressed_multiproofs() {
+    use crate::serialization::path_serialized_size;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..16).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let opened = |indices: &[usize]| -> Vec<(usize, Vec<u8>)> {
+        indices.iter().map(|index| (*index, leaves[*index].clone())).collect()
+    };
+
+    // four adjacent leaves: four siblings below their subtree, then one per level
+    let proof = generate_compressed_multiproof(&tree, &[6, 4, 5, 7]).unwrap();
+    assert_eq!(proof.indices, [4, 5, 6, 7]);
+    assert_eq!(proof.nodes.len(), 4 + 2 + 1 + 1);
+    for (path, index) in proof.expand().iter().zip(4..) {
+        let expected = tree.generate_proof(index).unwrap();
+        assert_eq!(path.leaf_index, index);
+        assert_eq!(path.leaf_sibling_hash, expected.leaf_sibling_hash);
+        assert_eq!(path.auth_path, expected.auth_path);
+    }
+    assert!(proof.verify(&params, &tree.root(), &opened(&[7, 5, 4, 6])).unwrap());
+    assert!(!proof.verify(&params, &tree.root(), &opened(&[4, 5, 6])).unwrap());
+    assert!(!proof.verify(&params, &tree.root(), &[(4, leaves[5].clone())]).unwrap());
+    let singles: usize = (4..8).map(|i| path_serialized_size(&tree.generate_proof(i).unwrap())).sum();
+    assert!(proof.serialized_size() < singles);
+
+    let mut bytes = Vec::new();
+    proof.serialize(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), proof.serialized_size());
+    assert_eq!(CompressedMultiProof::deserialize(&bytes[..]).unwrap(), proof);
+    // one node short
+    let mut short = proof.clone();
+    short.nodes.pop();
+    let mut bytes = Vec::new();
+    short.serialize(&mut bytes).unwrap();
+    assert!(CompressedMultiProof::deserialize(&bytes[..]).is_err());
+
+    // paths of two different trees
+    let other = params.build_tree(&leaves.iter().rev().collect::<Vec<_>>()).unwrap();
+    let paths = [tree.generate_proof(0).unwrap(), other.generate_proof(1).unwrap()];
+    assert!(matches!(CompressedMultiProof::from_paths(&paths), Err(MerkleError::InvalidProof)));
+    assert!(matches!(generate_compressed_multiproof(&tree, &[3, 3]), Err(MerkleError::DuplicateLeafIndex(3))));
+}
+
+// k = 8 adjacent leaves of a tree of height 16, checked with one path each and
+// with one multiproof: `cargo test --release multiproof_circuit -- --nocapture`
+#[cfg(feature = "constraints")]
+#[test]
+fn test_multiproof_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+    use crate::constraints::MultiMembershipCircuit;
+    use crate::sparse::SparseMerkleTree;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut tree = SparseMerkleTree::new(params.clone(), 15).unwrap();
+    let leaves: Vec<(usize, Vec<u8>)> = (0..8).map(|i| (i + 40, vec![i as u8; 8])).collect();
+    for (index, leaf) in &leaves {
+        tree.insert(*index as u64, leaf).unwrap();
+    }
+    let paths: Vec<SimplePath> = leaves.iter().map(|(index, _)| tree.generate_proof(*index as u64).unwrap()).collect();
+
+    let naive = assert_satisfied_with_report(MultiMembershipCircuit {
+        params: params.clone(),
+        root: tree.root(),
+        leaves: leaves.iter().map(|(_, leaf)| leaf.clone()).collect(),
+        auth_paths: Some(paths.clone()),
+    });
+    let proof = MultiPath::from_paths(&paths).unwrap();
+    let circuit = || MultiMembershipProofCircuit::new(&params, tree.root(), leaves.clone(), proof.clone());
+    let multi = assert_satisfied_with_report(circuit());
+    println!("8 leaves, height 16: {} constraints with 8 paths, {} with a multiproof", naive.constraints, multi.constraints);
+    assert!(multi.constraints < naive.constraints);
+
+    // a public leaf swapped for another
+    let mut swapped = circuit();
+    swapped.leaves[3].1 = vec![0xee; 8];
+    expect_unsatisfied(swapped, "membership");
+}
+
+#[test]
+fn test_range_proofs() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..16).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+
+    // at most one sibling per edge and level
+    let proof = generate_range_proof(&tree, 3, 11).unwrap();
+    assert!(proof.siblings.len() <= 2 * proof.depth);
+    assert!(proof.verify_range(&params, &tree.root(), 3, &leaves[3..11]).unwrap());
+    // not shifted by one, nor with a record missing at either end
+    assert!(!proof.verify_range(&params, &tree.root(), 3, &leaves[4..12]).unwrap());
+    assert!(!proof.verify_range(&params, &tree.root(), 4, &leaves[4..11]).unwrap());
+    assert!(!proof.verify_range(&params, &tree.root(), 3, &leaves[3..10]).unwrap());
+
+    // the whole tree needs no siblings
+    assert!(generate_range_proof(&tree, 0, 16).unwrap().siblings.is_empty());
+    assert!(matches!(
+        generate_range_proof(&tree, 12, 17),
+        Err(MerkleError::LeafIndexOutOfRange { index: 16, len: 16 })
+    ));
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_range_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let proof = generate_range_proof(&tree, 2, 6).unwrap();
+    let circuit =
+        |records: &[Vec<u8>]| RangeMembershipCircuit::new(&params, tree.root(), 2, records.to_vec(), proof.clone());
+
+    let metrics = assert_satisfied_with_report(circuit(&leaves[2..6]));
+    assert_eq!(circuit(&leaves[2..6]).public_inputs().len(), metrics.instance_variables - 1);
+    expect_unsatisfied(circuit(&leaves[1..5]), "membership");
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_subtree_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+    use crate::constraints::MultiMembershipCircuit;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..64).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let opened = |indices: &[usize]| -> (Vec<Vec<u8>>, Vec<SimplePath>) {
+        let paths = indices.iter().map(|index| tree.generate_proof(*index).unwrap()).collect();
+        (indices.iter().map(|index| leaves[*index].clone()).collect(), paths)
+    };
+
+    // four leaves of the subtree of height 2 over 20..24
+    let (records, paths) = opened(&[20, 21, 22, 23]);
+    let naive = assert_satisfied_with_report(MultiMembershipCircuit {
+        params: params.clone(),
+        root: tree.root(),
+        leaves: records.clone(),
+        auth_paths: Some(paths.clone()),
+    });
+    let shared = assert_satisfied_with_report(SubtreeMembershipCircuit::new(&params, tree.root(), 2, records, paths));
+    println!(
+        "4 leaves, height 7: {} constraints with 4 paths, {} sharing the path above their subtree",
+        naive.constraints, shared.constraints
+    );
+    assert!(shared.constraints * 3 < naive.constraints * 2);
+
+    // the positions are private: other leaves of another subtree, in any
+    // order, give the same circuit
+    let circuit = |indices: &[usize]| {
+        let (records, paths) = opened(indices);
+        SubtreeMembershipCircuit::new(&params, tree.root(), 2, records, paths)
+    };
+    assert_eq!(assert_satisfied_with_report(circuit(&[43, 40, 41])), assert_satisfied_with_report(circuit(&[1, 2, 0])));
+
+    // but all of them must be in the same subtree
+    expect_unsatisfied(circuit(&[40, 41, 44]), "membership");
+    let (mut records, paths) = opened(&[40, 41]);
+    records[1] = vec![0xee; 8];
+    expect_unsatisfied(SubtreeMembershipCircuit::new(&params, tree.root(), 2, records, paths), "membership");
+}
@@ -0,0 +1,316 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::{blank_path, LeafDigestVar, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+use crate::leaf::Leaf;
+#[cfg(feature = "constraints")]
+use crate::{Root, SimplePath};
+#[cfg(feature = "constraints")]
+use ark_crypto_primitives::crh::CRHGadget;
+use ark_crypto_primitives::crh::CRH;
+use ark_ff::to_bytes;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::fields::fp::FpVar;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::prelude::*;
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::collections::BTreeSet;
+use ark_std::vec::Vec;
+
+// Nullifiers let a verifier recognise a second use of the same secret without
+// learning anything else about it: `nullifier = LeafHash(domain || secret || scope)`.
+//
+// The leaf hash is reused as the PRF so no extra parameters need to be shipped.
+// The domain prefix keeps nullifiers apart from leaf digests computed with the
+// same generators, and the scope makes the same secret produce unrelated
+// nullifiers in unrelated contexts.
+//
+// `AnonymousClaimCircuit` is the Semaphore-style use of both: the tree holds
+// the members' identity commitments `LeafHash(commitment domain || secret)`,
+// and a claim proves that the prover knows the secret behind one of them
+// and shows its nullifier in the claim's scope, without saying which member
+// it is. A verifier keeps a `NullifierSet` per scope to allow one claim each.
+//
+// A claim can also be bound to a message, the action it is for: the recipient
+// of an airdrop, the option voted for. The message goes in as one more public
+// input, `intent_hash(message)`, so a proof verifies for that message only and
+// can't be replayed by whoever sees it for another recipient or another vote.
+// The nullifier stays that of the scope, so the same member still can't claim
+// twice with two messages. The circuit doesn't use the message hash in any
+// constraint, it only carries it along: a Groth16 proof is bound to every
+// public input, constrained or not, since arkworks' reduction to a QAP gives
+// each input a constraint of its own.
+
+/// A nullifier, as exposed to verifiers.
+pub type Nullifier = <LeafHash as CRH>::Output;
+
+const NULLIFIER_DOMAIN: &[u8] = b"merkle-tutorial/nullifier";
+const COMMITMENT_DOMAIN: &[u8] = b"merkle-tutorial/identity";
+const INTENT_DOMAIN: &[u8] = b"merkle-tutorial/intent";
+
+fn nullifier_input(secret: &[u8], scope: &[u8]) -> Vec<u8> {
+    let mut input = NULLIFIER_DOMAIN.to_vec();
+    input.extend_from_slice(secret);
+    input.extend_from_slice(scope);
+    input
+}
+
+/// Computes the nullifier of `secret` in `scope`.
+pub fn nullifier(
+    params: &MerkleParams,
+    secret: &[u8],
+    scope: &[u8],
+) -> Result<Nullifier, MerkleError> {
+    Ok(<LeafHash as CRH>::evaluate(&params.leaf_crh_params, &nullifier_input(secret, scope))?)
+}
+
+/// The in-circuit counterpart of `nullifier`.
+#[cfg(feature = "constraints")]
+pub fn nullifier_var(
+    leaf_crh_params: &LeafHashParamsVar,
+    secret: &[UInt8<ConstraintF>],
+    scope: &[UInt8<ConstraintF>],
+) -> Result<LeafDigestVar, SynthesisError> {
+    let mut input = UInt8::constant_vec(NULLIFIER_DOMAIN);
+    input.extend_from_slice(secret);
+    input.extend_from_slice(scope);
+    <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(leaf_crh_params, &input)
+}
+
+/// The leaf a member with `secret` is registered under: the bytes of
+/// `LeafHash(commitment domain || secret)`, which don't reveal the secret.
+pub fn identity_commitment(params: &MerkleParams, secret: &[u8]) -> Result<Leaf, MerkleError> {
+    let mut input = COMMITMENT_DOMAIN.to_vec();
+    input.extend_from_slice(secret);
+    Ok(Leaf::from(to_bytes![params.hash_leaf(&input)?]?))
+}
+
+/// The public input a claim bound to `message` exposes:
+/// `LeafHash(intent domain || message)`.
+pub fn intent_hash(params: &MerkleParams, message: &[u8]) -> Result<ConstraintF, MerkleError> {
+    let mut input = INTENT_DOMAIN.to_vec();
+    input.extend_from_slice(message);
+    params.hash_leaf(&input)
+}
+
+/// The nullifiers a verifier has already accepted.
+#[derive(Clone, Debug, Default)]
+pub struct NullifierSet {
+    seen: BTreeSet<Nullifier>,
+}
+
+impl NullifierSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, nullifier: &Nullifier) -> bool {
+        self.seen.contains(nullifier)
+    }
+
+    /// Records `nullifier`, returning false if it had been seen before.
+    pub fn insert(&mut self, nullifier: Nullifier) -> bool {
+        self.seen.insert(nullifier)
+    }
+}
+
+/// Proves "I know the secret of one of the identity commitments in the tree
+/// under `root`, and `nullifier` is its nullifier in `scope`", and if `message`
+/// is set, "for the message of that intent hash". The root, the scope, the
+/// nullifier and the message are public; the secret and the path are not. The
+/// lengths of the secret and the scope, and whether there is a message, are
+/// part of the shape of the circuit.
+#[cfg(feature = "constraints")]
+pub struct AnonymousClaimCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub scope: Vec<u8>,
+    pub nullifier: Nullifier,
+    pub message: Option<ConstraintF>,
+
+    // These are the private witnesses to the circuit.
+    pub secret: Vec<u8>,
+    pub auth_path: Option<SimplePath>,
+}
+
+#[cfg(feature = "constraints")]
+impl AnonymousClaimCircuit {
+    /// The claim of the member with `secret`, whose commitment `auth_path` opens.
+    pub fn new(
+        params: &MerkleParams,
+        root: Root,
+        scope: &[u8],
+        secret: &[u8],
+        auth_path: SimplePath,
+    ) -> Result<Self, MerkleError> {
+        Ok(AnonymousClaimCircuit {
+            params: params.clone(),
+            root,
+            scope: scope.to_vec(),
+            nullifier: nullifier(params, secret, scope)?,
+            message: None,
+            secret: secret.to_vec(),
+            auth_path: Some(auth_path),
+        })
+    }
+
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: &MerkleParams, tree_height: usize, secret_len: usize, scope_len: usize) -> Self {
+        AnonymousClaimCircuit {
+            params: params.clone(),
+            root: Default::default(),
+            scope: vec![0u8; scope_len],
+            nullifier: Default::default(),
+            message: None,
+            secret: vec![0u8; secret_len],
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The claim bound to the message of `intent_hash`; on a blank circuit,
+    /// the shape of bound claims, with any hash.
+    pub fn bound_to(self, intent_hash: ConstraintF) -> Self {
+        AnonymousClaimCircuit {
+            message: Some(intent_hash),
+            ..self
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order: the root, the
+    /// scope bytes, the nullifier, then the intent hash of a bound claim.
+    pub fn public_inputs(
+        root: &Root,
+        scope: &[u8],
+        nullifier: &Nullifier,
+        intent_hash: Option<&ConstraintF>,
+    ) -> Vec<ConstraintF> {
+        let mut inputs = vec![*root];
+        inputs.extend(<[u8] as ark_ff::ToConstraintField<ConstraintF>>::to_field_elements(scope).unwrap());
+        inputs.push(*nullifier);
+        inputs.extend(intent_hash.copied());
+        inputs
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for AnonymousClaimCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // Both hashes of the secret must fit the leaf hash gadget.
+        let longest = (NULLIFIER_DOMAIN.len() + self.scope.len()).max(COMMITMENT_DOMAIN.len()) + self.secret.len();
+        if longest * 8 > <LeafHash as CRH>::INPUT_SIZE_BITS {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let scope = UInt8::new_input_vec(ark_relations::ns!(cs, "scope_var"), &self.scope)?;
+        let expected = FpVar::new_input(ark_relations::ns!(cs, "nullifier_var"), || Ok(self.nullifier))?;
+        // only carried along, so that the proof commits to the message
+        let _message = self
+            .message
+            .map(|message| FpVar::new_input(ark_relations::ns!(cs, "message_var"), || Ok(message)))
+            .transpose()?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the secret and its path as private witnesses:
+        let secret = UInt8::new_witness_vec(ark_relations::ns!(cs, "secret_var"), &self.secret)?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // the commitment of the secret is a member
+        let mut input = UInt8::constant_vec(COMMITMENT_DOMAIN);
+        input.extend_from_slice(&secret);
+        let commitment = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(&leaf_crh_params, &input)?;
+        {
+            let _membership = ark_relations::ns!(cs, "membership");
+            let commitment = commitment.to_bytes()?;
+            path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &commitment.as_slice())?
+                .enforce_equal(&Boolean::TRUE)?;
+        }
+
+        // and the public nullifier is that of the same secret
+        let _nullifier = ark_relations::ns!(cs, "nullifier");
+        nullifier_var(&leaf_crh_params, &secret, &scope)?.enforce_equal(&expected)
+    }
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_anonymous_claims() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let secrets: Vec<[u8; 16]> = (0u8..4).map(|i| [i + 1; 16]).collect();
+    let commitments = secrets
+        .iter()
+        .map(|secret| identity_commitment(&params, secret))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let tree = params.build_tree(&commitments).unwrap();
+    let path = tree.generate_proof(2).unwrap();
+
+    let claim = || AnonymousClaimCircuit::new(&params, tree.root(), b"airdrop-1", &secrets[2], path.clone()).unwrap();
+    let metrics = assert_satisfied_with_report(claim());
+    let inputs = AnonymousClaimCircuit::public_inputs(&tree.root(), b"airdrop-1", &claim().nullifier, None);
+    assert_eq!(inputs.len(), metrics.instance_variables - 1);
+
+    // a second claim in the scope is caught, one in another scope isn't linked
+    let mut seen = NullifierSet::new();
+    assert!(seen.insert(claim().nullifier));
+    assert!(!seen.insert(nullifier(&params, &secrets[2], b"airdrop-1").unwrap()));
+    assert_ne!(nullifier(&params, &secrets[2], b"airdrop-2").unwrap(), claim().nullifier);
+
+    // another member's nullifier, or a secret that isn't a member's
+    let mut framed = claim();
+    framed.nullifier = nullifier(&params, &secrets[1], b"airdrop-1").unwrap();
+    expect_unsatisfied(framed, "nullifier");
+    let outsider = AnonymousClaimCircuit::new(&params, tree.root(), b"airdrop-1", &[9u8; 16], path).unwrap();
+    expect_unsatisfied(outsider, "membership");
+}
+
+#[cfg(feature = "snark")]
+#[test]
+fn test_claims_bound_to_a_message() {
+    use crate::snark;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let secrets: Vec<[u8; 16]> = (0u8..4).map(|i| [i + 1; 16]).collect();
+    let commitments = secrets
+        .iter()
+        .map(|secret| identity_commitment(&params, secret))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let tree = params.build_tree(&commitments).unwrap();
+    let root = tree.root();
+
+    let blank = AnonymousClaimCircuit::blank(&params, tree.height(), 16, 9).bound_to(Default::default());
+    let (pk, vk) = snark::setup(blank, &mut rng).unwrap();
+    let recipient = intent_hash(&params, b"0x00000000219ab540356cbb839cbe05303d7705fa").unwrap();
+    let claim = AnonymousClaimCircuit::new(&params, root, b"airdrop-1", &secrets[1], tree.generate_proof(1).unwrap())
+        .unwrap()
+        .bound_to(recipient);
+    let nullifier = claim.nullifier;
+    let proof = snark::prove(&pk, claim, &mut rng).unwrap();
+
+    let inputs = |message| AnonymousClaimCircuit::public_inputs(&root, b"airdrop-1", &nullifier, Some(message));
+    assert!(snark::verify(&vk, &inputs(&recipient), &proof).unwrap());
+    // the same proof, replayed for another recipient
+    let other = intent_hash(&params, b"0x000000000000000000000000000000000000dead").unwrap();
+    assert!(!snark::verify(&vk, &inputs(&other), &proof).unwrap());
+    // and the nullifier doesn't depend on the message
+    assert_eq!(nullifier, self::nullifier(&params, &secrets[1], b"airdrop-1").unwrap());
+}
@@ -0,0 +1,362 @@
+use crate::common::TreeParams;
+#[cfg(feature = "constraints")]
+use crate::constraints::MerkleCircuitConfig;
+#[cfg(feature = "constraints")]
+use crate::range::is_geq;
+use ark_crypto_primitives::crh::TwoToOneCRH;
+use ark_crypto_primitives::merkle_tree::Config;
+use ark_std::cmp::Ordering;
+use ark_std::marker::PhantomData;
+use ark_std::rand::Rng;
+#[cfg(any(test, feature = "constraints"))]
+use ark_std::vec::Vec;
+#[cfg(feature = "constraints")]
+use ark_crypto_primitives::crh::TwoToOneCRHGadget;
+#[cfg(feature = "constraints")]
+use ark_ff::PrimeField;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::{fields::fp::FpVar, prelude::*};
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{Namespace, SynthesisError};
+#[cfg(feature = "constraints")]
+use ark_std::borrow::Borrow;
+
+// Sorted-pair trees.
+//
+// `MerkleTree` hashes a node as hash(left, right), by position, and a path
+// carries the leaf index to say which side each sibling is on. Other
+// ecosystems, OpenZeppelin's `MerkleProof` first among them, hash the two
+// children smaller first, so a path needs no positions at all and a tree
+// built there has other roots than ours over the same leaves.
+//
+// `OrderedConfig<P>` is the tree configuration `P` with its node hash
+// wrapped in `OrderedTwoToOne`, whose parameters carry a `NodeOrdering`. The
+// ordering travels with the hash parameters, so `MerkleTree::new`,
+// `Path::verify` and the arkworks `PathVar` all follow it without knowing it
+// is there: under `Sorted`, the wrapped hash swaps its inputs when the left
+// one is the greater. Digests are compared as little-endian numbers, for
+// field digests their value, which is what a contract comparing them as
+// `uint256` sees once they are passed big-endian.
+//
+// In the circuit the ordering is a constant Boolean. Under `Positional` it is
+// false and the gadget is the wrapped one, at no cost; under `Sorted` each
+// node compares its children in 128-bit limbs and selects the pair to hash.
+// A sorted path verifies whatever leaf index it carries: the index says
+// nothing in such a tree.
+
+/// How a node orders its two children before hashing them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeOrdering {
+    /// hash(left, right), by position in the tree, like `MerkleTree`.
+    Positional,
+    /// hash(min, max), the smaller digest first, like OpenZeppelin.
+    Sorted,
+}
+
+impl Default for NodeOrdering {
+    fn default() -> Self {
+        NodeOrdering::Positional
+    }
+}
+
+// Whether `left` is the greater digest, both read as little-endian numbers.
+fn is_greater(left: &[u8], right: &[u8]) -> bool {
+    let ordering = left.len().cmp(&right.len()).then_with(|| left.iter().rev().cmp(right.iter().rev()));
+    ordering == Ordering::Greater
+}
+
+impl NodeOrdering {
+    /// The two children in the order they are hashed in.
+    pub fn order<'a>(&self, left: &'a [u8], right: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+        match self {
+            NodeOrdering::Sorted if is_greater(left, right) => (right, left),
+            _ => (left, right),
+        }
+    }
+}
+
+/// The parameters of the node hash `H` and the ordering it hashes in.
+#[derive(Clone, Default)]
+pub struct OrderedParams<P> {
+    pub params: P,
+    pub ordering: NodeOrdering,
+}
+
+/// The node hash `H`, with its children ordered by `OrderedParams::ordering`.
+pub struct OrderedTwoToOne<H>(PhantomData<H>);
+
+impl<H: TwoToOneCRH> TwoToOneCRH for OrderedTwoToOne<H> {
+    // children swap sides, so each must fit either
+    const LEFT_INPUT_SIZE_BITS: usize = if H::LEFT_INPUT_SIZE_BITS < H::RIGHT_INPUT_SIZE_BITS {
+        H::LEFT_INPUT_SIZE_BITS
+    } else {
+        H::RIGHT_INPUT_SIZE_BITS
+    };
+    const RIGHT_INPUT_SIZE_BITS: usize = Self::LEFT_INPUT_SIZE_BITS;
+    type Output = H::Output;
+    type Parameters = OrderedParams<H::Parameters>;
+
+    /// Samples the parameters of `H`, for a positional tree.
+    fn setup<R: Rng>(rng: &mut R) -> Result<Self::Parameters, ark_crypto_primitives::Error> {
+        Ok(OrderedParams {
+            params: H::setup(rng)?,
+            ordering: NodeOrdering::Positional,
+        })
+    }
+
+    fn evaluate(
+        parameters: &Self::Parameters,
+        left_input: &[u8],
+        right_input: &[u8],
+    ) -> Result<Self::Output, ark_crypto_primitives::Error> {
+        let (left, right) = parameters.ordering.order(left_input, right_input);
+        H::evaluate(&parameters.params, left, right)
+    }
+}
+
+/// The tree configuration `P` with its nodes hashed in a `NodeOrdering`.
+/// Leaves, digests and roots are those of `P`.
+#[derive(Clone)]
+pub struct OrderedConfig<P>(PhantomData<P>);
+
+impl<P: Config> Config for OrderedConfig<P> {
+    type LeafHash = P::LeafHash;
+    type TwoToOneHash = OrderedTwoToOne<P::TwoToOneHash>;
+}
+
+impl<P: Config> TreeParams<P> {
+    /// These parameters, for trees whose nodes are hashed in `ordering`.
+    /// Under `NodeOrdering::Positional` they build the same trees as `self`.
+    pub fn with_ordering(self, ordering: NodeOrdering) -> TreeParams<OrderedConfig<P>> {
+        TreeParams {
+            leaf_crh_params: self.leaf_crh_params,
+            two_to_one_crh_params: OrderedParams {
+                params: self.two_to_one_crh_params,
+                ordering,
+            },
+        }
+    }
+}
+
+impl<P: Config> TreeParams<OrderedConfig<P>> {
+    /// The ordering these parameters hash nodes in.
+    pub fn ordering(&self) -> NodeOrdering {
+        self.two_to_one_crh_params.ordering
+    }
+}
+
+/// The R1CS equivalent of `OrderedParams`: the parameters of the wrapped
+/// gadget, and whether the children are sorted, a constant when the
+/// parameters are.
+#[cfg(feature = "constraints")]
+#[derive(Clone)]
+pub struct OrderedParamsVar<V, F: PrimeField> {
+    pub params: V,
+    pub sorted: Boolean<F>,
+}
+
+#[cfg(feature = "constraints")]
+impl<P, V: AllocVar<P, F>, F: PrimeField> AllocVar<OrderedParams<P>, F> for OrderedParamsVar<V, F> {
+    fn new_variable<T: Borrow<OrderedParams<P>>>(
+        cs: impl Into<Namespace<F>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let ns = cs.into();
+        let cs = ns.cs();
+        f().and_then(|val| {
+            let val = val.borrow();
+            Ok(OrderedParamsVar {
+                params: V::new_variable(cs.clone(), || Ok(&val.params), mode)?,
+                sorted: Boolean::new_variable(cs.clone(), || Ok(val.ordering == NodeOrdering::Sorted), mode)?,
+            })
+        })
+    }
+}
+
+// The bytes of a digest as a little-endian number, in limbs of 16 bytes from
+// the least significant up.
+#[cfg(feature = "constraints")]
+fn limbs<F: PrimeField>(bytes: &[UInt8<F>]) -> Result<Vec<(FpVar<F>, usize)>, SynthesisError> {
+    bytes
+        .chunks(16)
+        .map(|chunk| {
+            let mut bits = Vec::new();
+            for byte in chunk {
+                bits.extend(byte.to_bits_le()?);
+            }
+            Ok((Boolean::le_bits_to_fp_var(&bits)?, bits.len()))
+        })
+        .collect()
+}
+
+// `is_greater` in the circuit, for inputs of the same length.
+#[cfg(feature = "constraints")]
+fn is_greater_var<F: PrimeField>(left: &[UInt8<F>], right: &[UInt8<F>]) -> Result<Boolean<F>, SynthesisError> {
+    let mut greater = Boolean::FALSE;
+    for ((l, num_bits), (r, _)) in limbs(left)?.iter().zip(limbs(right)?.iter()) {
+        // a greater higher limb decides, an equal one leaves it to the limbs below
+        let limb_greater = is_geq(r, l, *num_bits)?.not();
+        greater = limb_greater.or(&l.is_eq(r)?.and(&greater)?)?;
+    }
+    Ok(greater)
+}
+
+#[cfg(feature = "constraints")]
+fn select_bytes<F: PrimeField>(
+    cond: &Boolean<F>,
+    true_value: &[UInt8<F>],
+    false_value: &[UInt8<F>],
+) -> Result<Vec<UInt8<F>>, SynthesisError> {
+    true_value
+        .iter()
+        .zip(false_value)
+        .map(|(t, f)| {
+            let bits = t
+                .to_bits_le()?
+                .iter()
+                .zip(f.to_bits_le()?.iter())
+                .map(|(t, f)| cond.select(t, f))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(UInt8::from_bits_le(&bits))
+        })
+        .collect()
+}
+
+/// The gadget of `OrderedTwoToOne<H>` over `HG`, the gadget of `H`.
+#[cfg(feature = "constraints")]
+pub struct OrderedTwoToOneGadget<H, HG>(PhantomData<(H, HG)>);
+
+#[cfg(feature = "constraints")]
+impl<H, HG, F> TwoToOneCRHGadget<OrderedTwoToOne<H>, F> for OrderedTwoToOneGadget<H, HG>
+where
+    H: TwoToOneCRH,
+    HG: TwoToOneCRHGadget<H, F>,
+    F: PrimeField,
+{
+    type OutputVar = HG::OutputVar;
+    type ParametersVar = OrderedParamsVar<HG::ParametersVar, F>;
+
+    fn evaluate(
+        parameters: &Self::ParametersVar,
+        left_input: &[UInt8<F>],
+        right_input: &[UInt8<F>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        if let Boolean::Constant(false) = parameters.sorted {
+            return HG::evaluate(&parameters.params, left_input, right_input);
+        }
+        if left_input.len() != right_input.len() {
+            // the longer input is the greater, but inputs of different lengths
+            // can't be selected between: only a constant ordering can swap them
+            if parameters.sorted.is_constant() && left_input.len() > right_input.len() {
+                return HG::evaluate(&parameters.params, right_input, left_input);
+            }
+            return HG::evaluate(&parameters.params, left_input, right_input);
+        }
+        let swap = parameters.sorted.and(&is_greater_var(left_input, right_input)?)?;
+        let left = select_bytes(&swap, right_input, left_input)?;
+        let right = select_bytes(&swap, left_input, right_input)?;
+        HG::evaluate(&parameters.params, &left, &right)
+    }
+}
+
+/// The membership circuit of `C`, over `OrderedConfig<C::Config>`.
+#[cfg(feature = "constraints")]
+pub struct OrderedMerkleCircuit<C>(PhantomData<C>);
+
+#[cfg(feature = "constraints")]
+impl<C: MerkleCircuitConfig> MerkleCircuitConfig for OrderedMerkleCircuit<C> {
+    type Config = OrderedConfig<C::Config>;
+    type ConstraintF = C::ConstraintF;
+    type LeafHashGadget = C::LeafHashGadget;
+    type TwoToOneHashGadget = OrderedTwoToOneGadget<<C::Config as Config>::TwoToOneHash, C::TwoToOneHashGadget>;
+}
+
+// The root of a sorted-pair tree over `leaves`, computed level by level the
+// other ecosystems' way, without `MerkleTree`.
+#[cfg(test)]
+fn sorted_root<P: Config>(
+    params: &TreeParams<P>,
+    leaves: &[Vec<u8>],
+) -> <P::TwoToOneHash as TwoToOneCRH>::Output {
+    use ark_crypto_primitives::crh::CRH;
+    use ark_ff::to_bytes;
+
+    let mut level: Vec<Vec<u8>> = leaves
+        .iter()
+        .map(|leaf| to_bytes![<P::LeafHash as CRH>::evaluate(&params.leaf_crh_params, leaf).unwrap()].unwrap())
+        .collect();
+    loop {
+        let parents: Vec<_> = level
+            .chunks(2)
+            .map(|pair| {
+                let (left, right) = NodeOrdering::Sorted.order(&pair[0], &pair[1]);
+                <P::TwoToOneHash as TwoToOneCRH>::evaluate(&params.two_to_one_crh_params, left, right).unwrap()
+            })
+            .collect();
+        if parents.len() == 1 {
+            return parents.into_iter().next().unwrap();
+        }
+        level = parents.iter().map(|parent| to_bytes![parent].unwrap()).collect();
+    }
+}
+
+#[test]
+fn test_sorted_trees_natively() {
+    use crate::mock::MockParams;
+
+    let params = MockParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 8]).collect();
+    let positional = params.clone().with_ordering(NodeOrdering::Positional);
+    let sorted = params.clone().with_ordering(NodeOrdering::Sorted);
+
+    // a positional tree is the tree of the wrapped configuration
+    let plain_root = params.build_tree(&leaves).unwrap().root();
+    assert_eq!(positional.build_tree(&leaves).unwrap().root(), plain_root);
+    // a sorted one has the root a sorted-pair implementation computes
+    let tree = sorted.build_tree(&leaves).unwrap();
+    assert_eq!(tree.root(), sorted_root(&params, &leaves));
+    assert_ne!(tree.root(), plain_root);
+
+    for (index, leaf) in leaves.iter().enumerate() {
+        let path = tree.generate_proof(index).unwrap();
+        assert!(sorted.verify_path(&path, &tree.root(), leaf).unwrap());
+        // the index says nothing about where the siblings go
+        let mut moved = path.clone();
+        moved.leaf_index ^= 0b101;
+        assert!(sorted.verify_path(&moved, &tree.root(), leaf).unwrap());
+        assert!(!sorted.verify_path(&path, &tree.root(), &leaves[index ^ 1]).unwrap());
+    }
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_sorted_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+    use crate::constraints::MerkleTreeVerification;
+    use crate::mock::{MockMerkleCircuit, MockMerkleConfig, MockParams};
+
+    let params = MockParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 8]).collect();
+    let circuit = |params: &TreeParams<OrderedConfig<MockMerkleConfig>>, index: usize, leaf: &[u8]| {
+        let tree = params.build_tree(&leaves).unwrap();
+        MerkleTreeVerification::<OrderedMerkleCircuit<MockMerkleCircuit>> {
+            leaf_crh_params: params.leaf_crh_params.clone(),
+            two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+            root: tree.root(),
+            leaf: leaf.to_vec(),
+            auth_path: Some(tree.generate_proof(index).unwrap()),
+        }
+    };
+
+    let sorted = params.clone().with_ordering(NodeOrdering::Sorted);
+    let positional = params.with_ordering(NodeOrdering::Positional);
+    for index in 0..leaves.len() {
+        assert_satisfied_with_report(circuit(&sorted, index, &leaves[index]));
+        expect_unsatisfied(circuit(&sorted, index, &leaves[index ^ 2]), "membership");
+    }
+    // sorting costs comparisons; positional ordering costs nothing over the wrapped circuit
+    let sorted_report = assert_satisfied_with_report(circuit(&sorted, 5, &leaves[5]));
+    let positional_report = assert_satisfied_with_report(circuit(&positional, 5, &leaves[5]));
+    assert!(sorted_report.constraints > positional_report.constraints);
+}
@@ -0,0 +1,283 @@
+use crate::common::*;
+use crate::constraints::{blank_path, IndexedPathVar, RootVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::byte_inputs;
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::rand::{CryptoRng, RngCore};
+
+// Membership in two trees at once.
+//
+// Rollup-style state is often split over several trees, e.g. an account tree
+// and a balance tree, and a statement about one record needs a leaf from
+// each: "this account, with this balance". `PairedMembershipCircuit` checks
+// both paths in one constraint system, against two public roots, with one
+// copy of the hash parameters as constants. The two trees may differ in
+// height and leaf length. With `Link::SameIndex` the circuit also enforces
+// that both leaves sit at the same index, which is how such trees usually
+// relate their entries; `Link::Independent` only checks the two memberships.
+
+/// How the leaves of the two trees relate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Link {
+    /// Any leaf of tree A with any leaf of tree B.
+    Independent,
+    /// Leaves at the same index in both trees.
+    SameIndex,
+}
+
+/// A leaf, its path and the root the path opens it against.
+#[derive(Clone)]
+pub struct Opening {
+    pub root: Root,
+    pub leaf: Vec<u8>,
+    pub path: SimplePath,
+}
+
+impl Opening {
+    /// The opening of `leaf` at `index` in `tree`.
+    pub fn new(tree: &SimpleMerkleTree, leaf: &[u8], index: usize) -> Result<Self, MerkleError> {
+        Ok(Opening {
+            root: tree.root(),
+            leaf: leaf.to_vec(),
+            path: generate_proof(tree, LeafIndex::checked_new(index as u64, leaf_count(tree))?)?,
+        })
+    }
+}
+
+/// Checks both openings and, for `Link::SameIndex`, that their indices match.
+pub fn verify_paired(params: &MerkleParams, link: Link, a: &Opening, b: &Opening) -> Result<bool, MerkleError> {
+    if link == Link::SameIndex && a.path.leaf_index != b.path.leaf_index {
+        return Ok(false);
+    }
+    Ok(params.verify_path(&a.path, &a.root, &a.leaf)? && params.verify_path(&b.path, &b.root, &b.leaf)?)
+}
+
+/// Proves that the public `leaf_a` is under `root_a` and the public `leaf_b`
+/// under `root_b`, related as `link` says, keeping both paths private.
+pub struct PairedMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+    pub link: Link,
+
+    // These are the public inputs to the circuit.
+    pub root_a: Root,
+    pub leaf_a: Vec<u8>,
+    pub root_b: Root,
+    pub leaf_b: Vec<u8>,
+
+    // These are the private witnesses to the circuit.
+    pub auth_path_a: Option<SimplePath>,
+    pub auth_path_b: Option<SimplePath>,
+}
+
+impl PairedMembershipCircuit {
+    pub fn new(params: &MerkleParams, link: Link, a: Opening, b: Opening) -> Self {
+        PairedMembershipCircuit {
+            params: params.clone(),
+            link,
+            root_a: a.root,
+            leaf_a: a.leaf,
+            root_b: b.root,
+            leaf_b: b.leaf,
+            auth_path_a: Some(a.path),
+            auth_path_b: Some(b.path),
+        }
+    }
+
+    /// A circuit of the right shape for key generation: tree A of
+    /// `tree_height_a` levels with leaves of `leaf_len_a` bytes, and tree B
+    /// likewise.
+    pub fn blank(
+        params: &MerkleParams,
+        link: Link,
+        tree_height_a: usize,
+        leaf_len_a: usize,
+        tree_height_b: usize,
+        leaf_len_b: usize,
+    ) -> Self {
+        PairedMembershipCircuit {
+            params: params.clone(),
+            link,
+            root_a: Default::default(),
+            leaf_a: vec![0u8; leaf_len_a],
+            root_b: Default::default(),
+            leaf_b: vec![0u8; leaf_len_b],
+            auth_path_a: Some(blank_path(tree_height_a)),
+            auth_path_b: Some(blank_path(tree_height_b)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order: tree A's root
+    /// and leaf, then tree B's.
+    pub fn public_inputs(root_a: &Root, leaf_a: &[u8], root_b: &Root, leaf_b: &[u8]) -> Vec<ConstraintF> {
+        let mut inputs = vec![*root_a];
+        inputs.extend(byte_inputs(leaf_a));
+        inputs.push(*root_b);
+        inputs.extend(byte_inputs(leaf_b));
+        inputs
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for PairedMembershipCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root_a = RootVar::new_input(ark_relations::ns!(cs, "root_a_var"), || Ok(&self.root_a))?;
+        let leaf_a = UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_a_var"), &self.leaf_a)?;
+        let root_b = RootVar::new_input(ark_relations::ns!(cs, "root_b_var"), || Ok(&self.root_b))?;
+        let leaf_b = UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_b_var"), &self.leaf_b)?;
+
+        // Then, we allocate the public parameters as constants, once for both trees:
+        let leaf_crh_params = LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the two paths as private witnesses:
+        let path_a = IndexedPathVar::new_witness(ark_relations::ns!(cs, "path_a_var"), || {
+            self.auth_path_a.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path_b = IndexedPathVar::new_witness(ark_relations::ns!(cs, "path_b_var"), || {
+            self.auth_path_b.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        {
+            let _membership_a = ark_relations::ns!(cs, "membership_a");
+            path_a
+                .verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root_a, &leaf_a)?
+                .enforce_equal(&Boolean::TRUE)?;
+        }
+        {
+            let _membership_b = ark_relations::ns!(cs, "membership_b");
+            path_b
+                .verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root_b, &leaf_b)?
+                .enforce_equal(&Boolean::TRUE)?;
+        }
+
+        if self.link == Link::SameIndex {
+            let _link = ark_relations::ns!(cs, "link");
+            path_a.index()?.enforce_equal(&path_b.index()?)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Generates the keys for paired proofs over trees of these shapes.
+pub fn setup_paired<R: RngCore + CryptoRng>(
+    params: &MerkleParams,
+    link: Link,
+    tree_height_a: usize,
+    leaf_len_a: usize,
+    tree_height_b: usize,
+    leaf_len_b: usize,
+    rng: &mut R,
+) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+    snark::setup(
+        PairedMembershipCircuit::blank(params, link, tree_height_a, leaf_len_a, tree_height_b, leaf_len_b),
+        rng,
+    )
+}
+
+/// Proves both openings in one Groth16 proof.
+pub fn prove_paired<R: RngCore + CryptoRng>(
+    pk: &ProvingKey,
+    params: &MerkleParams,
+    link: Link,
+    a: Opening,
+    b: Opening,
+    rng: &mut R,
+) -> Result<Proof, MerkleError> {
+    snark::prove(pk, PairedMembershipCircuit::new(params, link, a, b), rng)
+}
+
+/// Checks a paired proof of `leaf_a` under `root_a` and `leaf_b` under `root_b`.
+pub fn verify_paired_proof(
+    vk: &VerifyingKey,
+    root_a: &Root,
+    leaf_a: &[u8],
+    root_b: &Root,
+    leaf_b: &[u8],
+    proof: &Proof,
+) -> Result<bool, MerkleError> {
+    snark::verify(vk, &PairedMembershipCircuit::public_inputs(root_a, leaf_a, root_b, leaf_b), proof)
+}
+
+#[cfg(test)]
+fn test_trees(params: &MerkleParams) -> (Vec<Vec<u8>>, SimpleMerkleTree, Vec<Vec<u8>>, SimpleMerkleTree) {
+    // four accounts of 32 bytes, and their balances, 8 bytes each, in a tree
+    // with room for eight
+    let accounts: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 32]).collect();
+    let balances: Vec<Vec<u8>> = (0u64..8).map(|i| (i * 100).to_le_bytes().to_vec()).collect();
+    let account_tree = params.build_tree(&accounts).unwrap();
+    let balance_tree = params.build_tree(&balances).unwrap();
+    (accounts, account_tree, balances, balance_tree)
+}
+
+#[test]
+fn test_verify_paired() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let (accounts, account_tree, balances, balance_tree) = test_trees(&params);
+    let account = Opening::new(&account_tree, &accounts[2], 2).unwrap();
+    let balance = Opening::new(&balance_tree, &balances[2], 2).unwrap();
+    let other_balance = Opening::new(&balance_tree, &balances[3], 3).unwrap();
+
+    assert!(verify_paired(&params, Link::SameIndex, &account, &balance).unwrap());
+    assert!(!verify_paired(&params, Link::SameIndex, &account, &other_balance).unwrap());
+    assert!(verify_paired(&params, Link::Independent, &account, &other_balance).unwrap());
+
+    let mut forged = balance.clone();
+    forged.leaf = balances[3].clone();
+    assert!(!verify_paired(&params, Link::Independent, &account, &forged).unwrap());
+    assert!(matches!(
+        Opening::new(&account_tree, &accounts[0], 4),
+        Err(MerkleError::LeafIndexOutOfRange { .. })
+    ));
+}
+
+#[test]
+fn test_paired_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied, synthesize_with_report};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let (accounts, account_tree, balances, balance_tree) = test_trees(&params);
+    let account = Opening::new(&account_tree, &accounts[2], 2).unwrap();
+    let balance = Opening::new(&balance_tree, &balances[2], 2).unwrap();
+    let other_balance = Opening::new(&balance_tree, &balances[3], 3).unwrap();
+    let circuit = |link, b: &Opening| PairedMembershipCircuit::new(&params, link, account.clone(), b.clone());
+
+    let metrics = assert_satisfied_with_report(circuit(Link::SameIndex, &balance));
+    let blank = PairedMembershipCircuit::blank(&params, Link::SameIndex, account_tree.height(), 32, balance_tree.height(), 8);
+    assert_eq!(metrics, synthesize_with_report(blank).unwrap().metrics);
+    let inputs = PairedMembershipCircuit::public_inputs(&account.root, &account.leaf, &balance.root, &balance.leaf);
+    assert_eq!(inputs.len() + 1, metrics.instance_variables);
+
+    // the balance of another account
+    expect_unsatisfied(circuit(Link::SameIndex, &other_balance), "link");
+    assert_satisfied_with_report(circuit(Link::Independent, &other_balance));
+
+    // a leaf that isn't in tree B
+    let mut forged = balance.clone();
+    forged.leaf = 7u64.to_le_bytes().to_vec();
+    expect_unsatisfied(circuit(Link::SameIndex, &forged), "membership_b");
+}
+
+#[test]
+fn test_paired_proof() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (accounts, account_tree, balances, balance_tree) = test_trees(&params);
+    let account = Opening::new(&account_tree, &accounts[1], 1).unwrap();
+    let balance = Opening::new(&balance_tree, &balances[1], 1).unwrap();
+
+    let (pk, vk) = setup_paired(&params, Link::SameIndex, account_tree.height(), 32, balance_tree.height(), 8, &mut rng).unwrap();
+    let proof = prove_paired(&pk, &params, Link::SameIndex, account.clone(), balance.clone(), &mut rng).unwrap();
+    assert!(verify_paired_proof(&vk, &account.root, &account.leaf, &balance.root, &balance.leaf, &proof).unwrap());
+    assert!(!verify_paired_proof(&vk, &account.root, &account.leaf, &balance.root, &balances[2], &proof).unwrap());
+    assert!(!verify_paired_proof(&vk, &balance.root, &account.leaf, &account.root, &balance.leaf, &proof).unwrap());
+}
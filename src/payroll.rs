@@ -0,0 +1,424 @@
+use crate::common::*;
+use crate::constraints::{blank_path, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::PublicInputsBuilder;
+use crate::range::{enforce_bit_length, enforce_geq};
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::rand::{CryptoRng, RngCore};
+
+// Salary bracket proofs on top of a payroll tree.
+//
+// HR periodically republishes a tree whose leaves are
+// `employee_id_hash || salary` (32 bytes of keccak256 followed by the yearly
+// salary as 8 little-endian bytes), stamped with an epoch. An employee can
+// show a bank that their salary lies within a public bracket `[min, max]`,
+// both bounds inclusive, without disclosing the figure itself. The bank only
+// accepts proofs against the root of the latest epoch it knows about.
+
+/// Size of a payroll leaf in bytes.
+pub const PAYROLL_LEAF_LEN: usize = 40;
+
+// Salaries are u64, so every comparison is 64 bits wide.
+const SALARY_BITS: usize = 64;
+
+/// Hashes an employee identifier into the 32 bytes stored in the leaf.
+pub fn employee_id_hash(employee_id: &[u8]) -> [u8; 32] {
+    keccak256(employee_id)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayrollRecord {
+    pub employee_id_hash: [u8; 32],
+    pub salary: u64,
+}
+
+impl PayrollRecord {
+    pub fn new(employee_id: &[u8], salary: u64) -> Self {
+        PayrollRecord {
+            employee_id_hash: employee_id_hash(employee_id),
+            salary,
+        }
+    }
+
+    /// The leaf bytes this record is committed as.
+    pub fn to_leaf_bytes(&self) -> Vec<u8> {
+        let mut leaf = self.employee_id_hash.to_vec();
+        leaf.extend_from_slice(&self.salary.to_le_bytes());
+        leaf
+    }
+}
+
+/// The root HR published at some epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublishedRoot {
+    pub epoch: u64,
+    pub root: Root,
+}
+
+/// What an employee gets from HR after each republication.
+#[derive(Clone)]
+pub struct PayslipWitness {
+    pub published: PublishedRoot,
+    pub record: PayrollRecord,
+    pub path: SimplePath,
+}
+
+/// The employer side: keeps the payroll and republishes its tree.
+pub struct Employer {
+    params: MerkleParams,
+    options: TreeOptions,
+    records: Vec<PayrollRecord>,
+    tree: SimpleMerkleTree,
+    epoch: u64,
+}
+
+impl Employer {
+    /// Creates an employer whose trees hold up to `capacity` employees. The
+    /// capacity fixes the circuit shape, so it must stay the same for the
+    /// lifetime of the keys.
+    pub fn new(params: MerkleParams, capacity: usize) -> Result<Self, MerkleError> {
+        let options = TreeOptions::for_capacity(capacity).padding(PaddingStrategy::Zeros(PAYROLL_LEAF_LEN));
+        let tree = Self::build_tree(&params, &options, &[])?;
+        Ok(Employer {
+            params,
+            options,
+            records: Vec::new(),
+            tree,
+            epoch: 0,
+        })
+    }
+
+    fn build_tree(
+        params: &MerkleParams,
+        options: &TreeOptions,
+        records: &[PayrollRecord],
+    ) -> Result<SimpleMerkleTree, MerkleError> {
+        Ok(params.build_tree_with_options(records.iter().map(PayrollRecord::to_leaf_bytes), options)?.tree)
+    }
+
+    /// Number of levels of the payroll trees, leaves and root included.
+    pub fn tree_height(&self) -> usize {
+        self.options.height
+    }
+
+    pub fn published(&self) -> PublishedRoot {
+        PublishedRoot {
+            epoch: self.epoch,
+            root: self.tree.root(),
+        }
+    }
+
+    /// Sets the salary of `employee_id`, adding the employee if needed. The
+    /// change becomes visible with the next `republish`.
+    pub fn set_salary(&mut self, employee_id: &[u8], salary: u64) -> Result<(), MerkleError> {
+        let record = PayrollRecord::new(employee_id, salary);
+        match self
+            .records
+            .iter_mut()
+            .find(|r| r.employee_id_hash == record.employee_id_hash)
+        {
+            Some(existing) => existing.salary = salary,
+            None if self.records.len() == self.options.capacity() => {
+                return Err(MerkleError::CapacityExceeded {
+                    len: self.records.len() + 1,
+                    capacity: self.options.capacity(),
+                })
+            }
+            None => self.records.push(record),
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the tree from the current payroll and starts a new epoch.
+    pub fn republish(&mut self) -> Result<PublishedRoot, MerkleError> {
+        self.tree = Self::build_tree(&self.params, &self.options, &self.records)?;
+        self.epoch += 1;
+        Ok(self.published())
+    }
+
+    /// The witness `employee_id` needs to prove against the current epoch.
+    pub fn payslip(&self, employee_id: &[u8]) -> Result<PayslipWitness, MerkleError> {
+        let id_hash = employee_id_hash(employee_id);
+        let index = self
+            .records
+            .iter()
+            .position(|r| r.employee_id_hash == id_hash)
+            .ok_or(MerkleError::LeafNotFound)?;
+        let path = generate_proof(&self.tree, LeafIndex::checked_new(index as u64, leaf_count(&self.tree))?)?;
+        // records edited since the last republication aren't in the tree yet
+        if !path.verify(
+            &self.params.leaf_crh_params,
+            &self.params.two_to_one_crh_params,
+            &self.tree.root(),
+            &self.records[index].to_leaf_bytes(),
+        )? {
+            return Err(MerkleError::LeafNotFound);
+        }
+        Ok(PayslipWitness {
+            published: self.published(),
+            record: self.records[index].clone(),
+            path,
+        })
+    }
+
+    pub fn setup<R: RngCore + CryptoRng>(
+        &self,
+        rng: &mut R,
+    ) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+        snark::setup(SalaryBracketCircuit::blank(self.params.clone(), self.tree_height()), rng)
+    }
+}
+
+/// Proves that the employee with a public `employee_id_hash` is in the tree
+/// under `root` with a private salary in `[min, max]`.
+pub struct SalaryBracketCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub employee_id_hash: [u8; 32],
+    pub min: u64,
+    pub max: u64,
+
+    // These are the private witnesses to the circuit.
+    pub salary: Option<u64>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl SalaryBracketCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, tree_height: usize) -> Self {
+        SalaryBracketCircuit {
+            params,
+            root: Default::default(),
+            employee_id_hash: [0u8; 32],
+            min: 0,
+            max: 0,
+            salary: Some(0),
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(root: &Root, employee_id_hash: &[u8; 32], min: u64, max: u64) -> Vec<ConstraintF> {
+        PublicInputsBuilder::bracket()
+            .root(root)
+            .leaf_bytes(employee_id_hash)
+            .scalar(min)
+            .scalar(max)
+            .finish()
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for SalaryBracketCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let employee_id_hash =
+            UInt8::new_input_vec(ark_relations::ns!(cs, "employee_id_var"), &self.employee_id_hash)?;
+        let min = FpVar::new_input(ark_relations::ns!(cs, "min_var"), || Ok(ConstraintF::from(self.min)))?;
+        let max = FpVar::new_input(ark_relations::ns!(cs, "max_var"), || Ok(ConstraintF::from(self.max)))?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the private salary and path:
+        let salary = UInt64::new_witness(ark_relations::ns!(cs, "salary_var"), || {
+            self.salary.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let salary_bits = salary.to_bits_le();
+        {
+            let _membership = ark_relations::ns!(cs, "membership");
+            let mut leaf_bytes = employee_id_hash;
+            leaf_bytes.extend(salary_bits.chunks(8).map(UInt8::from_bits_le));
+            let is_member = path.verify_membership(
+                &leaf_crh_params,
+                &two_to_one_crh_params,
+                &root,
+                &leaf_bytes.as_slice(),
+            )?;
+            is_member.enforce_equal(&Boolean::TRUE)?;
+        }
+
+        // min <= salary <= max; the salary is range checked by its UInt64
+        // decomposition, the bounds here
+        let _bracket = ark_relations::ns!(cs, "bracket");
+        let salary = Boolean::le_bits_to_fp_var(&salary_bits)?;
+        enforce_bit_length(&min, SALARY_BITS)?;
+        enforce_bit_length(&max, SALARY_BITS)?;
+        enforce_geq(&salary, &min, SALARY_BITS)?;
+        enforce_geq(&max, &salary, SALARY_BITS)?;
+
+        Ok(())
+    }
+}
+
+/// What an employee hands to the bank.
+#[derive(Clone)]
+pub struct ProofPackage {
+    pub published: PublishedRoot,
+    pub employee_id_hash: [u8; 32],
+    pub min: u64,
+    pub max: u64,
+    pub proof: Proof,
+}
+
+impl PayslipWitness {
+    pub fn bracket_circuit(&self, params: &MerkleParams, min: u64, max: u64) -> SalaryBracketCircuit {
+        SalaryBracketCircuit {
+            params: params.clone(),
+            root: self.published.root,
+            employee_id_hash: self.record.employee_id_hash,
+            min,
+            max,
+            salary: Some(self.record.salary),
+            auth_path: Some(self.path.clone()),
+        }
+    }
+
+    /// Proves that the salary lies within `[min, max]`.
+    pub fn prove_bracket<R: RngCore + CryptoRng>(
+        &self,
+        params: &MerkleParams,
+        pk: &ProvingKey,
+        min: u64,
+        max: u64,
+        rng: &mut R,
+    ) -> Result<ProofPackage, MerkleError> {
+        if !(min..=max).contains(&self.record.salary) {
+            return Err(MerkleError::Synthesis(SynthesisError::Unsatisfiable));
+        }
+        Ok(ProofPackage {
+            published: self.published,
+            employee_id_hash: self.record.employee_id_hash,
+            min,
+            max,
+            proof: snark::prove(pk, self.bracket_circuit(params, min, max), rng)?,
+        })
+    }
+}
+
+/// The bank side: tracks the employer's latest root and checks packages.
+pub struct Bank {
+    vk: VerifyingKey,
+    latest: PublishedRoot,
+}
+
+impl Bank {
+    pub fn new(vk: VerifyingKey, latest: PublishedRoot) -> Self {
+        Bank { vk, latest }
+    }
+
+    /// Moves to a newer publication; older ones are ignored.
+    pub fn update_root(&mut self, published: PublishedRoot) {
+        if published.epoch > self.latest.epoch {
+            self.latest = published;
+        }
+    }
+
+    /// Checks that `package` shows `employee_id` earning within `[min, max]`.
+    pub fn verify(
+        &self,
+        package: &ProofPackage,
+        employee_id: &[u8],
+        min: u64,
+        max: u64,
+    ) -> Result<bool, MerkleError> {
+        if package.published != self.latest
+            || package.employee_id_hash != employee_id_hash(employee_id)
+            || package.min != min
+            || package.max != max
+        {
+            return Ok(false);
+        }
+        let inputs =
+            SalaryBracketCircuit::public_inputs(&self.latest.root, &package.employee_id_hash, min, max);
+        snark::verify(&self.vk, &inputs, &package.proof)
+    }
+}
+
+#[cfg(test)]
+fn test_employer() -> Employer {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut employer = Employer::new(params, 4).unwrap();
+    employer.set_salary(b"alice", 50_000).unwrap();
+    employer.set_salary(b"bob", 100_000).unwrap();
+    employer.set_salary(b"carol", 75_000).unwrap();
+    employer.republish().unwrap();
+    employer
+}
+
+#[test]
+fn test_payroll_bracket_boundaries() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let mut rng = ark_std::test_rng();
+    let employer = test_employer();
+    let params = &employer.params;
+    let (pk, vk) = employer.setup(&mut rng).unwrap();
+    let bank = Bank::new(vk, employer.published());
+
+    // both bounds are inclusive
+    let alice = employer.payslip(b"alice").unwrap();
+    let bob = employer.payslip(b"bob").unwrap();
+    for (name, witness) in [(&b"alice"[..], &alice), (&b"bob"[..], &bob)].iter() {
+        let package = witness.prove_bracket(params, &pk, 50_000, 100_000, &mut rng).unwrap();
+        assert!(bank.verify(&package, name, 50_000, 100_000).unwrap());
+        assert!(!bank.verify(&package, name, 50_001, 100_000).unwrap());
+        assert_satisfied_with_report(witness.bracket_circuit(params, 50_000, 100_000));
+    }
+
+    // one past either end is out of the bracket
+    expect_unsatisfied(alice.bracket_circuit(params, 50_001, 100_000), "bracket");
+    expect_unsatisfied(bob.bracket_circuit(params, 50_000, 99_999), "bracket");
+    assert!(alice.prove_bracket(params, &pk, 50_001, 100_000, &mut rng).is_err());
+
+    // a proof for alice doesn't vouch for carol
+    let package = alice.prove_bracket(params, &pk, 50_000, 100_000, &mut rng).unwrap();
+    assert!(!bank.verify(&package, b"carol", 50_000, 100_000).unwrap());
+}
+
+#[test]
+fn test_payroll_stale_epoch_rejected() {
+    use crate::circuit_report::expect_unsatisfied;
+
+    let mut rng = ark_std::test_rng();
+    let mut employer = test_employer();
+    let (pk, vk) = employer.setup(&mut rng).unwrap();
+    let mut bank = Bank::new(vk, employer.published());
+
+    let stale = employer
+        .payslip(b"alice")
+        .unwrap()
+        .prove_bracket(&employer.params, &pk, 50_000, 100_000, &mut rng)
+        .unwrap();
+    assert!(bank.verify(&stale, b"alice", 50_000, 100_000).unwrap());
+
+    // alice got fired; the old proof must not outlive the republication
+    employer.set_salary(b"alice", 0).unwrap();
+    assert!(employer.payslip(b"alice").is_err());
+    bank.update_root(employer.republish().unwrap());
+    assert!(!bank.verify(&stale, b"alice", 50_000, 100_000).unwrap());
+
+    let fresh = employer.payslip(b"alice").unwrap();
+    assert_eq!(fresh.published.epoch, stale.published.epoch + 1);
+    expect_unsatisfied(fresh.bracket_circuit(&employer.params, 50_000, 100_000), "bracket");
+}
@@ -0,0 +1,30 @@
+//! The common working set, for both native and circuit usage:
+//!
+//! ```ignore
+//! use merkle_tree_tutorial::prelude::*;
+//! ```
+
+pub use crate::backend::{EagerTree, MerkleBackend};
+pub use crate::common::{ConstraintF, LeafHash, MerkleParams, TwoToOneHash};
+#[cfg(feature = "constraints")]
+pub use crate::constraints::{
+    compute_root_var, ByteLeaf, FieldLeaf, FixedLenMTreeVerification, IndexedPathVar, JubjubMerkleCircuit, LeafInput,
+    LeafUpdateCircuit, MTreeCircuit, MTreePreimageVerification, MTreeVerification, MaxDepthCircuit, MerkleCircuitConfig,
+    MerkleTreeVar, MerkleTreeVerification, MultiMembershipCircuit, PaddedLeaf, PositionalMembershipCircuit,
+    PreimageMembershipCircuit, RootVar, SimplePathVar, TreeBackend, WitnessAssignment,
+};
+pub use crate::error::MerkleError;
+pub use crate::index::LeafIndex;
+pub use crate::kit::{MembershipProof, MerkleKit};
+pub use crate::leaf::Leaf;
+pub use crate::leaf_encoding::ToLeafBytes;
+pub use crate::root::MerkleRoot;
+pub use crate::serialization::{PathSiblings, PathSiblingsExt, SerializableParams, SerializablePath};
+#[cfg(feature = "constraints")]
+pub use crate::stats::CircuitStats;
+pub use crate::stats::{TreeStats, TreeStatsExt};
+pub use crate::{MerkleConfig, Root, SimpleMerkleTree, SimplePath};
+
+pub use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+#[cfg(feature = "constraints")]
+pub use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
@@ -0,0 +1,302 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::serialization::{path_serialized_size, read_path, write_path};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_ff::to_bytes;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::io::{Read, Write};
+
+// Supply-chain provenance as a hash-linked chain of trees.
+//
+// Every custody step publishes a tree of item states, and every leaf of step
+// n starts with the root of step n - 1 (the zero root for the first step):
+//
+//   leaf = prev_root || item_id || custodian || status
+//
+// (32 + 32 + 32 + 4 bytes). Anyone holding only the latest root can then walk
+// an item's history backwards: the leaf proven under the latest root names the
+// root of the step before, under which the previous leaf is proven, and so on
+// down to the step the item first appeared in.
+
+/// Size of a provenance leaf in bytes.
+pub const PROVENANCE_LEAF_LEN: usize = 100;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ItemState {
+    pub item_id: [u8; 32],
+    pub custodian: [u8; 32],
+    pub status: u32,
+}
+
+impl ItemState {
+    pub fn new(item_id: &[u8], custodian: &[u8], status: u32) -> Self {
+        ItemState {
+            item_id: keccak256(item_id),
+            custodian: keccak256(custodian),
+            status,
+        }
+    }
+
+    /// The leaf bytes of this state in a step following `prev_root`.
+    pub fn to_leaf_bytes(&self, prev_root: &Root) -> Result<Vec<u8>, MerkleError> {
+        let mut leaf = to_bytes![prev_root]?;
+        leaf.extend_from_slice(&self.item_id);
+        leaf.extend_from_slice(&self.custodian);
+        leaf.extend_from_slice(&self.status.to_le_bytes());
+        Ok(leaf)
+    }
+}
+
+struct Step {
+    items: Vec<ItemState>,
+    tree: SimpleMerkleTree,
+}
+
+/// The chain of custody steps.
+pub struct ProvenanceChain {
+    params: MerkleParams,
+    steps: Vec<Step>,
+}
+
+/// One item's state in one step, and its path in that step's tree.
+#[derive(Clone)]
+pub struct ProvenanceLink {
+    pub prev_root: Root,
+    pub state: ItemState,
+    pub path: SimplePath,
+}
+
+/// An item's history, from the step it first appeared in to the latest one.
+#[derive(Clone)]
+pub struct ProvenanceProof {
+    pub links: Vec<ProvenanceLink>,
+}
+
+impl ProvenanceChain {
+    pub fn new(params: MerkleParams) -> Self {
+        ProvenanceChain {
+            params,
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// The root of the latest step, or the zero root before the first one.
+    pub fn head(&self) -> Root {
+        self.steps.last().map(|s| s.tree.root()).unwrap_or_default()
+    }
+
+    /// Publishes a custody step with the given item states and returns its root.
+    pub fn append_step(&mut self, items: Vec<ItemState>) -> Result<Root, MerkleError> {
+        let prev_root = self.head();
+        for (i, item) in items.iter().enumerate() {
+            if items[..i].iter().any(|other| other.item_id == item.item_id) {
+                return Err(MerkleError::DuplicateLeafIndex(i));
+            }
+        }
+        let leaves = items
+            .iter()
+            .map(|item| item.to_leaf_bytes(&prev_root))
+            .collect::<Result<Vec<_>, _>>()?;
+        let options = TreeOptions::for_capacity(leaves.len()).padding(PaddingStrategy::Zeros(PROVENANCE_LEAF_LEN));
+        let tree = self.params.build_tree_with_options(&leaves, &options)?.tree;
+        let root = tree.root();
+        self.steps.push(Step { items, tree });
+        Ok(root)
+    }
+
+    /// Proves the history of `item_id`. The item must be present in every
+    /// step from its first appearance up to the latest one.
+    pub fn prove(&self, item_id: &[u8]) -> Result<ProvenanceProof, MerkleError> {
+        let item_id = keccak256(item_id);
+        let origin = self
+            .steps
+            .iter()
+            .position(|s| s.items.iter().any(|i| i.item_id == item_id))
+            .ok_or(MerkleError::LeafNotFound)?;
+
+        let mut links = Vec::with_capacity(self.steps.len() - origin);
+        for (n, step) in self.steps.iter().enumerate().skip(origin) {
+            let index = step
+                .items
+                .iter()
+                .position(|i| i.item_id == item_id)
+                .ok_or(MerkleError::LeafNotFound)?;
+            let prev_root = if n == 0 {
+                Root::default()
+            } else {
+                self.steps[n - 1].tree.root()
+            };
+            links.push(ProvenanceLink {
+                prev_root,
+                state: step.items[index].clone(),
+                path: generate_proof(&step.tree, LeafIndex::checked_new(index as u64, leaf_count(&step.tree))?)?,
+            });
+        }
+        Ok(ProvenanceProof { links })
+    }
+}
+
+/// Walks `proof` back from `head`, checking that every link is in the tree
+/// named by the link after it and that all links are about the same item.
+pub fn verify_provenance(
+    params: &MerkleParams,
+    head: &Root,
+    proof: &ProvenanceProof,
+) -> Result<bool, MerkleError> {
+    let item_id = match proof.links.first() {
+        Some(link) => link.state.item_id,
+        None => return Ok(false),
+    };
+    let mut root = *head;
+    for link in proof.links.iter().rev() {
+        if link.state.item_id != item_id {
+            return Ok(false);
+        }
+        let leaf = link.state.to_leaf_bytes(&link.prev_root)?;
+        if !params.verify_path(&link.path, &root, &leaf)? {
+            return Ok(false);
+        }
+        root = link.prev_root;
+    }
+    Ok(true)
+}
+
+impl ProvenanceProof {
+    /// The item's state in the latest step.
+    pub fn current(&self) -> Option<&ItemState> {
+        self.links.last().map(|link| &link.state)
+    }
+}
+
+impl CanonicalSerialize for ProvenanceLink {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.prev_root.serialize(&mut writer)?;
+        writer.write_all(&self.state.item_id)?;
+        writer.write_all(&self.state.custodian)?;
+        self.state.status.serialize(&mut writer)?;
+        write_path(&self.path, &mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.prev_root.serialized_size() + 32 + 32 + self.state.status.serialized_size() + path_serialized_size(&self.path)
+    }
+}
+
+impl CanonicalDeserialize for ProvenanceLink {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let prev_root = Root::deserialize(&mut reader)?;
+        let mut item_id = [0u8; 32];
+        reader.read_exact(&mut item_id)?;
+        let mut custodian = [0u8; 32];
+        reader.read_exact(&mut custodian)?;
+        let status = u32::deserialize(&mut reader)?;
+        Ok(ProvenanceLink {
+            prev_root,
+            state: ItemState {
+                item_id,
+                custodian,
+                status,
+            },
+            path: read_path(&mut reader)?,
+        })
+    }
+}
+
+impl CanonicalSerialize for ProvenanceProof {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.links.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.links.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for ProvenanceProof {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        Ok(ProvenanceProof {
+            links: Vec::<ProvenanceLink>::deserialize(reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+fn test_chain() -> ProvenanceChain {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let mut chain = ProvenanceChain::new(params);
+    chain
+        .append_step(vec![
+            ItemState::new(b"crate-1", b"farm", 0),
+            ItemState::new(b"crate-2", b"farm", 0),
+        ])
+        .unwrap();
+    chain
+        .append_step(vec![
+            ItemState::new(b"crate-1", b"shipper", 1),
+            ItemState::new(b"crate-2", b"shipper", 1),
+        ])
+        .unwrap();
+    chain
+        .append_step(vec![ItemState::new(b"crate-1", b"retailer", 2)])
+        .unwrap();
+    chain
+}
+
+#[test]
+fn test_provenance_three_steps() {
+    let chain = test_chain();
+    let proof = chain.prove(b"crate-1").unwrap();
+    assert_eq!(proof.links.len(), 3);
+    assert_eq!(proof.current().unwrap().custodian, keccak256(b"retailer"));
+    assert!(verify_provenance(&chain.params, &chain.head(), &proof).unwrap());
+
+    // crate-2 dropped out of the chain after the second step
+    assert!(matches!(chain.prove(b"crate-2"), Err(MerkleError::LeafNotFound)));
+}
+
+#[test]
+fn test_provenance_broken_link() {
+    let chain = test_chain();
+    let proof = chain.prove(b"crate-1").unwrap();
+
+    // pointing the last step at a forged intermediate tree breaks the chain at the head
+    let mut tampered = proof.clone();
+    tampered.links[1].prev_root = tampered.links[0].prev_root;
+    tampered.links[2].prev_root = Root::default();
+    assert!(!verify_provenance(&chain.params, &chain.head(), &tampered).unwrap());
+
+    // so does rewriting history in an intermediate step
+    let mut tampered = proof.clone();
+    tampered.links[1].state.custodian = keccak256(b"smuggler");
+    assert!(!verify_provenance(&chain.params, &chain.head(), &tampered).unwrap());
+
+    // and splicing in another item's link
+    let mut tampered = proof;
+    tampered.links[0].state.item_id = keccak256(b"crate-2");
+    assert!(!verify_provenance(&chain.params, &chain.head(), &tampered).unwrap());
+}
+
+#[test]
+fn test_provenance_proof_serialization() {
+    let chain = test_chain();
+    let proof = chain.prove(b"crate-1").unwrap();
+
+    let mut bytes = Vec::new();
+    proof.serialize(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), proof.serialized_size());
+    let decoded = ProvenanceProof::deserialize(&bytes[..]).unwrap();
+    assert_eq!(decoded.links.len(), proof.links.len());
+    assert!(verify_provenance(&chain.params, &chain.head(), &decoded).unwrap());
+
+    assert!(ProvenanceProof::deserialize(&bytes[..bytes.len() - 1]).is_err());
+}
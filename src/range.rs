@@ -0,0 +1,56 @@
+use ark_ff::{Field, PrimeField};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+
+// In-circuit comparisons over field elements that are known to be small.
+//
+// Field elements have no ordering, so "a >= b" is expressed as "a - b fits in
+// `num_bits` bits": if both sides are below 2^num_bits and a < b, the
+// difference wraps around to something close to the modulus, which does not.
+
+/// Enforces that `value` fits in `num_bits` bits.
+pub fn enforce_bit_length<F: PrimeField>(
+    value: &FpVar<F>,
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    assert!(num_bits < F::size_in_bits() - 1, "comparison width too large for the field");
+    let bits = value.to_bits_le()?;
+    for bit in &bits[num_bits..] {
+        bit.enforce_equal(&Boolean::FALSE)?;
+    }
+    Ok(())
+}
+
+/// Enforces `lhs >= rhs`. The caller must make sure both sides fit in `num_bits` bits.
+pub fn enforce_geq<F: PrimeField>(
+    lhs: &FpVar<F>,
+    rhs: &FpVar<F>,
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    enforce_bit_length(&(lhs - rhs), num_bits)
+}
+
+/// Enforces `lhs > rhs`. The caller must make sure both sides fit in `num_bits` bits.
+pub fn enforce_gt<F: PrimeField>(
+    lhs: &FpVar<F>,
+    rhs: &FpVar<F>,
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    enforce_bit_length(&(lhs - rhs - FpVar::one()), num_bits)
+}
+
+/// Whether `lhs >= rhs`, for circuits that branch on the comparison rather
+/// than enforce it. The caller must make sure both sides fit in `num_bits` bits.
+pub fn is_geq<F: PrimeField>(
+    lhs: &FpVar<F>,
+    rhs: &FpVar<F>,
+    num_bits: usize,
+) -> Result<Boolean<F>, SynthesisError> {
+    assert!(num_bits + 1 < F::size_in_bits() - 1, "comparison width too large for the field");
+    // lhs - rhs + 2^num_bits lies in [1, 2^(num_bits + 1)), with its top bit
+    // set exactly when lhs >= rhs
+    let offset = FpVar::constant(F::from(2u64).pow([num_bits as u64]));
+    let bits = (lhs - rhs + offset).to_bits_le()?;
+    Ok(bits[num_bits].clone())
+}
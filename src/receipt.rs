@@ -0,0 +1,202 @@
+use crate::bundle::{fingerprint, Fingerprint};
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::leaf::Leaf;
+use crate::root::MerkleRoot;
+#[cfg(feature = "serde")]
+use crate::serialization::{deserialize_path, serialize_path};
+use crate::walkthrough::path_siblings;
+use crate::{SimpleMerkleTree, SimplePath};
+#[cfg(feature = "serde")]
+use ark_std::convert::TryFrom;
+use ark_std::fmt;
+#[cfg(feature = "serde")]
+use ark_std::string::String;
+use ark_std::vec::Vec;
+
+// A receipt of inclusion, for the person whose leaf it is.
+//
+// A service that took a leaf into its tree hands its owner the root, the leaf,
+// its index and path, the fingerprint of the parameters the tree was hashed
+// with (`bundle::fingerprint`) and when it issued the receipt. That is all it
+// takes to check the inclusion later, against the parameters alone, and to
+// hold the service to it: a receipt that verifies shows the leaf was in the
+// tree of that root.
+//
+// `Display` renders a receipt for people: the leaf, the root, the parameters
+// and every sibling of the path from the leaf up, with the side it is on.
+// With `serde` it serializes as an object of hex strings and numbers, the path
+// in the encoding of `serialization::serialize_path`. The timestamp is
+// whatever the issuer says it is, in seconds since the Unix epoch: the receipt
+// doesn't prove when the leaf went in, only that it did.
+
+/// A checkable record that `leaf` is at `index` in the tree of `root`.
+#[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "ReceiptJson", try_from = "ReceiptJson")
+)]
+pub struct InclusionReceipt {
+    pub root: MerkleRoot,
+    pub leaf: Vec<u8>,
+    pub index: LeafIndex,
+    pub path: SimplePath,
+    pub params_fingerprint: Fingerprint,
+    /// Seconds since the Unix epoch, as the issuer tells it.
+    pub issued_at: u64,
+}
+
+impl InclusionReceipt {
+    /// The receipt for the leaf at `index` of `tree`, built with `params`.
+    /// Fails with `InvalidProof` if `leaf` isn't the leaf there.
+    pub fn issue(
+        params: &MerkleParams,
+        tree: &SimpleMerkleTree,
+        index: usize,
+        leaf: impl Into<Leaf>,
+        issued_at: u64,
+    ) -> Result<Self, MerkleError> {
+        let index = LeafIndex::checked_new(index as u64, 1 << (tree.height() - 1))?;
+        let receipt = InclusionReceipt {
+            root: tree.root().into(),
+            leaf: leaf.into().into_vec(),
+            index,
+            path: generate_proof(tree, index)?,
+            params_fingerprint: fingerprint(params)?,
+            issued_at,
+        };
+        if !receipt.verify(params)? {
+            return Err(MerkleError::InvalidProof);
+        }
+        Ok(receipt)
+    }
+
+    /// Checks the receipt, failing with `ParamsMismatch` if it was issued for
+    /// a tree of other parameters than `params`.
+    pub fn verify(&self, params: &MerkleParams) -> Result<bool, MerkleError> {
+        if fingerprint(params)? != self.params_fingerprint {
+            return Err(MerkleError::ParamsMismatch);
+        }
+        Ok(self.path.leaf_index as u64 == self.index.get()
+            && params.verify_path(&self.path, &self.root.into(), &self.leaf)?)
+    }
+}
+
+impl fmt::Display for InclusionReceipt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let siblings = path_siblings(&self.path);
+        writeln!(f, "Inclusion receipt")?;
+        writeln!(
+            f,
+            "  leaf       #{} of {}: {} ({} bytes)",
+            self.index,
+            1u64 << siblings.len(),
+            to_hex(&self.leaf),
+            self.leaf.len()
+        )?;
+        writeln!(f, "  root       {}", self.root)?;
+        writeln!(f, "  params     {}", to_hex(&self.params_fingerprint))?;
+        writeln!(f, "  issued at  {} (Unix time)", self.issued_at)?;
+        write!(f, "  path       {} siblings, from the leaf up:", siblings.len())?;
+        for (level, sibling) in siblings.iter().enumerate() {
+            let side = if self.index.ancestor(level as u32).is_right() { "left" } else { "right" };
+            write!(f, "\n    level {:<3} {} on the {}", level, MerkleRoot::from(*sibling), side)?;
+        }
+        Ok(())
+    }
+}
+
+// The serde form of a receipt.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ReceiptJson {
+    root: String,
+    leaf: String,
+    index: u64,
+    path: String,
+    params_fingerprint: String,
+    issued_at: u64,
+}
+
+#[cfg(feature = "serde")]
+impl From<InclusionReceipt> for ReceiptJson {
+    fn from(receipt: InclusionReceipt) -> Self {
+        ReceiptJson {
+            root: receipt.root.to_hex(),
+            leaf: to_hex(&receipt.leaf),
+            index: receipt.index.get(),
+            path: to_hex(&serialize_path(&receipt.path)),
+            params_fingerprint: to_hex(&receipt.params_fingerprint),
+            issued_at: receipt.issued_at,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ReceiptJson> for InclusionReceipt {
+    type Error = MerkleError;
+
+    fn try_from(json: ReceiptJson) -> Result<Self, MerkleError> {
+        let path = deserialize_path(&from_hex(&json.path).ok_or(MerkleError::InvalidHex)?)?;
+        let params_fingerprint = from_hex(&json.params_fingerprint).ok_or(MerkleError::InvalidHex)?;
+        let params_fingerprint = Fingerprint::try_from(&params_fingerprint[..]).map_err(|_| MerkleError::InvalidHex)?;
+        Ok(InclusionReceipt {
+            root: MerkleRoot::from_hex(&json.root)?,
+            leaf: from_hex(&json.leaf).ok_or(MerkleError::InvalidHex)?,
+            index: LeafIndex::checked_new(json.index, 1 << (path.auth_path.len() + 1))?,
+            path,
+            params_fingerprint,
+            issued_at: json.issued_at,
+        })
+    }
+}
+
+#[test]
+fn test_inclusion_receipts() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+
+    let receipt = InclusionReceipt::issue(&params, &tree, 5, leaves[5].clone(), 1_700_000_000).unwrap();
+    assert!(receipt.verify(&params).unwrap());
+    let rendered = receipt.to_string();
+    assert!(rendered.contains("#5 of 8: 05050505 (4 bytes)"));
+    assert!(rendered.contains(&MerkleRoot::from(tree.root()).to_hex()));
+    // leaf 5 is a right child, then a left one, then a right one
+    assert_eq!(rendered.lines().filter(|line| line.ends_with("on the left")).count(), 2);
+
+    assert!(matches!(
+        InclusionReceipt::issue(&params, &tree, 5, leaves[4].clone(), 0),
+        Err(MerkleError::InvalidProof)
+    ));
+    let mut forged = receipt.clone();
+    forged.leaf = leaves[4].clone();
+    assert!(!forged.verify(&params).unwrap());
+    let mut moved = receipt.clone();
+    moved.index = LeafIndex::checked_new(4, 8).unwrap();
+    assert!(!moved.verify(&params).unwrap());
+    let other = MerkleParams::setup(&mut rng).unwrap();
+    assert!(matches!(receipt.verify(&other), Err(MerkleError::ParamsMismatch)));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_inclusion_receipt_json() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let receipt = InclusionReceipt::issue(&params, &tree, 2, leaves[2].clone(), 42).unwrap();
+
+    let json = serde_json::to_value(&receipt).unwrap();
+    assert_eq!(json["index"], 2);
+    assert_eq!(json["leaf"], "02020202");
+    let decoded: InclusionReceipt = serde_json::from_value(json).unwrap();
+    assert!(decoded.verify(&params).unwrap());
+    assert_eq!(decoded.to_string(), receipt.to_string());
+    let mut bad = serde_json::to_value(&receipt).unwrap();
+    bad["path"] = "zz".into();
+    assert!(serde_json::from_value::<InclusionReceipt>(bad).is_err());
+}
@@ -0,0 +1,269 @@
+use crate::common::TreeParams;
+use crate::constraints::{Bls12_377MerkleCircuit, Bls12_377MerkleConfig, MerkleTreeVerification};
+use crate::error::MerkleError;
+use ark_bls12_377::{constraints::PairingVar, Bls12_377};
+use ark_bw6_761::BW6_761;
+use ark_crypto_primitives::merkle_tree::Path;
+use ark_crypto_primitives::snark::constraints::{BooleanInputVar, SNARKGadget};
+use ark_ff::{to_bytes, BigInteger, PrimeField, ToConstraintField, Zero};
+use ark_groth16::constraints::{Groth16VerifierGadget, ProofVar, VerifyingKeyVar};
+use ark_groth16::Groth16;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_snark::SNARK;
+use ark_std::rand::{CryptoRng, RngCore};
+use ark_std::vec::Vec;
+
+// Membership proofs verified inside another proof.
+//
+// A circuit verifying a Groth16 proof computes its pairing in the circuit's
+// field, which must be the base field of the proof's curve. No curve has the
+// base field of BLS12-381, which `snark` proves over, as its scalar field, so
+// the inner proofs here are over BLS12-377, of the membership circuit of
+// `constraints::Bls12_377MerkleCircuit` (the crate's Pedersen trees over the
+// Edwards curve on BLS12-377), and the outer proof is over BW6-761, whose
+// scalar field is BLS12-377's base field.
+//
+// `AggregationCircuit` verifies any number of inner membership proofs, all
+// under one verifying key built into it as a constant. Its public inputs are
+// those of the inner proofs, each root and leaf element lifted as one input
+// into the larger outer field, so the outer proof is checked against the same
+// statements the inner ones were. A rollup proves each membership on its own
+// and hands the verifier one outer proof for all of them. The verifier gadget
+// takes each inner input as its bits: witnesses, bound to the public input by
+// recomposing them. Every inner proof costs a pairing check in the circuit,
+// which dwarfs everything else in it.
+
+/// The scalar field of BLS12-377, which the inner proofs' circuit lives in.
+pub type InnerF = ark_bls12_377::Fr;
+/// The base field of BLS12-377, and the scalar field of BW6-761, which the
+/// aggregation circuit lives in.
+pub type OuterF = ark_bls12_377::Fq;
+
+pub type InnerParams = TreeParams<Bls12_377MerkleConfig>;
+pub type InnerPath = Path<Bls12_377MerkleConfig>;
+/// The membership circuit the inner proofs are of.
+pub type InnerCircuit = MerkleTreeVerification<Bls12_377MerkleCircuit>;
+
+pub type InnerProvingKey = ark_groth16::ProvingKey<Bls12_377>;
+pub type InnerVerifyingKey = ark_groth16::VerifyingKey<Bls12_377>;
+pub type InnerProof = ark_groth16::Proof<Bls12_377>;
+
+pub type OuterProvingKey = ark_groth16::ProvingKey<BW6_761>;
+pub type OuterVerifyingKey = ark_groth16::VerifyingKey<BW6_761>;
+pub type OuterProof = ark_groth16::Proof<BW6_761>;
+
+type InnerVerifierGadget = Groth16VerifierGadget<Bls12_377, PairingVar>;
+
+/// The circuit proving that `leaf` opens `path` against `root`.
+pub fn inner_circuit(params: &InnerParams, root: InnerF, leaf: &[u8], path: InnerPath) -> InnerCircuit {
+    MerkleTreeVerification {
+        leaf_crh_params: params.leaf_crh_params.clone(),
+        two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+        root,
+        leaf: leaf.to_vec(),
+        auth_path: Some(path),
+    }
+}
+
+/// The public inputs of an inner proof: the root, then the leaf bytes packed
+/// like `UInt8::new_input_vec` packs them.
+pub fn inner_public_inputs(root: &InnerF, leaf: &[u8]) -> Vec<InnerF> {
+    let mut inputs = vec![*root];
+    inputs.extend(<[u8] as ToConstraintField<InnerF>>::to_field_elements(leaf).unwrap());
+    inputs
+}
+
+/// The keys for inner proofs in trees of `tree_height` levels (leaves and
+/// root included) over leaves of `leaf_len` bytes.
+pub fn setup_inner<R: RngCore + CryptoRng>(
+    params: &InnerParams,
+    tree_height: usize,
+    leaf_len: usize,
+    rng: &mut R,
+) -> Result<(InnerProvingKey, InnerVerifyingKey), MerkleError> {
+    let path = InnerPath {
+        leaf_sibling_hash: Default::default(),
+        auth_path: vec![Default::default(); tree_height - 2],
+        leaf_index: 0,
+    };
+    let circuit = inner_circuit(params, Default::default(), &vec![0u8; leaf_len], path);
+    Ok(Groth16::<Bls12_377>::circuit_specific_setup(circuit, rng)?)
+}
+
+/// Proves that `leaf` opens `path` against `root`, revealing the root and
+/// the leaf but not the path.
+pub fn prove_inner<R: RngCore + CryptoRng>(
+    pk: &InnerProvingKey,
+    params: &InnerParams,
+    root: InnerF,
+    leaf: &[u8],
+    path: InnerPath,
+    rng: &mut R,
+) -> Result<InnerProof, MerkleError> {
+    Ok(Groth16::<Bls12_377>::prove(pk, inner_circuit(params, root, leaf, path), rng)?)
+}
+
+/// Checks an inner proof that `leaf` is in the tree of `root`.
+pub fn verify_inner(
+    vk: &InnerVerifyingKey,
+    root: &InnerF,
+    leaf: &[u8],
+    proof: &InnerProof,
+) -> Result<bool, MerkleError> {
+    Ok(Groth16::<Bls12_377>::verify(vk, &inner_public_inputs(root, leaf), proof)?)
+}
+
+// An inner field element as the outer one of the same integer. The outer
+// field is the larger, so nothing wraps around.
+fn lift(x: &InnerF) -> OuterF {
+    OuterF::from_le_bytes_mod_order(&to_bytes![x].unwrap())
+}
+
+/// The public inputs of an aggregation circuit over `statements`, the
+/// public inputs of its inner proofs: every element lifted, in order.
+pub fn aggregate_public_inputs(statements: &[Vec<InnerF>]) -> Vec<OuterF> {
+    statements.iter().flatten().map(lift).collect()
+}
+
+/// Verifies inner membership proofs, all under `vk`, each against the inner
+/// public inputs of its statement.
+#[derive(Clone)]
+pub struct AggregationCircuit {
+    // This is a constant embedded into the circuit
+    pub vk: InnerVerifyingKey,
+
+    // These are the public inputs to the circuit
+    pub statements: Vec<Vec<InnerF>>,
+
+    // This is the private witness to the circuit
+    pub proofs: Vec<InnerProof>,
+}
+
+impl AggregationCircuit {
+    /// The circuit verifying each of `proofs` against the statement at the
+    /// same position; there must be as many of either.
+    pub fn new(vk: InnerVerifyingKey, statements: Vec<Vec<InnerF>>, proofs: Vec<InnerProof>) -> Self {
+        assert_eq!(statements.len(), proofs.len(), "one statement per proof");
+        AggregationCircuit { vk, statements, proofs }
+    }
+
+    /// A circuit of the right shape for key generation: `count` proofs under
+    /// `vk`, with dummy statements and proofs.
+    pub fn blank(vk: InnerVerifyingKey, count: usize) -> Self {
+        let inputs = vk.gamma_abc_g1.len() - 1;
+        Self::new(vk, vec![vec![InnerF::zero(); inputs]; count], vec![InnerProof::default(); count])
+    }
+
+    /// The public inputs to verify a proof of this circuit with.
+    pub fn public_inputs(&self) -> Vec<OuterF> {
+        aggregate_public_inputs(&self.statements)
+    }
+}
+
+impl ConstraintSynthesizer<OuterF> for AggregationCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<OuterF>) -> Result<(), SynthesisError> {
+        let vk = VerifyingKeyVar::<Bls12_377, PairingVar>::new_constant(cs.clone(), &self.vk)?;
+
+        for (statement, proof) in self.statements.iter().zip(&self.proofs) {
+            // First, the statement, as public inputs of this circuit
+            let inputs = statement
+                .iter()
+                .map(|x| FpVar::new_input(ark_relations::ns!(cs, "statement"), || Ok(lift(x))))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // Then the bits of each input for the verifier gadget, which must
+            // make up that input
+            let mut input_bits = Vec::with_capacity(inputs.len());
+            for (x, input) in statement.iter().zip(&inputs) {
+                let mut value = x.into_repr().to_bits_le();
+                value.truncate(InnerF::size_in_bits());
+                let bits = Vec::<Boolean<OuterF>>::new_witness(ark_relations::ns!(cs, "statement_bits"), || Ok(value))?;
+                Boolean::le_bits_to_fp_var(&bits)?.enforce_equal(input)?;
+                input_bits.push(bits);
+            }
+
+            // Finally, the proof as a private witness, which must verify
+            let proof = ProofVar::<Bls12_377, PairingVar>::new_witness(ark_relations::ns!(cs, "proof"), || Ok(proof))?;
+            let _verify = ark_relations::ns!(cs, "verify");
+            <InnerVerifierGadget as SNARKGadget<InnerF, OuterF, Groth16<Bls12_377>>>::verify(
+                &vk,
+                &BooleanInputVar::new(input_bits),
+                &proof,
+            )?
+            .enforce_equal(&Boolean::TRUE)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The keys for aggregating `count` inner proofs under `vk` at a time.
+pub fn setup_aggregation<R: RngCore + CryptoRng>(
+    vk: &InnerVerifyingKey,
+    count: usize,
+    rng: &mut R,
+) -> Result<(OuterProvingKey, OuterVerifyingKey), MerkleError> {
+    Ok(Groth16::<BW6_761>::circuit_specific_setup(AggregationCircuit::blank(vk.clone(), count), rng)?)
+}
+
+/// One proof that every proof of `proofs` verifies under `vk` against the
+/// statement at the same position.
+pub fn aggregate<R: RngCore + CryptoRng>(
+    pk: &OuterProvingKey,
+    vk: &InnerVerifyingKey,
+    statements: Vec<Vec<InnerF>>,
+    proofs: Vec<InnerProof>,
+    rng: &mut R,
+) -> Result<OuterProof, MerkleError> {
+    Ok(Groth16::<BW6_761>::prove(pk, AggregationCircuit::new(vk.clone(), statements, proofs), rng)?)
+}
+
+/// Checks an aggregate proof against the statements of the proofs it stands for.
+pub fn verify_aggregate(
+    vk: &OuterVerifyingKey,
+    statements: &[Vec<InnerF>],
+    proof: &OuterProof,
+) -> Result<bool, MerkleError> {
+    Ok(Groth16::<BW6_761>::verify(vk, &aggregate_public_inputs(statements), proof)?)
+}
+
+#[test]
+fn test_aggregation_circuit_verifies_inner_proofs() {
+    use ark_relations::r1cs::ConstraintSystem;
+
+    let mut rng = ark_std::test_rng();
+    let params = InnerParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = tree.root();
+    let (pk, vk) = setup_inner(&params, tree.height(), 8, &mut rng).unwrap();
+
+    let mut statements = Vec::new();
+    let mut proofs = Vec::new();
+    for index in [1usize, 2].iter().copied() {
+        let path = tree.generate_proof(index).unwrap();
+        let proof = prove_inner(&pk, &params, root, &leaves[index], path, &mut rng).unwrap();
+        assert!(verify_inner(&vk, &root, &leaves[index], &proof).unwrap());
+        statements.push(inner_public_inputs(&root, &leaves[index]));
+        proofs.push(proof);
+    }
+
+    // The outer Groth16 setup over BW6-761 takes long; satisfying the circuit
+    // is what an outer proof would show.
+    let satisfied = |statements: Vec<Vec<InnerF>>| {
+        let circuit = AggregationCircuit::new(vk.clone(), statements, proofs.clone());
+        let public_inputs = circuit.public_inputs();
+        let cs = ConstraintSystem::<OuterF>::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert_eq!(public_inputs.len(), cs.num_instance_variables() - 1);
+        cs.is_satisfied().unwrap()
+    };
+    assert!(satisfied(statements.clone()));
+    // each proof goes with its own leaf only
+    let mut swapped = statements.clone();
+    swapped.swap(0, 1);
+    assert!(!satisfied(swapped));
+    assert_eq!(AggregationCircuit::blank(vk, 2).statements, vec![vec![InnerF::zero(); statements[0].len()]; 2]);
+}
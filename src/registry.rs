@@ -0,0 +1,419 @@
+use crate::backend::{EagerTree, MerkleBackend};
+use crate::common::*;
+use crate::constraints::{blank_path, IndexedPathVar, RootVar};
+use crate::error::MerkleError;
+use crate::schnorr::{self, PublicKey, Signature, SignatureVar};
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimplePath};
+use ark_ed_on_bls12_381::constraints::EdwardsVar;
+use ark_ff::to_bytes;
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::rand::{CryptoRng, RngCore};
+
+// An NFT-like asset registry.
+//
+// Leaves are `asset_id || owner_pubkey || transfers` (32 bytes, the 64-byte
+// affine encoding of the owner's Jubjub key, and the number of times the
+// asset changed hands as a little-endian u64). A transfer is authorised by a
+// Schnorr signature of the current owner over `asset_id || new_owner ||
+// transfers`, and can be proven in zero knowledge: the `TransferCircuit` only
+// exposes the old and the new root, and enforces that they differ by exactly
+// such a signed ownership change, with the count one higher.
+//
+// The count is what makes a signature good for one transfer only. Without
+// it, once A hands an asset to B and B hands it back, the tree is as it was
+// and A's first signature moves the asset to B again; signing the old root
+// wouldn't help, since the root is back too. With it, the leaf the signature
+// was made for is gone for good.
+
+pub type AssetId = [u8; 32];
+
+/// Size of an asset leaf in bytes.
+pub const ASSET_LEAF_LEN: usize = 104;
+
+fn asset_leaf(asset_id: &AssetId, owner: &PublicKey, transfers: u64) -> Result<Vec<u8>, MerkleError> {
+    let mut leaf = asset_id.to_vec();
+    leaf.extend(to_bytes![owner]?);
+    leaf.extend_from_slice(&transfers.to_le_bytes());
+    Ok(leaf)
+}
+
+/// The message the current owner signs to hand `asset_id` over to
+/// `new_owner`, after it changed hands `transfers` times.
+pub fn transfer_message(asset_id: &AssetId, new_owner: &PublicKey, transfers: u64) -> Result<Vec<u8>, MerkleError> {
+    // the layout of a leaf, with the count before the transfer
+    asset_leaf(asset_id, new_owner, transfers)
+}
+
+/// The registry, over any tree backend; `Registry::new` uses an `EagerTree`.
+pub struct Registry<B = EagerTree> {
+    params: MerkleParams,
+    // each asset, its owner and how many times it changed hands
+    assets: Vec<(AssetId, PublicKey, u64)>,
+    tree: B,
+}
+
+impl Registry {
+    /// Creates an empty registry with room for `capacity` assets.
+    pub fn new(params: MerkleParams, capacity: usize) -> Result<Self, MerkleError> {
+        // an all-zero owner is not a curve point, so nobody can sign for padding leaves
+        let tree = EagerTree::with_capacity(params.clone(), capacity, vec![0u8; ASSET_LEAF_LEN])?;
+        Ok(Self::with_backend(params, tree))
+    }
+}
+
+impl<B: MerkleBackend> Registry<B> {
+    /// Creates an empty registry on `tree`, whose leaves must all be the
+    /// all-zero padding leaf of `ASSET_LEAF_LEN` bytes.
+    pub fn with_backend(params: MerkleParams, tree: B) -> Self {
+        Registry {
+            params,
+            assets: Vec::new(),
+            tree,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.tree.leaf_count() as usize
+    }
+
+    pub fn tree_height(&self) -> usize {
+        self.capacity().trailing_zeros() as usize + 1
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    pub fn owner(&self, asset_id: &AssetId) -> Option<&PublicKey> {
+        self.assets.iter().find(|(id, ..)| id == asset_id).map(|(_, owner, _)| owner)
+    }
+
+    /// How many times `asset_id` changed hands, which its next transfer
+    /// message must carry.
+    pub fn transfers(&self, asset_id: &AssetId) -> Option<u64> {
+        self.assets.iter().find(|(id, ..)| id == asset_id).map(|(.., transfers)| *transfers)
+    }
+
+    fn position(&self, asset_id: &AssetId) -> Result<usize, MerkleError> {
+        self.assets
+            .iter()
+            .position(|(id, ..)| id == asset_id)
+            .ok_or(MerkleError::LeafNotFound)
+    }
+
+    /// Registers a new asset owned by `owner`.
+    pub fn register(&mut self, asset_id: AssetId, owner: PublicKey) -> Result<Root, MerkleError> {
+        if self.owner(&asset_id).is_some() {
+            return Err(MerkleError::DuplicateLeafIndex(self.position(&asset_id)?));
+        }
+        if self.assets.len() == self.capacity() {
+            return Err(MerkleError::CapacityExceeded {
+                len: self.assets.len() + 1,
+                capacity: self.capacity(),
+            });
+        }
+        let root = self.tree.update(self.assets.len(), asset_leaf(&asset_id, &owner, 0)?.into())?;
+        self.assets.push((asset_id, owner, 0));
+        Ok(root)
+    }
+
+    /// Builds the circuit proving the transfer of `asset_id` to `new_owner`
+    /// against the current root, without applying it.
+    pub fn transfer_circuit(
+        &self,
+        asset_id: &AssetId,
+        new_owner: &PublicKey,
+        signature: &Signature,
+    ) -> Result<TransferCircuit, MerkleError>
+    where
+        B: Clone,
+    {
+        let index = self.position(asset_id)?;
+        let (_, old_owner, transfers) = self.assets[index];
+        let new_leaf = asset_leaf(asset_id, new_owner, transfers.wrapping_add(1))?;
+        let new_root = self.tree.clone().update(index, new_leaf.into())?;
+
+        Ok(TransferCircuit {
+            params: self.params.clone(),
+            old_root: self.root(),
+            new_root,
+            asset_id: Some(*asset_id),
+            old_owner: Some(old_owner),
+            new_owner: Some(*new_owner),
+            transfers: Some(transfers),
+            signature: Some(signature.clone()),
+            auth_path: Some(self.tree.prove(index)?),
+        })
+    }
+
+    /// Checks the owner's signature and moves `asset_id` to `new_owner`,
+    /// returning the new root.
+    pub fn apply_transfer(
+        &mut self,
+        asset_id: &AssetId,
+        new_owner: &PublicKey,
+        signature: &Signature,
+    ) -> Result<Root, MerkleError> {
+        let index = self.position(asset_id)?;
+        let (_, old_owner, transfers) = self.assets[index];
+        let message = transfer_message(asset_id, new_owner, transfers)?;
+        if !schnorr::verify(&self.params, &old_owner, &message, signature)? {
+            return Err(MerkleError::InvalidSignature);
+        }
+        let transfers = transfers.wrapping_add(1);
+        let root = self.tree.update(index, asset_leaf(asset_id, new_owner, transfers)?.into())?;
+        self.assets[index] = (*asset_id, *new_owner, transfers);
+        Ok(root)
+    }
+}
+
+/// Proves that `new_root` is `old_root` with one asset handed over by its owner.
+pub struct TransferCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub old_root: Root,
+    pub new_root: Root,
+
+    // These are the private witnesses to the circuit.
+    pub asset_id: Option<AssetId>,
+    pub old_owner: Option<PublicKey>,
+    pub new_owner: Option<PublicKey>,
+    pub transfers: Option<u64>,
+    pub signature: Option<Signature>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl TransferCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, tree_height: usize) -> Self {
+        TransferCircuit {
+            params,
+            old_root: Default::default(),
+            new_root: Default::default(),
+            asset_id: None,
+            old_owner: None,
+            new_owner: None,
+            transfers: None,
+            signature: None,
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(old_root: &Root, new_root: &Root) -> Vec<ConstraintF> {
+        vec![*old_root, *new_root]
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for TransferCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let old_root =
+            RootVar::new_input(ark_relations::ns!(cs, "old_root_var"), || Ok(&self.old_root))?;
+        let new_root =
+            RootVar::new_input(ark_relations::ns!(cs, "new_root_var"), || Ok(&self.new_root))?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the transfer itself as private witnesses:
+        let asset_id = UInt8::new_witness_vec(
+            ark_relations::ns!(cs, "asset_id_var"),
+            &self.asset_id.unwrap_or_default(),
+        )?;
+        let old_owner = EdwardsVar::new_witness(ark_relations::ns!(cs, "old_owner_var"), || {
+            self.old_owner.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let new_owner = EdwardsVar::new_witness(ark_relations::ns!(cs, "new_owner_var"), || {
+            self.new_owner.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let transfers = UInt64::new_witness(ark_relations::ns!(cs, "transfers_var"), || {
+            self.transfers.ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let signature = SignatureVar::new_witness(ark_relations::ns!(cs, "signature_var"), || {
+            self.signature.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = IndexedPathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // the same bytes as `u64::to_le_bytes`
+        let le_bytes = |count: &UInt64<ConstraintF>| -> Vec<UInt8<ConstraintF>> {
+            count.to_bits_le().chunks(8).map(UInt8::from_bits_le).collect()
+        };
+        let next = UInt64::addmany(&[transfers.clone(), UInt64::constant(1)])?;
+
+        let mut old_leaf = asset_id.clone();
+        old_leaf.extend(old_owner.to_bytes()?);
+        old_leaf.extend(le_bytes(&transfers));
+        let mut message = asset_id;
+        message.extend(new_owner.to_bytes()?);
+        let mut new_leaf = message.clone();
+        message.extend(le_bytes(&transfers));
+        new_leaf.extend(le_bytes(&next));
+
+        // the old leaf is in the old tree, and swapping it for the new one yields the new tree
+        path.enforce_update(
+            &leaf_crh_params,
+            &two_to_one_crh_params,
+            &old_root,
+            &new_root,
+            &old_leaf,
+            &new_leaf,
+        )?;
+
+        // the old owner signed `asset_id || new_owner || transfers`, for this transfer alone
+        let _signature = ark_relations::ns!(cs, "signature");
+        let is_authorised = schnorr::verify_var(&leaf_crh_params, &old_owner, &message, &signature)?;
+        is_authorised.enforce_equal(&Boolean::TRUE)?;
+
+        Ok(())
+    }
+}
+
+pub fn setup_transfer<R: RngCore + CryptoRng>(
+    params: &MerkleParams,
+    tree_height: usize,
+    rng: &mut R,
+) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+    snark::setup(TransferCircuit::blank(params.clone(), tree_height), rng)
+}
+
+pub fn prove_transfer<R: RngCore + CryptoRng>(
+    pk: &ProvingKey,
+    circuit: TransferCircuit,
+    rng: &mut R,
+) -> Result<Proof, MerkleError> {
+    snark::prove(pk, circuit, rng)
+}
+
+pub fn verify_transfer(
+    vk: &VerifyingKey,
+    old_root: &Root,
+    new_root: &Root,
+    proof: &Proof,
+) -> Result<bool, MerkleError> {
+    snark::verify(vk, &TransferCircuit::public_inputs(old_root, new_root), proof)
+}
+
+#[test]
+fn test_registry_transfer() {
+    use crate::circuit_report::assert_satisfied_with_report;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (alice_sk, alice) = schnorr::keygen(&mut rng);
+    let (bob_sk, bob) = schnorr::keygen(&mut rng);
+
+    let mut registry = Registry::new(params.clone(), 4).unwrap();
+    let asset = [7u8; 32];
+    let old_root = registry.register(asset, alice).unwrap();
+
+    let message = transfer_message(&asset, &bob, 0).unwrap();
+    let signature = schnorr::sign(&params, &alice_sk, &message).unwrap();
+    let circuit = registry.transfer_circuit(&asset, &bob, &signature).unwrap();
+
+    let (pk, vk) = setup_transfer(&params, registry.tree_height(), &mut rng).unwrap();
+    let proof = prove_transfer(&pk, circuit, &mut rng).unwrap();
+    let new_root = registry.apply_transfer(&asset, &bob, &signature).unwrap();
+    assert_eq!(registry.owner(&asset), Some(&bob));
+    assert!(verify_transfer(&vk, &old_root, &new_root, &proof).unwrap());
+    assert!(!verify_transfer(&vk, &new_root, &old_root, &proof).unwrap());
+
+    // bob can pass it on; replaying alice's signature can't, she no longer owns it
+    assert!(matches!(
+        registry.apply_transfer(&asset, &alice, &signature),
+        Err(MerkleError::InvalidSignature)
+    ));
+    let message = transfer_message(&asset, &alice, 1).unwrap();
+    let signature = schnorr::sign(&params, &bob_sk, &message).unwrap();
+    assert_satisfied_with_report(registry.transfer_circuit(&asset, &alice, &signature).unwrap());
+}
+
+#[test]
+fn test_registry_transfer_signed_by_wrong_key() {
+    use crate::circuit_report::expect_unsatisfied;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (_, alice) = schnorr::keygen(&mut rng);
+    let (mallory_sk, mallory) = schnorr::keygen(&mut rng);
+
+    let mut registry = Registry::new(params.clone(), 4).unwrap();
+    let asset = [7u8; 32];
+    registry.register(asset, alice).unwrap();
+
+    let message = transfer_message(&asset, &mallory, 0).unwrap();
+    let signature = schnorr::sign(&params, &mallory_sk, &message).unwrap();
+    expect_unsatisfied(registry.transfer_circuit(&asset, &mallory, &signature).unwrap(), "signature");
+    assert!(matches!(
+        registry.apply_transfer(&asset, &mallory, &signature),
+        Err(MerkleError::InvalidSignature)
+    ));
+    assert_eq!(registry.owner(&asset), Some(&alice));
+}
+
+#[test]
+fn test_registry_transfer_of_nonexistent_asset() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (alice_sk, alice) = schnorr::keygen(&mut rng);
+    let (_, bob) = schnorr::keygen(&mut rng);
+
+    let mut registry = Registry::new(params.clone(), 4).unwrap();
+    registry.register([7u8; 32], alice).unwrap();
+
+    let missing = [8u8; 32];
+    let message = transfer_message(&missing, &bob, 0).unwrap();
+    let signature = schnorr::sign(&params, &alice_sk, &message).unwrap();
+    assert!(matches!(
+        registry.transfer_circuit(&missing, &bob, &signature),
+        Err(MerkleError::LeafNotFound)
+    ));
+    assert!(matches!(
+        registry.apply_transfer(&missing, &bob, &signature),
+        Err(MerkleError::LeafNotFound)
+    ));
+}
+
+#[test]
+fn test_registry_transfer_replay() {
+    use crate::circuit_report::expect_unsatisfied;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (alice_sk, alice) = schnorr::keygen(&mut rng);
+    let (bob_sk, bob) = schnorr::keygen(&mut rng);
+
+    let mut registry = Registry::new(params.clone(), 4).unwrap();
+    let asset = [7u8; 32];
+    let first_root = registry.register(asset, alice).unwrap();
+
+    // alice hands the asset to bob, and bob hands it back
+    let to_bob = schnorr::sign(&params, &alice_sk, &transfer_message(&asset, &bob, 0).unwrap()).unwrap();
+    registry.apply_transfer(&asset, &bob, &to_bob).unwrap();
+    let to_alice = schnorr::sign(&params, &bob_sk, &transfer_message(&asset, &alice, 1).unwrap()).unwrap();
+    let root = registry.apply_transfer(&asset, &alice, &to_alice).unwrap();
+    assert_eq!((registry.owner(&asset), registry.transfers(&asset)), (Some(&alice), Some(2)));
+    // the count keeps the tree from being the one alice signed for
+    assert_ne!(root, first_root);
+
+    // so her first signature moves nothing, natively or in the circuit
+    expect_unsatisfied(registry.transfer_circuit(&asset, &bob, &to_bob).unwrap(), "signature");
+    assert!(matches!(
+        registry.apply_transfer(&asset, &bob, &to_bob),
+        Err(MerkleError::InvalidSignature)
+    ));
+    assert_eq!(registry.owner(&asset), Some(&alice));
+}
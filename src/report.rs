@@ -0,0 +1,239 @@
+use crate::common::poseidon::{PoseidonMTreeVerification, PoseidonParams};
+use crate::common::{ConstraintF, MerkleParams, TreeParams};
+use crate::constraints::MTreeVerification;
+use crate::error::MerkleError;
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::merkle_tree::{Config, Path};
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+use ark_std::rand::{CryptoRng, RngCore};
+use std::fmt::Write;
+use std::time::Duration;
+
+// How big, and with `snark` how slow, the membership circuit is over each
+// hash, to pick a hash and a tree height with.
+//
+// The circuits are those of the leftmost leaf, all zero bytes, of a tree
+// whose other nodes all hash to the default digest: a real root and path,
+// so the witness satisfies the circuit, without building the 2^(height - 1)
+// leaves of an actual tree. Their size only depends on the height and the
+// leaf length, and so does the prover's time.
+
+/// A hash the membership circuit can be built over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashBackend {
+    /// `MTreeVerification`, the crate's Pedersen tree.
+    Pedersen,
+    /// `PoseidonMTreeVerification`, from `common::poseidon`.
+    Poseidon,
+}
+
+impl HashBackend {
+    pub const ALL: [HashBackend; 2] = [HashBackend::Pedersen, HashBackend::Poseidon];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashBackend::Pedersen => "pedersen",
+            HashBackend::Poseidon => "poseidon",
+        }
+    }
+}
+
+/// The membership circuit of one backend at one tree height.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConstraintReport {
+    pub backend: HashBackend,
+    /// Levels, leaves and root included, like `MerkleTree::height`.
+    pub tree_height: usize,
+    pub leaf_len: usize,
+    pub constraints: usize,
+    /// Including the constant one.
+    pub instance_variables: usize,
+    pub witness_variables: usize,
+    /// The Groth16 prover's time, if the report was made with `timed_constraint_report`.
+    pub proving_time: Option<Duration>,
+}
+
+/// The reports of every backend at every height of `tree_heights`, for leaves
+/// of `leaf_len` bytes, backend by backend.
+pub fn constraint_report<R: RngCore + CryptoRng>(
+    tree_heights: &[usize],
+    leaf_len: usize,
+    rng: &mut R,
+) -> Result<Vec<ConstraintReport>, MerkleError> {
+    let pedersen = MerkleParams::setup(rng)?;
+    let poseidon = PoseidonParams::setup(rng)?;
+    let mut reports = Vec::new();
+    for &backend in HashBackend::ALL.iter() {
+        for &tree_height in tree_heights {
+            let counts = match backend {
+                HashBackend::Pedersen => count(pedersen_circuit(&pedersen, tree_height, leaf_len)?)?,
+                HashBackend::Poseidon => count(poseidon_circuit(&poseidon, tree_height, leaf_len)?)?,
+            };
+            reports.push(ConstraintReport {
+                backend,
+                tree_height,
+                leaf_len,
+                constraints: counts.0,
+                instance_variables: counts.1,
+                witness_variables: counts.2,
+                proving_time: None,
+            });
+        }
+    }
+    Ok(reports)
+}
+
+/// `constraint_report`, with a Groth16 setup for each circuit and the time of
+/// one proof with its key.
+#[cfg(feature = "snark")]
+pub fn timed_constraint_report<R: RngCore + CryptoRng>(
+    tree_heights: &[usize],
+    leaf_len: usize,
+    rng: &mut R,
+) -> Result<Vec<ConstraintReport>, MerkleError> {
+    let pedersen = MerkleParams::setup(rng)?;
+    let poseidon = PoseidonParams::setup(rng)?;
+    let mut reports = constraint_report(tree_heights, leaf_len, rng)?;
+    for report in reports.iter_mut() {
+        let (height, len) = (report.tree_height, report.leaf_len);
+        report.proving_time = Some(match report.backend {
+            HashBackend::Pedersen => time_proof(|| pedersen_circuit(&pedersen, height, len), rng)?,
+            HashBackend::Poseidon => time_proof(|| poseidon_circuit(&poseidon, height, len), rng)?,
+        });
+    }
+    Ok(reports)
+}
+
+/// The reports as a plain-text table, one line each.
+pub fn report_table(reports: &[ConstraintReport]) -> String {
+    let mut table = String::from("backend   height  leaf  constraints  instance  witness  proving\n");
+    for report in reports {
+        let proving = match report.proving_time {
+            Some(time) => format!("{:.2?}", time),
+            None => "-".to_string(),
+        };
+        // writing to a `String` can't fail
+        let _ = writeln!(
+            table,
+            "{:<9} {:>6} {:>5} {:>12} {:>9} {:>8}  {}",
+            report.backend.name(),
+            report.tree_height,
+            report.leaf_len,
+            report.constraints,
+            report.instance_variables,
+            report.witness_variables,
+            proving
+        );
+    }
+    table
+}
+
+/// The Pedersen membership circuit for a tree of `tree_height` levels, with a
+/// satisfying witness.
+pub fn pedersen_circuit(
+    params: &MerkleParams,
+    tree_height: usize,
+    leaf_len: usize,
+) -> Result<MTreeVerification, MerkleError> {
+    let leaf = vec![0u8; leaf_len];
+    let (root, path) = leftmost_path(params, &leaf, tree_height)?;
+    Ok(<MTreeVerification>::new(params, root, leaf, path))
+}
+
+/// The Poseidon membership circuit for a tree of `tree_height` levels, with a
+/// satisfying witness.
+pub fn poseidon_circuit(
+    params: &PoseidonParams,
+    tree_height: usize,
+    leaf_len: usize,
+) -> Result<PoseidonMTreeVerification, MerkleError> {
+    let leaf = vec![0u8; leaf_len];
+    let (root, path) = leftmost_path(params, &leaf, tree_height)?;
+    Ok(PoseidonMTreeVerification::new(params, root, leaf, path))
+}
+
+// The path of leaf 0 with default siblings throughout, and the root it leads to.
+fn leftmost_path<P>(
+    params: &TreeParams<P>,
+    leaf: &[u8],
+    tree_height: usize,
+) -> Result<(<P::TwoToOneHash as TwoToOneCRH>::Output, Path<P>), MerkleError>
+where
+    P: Config,
+    P::LeafHash: CRH<Output = <P::TwoToOneHash as TwoToOneCRH>::Output>,
+{
+    assert!(tree_height >= 2, "a tree has at least two levels");
+    let sibling = <P::TwoToOneHash as TwoToOneCRH>::Output::default();
+    let mut root = params.hash_children(&params.hash_leaf(leaf)?, &sibling)?;
+    for _ in 2..tree_height {
+        root = params.hash_children(&root, &sibling)?;
+    }
+    let path = Path {
+        leaf_sibling_hash: sibling.clone(),
+        auth_path: vec![sibling; tree_height - 2],
+        leaf_index: 0,
+    };
+    Ok((root, path))
+}
+
+fn count<C: ConstraintSynthesizer<ConstraintF>>(circuit: C) -> Result<(usize, usize, usize), MerkleError> {
+    let cs = ConstraintSystem::<ConstraintF>::new_ref();
+    circuit.generate_constraints(cs.clone())?;
+    Ok((cs.num_constraints(), cs.num_instance_variables(), cs.num_witness_variables()))
+}
+
+// Sets up keys for a circuit of `circuit` and times proving another one.
+#[cfg(feature = "snark")]
+fn time_proof<C, R>(circuit: impl Fn() -> Result<C, MerkleError>, rng: &mut R) -> Result<Duration, MerkleError>
+where
+    C: ConstraintSynthesizer<ConstraintF>,
+    R: RngCore + CryptoRng,
+{
+    let (pk, _) = crate::snark::setup(circuit()?, rng)?;
+    let circuit = circuit()?;
+    let start = std::time::Instant::now();
+    crate::snark::prove(&pk, circuit, rng)?;
+    Ok(start.elapsed())
+}
+
+#[test]
+fn test_report_circuits_are_satisfied() {
+    use crate::circuit_report::assert_satisfied_with_report;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let circuit = pedersen_circuit(&params, 4, 8).unwrap();
+    assert!(params
+        .verify_path(circuit.auth_path.as_ref().unwrap(), &circuit.root, &circuit.leaf)
+        .unwrap());
+    let pedersen = assert_satisfied_with_report(circuit);
+    // the same shape as the keys of `MTreeVerification::blank`
+    let blank = crate::circuit_report::synthesize_with_report(<MTreeVerification>::blank(&params, 4, 8))
+        .unwrap()
+        .metrics;
+    assert_eq!(pedersen, blank);
+
+    let params = PoseidonParams::setup(&mut rng).unwrap();
+    assert_satisfied_with_report(poseidon_circuit(&params, 4, 8).unwrap());
+}
+
+#[test]
+fn test_constraint_report() {
+    let reports = constraint_report(&[3, 5], 16, &mut ark_std::test_rng()).unwrap();
+    assert_eq!(reports.len(), 4);
+    let find = |backend, height| {
+        reports
+            .iter()
+            .find(|r| r.backend == backend && r.tree_height == height)
+            .unwrap()
+    };
+    for &backend in HashBackend::ALL.iter() {
+        assert!(find(backend, 5).constraints > find(backend, 3).constraints);
+        assert_eq!(find(backend, 5).instance_variables, find(backend, 3).instance_variables);
+        assert_eq!(find(backend, 3).proving_time, None);
+    }
+    assert!(find(HashBackend::Poseidon, 5).constraints < find(HashBackend::Pedersen, 5).constraints);
+    let table = report_table(&reports);
+    assert_eq!(table.lines().count(), 5);
+    assert!(table.lines().nth(1).unwrap().starts_with("pedersen"));
+}
@@ -0,0 +1,40 @@
+use ark_std::rand::{CryptoRng, Error, RngCore};
+use rand::rngs::OsRng;
+
+// Randomness for code that isn't a test.
+//
+// Every function of this crate that needs randomness takes it as an
+// `R: RngCore + CryptoRng` argument, and none of them makes its own, except
+// `kit`, which proves with `OsRngProvider` so callers don't have to pass
+// anything. The only fixed-seed RNGs are `kit::default_params`, whose
+// parameters are meant to be reproducible, and the `testing` helpers.
+// The fixed-seed RNG `ark_std` has for tests is for tests only;
+// `tests/randomness.rs` fails if it shows up anywhere else in the crate.
+
+/// The operating system's RNG, for wherever a `RngCore + CryptoRng` is asked for:
+///
+/// ```ignore
+/// let params = MerkleParams::setup(&mut OsRngProvider::default())?;
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsRngProvider(OsRng);
+
+impl RngCore for OsRngProvider {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for OsRngProvider {}
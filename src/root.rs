@@ -0,0 +1,137 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::Root;
+use ark_ff::ToConstraintField;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::fmt;
+use ark_std::io::{Read, Write};
+use ark_std::string::String;
+use ark_std::vec::Vec;
+
+// A tree root with an API of its own.
+//
+// `Root` is an alias for whatever digest type arkworks hands out, so naming it
+// downstream ties that code to the arkworks version. `MerkleRoot` only
+// promises bytes, hex and field elements; `Root` is kept for the circuit code
+// that needs the inner type.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MerkleRoot(Root);
+
+impl MerkleRoot {
+    /// The canonical encoding of the root.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.0.serialized_size());
+        // writing into a Vec cannot fail
+        self.0.serialize(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Decodes a root written by `to_bytes`, rejecting trailing bytes.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, MerkleError> {
+        let root = Root::deserialize(&mut bytes)?;
+        if !bytes.is_empty() {
+            return Err(SerializationError::InvalidData.into());
+        }
+        Ok(MerkleRoot(root))
+    }
+
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.to_bytes())
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self, MerkleError> {
+        Self::from_bytes(&from_hex(hex).ok_or(MerkleError::InvalidHex)?)
+    }
+
+    /// The root as the public inputs a circuit allocates it to.
+    pub fn to_field_elements(&self) -> Vec<ConstraintF> {
+        vec![self.0]
+    }
+
+    pub fn into_inner(self) -> Root {
+        self.0
+    }
+}
+
+impl fmt::Display for MerkleRoot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl From<Root> for MerkleRoot {
+    fn from(root: Root) -> Self {
+        MerkleRoot(root)
+    }
+}
+
+impl From<MerkleRoot> for Root {
+    fn from(root: MerkleRoot) -> Self {
+        root.0
+    }
+}
+
+// The same bytes as `to_bytes`, so a root can sit inside larger canonical
+// artifacts without going through `Root`.
+impl CanonicalSerialize for MerkleRoot {
+    fn serialize<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        self.0.serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.0.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for MerkleRoot {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        Ok(MerkleRoot(Root::deserialize(reader)?))
+    }
+}
+
+impl ToConstraintField<ConstraintF> for MerkleRoot {
+    fn to_field_elements(&self) -> Option<Vec<ConstraintF>> {
+        Some(MerkleRoot::to_field_elements(self))
+    }
+}
+
+#[test]
+fn test_root_hex_and_bytes_round_trip() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let root = MerkleRoot::from(params.build_tree(&leaves).unwrap().root());
+
+    assert_eq!(root.to_hex().len(), 64);
+    assert_eq!(MerkleRoot::from_hex(&root.to_hex()).unwrap(), root);
+    assert_eq!(MerkleRoot::from_bytes(&root.to_bytes()).unwrap(), root);
+    assert_eq!(format!("{}", root), root.to_hex());
+    assert_eq!(root.to_field_elements(), vec![root.into_inner()]);
+
+    let mut long = root.to_bytes();
+    long.push(0);
+    assert!(MerkleRoot::from_bytes(&long).is_err());
+    assert!(MerkleRoot::from_bytes(&root.to_bytes()[..31]).is_err());
+    assert!(matches!(MerkleRoot::from_hex("not hex"), Err(MerkleError::InvalidHex)));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_roots_as_map_keys() {
+    use std::collections::HashMap;
+
+    type Epoch = u64;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut epochs: HashMap<MerkleRoot, Epoch> = HashMap::new();
+    for epoch in 0..4u64 {
+        let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; epoch as usize + 1]).collect();
+        epochs.insert(params.build_tree(&leaves).unwrap().root().into(), epoch);
+    }
+    assert_eq!(epochs.len(), 4);
+
+    // a root that went through hex finds the same entry
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 3]).collect();
+    let root = MerkleRoot::from(params.build_tree(&leaves).unwrap().root());
+    assert_eq!(epochs[&MerkleRoot::from_hex(&root.to_hex()).unwrap()], 2);
+}
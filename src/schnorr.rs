@@ -0,0 +1,200 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ed_on_bls12_381::{EdwardsAffine, EdwardsProjective, Fr};
+use ark_ff::{to_bytes, BigInteger, PrimeField, UniformRand};
+use ark_std::rand::{CryptoRng, RngCore};
+#[cfg(feature = "constraints")]
+use {
+    ark_crypto_primitives::crh::CRHGadget,
+    ark_ed_on_bls12_381::constraints::EdwardsVar,
+    ark_r1cs_std::fields::fp::FpVar,
+    ark_r1cs_std::prelude::*,
+    ark_relations::r1cs::{Namespace, SynthesisError},
+    ark_std::borrow::Borrow,
+};
+
+// Schnorr signatures over the Jubjub curve the Pedersen hashes already use.
+//
+// The challenge is computed with the leaf hash rather than a byte-oriented
+// hash so that the very same scheme can be checked inside a circuit:
+//
+//   m' = LeafHash(message)
+//   e  = LeafHash(domain || x(R) || x(pk) || m')
+//   s  = k + e * sk                       (in the scalar field)
+//
+// and a signature (R, s) is valid iff `s * G == R + e * pk`. The challenge is
+// a base-field element; it is used as an integer multiplier, which is the same
+// as reducing it into the scalar field because pk lies in the prime-order
+// subgroup. Nonces are derived deterministically from the key and message.
+
+pub type SecretKey = Fr;
+pub type PublicKey = EdwardsAffine;
+
+const CHALLENGE_DOMAIN: &[u8] = b"schnorr/";
+const NONCE_DOMAIN: &[u8] = b"merkle-tutorial/schnorr-nonce";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub r: EdwardsAffine,
+    pub s: Fr,
+}
+
+pub fn generator() -> EdwardsProjective {
+    EdwardsProjective::prime_subgroup_generator()
+}
+
+pub fn keygen<R: RngCore + CryptoRng>(rng: &mut R) -> (SecretKey, PublicKey) {
+    let sk = Fr::rand(rng);
+    (sk, public_key(&sk))
+}
+
+pub fn public_key(sk: &SecretKey) -> PublicKey {
+    generator().mul(sk.into_repr()).into_affine()
+}
+
+/// The challenge `e` of a signature by `pk` with commitment `r` over `message`.
+pub fn challenge(
+    params: &MerkleParams,
+    r: &EdwardsAffine,
+    pk: &PublicKey,
+    message: &[u8],
+) -> Result<ConstraintF, MerkleError> {
+    let message_hash = params.hash_leaf(message)?;
+    let mut input = CHALLENGE_DOMAIN.to_vec();
+    input.extend(to_bytes![r.x, pk.x, message_hash]?);
+    params.hash_leaf(&input)
+}
+
+pub fn sign(params: &MerkleParams, sk: &SecretKey, message: &[u8]) -> Result<Signature, MerkleError> {
+    let pk = public_key(sk);
+
+    let mut nonce_input = NONCE_DOMAIN.to_vec();
+    nonce_input.extend(to_bytes![sk]?);
+    nonce_input.extend_from_slice(message);
+    let k = Fr::from_le_bytes_mod_order(&keccak256(&nonce_input));
+    let r = generator().mul(k.into_repr()).into_affine();
+
+    let e = challenge(params, &r, &pk, message)?;
+    let e = Fr::from_le_bytes_mod_order(&e.into_repr().to_bytes_le());
+    Ok(Signature { r, s: k + e * sk })
+}
+
+pub fn verify(
+    params: &MerkleParams,
+    pk: &PublicKey,
+    message: &[u8],
+    signature: &Signature,
+) -> Result<bool, MerkleError> {
+    let e = challenge(params, &signature.r, pk, message)?;
+    let lhs = generator().mul(signature.s.into_repr());
+    let rhs = signature.r.into_projective() + pk.mul(e.into_repr());
+    Ok(lhs == rhs)
+}
+
+/// The R1CS equivalent of a `Signature`: the commitment point and the bits of the response.
+#[cfg(feature = "constraints")]
+pub struct SignatureVar {
+    pub r: EdwardsVar,
+    pub s: Vec<Boolean<ConstraintF>>,
+}
+
+#[cfg(feature = "constraints")]
+impl AllocVar<Signature, ConstraintF> for SignatureVar {
+    fn new_variable<T: Borrow<Signature>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let ns = cs.into();
+        let cs = ns.cs();
+        let value = f().map(|v| v.borrow().clone());
+        let r = EdwardsVar::new_variable(cs.clone(), || value.clone().map(|v| v.r), mode)?;
+        let s = (0..Fr::size_in_bits())
+            .map(|i| {
+                Boolean::new_variable(
+                    cs.clone(),
+                    || value.clone().map(|v| v.s.into_repr().get_bit(i)),
+                    mode,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SignatureVar { r, s })
+    }
+}
+
+/// The in-circuit counterpart of `verify`: true iff `signature` is valid for `message` under `pk`.
+#[cfg(feature = "constraints")]
+pub fn verify_var(
+    leaf_crh_params: &LeafHashParamsVar,
+    pk: &EdwardsVar,
+    message: &[UInt8<ConstraintF>],
+    signature: &SignatureVar,
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    let message_hash =
+        <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(leaf_crh_params, message)?;
+    verify_hashed_var(leaf_crh_params, pk, &message_hash, signature)
+}
+
+/// Like `verify_var`, for a message given by its leaf hash. Lets circuits
+/// expose the hash instead of the message, whatever its length.
+#[cfg(feature = "constraints")]
+pub fn verify_hashed_var(
+    leaf_crh_params: &LeafHashParamsVar,
+    pk: &EdwardsVar,
+    message_hash: &FpVar<ConstraintF>,
+    signature: &SignatureVar,
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    let mut input = UInt8::constant_vec(CHALLENGE_DOMAIN);
+    input.extend(signature.r.x.to_bytes()?);
+    input.extend(pk.x.to_bytes()?);
+    input.extend(message_hash.to_bytes()?);
+    let e = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(leaf_crh_params, &input)?;
+
+    let lhs = EdwardsVar::constant(generator()).scalar_mul_le(signature.s.iter())?;
+    let rhs = signature.r.clone() + pk.scalar_mul_le(e.to_bits_le()?.iter())?;
+    lhs.is_eq(&rhs)
+}
+
+#[test]
+fn test_schnorr_sign_verify() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (sk, pk) = keygen(&mut rng);
+    let (_, other_pk) = keygen(&mut rng);
+
+    let signature = sign(&params, &sk, b"hello").unwrap();
+    assert!(verify(&params, &pk, b"hello", &signature).unwrap());
+    assert!(!verify(&params, &pk, b"hellp", &signature).unwrap());
+    assert!(!verify(&params, &other_pk, b"hello", &signature).unwrap());
+    // nonces are deterministic
+    assert_eq!(signature, sign(&params, &sk, b"hello").unwrap());
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_schnorr_gadget_matches_native() {
+    use ark_relations::r1cs::ConstraintSystem;
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (sk, pk) = keygen(&mut rng);
+    let (_, other_pk) = keygen(&mut rng);
+    let signature = sign(&params, &sk, b"hello").unwrap();
+
+    let cases = [
+        (pk, &b"hello"[..], true),
+        (other_pk, &b"hello"[..], false),
+        (pk, &b"hellp"[..], false),
+    ];
+    for (key, message, expected) in cases.iter() {
+        let cs = ConstraintSystem::<ConstraintF>::new_ref();
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &params.leaf_crh_params).unwrap();
+        let pk_var = EdwardsVar::new_witness(cs.clone(), || Ok(*key)).unwrap();
+        let message_var = UInt8::new_witness_vec(cs.clone(), &message[..]).unwrap();
+        let signature_var = SignatureVar::new_witness(cs.clone(), || Ok(&signature)).unwrap();
+        let valid = verify_var(&leaf_crh_params, &pk_var, &message_var, &signature_var).unwrap();
+        assert_eq!(valid.value().unwrap(), *expected);
+        assert!(cs.is_satisfied().unwrap());
+    }
+}
@@ -0,0 +1,383 @@
+use crate::common::MerkleParams;
+use crate::error::MerkleError;
+use crate::{Root, SimplePath};
+use ark_crypto_primitives::crh::pedersen;
+use ark_crypto_primitives::merkle_tree::{Config, Path};
+use ark_ec::ProjectiveCurve;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::io::{Read, Write};
+use ark_std::vec::Vec;
+
+// `Path` comes from arkworks and doesn't implement the canonical
+// serialization traits itself, so its fields are written out one by one:
+// the leaf index as a u64, the leaf sibling digest, then the auth path.
+
+/// Writes `path` to `writer`.
+pub fn write_path<P: Config, W: Write>(path: &Path<P>, mut writer: W) -> Result<(), SerializationError> {
+    (path.leaf_index as u64).serialize(&mut writer)?;
+    path.leaf_sibling_hash.serialize(&mut writer)?;
+    path.auth_path.serialize(&mut writer)
+}
+
+/// Reads a path written by `write_path`.
+pub fn read_path<P: Config, R: Read>(mut reader: R) -> Result<Path<P>, SerializationError> {
+    let leaf_index = u64::deserialize(&mut reader)? as usize;
+    let leaf_sibling_hash = CanonicalDeserialize::deserialize(&mut reader)?;
+    let auth_path = CanonicalDeserialize::deserialize(&mut reader)?;
+    Ok(Path {
+        leaf_sibling_hash,
+        auth_path,
+        leaf_index,
+    })
+}
+
+/// Number of bytes `write_path` produces for `path`.
+pub fn path_serialized_size<P: Config>(path: &Path<P>) -> usize {
+    0u64.serialized_size() + path.leaf_sibling_hash.serialized_size() + path.auth_path.serialized_size()
+}
+
+pub fn serialize_path(path: &SimplePath) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(path_serialized_size(path));
+    // writing into a Vec cannot fail
+    write_path(path, &mut bytes).unwrap();
+    bytes
+}
+
+pub fn deserialize_path(bytes: &[u8]) -> Result<SimplePath, SerializationError> {
+    read_path(bytes)
+}
+
+/// Whether curve points are written compressed, i.e. as one coordinate and a
+/// sign bit. Compressed parameters are half the size; uncompressed ones are
+/// faster to read back. Roots and paths are field elements, which are written
+/// the same either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Compressed,
+    Uncompressed,
+}
+
+pub fn serialize_root(root: &Root) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(root.serialized_size());
+    // writing into a Vec cannot fail
+    root.serialize(&mut bytes).unwrap();
+    bytes
+}
+
+/// Reads a root written by `serialize_root`, rejecting trailing bytes.
+pub fn deserialize_root(bytes: &[u8]) -> Result<Root, SerializationError> {
+    decode_whole(bytes)
+}
+
+// Pedersen parameters don't implement the canonical serialization traits
+// either; they are their generators, window by window.
+
+/// Writes the parameters of one Pedersen hash, e.g. `MerkleParams::leaf_crh_params`.
+pub fn write_pedersen_params<C: ProjectiveCurve, W: Write>(
+    params: &pedersen::Parameters<C>,
+    compression: Compression,
+    writer: W,
+) -> Result<(), SerializationError> {
+    match compression {
+        Compression::Compressed => params.generators.serialize(writer),
+        Compression::Uncompressed => params.generators.serialize_uncompressed(writer),
+    }
+}
+
+/// Reads parameters written by `write_pedersen_params` with the same
+/// `compression`. Every generator is checked to be on the curve.
+pub fn read_pedersen_params<C: ProjectiveCurve, R: Read>(
+    compression: Compression,
+    reader: R,
+) -> Result<pedersen::Parameters<C>, SerializationError> {
+    let generators = match compression {
+        Compression::Compressed => CanonicalDeserialize::deserialize(reader)?,
+        Compression::Uncompressed => CanonicalDeserialize::deserialize_uncompressed(reader)?,
+    };
+    Ok(pedersen::Parameters { generators })
+}
+
+/// Encodes both hash parameter sets: the leaf hash's, then the two-to-one hash's.
+pub fn serialize_params(params: &MerkleParams, compression: Compression) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    // writing into a Vec cannot fail
+    write_pedersen_params(&params.leaf_crh_params, compression, &mut bytes).unwrap();
+    write_pedersen_params(&params.two_to_one_crh_params, compression, &mut bytes).unwrap();
+    bytes
+}
+
+/// Decodes parameters written by `serialize_params` with the same `compression`.
+pub fn deserialize_params(mut bytes: &[u8], compression: Compression) -> Result<MerkleParams, SerializationError> {
+    let params = MerkleParams {
+        leaf_crh_params: read_pedersen_params(compression, &mut bytes)?,
+        two_to_one_crh_params: read_pedersen_params(compression, &mut bytes)?,
+    };
+    if !bytes.is_empty() {
+        return Err(SerializationError::InvalidData);
+    }
+    Ok(params)
+}
+
+/// A `SimplePath` with the canonical serialization traits, written like
+/// `write_path`, for code that composes paths into larger `CanonicalSerialize`
+/// artifacts or hands them to generic storage.
+#[derive(Clone, Debug)]
+pub struct SerializablePath(pub SimplePath);
+
+impl CanonicalSerialize for SerializablePath {
+    fn serialize<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        write_path(&self.0, writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        path_serialized_size(&self.0)
+    }
+}
+
+impl CanonicalDeserialize for SerializablePath {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        Ok(SerializablePath(read_path(reader)?))
+    }
+}
+
+/// `MerkleParams` with the canonical serialization traits: `serialize` writes
+/// them like `serialize_params` with `Compression::Compressed`,
+/// `serialize_uncompressed` with `Compression::Uncompressed`.
+#[derive(Clone)]
+pub struct SerializableParams(pub MerkleParams);
+
+impl CanonicalSerialize for SerializableParams {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        write_pedersen_params(&self.0.leaf_crh_params, Compression::Compressed, &mut writer)?;
+        write_pedersen_params(&self.0.two_to_one_crh_params, Compression::Compressed, &mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.0.leaf_crh_params.generators.serialized_size() + self.0.two_to_one_crh_params.generators.serialized_size()
+    }
+
+    fn serialize_uncompressed<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        write_pedersen_params(&self.0.leaf_crh_params, Compression::Uncompressed, &mut writer)?;
+        write_pedersen_params(&self.0.two_to_one_crh_params, Compression::Uncompressed, &mut writer)
+    }
+
+    fn uncompressed_size(&self) -> usize {
+        self.0.leaf_crh_params.generators.uncompressed_size()
+            + self.0.two_to_one_crh_params.generators.uncompressed_size()
+    }
+}
+
+impl CanonicalDeserialize for SerializableParams {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(SerializableParams(MerkleParams {
+            leaf_crh_params: read_pedersen_params(Compression::Compressed, &mut reader)?,
+            two_to_one_crh_params: read_pedersen_params(Compression::Compressed, &mut reader)?,
+        }))
+    }
+
+    fn deserialize_uncompressed<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(SerializableParams(MerkleParams {
+            leaf_crh_params: read_pedersen_params(Compression::Uncompressed, &mut reader)?,
+            two_to_one_crh_params: read_pedersen_params(Compression::Uncompressed, &mut reader)?,
+        }))
+    }
+}
+
+/// A path as a leaf index and plain sibling digests, each in its compressed
+/// canonical encoding, for verifiers that don't use arkworks.
+///
+/// The siblings are bottom-up. `leaf_sibling` is the sibling of the leaf
+/// itself: a leaf hash, the digest of the neighbouring leaf, which is combined
+/// with the hash of the proven leaf into their parent. `nodes[i]` is the
+/// sibling of the node at level `i + 1`, counting the leaves at 0: two-to-one
+/// hashes of inner nodes, never of leaves. A tree of `2^k` leaves has `k - 1`
+/// of them, and the root is not among them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathSiblings {
+    pub leaf_index: u64,
+    pub leaf_sibling: Vec<u8>,
+    pub nodes: Vec<Vec<u8>>,
+}
+
+/// Conversion between a `Path` and its `PathSiblings`.
+pub trait PathSiblingsExt: Sized {
+    // `&self` rather than `self`, since the path is usually kept
+    #[allow(clippy::wrong_self_convention)]
+    fn into_siblings(&self) -> PathSiblings;
+
+    /// Errors if a digest doesn't decode, or if `leaf_index` doesn't fit the
+    /// tree that many siblings make up.
+    fn from_siblings(siblings: PathSiblings) -> Result<Self, MerkleError>;
+}
+
+impl<P: Config> PathSiblingsExt for Path<P> {
+    fn into_siblings(&self) -> PathSiblings {
+        PathSiblings {
+            leaf_index: self.leaf_index as u64,
+            leaf_sibling: encode(&self.leaf_sibling_hash),
+            // `auth_path` is top-down
+            nodes: self.auth_path.iter().rev().map(encode).collect(),
+        }
+    }
+
+    fn from_siblings(siblings: PathSiblings) -> Result<Self, MerkleError> {
+        // the leaves, the leaf level's siblings and one level per node
+        let height = siblings.nodes.len() + 1;
+        if height >= 64 || siblings.leaf_index >= 1 << height {
+            return Err(MerkleError::LeafIndexOutOfRange {
+                index: siblings.leaf_index as usize,
+                len: 1usize.checked_shl(height as u32).unwrap_or(usize::MAX),
+            });
+        }
+        Ok(Path {
+            leaf_sibling_hash: decode_whole(&siblings.leaf_sibling)?,
+            auth_path: siblings
+                .nodes
+                .iter()
+                .rev()
+                .map(|node| decode_whole(node))
+                .collect::<Result<Vec<_>, SerializationError>>()?,
+            leaf_index: siblings.leaf_index as usize,
+        })
+    }
+}
+
+fn encode<T: CanonicalSerialize>(digest: &T) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(digest.serialized_size());
+    // writing into a Vec cannot fail
+    digest.serialize(&mut bytes).unwrap();
+    bytes
+}
+
+// Decodes one digest, which must take up all of `bytes`.
+fn decode_whole<T: CanonicalDeserialize>(mut bytes: &[u8]) -> Result<T, SerializationError> {
+    let digest = T::deserialize(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(SerializationError::InvalidData);
+    }
+    Ok(digest)
+}
+
+#[test]
+fn test_path_siblings_roundtrip() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+
+    for index in 0..leaves.len() {
+        let path = tree.generate_proof(index).unwrap();
+        let siblings = path.into_siblings();
+        assert_eq!(siblings.leaf_index, index as u64);
+        assert_eq!(siblings.nodes.len(), 2);
+        assert_eq!(siblings.nodes[1].len(), path.auth_path[0].serialized_size());
+
+        let path = SimplePath::from_siblings(siblings.clone()).unwrap();
+        assert_eq!(path.into_siblings(), siblings);
+        assert!(params.verify_path(&path, &tree.root(), &leaves[index]).unwrap());
+    }
+}
+
+#[test]
+fn test_path_siblings_rejects_the_wrong_length() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let siblings = tree.generate_proof(6).unwrap().into_siblings();
+
+    // leaf 6 doesn't fit in the four leaves one node would make
+    let mut short = siblings.clone();
+    short.nodes.pop();
+    assert!(matches!(
+        SimplePath::from_siblings(short),
+        Err(MerkleError::LeafIndexOutOfRange { index: 6, len: 4 })
+    ));
+
+    // an extra node decodes, but the path no longer leads to the root
+    let mut long = siblings.clone();
+    long.nodes.push(siblings.nodes[0].clone());
+    let path = SimplePath::from_siblings(long).unwrap();
+    assert!(!params.verify_path(&path, &tree.root(), &leaves[6]).unwrap());
+
+    let mut truncated = siblings;
+    truncated.nodes[0].pop();
+    assert!(SimplePath::from_siblings(truncated).is_err());
+}
+
+#[test]
+fn test_serialized_artifacts_roundtrip() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+
+    let root = deserialize_root(&serialize_root(&tree.root())).unwrap();
+    let path = deserialize_path(&serialize_path(&tree.generate_proof(1).unwrap())).unwrap();
+    assert_eq!(root, tree.root());
+
+    for compression in [Compression::Compressed, Compression::Uncompressed].iter().copied() {
+        let bytes = serialize_params(&params, compression);
+        let decoded = deserialize_params(&bytes, compression).unwrap();
+        assert!(decoded.verify_path(&path, &root, &leaves[1]).unwrap());
+        assert_eq!(decoded.build_tree(&leaves).unwrap().root(), root);
+    }
+    assert!(
+        serialize_params(&params, Compression::Compressed).len()
+            < serialize_params(&params, Compression::Uncompressed).len()
+    );
+}
+
+#[test]
+fn test_corrupted_artifacts_are_errors() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+
+    let path = serialize_path(&tree.generate_proof(1).unwrap());
+    assert!(deserialize_path(&path[..path.len() - 1]).is_err());
+    let root = serialize_root(&tree.root());
+    assert!(deserialize_root(&root[..root.len() - 1]).is_err());
+    assert!(deserialize_root(&[root.clone(), vec![0]].concat()).is_err());
+
+    let mut bytes = serialize_params(&params, Compression::Uncompressed);
+    assert!(deserialize_params(&bytes[..bytes.len() - 1], Compression::Uncompressed).is_err());
+    // a generator that is no longer on the curve
+    bytes[8 + 8 + 3] ^= 1;
+    assert!(deserialize_params(&bytes, Compression::Uncompressed).is_err());
+    // and parameters written one way don't read the other
+    let bytes = serialize_params(&params, Compression::Compressed);
+    assert!(deserialize_params(&bytes, Compression::Uncompressed).is_err());
+}
+
+#[test]
+fn test_serializable_wrappers_match_the_byte_functions() {
+    use crate::root::MerkleRoot;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+
+    // a prover's message: root, path and params in one stream
+    let root = MerkleRoot::from(tree.root());
+    let path = SerializablePath(tree.generate_proof(2).unwrap());
+    let wrapped = SerializableParams(params.clone());
+    let mut message = Vec::new();
+    root.serialize(&mut message).unwrap();
+    path.serialize(&mut message).unwrap();
+    wrapped.serialize(&mut message).unwrap();
+    assert_eq!(message.len(), root.serialized_size() + path.serialized_size() + wrapped.serialized_size());
+    assert_eq!(path.serialized_size(), serialize_path(&path.0).len());
+
+    // the verifier's side
+    let mut reader = &message[..];
+    let root = MerkleRoot::deserialize(&mut reader).unwrap();
+    let path = SerializablePath::deserialize(&mut reader).unwrap();
+    let received = SerializableParams::deserialize(&mut reader).unwrap().0;
+    assert!(reader.is_empty());
+    assert!(received.verify_path(&path.0, &root.into_inner(), &leaves[2]).unwrap());
+
+    let mut uncompressed = Vec::new();
+    wrapped.serialize_uncompressed(&mut uncompressed).unwrap();
+    assert_eq!(uncompressed, serialize_params(&params, Compression::Uncompressed));
+    assert_eq!(uncompressed.len(), wrapped.uncompressed_size());
+    let received = SerializableParams::deserialize_uncompressed(&uncompressed[..]).unwrap().0;
+    assert_eq!(received.build_tree(&leaves).unwrap().root(), tree.root());
+}
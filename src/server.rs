@@ -0,0 +1,326 @@
+use crate::backend::MerkleBackend;
+use crate::common::{from_hex, to_hex, MerkleParams};
+use crate::error::MerkleError;
+use crate::rng::OsRngProvider;
+use crate::root::MerkleRoot;
+use crate::serialization::{deserialize_path, serialize_path};
+use crate::snark::{self, ProvingKey};
+use crate::Root;
+use ark_serialize::CanonicalSerialize;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+// A Merkle proof API over HTTP.
+//
+// `ProofService` holds a tree behind a lock, any `MerkleBackend`: a
+// `LevelTree` in memory, or a `storage::StoredTree` on disk that outlives
+// the process. `router` serves it with axum:
+//
+//     GET  /root           {"root": hex}
+//     GET  /proof/{index}  {"index", "root": hex, "path": hex}
+//     POST /verify         {"leaf": hex, "path": hex, "root": hex?} -> {"valid"}
+//     POST /snark/prove    {"index", "leaf": hex} -> {"root": hex, "proof": hex}
+//
+// Paths are `serialization::serialize_path` bytes and roots `MerkleRoot`
+// bytes, so clients decode them with the same functions; `/verify` checks
+// against the current root unless given another. Groth16 proofs take
+// seconds, so `/snark/prove` runs on tokio's blocking pool rather than
+// holding up the other requests, and answers 404 if the service has no
+// proving key. Errors come back as {"error": message}, with 400 for what the
+// client got wrong and 500 for the rest.
+
+/// A tree, its parameters and, for `/snark/prove`, a Groth16 proving key.
+pub struct ProofService<B> {
+    params: MerkleParams,
+    tree: RwLock<B>,
+    proving_key: Option<ProvingKey>,
+}
+
+impl<B: MerkleBackend> ProofService<B> {
+    pub fn new(params: MerkleParams, tree: B) -> Self {
+        ProofService {
+            params,
+            tree: RwLock::new(tree),
+            proving_key: None,
+        }
+    }
+
+    /// Serves `/snark/prove` with `pk`, made for the height of the tree.
+    pub fn with_proving_key(mut self, pk: ProvingKey) -> Self {
+        self.proving_key = Some(pk);
+        self
+    }
+
+    pub fn params(&self) -> &MerkleParams {
+        &self.params
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.read().unwrap().root()
+    }
+
+    /// Runs `f` on the tree under the write lock, to change it between requests.
+    pub fn update<T>(&self, f: impl FnOnce(&mut B) -> T) -> T {
+        f(&mut self.tree.write().unwrap())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RootResponse {
+    pub root: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProofResponse {
+    pub index: usize,
+    pub root: String,
+    pub path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyRequest {
+    pub leaf: String,
+    pub path: String,
+    /// The root to verify against, the current one if absent.
+    #[serde(default)]
+    pub root: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyResponse {
+    pub valid: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnarkProveRequest {
+    pub index: usize,
+    pub leaf: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnarkProveResponse {
+    pub root: String,
+    pub proof: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// An error as the API answers it.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<MerkleError> for ApiError {
+    fn from(e: MerkleError) -> Self {
+        let status = match e.root_cause() {
+            MerkleError::LeafIndexOutOfRange { .. }
+            | MerkleError::LeafTooLong { .. }
+            | MerkleError::InvalidHex
+            | MerkleError::InvalidProof
+            | MerkleError::Serialization(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ApiError {
+            status,
+            message: e.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(ErrorResponse { error: self.message })).into_response()
+    }
+}
+
+fn hex_field(name: &str, hex: &str) -> Result<Vec<u8>, ApiError> {
+    from_hex(hex).ok_or_else(|| ApiError::bad_request(format!("`{}` is not hex", name)))
+}
+
+fn root_hex(root: Root) -> String {
+    MerkleRoot::from(root).to_hex()
+}
+
+async fn get_root<B: MerkleBackend>(State(service): State<Arc<ProofService<B>>>) -> Json<RootResponse> {
+    Json(RootResponse {
+        root: root_hex(service.root()),
+    })
+}
+
+async fn get_proof<B: MerkleBackend>(
+    State(service): State<Arc<ProofService<B>>>,
+    Path(index): Path<usize>,
+) -> Result<Json<ProofResponse>, ApiError> {
+    // the path and the root from under the same lock
+    let tree = service.tree.read().unwrap();
+    let path = tree.prove(index)?;
+    Ok(Json(ProofResponse {
+        index,
+        root: root_hex(tree.root()),
+        path: to_hex(&serialize_path(&path)),
+    }))
+}
+
+async fn verify<B: MerkleBackend>(
+    State(service): State<Arc<ProofService<B>>>,
+    Json(request): Json<VerifyRequest>,
+) -> Result<Json<VerifyResponse>, ApiError> {
+    let leaf = hex_field("leaf", &request.leaf)?;
+    let path = deserialize_path(&hex_field("path", &request.path)?).map_err(MerkleError::from)?;
+    let root = match &request.root {
+        Some(hex) => MerkleRoot::from_hex(hex)?.into(),
+        None => service.root(),
+    };
+    Ok(Json(VerifyResponse {
+        valid: service.params.verify_path(&path, &root, &leaf)?,
+    }))
+}
+
+async fn snark_prove<B: MerkleBackend + Send + Sync + 'static>(
+    State(service): State<Arc<ProofService<B>>>,
+    Json(request): Json<SnarkProveRequest>,
+) -> Result<Json<SnarkProveResponse>, ApiError> {
+    if service.proving_key.is_none() {
+        return Err(ApiError {
+            status: StatusCode::NOT_FOUND,
+            message: "this service has no proving key".into(),
+        });
+    }
+    let leaf = hex_field("leaf", &request.leaf)?;
+    let prove = move || -> Result<SnarkProveResponse, ApiError> {
+        let (path, root) = {
+            let tree = service.tree.read().unwrap();
+            (tree.prove(request.index)?, tree.root())
+        };
+        // a proof for a leaf that isn't there would only fail to verify later
+        if !service.params.verify_path(&path, &root, &leaf)? {
+            return Err(ApiError::bad_request(format!("the leaf is not at index {}", request.index)));
+        }
+        let pk = service.proving_key.as_ref().unwrap();
+        let proof = snark::prove_membership(pk, &service.params, root, &leaf, path, &mut OsRngProvider::default())?;
+        let mut bytes = Vec::new();
+        proof.serialize(&mut bytes).map_err(MerkleError::from)?;
+        Ok(SnarkProveResponse {
+            root: root_hex(root),
+            proof: to_hex(&bytes),
+        })
+    };
+    let response = tokio::task::spawn_blocking(prove).await.map_err(|e| ApiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        message: e.to_string(),
+    })??;
+    Ok(Json(response))
+}
+
+/// The routes of the API over `service`.
+pub fn router<B: MerkleBackend + Send + Sync + 'static>(service: Arc<ProofService<B>>) -> Router {
+    Router::new()
+        .route("/root", get(get_root::<B>))
+        .route("/proof/:index", get(get_proof::<B>))
+        .route("/verify", post(verify::<B>))
+        .route("/snark/prove", post(snark_prove::<B>))
+        .with_state(service)
+}
+
+/// Serves the API over `service` on `addr` until the server fails.
+pub async fn serve<B: MerkleBackend + Send + Sync + 'static>(
+    addr: SocketAddr,
+    service: Arc<ProofService<B>>,
+) -> std::io::Result<()> {
+    axum::Server::bind(&addr)
+        .serve(router(service).into_make_service())
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+}
+
+#[cfg(test)]
+async fn call<T: serde::de::DeserializeOwned>(
+    router: &Router,
+    request: axum::http::Request<axum::body::Body>,
+) -> (StatusCode, T) {
+    use tower::ServiceExt;
+
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[cfg(test)]
+fn post_json(uri: &str, body: serde_json::Value) -> axum::http::Request<axum::body::Body> {
+    axum::http::Request::post(uri)
+        .header("content-type", "application/json")
+        .body(axum::body::Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_proof_service_routes() {
+    use crate::level_tree::LevelTree;
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::json;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = LevelTree::new(params.clone(), &leaves).unwrap();
+    let (pk, vk) = snark::setup_groth16(&params, 3, 8, &mut rng).unwrap();
+    let service = Arc::new(ProofService::new(params, tree).with_proving_key(pk));
+    let router = router(service.clone());
+    let root = root_hex(service.root());
+
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+    let (status, body): (_, RootResponse) = call(&router, get("/root")).await;
+    assert_eq!((status, body.root), (StatusCode::OK, root.clone()));
+
+    let (status, proof): (_, ProofResponse) = call(&router, get("/proof/2")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(proof.root, root);
+    let (status, error): (_, ErrorResponse) = call(&router, get("/proof/4")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(error.error.contains("out of range"));
+
+    let verify = |leaf: &[u8]| post_json("/verify", json!({ "leaf": to_hex(leaf), "path": proof.path }));
+    let (_, body): (_, VerifyResponse) = call(&router, verify(&leaves[2])).await;
+    assert!(body.valid);
+    let (_, body): (_, VerifyResponse) = call(&router, verify(&leaves[3])).await;
+    assert!(!body.valid);
+    let not_hex = post_json("/verify", json!({ "leaf": "zz", "path": "" }));
+    let (status, _): (_, ErrorResponse) = call(&router, not_hex).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let prove = |index: usize, leaf: &[u8]| {
+        post_json("/snark/prove", json!({ "index": index, "leaf": to_hex(leaf) }))
+    };
+    let (status, body): (_, SnarkProveResponse) = call(&router, prove(1, &leaves[1])).await;
+    assert_eq!(status, StatusCode::OK);
+    let proof = {
+        use ark_serialize::CanonicalDeserialize;
+        snark::Proof::deserialize(&from_hex(&body.proof).unwrap()[..]).unwrap()
+    };
+    assert!(snark::verify_membership_proof(&vk, &service.root(), &leaves[1], &proof).unwrap());
+    let (status, _): (_, ErrorResponse) = call(&router, prove(1, &leaves[2])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
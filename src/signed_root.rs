@@ -0,0 +1,196 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::{blank_path, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+#[cfg(feature = "constraints")]
+use crate::inputs::byte_inputs;
+use crate::schnorr::{self, PublicKey, SecretKey, Signature};
+#[cfg(feature = "constraints")]
+use crate::schnorr::SignatureVar;
+use crate::{Root, SimplePath};
+use ark_ff::to_bytes;
+use ark_std::vec::Vec;
+#[cfg(feature = "constraints")]
+use {
+    ark_ed_on_bls12_381::constraints::EdwardsVar,
+    ark_r1cs_std::prelude::*,
+    ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError},
+};
+
+// Roots signed by an authority, and membership under them.
+//
+// An authority publishes the root of its tree signed with `schnorr`, over
+// the root's bytes. A light client that trusts the authority's key needs
+// nothing but the leaf, a path and the signature to believe that a record is
+// in the tree. `SignedRootMembershipCircuit` proves the same in one SNARK,
+// with the root, the signature and the path as witnesses: "this leaf is in
+// some tree whose root the holder of this key signed", without saying which
+// root, so proofs about different snapshots can't be told apart.
+
+/// The bytes of `root` the authority signs.
+pub fn root_message(root: &Root) -> Result<Vec<u8>, MerkleError> {
+    Ok(to_bytes![root]?)
+}
+
+/// Signs `root` as the authority with `sk`.
+pub fn sign_root(params: &MerkleParams, sk: &SecretKey, root: &Root) -> Result<Signature, MerkleError> {
+    schnorr::sign(params, sk, &root_message(root)?)
+}
+
+/// Checks that `signature` is `authority`'s over `root` and that `leaf`
+/// opens `path` against it.
+pub fn verify_signed_membership(
+    params: &MerkleParams,
+    authority: &PublicKey,
+    root: &Root,
+    signature: &Signature,
+    leaf: &[u8],
+    path: &SimplePath,
+) -> Result<bool, MerkleError> {
+    Ok(schnorr::verify(params, authority, &root_message(root)?, signature)?
+        && params.verify_path(path, root, leaf)?)
+}
+
+/// Proves that a public leaf is in a tree whose root the public `authority`
+/// key has signed, keeping the root, its signature and the path private.
+#[cfg(feature = "constraints")]
+pub struct SignedRootMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub authority: PublicKey,
+    pub leaf: Vec<u8>,
+
+    // These are the private witnesses to the circuit.
+    pub root: Option<Root>,
+    pub signature: Option<Signature>,
+    pub auth_path: Option<SimplePath>,
+}
+
+#[cfg(feature = "constraints")]
+impl SignedRootMembershipCircuit {
+    pub fn new(
+        params: &MerkleParams,
+        authority: PublicKey,
+        leaf: &[u8],
+        root: Root,
+        signature: Signature,
+        auth_path: SimplePath,
+    ) -> Self {
+        SignedRootMembershipCircuit {
+            params: params.clone(),
+            authority,
+            leaf: leaf.to_vec(),
+            root: Some(root),
+            signature: Some(signature),
+            auth_path: Some(auth_path),
+        }
+    }
+
+    /// A circuit of the right shape for key generation: trees of
+    /// `tree_height` levels and leaves of `leaf_len` bytes.
+    pub fn blank(params: &MerkleParams, tree_height: usize, leaf_len: usize) -> Self {
+        SignedRootMembershipCircuit {
+            params: params.clone(),
+            authority: schnorr::generator().into(),
+            leaf: vec![0u8; leaf_len],
+            root: None,
+            signature: None,
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order: the
+    /// coordinates of the authority's key, then the leaf bytes.
+    pub fn public_inputs(authority: &PublicKey, leaf: &[u8]) -> Vec<ConstraintF> {
+        let mut inputs = vec![authority.x, authority.y];
+        inputs.extend(byte_inputs(leaf));
+        inputs
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for SignedRootMembershipCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let authority = EdwardsVar::new_input(ark_relations::ns!(cs, "authority_var"), || Ok(self.authority))?;
+        let leaf = UInt8::new_input_vec(ark_relations::ns!(cs, "leaf_var"), &self.leaf)?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params = LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the root, its signature and the path as private witnesses:
+        let root = RootVar::new_witness(ark_relations::ns!(cs, "root_var"), || {
+            self.root.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let signature = SignatureVar::new_witness(ark_relations::ns!(cs, "signature_var"), || {
+            self.signature.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // the authority signed the root
+        {
+            let _signature = ark_relations::ns!(cs, "signature");
+            schnorr::verify_var(&leaf_crh_params, &authority, &root.to_bytes()?, &signature)?
+                .enforce_equal(&Boolean::TRUE)?;
+        }
+
+        // and the leaf is under it
+        let _membership = ark_relations::ns!(cs, "membership");
+        path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaf.as_slice())?
+            .enforce_equal(&Boolean::TRUE)
+    }
+}
+
+#[test]
+fn test_signed_membership() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (sk, authority) = schnorr::keygen(&mut rng);
+    let (_, other) = schnorr::keygen(&mut rng);
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let signature = sign_root(&params, &sk, &tree.root()).unwrap();
+    let path = tree.generate_proof(2).unwrap();
+
+    assert!(verify_signed_membership(&params, &authority, &tree.root(), &signature, &leaves[2], &path).unwrap());
+    assert!(!verify_signed_membership(&params, &other, &tree.root(), &signature, &leaves[2], &path).unwrap());
+    assert!(!verify_signed_membership(&params, &authority, &tree.root(), &signature, &leaves[1], &path).unwrap());
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_signed_root_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied, synthesize_with_report};
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (sk, authority) = schnorr::keygen(&mut rng);
+    let (other_sk, other) = schnorr::keygen(&mut rng);
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = tree.root();
+    let signature = sign_root(&params, &sk, &root).unwrap();
+    let circuit = |authority, leaf: &[u8], signature: &Signature| {
+        SignedRootMembershipCircuit::new(&params, authority, leaf, root, signature.clone(), tree.generate_proof(2).unwrap())
+    };
+
+    let metrics = assert_satisfied_with_report(circuit(authority, &leaves[2], &signature));
+    let blank = synthesize_with_report(SignedRootMembershipCircuit::blank(&params, tree.height(), 8)).unwrap();
+    assert_eq!(metrics, blank.metrics);
+    assert_eq!(SignedRootMembershipCircuit::public_inputs(&authority, &leaves[2]).len() + 1, metrics.instance_variables);
+
+    // another key, or a root signed by someone else
+    expect_unsatisfied(circuit(other, &leaves[2], &signature), "signature");
+    let forged = sign_root(&params, &other_sk, &root).unwrap();
+    expect_unsatisfied(circuit(authority, &leaves[2], &forged), "signature");
+    expect_unsatisfied(circuit(authority, &leaves[1], &signature), "membership");
+}
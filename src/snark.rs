@@ -0,0 +1,159 @@
+use crate::common::{ConstraintF, MerkleParams};
+use crate::constraints::MTreeVerification;
+use crate::error::MerkleError;
+use crate::inputs::PublicInputs;
+use crate::{Root, SimplePath};
+use ark_bls12_381::Bls12_381;
+use ark_groth16::Groth16;
+use ark_relations::r1cs::ConstraintSynthesizer;
+use ark_snark::SNARK;
+use ark_std::rand::{CryptoRng, RngCore};
+
+// Groth16 over BLS12-381. The scalar field of BLS12-381 is the base field of
+// the Jubjub curve the hashes live on, i.e. exactly `ConstraintF`, so every
+// circuit in this crate can be proven here without any field emulation.
+//
+// `setup`, `prove` and `verify` take any circuit; the `_membership` functions
+// below are the same for `MTreeVerification`, with its inputs as a root, a
+// leaf and a path instead of a circuit and field elements.
+//
+// A proving key only depends on the shape of its circuit. `MTreeCircuit`
+// has its shape in its type, so the keys of `setup(MTreeCircuit::<D>::setup(..))`
+// prove every `WitnessAssignment` of depth `D`, and its proofs verify with
+// `verify_membership_proof`.
+
+pub type ProvingKey = ark_groth16::ProvingKey<Bls12_381>;
+pub type VerifyingKey = ark_groth16::VerifyingKey<Bls12_381>;
+pub type Proof = ark_groth16::Proof<Bls12_381>;
+
+/// Runs the circuit-specific setup. Only the shape of `circuit` matters, so a
+/// circuit filled with dummy witnesses of the right size is fine.
+#[cfg_attr(feature = "tracing", tracing::instrument(name = "snark_setup", skip_all))]
+pub fn setup<C, R>(circuit: C, rng: &mut R) -> Result<(ProvingKey, VerifyingKey), MerkleError>
+where
+    C: ConstraintSynthesizer<ConstraintF>,
+    R: RngCore + CryptoRng,
+{
+    Ok(Groth16::<Bls12_381>::circuit_specific_setup(circuit, rng)?)
+}
+
+/// Proves that `circuit` is satisfied by its witness.
+#[cfg_attr(feature = "tracing", tracing::instrument(name = "snark_prove", skip_all))]
+pub fn prove<C, R>(pk: &ProvingKey, circuit: C, rng: &mut R) -> Result<Proof, MerkleError>
+where
+    C: ConstraintSynthesizer<ConstraintF>,
+    R: RngCore + CryptoRng,
+{
+    Ok(Groth16::<Bls12_381>::prove(pk, circuit, rng)?)
+}
+
+/// Checks `proof` against the public inputs, given in the order the circuit allocates them.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "snark_verify",
+        skip_all,
+        fields(public_inputs = public_inputs.len(), result = tracing::field::Empty)
+    )
+)]
+pub fn verify(
+    vk: &VerifyingKey,
+    public_inputs: &[ConstraintF],
+    proof: &Proof,
+) -> Result<bool, MerkleError> {
+    let result = Groth16::<Bls12_381>::verify(vk, public_inputs, proof)?;
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("result", &result);
+    Ok(result)
+}
+
+/// The keys for membership proofs in trees of `tree_height` levels (leaves
+/// and root included) over leaves of `leaf_len` bytes.
+pub fn setup_groth16<R: RngCore + CryptoRng>(
+    params: &MerkleParams,
+    tree_height: usize,
+    leaf_len: usize,
+    rng: &mut R,
+) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+    setup(<MTreeVerification>::blank(params, tree_height, leaf_len), rng)
+}
+
+/// Proves in zero knowledge that `leaf` opens `path` against `root`,
+/// revealing the root and the leaf but not the path.
+pub fn prove_membership<R: RngCore + CryptoRng>(
+    pk: &ProvingKey,
+    params: &MerkleParams,
+    root: Root,
+    leaf: &[u8],
+    path: SimplePath,
+    rng: &mut R,
+) -> Result<Proof, MerkleError> {
+    prove(pk, <MTreeVerification>::new(params, root, leaf, path), rng)
+}
+
+/// Checks a proof made by `prove_membership` for `leaf` against `root`.
+pub fn verify_membership_proof(
+    vk: &VerifyingKey,
+    root: &Root,
+    leaf: &[u8],
+    proof: &Proof,
+) -> Result<bool, MerkleError> {
+    verify(vk, &PublicInputs::from_parts(root, leaf).to_field_elements(), proof)
+}
+
+#[test]
+fn test_membership_proof_round_trip() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let (pk, vk) = setup_groth16(&params, tree.height(), 8, &mut rng).unwrap();
+
+    let path = tree.generate_proof(1).unwrap();
+    let proof = prove_membership(&pk, &params, tree.root(), &leaves[1], path, &mut rng).unwrap();
+    assert!(verify_membership_proof(&vk, &tree.root(), &leaves[1], &proof).unwrap());
+    // the proof is bound to its leaf and root
+    assert!(!verify_membership_proof(&vk, &tree.root(), &leaves[2], &proof).unwrap());
+    assert!(!verify_membership_proof(&vk, &Root::from(1u64), &leaves[1], &proof).unwrap());
+}
+
+#[test]
+fn test_one_proving_key_for_every_assignment_of_a_shape() {
+    use crate::constraints::{MTreeCircuit, WitnessAssignment};
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 32]).collect();
+    let (pk, vk) = setup(MTreeCircuit::<2>::setup(&params), &mut rng).unwrap();
+
+    // two trees, and the same keys for every leaf of both
+    let reversed: Vec<Vec<u8>> = leaves.iter().rev().cloned().collect();
+    for leaves in [leaves, reversed].iter() {
+        let tree = params.build_tree(leaves).unwrap();
+        for (index, leaf) in leaves.iter().enumerate() {
+            let assignment = WitnessAssignment::new(tree.root(), leaf.clone(), tree.generate_proof(index).unwrap());
+            let proof = prove(&pk, MTreeCircuit::<2>::assign(&params, assignment).unwrap(), &mut rng).unwrap();
+            assert!(verify_membership_proof(&vk, &tree.root(), leaf, &proof).unwrap());
+        }
+    }
+}
+
+#[test]
+fn test_one_proving_key_for_trees_of_every_depth_up_to_a_bound() {
+    use crate::constraints::{max_depth_public_inputs, MaxDepthCircuit, WitnessAssignment};
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let (pk, vk) = setup(MaxDepthCircuit::<4>::setup(&params), &mut rng).unwrap();
+
+    for depth in 1..=4 {
+        let leaves: Vec<Vec<u8>> = (0..1u8 << depth).map(|i| vec![i; 32]).collect();
+        let tree = params.build_tree(&leaves).unwrap();
+        let assignment = WitnessAssignment::new(tree.root(), leaves[1].clone(), tree.generate_proof(1).unwrap());
+        let proof = prove(&pk, MaxDepthCircuit::<4>::assign(&params, assignment).unwrap(), &mut rng).unwrap();
+        assert!(verify(&vk, &max_depth_public_inputs(&tree.root(), depth, &leaves[1]), &proof).unwrap());
+        // the proof is for the depth of the tree
+        let other_depth = depth % 4 + 1;
+        assert!(!verify(&vk, &max_depth_public_inputs(&tree.root(), other_depth, &leaves[1]), &proof).unwrap());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::common::{to_hex, ConstraintF};
+use crate::inputs::PublicInputs;
+use crate::snark::{Proof, VerifyingKey};
+use crate::Root;
+use ark_bls12_381::{Fq, Fq2, G1Affine, G2Affine};
+use ark_ff::{BigInteger, FpParameters, PrimeField};
+use std::fmt::Write;
+
+// Checking the proofs of `snark` on the EVM.
+//
+// The proofs are Groth16 over BLS12-381, which the EIP-197 precompiles for
+// BN254 cannot check, and the curve can't be swapped for BN254: the hashes
+// live on Jubjub, whose base field is the scalar field of BLS12-381 (`bn254`
+// has the same trees over Baby Jubjub instead, for proofs over BN254). What
+// the EVM has for BLS12-381 are the EIP-2537 precompiles, so everything here is
+// in their encoding: a base field element is 64 big-endian bytes, the top 16
+// of them zero, a G1 point is `x || y`, a G2 point `x.c0 || x.c1 || y.c0 ||
+// y.c1`, and the point at infinity is all zeros.
+//
+// `verifier_contract` writes a contract with the key baked in. It computes
+// `vk_x` with G1MSM and checks
+// `e(A, B) · e(alpha, -beta) · e(vk_x, -gamma) · e(C, -delta) = 1` with one
+// call to the pairing precompile; the G2 points of the key are negated here,
+// so the contract never needs field arithmetic of its own. The JSON of
+// `verifying_key_json` and `proof_json` is snarkjs's, with decimal strings
+// and `"curve": "bls12381"`.
+
+/// The EIP-2537 address of G1MSM.
+pub const G1_MSM_PRECOMPILE: u8 = 0x0c;
+/// The EIP-2537 address of the pairing check.
+pub const PAIRING_PRECOMPILE: u8 = 0x0f;
+
+/// `a || b || c`, 512 bytes, the `proof` argument of the contract's `verifyProof`.
+pub fn proof_calldata(proof: &Proof) -> Vec<u8> {
+    let mut calldata = g1_bytes(&proof.a);
+    calldata.extend(g2_bytes(&proof.b));
+    calldata.extend(g1_bytes(&proof.c));
+    calldata
+}
+
+/// The public inputs as the big-endian `uint256`s of the `input` argument.
+pub fn input_words(inputs: &[ConstraintF]) -> Vec<[u8; 32]> {
+    inputs
+        .iter()
+        .map(|input| {
+            let mut word = [0u8; 32];
+            word.copy_from_slice(&input.into_repr().to_bytes_be());
+            word
+        })
+        .collect()
+}
+
+/// `input_words` for a proof of `snark::prove_membership`.
+pub fn membership_input_words(root: &Root, leaf: &[u8]) -> Vec<[u8; 32]> {
+    input_words(&PublicInputs::from_parts(root, leaf).to_field_elements())
+}
+
+/// The key in the layout of snarkjs's `verification_key.json`.
+pub fn verifying_key_json(vk: &VerifyingKey) -> String {
+    let ic: Vec<String> = vk.gamma_abc_g1.iter().map(g1_json).collect();
+    format!(
+        "{{\"protocol\":\"groth16\",\"curve\":\"bls12381\",\"nPublic\":{},\"vk_alpha_1\":{},\"vk_beta_2\":{},\"vk_gamma_2\":{},\"vk_delta_2\":{},\"IC\":[{}]}}",
+        vk.gamma_abc_g1.len() - 1,
+        g1_json(&vk.alpha_g1),
+        g2_json(&vk.beta_g2),
+        g2_json(&vk.gamma_g2),
+        g2_json(&vk.delta_g2),
+        ic.join(",")
+    )
+}
+
+/// The proof in the layout of snarkjs's `proof.json`.
+pub fn proof_json(proof: &Proof) -> String {
+    format!(
+        "{{\"protocol\":\"groth16\",\"curve\":\"bls12381\",\"pi_a\":{},\"pi_b\":{},\"pi_c\":{}}}",
+        g1_json(&proof.a),
+        g2_json(&proof.b),
+        g1_json(&proof.c)
+    )
+}
+
+/// Solidity source of a contract `name` with one function,
+/// `verifyProof(bytes calldata proof, uint256[] calldata input) returns (bool)`,
+/// that checks proofs made with the proving key of `vk`.
+pub fn verifier_contract(vk: &VerifyingKey, name: &str) -> String {
+    let inputs = vk.gamma_abc_g1.len() - 1;
+    let mut source = String::new();
+    // writing to a `String` can't fail
+    let _ = writeln!(source, "// SPDX-License-Identifier: UNLICENSED");
+    let _ = writeln!(source, "// Generated by merkle_tree_tutorial::solidity. Needs the EIP-2537 precompiles.");
+    let _ = writeln!(source, "pragma solidity ^0.8.0;\n");
+    let _ = writeln!(source, "contract {} {{", name);
+    let _ = writeln!(
+        source,
+        "    uint256 constant SCALAR_FIELD = 0x{};",
+        to_hex(&<ConstraintF as PrimeField>::Params::MODULUS.to_bytes_be())
+    );
+    let _ = writeln!(source, "    bytes constant ALPHA = hex\"{}\";", to_hex(&g1_bytes(&vk.alpha_g1)));
+    let _ = writeln!(source, "    bytes constant NEG_BETA = hex\"{}\";", to_hex(&g2_bytes(&-vk.beta_g2)));
+    let _ = writeln!(source, "    bytes constant NEG_GAMMA = hex\"{}\";", to_hex(&g2_bytes(&-vk.gamma_g2)));
+    let _ = writeln!(source, "    bytes constant NEG_DELTA = hex\"{}\";", to_hex(&g2_bytes(&-vk.delta_g2)));
+    for (i, point) in vk.gamma_abc_g1.iter().enumerate() {
+        let _ = writeln!(source, "    bytes constant IC{} = hex\"{}\";", i, to_hex(&g1_bytes(point)));
+    }
+    let _ = writeln!(source);
+    let _ = writeln!(
+        source,
+        "    function verifyProof(bytes calldata proof, uint256[] calldata input) external view returns (bool) {{"
+    );
+    let _ = writeln!(source, "        require(proof.length == 512, \"proof must be 512 bytes\");");
+    let _ = writeln!(source, "        require(input.length == {}, \"wrong number of inputs\");", inputs);
+    let _ = writeln!(source, "        for (uint256 i = 0; i < input.length; i++) {{");
+    let _ = writeln!(source, "            require(input[i] < SCALAR_FIELD, \"input out of field\");");
+    let _ = writeln!(source, "        }}");
+    let mut msm = String::from("IC0, uint256(1)");
+    for i in 0..inputs {
+        let _ = write!(msm, ", IC{}, input[{}]", i + 1, i);
+    }
+    let _ = writeln!(source, "        bytes memory msm = abi.encodePacked({});", msm);
+    let _ = writeln!(
+        source,
+        "        (bool ok, bytes memory vkX) = address({:#04x}).staticcall(msm);",
+        G1_MSM_PRECOMPILE
+    );
+    let _ = writeln!(source, "        if (!ok || vkX.length != 128) return false;");
+    let _ = writeln!(
+        source,
+        "        bytes memory pairing = abi.encodePacked(proof[0:128], proof[128:384], ALPHA, NEG_BETA, vkX, NEG_GAMMA, proof[384:512], NEG_DELTA);"
+    );
+    let _ = writeln!(
+        source,
+        "        (bool paired, bytes memory result) = address({:#04x}).staticcall(pairing);",
+        PAIRING_PRECOMPILE
+    );
+    let _ = writeln!(source, "        return paired && result.length == 32 && abi.decode(result, (uint256)) == 1;");
+    let _ = writeln!(source, "    }}");
+    let _ = writeln!(source, "}}");
+    source
+}
+
+fn fq_bytes(x: &Fq) -> Vec<u8> {
+    let mut bytes = vec![0u8; 16];
+    bytes.extend(x.into_repr().to_bytes_be());
+    bytes
+}
+
+fn fq2_bytes(x: &Fq2) -> Vec<u8> {
+    let mut bytes = fq_bytes(&x.c0);
+    bytes.extend(fq_bytes(&x.c1));
+    bytes
+}
+
+fn g1_bytes(point: &G1Affine) -> Vec<u8> {
+    if point.infinity {
+        return vec![0u8; 128];
+    }
+    let mut bytes = fq_bytes(&point.x);
+    bytes.extend(fq_bytes(&point.y));
+    bytes
+}
+
+fn g2_bytes(point: &G2Affine) -> Vec<u8> {
+    if point.infinity {
+        return vec![0u8; 256];
+    }
+    let mut bytes = fq2_bytes(&point.x);
+    bytes.extend(fq2_bytes(&point.y));
+    bytes
+}
+
+// The big-endian number `bytes` in decimal.
+fn decimal(bytes: &[u8]) -> String {
+    let mut number = bytes.to_vec();
+    let mut digits = Vec::new();
+    while number.iter().any(|&b| b != 0) {
+        // long division by 10
+        let mut remainder = 0u32;
+        for byte in number.iter_mut() {
+            let value = (remainder << 8) | *byte as u32;
+            *byte = (value / 10) as u8;
+            remainder = value % 10;
+        }
+        digits.push(b'0' + remainder as u8);
+    }
+    if digits.is_empty() {
+        digits.push(b'0');
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
+}
+
+fn fq_json(x: &Fq) -> String {
+    format!("\"{}\"", decimal(&x.into_repr().to_bytes_be()))
+}
+
+// Projective coordinates, as snarkjs keeps them; infinity is (0, 1, 0).
+fn g1_json(point: &G1Affine) -> String {
+    if point.infinity {
+        return "[\"0\",\"1\",\"0\"]".to_string();
+    }
+    format!("[{},{},\"1\"]", fq_json(&point.x), fq_json(&point.y))
+}
+
+fn g2_json(point: &G2Affine) -> String {
+    if point.infinity {
+        return "[[\"0\",\"0\"],[\"1\",\"0\"],[\"0\",\"0\"]]".to_string();
+    }
+    format!(
+        "[[{},{}],[{},{}],[\"1\",\"0\"]]",
+        fq_json(&point.x.c0),
+        fq_json(&point.x.c1),
+        fq_json(&point.y.c0),
+        fq_json(&point.y.c1)
+    )
+}
+
+#[cfg(test)]
+fn test_key(inputs: usize) -> VerifyingKey {
+    use ark_ec::AffineCurve;
+    VerifyingKey {
+        alpha_g1: G1Affine::prime_subgroup_generator(),
+        beta_g2: G2Affine::prime_subgroup_generator(),
+        gamma_g2: G2Affine::prime_subgroup_generator(),
+        delta_g2: G2Affine::prime_subgroup_generator(),
+        gamma_abc_g1: vec![G1Affine::prime_subgroup_generator(); inputs + 1],
+    }
+}
+
+#[test]
+fn test_decimal() {
+    assert_eq!(decimal(&[]), "0");
+    assert_eq!(decimal(&[0, 0]), "0");
+    assert_eq!(decimal(&[0x01, 0x00]), "256");
+    assert_eq!(decimal(&u64::MAX.to_be_bytes()), u64::MAX.to_string());
+}
+
+#[test]
+fn test_eip2537_encoding() {
+    use ark_ec::AffineCurve;
+    use ark_ff::Zero;
+    let generator = g1_bytes(&G1Affine::prime_subgroup_generator());
+    assert_eq!(generator.len(), 128);
+    assert_eq!(&generator[..16], &[0u8; 16]);
+    // the x coordinate of the BLS12-381 G1 generator
+    assert_eq!(
+        to_hex(&generator[16..64]),
+        "17f1d3a73197d7942695638c4fa9ac0fc3688c4f9774b905a14e3a3f171bac586c55e83ff97a1aeffb3af00adb22c6bb"
+    );
+    assert_eq!(g1_bytes(&G1Affine::zero()), vec![0u8; 128]);
+    assert_eq!(g2_bytes(&G2Affine::prime_subgroup_generator()).len(), 256);
+
+    let proof = Proof {
+        a: G1Affine::prime_subgroup_generator(),
+        b: G2Affine::prime_subgroup_generator(),
+        c: G1Affine::zero(),
+    };
+    let calldata = proof_calldata(&proof);
+    assert_eq!(calldata.len(), 512);
+    assert_eq!(&calldata[..128], &generator[..]);
+    assert_eq!(&calldata[384..], &[0u8; 128][..]);
+}
+
+#[test]
+fn test_input_words() {
+    let words = input_words(&[ConstraintF::from(1u64), ConstraintF::from(0x0102u64)]);
+    assert_eq!(words[0][31], 1);
+    assert_eq!(&words[1][30..], &[1, 2]);
+    assert!(words[1][..30].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn test_verifier_artifacts() {
+    let vk = test_key(3);
+    let json = verifying_key_json(&vk);
+    assert!(json.starts_with("{\"protocol\":\"groth16\",\"curve\":\"bls12381\",\"nPublic\":3,"));
+    // alpha and the four IC points, each closed by its z = "1"
+    assert_eq!(json.matches(",\"1\"]").count(), 1 + 4);
+
+    let contract = verifier_contract(&vk, "MembershipVerifier");
+    assert!(contract.contains("contract MembershipVerifier {"));
+    assert!(contract.contains("bytes constant IC3 = hex\""));
+    assert!(!contract.contains("IC4"));
+    assert!(contract.contains("require(input.length == 3,"));
+    assert!(contract.contains("IC0, uint256(1), IC1, input[0], IC2, input[1], IC3, input[2]"));
+    assert!(contract.contains("address(0x0c)") && contract.contains("address(0x0f)"));
+    // the negated generator differs from the generator only in y
+    let g2 = to_hex(&g2_bytes(&vk.beta_g2));
+    let negated = to_hex(&g2_bytes(&-vk.beta_g2));
+    assert_eq!(&g2[..256], &negated[..256]);
+    assert_ne!(g2, negated);
+}
@@ -0,0 +1,528 @@
+use crate::common::*;
+use crate::constraints::{blank_path, IndexedPathVar, MerkleTreeVar, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::byte_inputs;
+use crate::range::{enforce_bit_length, enforce_geq, enforce_gt};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, Namespace, SynthesisError};
+
+// Proof of solvency on top of the account tree.
+//
+// An exchange commits to a tree whose leaves are `user_id_hash || balance`
+// (32 bytes of keccak256 followed by the balance as 8 little-endian bytes).
+// A user can ask for a proof that their account is in the committed tree with
+// a balance of at least what they expect, and an auditor can ask for a proof
+// that a set of accounts sums to at least the published liabilities. Balances
+// are private witnesses in both cases.
+//
+// A proof of reserves covers the whole tree: `ReserveCircuit` takes every
+// leaf as a witness, rebuilds the tree from them in the circuit and shows
+// that its root is the committed one and that all balances add up to exactly
+// the published total, along with one user's account and balance. Every user
+// who checks their own proof against the same root and total knows their
+// balance is counted in that total, and that no account, hidden or not, was
+// left out of it; the padding accounts hold nothing.
+
+/// Size of an account leaf in bytes.
+pub const ACCOUNT_LEAF_LEN: usize = 40;
+
+// Balances are u64, so every comparison on a single balance is 64 bits wide.
+const BALANCE_BITS: usize = 64;
+
+/// One account as the exchange commits to it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Account {
+    pub user_id_hash: [u8; 32],
+    pub balance: u64,
+}
+
+impl Account {
+    pub fn new(user_id: &[u8], balance: u64) -> Self {
+        Account {
+            user_id_hash: user_id_hash(user_id),
+            balance,
+        }
+    }
+
+    /// The leaf bytes this account is committed as.
+    pub fn to_leaf_bytes(&self) -> Vec<u8> {
+        let mut leaf = self.user_id_hash.to_vec();
+        leaf.extend_from_slice(&self.balance.to_le_bytes());
+        leaf
+    }
+}
+
+/// Hashes a user identifier into the 32 bytes stored in the account leaf.
+pub fn user_id_hash(user_id: &[u8]) -> [u8; 32] {
+    keccak256(user_id)
+}
+
+/// The exchange side: owns the accounts and the committed tree.
+pub struct Exchange {
+    params: MerkleParams,
+    accounts: Vec<Account>,
+    tree: SimpleMerkleTree,
+}
+
+impl Exchange {
+    /// Commits to `accounts`. The tree is padded with zero-balance accounts up to
+    /// the next power of two, which cannot inflate any sum.
+    pub fn new(params: MerkleParams, accounts: Vec<Account>) -> Result<Self, MerkleError> {
+        let options = TreeOptions::for_capacity(accounts.len()).padding(PaddingStrategy::Zeros(ACCOUNT_LEAF_LEN));
+        let tree = params.build_tree_with_options(accounts.iter().map(Account::to_leaf_bytes), &options)?.tree;
+        Ok(Exchange {
+            params,
+            accounts,
+            tree,
+        })
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    pub fn accounts(&self) -> &[Account] {
+        &self.accounts
+    }
+
+    /// The sum of all balances, which `prove_reserves` shows the tree holds.
+    pub fn total_balance(&self) -> u128 {
+        self.accounts.iter().map(|account| account.balance as u128).sum()
+    }
+
+    // Every leaf of the tree, padding included, as an account.
+    fn padded_accounts(&self) -> Vec<Account> {
+        let mut accounts = self.accounts.clone();
+        accounts.resize(self.tree_len(), Account::default());
+        accounts
+    }
+
+    fn tree_len(&self) -> usize {
+        1 << (self.tree.height() - 1)
+    }
+
+    fn path(&self, index: usize) -> Result<SimplePath, MerkleError> {
+        let index = LeafIndex::checked_new(index as u64, self.accounts.len() as u64)?;
+        generate_proof(&self.tree, index)
+    }
+
+    /// Builds the circuit proving that the account at `index` holds at least
+    /// `threshold`. The threshold is chosen by the user, not the exchange.
+    pub fn prove_balance(
+        &self,
+        index: usize,
+        threshold: u64,
+    ) -> Result<BalanceThresholdCircuit, MerkleError> {
+        let auth_path = self.path(index)?;
+        let account = &self.accounts[index];
+        Ok(BalanceThresholdCircuit {
+            params: self.params.clone(),
+            root: self.root(),
+            user_id_hash: account.user_id_hash,
+            threshold,
+            balance: Some(account.balance),
+            auth_path: Some(auth_path),
+        })
+    }
+
+    /// Builds the circuit proving that the accounts at `indices` are distinct
+    /// leaves of the committed tree whose balances sum to at least `liability`.
+    pub fn prove_liabilities(
+        &self,
+        indices: &[usize],
+        liability: u64,
+    ) -> Result<LiabilitiesCircuit, MerkleError> {
+        let mut sorted = indices.to_vec();
+        sorted.sort_unstable();
+        if let Some(w) = sorted.windows(2).find(|w| w[0] == w[1]) {
+            return Err(MerkleError::DuplicateLeafIndex(w[0]));
+        }
+
+        let mut accounts = Vec::with_capacity(sorted.len());
+        for &index in &sorted {
+            let path = self.path(index)?;
+            accounts.push((self.accounts[index].clone(), path));
+        }
+        Ok(LiabilitiesCircuit {
+            params: self.params.clone(),
+            root: self.root(),
+            liability,
+            num_accounts: sorted.len(),
+            accounts: Some(accounts),
+        })
+    }
+
+    /// Builds the circuit proving that the account at `index` is in the tree
+    /// with its balance, and that all balances in the tree sum to
+    /// `total_balance`.
+    pub fn prove_reserves(&self, index: usize) -> Result<ReserveCircuit, MerkleError> {
+        let auth_path = self.path(index)?;
+        Ok(ReserveCircuit {
+            params: self.params.clone(),
+            root: self.root(),
+            total: self.total_balance(),
+            account: self.accounts[index].clone(),
+            num_leaves: self.tree_len(),
+            accounts: Some(self.padded_accounts()),
+            auth_path: Some(auth_path),
+        })
+    }
+}
+
+// Allocates a private balance and returns it both as leaf bytes and as a field element.
+fn balance_witness(
+    cs: impl Into<Namespace<ConstraintF>>,
+    balance: Option<u64>,
+) -> Result<(Vec<UInt8<ConstraintF>>, FpVar<ConstraintF>), SynthesisError> {
+    let balance = UInt64::new_witness(cs, || balance.ok_or(SynthesisError::AssignmentMissing))?;
+    let bits = balance.to_bits_le();
+    let bytes = bits.chunks(8).map(UInt8::from_bits_le).collect();
+    Ok((bytes, Boolean::le_bits_to_fp_var(&bits)?))
+}
+
+/// Proves that an account with a public `user_id_hash` is in the tree under
+/// `root` with a private balance of at least the public `threshold`.
+pub struct BalanceThresholdCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub user_id_hash: [u8; 32],
+    pub threshold: u64,
+
+    // These are the private witnesses to the circuit.
+    pub balance: Option<u64>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl ConstraintSynthesizer<ConstraintF> for BalanceThresholdCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let user_id_hash =
+            UInt8::new_input_vec(ark_relations::ns!(cs, "user_id_var"), &self.user_id_hash)?;
+        let threshold = FpVar::new_input(ark_relations::ns!(cs, "threshold_var"), || {
+            Ok(ConstraintF::from(self.threshold))
+        })?;
+
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        let (balance_bytes, balance) =
+            balance_witness(ark_relations::ns!(cs, "balance_var"), self.balance)?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        {
+            let _membership = ark_relations::ns!(cs, "membership");
+            let mut leaf_bytes = user_id_hash;
+            leaf_bytes.extend(balance_bytes);
+            let is_member = path.verify_membership(
+                &leaf_crh_params,
+                &two_to_one_crh_params,
+                &root,
+                &leaf_bytes.as_slice(),
+            )?;
+            is_member.enforce_equal(&Boolean::TRUE)?;
+        }
+
+        // the balance is range checked by its UInt64 decomposition; the threshold is
+        // checked here so the comparison stays sound whatever the verifier plugs in
+        let _threshold = ark_relations::ns!(cs, "threshold");
+        enforce_bit_length(&threshold, BALANCE_BITS)?;
+        enforce_geq(&balance, &threshold, BALANCE_BITS)?;
+
+        Ok(())
+    }
+}
+
+/// Proves that `num_accounts` distinct leaves of the tree under `root` have
+/// balances summing to at least the public `liability`, without revealing
+/// which accounts they are or what they hold.
+pub struct LiabilitiesCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub liability: u64,
+
+    // The shape of the circuit.
+    pub num_accounts: usize,
+
+    // This is the private witness to the circuit: the accounts sorted by leaf index.
+    pub accounts: Option<Vec<(Account, SimplePath)>>,
+}
+
+impl ConstraintSynthesizer<ConstraintF> for LiabilitiesCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let liability = FpVar::new_input(ark_relations::ns!(cs, "liability_var"), || {
+            Ok(ConstraintF::from(self.liability))
+        })?;
+
+        // the parameters are shared by every membership check, so allocate them once
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // a sum of k u64 balances needs 64 + log2(k) bits
+        let sum_bits = BALANCE_BITS + (self.num_accounts.next_power_of_two().trailing_zeros() as usize);
+
+        let mut total = FpVar::zero();
+        let mut previous_index: Option<FpVar<ConstraintF>> = None;
+        for i in 0..self.num_accounts {
+            let account = self.accounts.as_ref().map(|a| &a[i]);
+
+            let user_id_hash = UInt8::new_witness_vec(
+                ark_relations::ns!(cs, "user_id_var"),
+                &account.map_or([0u8; 32], |(a, _)| a.user_id_hash),
+            )?;
+            let (balance_bytes, balance) = balance_witness(
+                ark_relations::ns!(cs, "balance_var"),
+                account.map(|(a, _)| a.balance),
+            )?;
+            let path = IndexedPathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+                account.map(|(_, p)| p).ok_or(SynthesisError::AssignmentMissing)
+            })?;
+
+            {
+                let _membership = ark_relations::ns!(cs, "membership");
+                let mut leaf_bytes = user_id_hash;
+                leaf_bytes.extend(balance_bytes);
+                let is_member =
+                    path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &leaf_bytes)?;
+                is_member.enforce_equal(&Boolean::TRUE)?;
+            }
+
+            // strictly increasing leaf indices rule out counting one account twice
+            let _distinct = ark_relations::ns!(cs, "distinct");
+            let index = path.index()?;
+            if let Some(previous) = &previous_index {
+                enforce_gt(&index, previous, path.position.len())?;
+            }
+            previous_index = Some(index);
+
+            total += &balance;
+        }
+
+        let _total = ark_relations::ns!(cs, "total");
+        enforce_bit_length(&liability, BALANCE_BITS)?;
+        enforce_geq(&total, &liability, sum_bits)?;
+
+        Ok(())
+    }
+}
+
+/// Proves that the tree under `root` holds balances summing to exactly the
+/// public `total`, and that it holds the public `account`.
+pub struct ReserveCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub total: u128,
+    pub account: Account,
+
+    // The shape of the circuit: the number of leaves of the tree.
+    pub num_leaves: usize,
+
+    // These are the private witnesses to the circuit: every leaf of the tree,
+    // padding included, and the path of `account`.
+    pub accounts: Option<Vec<Account>>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl ReserveCircuit {
+    /// A circuit of the right shape for key generation, for trees of
+    /// `num_leaves` accounts.
+    pub fn blank(params: MerkleParams, num_leaves: usize) -> Self {
+        ReserveCircuit {
+            params,
+            root: Default::default(),
+            total: 0,
+            account: Account::default(),
+            num_leaves,
+            accounts: None,
+            auth_path: Some(blank_path(num_leaves.trailing_zeros() as usize + 1)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order: the root, the
+    /// total, then the account leaf bytes.
+    pub fn public_inputs(root: &Root, total: u128, account: &Account) -> Vec<ConstraintF> {
+        let mut inputs = vec![*root, ConstraintF::from(total)];
+        inputs.extend(byte_inputs(&account.to_leaf_bytes()));
+        inputs
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for ReserveCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let total = FpVar::new_input(ark_relations::ns!(cs, "total_var"), || Ok(ConstraintF::from(self.total)))?;
+        let account = UInt8::new_input_vec(ark_relations::ns!(cs, "account_var"), &self.account.to_leaf_bytes())?;
+
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // every leaf, with its balance as a field element to sum
+        let mut leaves = Vec::with_capacity(self.num_leaves);
+        let mut sum = FpVar::zero();
+        for i in 0..self.num_leaves {
+            let leaf = self.accounts.as_ref().map(|accounts| &accounts[i]);
+            let mut leaf_bytes = UInt8::new_witness_vec(
+                ark_relations::ns!(cs, "user_id_var"),
+                &leaf.map_or([0u8; 32], |a| a.user_id_hash),
+            )?;
+            let (balance_bytes, balance) =
+                balance_witness(ark_relations::ns!(cs, "balance_var"), leaf.map(|a| a.balance))?;
+            leaf_bytes.extend(balance_bytes);
+            leaves.push(leaf_bytes);
+            sum += &balance;
+        }
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // the leaves are those of the committed tree, and they hold the total
+        {
+            let _tree = ark_relations::ns!(cs, "tree");
+            MerkleTreeVar::new(&leaf_crh_params, &two_to_one_crh_params, &leaves)?
+                .root()
+                .enforce_equal(&root)?;
+        }
+        {
+            let _total = ark_relations::ns!(cs, "total");
+            sum.enforce_equal(&total)?;
+        }
+
+        let _membership = ark_relations::ns!(cs, "membership");
+        path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &account.as_slice())?
+            .enforce_equal(&Boolean::TRUE)
+    }
+}
+
+#[cfg(test)]
+fn test_exchange() -> Exchange {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let accounts = vec![
+        Account::new(b"alice", 100),
+        Account::new(b"bob", 2_500),
+        Account::new(b"carol", 0),
+        Account::new(b"dave", 40_000),
+        Account::new(b"erin", 7),
+    ];
+    Exchange::new(params, accounts).unwrap()
+}
+
+#[test]
+fn test_solvency_balance_threshold() {
+    use crate::circuit_report::assert_satisfied_with_report;
+
+    let exchange = test_exchange();
+
+    // bob holds exactly 2500, so both the exact amount and anything below it must prove
+    assert_satisfied_with_report(exchange.prove_balance(1, 2_500).unwrap());
+    assert_satisfied_with_report(exchange.prove_balance(1, 1).unwrap());
+    assert_satisfied_with_report(exchange.prove_balance(2, 0).unwrap());
+}
+
+#[test]
+fn test_solvency_understated_balance_fails() {
+    use crate::circuit_report::expect_unsatisfied;
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    // alice deposited 100, but the exchange only committed to 90
+    let accounts = vec![Account::new(b"alice", 90), Account::new(b"bob", 2_500)];
+    let exchange = Exchange::new(params, accounts).unwrap();
+
+    expect_unsatisfied(exchange.prove_balance(0, 100).unwrap(), "threshold");
+
+    // forging the witness balance doesn't help, the leaf no longer matches the root
+    let mut forged = exchange.prove_balance(0, 100).unwrap();
+    forged.balance = Some(100);
+    expect_unsatisfied(forged, "membership");
+}
+
+#[test]
+fn test_solvency_aggregate_liabilities() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let exchange = test_exchange();
+    let total: u64 = exchange.accounts().iter().map(|a| a.balance).sum();
+
+    assert_satisfied_with_report(exchange.prove_liabilities(&[0, 1, 2, 3, 4], total).unwrap());
+    expect_unsatisfied(exchange.prove_liabilities(&[0, 1, 2, 3, 4], total + 1).unwrap(), "total");
+    // a subset only covers its own sum
+    assert_satisfied_with_report(exchange.prove_liabilities(&[3, 1], 42_500).unwrap());
+    expect_unsatisfied(exchange.prove_liabilities(&[3, 1], 42_501).unwrap(), "total");
+
+    assert!(matches!(
+        exchange.prove_liabilities(&[3, 3], 0),
+        Err(MerkleError::DuplicateLeafIndex(3))
+    ));
+
+    // listing the same account twice in the witness is caught in-circuit
+    let mut circuit = exchange.prove_liabilities(&[1, 3], 0).unwrap();
+    let accounts = circuit.accounts.as_mut().unwrap();
+    accounts[0] = accounts[1].clone();
+    expect_unsatisfied(circuit, "distinct");
+}
+
+#[test]
+fn test_solvency_proof_of_reserves() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+    use crate::snark;
+
+    let exchange = test_exchange();
+    let total = exchange.total_balance();
+    assert_eq!(total, 42_607);
+    let circuit = |index: usize| exchange.prove_reserves(index).unwrap();
+    assert_satisfied_with_report(circuit(3));
+
+    // an understated total, or another balance for the account, doesn't prove
+    let mut low = circuit(3);
+    low.total -= 1;
+    expect_unsatisfied(low, "total");
+    let mut inflated = circuit(1);
+    inflated.account.balance += 1;
+    expect_unsatisfied(inflated, "membership");
+    // hiding an account from the sum changes the root
+    let mut hidden = circuit(1);
+    hidden.accounts.as_mut().unwrap()[3].balance = 0;
+    hidden.total -= 40_000;
+    expect_unsatisfied(hidden, "tree");
+
+    // the whole round: the exchange proves, each user verifies their account
+    let mut rng = ark_std::test_rng();
+    let (pk, vk) = snark::setup(ReserveCircuit::blank(exchange.params.clone(), 8), &mut rng).unwrap();
+    let proof = snark::prove(&pk, circuit(1), &mut rng).unwrap();
+    let bob = &exchange.accounts()[1];
+    let inputs = ReserveCircuit::public_inputs(&exchange.root(), total, bob);
+    assert!(snark::verify(&vk, &inputs, &proof).unwrap());
+    let inputs = ReserveCircuit::public_inputs(&exchange.root(), total + 1, bob);
+    assert!(!snark::verify(&vk, &inputs, &proof).unwrap());
+}
@@ -0,0 +1,282 @@
+use crate::backend::MerkleBackend;
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::{IndexedPathVar, RootVar};
+use crate::error::MerkleError;
+use crate::leaf::Leaf;
+use crate::level_tree::LevelTree;
+#[cfg(feature = "constraints")]
+use crate::range::enforce_gt;
+use crate::{Root, SimplePath};
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::fields::fp::FpVar;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::prelude::*;
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::vec::Vec;
+
+// Proofs that a value is *not* in a tree, by adjacency.
+//
+// A `SortedMerkleTree` holds u64 values in strictly increasing order, each
+// leaf the 8 little-endian bytes of its value. Leaf 0 is the lower bound 0
+// and every leaf after the last value is the upper bound `u64::MAX`, so every
+// value not in the tree lies strictly between two adjacent leaves, `low` at
+// some index and `high` at the next one. A non-membership proof is those two
+// leaves with their paths: both open against the root, their indices are
+// consecutive, and `low < value < high`. The bounds are reserved: 0 and
+// `u64::MAX` can be neither inserted nor proven absent.
+//
+// `NonMembershipCircuit` proves the same in zero knowledge, with `low`,
+// `high` and both paths as witnesses.
+
+/// The bytes of the leaf holding `value`.
+pub fn value_leaf(value: u64) -> Vec<u8> {
+    value.to_le_bytes().to_vec()
+}
+
+pub struct SortedMerkleTree {
+    values: Vec<u64>,
+    tree: LevelTree,
+}
+
+/// A proof that `value` lies strictly between the adjacent leaves `low` and `high`.
+#[derive(Clone)]
+pub struct NonMembershipProof {
+    pub value: u64,
+    pub low: u64,
+    pub high: u64,
+    pub low_path: SimplePath,
+    pub high_path: SimplePath,
+}
+
+impl SortedMerkleTree {
+    /// The tree over `values`, which must be strictly increasing and strictly
+    /// between the bounds.
+    pub fn new(params: MerkleParams, values: impl IntoIterator<Item = u64>) -> Result<Self, MerkleError> {
+        let mut sorted = SortedMerkleTree {
+            values: Vec::new(),
+            tree: LevelTree::with_capacity(params, 2, Leaf::from(value_leaf(u64::MAX)))?,
+        };
+        for value in values {
+            sorted.check_next(value)?;
+            sorted.values.push(value);
+        }
+        sorted.rebuild()?;
+        Ok(sorted)
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    pub fn values(&self) -> &[u64] {
+        &self.values
+    }
+
+    /// Appends `value`, which must be greater than every value in the tree.
+    pub fn insert(&mut self, value: u64) -> Result<Root, MerkleError> {
+        self.check_next(value)?;
+        // the leaf after the new one has to stay an upper bound
+        if self.values.len() + 2 >= self.tree.leaf_count() as usize {
+            self.values.push(value);
+            if let Err(e) = self.rebuild() {
+                self.values.pop();
+                return Err(e);
+            }
+            return Ok(self.root());
+        }
+        let root = self.tree.update(self.values.len() + 1, Leaf::from(value_leaf(value)))?;
+        self.values.push(value);
+        Ok(root)
+    }
+
+    /// Proves that `value` is not in the tree.
+    pub fn prove_non_membership(&self, value: u64) -> Result<NonMembershipProof, MerkleError> {
+        if value == 0 || value == u64::MAX {
+            return Err(MerkleError::ValueInTree);
+        }
+        // the values sit at leaves 1..=len, so the value below `value` is at `position`
+        let position = match self.values.binary_search(&value) {
+            Ok(_) => return Err(MerkleError::ValueInTree),
+            Err(position) => position,
+        };
+        let low = if position == 0 { 0 } else { self.values[position - 1] };
+        let high = self.values.get(position).copied().unwrap_or(u64::MAX);
+        Ok(NonMembershipProof {
+            value,
+            low,
+            high,
+            low_path: self.tree.prove(position)?,
+            high_path: self.tree.prove(position + 1)?,
+        })
+    }
+
+    fn check_next(&self, value: u64) -> Result<(), MerkleError> {
+        let last = self.values.last().copied().unwrap_or(0);
+        if value <= last || value == u64::MAX {
+            return Err(MerkleError::UnsortedValue { index: self.values.len() });
+        }
+        Ok(())
+    }
+
+    fn rebuild(&mut self) -> Result<(), MerkleError> {
+        // the lower bound, the values, and at least one upper bound
+        let capacity = (self.values.len() + 2).next_power_of_two();
+        let mut leaves = vec![value_leaf(0)];
+        leaves.extend(self.values.iter().map(|value| value_leaf(*value)));
+        leaves.resize(capacity, value_leaf(u64::MAX));
+        self.tree = LevelTree::new(self.tree.params().clone(), leaves)?;
+        Ok(())
+    }
+}
+
+/// Checks `proof` against `root`.
+pub fn verify_non_membership(params: &MerkleParams, root: &Root, proof: &NonMembershipProof) -> Result<bool, MerkleError> {
+    Ok(proof.low < proof.value
+        && proof.value < proof.high
+        && proof.low_path.leaf_index + 1 == proof.high_path.leaf_index
+        && params.verify_path(&proof.low_path, root, value_leaf(proof.low))?
+        && params.verify_path(&proof.high_path, root, value_leaf(proof.high))?)
+}
+
+/// Proves that `value` is not in the sorted tree with root `root`.
+#[cfg(feature = "constraints")]
+pub struct NonMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub value: u64,
+
+    // These are the private witnesses to the circuit.
+    pub low: Option<u64>,
+    pub high: Option<u64>,
+    pub low_path: Option<SimplePath>,
+    pub high_path: Option<SimplePath>,
+}
+
+#[cfg(feature = "constraints")]
+impl NonMembershipCircuit {
+    pub fn new(params: &MerkleParams, root: Root, proof: NonMembershipProof) -> Self {
+        NonMembershipCircuit {
+            params: params.clone(),
+            root,
+            value: proof.value,
+            low: Some(proof.low),
+            high: Some(proof.high),
+            low_path: Some(proof.low_path),
+            high_path: Some(proof.high_path),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(root: &Root, value: u64) -> Vec<ConstraintF> {
+        vec![*root, ConstraintF::from(value)]
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for NonMembershipCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let value = FpVar::new_input(ark_relations::ns!(cs, "value_var"), || {
+            Ok(ConstraintF::from(self.value))
+        })?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the two neighbours and their paths as private witnesses:
+        let low_leaf = UInt8::new_witness_vec(
+            ark_relations::ns!(cs, "low_var"),
+            &value_leaf(self.low.unwrap_or_default()),
+        )?;
+        let high_leaf = UInt8::new_witness_vec(
+            ark_relations::ns!(cs, "high_var"),
+            &value_leaf(self.high.unwrap_or_default()),
+        )?;
+        let low_path = IndexedPathVar::new_witness(ark_relations::ns!(cs, "low_path_var"), || {
+            self.low_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        let high_path = IndexedPathVar::new_witness(ark_relations::ns!(cs, "high_path_var"), || {
+            self.high_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        // both neighbours are in the tree, next to each other
+        low_path
+            .verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &low_leaf)?
+            .enforce_equal(&Boolean::TRUE)?;
+        high_path
+            .verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &high_leaf)?
+            .enforce_equal(&Boolean::TRUE)?;
+        (low_path.index()? + FpVar::one()).enforce_equal(&high_path.index()?)?;
+
+        // and the value lies strictly between them; all three are below 2^64
+        let low = Boolean::le_bits_to_fp_var(&low_leaf.to_bits_le()?)?;
+        let high = Boolean::le_bits_to_fp_var(&high_leaf.to_bits_le()?)?;
+        let _order = ark_relations::ns!(cs, "order");
+        enforce_gt(&value, &low, 64)?;
+        enforce_gt(&high, &value, 64)?;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_non_membership_proofs() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut tree = SortedMerkleTree::new(params.clone(), vec![10, 20, 30]).unwrap();
+
+    // below the smallest, between two, and above the largest value
+    for (value, low, high) in [(5, 0, 10), (25, 20, 30), (31, 30, u64::MAX)].iter().copied() {
+        let proof = tree.prove_non_membership(value).unwrap();
+        assert_eq!((proof.low, proof.high), (low, high));
+        assert!(verify_non_membership(&params, &tree.root(), &proof).unwrap());
+    }
+
+    // values in the tree, and the bounds, have no such proof
+    for value in [10, 30, 0, u64::MAX].iter().copied() {
+        assert!(matches!(tree.prove_non_membership(value), Err(MerkleError::ValueInTree)));
+    }
+    // a proof for 25 doesn't pass for 20
+    let mut proof = tree.prove_non_membership(25).unwrap();
+    proof.value = 20;
+    assert!(!verify_non_membership(&params, &tree.root(), &proof).unwrap());
+
+    // inserting keeps the order, and grows the tree when it runs out of bounds
+    assert!(matches!(tree.insert(30), Err(MerkleError::UnsortedValue { index: 3 })));
+    tree.insert(40).unwrap();
+    tree.insert(50).unwrap();
+    assert_eq!(tree.values(), &[10, 20, 30, 40, 50]);
+    let proof = tree.prove_non_membership(45).unwrap();
+    assert_eq!((proof.low, proof.high), (40, 50));
+    assert!(verify_non_membership(&params, &tree.root(), &proof).unwrap());
+    assert_eq!(tree.root(), SortedMerkleTree::new(params, vec![10, 20, 30, 40, 50]).unwrap().root());
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_non_membership_circuit() {
+    use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let tree = SortedMerkleTree::new(params.clone(), vec![10, 20, 30]).unwrap();
+    for value in [5, 25, 31].iter().copied() {
+        let proof = tree.prove_non_membership(value).unwrap();
+        assert_satisfied_with_report(NonMembershipCircuit::new(&params, tree.root(), proof));
+    }
+
+    // neighbours around 25 don't show that 20 is absent
+    let mut circuit = NonMembershipCircuit::new(&params, tree.root(), tree.prove_non_membership(25).unwrap());
+    circuit.value = 20;
+    expect_unsatisfied(circuit, "order");
+}
@@ -0,0 +1,319 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::{blank_path, IndexedPathVar, RootVar};
+use crate::error::{ErrorContext, MerkleError, ResultExt};
+use crate::index::LeafIndex;
+use crate::leaf::Leaf;
+use crate::{Root, SimplePath};
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::fields::fp::FpVar;
+#[cfg(feature = "constraints")]
+use ark_r1cs_std::prelude::*;
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::collections::BTreeMap;
+use ark_std::vec::Vec;
+
+// A tree of 2^depth leaves, nearly all of them empty.
+//
+// Every leaf starts out as `EMPTY_LEAF`, so every subtree without a set leaf
+// has the same root, which depends only on its level: `empty[level]`, computed
+// once up front. Only the nodes on the path of a set leaf are stored, one map
+// per level, and a node missing from its map is the empty one of its level.
+// Trees of depth 32 are as cheap as the leaves set in them.
+//
+// The digests are those of a dense tree over the same leaves, with the empty
+// ones spelled out, and the paths are plain `SimplePath`s: they verify with
+// `MerkleParams::verify_path` and in the membership circuits.
+//
+// A key is absent when its leaf is `EMPTY_LEAF`: `prove_non_membership` is
+// the path of that empty leaf, and `NonMembershipCircuit` proves it without
+// revealing the path, binding the path's position bits to the public key.
+// Inserting `EMPTY_LEAF` at a key is the same as never setting it.
+
+/// The leaf at every index nothing was inserted at.
+pub const EMPTY_LEAF: &[u8] = b"";
+
+/// The deepest tree `SparseMerkleTree::new` builds.
+pub const MAX_SPARSE_DEPTH: usize = 32;
+
+#[derive(Clone)]
+pub struct SparseMerkleTree {
+    params: MerkleParams,
+    depth: usize,
+    leaves: BTreeMap<u64, Leaf>,
+    // nodes[0] are the leaf hashes, nodes[depth] holds at most the root
+    nodes: Vec<BTreeMap<u64, Root>>,
+    // empty[level] is the root of an empty subtree at `level`
+    empty: Vec<Root>,
+}
+
+impl SparseMerkleTree {
+    /// An empty tree of 2^depth leaves, `depth` from 1 to `MAX_SPARSE_DEPTH`.
+    pub fn new(params: MerkleParams, depth: usize) -> Result<Self, MerkleError> {
+        assert!(
+            (1..=MAX_SPARSE_DEPTH).contains(&depth),
+            "a sparse tree is 1 to {} levels deep",
+            MAX_SPARSE_DEPTH
+        );
+        let mut empty = vec![params.hash_leaf(EMPTY_LEAF)?];
+        for level in 1..=depth {
+            let below = &empty[level - 1];
+            empty.push(params.hash_children(below, below)?);
+        }
+        Ok(SparseMerkleTree {
+            params,
+            depth,
+            leaves: BTreeMap::new(),
+            nodes: vec![BTreeMap::new(); depth + 1],
+            empty,
+        })
+    }
+
+    /// The number of levels above the leaves.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The number of leaves that were set.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn root(&self) -> Root {
+        self.node(self.depth, 0)
+    }
+
+    pub fn params(&self) -> &MerkleParams {
+        &self.params
+    }
+
+    /// The leaf at `index`, if one was set there.
+    pub fn leaf(&self, index: u64) -> Option<&Leaf> {
+        self.leaves.get(&index)
+    }
+
+    /// Sets the leaf at `index`, rehashing its path, and returns the leaf
+    /// that was there before, if any.
+    pub fn insert(&mut self, index: u64, leaf: &[u8]) -> Result<Option<Leaf>, MerkleError> {
+        let index = self.check_index(index)?.get();
+        // the whole path is hashed before any of it is stored, so a failed
+        // insert leaves the tree as it was
+        let mut path = Vec::with_capacity(self.depth + 1);
+        path.push(
+            self.params
+                .hash_leaf(leaf)
+                .with_context(|| ErrorContext::new("sparse_insert").index(index as usize))?,
+        );
+        for level in 1..=self.depth {
+            let child = index >> (level - 1);
+            let sibling = self.node(level - 1, child ^ 1);
+            let current = &path[level - 1];
+            let (left, right) = if child & 1 == 1 { (&sibling, current) } else { (current, &sibling) };
+            path.push(self.params.hash_children(left, right)?);
+        }
+        for (level, node) in path.into_iter().enumerate() {
+            self.nodes[level].insert(index >> level, node);
+        }
+        Ok(self.leaves.insert(index, Leaf::from(leaf)))
+    }
+
+    /// The path of the leaf at `index`; for an index nothing was set at, it
+    /// opens `EMPTY_LEAF`.
+    pub fn generate_proof(&self, index: u64) -> Result<SimplePath, MerkleError> {
+        let index = self.check_index(index)?.get();
+        Ok(SimplePath {
+            leaf_sibling_hash: self.node(0, index ^ 1),
+            // top-down, and neither the leaf level nor the root
+            auth_path: (1..self.depth)
+                .rev()
+                .map(|level| self.node(level, (index >> level) ^ 1))
+                .collect(),
+            leaf_index: index as usize,
+        })
+    }
+
+    /// The path of the empty leaf at `key`, proving nothing is set there.
+    /// Fails with `ValueInTree` if something is.
+    pub fn prove_non_membership(&self, key: u64) -> Result<SimplePath, MerkleError> {
+        if self.leaf(key).map_or(false, |leaf| leaf.as_ref() != EMPTY_LEAF) {
+            return Err(MerkleError::ValueInTree);
+        }
+        self.generate_proof(key)
+    }
+
+    fn node(&self, level: usize, index: u64) -> Root {
+        self.nodes[level].get(&index).copied().unwrap_or(self.empty[level])
+    }
+
+    fn check_index(&self, index: u64) -> Result<LeafIndex, MerkleError> {
+        LeafIndex::checked_new(index, 1 << self.depth)
+    }
+}
+
+/// Checks that `path` opens `EMPTY_LEAF` at `key` against `root`.
+pub fn verify_non_membership(
+    params: &MerkleParams,
+    root: &Root,
+    key: u64,
+    path: &SimplePath,
+) -> Result<bool, MerkleError> {
+    Ok(path.leaf_index as u64 == key && params.verify_path(path, root, EMPTY_LEAF)?)
+}
+
+/// Proves "nothing is set at `key` in the sparse tree with root `root`".
+#[cfg(feature = "constraints")]
+pub struct NonMembershipCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub key: u64,
+
+    // This is the private witness to the circuit.
+    pub auth_path: Option<SimplePath>,
+}
+
+#[cfg(feature = "constraints")]
+impl NonMembershipCircuit {
+    /// The circuit for `key` of `tree`; fails if something is set there.
+    pub fn new(tree: &SparseMerkleTree, key: u64) -> Result<Self, MerkleError> {
+        Ok(NonMembershipCircuit {
+            params: tree.params.clone(),
+            root: tree.root(),
+            key,
+            auth_path: Some(tree.prove_non_membership(key)?),
+        })
+    }
+
+    /// A circuit of the right shape for key generation, for trees of `depth`.
+    pub fn blank(params: MerkleParams, depth: usize) -> Self {
+        NonMembershipCircuit {
+            params,
+            root: Default::default(),
+            key: 0,
+            auth_path: Some(blank_path(depth + 1)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order: the root, then the key.
+    pub fn public_inputs(root: &Root, key: u64) -> Vec<ConstraintF> {
+        vec![*root, ConstraintF::from(key)]
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl ConstraintSynthesizer<ConstraintF> for NonMembershipCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<ConstraintF>) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let key = FpVar::new_input(ark_relations::ns!(cs, "key_var"), || Ok(ConstraintF::from(self.key)))?;
+
+        // Then, we allocate the public parameters, and the empty leaf, as constants:
+        let leaf_crh_params = LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+        let empty_leaf = UInt8::constant_vec(EMPTY_LEAF);
+
+        // Finally, we allocate our path, with its position bits, as a private witness:
+        let path = IndexedPathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let _absence = ark_relations::ns!(cs, "absence");
+        path.verify_membership(&leaf_crh_params, &two_to_one_crh_params, &root, &empty_leaf)?
+            .enforce_equal(&Boolean::TRUE)?;
+        // the empty leaf is the one at the key, not just any
+        let _position = ark_relations::ns!(cs, "position");
+        path.index()?.enforce_equal(&key)
+    }
+}
+
+#[test]
+fn test_sparse_tree_matches_the_dense_tree() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut sparse = SparseMerkleTree::new(params.clone(), 3).unwrap();
+    let mut leaves = vec![EMPTY_LEAF.to_vec(); 8];
+    assert_eq!(sparse.root(), params.build_tree(&leaves).unwrap().root());
+
+    for (index, value) in [(5u64, 1u8), (0, 2), (6, 3)].iter().copied() {
+        leaves[index as usize] = vec![value; 4];
+        assert!(sparse.insert(index, &leaves[index as usize]).unwrap().is_none());
+    }
+    // overwriting hands back what was there
+    leaves[5] = vec![9; 4];
+    assert_eq!(sparse.insert(5, &leaves[5]).unwrap(), Some(Leaf::from(vec![1u8; 4])));
+    assert_eq!(sparse.len(), 3);
+
+    let dense = params.build_tree(&leaves).unwrap();
+    assert_eq!(sparse.root(), dense.root());
+    // set and empty leaves alike
+    for index in 0..8 {
+        let path = sparse.generate_proof(index).unwrap();
+        let expected = dense.generate_proof(index as usize).unwrap();
+        assert_eq!(path.leaf_sibling_hash, expected.leaf_sibling_hash);
+        assert_eq!(path.auth_path, expected.auth_path);
+        assert!(params.verify_path(&path, &sparse.root(), &leaves[index as usize]).unwrap());
+    }
+    assert!(params.verify_path(&sparse.generate_proof(3).unwrap(), &sparse.root(), EMPTY_LEAF).unwrap());
+    assert!(matches!(
+        sparse.insert(8, &[1]),
+        Err(MerkleError::LeafIndexOutOfRange { index: 8, len: 8 })
+    ));
+}
+
+#[test]
+fn test_sparse_tree_of_depth_32() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut sparse = SparseMerkleTree::new(params.clone(), MAX_SPARSE_DEPTH).unwrap();
+    let last = (1u64 << 32) - 1;
+    sparse.insert(last, b"last").unwrap();
+    sparse.insert(1 << 31, b"middle").unwrap();
+
+    let path = sparse.generate_proof(last).unwrap();
+    assert_eq!(path.auth_path.len(), MAX_SPARSE_DEPTH - 1);
+    assert!(params.verify_path(&path, &sparse.root(), &b"last"[..]).unwrap());
+    assert!(params.verify_path(&sparse.generate_proof(7).unwrap(), &sparse.root(), EMPTY_LEAF).unwrap());
+    assert!(sparse.generate_proof(1 << 32).is_err());
+}
+
+#[test]
+fn test_non_membership() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut tree = SparseMerkleTree::new(params.clone(), 16).unwrap();
+    tree.insert(300, b"alice").unwrap();
+    tree.insert(301, b"bob").unwrap();
+
+    let path = tree.prove_non_membership(302).unwrap();
+    assert!(verify_non_membership(&params, &tree.root(), 302, &path).unwrap());
+    // the path of one absent key says nothing about another
+    assert!(!verify_non_membership(&params, &tree.root(), 303, &path).unwrap());
+    assert!(matches!(tree.prove_non_membership(301), Err(MerkleError::ValueInTree)));
+    // the path of a set key doesn't open the empty leaf
+    let member = tree.generate_proof(301).unwrap();
+    assert!(!verify_non_membership(&params, &tree.root(), 301, &member).unwrap());
+
+    #[cfg(feature = "constraints")]
+    {
+        use crate::circuit_report::{assert_satisfied_with_report, expect_unsatisfied};
+
+        let circuit = NonMembershipCircuit::new(&tree, 302).unwrap();
+        assert_eq!(NonMembershipCircuit::public_inputs(&tree.root(), 302).len(), 2);
+        assert_satisfied_with_report(circuit);
+
+        // an empty leaf elsewhere doesn't prove the key absent
+        let mut elsewhere = NonMembershipCircuit::new(&tree, 302).unwrap();
+        elsewhere.key = 301;
+        expect_unsatisfied(elsewhere, "position");
+        // nor does a set leaf's path
+        let mut set = NonMembershipCircuit::new(&tree, 302).unwrap();
+        set.key = 301;
+        set.auth_path = Some(member);
+        expect_unsatisfied(set, "absence");
+    }
+}
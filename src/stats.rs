@@ -0,0 +1,138 @@
+#[cfg(feature = "constraints")]
+use crate::common::MerkleParams;
+#[cfg(feature = "constraints")]
+use crate::constraints::{LeafInput, MTreeVerification, MerkleCircuitConfig, MerkleTreeVerification};
+#[cfg(feature = "constraints")]
+use crate::error::MerkleError;
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::merkle_tree::{Config, MerkleTree};
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+use ark_std::mem::size_of;
+
+// What a tree and its membership circuit cost, to pick a tree height with.
+//
+// A tree of height h (levels, leaves and root included, like
+// `MerkleTree::height`) has 2^(h-1) leaves and keeps 2^h - 1 digests: one
+// per leaf and one per internal node. `TreeStats::for_height` works that out
+// without building anything; `TreeStatsExt::stats` does it for a tree at
+// hand. The memory is that of the digests, which grows with the tree; the
+// hash parameters the tree keeps a copy of are the same at every height.
+//
+// A circuit's size is only known once its constraints are generated, so
+// `MerkleTreeVerification::stats` generates them, and `blank_stats` does it
+// for a blank circuit of a given shape, with nothing else to build.
+
+/// The size of a tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TreeStats {
+    /// Levels, leaves and root included.
+    pub height: usize,
+    pub leaf_count: u64,
+    /// The digests the tree keeps, leaf digests included.
+    pub node_count: u64,
+    /// The bytes of those digests.
+    pub memory_bytes: u64,
+}
+
+impl TreeStats {
+    /// The size of a `P` tree of `height` levels, at least 2, fewer than 64.
+    pub fn for_height<P: Config>(height: usize) -> Self {
+        assert!((2..64).contains(&height), "a tree has 2 to 63 levels");
+        let leaf_count = 1u64 << (height - 1);
+        let leaf_digest = size_of::<<P::LeafHash as CRH>::Output>() as u64;
+        let node_digest = size_of::<<P::TwoToOneHash as TwoToOneCRH>::Output>() as u64;
+        TreeStats {
+            height,
+            leaf_count,
+            node_count: 2 * leaf_count - 1,
+            memory_bytes: leaf_count * leaf_digest + (leaf_count - 1) * node_digest,
+        }
+    }
+}
+
+/// `TreeStats` of a tree built already.
+pub trait TreeStatsExt {
+    fn stats(&self) -> TreeStats;
+}
+
+impl<P: Config> TreeStatsExt for MerkleTree<P> {
+    fn stats(&self) -> TreeStats {
+        TreeStats::for_height::<P>(self.height())
+    }
+}
+
+/// The size of a circuit.
+#[cfg(feature = "constraints")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitStats {
+    pub constraints: usize,
+    pub witness_variables: usize,
+    /// The field elements a verifier passes, without the constant one.
+    pub public_inputs: usize,
+}
+
+#[cfg(feature = "constraints")]
+impl<C: MerkleCircuitConfig, L: LeafInput<C::ConstraintF>> MerkleTreeVerification<C, L>
+where
+    L::Value: Clone,
+{
+    /// Generates the constraints of a copy of this circuit and counts them.
+    pub fn stats(&self) -> Result<CircuitStats, MerkleError> {
+        let circuit = MerkleTreeVerification::<C, L> {
+            leaf_crh_params: self.leaf_crh_params.clone(),
+            two_to_one_crh_params: self.two_to_one_crh_params.clone(),
+            root: self.root.clone(),
+            leaf: self.leaf.clone(),
+            auth_path: self.auth_path.clone(),
+        };
+        let cs = ConstraintSystem::<C::ConstraintF>::new_ref();
+        circuit.generate_constraints(cs.clone())?;
+        Ok(CircuitStats {
+            constraints: cs.num_constraints(),
+            witness_variables: cs.num_witness_variables(),
+            public_inputs: cs.num_instance_variables() - 1,
+        })
+    }
+}
+
+#[cfg(feature = "constraints")]
+impl MTreeVerification {
+    /// The size of the membership circuit for a tree of `tree_height` levels
+    /// and a leaf of `leaf_len` bytes.
+    pub fn blank_stats(params: &MerkleParams, tree_height: usize, leaf_len: usize) -> Result<CircuitStats, MerkleError> {
+        Self::blank(params, tree_height, leaf_len).stats()
+    }
+}
+
+#[test]
+fn test_tree_stats() {
+    use crate::common::MerkleParams;
+    use crate::MerkleConfig;
+
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let stats = params.build_tree(&leaves).unwrap().stats();
+    assert_eq!(stats, TreeStats::for_height::<MerkleConfig>(4));
+    assert_eq!((stats.leaf_count, stats.node_count), (8, 15));
+    assert_eq!(stats.memory_bytes, 15 * size_of::<crate::Root>() as u64);
+    // a level more doubles the tree
+    assert_eq!(TreeStats::for_height::<MerkleConfig>(5).node_count, 31);
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_circuit_stats() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let small = <MTreeVerification>::blank_stats(&params, 3, 8).unwrap();
+    let tall = <MTreeVerification>::blank_stats(&params, 6, 8).unwrap();
+    assert!(tall.constraints > small.constraints);
+    // the root and one input per leaf byte, whatever the height
+    assert_eq!(small.public_inputs, <MTreeVerification>::blank(&params, 3, 8).public_inputs().len());
+    assert_eq!(small.public_inputs, tall.public_inputs);
+
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let circuit = <MTreeVerification>::new(&params, tree.root(), leaves[1].clone(), tree.generate_proof(1).unwrap());
+    assert_eq!(circuit.stats().unwrap(), small);
+}
@@ -0,0 +1,356 @@
+use crate::backend::MerkleBackend;
+use crate::common::*;
+use crate::error::{ErrorContext, MerkleError, ResultExt};
+use crate::index::LeafIndex;
+use crate::leaf::Leaf;
+use crate::level_tree::LevelTree;
+use crate::{Root, SimplePath};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Trees whose nodes live in a store instead of in memory.
+//
+// A `NodeStore` holds every digest of a tree, addressed like
+// `LevelTree::node`: the leaf hashes at level 0, the root alone at level
+// `depth()`. `StoredTree` is a `MerkleBackend` on top of one. It reads only
+// the nodes it needs, so a path costs `depth()` reads and an update as many
+// reads and writes, and it keeps nothing of the tree but its root. Leaves
+// aren't stored, only their hashes; the caller keeps the leaves it wants to
+// open.
+//
+// `FileStore` keeps the digests in one file and reads them on demand. Writes
+// are held back in memory until `flush`, which writes them in place; a store
+// dropped without it loses them. The file is the magic `MTNS`, the format
+// version and the depth as a u64, then the digests level by level from the
+// leaf hashes up, each in its canonical encoding. Every digest has the same
+// size, so the depth alone gives the offset of each node.
+
+/// The first bytes of a `FileStore`.
+pub const NODE_STORE_MAGIC: [u8; 4] = *b"MTNS";
+
+/// The version of the format `FileStore` writes.
+pub const NODE_STORE_FORMAT_VERSION: u8 = 1;
+
+// magic, version and depth
+const HEADER_LEN: u64 = 4 + 1 + 8;
+
+/// Where the digests of a tree are kept.
+pub trait NodeStore {
+    /// The number of levels above the leaves.
+    fn depth(&self) -> usize;
+
+    /// The node at `index` of `level`, counting levels from the leaves at 0.
+    fn node(&self, level: usize, index: usize) -> Result<Root, MerkleError>;
+
+    fn set_node(&mut self, level: usize, index: usize, digest: Root) -> Result<(), MerkleError>;
+
+    /// Makes every `set_node` so far durable.
+    fn flush(&mut self) -> Result<(), MerkleError>;
+}
+
+/// A store in memory, for tests and small trees.
+#[derive(Clone)]
+pub struct MemoryStore {
+    levels: Vec<Vec<Root>>,
+}
+
+impl MemoryStore {
+    /// A store of `depth` levels above the leaves, every node the default digest.
+    pub fn new(depth: usize) -> Self {
+        MemoryStore {
+            levels: (0..=depth).map(|level| vec![Root::default(); 1 << (depth - level)]).collect(),
+        }
+    }
+}
+
+impl NodeStore for MemoryStore {
+    fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    fn node(&self, level: usize, index: usize) -> Result<Root, MerkleError> {
+        Ok(self.levels[level][index])
+    }
+
+    fn set_node(&mut self, level: usize, index: usize, digest: Root) -> Result<(), MerkleError> {
+        self.levels[level][index] = digest;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), MerkleError> {
+        Ok(())
+    }
+}
+
+/// A store in a file, read lazily and written back on `flush`.
+pub struct FileStore {
+    file: File,
+    depth: usize,
+    digest_len: u64,
+    // written since the last flush, by (level, index)
+    dirty: BTreeMap<(usize, usize), Root>,
+}
+
+impl FileStore {
+    /// Creates the file at `path`, replacing any, for a tree of `depth`
+    /// levels above the leaves, every node the zero digest.
+    pub fn create<P: AsRef<Path>>(path: P, depth: usize) -> Result<Self, MerkleError> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.write_all(&NODE_STORE_MAGIC)?;
+        file.write_all(&[NODE_STORE_FORMAT_VERSION])?;
+        (depth as u64).serialize(&mut file)?;
+        let store = FileStore {
+            file,
+            depth,
+            digest_len: Root::default().serialized_size() as u64,
+            dirty: BTreeMap::new(),
+        };
+        store.file.set_len(store.offset(depth + 1, 0))?;
+        Ok(store)
+    }
+
+    /// Opens a store written by `create` and `flush`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, MerkleError> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut header = [0u8; 5];
+        file.read_exact(&mut header)?;
+        if header[..4] != NODE_STORE_MAGIC || header[4] != NODE_STORE_FORMAT_VERSION {
+            return Err(SerializationError::InvalidData.into());
+        }
+        let depth = u64::deserialize(&mut file)? as usize;
+        if depth == 0 || depth >= 64 {
+            return Err(SerializationError::InvalidData.into());
+        }
+        let store = FileStore {
+            file,
+            depth,
+            digest_len: Root::default().serialized_size() as u64,
+            dirty: BTreeMap::new(),
+        };
+        if store.file.metadata()?.len() != store.offset(depth + 1, 0) {
+            return Err(SerializationError::InvalidData.into());
+        }
+        Ok(store)
+    }
+
+    // The offset of a node in the file. Level `l` holds `2^(depth - l)` nodes.
+    fn offset(&self, level: usize, index: usize) -> u64 {
+        let below: u64 = (0..level).map(|l| 1u64 << (self.depth - l)).sum();
+        HEADER_LEN + (below + index as u64) * self.digest_len
+    }
+}
+
+impl NodeStore for FileStore {
+    fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn node(&self, level: usize, index: usize) -> Result<Root, MerkleError> {
+        if let Some(digest) = self.dirty.get(&(level, index)) {
+            return Ok(*digest);
+        }
+        // `&File` reads and seeks, so lookups don't need the store mutably
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.offset(level, index)))?;
+        Ok(Root::deserialize(file)?)
+    }
+
+    fn set_node(&mut self, level: usize, index: usize, digest: Root) -> Result<(), MerkleError> {
+        self.dirty.insert((level, index), digest);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), MerkleError> {
+        for (&(level, index), digest) in &self.dirty {
+            self.file.seek(SeekFrom::Start(self.offset(level, index)))?;
+            digest.serialize(&mut self.file)?;
+        }
+        self.file.sync_data()?;
+        self.dirty.clear();
+        Ok(())
+    }
+}
+
+/// A tree whose nodes are kept in a `NodeStore`.
+pub struct StoredTree<S> {
+    params: MerkleParams,
+    store: S,
+    root: Root,
+}
+
+impl<S: NodeStore> StoredTree<S> {
+    /// Builds the tree over `leaves` into `store`, padding them with empty
+    /// leaves up to its `2^depth`.
+    pub fn build<L: Into<Leaf>>(
+        params: MerkleParams,
+        leaves: impl IntoIterator<Item = L>,
+        mut store: S,
+    ) -> Result<Self, MerkleError> {
+        let capacity = 1usize << store.depth();
+        let mut leaves: Vec<Leaf> = leaves.into_iter().map(Into::into).collect();
+        if leaves.len() > capacity {
+            return Err(MerkleError::CapacityExceeded { len: leaves.len(), capacity });
+        }
+        leaves.resize(capacity, Leaf::default());
+        let tree = LevelTree::new(params, leaves)?;
+        for (level, nodes) in tree.levels().enumerate() {
+            for (index, digest) in nodes.iter().enumerate() {
+                store.set_node(level, index, *digest)?;
+            }
+        }
+        Ok(StoredTree {
+            params: tree.params().clone(),
+            root: tree.root(),
+            store,
+        })
+    }
+
+    /// The tree already in `store`, built with `params`.
+    pub fn from_store(params: MerkleParams, store: S) -> Result<Self, MerkleError> {
+        let root = store.node(store.depth(), 0)?;
+        Ok(StoredTree { params, store, root })
+    }
+
+    /// Makes the updates so far durable, see `NodeStore::flush`.
+    pub fn flush(&mut self) -> Result<(), MerkleError> {
+        self.store.flush()
+    }
+
+    pub fn params(&self) -> &MerkleParams {
+        &self.params
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn depth(&self) -> usize {
+        self.store.depth()
+    }
+
+    fn check_index(&self, index: usize) -> Result<LeafIndex, MerkleError> {
+        LeafIndex::checked_new(index as u64, self.leaf_count())
+    }
+}
+
+impl StoredTree<FileStore> {
+    /// Builds the tree over `leaves`, padded to `2^depth`, into a new file at `path`.
+    pub fn create<P: AsRef<Path>, L: Into<Leaf>>(
+        path: P,
+        params: MerkleParams,
+        depth: usize,
+        leaves: impl IntoIterator<Item = L>,
+    ) -> Result<Self, MerkleError> {
+        let mut tree = Self::build(params, leaves, FileStore::create(path, depth)?)?;
+        tree.flush()?;
+        Ok(tree)
+    }
+
+    /// Opens the tree saved at `path`, reading nothing but its root.
+    pub fn open<P: AsRef<Path>>(path: P, params: MerkleParams) -> Result<Self, MerkleError> {
+        Self::from_store(params, FileStore::open(path)?)
+    }
+}
+
+impl<S: NodeStore> MerkleBackend for StoredTree<S> {
+    fn root(&self) -> Root {
+        self.root
+    }
+
+    fn prove(&self, index: usize) -> Result<SimplePath, MerkleError> {
+        let index = self.check_index(index)?.as_usize();
+        Ok(SimplePath {
+            leaf_sibling_hash: self.store.node(0, index ^ 1)?,
+            // top-down, and neither the leaf level nor the root
+            auth_path: (1..self.depth())
+                .rev()
+                .map(|level| self.store.node(level, (index >> level) ^ 1))
+                .collect::<Result<_, _>>()?,
+            leaf_index: index,
+        })
+    }
+
+    fn update(&mut self, index: usize, leaf: Leaf) -> Result<Root, MerkleError> {
+        self.check_index(index)?;
+        // rehash the path first, so a failure leaves the store alone
+        let mut path = vec![self
+            .params
+            .hash_leaf(leaf.as_ref())
+            .with_context(|| ErrorContext::new("update").index(index))?];
+        for level in 1..=self.depth() {
+            let child = index >> (level - 1);
+            let sibling = self.store.node(level - 1, child ^ 1)?;
+            let current = path[level - 1];
+            let (left, right) = if child & 1 == 1 { (sibling, current) } else { (current, sibling) };
+            path.push(
+                self.params
+                    .hash_children(&left, &right)
+                    .with_context(|| ErrorContext::new("update").index(index).level(level))?,
+            );
+        }
+        for (level, digest) in path.into_iter().enumerate() {
+            self.store.set_node(level, index >> level, digest)?;
+        }
+        self.root = self.store.node(self.depth(), 0)?;
+        Ok(self.root)
+    }
+
+    fn leaf_count(&self) -> u64 {
+        1 << self.depth()
+    }
+}
+
+#[test]
+fn test_stored_tree_conformance() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    crate::backend::conformance::check(&params, |params, leaves| {
+        let depth = leaves.len().trailing_zeros() as usize;
+        StoredTree::build(params.clone(), leaves, MemoryStore::new(depth))
+    });
+}
+
+#[test]
+fn test_stored_tree_survives_reopening() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let path = std::env::temp_dir().join(format!("merkle-store-{}", std::process::id()));
+    let mut leaves: Vec<Vec<u8>> = (0u8..5).map(|i| vec![i; 8]).collect();
+
+    let mut tree = StoredTree::create(&path, params.clone(), 3, &leaves).unwrap();
+    leaves.resize(8, Vec::new());
+    assert_eq!(tree.root(), params.build_tree(&leaves).unwrap().root());
+    leaves[6] = vec![6; 8];
+    tree.update(6, Leaf::from(&leaves[6])).unwrap();
+    tree.flush().unwrap();
+    let root = tree.root();
+    drop(tree);
+
+    let mut reopened = StoredTree::open(&path, params.clone()).unwrap();
+    assert_eq!(reopened.root(), root);
+    assert_eq!(root, params.build_tree(&leaves).unwrap().root());
+    assert!(params.verify_path(&reopened.prove(6).unwrap(), &root, &leaves[6]).unwrap());
+
+    // an update that isn't flushed is gone on the next open
+    reopened.update(0, Leaf::from(vec![9u8; 8])).unwrap();
+    assert_ne!(reopened.root(), root);
+    drop(reopened);
+    assert_eq!(StoredTree::open(&path, params.clone()).unwrap().root(), root);
+
+    // a truncated file isn't a store
+    let len = std::fs::metadata(&path).unwrap().len();
+    OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 1).unwrap();
+    assert!(StoredTree::open(&path, params).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_stored_tree_capacity() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..5).map(|i| vec![i; 8]).collect();
+    assert!(matches!(
+        StoredTree::build(params, &leaves, MemoryStore::new(2)),
+        Err(MerkleError::CapacityExceeded { len: 5, capacity: 4 })
+    ));
+}
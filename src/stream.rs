@@ -0,0 +1,277 @@
+use crate::common::*;
+use crate::error::{ErrorContext, MerkleError, ResultExt};
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::merkle_tree::{Config, Path};
+use ark_ff::to_bytes;
+use ark_std::collections::BTreeMap;
+use ark_std::vec::Vec;
+
+// Building a tree without holding its leaves.
+//
+// `TreeBuilder` hashes each leaf as it is pushed and keeps only the frontier:
+// for every level, the finished left node still waiting for its right
+// sibling, O(log n) digests in all. When every slot up to the capacity is
+// filled, the last node placed is the root. Leaves that were never pushed are
+// `PADDING_LEAF`, and are filled in whole subtrees at a time from precomputed
+// digests, so the root is that of `TreeParams::build_padded_tree` over the
+// same leaves.
+//
+// The paths of a few leaves can be recorded on the way: every node is
+// checked, as it is placed, against the siblings those paths need.
+
+type LeafDigest<P> = <<P as Config>::LeafHash as CRH>::Output;
+type NodeDigest<P> = <<P as Config>::TwoToOneHash as TwoToOneCRH>::Output;
+
+pub struct TreeBuilder<P: Config> {
+    params: TreeParams<P>,
+    capacity: usize,
+    // the level of the root, with the leaves at level 0
+    depth: usize,
+    len: usize,
+    // the left leaf of the pair being filled
+    pending_leaf: Option<LeafDigest<P>>,
+    // frontier[level - 1] is the left node at `level` waiting for its sibling
+    frontier: Vec<Option<NodeDigest<P>>>,
+    root: Option<NodeDigest<P>>,
+    // the leaves whose paths are recorded, and the siblings they need by
+    // index (leaf level) and by (level, index)
+    indices: Vec<usize>,
+    leaf_siblings: BTreeMap<usize, Option<LeafDigest<P>>>,
+    node_siblings: BTreeMap<(usize, usize), Option<NodeDigest<P>>>,
+}
+
+impl<P: Config> TreeBuilder<P> {
+    /// A builder for a tree of `capacity` leaves, a power of two, at least two.
+    pub fn new(params: TreeParams<P>, capacity: usize) -> Self {
+        Self::with_proofs(params, capacity, &[])
+    }
+
+    /// A builder that also records the paths of the leaves at `indices`.
+    pub fn with_proofs(params: TreeParams<P>, capacity: usize, indices: &[usize]) -> Self {
+        assert!(
+            capacity.is_power_of_two() && capacity > 1,
+            "a tree needs a power of two leaves, at least two"
+        );
+        let depth = capacity.trailing_zeros() as usize;
+        let mut leaf_siblings = BTreeMap::new();
+        let mut node_siblings = BTreeMap::new();
+        for &index in indices {
+            leaf_siblings.insert(index ^ 1, None);
+            for level in 1..depth {
+                node_siblings.insert((level, (index >> level) ^ 1), None);
+            }
+        }
+        TreeBuilder {
+            params,
+            capacity,
+            depth,
+            len: 0,
+            pending_leaf: None,
+            frontier: vec![None; depth - 1],
+            root: None,
+            indices: indices.to_vec(),
+            leaf_siblings,
+            node_siblings,
+        }
+    }
+
+    /// The number of leaves pushed so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Hashes `leaf` into the next slot.
+    pub fn push_leaf(&mut self, leaf: &[u8]) -> Result<(), MerkleError> {
+        if self.len == self.capacity {
+            return Err(MerkleError::CapacityExceeded {
+                len: self.len + 1,
+                capacity: self.capacity,
+            });
+        }
+        let index = self.len;
+        let digest = self
+            .params
+            .hash_leaf(leaf)
+            .with_context(|| ErrorContext::new("push_leaf").index(index))?;
+        self.place_leaf(index, digest)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Pushes every leaf of `leaves`, in order, stopping at the first that
+    /// fails. The leaves are hashed as they are produced, so a reader over a
+    /// file or a generator never has to be collected first.
+    pub fn push_leaves<L: AsRef<[u8]>>(&mut self, leaves: impl IntoIterator<Item = L>) -> Result<(), MerkleError> {
+        for leaf in leaves {
+            self.push_leaf(leaf.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// The root of the tree, padded up to its capacity.
+    pub fn finalize_root(self) -> Result<NodeDigest<P>, MerkleError> {
+        Ok(self.finalize_with_proofs()?.0)
+    }
+
+    /// The root and the recorded paths, in the order their indices were given.
+    /// Fails if an index is past the leaves that were pushed.
+    pub fn finalize_with_proofs(mut self) -> Result<(NodeDigest<P>, Vec<Path<P>>), MerkleError> {
+        if let Some(&index) = self.indices.iter().find(|index| **index >= self.len) {
+            return Err(MerkleError::LeafIndexOutOfRange { index, len: self.len });
+        }
+        self.pad()?;
+        let root = self.root.clone().expect("a full tree has a root");
+        let paths = self
+            .indices
+            .iter()
+            .map(|&index| Path {
+                leaf_sibling_hash: self.leaf_siblings[&(index ^ 1)].clone().expect("recorded"),
+                // top-down, and neither the leaf level nor the root
+                auth_path: (1..self.depth)
+                    .rev()
+                    .map(|level| {
+                        self.node_siblings[&(level, (index >> level) ^ 1)]
+                            .clone()
+                            .expect("recorded")
+                    })
+                    .collect(),
+                leaf_index: index,
+            })
+            .collect();
+        Ok((root, paths))
+    }
+
+    // Fills every slot from `len` on with padding, a whole empty subtree at a time.
+    fn pad(&mut self) -> Result<(), MerkleError> {
+        let padding_leaf = self.params.hash_leaf(PADDING_LEAF)?;
+        // empty[level - 1] is the root of an empty subtree at `level`
+        let mut empty = vec![self.hash_leaves(&padding_leaf, &padding_leaf)?];
+        for level in 1..self.depth {
+            let below = &empty[level - 1];
+            empty.push(self.params.hash_children(below, below)?);
+        }
+        let mut position = self.len;
+        while position < self.capacity {
+            // the largest empty subtree that starts at `position`
+            let level = if position == 0 {
+                self.depth
+            } else {
+                position.trailing_zeros() as usize
+            };
+            if level == 0 {
+                self.place_leaf(position, padding_leaf.clone())?;
+            } else {
+                self.place_node(level, position >> level, empty[level - 1].clone())?;
+            }
+            position += 1 << level;
+        }
+        Ok(())
+    }
+
+    fn hash_leaves(&self, left: &LeafDigest<P>, right: &LeafDigest<P>) -> Result<NodeDigest<P>, MerkleError> {
+        Ok(<P::TwoToOneHash as TwoToOneCRH>::evaluate(
+            &self.params.two_to_one_crh_params,
+            &to_bytes![left]?,
+            &to_bytes![right]?,
+        )?)
+    }
+
+    fn place_leaf(&mut self, index: usize, digest: LeafDigest<P>) -> Result<(), MerkleError> {
+        if let Some(slot) = self.leaf_siblings.get_mut(&index) {
+            *slot = Some(digest.clone());
+        }
+        if index % 2 == 0 {
+            self.pending_leaf = Some(digest);
+            return Ok(());
+        }
+        let left = self.pending_leaf.take().expect("the left leaf comes first");
+        let parent = self.hash_leaves(&left, &digest)?;
+        self.place_node(1, index / 2, parent)
+    }
+
+    fn place_node(&mut self, level: usize, index: usize, digest: NodeDigest<P>) -> Result<(), MerkleError> {
+        if let Some(slot) = self.node_siblings.get_mut(&(level, index)) {
+            *slot = Some(digest.clone());
+        }
+        if level == self.depth {
+            self.root = Some(digest);
+            return Ok(());
+        }
+        if index % 2 == 0 {
+            self.frontier[level - 1] = Some(digest);
+            return Ok(());
+        }
+        let left = self.frontier[level - 1].take().expect("the left node comes first");
+        let parent = self.params.hash_children(&left, &digest)?;
+        self.place_node(level + 1, index / 2, parent)
+    }
+}
+
+#[test]
+fn test_streamed_root_matches_the_batch_tree() {
+    use crate::mock::MockParams;
+
+    let params = MockParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0..1u32 << 14).map(|i| i.to_le_bytes().to_vec()).collect();
+    let indices = [0, 1, 4097, (1 << 14) - 1];
+    let mut builder = TreeBuilder::with_proofs(params.clone(), 1 << 14, &indices);
+    for leaf in &leaves {
+        builder.push_leaf(leaf).unwrap();
+    }
+    assert!(builder.push_leaf(&[0u8]).is_err());
+
+    let tree = params.build_tree(&leaves).unwrap();
+    let (root, paths) = builder.finalize_with_proofs().unwrap();
+    assert_eq!(root, tree.root());
+    for (index, path) in indices.iter().zip(paths.iter()) {
+        assert_eq!(path.leaf_index, *index);
+        assert!(params.verify_path(path, &root, &leaves[*index]).unwrap());
+    }
+}
+
+#[test]
+fn test_streamed_root_pads_like_build_padded_tree() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..5).map(|i| vec![i; 4]).collect();
+    let mut builder = TreeBuilder::with_proofs(params.clone(), 8, &[4]);
+    for leaf in &leaves {
+        builder.push_leaf(leaf).unwrap();
+    }
+    let (root, paths) = builder.finalize_with_proofs().unwrap();
+    assert_eq!(root, params.build_padded_tree(&leaves).unwrap().root());
+    assert!(params.verify_path(&paths[0], &root, &leaves[4]).unwrap());
+
+    // only leaves that were pushed have paths
+    let mut builder = TreeBuilder::with_proofs(params.clone(), 8, &[5]);
+    for leaf in &leaves {
+        builder.push_leaf(leaf).unwrap();
+    }
+    assert!(matches!(
+        builder.finalize_with_proofs(),
+        Err(MerkleError::LeafIndexOutOfRange { index: 5, len: 5 })
+    ));
+}
+
+#[test]
+fn test_leaves_streamed_from_an_iterator() {
+    use crate::mock::MockParams;
+
+    let params = MockParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaf = |i: u32| i.to_be_bytes();
+    let mut builder = TreeBuilder::new(params.clone(), 4096);
+    builder.push_leaves((0..3000).map(leaf)).unwrap();
+    assert_eq!(builder.len(), 3000);
+    let expected: Vec<Vec<u8>> = (0..3000).map(|i| leaf(i).to_vec()).collect();
+    assert_eq!(builder.finalize_root().unwrap(), params.build_padded_tree(&expected).unwrap().root());
+
+    let mut builder = TreeBuilder::new(params, 4);
+    assert!(matches!(
+        builder.push_leaves((0..5).map(leaf)),
+        Err(MerkleError::CapacityExceeded { len: 5, capacity: 4 })
+    ));
+    assert_eq!(builder.len(), 4);
+}
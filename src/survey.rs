@@ -0,0 +1,385 @@
+use crate::common::*;
+use crate::constraints::{blank_path, RootVar, SimplePathVar};
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::inputs::PublicInputsBuilder;
+use crate::leaf::Leaf;
+use crate::nullifier::{nullifier, nullifier_var, Nullifier, NullifierSet};
+use crate::snark::{self, Proof, ProvingKey, VerifyingKey};
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_crypto_primitives::crh::{CRHGadget, CRH};
+use ark_ff::to_bytes;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::collections::BTreeMap;
+use ark_std::io::{Read, Write};
+use ark_std::rand::{CryptoRng, RngCore};
+
+// Anonymous surveys with one response per eligible participant.
+//
+// Each participant holds a random secret and registers the commitment
+// `LeafHash(domain || secret)` with the organizer, who publishes a tree of all
+// commitments. A response carries a proof that its author knows the secret
+// behind one of the leaves, together with the nullifier of that secret scoped
+// to the survey id. Nullifiers for different surveys are unrelated, so the
+// same participant can answer every survey, but only once each. The response
+// itself is bound to the proof through its hash, so a submission can't be
+// replayed with different answers.
+
+const COMMITMENT_DOMAIN: &[u8] = b"merkle-tutorial/survey-commitment";
+
+/// A participant's registered commitment.
+pub type Commitment = <LeafHash as CRH>::Output;
+
+/// The scope a survey id gives to nullifiers.
+fn survey_scope(survey_id: u64) -> [u8; 8] {
+    survey_id.to_le_bytes()
+}
+
+pub struct Participant {
+    secret: [u8; 32],
+}
+
+impl Participant {
+    pub fn new<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut secret = [0u8; 32];
+        rng.fill_bytes(&mut secret);
+        Participant { secret }
+    }
+
+    pub fn commitment(&self, params: &MerkleParams) -> Result<Commitment, MerkleError> {
+        let mut input = COMMITMENT_DOMAIN.to_vec();
+        input.extend_from_slice(&self.secret);
+        Ok(<LeafHash as CRH>::evaluate(&params.leaf_crh_params, &input)?)
+    }
+
+    /// The nullifier this participant's answer to `survey_id` carries.
+    pub fn nullifier(&self, params: &MerkleParams, survey_id: u64) -> Result<Nullifier, MerkleError> {
+        Ok(nullifier(params, &self.secret, &survey_scope(survey_id))?)
+    }
+
+    /// Answers `survey_id` with `response`, proving eligibility in `roster`.
+    pub fn respond<R: RngCore + CryptoRng>(
+        &self,
+        params: &MerkleParams,
+        pk: &ProvingKey,
+        roster: &Roster,
+        survey_id: u64,
+        response: &[u8],
+        rng: &mut R,
+    ) -> Result<Submission, MerkleError> {
+        let nullifier = self.nullifier(params, survey_id)?;
+        let circuit = ResponseCircuit {
+            params: params.clone(),
+            root: roster.root(),
+            survey_id,
+            response_hash: keccak256(response),
+            nullifier,
+            secret: Some(self.secret),
+            auth_path: Some(roster.path(&self.commitment(params)?)?),
+        };
+        Ok(Submission {
+            survey_id,
+            response: response.to_vec(),
+            nullifier,
+            proof: snark::prove(pk, circuit, rng)?,
+        })
+    }
+}
+
+/// The organizer's tree of eligible-participant commitments.
+pub struct Roster {
+    commitments: Vec<Commitment>,
+    tree: SimpleMerkleTree,
+}
+
+impl Roster {
+    pub fn new(params: &MerkleParams, commitments: Vec<Commitment>) -> Result<Self, MerkleError> {
+        let leaves = commitments
+            .iter()
+            .map(|c| to_bytes![c])
+            .collect::<Result<Vec<_>, _>>()?;
+        // padding leaves are the zero digest, which no secret opens to
+        let padding = PaddingStrategy::Custom(Leaf::from(to_bytes![Commitment::default()]?));
+        let options = TreeOptions::for_capacity(leaves.len()).padding(padding);
+        let tree = params.build_tree_with_options(&leaves, &options)?.tree;
+        Ok(Roster { commitments, tree })
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    /// Number of levels of the tree, leaves and root included.
+    pub fn tree_height(&self) -> usize {
+        self.tree.height()
+    }
+
+    pub fn path(&self, commitment: &Commitment) -> Result<SimplePath, MerkleError> {
+        let index = self
+            .commitments
+            .iter()
+            .position(|c| c == commitment)
+            .ok_or(MerkleError::LeafNotFound)?;
+        generate_proof(&self.tree, LeafIndex::checked_new(index as u64, leaf_count(&self.tree))?)
+    }
+
+    /// Generates the proving and verifying keys for responses against this roster.
+    pub fn setup<R: RngCore + CryptoRng>(
+        &self,
+        params: &MerkleParams,
+        rng: &mut R,
+    ) -> Result<(ProvingKey, VerifyingKey), MerkleError> {
+        snark::setup(ResponseCircuit::blank(params.clone(), self.tree_height()), rng)
+    }
+}
+
+/// Proves "I am on the roster under `root`, and `nullifier` is my nullifier for `survey_id`".
+pub struct ResponseCircuit {
+    // These are constants that will be embedded into the circuit
+    pub params: MerkleParams,
+
+    // These are the public inputs to the circuit.
+    pub root: Root,
+    pub survey_id: u64,
+    pub response_hash: [u8; 32],
+    pub nullifier: Nullifier,
+
+    // These are the private witnesses to the circuit.
+    pub secret: Option<[u8; 32]>,
+    pub auth_path: Option<SimplePath>,
+}
+
+impl ResponseCircuit {
+    /// A circuit of the right shape for key generation.
+    pub fn blank(params: MerkleParams, tree_height: usize) -> Self {
+        ResponseCircuit {
+            params,
+            root: Default::default(),
+            survey_id: 0,
+            response_hash: [0u8; 32],
+            nullifier: Default::default(),
+            secret: Some([0u8; 32]),
+            auth_path: Some(blank_path(tree_height)),
+        }
+    }
+
+    /// The public inputs of the circuit, in allocation order.
+    pub fn public_inputs(
+        root: &Root,
+        survey_id: u64,
+        response_hash: &[u8; 32],
+        nullifier: &Nullifier,
+    ) -> Vec<ConstraintF> {
+        PublicInputsBuilder::scoped_nullifier()
+            .root(root)
+            .leaf_bytes(&survey_scope(survey_id))
+            .leaf_bytes(response_hash)
+            .nullifier(nullifier)
+            .finish()
+    }
+}
+
+impl ConstraintSynthesizer<ConstraintF> for ResponseCircuit {
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        // First, we allocate the public inputs
+        let root = RootVar::new_input(ark_relations::ns!(cs, "root_var"), || Ok(&self.root))?;
+        let survey_id =
+            UInt8::new_input_vec(ark_relations::ns!(cs, "survey_id_var"), &survey_scope(self.survey_id))?;
+        // only carried along, so that the proof commits to the response
+        let _response_hash =
+            UInt8::new_input_vec(ark_relations::ns!(cs, "response_hash_var"), &self.response_hash)?;
+        let expected_nullifier =
+            FpVar::new_input(ark_relations::ns!(cs, "nullifier_var"), || Ok(self.nullifier))?;
+
+        // Then, we allocate the public parameters as constants:
+        let leaf_crh_params =
+            LeafHashParamsVar::new_constant(cs.clone(), &self.params.leaf_crh_params)?;
+        let two_to_one_crh_params =
+            TwoToOneHashParamsVar::new_constant(cs.clone(), &self.params.two_to_one_crh_params)?;
+
+        // Finally, the secret and its path:
+        let secret = UInt8::new_witness_vec(
+            ark_relations::ns!(cs, "secret_var"),
+            &self.secret.unwrap_or_default(),
+        )?;
+        let path = SimplePathVar::new_witness(ark_relations::ns!(cs, "path_var"), || {
+            self.auth_path.as_ref().ok_or(SynthesisError::AssignmentMissing)
+        })?;
+
+        let mut commitment_input = UInt8::constant_vec(COMMITMENT_DOMAIN);
+        commitment_input.extend_from_slice(&secret);
+        let commitment =
+            <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::evaluate(&leaf_crh_params, &commitment_input)?;
+        let leaf_bytes = commitment.to_bytes()?;
+
+        let is_member = path.verify_membership(
+            &leaf_crh_params,
+            &two_to_one_crh_params,
+            &root,
+            &leaf_bytes.as_slice(),
+        )?;
+        is_member.enforce_equal(&Boolean::TRUE)?;
+
+        let nullifier = nullifier_var(&leaf_crh_params, &secret, &survey_id)?;
+        nullifier.enforce_equal(&expected_nullifier)?;
+
+        Ok(())
+    }
+}
+
+/// An anonymous answer to a survey.
+#[derive(Clone)]
+pub struct Submission {
+    pub survey_id: u64,
+    pub response: Vec<u8>,
+    pub nullifier: Nullifier,
+    pub proof: Proof,
+}
+
+/// The organizer side: verifies submissions and keeps one response per
+/// participant and survey.
+pub struct ResponsePool {
+    vk: VerifyingKey,
+    root: Root,
+    spent: BTreeMap<u64, NullifierSet>,
+    responses: BTreeMap<u64, Vec<Vec<u8>>>,
+}
+
+impl ResponsePool {
+    pub fn new(vk: VerifyingKey, root: Root) -> Self {
+        ResponsePool {
+            vk,
+            root,
+            spent: BTreeMap::new(),
+            responses: BTreeMap::new(),
+        }
+    }
+
+    /// Accepts `submission` if its proof verifies and its author hasn't
+    /// answered this survey yet.
+    pub fn submit(&mut self, submission: &Submission) -> Result<bool, MerkleError> {
+        let survey_id = submission.survey_id;
+        if self
+            .spent
+            .get(&survey_id)
+            .map_or(false, |spent| spent.contains(&submission.nullifier))
+        {
+            return Ok(false);
+        }
+        let inputs = ResponseCircuit::public_inputs(
+            &self.root,
+            survey_id,
+            &keccak256(&submission.response),
+            &submission.nullifier,
+        );
+        if !snark::verify(&self.vk, &inputs, &submission.proof)? {
+            return Ok(false);
+        }
+        self.spent.entry(survey_id).or_default().insert(submission.nullifier);
+        self.responses
+            .entry(survey_id)
+            .or_default()
+            .push(submission.response.clone());
+        Ok(true)
+    }
+
+    /// The responses accepted for `survey_id`, in arrival order.
+    pub fn responses(&self, survey_id: u64) -> &[Vec<u8>] {
+        self.responses.get(&survey_id).map_or(&[], |r| r.as_slice())
+    }
+}
+
+impl CanonicalSerialize for Submission {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.survey_id.serialize(&mut writer)?;
+        self.response.serialize(&mut writer)?;
+        self.nullifier.serialize(&mut writer)?;
+        self.proof.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.survey_id.serialized_size()
+            + self.response.serialized_size()
+            + self.nullifier.serialized_size()
+            + self.proof.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for Submission {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(Submission {
+            survey_id: u64::deserialize(&mut reader)?,
+            response: Vec::<u8>::deserialize(&mut reader)?,
+            nullifier: Nullifier::deserialize(&mut reader)?,
+            proof: Proof::deserialize(&mut reader)?,
+        })
+    }
+}
+
+#[cfg(test)]
+fn test_survey() -> (MerkleParams, Vec<Participant>, Roster) {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let participants: Vec<_> = (0..3).map(|_| Participant::new(&mut rng)).collect();
+    let commitments = participants
+        .iter()
+        .map(|p| p.commitment(&params).unwrap())
+        .collect();
+    let roster = Roster::new(&params, commitments).unwrap();
+    (params, participants, roster)
+}
+
+#[test]
+fn test_survey_cross_survey_reuse_allowed() {
+    let mut rng = ark_std::test_rng();
+    let (params, participants, roster) = test_survey();
+    let (pk, vk) = roster.setup(&params, &mut rng).unwrap();
+    let mut pool = ResponsePool::new(vk, roster.root());
+
+    let alice = &participants[0];
+    let first = alice.respond(&params, &pk, &roster, 1, b"yes", &mut rng).unwrap();
+    let second = alice.respond(&params, &pk, &roster, 2, b"no", &mut rng).unwrap();
+    assert_ne!(first.nullifier, second.nullifier);
+
+    assert!(pool.submit(&first).unwrap());
+    assert!(pool.submit(&second).unwrap());
+    assert_eq!(pool.responses(1), &[b"yes".to_vec()]);
+    assert_eq!(pool.responses(2), &[b"no".to_vec()]);
+}
+
+#[test]
+fn test_survey_same_survey_reuse_rejected() {
+    let mut rng = ark_std::test_rng();
+    let (params, participants, roster) = test_survey();
+    let (pk, vk) = roster.setup(&params, &mut rng).unwrap();
+    let mut pool = ResponsePool::new(vk, roster.root());
+
+    let alice = &participants[0];
+    let submission = alice.respond(&params, &pk, &roster, 1, b"yes", &mut rng).unwrap();
+
+    // submissions survive the trip through bytes
+    let mut bytes = Vec::new();
+    submission.serialize(&mut bytes).unwrap();
+    let decoded = Submission::deserialize(&bytes[..]).unwrap();
+    assert!(pool.submit(&decoded).unwrap());
+
+    // neither a replay nor a fresh answer from the same participant gets in
+    assert!(!pool.submit(&submission).unwrap());
+    let again = alice.respond(&params, &pk, &roster, 1, b"no", &mut rng).unwrap();
+    assert!(!pool.submit(&again).unwrap());
+
+    // answers can't be swapped under someone else's proof
+    let mut tampered = participants[1].respond(&params, &pk, &roster, 1, b"yes", &mut rng).unwrap();
+    tampered.response = b"no".to_vec();
+    assert!(!pool.submit(&tampered).unwrap());
+
+    let bob = participants[1].respond(&params, &pk, &roster, 1, b"no", &mut rng).unwrap();
+    assert!(pool.submit(&bob).unwrap());
+    assert_eq!(pool.responses(1), &[b"yes".to_vec(), b"no".to_vec()]);
+}
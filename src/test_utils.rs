@@ -0,0 +1,215 @@
+use crate::common::*;
+#[cfg(feature = "constraints")]
+use crate::constraints::{MerkleCircuitConfig, MerkleTreeVerification};
+use crate::gen::{random_leaf, random_leaves};
+use ark_crypto_primitives::crh::TwoToOneCRH;
+use ark_crypto_primitives::merkle_tree::{Config, MerkleTree, Path};
+#[cfg(feature = "constraints")]
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+use ark_std::rand::Rng;
+use ark_std::vec::Vec;
+#[cfg(feature = "constraints")]
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+// A soundness harness for tree configurations, the crate's and others'.
+//
+// A backend is only as good as its rejections: natively, a path must stop
+// verifying when any part of it changes, and in the circuit the same change
+// must leave the constraint system unsatisfied. `mutations` lists every
+// single-value change of a path: each sibling replaced, the path one level
+// short, each bit of the leaf index flipped. `assert_native_rejects` and
+// `assert_circuit_rejects` check the honest opening is accepted and every
+// mutation, and a wrong leaf, rejected; `fuzz_native` and `fuzz_circuit` do
+// so over random trees from `gen`. A downstream `MerkleCircuitConfig` gets
+// the same checks the crate's own backends pass, with one call.
+//
+// A replaced sibling is the default digest, which for a sound hash is never
+// a node; a sibling that already is the default is left out.
+
+/// One change to a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mutation {
+    /// The sibling this many levels above the leaf, 0 being the leaf's own.
+    Sibling(usize),
+    /// The path without its lowest auth path entry, one level short.
+    Truncated,
+    /// This bit of the leaf index flipped.
+    IndexBit(u32),
+}
+
+/// `path` with `mutation` applied, or `None` if it wouldn't change it.
+pub fn mutate<P: Config>(path: &Path<P>, mutation: Mutation) -> Option<Path<P>> {
+    let mut mutated = path.clone();
+    match mutation {
+        Mutation::Sibling(0) if path.leaf_sibling_hash != Default::default() => {
+            mutated.leaf_sibling_hash = Default::default();
+        }
+        Mutation::Sibling(level) if level > 0 && level <= path.auth_path.len() => {
+            // the auth path runs from the root down
+            let i = path.auth_path.len() - level;
+            if path.auth_path[i] == Default::default() {
+                return None;
+            }
+            mutated.auth_path[i] = Default::default();
+        }
+        Mutation::Truncated if !path.auth_path.is_empty() => {
+            mutated.auth_path.pop();
+        }
+        Mutation::IndexBit(bit) if (bit as usize) <= path.auth_path.len() => mutated.leaf_index ^= 1 << bit,
+        _ => return None,
+    }
+    Some(mutated)
+}
+
+/// Every mutation that changes `path`, with the changed path.
+pub fn mutations<P: Config>(path: &Path<P>) -> Vec<(Mutation, Path<P>)> {
+    let levels = path.auth_path.len() + 1;
+    (0..levels)
+        .map(Mutation::Sibling)
+        .chain(Some(Mutation::Truncated))
+        .chain((0..levels as u32).map(Mutation::IndexBit))
+        .filter_map(|mutation| Some((mutation, mutate(path, mutation)?)))
+        .collect()
+}
+
+/// A leaf of the same length as `leaf` that isn't it.
+pub fn other_leaf(leaf: &[u8]) -> Vec<u8> {
+    let mut other = leaf.to_vec();
+    match other.first_mut() {
+        Some(byte) => *byte ^= 1,
+        None => other.push(0),
+    }
+    other
+}
+
+/// Panics unless `path` opens `leaf` against `root` natively, and no
+/// mutation of it, nor the path with another leaf, does.
+pub fn assert_native_rejects<P: Config>(
+    params: &TreeParams<P>,
+    root: &<P::TwoToOneHash as TwoToOneCRH>::Output,
+    leaf: &[u8],
+    path: &Path<P>,
+) {
+    assert!(params.verify_path(path, root, leaf).unwrap(), "the honest path doesn't verify");
+    assert!(
+        !params.verify_path(path, root, other_leaf(leaf)).unwrap_or(false),
+        "the path verifies for another leaf"
+    );
+    for (mutation, mutated) in mutations(path) {
+        assert!(
+            !params.verify_path(&mutated, root, leaf).unwrap_or(false),
+            "the path verifies after {:?}",
+            mutation
+        );
+    }
+}
+
+/// Whether `circuit` synthesizes into a satisfied constraint system. A
+/// synthesis error or a panic counts as unsatisfied: no proof comes out of
+/// either.
+#[cfg(feature = "constraints")]
+pub fn is_satisfied<C: MerkleCircuitConfig>(circuit: MerkleTreeVerification<C>) -> bool {
+    catch_unwind(AssertUnwindSafe(|| {
+        let cs = ConstraintSystem::<C::ConstraintF>::new_ref();
+        match circuit.generate_constraints(cs.clone()) {
+            Ok(()) => cs.is_satisfied().unwrap(),
+            Err(_) => false,
+        }
+    }))
+    .unwrap_or(false)
+}
+
+/// `assert_native_rejects` for the membership circuit of `C`: the honest
+/// witness satisfies it, and no mutated path nor another leaf does.
+#[cfg(feature = "constraints")]
+pub fn assert_circuit_rejects<C: MerkleCircuitConfig>(
+    params: &TreeParams<C::Config>,
+    root: &<<C::Config as Config>::TwoToOneHash as TwoToOneCRH>::Output,
+    leaf: &[u8],
+    path: &Path<C::Config>,
+) {
+    let circuit = |leaf: &[u8], path: &Path<C::Config>| MerkleTreeVerification::<C> {
+        leaf_crh_params: params.leaf_crh_params.clone(),
+        two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+        root: root.clone(),
+        leaf: leaf.to_vec(),
+        auth_path: Some(path.clone()),
+    };
+    assert!(is_satisfied(circuit(leaf, path)), "the honest witness doesn't satisfy the circuit");
+    assert!(!is_satisfied(circuit(&other_leaf(leaf), path)), "the circuit accepts another leaf");
+    for (mutation, mutated) in mutations(path) {
+        assert!(!is_satisfied(circuit(leaf, &mutated)), "the circuit accepts the path after {:?}", mutation);
+    }
+}
+
+// A random tree of 2 to 32 leaves, and a random index in it.
+fn random_opening<P: Config, R: Rng>(rng: &mut R, params: &TreeParams<P>) -> (Vec<Vec<u8>>, MerkleTree<P>, usize) {
+    let n = 1 << rng.gen_range(1, 6);
+    let mut leaves = random_leaves(rng, n, 32);
+    let index = rng.gen_range(0, n);
+    // half the time, an opened leaf of another length
+    if rng.gen() {
+        let len = rng.gen_range(0, 33);
+        leaves[index] = random_leaf(rng, len);
+    }
+    let tree = params.build_tree(&leaves).expect("random leaves build a tree");
+    (leaves, tree, index)
+}
+
+/// `assert_native_rejects` for a random leaf of each of `trees` random trees.
+pub fn fuzz_native<P: Config, R: Rng>(rng: &mut R, params: &TreeParams<P>, trees: usize) {
+    for _ in 0..trees {
+        let (leaves, tree, index) = random_opening(rng, params);
+        let path = tree.generate_proof(index).unwrap();
+        assert_native_rejects(params, &tree.root(), &leaves[index], &path);
+    }
+}
+
+/// `assert_circuit_rejects` for a random leaf of each of `trees` random trees.
+#[cfg(feature = "constraints")]
+pub fn fuzz_circuit<C: MerkleCircuitConfig, R: Rng>(rng: &mut R, params: &TreeParams<C::Config>, trees: usize) {
+    for _ in 0..trees {
+        let (leaves, tree, index) = random_opening(rng, params);
+        let path = tree.generate_proof(index).unwrap();
+        assert_circuit_rejects::<C>(params, &tree.root(), &leaves[index], &path);
+    }
+}
+
+#[test]
+fn test_mutations_of_a_path() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let path = tree.generate_proof(5).unwrap();
+
+    let kinds: Vec<Mutation> = mutations(&path).into_iter().map(|(mutation, _)| mutation).collect();
+    assert_eq!(kinds.len(), 3 + 1 + 3);
+    let truncated = mutate(&path, Mutation::Truncated).unwrap();
+    assert_eq!(truncated.auth_path, path.auth_path[..1]);
+    assert_eq!(mutate(&path, Mutation::IndexBit(2)).unwrap().leaf_index, 1);
+    assert!(mutate(&path, Mutation::IndexBit(3)).is_none());
+    assert!(mutate(&path, Mutation::Sibling(3)).is_none());
+    assert_eq!(mutate(&path, Mutation::Sibling(2)).unwrap().auth_path[0], Default::default());
+    assert_native_rejects(&params, &tree.root(), &leaves[5], &path);
+}
+
+#[test]
+fn test_fuzz_native_backends() {
+    let mut rng = ark_std::test_rng();
+    fuzz_native(&mut rng, &MerkleParams::setup(&mut rng).unwrap(), 4);
+    fuzz_native(&mut rng, &crate::mock::MockParams::setup(&mut rng).unwrap(), 16);
+    fuzz_native(&mut rng, &crate::common::blake2s::Blake2sParams::setup(&mut rng).unwrap(), 16);
+}
+
+#[cfg(feature = "constraints")]
+#[test]
+fn test_fuzz_circuit_backends() {
+    use crate::common::poseidon::{PoseidonMerkleCircuit, PoseidonParams};
+    use crate::constraints::JubjubMerkleCircuit;
+    use crate::mock::{MockMerkleCircuit, MockParams};
+
+    let mut rng = ark_std::test_rng();
+    fuzz_circuit::<MockMerkleCircuit, _>(&mut rng, &MockParams::setup(&mut rng).unwrap(), 8);
+    fuzz_circuit::<PoseidonMerkleCircuit, _>(&mut rng, &PoseidonParams::setup(&mut rng).unwrap(), 2);
+    fuzz_circuit::<JubjubMerkleCircuit, _>(&mut rng, &MerkleParams::setup(&mut rng).unwrap(), 1);
+}
@@ -0,0 +1,346 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::leaf::Leaf;
+use crate::{Root, SimpleMerkleTree, SimplePath};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_std::vec::Vec;
+
+// A record of how a tree got its root, for audits and disputes.
+//
+// A `TranscriptTree` logs every operation on it to a `TreeTranscript`: the
+// leaf hashes it was built over, then each update (index and new leaf hash)
+// and append (new leaf hash), each with the root it resulted in. Only leaf
+// hashes are logged, never leaves, so a transcript can be handed to an
+// auditor without the data. `replay` rebuilds the tree from the hashes alone
+// and checks every recorded root on the way; editing any entry, even one
+// whose effect a later update overwrote, makes the replay fail, and leaving
+// the recorded roots consistent with an edit takes a hash collision.
+//
+// The tree has as many leaves as were given or appended, padded with empty
+// leaves up to a power of two, like `MerkleKit`.
+
+/// One logged operation, with the root right after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TranscriptEntry {
+    /// The tree was built over leaves with these hashes.
+    Build { leaf_hashes: Vec<Root>, root: Root },
+    /// The leaf at `index` was replaced by one with hash `leaf_hash`.
+    Update { index: LeafIndex, leaf_hash: Root, root: Root },
+    /// A leaf with hash `leaf_hash` was added after the last one.
+    Append { leaf_hash: Root, root: Root },
+}
+
+impl TranscriptEntry {
+    /// The root the operation resulted in.
+    pub fn root(&self) -> Root {
+        match self {
+            TranscriptEntry::Build { root, .. }
+            | TranscriptEntry::Update { root, .. }
+            | TranscriptEntry::Append { root, .. } => *root,
+        }
+    }
+}
+
+/// The append-only log of a `TranscriptTree`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeTranscript {
+    entries: Vec<TranscriptEntry>,
+}
+
+impl TreeTranscript {
+    pub fn entries(&self) -> &[TranscriptEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Encodes the transcript: the number of entries, then each entry as a tag
+    /// byte (0 build, 1 update, 2 append) followed by its fields.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        // writing into a Vec cannot fail
+        (self.entries.len() as u64).serialize(&mut bytes).unwrap();
+        for entry in &self.entries {
+            match entry {
+                TranscriptEntry::Build { leaf_hashes, root } => {
+                    0u8.serialize(&mut bytes).unwrap();
+                    leaf_hashes.serialize(&mut bytes).unwrap();
+                    root.serialize(&mut bytes).unwrap();
+                }
+                TranscriptEntry::Update { index, leaf_hash, root } => {
+                    1u8.serialize(&mut bytes).unwrap();
+                    index.serialize(&mut bytes).unwrap();
+                    leaf_hash.serialize(&mut bytes).unwrap();
+                    root.serialize(&mut bytes).unwrap();
+                }
+                TranscriptEntry::Append { leaf_hash, root } => {
+                    2u8.serialize(&mut bytes).unwrap();
+                    leaf_hash.serialize(&mut bytes).unwrap();
+                    root.serialize(&mut bytes).unwrap();
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Decodes a transcript written by `to_bytes`, rejecting trailing bytes.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, MerkleError> {
+        let len = u64::deserialize(&mut bytes)?;
+        let mut entries = Vec::new();
+        for _ in 0..len {
+            let entry = match u8::deserialize(&mut bytes)? {
+                0 => TranscriptEntry::Build {
+                    leaf_hashes: Vec::<Root>::deserialize(&mut bytes)?,
+                    root: Root::deserialize(&mut bytes)?,
+                },
+                1 => TranscriptEntry::Update {
+                    index: LeafIndex::deserialize(&mut bytes)?,
+                    leaf_hash: Root::deserialize(&mut bytes)?,
+                    root: Root::deserialize(&mut bytes)?,
+                },
+                2 => TranscriptEntry::Append {
+                    leaf_hash: Root::deserialize(&mut bytes)?,
+                    root: Root::deserialize(&mut bytes)?,
+                },
+                _ => return Err(SerializationError::InvalidData.into()),
+            };
+            entries.push(entry);
+        }
+        if !bytes.is_empty() {
+            return Err(SerializationError::InvalidData.into());
+        }
+        Ok(TreeTranscript { entries })
+    }
+}
+
+// The root of the tree over leaves with hashes `leaf_hashes`, padded with
+// empty leaves, computed level by level like `MerkleTree::new` does.
+fn root_from_leaf_hashes(params: &MerkleParams, leaf_hashes: &[Root]) -> Result<Root, MerkleError> {
+    let mut level = leaf_hashes.to_vec();
+    level.resize(leaf_hashes.len().next_power_of_two().max(2), params.hash_leaf(&[])?);
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| params.hash_children(&pair[0], &pair[1]))
+            .collect::<Result<Vec<_>, _>>()?;
+    }
+    Ok(level[0])
+}
+
+/// Rebuilds the tree `transcript` describes from its leaf hashes and returns
+/// its final root. Fails with `InvalidTranscript` at the first entry that
+/// isn't a valid operation or doesn't lead to the root it recorded.
+pub fn replay(transcript: &TreeTranscript, params: &MerkleParams) -> Result<Root, MerkleError> {
+    let mut leaf_hashes: Vec<Root> = Vec::new();
+    for (n, entry) in transcript.entries.iter().enumerate() {
+        let invalid = MerkleError::InvalidTranscript { entry: n };
+        match entry {
+            TranscriptEntry::Build { leaf_hashes: built, .. } if n == 0 => leaf_hashes = built.clone(),
+            TranscriptEntry::Update { index, leaf_hash, .. } if n > 0 => {
+                let index = LeafIndex::checked_new(index.get(), leaf_hashes.len() as u64).map_err(|_| invalid)?;
+                leaf_hashes[index.as_usize()] = *leaf_hash;
+            }
+            TranscriptEntry::Append { leaf_hash, .. } if n > 0 => leaf_hashes.push(*leaf_hash),
+            // a transcript starts with exactly one build
+            _ => return Err(invalid),
+        }
+        if root_from_leaf_hashes(params, &leaf_hashes)? != entry.root() {
+            return Err(MerkleError::InvalidTranscript { entry: n });
+        }
+    }
+    transcript
+        .entries
+        .last()
+        .map(TranscriptEntry::root)
+        .ok_or(MerkleError::InvalidTranscript { entry: 0 })
+}
+
+/// Checks that `transcript` replays, and to `claimed_root`.
+pub fn verify_transcript(
+    transcript: &TreeTranscript,
+    params: &MerkleParams,
+    claimed_root: &Root,
+) -> Result<bool, MerkleError> {
+    match replay(transcript, params) {
+        Ok(root) => Ok(root == *claimed_root),
+        Err(MerkleError::InvalidTranscript { .. }) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// A tree that logs every operation on it.
+pub struct TranscriptTree {
+    params: MerkleParams,
+    leaves: Vec<Leaf>,
+    tree: SimpleMerkleTree,
+    transcript: TreeTranscript,
+}
+
+impl TranscriptTree {
+    /// Builds the tree over `leaves` and starts its transcript.
+    pub fn new<L: Into<Leaf>>(params: MerkleParams, leaves: impl IntoIterator<Item = L>) -> Result<Self, MerkleError> {
+        let leaves: Vec<Leaf> = leaves.into_iter().map(Into::into).collect();
+        let leaf_hashes = leaves
+            .iter()
+            .map(|leaf| params.hash_leaf(leaf.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tree = Self::build_tree(&params, &leaves)?;
+        let transcript = TreeTranscript {
+            entries: vec![TranscriptEntry::Build {
+                leaf_hashes,
+                root: tree.root(),
+            }],
+        };
+        Ok(TranscriptTree {
+            params,
+            leaves,
+            tree,
+            transcript,
+        })
+    }
+
+    fn build_tree(params: &MerkleParams, leaves: &[Leaf]) -> Result<SimpleMerkleTree, MerkleError> {
+        let mut padded = leaves.to_vec();
+        padded.resize(leaves.len().next_power_of_two().max(2), Leaf::default());
+        params.build_tree(&padded)
+    }
+
+    pub fn root(&self) -> Root {
+        self.tree.root()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn transcript(&self) -> &TreeTranscript {
+        &self.transcript
+    }
+
+    pub fn prove(&self, index: usize) -> Result<SimplePath, MerkleError> {
+        generate_proof(&self.tree, LeafIndex::checked_new(index as u64, self.leaves.len() as u64)?)
+    }
+
+    // Applies `leaves` and logs the entry `entry` makes of the new root.
+    fn commit(&mut self, leaves: Vec<Leaf>, entry: impl FnOnce(Root) -> TranscriptEntry) -> Result<Root, MerkleError> {
+        self.tree = Self::build_tree(&self.params, &leaves)?;
+        self.leaves = leaves;
+        self.transcript.entries.push(entry(self.root()));
+        Ok(self.root())
+    }
+
+    /// Replaces the leaf at `index`, returning the new root.
+    pub fn update(&mut self, index: usize, leaf: impl Into<Leaf>) -> Result<Root, MerkleError> {
+        let index = LeafIndex::checked_new(index as u64, self.leaves.len() as u64)?;
+        let leaf = leaf.into();
+        let leaf_hash = self.params.hash_leaf(leaf.as_ref())?;
+        let mut leaves = self.leaves.clone();
+        leaves[index.as_usize()] = leaf;
+        self.commit(leaves, |root| TranscriptEntry::Update { index, leaf_hash, root })
+    }
+
+    /// Adds `leaf` after the last leaf, returning the new root.
+    pub fn append(&mut self, leaf: impl Into<Leaf>) -> Result<Root, MerkleError> {
+        let leaf = leaf.into();
+        let leaf_hash = self.params.hash_leaf(leaf.as_ref())?;
+        let mut leaves = self.leaves.clone();
+        leaves.push(leaf);
+        self.commit(leaves, |root| TranscriptEntry::Append { leaf_hash, root })
+    }
+}
+
+#[cfg(test)]
+fn sample_tree(params: &MerkleParams) -> TranscriptTree {
+    let mut tree = TranscriptTree::new(params.clone(), (0u8..3).map(|i| vec![i; 8])).unwrap();
+    tree.append(vec![3u8; 8]).unwrap();
+    tree.update(1, vec![9u8; 8]).unwrap();
+    tree.append(vec![4u8; 8]).unwrap();
+    tree.update(1, vec![10u8; 8]).unwrap();
+    tree.update(4, vec![11u8; 8]).unwrap();
+    tree
+}
+
+#[test]
+fn test_transcript_replays_appends_and_updates() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let tree = sample_tree(&params);
+    assert_eq!(tree.transcript().len(), 6);
+    assert_eq!(tree.len(), 5);
+
+    let root = tree.root();
+    assert_eq!(replay(tree.transcript(), &params).unwrap(), root);
+    assert!(verify_transcript(tree.transcript(), &params, &root).unwrap());
+
+    // the replayed root is the root of the final leaves, built from scratch
+    let leaves = vec![
+        vec![0u8; 8],
+        vec![10u8; 8],
+        vec![2u8; 8],
+        vec![3u8; 8],
+        vec![11u8; 8],
+        vec![],
+        vec![],
+        vec![],
+    ];
+    assert_eq!(params.build_tree(&leaves).unwrap().root(), root);
+    assert!(params.verify_path(&tree.prove(4).unwrap(), &root, &leaves[4]).unwrap());
+
+    let decoded = TreeTranscript::from_bytes(&tree.transcript().to_bytes()).unwrap();
+    assert_eq!(&decoded, tree.transcript());
+    assert_eq!(replay(&decoded, &params).unwrap(), root);
+
+    // a failed operation isn't logged
+    let mut tree = tree;
+    assert!(tree.update(5, vec![0u8]).is_err());
+    assert_eq!(tree.transcript().len(), 6);
+}
+
+#[test]
+fn test_edited_transcript_does_not_replay() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let tree = sample_tree(&params);
+    let root = tree.root();
+    let other_hash = params.hash_leaf(b"forged").unwrap();
+
+    // editing any single entry is caught, including the first update to leaf 1,
+    // which the second update to it overwrote
+    for n in 0..tree.transcript().len() {
+        let mut edited = tree.transcript().clone();
+        match &mut edited.entries[n] {
+            TranscriptEntry::Build { leaf_hashes, .. } => leaf_hashes[0] = other_hash,
+            TranscriptEntry::Update { leaf_hash, .. } | TranscriptEntry::Append { leaf_hash, .. } => {
+                *leaf_hash = other_hash
+            }
+        }
+        assert!(matches!(
+            replay(&edited, &params),
+            Err(MerkleError::InvalidTranscript { entry }) if entry == n
+        ));
+        assert!(!verify_transcript(&edited, &params, &root).unwrap());
+    }
+
+    // so is moving an update, dropping the build, or claiming another root
+    let mut moved = tree.transcript().clone();
+    if let TranscriptEntry::Update { index, .. } = &mut moved.entries[2] {
+        *index = LeafIndex::checked_new(2, 4).unwrap();
+    }
+    assert!(!verify_transcript(&moved, &params, &root).unwrap());
+    let mut headless = tree.transcript().clone();
+    headless.entries.remove(0);
+    assert!(matches!(replay(&headless, &params), Err(MerkleError::InvalidTranscript { entry: 0 })));
+    assert!(!verify_transcript(tree.transcript(), &params, &Root::default()).unwrap());
+
+    let bytes = tree.transcript().to_bytes();
+    assert!(TreeTranscript::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
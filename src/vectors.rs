@@ -0,0 +1,284 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::inputs::PublicInputs;
+use crate::level_tree::SNAPSHOT_HASH_CONFIG;
+use crate::root::MerkleRoot;
+use crate::serialization::{deserialize_path, serialize_path};
+use crate::walkthrough::path_siblings;
+use crate::{Root, SimplePath};
+use ark_serialize::SerializationError;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Test vectors, for implementations of these trees in other languages.
+//
+// A vector is everything another implementation needs to check itself
+// against this crate: the seed of the parameters (`MerkleParams::from_seed`,
+// so both sides derive the same Pedersen generators), the leaves, the digest
+// of each leaf, the root, and for every leaf its path and the public inputs
+// of its membership proof. The path is given twice: as its siblings from the
+// leaf up, for code that doesn't decode arkworks' encoding, and as the bytes
+// of `serialization::serialize_path`, for code that does.
+//
+// Every byte string is lowercase hex, and every digest and field element is
+// the hex of its canonical encoding (little-endian, as `MerkleRoot::to_hex`
+// writes roots). A file is a `VectorFile`: a format version, the hash
+// configuration of `level_tree` snapshots, and the vectors.
+//
+// `TestVector::verify` recomputes the vector from its seed and leaves and
+// fails with `VectorMismatch` on the first field that differs, so a vector
+// file is a golden file for this crate as much as for the others. The
+// standard vectors are committed as `tests/data/vectors.json`, and a change
+// to the hashes or the layout that doesn't regenerate them fails the tests.
+
+/// The version of the vector file layout.
+pub const VECTOR_FORMAT_VERSION: u32 = 1;
+
+/// A file of test vectors.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorFile {
+    pub version: u32,
+    pub hash_config: String,
+    pub vectors: Vec<TestVector>,
+}
+
+/// One tree, with the path and public inputs of every leaf.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVector {
+    pub description: String,
+    /// The parameters are `MerkleParams::from_seed` of these bytes.
+    pub params_seed: String,
+    pub leaves: Vec<String>,
+    pub leaf_hashes: Vec<String>,
+    pub root: String,
+    pub proofs: Vec<VectorProof>,
+}
+
+/// The path of one leaf and the public inputs of its membership proof.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorProof {
+    pub index: usize,
+    /// From the leaf's sibling up to the root's children.
+    pub siblings: Vec<String>,
+    /// `serialization::serialize_path` of the path.
+    pub path: String,
+    /// Those of `MTreeVerification`: the root, then the leaf bytes packed
+    /// into field elements.
+    pub public_inputs: Vec<String>,
+}
+
+fn field_hex(x: &Root) -> String {
+    MerkleRoot::from(*x).to_hex()
+}
+
+fn proof_vector(root: &Root, index: usize, leaf: &[u8], path: &SimplePath) -> VectorProof {
+    VectorProof {
+        index,
+        siblings: path_siblings(path).iter().map(field_hex).collect(),
+        path: to_hex(&serialize_path(path)),
+        public_inputs: PublicInputs::from_parts(root, leaf).to_field_elements().iter().map(field_hex).collect(),
+    }
+}
+
+impl TestVector {
+    /// The vector of the tree over `leaves`, a power of two of them, with
+    /// the parameters of `seed`.
+    pub fn generate(description: &str, seed: &[u8], leaves: &[Vec<u8>]) -> Result<Self, MerkleError> {
+        let params = MerkleParams::from_seed(seed)?;
+        let tree = params.build_tree(leaves)?;
+        let root = tree.root();
+        let proofs = generate_proofs(&tree, &(0..leaves.len()).collect::<Vec<_>>())?;
+        Ok(TestVector {
+            description: description.into(),
+            params_seed: to_hex(seed),
+            leaves: leaves.iter().map(|leaf| to_hex(leaf)).collect(),
+            leaf_hashes: leaves
+                .iter()
+                .map(|leaf| Ok(field_hex(&params.hash_leaf(leaf)?)))
+                .collect::<Result<_, MerkleError>>()?,
+            root: field_hex(&root),
+            proofs: leaves
+                .iter()
+                .zip(&proofs)
+                .enumerate()
+                .map(|(index, (leaf, path))| proof_vector(&root, index, leaf, path))
+                .collect(),
+        })
+    }
+
+    /// Recomputes the vector and checks every field of it; `vector` is its
+    /// position, for the error.
+    pub fn verify(&self, vector: usize) -> Result<(), MerkleError> {
+        let mismatch = |field| MerkleError::VectorMismatch { vector, field };
+        let seed = from_hex(&self.params_seed).ok_or(MerkleError::InvalidHex)?;
+        let leaves = self
+            .leaves
+            .iter()
+            .map(|leaf| from_hex(leaf).ok_or(MerkleError::InvalidHex))
+            .collect::<Result<Vec<_>, _>>()?;
+        let expected = TestVector::generate(&self.description, &seed, &leaves)?;
+        if self.leaf_hashes != expected.leaf_hashes {
+            return Err(mismatch("leaf hash"));
+        }
+        if self.root != expected.root {
+            return Err(mismatch("root"));
+        }
+        if self.proofs.len() != expected.proofs.len() {
+            return Err(mismatch("number of proofs"));
+        }
+
+        let params = MerkleParams::from_seed(&seed)?;
+        let root = MerkleRoot::from_hex(&self.root)?.into_inner();
+        for (proof, expected) in self.proofs.iter().zip(&expected.proofs) {
+            if proof.index != expected.index || proof.siblings != expected.siblings || proof.path != expected.path {
+                return Err(mismatch("path"));
+            }
+            // the encoded path must also decode and verify, not just match
+            let path = deserialize_path(&from_hex(&proof.path).ok_or(MerkleError::InvalidHex)?)?;
+            if path.leaf_index != proof.index || !params.verify_path(&path, &root, &leaves[proof.index])? {
+                return Err(mismatch("path"));
+            }
+            if proof.public_inputs != expected.public_inputs {
+                return Err(mismatch("public inputs"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl VectorFile {
+    pub fn new(vectors: Vec<TestVector>) -> Self {
+        VectorFile {
+            version: VECTOR_FORMAT_VERSION,
+            hash_config: SNAPSHOT_HASH_CONFIG.into(),
+            vectors,
+        }
+    }
+
+    /// The vectors this crate publishes: trees of two to sixteen leaves, of
+    /// empty, short and full-length leaves.
+    pub fn standard() -> Result<Self, MerkleError> {
+        let vectors = vec![
+            TestVector::generate("two one-byte leaves", b"vectors/1", &[vec![0u8], vec![1u8]])?,
+            TestVector::generate("four empty leaves", b"vectors/2", &(0..4).map(|_| Vec::new()).collect::<Vec<_>>())?,
+            TestVector::generate(
+                "eight keccak256 leaves",
+                b"vectors/3",
+                &(0u8..8).map(|i| keccak256(&[i]).to_vec()).collect::<Vec<_>>(),
+            )?,
+            TestVector::generate(
+                "sixteen leaves of the longest length",
+                b"vectors/4",
+                &(0u8..16).map(|i| vec![i; MAX_LEAF_LEN]).collect::<Vec<_>>(),
+            )?,
+        ];
+        Ok(VectorFile::new(vectors))
+    }
+
+    pub fn to_json(&self) -> String {
+        // a struct of strings and numbers always serializes
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Parses a vector file, failing with `UnknownVersion` for a later
+    /// layout and `ParamsMismatch` for another hash configuration.
+    pub fn from_json(json: &str) -> Result<Self, MerkleError> {
+        let file: VectorFile = serde_json::from_str(json).map_err(|_| SerializationError::InvalidData)?;
+        if file.version != VECTOR_FORMAT_VERSION {
+            return Err(MerkleError::UnknownVersion {
+                version: file.version as usize,
+                latest: VECTOR_FORMAT_VERSION as usize,
+            });
+        }
+        if file.hash_config != SNAPSHOT_HASH_CONFIG {
+            return Err(MerkleError::ParamsMismatch);
+        }
+        Ok(file)
+    }
+
+    /// Checks every vector, in order.
+    pub fn verify(&self) -> Result<(), MerkleError> {
+        for (i, vector) in self.vectors.iter().enumerate() {
+            vector.verify(i)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `file` to `path` as JSON.
+pub fn write_vector_file(path: impl AsRef<Path>, file: &VectorFile) -> Result<(), MerkleError> {
+    Ok(std::fs::write(path, file.to_json())?)
+}
+
+/// Reads the vector file at `path` and checks every vector in it against
+/// this crate, returning how many there were.
+pub fn verify_vector_file(path: impl AsRef<Path>) -> Result<usize, MerkleError> {
+    let file = VectorFile::from_json(&std::fs::read_to_string(path)?)?;
+    file.verify()?;
+    Ok(file.vectors.len())
+}
+
+#[test]
+fn test_vector_file_round_trip() {
+    let file = VectorFile::standard().unwrap();
+    file.verify().unwrap();
+    assert_eq!(VectorFile::from_json(&file.to_json()).unwrap(), file);
+
+    let path = std::env::temp_dir().join(format!("merkle-vectors-{}.json", std::process::id()));
+    write_vector_file(&path, &file).unwrap();
+    assert_eq!(verify_vector_file(&path).unwrap(), 4);
+    std::fs::remove_file(&path).unwrap();
+
+    // the second vector has four leaves, and paths of two siblings
+    let vector = &file.vectors[1];
+    assert_eq!(vector.proofs[3].siblings.len(), 2);
+    assert_eq!(vector.proofs[3].public_inputs[0], vector.root);
+}
+
+#[cfg(test)]
+const GOLDEN_VECTORS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/vectors.json");
+
+#[test]
+fn test_golden_vector_file() {
+    assert_eq!(verify_vector_file(GOLDEN_VECTORS).unwrap(), 4);
+    let golden = VectorFile::from_json(&std::fs::read_to_string(GOLDEN_VECTORS).unwrap()).unwrap();
+    assert_eq!(golden, VectorFile::standard().unwrap());
+}
+
+// Rewrites the golden file, for a deliberate change to the vectors:
+// `cargo test --lib -- --ignored regenerate_golden_vectors`
+#[test]
+#[ignore]
+fn regenerate_golden_vectors() {
+    write_vector_file(GOLDEN_VECTORS, &VectorFile::standard().unwrap()).unwrap();
+}
+
+#[test]
+fn test_vectors_catch_every_mismatch() {
+    let file = VectorFile::standard().unwrap();
+    let tampered = |edit: &dyn Fn(&mut TestVector)| {
+        let mut file = file.clone();
+        edit(&mut file.vectors[2]);
+        file.verify()
+    };
+    let mismatch = |result: Result<(), MerkleError>, expected: &str| {
+        assert!(matches!(result, Err(MerkleError::VectorMismatch { vector: 2, field }) if field == expected));
+    };
+
+    mismatch(tampered(&|v| v.root = v.leaf_hashes[0].clone()), "root");
+    mismatch(tampered(&|v| v.leaf_hashes.swap(0, 1)), "leaf hash");
+    mismatch(tampered(&|v| v.proofs[1].siblings.reverse()), "path");
+    mismatch(tampered(&|v| v.proofs[4].index = 5), "path");
+    mismatch(tampered(&|v| v.proofs[0].public_inputs.truncate(1)), "public inputs");
+    mismatch(tampered(&|v| v.params_seed = to_hex(b"another seed")), "leaf hash");
+
+    let mut json: serde_json::Value = serde_json::from_str(&file.to_json()).unwrap();
+    json["version"] = 2.into();
+    assert!(matches!(
+        VectorFile::from_json(&json.to_string()),
+        Err(MerkleError::UnknownVersion { version: 2, latest: 1 })
+    ));
+    json["version"] = 1.into();
+    json["hash_config"] = "poseidon/v1".into();
+    assert!(matches!(VectorFile::from_json(&json.to_string()), Err(MerkleError::ParamsMismatch)));
+}
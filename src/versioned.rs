@@ -0,0 +1,268 @@
+use crate::backend::MerkleBackend;
+use crate::common::*;
+use crate::error::{ErrorContext, MerkleError, ResultExt};
+use crate::index::LeafIndex;
+use crate::leaf::Leaf;
+use crate::level_tree::LevelTree;
+use crate::{Root, SimplePath};
+use ark_std::vec::Vec;
+
+// A tree that keeps all its past versions.
+//
+// Version 0 is the tree as built, and every leaf update makes the next
+// version. `VersionedTree` keeps the root of each version and, for every
+// node and leaf, the versions at which it changed and what it changed to,
+// so the tree of any version can be read without replaying the updates: a
+// node at version v is its last change at or before v. That costs one
+// digest per level per update on top of the tree itself. `prove_at` opens
+// a leaf against the root of any version.
+//
+// An `EvolutionProof` from version i to version j shows how i's root became
+// j's: for each update in between, the leaf before and after and the one path
+// that opens the old leaf against the root before the update and the new
+// leaf against the root after it. The same siblings on both sides mean only
+// that leaf changed. An auditor holding the two roots learns every change,
+// in order, and that nothing else happened in between.
+
+/// One leaf update, as an `EvolutionProof` shows it.
+#[derive(Clone)]
+pub struct UpdateStep {
+    pub old_leaf: Leaf,
+    pub new_leaf: Leaf,
+    /// The path of the updated leaf, the same before and after.
+    pub path: SimplePath,
+    /// The root after the update.
+    pub root: Root,
+}
+
+/// Every update between two versions, in order.
+#[derive(Clone)]
+pub struct EvolutionProof {
+    pub from_version: usize,
+    pub steps: Vec<UpdateStep>,
+}
+
+impl EvolutionProof {
+    pub fn to_version(&self) -> usize {
+        self.from_version + self.steps.len()
+    }
+}
+
+pub struct VersionedTree {
+    params: MerkleParams,
+    // per leaf, (version, leaf) at every change, oldest first
+    leaves: Vec<Vec<(usize, Leaf)>>,
+    // nodes[level][index], likewise; levels[0] are the leaf hashes
+    nodes: Vec<Vec<Vec<(usize, Root)>>>,
+    // the root of each version
+    roots: Vec<Root>,
+    // the leaf each version but 0 updated
+    updated: Vec<usize>,
+}
+
+// The value of a history at `version`: its last change at or before it.
+fn at<T>(history: &[(usize, T)], version: usize) -> &T {
+    let changes = history.partition_point(|(changed, _)| *changed <= version);
+    &history[changes - 1].1
+}
+
+impl VersionedTree {
+    /// Version 0, the tree over `leaves`; their number must be a power of two, at least two.
+    pub fn new<L: Into<Leaf>>(params: MerkleParams, leaves: impl IntoIterator<Item = L>) -> Result<Self, MerkleError> {
+        let leaves: Vec<Leaf> = leaves.into_iter().map(Into::into).collect();
+        let tree = LevelTree::new(params, leaves.iter())?;
+        Ok(VersionedTree {
+            params: tree.params().clone(),
+            nodes: tree
+                .levels()
+                .map(|level| level.iter().map(|digest| vec![(0, *digest)]).collect())
+                .collect(),
+            leaves: leaves.into_iter().map(|leaf| vec![(0, leaf)]).collect(),
+            roots: vec![tree.root()],
+            updated: Vec::new(),
+        })
+    }
+
+    /// The latest version.
+    pub fn version(&self) -> usize {
+        self.roots.len() - 1
+    }
+
+    pub fn root(&self) -> Root {
+        self.roots[self.version()]
+    }
+
+    pub fn root_at(&self, version: usize) -> Option<Root> {
+        self.roots.get(version).copied()
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn params(&self) -> &MerkleParams {
+        &self.params
+    }
+
+    fn depth(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    fn check(&self, index: usize, version: usize) -> Result<usize, MerkleError> {
+        if version > self.version() {
+            return Err(MerkleError::UnknownVersion {
+                version,
+                latest: self.version(),
+            });
+        }
+        Ok(LeafIndex::checked_new(index as u64, self.leaf_count() as u64)?.as_usize())
+    }
+
+    fn node_at(&self, level: usize, index: usize, version: usize) -> Root {
+        *at(&self.nodes[level][index], version)
+    }
+
+    /// The leaf at `index` as it was at `version`.
+    pub fn leaf_at(&self, index: usize, version: usize) -> Result<&Leaf, MerkleError> {
+        let index = self.check(index, version)?;
+        Ok(at(&self.leaves[index], version))
+    }
+
+    /// The path of leaf `index` in the tree of `version`, opening
+    /// `leaf_at(index, version)` against `root_at(version)`.
+    pub fn prove_at(&self, index: usize, version: usize) -> Result<SimplePath, MerkleError> {
+        let index = self.check(index, version)?;
+        Ok(SimplePath {
+            leaf_sibling_hash: self.node_at(0, index ^ 1, version),
+            // top-down, and neither the leaf level nor the root
+            auth_path: (1..self.depth())
+                .rev()
+                .map(|level| self.node_at(level, (index >> level) ^ 1, version))
+                .collect(),
+            leaf_index: index,
+        })
+    }
+
+    /// Replaces the leaf at `index`, making a new version, whose number it returns.
+    pub fn update(&mut self, index: usize, leaf: impl Into<Leaf>) -> Result<usize, MerkleError> {
+        let (index, latest) = (self.check(index, 0)?, self.version());
+        let leaf = leaf.into();
+        // rehash the path first, so a failure leaves the tree alone
+        let mut path = vec![self
+            .params
+            .hash_leaf(leaf.as_ref())
+            .with_context(|| ErrorContext::new("update").index(index))?];
+        for level in 1..=self.depth() {
+            let child = index >> (level - 1);
+            let sibling = self.node_at(level - 1, child ^ 1, latest);
+            let current = path[level - 1];
+            let (left, right) = if child & 1 == 1 { (sibling, current) } else { (current, sibling) };
+            path.push(
+                self.params
+                    .hash_children(&left, &right)
+                    .with_context(|| ErrorContext::new("update").index(index).level(level))?,
+            );
+        }
+
+        let version = latest + 1;
+        self.roots.push(path[self.depth()]);
+        for (level, digest) in path.into_iter().enumerate() {
+            self.nodes[level][index >> level].push((version, digest));
+        }
+        self.leaves[index].push((version, leaf));
+        self.updated.push(index);
+        Ok(version)
+    }
+
+    /// The updates that turned version `from` into version `to`.
+    pub fn prove_evolution(&self, from: usize, to: usize) -> Result<EvolutionProof, MerkleError> {
+        self.check(0, to)?;
+        assert!(from <= to, "a tree only evolves forwards");
+        let mut steps = Vec::new();
+        for version in from + 1..=to {
+            let index = self.updated[version - 1];
+            steps.push(UpdateStep {
+                old_leaf: at(&self.leaves[index], version - 1).clone(),
+                new_leaf: at(&self.leaves[index], version).clone(),
+                path: self.prove_at(index, version - 1)?,
+                root: self.roots[version],
+            });
+        }
+        Ok(EvolutionProof { from_version: from, steps })
+    }
+}
+
+/// Checks that `proof` turns `old_root` into `new_root`, one leaf at a time.
+pub fn verify_evolution(
+    params: &MerkleParams,
+    old_root: &Root,
+    new_root: &Root,
+    proof: &EvolutionProof,
+) -> Result<bool, MerkleError> {
+    let mut root = *old_root;
+    for step in &proof.steps {
+        if !params.verify_path(&step.path, &root, step.old_leaf.as_ref())?
+            || !params.verify_path(&step.path, &step.root, step.new_leaf.as_ref())?
+        {
+            return Ok(false);
+        }
+        root = step.root;
+    }
+    Ok(root == *new_root)
+}
+
+#[test]
+fn test_versioned_tree_proves_past_versions() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let mut leaves: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i; 4]).collect();
+    let mut tree = VersionedTree::new(params.clone(), &leaves).unwrap();
+    let mut snapshots = vec![leaves.clone()];
+    for (step, index) in [3usize, 5, 3, 0].iter().enumerate() {
+        leaves[*index] = vec![0xf0 + step as u8; 4];
+        assert_eq!(tree.update(*index, &leaves[*index]).unwrap(), step + 1);
+        snapshots.push(leaves.clone());
+    }
+    assert_eq!(tree.version(), 4);
+
+    for (version, snapshot) in snapshots.iter().enumerate() {
+        let root = tree.root_at(version).unwrap();
+        assert_eq!(root, params.build_tree(snapshot).unwrap().root());
+        for index in [0usize, 3, 5, 6].iter().copied() {
+            assert_eq!(tree.leaf_at(index, version).unwrap().as_ref(), &snapshot[index][..]);
+            let path = tree.prove_at(index, version).unwrap();
+            assert!(params.verify_path(&path, &root, &snapshot[index]).unwrap());
+        }
+    }
+    // leaf 3 at version 1 doesn't open at version 3
+    let path = tree.prove_at(3, 1).unwrap();
+    assert!(!params.verify_path(&path, &tree.root_at(3).unwrap(), &snapshots[1][3]).unwrap());
+    assert!(matches!(tree.prove_at(0, 5), Err(MerkleError::UnknownVersion { version: 5, latest: 4 })));
+    assert!(matches!(tree.prove_at(8, 0), Err(MerkleError::LeafIndexOutOfRange { .. })));
+}
+
+#[test]
+fn test_versioned_tree_evolution_proof() {
+    let params = MerkleParams::setup(&mut ark_std::test_rng()).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 4]).collect();
+    let mut tree = VersionedTree::new(params.clone(), &leaves).unwrap();
+    for (step, index) in [1usize, 2, 1].iter().enumerate() {
+        tree.update(*index, vec![0xa0 + step as u8; 4]).unwrap();
+    }
+    let root = |version| tree.root_at(version).unwrap();
+
+    let proof = tree.prove_evolution(0, 3).unwrap();
+    assert_eq!(proof.steps.len(), 3);
+    assert_eq!(proof.to_version(), 3);
+    assert!(verify_evolution(&params, &root(0), &root(3), &proof).unwrap());
+    assert!(!verify_evolution(&params, &root(1), &root(3), &proof).unwrap());
+    assert!(!verify_evolution(&params, &root(0), &root(2), &proof).unwrap());
+
+    let partial = tree.prove_evolution(1, 2).unwrap();
+    assert!(verify_evolution(&params, &root(1), &root(2), &partial).unwrap());
+    assert!(verify_evolution(&params, &root(2), &root(2), &tree.prove_evolution(2, 2).unwrap()).unwrap());
+
+    // hiding a change behind a claimed no-op doesn't verify
+    let mut forged = proof.clone();
+    forged.steps[1].new_leaf = forged.steps[1].old_leaf.clone();
+    assert!(!verify_evolution(&params, &root(0), &root(3), &forged).unwrap());
+}
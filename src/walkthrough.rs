@@ -0,0 +1,227 @@
+use crate::common::*;
+use crate::error::{ErrorContext, MerkleError, ResultExt};
+use crate::index::LeafIndex;
+use crate::{Root, SimplePath};
+#[cfg(test)]
+use crate::SimpleMerkleTree;
+use ark_ff::to_bytes;
+use ark_std::string::String;
+use ark_std::vec::Vec;
+
+// A step-by-step record of building a tree, opening one leaf and verifying the
+// opening, for tutorials and teaching material.
+//
+// Nothing here is a second implementation of the tree: the tree and the path
+// come from `SimpleMerkleTree` itself, and the intermediate nodes are computed
+// with the same `MerkleParams::hash_leaf`/`hash_children` the rest of the
+// crate uses. The tests check that the recorded root and path are exactly the
+// ones `SimpleMerkleTree` produces.
+
+/// Hex encoding of a digest, in its canonical little-endian byte order.
+pub fn digest_hex(digest: &Root) -> Result<String, MerkleError> {
+    Ok(to_hex(&to_bytes![digest]?))
+}
+
+/// One level of the opened path, from the leaves upwards.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathStep {
+    /// The sibling of the node on the path at this level.
+    pub sibling: String,
+    /// Whether the node on the path is the right child at this level.
+    pub is_right: bool,
+}
+
+/// One recomputed parent during verification, from the leaves upwards.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerificationStep {
+    pub left: String,
+    pub right: String,
+    pub parent: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Walkthrough {
+    /// The hash of every leaf, in leaf order.
+    pub leaf_hashes: Vec<String>,
+    /// The internal nodes level by level, starting with the parents of the
+    /// leaves and ending with the single root.
+    pub internal_nodes: Vec<Vec<String>>,
+    /// The index of the opened leaf.
+    pub index: usize,
+    /// The path `SimpleMerkleTree::generate_proof` returns for `index`.
+    pub path: Vec<PathStep>,
+    /// The root of the tree.
+    pub root: String,
+    /// Every parent recomputed from the leaf and the path while verifying.
+    pub verification: Vec<VerificationStep>,
+    /// The root the verification arrived at.
+    pub recomputed_root: String,
+    /// What `MerkleParams::verify_path` said about the opening.
+    pub verified: bool,
+}
+
+impl Walkthrough {
+    /// Builds the tree over `leaves`, opens the leaf at `index` and verifies
+    /// the opening, recording every intermediate value. Like
+    /// `SimpleMerkleTree::new`, the number of leaves must be a power of two.
+    pub fn run(leaves: &[Vec<u8>], index: usize, params: &MerkleParams) -> Result<Self, MerkleError> {
+        let leaf_index = LeafIndex::checked_new(index as u64, leaves.len() as u64)?;
+        let tree = params.build_tree(leaves)?;
+        let proof = generate_proof(&tree, leaf_index)?;
+
+        // The tree, one level at a time.
+        let mut level = leaves
+            .iter()
+            .map(|leaf| params.hash_leaf(leaf))
+            .collect::<Result<Vec<_>, _>>()?;
+        let leaf_hashes = hex_all(&level)?;
+        let mut internal_nodes = Vec::new();
+        while level.len() > 1 {
+            let height = internal_nodes.len() + 1;
+            level = level
+                .chunks(2)
+                .enumerate()
+                .map(|(i, pair)| {
+                    params
+                        .hash_children(&pair[0], &pair[1])
+                        .with_context(|| ErrorContext::new("walkthrough").index(i).level(height))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            internal_nodes.push(hex_all(&level)?);
+        }
+
+        // The opened path, read off the proof bottom-up.
+        let siblings = path_siblings(&proof);
+        let path = siblings
+            .iter()
+            .enumerate()
+            .map(|(i, sibling)| {
+                Ok(PathStep {
+                    sibling: digest_hex(sibling)?,
+                    is_right: (index >> i) & 1 == 1,
+                })
+            })
+            .collect::<Result<Vec<_>, MerkleError>>()?;
+
+        // Verification: hash the leaf, then fold in the siblings.
+        let (leaf_hash, replayed) = replay_path(params, &leaves[index], &proof)?;
+        let verification = replayed
+            .iter()
+            .map(|step| {
+                Ok(VerificationStep {
+                    left: digest_hex(&step.left)?,
+                    right: digest_hex(&step.right)?,
+                    parent: digest_hex(&step.parent)?,
+                })
+            })
+            .collect::<Result<Vec<_>, MerkleError>>()?;
+        let current = replayed.last().map_or(leaf_hash, |step| step.parent);
+
+        let root = tree.root();
+        let verified = params.verify_path(&proof, &root, &leaves[index])?;
+
+        Ok(Walkthrough {
+            leaf_hashes,
+            internal_nodes,
+            index,
+            path,
+            root: digest_hex(&root)?,
+            verification,
+            recomputed_root: digest_hex(&current)?,
+            verified,
+        })
+    }
+}
+
+/// One parent recomputed while replaying a path.
+pub(crate) struct ReplayedStep {
+    pub left: Root,
+    pub right: Root,
+    pub parent: Root,
+    /// Whether the node on the path was the right child.
+    pub is_right: bool,
+}
+
+// Hashes `leaf` and folds in the siblings of `proof` from the leaf level up,
+// the way `Path::verify` does, returning the leaf hash and every parent. The
+// last parent is the root the path leads to. Shared by `Walkthrough` and
+// `explain::explain_verification`.
+pub(crate) fn replay_path(
+    params: &MerkleParams,
+    leaf: &[u8],
+    proof: &SimplePath,
+) -> Result<(Root, Vec<ReplayedStep>), MerkleError> {
+    let leaf_hash = params.hash_leaf(leaf)?;
+    let mut current = leaf_hash;
+    let mut steps = Vec::new();
+    for (i, sibling) in path_siblings(proof).iter().enumerate() {
+        let is_right = (proof.leaf_index >> i) & 1 == 1;
+        let (left, right) = if is_right { (*sibling, current) } else { (current, *sibling) };
+        current = params.hash_children(&left, &right)?;
+        steps.push(ReplayedStep {
+            left,
+            right,
+            parent: current,
+            is_right,
+        });
+    }
+    Ok((leaf_hash, steps))
+}
+
+fn hex_all(digests: &[Root]) -> Result<Vec<String>, MerkleError> {
+    digests.iter().map(digest_hex).collect()
+}
+
+// the siblings of `proof` from the leaf level up to just below the root
+pub(crate) fn path_siblings(proof: &SimplePath) -> Vec<Root> {
+    let mut siblings = vec![proof.leaf_sibling_hash];
+    siblings.extend(proof.auth_path.iter().rev().cloned());
+    siblings
+}
+
+#[test]
+fn test_walkthrough_matches_tree() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 4]).collect();
+    let tree = SimpleMerkleTree::new(
+        &params.leaf_crh_params,
+        &params.two_to_one_crh_params,
+        &leaves,
+    )
+    .unwrap();
+
+    for index in 0..leaves.len() {
+        let walkthrough = Walkthrough::run(&leaves, index, &params).unwrap();
+        assert_eq!(walkthrough.leaf_hashes.len(), 8);
+        let sizes: Vec<usize> = walkthrough.internal_nodes.iter().map(|l| l.len()).collect();
+        assert_eq!(sizes, vec![4, 2, 1]);
+
+        // the final root is the tree's root, both as built and as recomputed
+        let root = digest_hex(&tree.root()).unwrap();
+        assert_eq!(walkthrough.root, root);
+        assert_eq!(walkthrough.internal_nodes[2][0], root);
+        assert_eq!(walkthrough.recomputed_root, root);
+        assert!(walkthrough.verified);
+
+        // the path is the one `generate_proof` returns
+        let proof = tree.generate_proof(index).unwrap();
+        let expected: Vec<String> = path_siblings(&proof)
+            .iter()
+            .map(|s| digest_hex(s).unwrap())
+            .collect();
+        let siblings: Vec<String> = walkthrough.path.iter().map(|s| s.sibling.clone()).collect();
+        assert_eq!(siblings, expected);
+        assert_eq!(walkthrough.path[0].is_right, index % 2 == 1);
+
+        // each recomputed parent is the node on that level of the tree
+        for (level, step) in walkthrough.verification.iter().enumerate() {
+            assert_eq!(step.parent, walkthrough.internal_nodes[level][index >> (level + 1)]);
+        }
+    }
+
+    assert!(matches!(
+        Walkthrough::run(&leaves, 8, &params),
+        Err(MerkleError::LeafIndexOutOfRange { index: 8, len: 8 })
+    ));
+}
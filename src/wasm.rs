@@ -0,0 +1,85 @@
+use crate::common::*;
+use crate::error::MerkleError;
+use crate::index::LeafIndex;
+use crate::serialization::{
+    deserialize_params, deserialize_path, deserialize_root, serialize_params, serialize_path, serialize_root,
+    Compression,
+};
+use crate::SimpleMerkleTree;
+use ark_serialize::SerializationError;
+use wasm_bindgen::prelude::*;
+
+// JavaScript bindings, for proving membership in the browser.
+//
+// Everything crosses the boundary as byte arrays (`Uint8Array` on the JS
+// side): the parameters as `serialize_params` writes them uncompressed, which
+// are slower to move but faster to read back than compressed ones, roots as
+// `serialize_root`, paths as `serialize_path`. The leaves of a tree are passed
+// concatenated, `leaf_len` bytes each, since wasm-bindgen has no arrays of
+// arrays. Errors become JS exceptions carrying the `MerkleError` message.
+//
+//     const params = params_from_seed(seed);
+//     const root = build_tree(params, leaves, 32);
+//     const path = prove(params, leaves, 32, 5);
+//     verify(params, root, leaves.subarray(160, 192), path); // true
+
+fn to_js(error: MerkleError) -> JsValue {
+    JsValue::from_str(&error.to_string())
+}
+
+fn params(bytes: &[u8]) -> Result<MerkleParams, JsValue> {
+    deserialize_params(bytes, Compression::Uncompressed).map_err(|e| to_js(e.into()))
+}
+
+fn tree(params: &MerkleParams, leaves: &[u8], leaf_len: usize) -> Result<SimpleMerkleTree, MerkleError> {
+    if leaf_len == 0 || leaves.len() % leaf_len != 0 {
+        return Err(MerkleError::Serialization(SerializationError::InvalidData));
+    }
+    params.build_tree(leaves.chunks(leaf_len))
+}
+
+/// The parameters of `MerkleParams::from_seed(seed)`.
+#[wasm_bindgen]
+pub fn params_from_seed(seed: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let params = MerkleParams::from_seed(seed).map_err(to_js)?;
+    Ok(serialize_params(&params, Compression::Uncompressed))
+}
+
+/// The root of the tree over `leaves`, `leaf_len` bytes each.
+#[wasm_bindgen]
+pub fn build_tree(params_bytes: &[u8], leaves: &[u8], leaf_len: usize) -> Result<Vec<u8>, JsValue> {
+    let params = params(params_bytes)?;
+    Ok(serialize_root(&tree(&params, leaves, leaf_len).map_err(to_js)?.root()))
+}
+
+/// The path of leaf `index` in the tree over `leaves`.
+#[wasm_bindgen]
+pub fn prove(params_bytes: &[u8], leaves: &[u8], leaf_len: usize, index: u32) -> Result<Vec<u8>, JsValue> {
+    let params = params(params_bytes)?;
+    let tree = tree(&params, leaves, leaf_len).map_err(to_js)?;
+    let index = LeafIndex::checked_new(index as u64, leaf_count(&tree)).map_err(to_js)?;
+    Ok(serialize_path(&generate_proof(&tree, index).map_err(to_js)?))
+}
+
+/// Whether `path` opens `leaf` against `root`.
+#[wasm_bindgen]
+pub fn verify(params_bytes: &[u8], root: &[u8], leaf: &[u8], path: &[u8]) -> Result<bool, JsValue> {
+    let params = params(params_bytes)?;
+    let root = deserialize_root(root).map_err(|e| to_js(e.into()))?;
+    let path = deserialize_path(path).map_err(|e| to_js(e.into()))?;
+    params.verify_path(&path, &root, leaf).map_err(to_js)
+}
+
+#[test]
+fn test_wasm_bindings_round_trip() {
+    let params = params_from_seed(b"wasm").unwrap();
+    let leaves: Vec<u8> = (0u8..4).flat_map(|i| vec![i; 16]).collect();
+    let root = build_tree(&params, &leaves, 16).unwrap();
+    let path = prove(&params, &leaves, 16, 2).unwrap();
+    assert!(verify(&params, &root, &leaves[32..48], &path).unwrap());
+    assert!(!verify(&params, &root, &leaves[16..32], &path).unwrap());
+    assert!(matches!(
+        tree(&MerkleParams::from_seed(b"wasm").unwrap(), &leaves[..30], 16),
+        Err(MerkleError::Serialization(_))
+    ));
+}
@@ -0,0 +1,26 @@
+#![cfg(feature = "constraints")]
+
+// Everything here is named through this crate only, as a downstream user
+// without direct arkworks dependencies would have to.
+use merkle_tree_tutorial::ark::{ConstraintSystem, ConstraintSystemRef, Error, Fq, Path, SynthesisError};
+use merkle_tree_tutorial::common::{ConstraintF, MerkleParams};
+use merkle_tree_tutorial::{MerkleConfig, SimpleMerkleTree};
+
+#[test]
+fn public_api_types_are_nameable_through_the_crate() {
+    let mut rng = merkle_tree_tutorial::ark::std::test_rng();
+    let params: Result<MerkleParams, Error> = MerkleParams::setup(&mut rng);
+    let params = params.unwrap();
+
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = SimpleMerkleTree::new(&params.leaf_crh_params, &params.two_to_one_crh_params, &leaves).unwrap();
+    let path: Path<MerkleConfig> = tree.generate_proof(1).unwrap();
+    let root: Fq = tree.root();
+    assert!(path
+        .verify(&params.leaf_crh_params, &params.two_to_one_crh_params, &root, &leaves[1])
+        .unwrap());
+
+    let cs: ConstraintSystemRef<ConstraintF> = ConstraintSystem::new_ref();
+    let satisfied: Result<bool, SynthesisError> = cs.is_satisfied();
+    assert!(satisfied.unwrap());
+}
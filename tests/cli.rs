@@ -0,0 +1,130 @@
+#![cfg(feature = "std")]
+
+use assert_cmd::Command;
+use merkle_tree_tutorial::common::to_hex;
+use std::fs;
+use std::path::PathBuf;
+
+const LEAVES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/leaves.txt");
+
+// A fresh directory for the files of one test.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("merkle-cli-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn merkle(args: &[&str]) -> std::process::Output {
+    Command::cargo_bin("merkle").unwrap().args(args).output().unwrap()
+}
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap().trim().to_string()
+}
+
+fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn build_prove_and_verify_from_files() {
+    let dir = scratch("roundtrip");
+    let tree = dir.join("tree.bin");
+    let proof = dir.join("proof.bin");
+    let (tree, proof) = (tree.to_str().unwrap(), proof.to_str().unwrap());
+
+    let built = merkle(&["build", "--input", LEAVES, "--out", tree]);
+    assert!(built.status.success(), "{}", stderr(&built));
+    let root = stdout(&built);
+    assert_eq!(root.len(), 96);
+    assert_eq!(stdout(&merkle(&["root", "--tree", tree])), root);
+
+    // the fourth non-blank line of the fixture, "dave"
+    let proved = merkle(&["prove", "--tree", tree, "--index", "3", "--out", proof]);
+    assert!(proved.status.success(), "{}", stderr(&proved));
+    let leaf = stdout(&proved);
+    assert_eq!(leaf, to_hex(b"dave"));
+
+    let verified = merkle(&["verify", "--root", &root, "--leaf", &leaf, "--proof", proof]);
+    assert_eq!(verified.status.code(), Some(0));
+    assert_eq!(stdout(&verified), "valid");
+
+    let wrong_leaf = merkle(&["verify", "--root", &root, "--leaf", &to_hex(b"mallory"), "--proof", proof]);
+    assert_eq!(wrong_leaf.status.code(), Some(1));
+    assert_eq!(stdout(&wrong_leaf), "invalid");
+
+    // other params, other root
+    let seed = "11".repeat(32);
+    let reseeded = merkle(&["build", "--input", LEAVES, "--params-seed", &seed, "--out", tree]);
+    assert!(reseeded.status.success());
+    assert_ne!(stdout(&reseeded), root);
+    let other_params = merkle(&["verify", "--root", &root, "--leaf", &leaf, "--proof", proof, "--params-seed", &seed]);
+    assert_eq!(other_params.status.code(), Some(1));
+}
+
+#[test]
+fn bad_input_is_reported_not_panicked_on() {
+    let dir = scratch("errors");
+    let tree = dir.join("tree.bin");
+    let proof = dir.join("proof.bin");
+    let (tree, proof) = (tree.to_str().unwrap(), proof.to_str().unwrap());
+    let bad_leaves = dir.join("bad.txt");
+    fs::write(&bad_leaves, "00ff\nnot hex\n").unwrap();
+
+    let failed = merkle(&["build", "--input", bad_leaves.to_str().unwrap(), "--out", tree]);
+    assert_eq!(failed.status.code(), Some(2));
+    assert!(stderr(&failed).contains("line 2"), "{}", stderr(&failed));
+
+    assert!(merkle(&["build", "--input", LEAVES, "--out", tree]).status.success());
+    // five leaves, padded to eight; the padding can't be opened
+    let out_of_range = merkle(&["prove", "--tree", tree, "--index", "5", "--out", proof]);
+    assert_eq!(out_of_range.status.code(), Some(2));
+    assert!(stderr(&out_of_range).contains("out of range"), "{}", stderr(&out_of_range));
+
+    fs::write(proof, b"not a proof").unwrap();
+    let root = stdout(&merkle(&["root", "--tree", tree]));
+    let malformed = merkle(&["verify", "--root", &root, "--leaf", "00", "--proof", proof]);
+    assert_eq!(malformed.status.code(), Some(2));
+    assert!(stderr(&malformed).contains("is not a proof"), "{}", stderr(&malformed));
+
+    fs::write(tree, b"truncated").unwrap();
+    let truncated = merkle(&["root", "--tree", tree]);
+    assert_eq!(truncated.status.code(), Some(2));
+    assert!(stderr(&truncated).contains("is not a tree file"));
+
+    assert_eq!(merkle(&["frobnicate"]).status.code(), Some(2));
+    assert_eq!(merkle(&["root"]).status.code(), Some(2));
+    assert!(!stderr(&merkle(&[])).contains("panicked"));
+}
+
+#[cfg(feature = "snark")]
+#[test]
+fn snark_setup_prove_and_verify() {
+    let dir = scratch("snark");
+    let (tree, proof) = (dir.join("tree.bin"), dir.join("proof.bin"));
+    let (pk, vk) = (dir.join("pk.bin"), dir.join("vk.bin"));
+    let (tree, proof) = (tree.to_str().unwrap(), proof.to_str().unwrap());
+    let (pk, vk) = (pk.to_str().unwrap(), vk.to_str().unwrap());
+
+    let root = stdout(&merkle(&["build", "--input", LEAVES, "--out", tree]));
+    let setup = merkle(&["snark-setup", "--tree", tree, "--leaf-len", "4", "--pk", pk, "--vk", vk]);
+    assert!(setup.status.success(), "{}", stderr(&setup));
+
+    let proved = merkle(&["snark-prove", "--tree", tree, "--index", "3", "--pk", pk, "--out", proof]);
+    assert!(proved.status.success(), "{}", stderr(&proved));
+    let leaf = stdout(&proved);
+    assert_eq!(leaf, to_hex(b"dave"));
+
+    let verified = merkle(&["snark-verify", "--vk", vk, "--root", &root, "--leaf", &leaf, "--proof", proof]);
+    assert_eq!(verified.status.code(), Some(0));
+    assert_eq!(stdout(&verified), "valid");
+    let wrong_leaf = merkle(&["snark-verify", "--vk", vk, "--root", &root, "--leaf", &to_hex(b"eves"), "--proof", proof]);
+    assert_eq!(wrong_leaf.status.code(), Some(1));
+    let wrong_len = merkle(&["snark-verify", "--vk", vk, "--root", &root, "--leaf", &to_hex(b"eve"), "--proof", proof]);
+    assert_eq!(wrong_len.status.code(), Some(1));
+
+    // "alice" is five bytes, the key is for four
+    let other_len = merkle(&["snark-prove", "--tree", tree, "--index", "0", "--pk", pk, "--out", proof]);
+    assert_eq!(other_len.status.code(), Some(2));
+    assert!(stderr(&other_len).contains("the key is for"), "{}", stderr(&other_len));
+}
@@ -0,0 +1,105 @@
+// Process-wide fixtures for the integration tests.
+//
+// Pedersen setup, tree construction and Groth16 key generation are the slow
+// part of most tests, and they don't depend on the test. `shared()` does them
+// once per test binary, on first use, and every test reads from the result.
+// Tests must treat it as read-only: anything they want to change, they clone.
+// The tree is `fixtures::fixture_tree(8)`, the one of the original tutorial;
+// `build()` makes it again from scratch, for comparing the two.
+//
+// `assert_satisfied` and `assert_unsatisfied` are the assertions of
+// `circuit_report` with the `testing` feature, which report the failing
+// constraint. Without it they only check satisfiability, so the default
+// `cargo test` still runs every circuit test.
+//
+// Every test file that wants it declares `mod common;`, so not every file
+// uses every helper.
+#![allow(dead_code)]
+
+use merkle_tree_tutorial::fixtures::{fixture_leaf, fixture_tree, Fixture};
+use merkle_tree_tutorial::prelude::*;
+#[cfg(feature = "snark")]
+use merkle_tree_tutorial::snark::{self, ProvingKey, VerifyingKey};
+use once_cell::sync::Lazy;
+
+pub struct Shared {
+    pub fixture: Fixture,
+    pub root: Root,
+    /// The path of every leaf, by index.
+    pub proofs: Vec<SimplePath>,
+    /// Groth16 keys for the membership circuit over the shared tree.
+    #[cfg(feature = "snark")]
+    pub keys: (ProvingKey, VerifyingKey),
+}
+
+static SHARED: Lazy<Shared> = Lazy::new(build);
+
+pub fn shared() -> &'static Shared {
+    &SHARED
+}
+
+/// What `shared()` holds, built anew.
+pub fn build() -> Shared {
+    let fixture = fixture_tree(8);
+    let proofs: Vec<SimplePath> = (0..fixture.leaves.len()).map(|i| fixture.proof(i)).collect();
+    let root = fixture.root();
+    // any leaf will do: the keys only depend on the shape of the circuit
+    #[cfg(feature = "snark")]
+    let keys = snark::setup(fixture.circuit(0), &mut ark_std::test_rng()).unwrap();
+    Shared {
+        fixture,
+        root,
+        proofs,
+        #[cfg(feature = "snark")]
+        keys,
+    }
+}
+
+impl Shared {
+    /// The leaf for `value`, whether or not it is in the tree.
+    pub fn leaf(value: u8) -> Vec<u8> {
+        fixture_leaf(value)
+    }
+
+    pub fn params(&self) -> &MerkleParams {
+        &self.fixture.params
+    }
+
+    pub fn leaves(&self) -> &[Vec<u8>] {
+        &self.fixture.leaves
+    }
+
+    pub fn index_of(&self, value: u8) -> Option<usize> {
+        self.fixture.index_of(value)
+    }
+
+    /// The membership circuit for the leaf at `index`, with its real path.
+    #[cfg(feature = "constraints")]
+    pub fn circuit(&self, index: usize) -> MTreeVerification {
+        self.fixture.circuit(index)
+    }
+}
+
+#[cfg(all(feature = "constraints", feature = "testing"))]
+pub use merkle_tree_tutorial::circuit_report::{
+    assert_satisfied_with_report as assert_satisfied, expect_unsatisfied as assert_unsatisfied,
+};
+
+#[cfg(all(feature = "constraints", not(feature = "testing")))]
+pub fn assert_satisfied<C: ConstraintSynthesizer<ConstraintF>>(circuit: C) {
+    assert!(is_satisfied(circuit), "the circuit is not satisfied");
+}
+
+/// `_namespace` is the region `expect_unsatisfied` would check fails; only
+/// that some constraint does is checked here.
+#[cfg(all(feature = "constraints", not(feature = "testing")))]
+pub fn assert_unsatisfied<C: ConstraintSynthesizer<ConstraintF>>(circuit: C, _namespace: &str) {
+    assert!(!is_satisfied(circuit), "the circuit is satisfied");
+}
+
+#[cfg(all(feature = "constraints", not(feature = "testing")))]
+fn is_satisfied<C: ConstraintSynthesizer<ConstraintF>>(circuit: C) -> bool {
+    let cs = ConstraintSystem::new_ref();
+    circuit.generate_constraints(cs.clone()).unwrap();
+    cs.is_satisfied().unwrap()
+}
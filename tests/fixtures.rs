@@ -0,0 +1,18 @@
+#![cfg(feature = "std")]
+
+use merkle_tree_tutorial::fixtures::{fixture_leaf, fixture_tree};
+
+#[test]
+fn fixture_trees_are_deterministic() {
+    let a = fixture_tree(16);
+    let b = fixture_tree(16);
+    assert_eq!(a.root(), b.root());
+    assert_eq!(a.leaves, b.leaves);
+    assert_eq!(&a.raw_values[..8], &[1, 2, 3, 10, 9, 17, 70, 45]);
+
+    for index in 0..16 {
+        let proof = a.proof(index);
+        assert!(a.params.verify_path(&proof, &a.root(), &a.leaves[index]).unwrap());
+    }
+    assert_eq!(a.leaves[a.index_of(17).unwrap()], fixture_leaf(17));
+}
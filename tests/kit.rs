@@ -0,0 +1,17 @@
+#![cfg(feature = "snark")]
+
+use merkle_tree_tutorial::prelude::*;
+
+#[test]
+fn kit_end_to_end() {
+    let kit = MerkleKit::new((0u8..5).map(|i| vec![i; 16])).unwrap();
+    let proof = kit.prove(3).unwrap();
+    assert!(kit.verify(&proof));
+    assert_eq!(proof.root, kit.root());
+
+    let zk = kit.zk_prove(3).unwrap();
+    assert!(kit.zk_verify(&zk).unwrap());
+    let mut forged = zk.clone();
+    forged.leaf = vec![9u8; 16];
+    assert!(!kit.zk_verify(&forged).unwrap());
+}
@@ -0,0 +1,110 @@
+// The two tests the tutorial started with, on the shared fixture.
+#![cfg(feature = "std")]
+
+mod common;
+
+#[cfg(feature = "constraints")]
+use common::{assert_satisfied, assert_unsatisfied};
+use common::{shared, Shared};
+
+#[test]
+fn test_merkle_tree() {
+    // The shared tree has the leaves 1, 2, 3, 10, 9, 17, 70, 45 (hashed with keccak256),
+    // built with fixed public parameters for the hash functions.
+    let fixture = shared();
+
+    // Now, let's try to generate a membership proof for the 4th item.
+    let proof = &fixture.proofs[3]; // we're 0-indexing!
+
+    let leaf = Shared::leaf(10);
+    // This should be a proof for the membership of a leaf with value 10. Let's check that!
+
+    // Next, let's verify the proof against the root!
+    let result = fixture
+        .params()
+        .verify_path(
+            proof,
+            &fixture.root,
+            &leaf, // The claimed leaf
+        )
+        .unwrap();
+    assert!(result);
+}
+
+// Run this test via `cargo test --release test_merkle_tree`.
+#[cfg(feature = "constraints")]
+#[test]
+fn test_merkle_tree_constraints() {
+    let fixture = shared();
+
+    // Now, let's try to generate a membership proof for the 5th item, i.e. 9.
+    // This should be a proof for the membership of a leaf with value 9. Let's check that!
+    assert_eq!(fixture.index_of(9), Some(4));
+    assert_satisfied(fixture.circuit(4)); // we're 0-indexing!
+
+    // A leaf that isn't in the tree doesn't open the same path.
+    let mut circuit = fixture.circuit(4);
+    circuit.leaf = Shared::leaf(8);
+    assert_unsatisfied(circuit, "membership");
+}
+
+#[cfg(feature = "snark")]
+#[test]
+fn test_merkle_tree_groth16_with_shared_keys() {
+    use merkle_tree_tutorial::inputs::PublicInputsBuilder;
+    use merkle_tree_tutorial::snark;
+
+    let fixture = shared();
+    let (pk, vk) = &fixture.keys;
+    let mut rng = ark_std::test_rng();
+    for index in [0, 5].iter() {
+        let circuit = fixture.circuit(*index);
+        let inputs = circuit.public_inputs();
+        assert_eq!(
+            inputs,
+            PublicInputsBuilder::membership()
+                .root(&fixture.root)
+                .leaf_bytes(&fixture.leaves()[*index])
+                .finish()
+        );
+        let proof = snark::prove(pk, circuit, &mut rng).unwrap();
+        assert!(snark::verify(vk, &inputs, &proof).unwrap());
+
+        // the same proof checked against another leaf, or another root, fails
+        let mut other = fixture.circuit(*index);
+        other.leaf = fixture.leaves()[*index + 1].clone();
+        assert!(!snark::verify(vk, &other.public_inputs(), &proof).unwrap());
+        let mut other = fixture.circuit(*index);
+        other.root = fixture.proofs[0].leaf_sibling_hash;
+        assert!(!snark::verify(vk, &other.public_inputs(), &proof).unwrap());
+    }
+}
+
+// What sharing the fixture saves: building it for every test, as each test
+// did before, against reading the shared one.
+// `cargo test --release --test merkle_tree -- --ignored --nocapture time_shared_fixture`
+#[test]
+#[ignore]
+fn time_shared_fixture() {
+    use std::time::Instant;
+
+    const TESTS: u32 = 4;
+    let start = Instant::now();
+    for _ in 0..TESTS {
+        common::build();
+    }
+    let per_test = start.elapsed();
+
+    let start = Instant::now();
+    for _ in 0..TESTS {
+        shared();
+    }
+    let once = start.elapsed();
+    println!(
+        "{} tests: {:?} building the fixture in each, {:?} sharing it ({:.1}x)",
+        TESTS,
+        per_test,
+        once,
+        per_test.as_secs_f64() / once.as_secs_f64()
+    );
+}
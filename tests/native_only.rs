@@ -0,0 +1,30 @@
+// Compile check for the minimal feature set:
+//
+//     cargo test --no-default-features --test native_only
+//
+// Only the native tree, proofs, serialization and errors are used here, and
+// none of their signatures may mention gadget types for this to build.
+use merkle_tree_tutorial::append_only::{verify_inclusion, AppendOnlyTree};
+use merkle_tree_tutorial::common::MerkleParams;
+use merkle_tree_tutorial::error::MerkleError;
+use merkle_tree_tutorial::serialization::{deserialize_path, serialize_path};
+use merkle_tree_tutorial::{Root, SimpleMerkleTree, SimplePath};
+
+#[allow(dead_code)]
+fn native_roundtrip(params: &MerkleParams, leaves: &[Vec<u8>]) -> Result<bool, MerkleError> {
+    let tree = SimpleMerkleTree::new(&params.leaf_crh_params, &params.two_to_one_crh_params, leaves)?;
+    let root: Root = tree.root();
+    let path: SimplePath = deserialize_path(&serialize_path(&tree.generate_proof(0)?))?;
+    Ok(path.verify(&params.leaf_crh_params, &params.two_to_one_crh_params, &root, &leaves[0])?)
+}
+
+#[allow(dead_code)]
+fn append_only(params: &MerkleParams) -> Result<bool, MerkleError> {
+    let mut tree = AppendOnlyTree::new(params.clone());
+    tree.append(b"first")?;
+    let proof = tree.prove_inclusion(0)?;
+    verify_inclusion(params, b"first", &proof, &tree.root()?)
+}
+
+#[test]
+fn native_api_compiles() {}
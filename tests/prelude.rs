@@ -0,0 +1,35 @@
+#![cfg(feature = "constraints")]
+
+use merkle_tree_tutorial::prelude::*;
+
+#[test]
+fn prelude_is_enough_for_native_and_circuit_usage() {
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+
+    let tree = SimpleMerkleTree::new(&params.leaf_crh_params, &params.two_to_one_crh_params, &leaves).unwrap();
+    let root: Root = tree.root();
+    let path: SimplePath = tree.generate_proof(2).unwrap();
+    assert!(path
+        .verify(&params.leaf_crh_params, &params.two_to_one_crh_params, &root, &leaves[2])
+        .unwrap());
+
+    let circuit: MTreeVerification = MTreeVerification {
+        leaf_crh_params: params.leaf_crh_params.clone(),
+        two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+        root,
+        leaf: leaves[2].clone(),
+        auth_path: Some(path),
+    };
+    let cs = ConstraintSystem::<ConstraintF>::new_ref();
+    circuit.generate_constraints(cs.clone()).unwrap();
+    assert!(cs.is_satisfied().unwrap());
+
+    // the hash traits are in scope too
+    let _ = <LeafHash as CRH>::evaluate(&params.leaf_crh_params, &leaves[0]).unwrap();
+    let _: Root =
+        <TwoToOneHash as TwoToOneCRH>::evaluate(&params.two_to_one_crh_params, &leaves[0], &leaves[1]).unwrap();
+    let _: Option<MerkleError> = None;
+    let _ = MerkleConfig;
+}
@@ -0,0 +1,76 @@
+// `ark_std::test_rng` has a fixed seed: anything set up or proved with it is
+// predictable. It is fine in tests and nowhere else, so this scans the crate's
+// sources for uses outside a test item.
+//
+// The scan is line based. A use is allowed inside a top-level item with an
+// attribute mentioning `test` (`#[test]`, `#[cfg(test)]`,
+// `#[cfg(any(test, feature = "testing"))]`, ...). Doc comments are never
+// allowed to use it, since examples are what gets copied.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn rust_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            rust_files(&path, files);
+        } else if path.extension().map_or(false, |ext| ext == "rs") {
+            files.push(path);
+        }
+    }
+}
+
+// Whether `line` opens a top-level item, as opposed to continuing one, closing
+// one, or being an attribute or comment.
+fn is_item_start(line: &str) -> bool {
+    !line.is_empty()
+        && !line.starts_with(char::is_whitespace)
+        && !line.starts_with('}')
+        && !line.starts_with(')')
+        && !line.starts_with("#[")
+        && !line.starts_with("//")
+}
+
+// Whether the top-level item around line `at` carries a test attribute.
+fn in_test_item(lines: &[&str], at: usize) -> bool {
+    let mut start = at;
+    while start > 0 && !is_item_start(lines[start]) {
+        start -= 1;
+    }
+    // the attributes and doc comments right above the item, multi-line ones included
+    lines[..start]
+        .iter()
+        .rev()
+        .take_while(|line| {
+            !line.is_empty()
+                && (line.starts_with("#[")
+                    || line.starts_with("///")
+                    || line.starts_with(')')
+                    || line.starts_with(char::is_whitespace))
+        })
+        .any(|line| line.starts_with("#[") && line.contains("test"))
+}
+
+#[test]
+fn test_rng_is_only_used_in_tests() {
+    let mut files = Vec::new();
+    rust_files(&Path::new(env!("CARGO_MANIFEST_DIR")).join("src"), &mut files);
+    assert!(!files.is_empty());
+
+    let mut uses = Vec::new();
+    for file in &files {
+        let source = fs::read_to_string(file).unwrap();
+        let lines: Vec<&str> = source.lines().collect();
+        for (i, line) in lines.iter().enumerate() {
+            if !line.contains("test_rng") {
+                continue;
+            }
+            let doc = line.trim_start().starts_with("///") || line.trim_start().starts_with("//!");
+            if doc || !in_test_item(&lines, i) {
+                uses.push(format!("{}:{}: {}", file.display(), i + 1, line.trim()));
+            }
+        }
+    }
+    assert!(uses.is_empty(), "test_rng outside tests:\n{}", uses.join("\n"));
+}
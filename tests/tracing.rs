@@ -0,0 +1,110 @@
+#![cfg(all(feature = "tracing", feature = "snark"))]
+
+use merkle_tree_tutorial::common::{generate_proof, leaf_count};
+use merkle_tree_tutorial::index::LeafIndex;
+use merkle_tree_tutorial::inputs::PublicInputsBuilder;
+use merkle_tree_tutorial::prelude::*;
+use merkle_tree_tutorial::snark;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+
+// Every span opened while the layer is installed, in creation order, with the
+// fields recorded on it at creation or later.
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<(String, BTreeMap<String, String>)>>>);
+
+struct SpanIndex(usize);
+
+struct Fields<'a>(&'a mut BTreeMap<String, String>);
+
+impl Visit for Fields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = BTreeMap::new();
+        attrs.record(&mut Fields(&mut fields));
+        let mut spans = self.0.lock().unwrap();
+        spans.push((attrs.metadata().name().to_string(), fields));
+        ctx.span(id).unwrap().extensions_mut().insert(SpanIndex(spans.len() - 1));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let extensions = span.extensions();
+        let index = extensions.get::<SpanIndex>().unwrap().0;
+        values.record(&mut Fields(&mut self.0.lock().unwrap()[index].1));
+    }
+}
+
+impl Capture {
+    fn field(&self, span: &str, field: &str) -> Option<String> {
+        let spans = self.0.lock().unwrap();
+        let (_, fields) = spans.iter().find(|(name, _)| name == span)?;
+        fields.get(field).cloned()
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().map(|(name, _)| name.clone()).collect()
+    }
+}
+
+#[test]
+fn build_prove_verify_emits_spans() {
+    let capture = Capture::default();
+    let subscriber = tracing_subscriber::Registry::default().with(capture.clone());
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let mut rng = ark_std::test_rng();
+    let params = MerkleParams::setup(&mut rng).unwrap();
+    let leaves: Vec<Vec<u8>> = (0u8..4).map(|i| vec![i; 8]).collect();
+    let tree = params.build_tree(&leaves).unwrap();
+    let root = tree.root();
+    let index = LeafIndex::checked_new(2, leaf_count(&tree)).unwrap();
+    let path = generate_proof(&tree, index).unwrap();
+    assert!(params.verify_path(&path, &root, &leaves[2]).unwrap());
+
+    let circuit = |path| -> MTreeVerification {
+        MTreeVerification {
+            leaf_crh_params: params.leaf_crh_params.clone(),
+            two_to_one_crh_params: params.two_to_one_crh_params.clone(),
+            root,
+            leaf: leaves[2].clone(),
+            auth_path: Some(path),
+        }
+    };
+    let (pk, vk) = snark::setup(circuit(path.clone()), &mut rng).unwrap();
+    let proof = snark::prove(&pk, circuit(path), &mut rng).unwrap();
+    let inputs = PublicInputsBuilder::membership().root(&root).leaf_bytes(&leaves[2]).finish();
+    assert!(snark::verify(&vk, &inputs, &proof).unwrap());
+
+    let ours: Vec<String> = capture
+        .names()
+        .into_iter()
+        .filter(|name| {
+            ["params_setup", "tree_build", "proof_generate", "path_verify", "snark_setup", "snark_prove", "snark_verify"]
+                .contains(&name.as_str())
+        })
+        .collect();
+    assert_eq!(
+        ours,
+        vec!["params_setup", "tree_build", "proof_generate", "path_verify", "snark_setup", "snark_prove", "snark_verify"]
+    );
+
+    assert_eq!(capture.field("tree_build", "leaf_count").as_deref(), Some("4"));
+    assert_eq!(capture.field("tree_build", "height").as_deref(), Some("3"));
+    assert_eq!(capture.field("proof_generate", "index").as_deref(), Some("2"));
+    assert_eq!(capture.field("path_verify", "index").as_deref(), Some("2"));
+    assert_eq!(capture.field("path_verify", "result").as_deref(), Some("true"));
+    assert_eq!(capture.field("snark_verify", "public_inputs").as_deref(), Some(&*inputs.len().to_string()));
+    assert_eq!(capture.field("snark_verify", "result").as_deref(), Some("true"));
+}